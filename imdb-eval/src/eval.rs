@@ -3,12 +3,15 @@ use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::vec;
 
 use imdb_index::{
-    Index, IndexBuilder, MediaEntity, NameScorer, NgramType, Query, Searcher,
-    Similarity,
+    Combine, CorpusStats, DocStats, ErrorKind, Index, IndexBuilder,
+    MediaEntity, NameScorer, NgramType, Query, Scorer, SearchDiagnostics,
+    Searcher, Similarity, TermStats, DEFAULT_RESCORE_WINDOW,
 };
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
@@ -40,6 +43,18 @@ struct Task {
     answer: String,
 }
 
+/// Where the index used by an evaluation comes from.
+#[derive(Clone, Copy, Debug)]
+pub enum IndexDir<'a> {
+    /// A directory in which each specification gets its own index, named
+    /// after its index settings. Indexes are created as needed.
+    Eval(&'a Path),
+    /// An existing index, such as the one created by imdb-rename. It is
+    /// never created, and may only be used by specifications whose index
+    /// settings match it.
+    Shared(&'a Path),
+}
+
 impl Truth {
     /// Load truth data from the given TOML file.
     fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<Truth> {
@@ -59,8 +74,8 @@ impl Truth {
 /// A specification describes both how the index should be built and how
 /// queries should be generated. Specifications with equivalent index settings
 /// may reuse the same on-disk index. For example, the ngram size and type are
-/// index settings, but the similarity function, name scorer and result size
-/// are all query time settings.
+/// index settings, but the similarity function, name scorer, result size and
+/// candidate window are all query time settings.
 ///
 /// A specification cannot itself produce a complete query. Namely, a
 /// specification requires an information need (called a "task") to construct
@@ -74,7 +89,10 @@ pub struct Spec {
     ngram_size: usize,
     ngram_type: NgramType,
     sim: Similarity,
-    scorer: Option<NameScorer>,
+    scorer: Option<SpecScorer>,
+    combine: Combine,
+    window: usize,
+    sequel_boost: bool,
 }
 
 impl Spec {
@@ -85,7 +103,10 @@ impl Spec {
             ngram_size: 3,
             ngram_type: NgramType::default(),
             sim: Similarity::None,
-            scorer: Some(NameScorer::OkapiBM25),
+            scorer: Some(SpecScorer::Name(NameScorer::OkapiBM25)),
+            combine: Combine::Replace,
+            window: DEFAULT_RESCORE_WINDOW,
+            sequel_boost: false,
         }
     }
 
@@ -123,6 +144,22 @@ impl Spec {
         Ok(self)
     }
 
+    /// Set the candidate window for this specification, which is the number
+    /// of candidates fetched from the name index before filtering and
+    /// rescoring them.
+    ///
+    /// This returns an error if the given window is less than `1`.
+    pub fn with_window(mut self, window: usize) -> anyhow::Result<Spec> {
+        if window < 1 {
+            anyhow::bail!(
+                "candidate window {} is invalid, must be greater than 0",
+                window
+            );
+        }
+        self.window = window;
+        Ok(self)
+    }
+
     /// Set the ngram type for this specification.
     pub fn with_ngram_type(mut self, ngram_type: NgramType) -> Spec {
         self.ngram_type = ngram_type;
@@ -135,72 +172,174 @@ impl Spec {
         self
     }
 
+    /// Set how name scores are combined with the similarity ranker function
+    /// for this specification.
+    pub fn with_combine(mut self, combine: Combine) -> Spec {
+        self.combine = combine;
+        self
+    }
+
+    /// Set whether results are boosted by `Searcher::boost_sequel`, like
+    /// imdb-rename does, before the answer is ranked.
+    pub fn with_sequel_boost(mut self, yes: bool) -> Spec {
+        self.sequel_boost = yes;
+        self
+    }
+
     /// Set the name scorer for this specification.
     ///
     /// Note that if the given scorer is `None`, then an evaluation will likely
     /// be quite slow, since each information need will result in an exhaustive
     /// search of the corpus.
-    pub fn with_scorer(mut self, scorer: Option<NameScorer>) -> Spec {
+    pub fn with_scorer(mut self, scorer: Option<SpecScorer>) -> Spec {
         self.scorer = scorer;
         self
     }
 
     /// Evaluate this specification against the built-in truth data.
-    pub fn evaluate<P1: AsRef<Path>, P2: AsRef<Path>>(
+    pub fn evaluate<P: AsRef<Path>>(
         &self,
-        data_dir: P1,
-        eval_dir: P2,
-    ) -> anyhow::Result<Evaluation> {
-        let searcher = Searcher::new(self.index(data_dir, eval_dir)?);
+        data_dir: P,
+        index_dir: IndexDir,
+    ) -> anyhow::Result<Evaluation<'_>> {
+        let searcher = self.searcher(data_dir, index_dir)?;
         Ok(Evaluation {
-            evaluator: Evaluator { spec: self, searcher },
+            evaluator: Evaluator { spec: self, searcher, timeout: None },
             tasks: TRUTH.clone().tasks.into_iter(),
         })
     }
 
     /// Evaluate this specification against a set of truth data at the given
     /// file path.
-    pub fn evaluate_with<P1: AsRef<Path>, P2: AsRef<Path>, P3: AsRef<Path>>(
+    pub fn evaluate_with<P1: AsRef<Path>, P2: AsRef<Path>>(
         &self,
         data_dir: P1,
-        eval_dir: P2,
-        truth_path: P3,
-    ) -> anyhow::Result<Evaluation> {
-        let searcher = Searcher::new(self.index(data_dir, eval_dir)?);
+        index_dir: IndexDir,
+        truth_path: P2,
+    ) -> anyhow::Result<Evaluation<'_>> {
+        let searcher = self.searcher(data_dir, index_dir)?;
         Ok(Evaluation {
-            evaluator: Evaluator { spec: self, searcher },
+            evaluator: Evaluator { spec: self, searcher, timeout: None },
             tasks: Truth::from_path(truth_path)?.tasks.into_iter(),
         })
     }
 
+    /// Open a searcher for this specification, using its custom scorer if
+    /// it has one.
+    fn searcher<P: AsRef<Path>>(
+        &self,
+        data_dir: P,
+        index_dir: IndexDir,
+    ) -> anyhow::Result<Searcher> {
+        let idx = match index_dir {
+            IndexDir::Eval(eval_dir) => self.index(data_dir, eval_dir)?,
+            IndexDir::Shared(dir) => {
+                let idx = Index::open(data_dir, dir)?;
+                self.check_index(&idx)?;
+                idx
+            }
+        };
+        let mut searcher = Searcher::new(idx);
+        if let Some(SpecScorer::Custom(ref name)) = self.scorer {
+            match custom_scorer(name) {
+                None => anyhow::bail!("unknown custom scorer: {}", name),
+                Some(scorer) => searcher.custom_scorer(Some(scorer)),
+            };
+        }
+        Ok(searcher)
+    }
+
     /// Create a query derived from this specification and a particular
     /// information need or "task."
+    ///
+    /// A task's query may contain more than one name, separated by `|`, in
+    /// which case each is added to the query as an alternate name.
     fn query(&self, task: &Task) -> Query {
-        Query::new()
-            .name(&task.query)
-            .name_scorer(self.scorer.clone())
-            .similarity(self.sim.clone())
+        task.query
+            .split('|')
+            .map(str::trim)
+            .fold(Query::new(), |query, name| query.name_or(name))
+            .name_scorer(self.scorer.as_ref().map(SpecScorer::name_scorer))
+            .similarity(self.sim)
+            .combine(self.combine)
             .size(self.result_size)
+            .rescore_window(self.window)
+    }
+
+    /// Check that the given index was created with this specification's
+    /// index settings. If not, the returned error lists every setting that
+    /// differs.
+    pub fn check_index(&self, idx: &Index) -> anyhow::Result<()> {
+        let analyzer = idx.analyzer()?;
+        let mut mismatches = vec![];
+        if analyzer.ngram_size() != self.ngram_size {
+            mismatches.push(format!(
+                "ngram size is {}, but {} is required",
+                analyzer.ngram_size(),
+                self.ngram_size
+            ));
+        }
+        if analyzer.ngram_type() != self.ngram_type {
+            mismatches.push(format!(
+                "ngram type is {}, but {} is required",
+                analyzer.ngram_type(),
+                self.ngram_type
+            ));
+        }
+        if !mismatches.is_empty() {
+            anyhow::bail!(
+                "index at {} can't be used for {}: {}",
+                idx.index_dir().display(),
+                self,
+                mismatches.join(", ")
+            );
+        }
+        Ok(())
     }
 
     /// Either open or create an index suitable for this specification.
     ///
-    /// If no index exists in the expected sub-directory of `eval_dir`, then
-    /// a new index is created.
+    /// If no index exists in the expected sub-directory of `eval_dir`, if
+    /// creating the index there was interrupted, or if it was created by an
+    /// incompatible version, then a new index is created.
     fn index<P1: AsRef<Path>, P2: AsRef<Path>>(
         &self,
         data_dir: P1,
         eval_dir: P2,
     ) -> anyhow::Result<Index> {
+        let data_dir = data_dir.as_ref();
         let index_dir = self.index_dir(eval_dir.as_ref());
-        Ok(if index_dir.exists() {
-            Index::open(data_dir, index_dir)?
-        } else {
+        let create = || {
             IndexBuilder::new()
                 .ngram_size(self.ngram_size)
                 .ngram_type(self.ngram_type)
-                .create(data_dir, index_dir)?
-        })
+                .create(data_dir, &index_dir)
+        };
+        if !Index::exists(&index_dir) {
+            if index_dir.exists() {
+                log::warn!(
+                    "index at {} is incomplete, rebuilding it",
+                    index_dir.display(),
+                );
+            }
+            return Ok(create()?);
+        }
+        match Index::open(data_dir, &index_dir) {
+            Ok(idx) => Ok(idx),
+            Err(err) => match *err.kind() {
+                ErrorKind::VersionMismatch { expected, got } => {
+                    log::warn!(
+                        "index at {} has version {} but version {} is \
+                         required, rebuilding it",
+                        index_dir.display(),
+                        got,
+                        expected,
+                    );
+                    Ok(create()?)
+                }
+                _ => Err(err.into()),
+            },
+        }
     }
 
     /// The sub-directory of `eval_dir` in which to store this specification's
@@ -239,7 +378,19 @@ impl fmt::Display for Spec {
             self.ngram_type,
             self.sim,
             scorer,
-        )
+        )?;
+        // The default is omitted so that names from before combinations
+        // existed remain the same.
+        if self.combine != Combine::Replace {
+            write!(f, "_combine-{}", self.combine)?;
+        }
+        if self.window != DEFAULT_RESCORE_WINDOW {
+            write!(f, "_window-{}", self.window)?;
+        }
+        if self.sequel_boost {
+            write!(f, "_sequel-boost")?;
+        }
+        Ok(())
     }
 }
 
@@ -266,6 +417,9 @@ impl fmt::Display for Spec {
 /// each other. Namely, a specification that does really well on a smaller
 /// number of results might end up with a higher MRR than other specifications,
 /// but will have a lower ratio of successful searches.
+///
+/// The summary also includes the candidate window and the mean latency, so
+/// that the cost of a larger window can be weighed against its quality.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Summary {
     /// The specification name that this result is summarizing.
@@ -274,6 +428,15 @@ pub struct Summary {
     pub mrr: f64,
     /// The ratio of tasks that found an answer. The higher the better.
     pub found: f64,
+    /// The number of tasks that timed out. These count as not having found
+    /// an answer.
+    pub timeouts: u64,
+    /// The candidate window of the specification.
+    pub window: usize,
+    /// The mean number of candidates considered by each search.
+    pub mean_candidates: f64,
+    /// The mean time it took to execute each search, in seconds.
+    pub mean_seconds: f64,
 }
 
 impl Summary {
@@ -302,17 +465,28 @@ impl Summary {
         assert!(results.iter().all(|r| results[0].name == r.name));
 
         let mut precision_sum = 0.0;
-        let mut found = 0u64;
+        let (mut found, mut timeouts) = (0u64, 0u64);
+        let (mut candidates, mut seconds) = (0, 0.0);
         for r in results {
             precision_sum += r.rank.map_or(0.0, |rank| 1.0 / (rank as f64));
             if r.rank.is_some() {
                 found += 1;
             }
+            if r.timed_out {
+                timeouts += 1;
+            }
+            candidates += r.candidates;
+            seconds += r.duration_seconds;
         }
+        let len = results.len() as f64;
         Summary {
             name: results[0].name.clone(),
-            mrr: precision_sum / (results.len() as f64),
-            found: (found as f64) / (results.len() as f64),
+            mrr: precision_sum / len,
+            found: (found as f64) / len,
+            timeouts,
+            window: results[0].window,
+            mean_candidates: (candidates as f64) / len,
+            mean_seconds: seconds / len,
         }
     }
 }
@@ -344,6 +518,36 @@ pub struct TaskResult {
     pub rank: Option<u64>,
     /// The time it took to execute this query, in seconds.
     pub duration_seconds: f64,
+    /// Whether this query took longer than the task timeout, in which case
+    /// it has no rank.
+    #[serde(default)]
+    pub timed_out: bool,
+    /// The number of candidates fetched from the name index before
+    /// filtering and rescoring them.
+    #[serde(default = "default_window")]
+    pub window: usize,
+    /// The number of candidates considered by the search. When the answer
+    /// is missing and this is small, the name index is likely at fault.
+    #[serde(default)]
+    pub candidates: usize,
+    /// The number of candidates rejected by the query's filters.
+    #[serde(default)]
+    pub dropped_filter: usize,
+    /// The number of candidates cut because of the result size.
+    #[serde(default)]
+    pub dropped_size: usize,
+    /// The score of the lowest ranked result.
+    #[serde(default)]
+    pub lowest_score: Option<f64>,
+    /// The date of the IMDb data that the index was created from, if known.
+    /// Results are only comparable when they share the same data.
+    #[serde(default)]
+    pub data_snapshot: Option<String>,
+}
+
+/// The candidate window of results written before the window was recorded.
+fn default_window() -> usize {
+    DEFAULT_RESCORE_WINDOW
 }
 
 /// An evaluation is an iterator over all of the results of evaluating every
@@ -356,6 +560,23 @@ pub struct Evaluation<'s> {
     tasks: vec::IntoIter<Task>,
 }
 
+impl<'s> Evaluation<'s> {
+    /// Give up on any task that takes longer than the given duration, and
+    /// record it as timed out instead of ranking its results.
+    ///
+    /// Exhaustive searches are stopped once they exceed the timeout. Other
+    /// searches run to completion, but are recorded as timed out when they
+    /// exceeded it.
+    pub fn task_timeout(
+        mut self,
+        timeout: Option<Duration>,
+    ) -> Evaluation<'s> {
+        self.evaluator.searcher.max_scan_time(timeout);
+        self.evaluator.timeout = timeout;
+        self
+    }
+}
+
 impl<'s> Iterator for Evaluation<'s> {
     type Item = anyhow::Result<TaskResult>;
 
@@ -373,6 +594,9 @@ struct Evaluator<'s> {
     spec: &'s Spec,
     /// A handle to a searcher for an IMDb index.
     searcher: Searcher,
+    /// The longest time a single task may take. See
+    /// `Evaluation::task_timeout`.
+    timeout: Option<Duration>,
 }
 
 impl<'s> Evaluator<'s> {
@@ -380,21 +604,43 @@ impl<'s> Evaluator<'s> {
     /// evaluation.
     fn run(&mut self, task: &Task) -> anyhow::Result<TaskResult> {
         let start = Instant::now();
-        let rank = self.rank(task)?;
+        let (mut rank, diag) = self.rank(task)?;
         let duration = Instant::now().duration_since(start);
+        let timed_out =
+            diag.truncated || self.timeout.is_some_and(|t| duration > t);
+        if timed_out {
+            log::warn!(
+                "{}: query '{}' timed out after {:.3} secs",
+                self.spec,
+                task.query,
+                fractional_seconds(&duration)
+            );
+            rank = None;
+        }
         Ok(TaskResult {
             name: self.spec.to_string(),
             query: task.query.clone(),
             answer: task.answer.clone(),
             rank,
             duration_seconds: fractional_seconds(&duration),
+            timed_out,
+            window: self.spec.window,
+            candidates: diag.candidates,
+            dropped_filter: diag.dropped_by_filters(),
+            dropped_size: diag.dropped_size,
+            lowest_score: diag.lowest_score,
+            data_snapshot: self
+                .searcher
+                .index()
+                .data_snapshot()
+                .map(|snapshot| snapshot.to_string()),
         })
     }
 
     /// Execute the search for the given information need and determine the
     /// rank of the expected answer for the given information need. If the
     /// expected answer didn't appear in the search results, then `None` is
-    /// returned.
+    /// returned. Diagnostics about the search are returned as well.
     ///
     /// The rank of the answer is determined in exactly the way you might
     /// expect: if the answer appears as the Nth result in a search, then its
@@ -437,15 +683,23 @@ impl<'s> Evaluator<'s> {
     ///
     /// There are other strategies, but in general, we want to reward high
     /// precision rankers.
-    fn rank(&mut self, task: &Task) -> anyhow::Result<Option<u64>> {
-        let results = self.searcher.search(&self.spec.query(&task))?;
+    fn rank(
+        &mut self,
+        task: &Task,
+    ) -> anyhow::Result<(Option<u64>, SearchDiagnostics)> {
+        let (mut results, diag) =
+            self.searcher.search_diagnostics(&self.spec.query(task))?;
+        if self.spec.sequel_boost {
+            let name = task.query.split('|').next().unwrap_or("").trim();
+            self.searcher.boost_sequel(name, &mut results)?;
+        }
 
         let mut rank = results.len() as u64;
         let mut prev_score = None;
         let mut ranked: Vec<(u64, MediaEntity)> = vec![];
         for (i, scored) in results.into_iter().enumerate().rev() {
             let (score, entity) = scored.into_pair();
-            if prev_score.map_or(true, |s| !approx_eq(s, score)) {
+            if prev_score.is_none_or(|s| !approx_eq(s, score)) {
                 rank = i as u64 + 1;
                 prev_score = Some(score);
             }
@@ -455,10 +709,10 @@ impl<'s> Evaluator<'s> {
 
         for (rank, entity) in ranked {
             if entity.title().id == task.answer {
-                return Ok(Some(rank));
+                return Ok((Some(rank), diag));
             }
         }
-        Ok(None)
+        Ok((None, diag))
     }
 }
 
@@ -469,6 +723,102 @@ fn approx_eq(x1: f64, x2: f64) -> bool {
     (x1 - x2).abs() <= 0.0000000001
 }
 
+/// The name scorer used by a specification.
+///
+/// This is either one of the scorers built into imdb-index, or a custom scorer
+/// registered with `custom_scorer`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SpecScorer {
+    /// A built-in scorer.
+    Name(NameScorer),
+    /// The name of a custom scorer.
+    Custom(String),
+}
+
+impl SpecScorer {
+    /// The name scorer to put on queries for this scorer.
+    ///
+    /// Custom scorers are set on the searcher instead, but queries still need
+    /// a name scorer in order to use the name index at all.
+    fn name_scorer(&self) -> NameScorer {
+        match *self {
+            SpecScorer::Name(scorer) => scorer,
+            SpecScorer::Custom(_) => NameScorer::default(),
+        }
+    }
+}
+
+impl From<NameScorer> for SpecScorer {
+    fn from(scorer: NameScorer) -> SpecScorer {
+        SpecScorer::Name(scorer)
+    }
+}
+
+impl fmt::Display for SpecScorer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpecScorer::Name(ref scorer) => scorer.fmt(f),
+            SpecScorer::Custom(ref name) => write!(f, "custom:{}", name),
+        }
+    }
+}
+
+impl FromStr for SpecScorer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<SpecScorer> {
+        match s.strip_prefix("custom:") {
+            None => Ok(SpecScorer::Name(s.parse()?)),
+            Some(name) => {
+                if custom_scorer(name).is_none() {
+                    anyhow::bail!(
+                        "unknown custom scorer '{}', choose one of: {}",
+                        name,
+                        CUSTOM_SCORERS.join(", ")
+                    );
+                }
+                Ok(SpecScorer::Custom(name.to_string()))
+            }
+        }
+    }
+}
+
+/// The names of all custom scorers known to `custom_scorer`.
+pub const CUSTOM_SCORERS: &[&str] = &["bm15"];
+
+/// Return the custom scorer with the given name, if one exists.
+///
+/// This is a registry for experimenting with ranking functions. To evaluate a
+/// new one, implement `Scorer` for it, add it here and to `CUSTOM_SCORERS`,
+/// and then run an evaluation with `--scorer custom:<name>`.
+pub fn custom_scorer(name: &str) -> Option<Arc<dyn Scorer>> {
+    match name {
+        "bm15" => Some(Arc::new(BM15)),
+        _ => None,
+    }
+}
+
+/// BM15 is Okapi BM25 without document length normalization.
+#[derive(Debug)]
+struct BM15;
+
+impl Scorer for BM15 {
+    fn term_weight(
+        &self,
+        document_frequency: u64,
+        corpus: &CorpusStats,
+    ) -> f64 {
+        NameScorer::OkapiBM25.term_weight(document_frequency, corpus)
+    }
+
+    fn score(&self, term: &TermStats, _: &DocStats) -> f64 {
+        let k1 = 1.2;
+        let tf = term.frequency as f64;
+        let score = (tf * (k1 + 1.0)) / (tf + k1) * term.weight;
+        score.max(0.0)
+    }
+}
+
 /// Returns the number of seconds in this duration in fraction form.
 /// The number to the left of the decimal point is the number of seconds,
 /// and the number to the right is the number of milliseconds.
@@ -479,9 +829,36 @@ fn fractional_seconds(d: &Duration) -> f64 {
 
 #[cfg(test)]
 mod tests {
-    use imdb_index::{NameScorer, NgramType, Similarity};
+    use std::env;
+    use std::fs;
+
+    use imdb_index::{
+        Combine, Index, NameScorer, NgramType, Similarity,
+        DEFAULT_RESCORE_WINDOW,
+    };
 
-    use super::Spec;
+    use super::{Spec, SpecScorer, Summary, TaskResult};
+
+    #[test]
+    fn index_interrupted() {
+        let tmp = env::temp_dir().join("imdb-eval-test-index");
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp).unwrap();
+        }
+        let data_dir = "../data/test/small";
+        let spec = Spec::new();
+        spec.index(data_dir, &tmp).unwrap();
+        let index_dir = spec.index_dir(&tmp);
+        assert!(Index::exists(&index_dir));
+
+        // An index whose creation was interrupted has no config file, which
+        // is created again instead of failing to open.
+        fs::remove_file(index_dir.join("config.json")).unwrap();
+        assert!(!Index::exists(&index_dir));
+        spec.index(data_dir, &tmp).unwrap();
+        assert!(Index::exists(&index_dir));
+        fs::remove_dir_all(&tmp).unwrap();
+    }
 
     #[test]
     fn spec_printer() {
@@ -490,7 +867,10 @@ mod tests {
             ngram_size: 3,
             ngram_type: NgramType::Window,
             sim: Similarity::None,
-            scorer: Some(NameScorer::OkapiBM25),
+            scorer: Some(SpecScorer::Name(NameScorer::OkapiBM25)),
+            combine: Combine::Replace,
+            window: DEFAULT_RESCORE_WINDOW,
+            sequel_boost: false,
         };
         let expected =
             "size-30_ngram-3_ngram-type-window_sim-none_scorer-okapibm25";
@@ -502,8 +882,72 @@ mod tests {
             ngram_type: NgramType::Edge,
             sim: Similarity::Jaro,
             scorer: None,
+            combine: Combine::Replace,
+            window: DEFAULT_RESCORE_WINDOW,
+            sequel_boost: false,
         };
         let expected = "size-1_ngram-2_ngram-type-edge_sim-jaro_scorer-none";
         assert_eq!(spec.to_string(), expected);
+
+        let spec = spec.with_combine(Combine::weighted(0.7).unwrap());
+        let expected = "size-1_ngram-2_ngram-type-edge_sim-jaro_scorer-none\
+                        _combine-weighted:0.7";
+        assert_eq!(spec.to_string(), expected);
+
+        let spec = spec.with_window(50).unwrap();
+        let expected = "size-1_ngram-2_ngram-type-edge_sim-jaro_scorer-none\
+                        _combine-weighted:0.7_window-50";
+        assert_eq!(spec.to_string(), expected);
+
+        let spec = spec.with_sequel_boost(true);
+        let expected = "size-1_ngram-2_ngram-type-edge_sim-jaro_scorer-none\
+                        _combine-weighted:0.7_window-50_sequel-boost";
+        assert_eq!(spec.to_string(), expected);
+        assert!(Spec::new().with_window(0).is_err());
+    }
+
+    #[test]
+    fn spec_scorer() {
+        let scorer: SpecScorer = "tfidf".parse().unwrap();
+        assert_eq!(scorer, SpecScorer::Name(NameScorer::TFIDF));
+        let scorer: SpecScorer = "custom:bm15".parse().unwrap();
+        assert_eq!(scorer, SpecScorer::Custom("bm15".to_string()));
+        assert_eq!(scorer.to_string(), "custom:bm15");
+        assert!("custom:nope".parse::<SpecScorer>().is_err());
+        assert!("nope".parse::<SpecScorer>().is_err());
+
+        let spec = Spec::new().with_scorer(Some(scorer));
+        let expected =
+            "size-30_ngram-3_ngram-type-window_sim-none_scorer-custom:bm15";
+        assert_eq!(spec.to_string(), expected);
+    }
+
+    #[test]
+    fn summary_timeouts() {
+        let result = |rank: Option<u64>, timed_out: bool| TaskResult {
+            name: "spec".to_string(),
+            query: "query".to_string(),
+            answer: "tt0000001".to_string(),
+            rank,
+            duration_seconds: if timed_out { 2.0 } else { 0.5 },
+            timed_out,
+            window: DEFAULT_RESCORE_WINDOW,
+            candidates: 10,
+            dropped_filter: 0,
+            dropped_size: 0,
+            lowest_score: None,
+            data_snapshot: None,
+        };
+        let results = vec![
+            result(Some(1), false),
+            result(Some(2), false),
+            result(None, true),
+        ];
+        let summaries = Summary::from_task_results(&results);
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].timeouts, 1);
+        assert_eq!(summaries[0].mrr, 0.5);
+        assert_eq!(summaries[0].found, 2.0 / 3.0);
+        assert_eq!(summaries[0].mean_seconds, 1.0);
     }
 }
@@ -2,13 +2,15 @@ use std::env;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process;
-use std::result;
 use std::str::FromStr;
+use std::time::Duration;
 
-use imdb_index::{NameScorer, NgramType, Similarity};
+use imdb_index::{
+    Combine, Index, NameScorer, NgramType, Similarity, DEFAULT_RESCORE_WINDOW,
+};
 use lazy_static::lazy_static;
 
-use crate::eval::Spec;
+use crate::eval::{IndexDir, Spec, SpecScorer, CUSTOM_SCORERS};
 
 mod eval;
 mod logger;
@@ -41,18 +43,29 @@ fn try_main() -> anyhow::Result<()> {
         }
         return Ok(());
     }
+    let index_dir = match args.use_index {
+        None => IndexDir::Eval(&args.eval_dir),
+        Some(ref dir) => IndexDir::Shared(dir),
+    };
     run_eval(
         &args.data_dir,
-        &args.eval_dir,
-        args.truth.as_ref().map(|p| p.as_path()),
+        index_dir,
+        args.truth.as_deref(),
+        args.task_timeout,
         args.specs()?,
     )
 }
 
-/// Run an evaluation on the IMDb data in `data_dir`, and store any indexes
-/// created for the evaluation in `eval_dir`. If a path to truth data is given,
-/// then the information needs or "tasks" used for the evaluation are taken
-/// from that file, otherwise, a built-in truth data set is used.
+/// Run an evaluation on the IMDb data in `data_dir`, using the indexes given
+/// by `index_dir`. If a path to truth data is given, then the information
+/// needs or "tasks" used for the evaluation are taken from that file,
+/// otherwise, a built-in truth data set is used.
+///
+/// When a shared index is used, every spec is checked against it before any
+/// evaluation is run, so that a mismatch doesn't stop a run half way.
+///
+/// If a task timeout is given, then tasks that take longer are recorded as
+/// timed out, and the evaluation moves on.
 ///
 /// The specs given each describe the protocol for an evaluation. They each
 /// represent a configuration for how an IMDb index is built and how queries
@@ -60,8 +73,9 @@ fn try_main() -> anyhow::Result<()> {
 /// evaluate. That is, we want to find the "best" specification.
 fn run_eval(
     data_dir: &Path,
-    eval_dir: &Path,
+    index_dir: IndexDir,
     truth_path: Option<&Path>,
+    task_timeout: Option<Duration>,
     specs: Vec<Spec>,
 ) -> anyhow::Result<()> {
     if !data_dir.exists() {
@@ -71,13 +85,20 @@ fn run_eval(
             data_dir.display()
         );
     }
+    if let IndexDir::Shared(dir) = index_dir {
+        let idx = Index::open(data_dir, dir)?;
+        for spec in &specs {
+            spec.check_index(&idx)?;
+        }
+    }
 
     let mut wtr = csv::Writer::from_writer(io::stdout());
     for spec in &specs {
         let results = match truth_path {
-            None => spec.evaluate(data_dir, eval_dir)?,
-            Some(p) => spec.evaluate_with(data_dir, eval_dir, p)?,
-        };
+            None => spec.evaluate(data_dir, index_dir)?,
+            Some(p) => spec.evaluate_with(data_dir, index_dir, p)?,
+        }
+        .task_timeout(task_timeout);
         for result in results {
             wtr.serialize(result?)?;
             wtr.flush()?;
@@ -104,6 +125,7 @@ fn run_summarize(summarize: &Path) -> anyhow::Result<()> {
 
 #[derive(Debug)]
 struct Args {
+    combines: Vec<Combine>,
     data_dir: PathBuf,
     debug: bool,
     dry_run: bool,
@@ -111,10 +133,14 @@ struct Args {
     ngram_sizes: Vec<usize>,
     ngram_types: Vec<NgramType>,
     result_sizes: Vec<usize>,
-    scorers: Vec<Option<NameScorer>>,
+    scorers: Vec<Option<SpecScorer>>,
+    sequel_boosts: Vec<bool>,
     similarities: Vec<Similarity>,
     summarize: Option<PathBuf>,
+    task_timeout: Option<Duration>,
     truth: Option<PathBuf>,
+    use_index: Option<PathBuf>,
+    windows: Vec<usize>,
 }
 
 impl Args {
@@ -138,10 +164,10 @@ impl Args {
             matches,
             "scorer",
             vec![
-                OptionalNameScorer::from(NameScorer::OkapiBM25),
-                OptionalNameScorer::from(NameScorer::TFIDF),
-                OptionalNameScorer::from(NameScorer::Jaccard),
-                OptionalNameScorer::from(NameScorer::QueryRatio),
+                OptionalSpecScorer::from(NameScorer::OkapiBM25),
+                OptionalSpecScorer::from(NameScorer::TFIDF),
+                OptionalSpecScorer::from(NameScorer::Jaccard),
+                OptionalSpecScorer::from(NameScorer::QueryRatio),
             ],
         )?
         .into_iter()
@@ -150,6 +176,11 @@ impl Args {
         let ngram_types =
             parse_many_lossy(matches, "ngram-type", vec![NgramType::Window])?;
         Ok(Args {
+            combines: parse_many_lossy(
+                matches,
+                "combine",
+                vec![Combine::Replace],
+            )?,
             data_dir,
             debug: matches.is_present("debug"),
             dry_run: matches.is_present("dry-run"),
@@ -158,9 +189,31 @@ impl Args {
             ngram_types,
             result_sizes: parse_many_lossy(matches, "result-size", vec![30])?,
             scorers,
+            sequel_boosts: if matches.is_present("sequel-boost") {
+                vec![false, true]
+            } else {
+                vec![false]
+            },
             similarities,
             summarize: matches.value_of_os("summarize").map(PathBuf::from),
+            task_timeout: match matches.value_of_lossy("task-timeout") {
+                None => None,
+                Some(secs) => match Duration::try_from_secs_f64(secs.parse()?)
+                {
+                    Ok(timeout) if !timeout.is_zero() => Some(timeout),
+                    _ => anyhow::bail!(
+                        "task timeout {} is invalid, must be greater than 0",
+                        secs
+                    ),
+                },
+            },
             truth: matches.value_of_os("truth").map(PathBuf::from),
+            use_index: matches.value_of_os("use-index").map(PathBuf::from),
+            windows: parse_many_lossy(
+                matches,
+                "window",
+                vec![DEFAULT_RESCORE_WINDOW],
+            )?,
         })
     }
 
@@ -182,7 +235,7 @@ impl Args {
         }
         for spec in specs2.drain(..) {
             for sim in &self.similarities {
-                specs1.push(spec.clone().with_similarity(sim.clone()));
+                specs1.push(spec.clone().with_similarity(*sim));
             }
         }
         for spec in specs1.drain(..) {
@@ -192,10 +245,25 @@ impl Args {
         }
         for spec in specs2.drain(..) {
             for ngram_type in &self.ngram_types {
-                specs1.push(spec.clone().with_ngram_type(ngram_type.clone()));
+                specs1.push(spec.clone().with_ngram_type(*ngram_type));
+            }
+        }
+        for spec in specs1.drain(..) {
+            for &combine in &self.combines {
+                specs2.push(spec.clone().with_combine(combine));
+            }
+        }
+        for spec in specs2.drain(..) {
+            for &window in &self.windows {
+                specs1.push(spec.clone().with_window(window)?);
+            }
+        }
+        for spec in specs1.drain(..) {
+            for &boost in &self.sequel_boosts {
+                specs2.push(spec.clone().with_sequel_boost(boost));
             }
         }
-        Ok(specs1)
+        Ok(specs2)
     }
 }
 
@@ -216,9 +284,14 @@ fn app() -> clap::App<'static, 'static> {
             env::temp_dir().join("imdb-rename");
         static ref DEFAULT_EVAL_DIR: PathBuf =
             env::temp_dir().join("imdb-rename-eval");
+        static ref CUSTOM_SCORER_NAMES: Vec<String> = CUSTOM_SCORERS
+            .iter()
+            .map(|name| format!("custom:{}", name))
+            .collect();
         static ref POSSIBLE_SCORER_NAMES: Vec<&'static str> = {
             let mut names = NameScorer::possible_names().to_vec();
             names.insert(0, "none");
+            names.extend(CUSTOM_SCORER_NAMES.iter().map(|s| &**s));
             names
         };
     }
@@ -228,6 +301,17 @@ fn app() -> clap::App<'static, 'static> {
         .version(clap::crate_version!())
         .max_term_width(100)
         .setting(AppSettings::UnifiedHelpMessage)
+        .arg(Arg::with_name("combine")
+             .long("combine")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .help("Set how name scores are combined with the similarity \
+                    ranker function: 'replace', 'multiply' or \
+                    'weighted:W', where W in [0, 1] is the weight of the \
+                    similarity. An evaluation is performed for each \
+                    combination given. If none is given, 'replace' is \
+                    used."))
         .arg(Arg::with_name("data-dir")
              .long("data-dir")
              .env("IMDB_RENAME_DATA_DIR")
@@ -280,7 +364,17 @@ fn app() -> clap::App<'static, 'static> {
              .possible_values(&POSSIBLE_SCORER_NAMES)
              .help("Set the name scorer function to use. An evaluation is \
                     performed for each name function given. By default, \
-                    all name scorers are used, except for 'none'."))
+                    all built-in name scorers are used, except for 'none'. \
+                    Custom scorers, named 'custom:<name>', are registered \
+                    in the source of imdb-eval for experimenting with new \
+                    ranking functions."))
+        .arg(Arg::with_name("sequel-boost")
+             .long("sequel-boost")
+             .help("Also perform each evaluation with results boosted the \
+                    way imdb-rename boosts them when a name ends with a \
+                    number, like 'john wick 3'. This compares the boost \
+                    with plain search on the sequel tasks of the truth \
+                    data."))
         .arg(Arg::with_name("sim")
              .long("sim")
              .takes_value(true)
@@ -295,45 +389,69 @@ fn app() -> clap::App<'static, 'static> {
              .takes_value(true)
              .number_of_values(1)
              .help("Print summary statistics from an evaluation run."))
+        .arg(Arg::with_name("task-timeout")
+             .long("task-timeout")
+             .takes_value(true)
+             .value_name("SECS")
+             .help("Give up on any single task that takes longer than SECS \
+                    seconds. Such tasks are recorded without a rank and \
+                    with timed_out set, and the evaluation continues. \
+                    Exhaustive searches are stopped once they exceed the \
+                    timeout."))
         .arg(Arg::with_name("truth")
              .long("truth")
              .takes_value(true)
              .help("A file path containing evaluation truth data. By default, \
                     an evaluation uses truth data embedded in imdb-rename."))
+        .arg(Arg::with_name("use-index")
+             .long("use-index")
+             .takes_value(true)
+             .value_name("DIR")
+             .help("Use the existing index in DIR, such as the one created \
+                    by imdb-rename, instead of creating an index for each \
+                    evaluation in --eval-dir. Every evaluation must use the \
+                    ngram size and type that the index was created with."))
+        .arg(Arg::with_name("window")
+             .long("window")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .help("Set the number of candidates fetched from the name index \
+                    before filtering and rescoring them. An evaluation is \
+                    performed for each window given. If no window is given, \
+                    a default of 1000 is used."))
 }
 
-/// An optional name scorer is a `NameScorer` that may be absent.
+/// An optional spec scorer is a `SpecScorer` that may be absent.
 ///
 /// We define a type for it to make parsing it easier.
 #[derive(Debug)]
-struct OptionalNameScorer(Option<NameScorer>);
+struct OptionalSpecScorer(Option<SpecScorer>);
 
-impl FromStr for OptionalNameScorer {
-    type Err = imdb_index::Error;
+impl FromStr for OptionalSpecScorer {
+    type Err = anyhow::Error;
 
-    fn from_str(
-        s: &str,
-    ) -> result::Result<OptionalNameScorer, imdb_index::Error> {
+    fn from_str(s: &str) -> anyhow::Result<OptionalSpecScorer> {
         let opt = if s == "none" { None } else { Some(s.parse()?) };
-        Ok(OptionalNameScorer(opt))
+        Ok(OptionalSpecScorer(opt))
     }
 }
 
-impl From<NameScorer> for OptionalNameScorer {
-    fn from(scorer: NameScorer) -> OptionalNameScorer {
-        OptionalNameScorer(Some(scorer))
+impl From<NameScorer> for OptionalSpecScorer {
+    fn from(scorer: NameScorer) -> OptionalSpecScorer {
+        OptionalSpecScorer(Some(SpecScorer::from(scorer)))
     }
 }
 
 /// Parse a sequence of values from clap.
-fn parse_many_lossy<
-    E: std::error::Error + Send + Sync + 'static,
-    T: FromStr<Err = E>,
->(
+fn parse_many_lossy<E, T: FromStr<Err = E>>(
     matches: &clap::ArgMatches,
     name: &str,
     default: Vec<T>,
-) -> anyhow::Result<Vec<T>> {
+) -> anyhow::Result<Vec<T>>
+where
+    anyhow::Error: From<E>,
+{
     let strs = match matches.values_of_lossy(name) {
         None => return Ok(default),
         Some(strs) => strs,
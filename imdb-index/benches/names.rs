@@ -0,0 +1,102 @@
+/*!
+Benchmarks for building and searching the name index, and for resolving the
+titles it returns.
+
+Every benchmark runs over a synthetic corpus generated by
+`imdb_index::corpus`, which is only exported with the `bench` feature. The
+number of titles in the corpus defaults to 100,000 and can be changed with the
+`IMDB_BENCH_TITLES` environment variable.
+*/
+
+use std::env;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use imdb_index::corpus::Corpus;
+use imdb_index::{Index, IndexBuilder, NameQuery};
+
+const DEFAULT_TITLES: usize = 100_000;
+
+fn corpus() -> Corpus {
+    let titles = env::var("IMDB_BENCH_TITLES")
+        .ok()
+        .map(|n| n.parse().expect("IMDB_BENCH_TITLES must be an integer"))
+        .unwrap_or(DEFAULT_TITLES);
+    Corpus::new(titles)
+}
+
+fn tempdir(name: &str) -> std::path::PathBuf {
+    let dir = env::temp_dir().join(format!("imdb-index-bench-{}", name));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn build(data_dir: &Path) -> Index {
+    IndexBuilder::new().create(data_dir, data_dir.join("index")).unwrap()
+}
+
+fn index_build(c: &mut Criterion) {
+    let dir = tempdir("build");
+    corpus().write(&dir).unwrap();
+
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    group.bench_function("names", |b| b.iter(|| build(&dir)));
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    let dir = tempdir("search");
+    let corpus = corpus();
+    corpus.write(&dir).unwrap();
+    let mut idx = build(&dir);
+
+    let (common, rare) = (corpus.word(0), corpus.word(5_000));
+    let queries = [
+        // Every ngram of the most common word is a stop word by default, so
+        // stop words are disabled to make this search do any work at all.
+        (
+            "common",
+            NameQuery::new(&common).with_size(30).with_stop_word_ratio(0.0),
+        ),
+        ("rare", NameQuery::new(&rare).with_size(30)),
+        // The rare word drives the search while the postings of the common
+        // word are skipped to each candidate it yields.
+        (
+            "mixed",
+            NameQuery::new(&format!("{} {}", common, rare)).with_size(30),
+        ),
+    ];
+    let mut group = c.benchmark_group("search");
+    for (name, query) in &queries {
+        group.bench_function(*name, |b| b.iter(|| idx.search(query).unwrap()));
+    }
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let dir = tempdir("lookup");
+    let corpus = corpus();
+    corpus.write(&dir).unwrap();
+    let mut idx = build(&dir);
+
+    // Resolving the candidates of a search by name reads one record of
+    // title.basics for each of them.
+    let ids: Vec<String> =
+        (1..=1_000).map(|i| format!("tt{:07}", i)).collect();
+    let mut group = c.benchmark_group("lookup");
+    group.bench_function("titles", |b| {
+        b.iter(|| {
+            for id in &ids {
+                idx.title(id).unwrap().unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, index_build, search, lookup);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+#![no_main]
+
+use imdb_index::Query;
+use libfuzzer_sys::fuzz_target;
+
+// Parsing arbitrary input must never panic, and every query that parses must
+// print to a string that parses back into the same query.
+fuzz_target!(|data: &[u8]| {
+    let qstr = match std::str::from_utf8(data) {
+        Err(_) => return,
+        Ok(qstr) => qstr,
+    };
+    let query: Query = match qstr.parse() {
+        Err(_) => return,
+        Ok(query) => query,
+    };
+    let printed = query.to_string();
+    let reparsed: Query = match printed.parse() {
+        Err(err) => {
+            panic!("{:?} printed as {:?} failed: {}", qstr, printed, err)
+        }
+        Ok(reparsed) => reparsed,
+    };
+    assert_eq!(query, reparsed, "{:?} printed as {:?}", qstr, printed);
+});
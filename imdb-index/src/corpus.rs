@@ -0,0 +1,253 @@
+/*!
+A generator of synthetic IMDb data, for benchmarks and tests.
+
+This module is not part of the public API of this crate. It is only compiled
+for tests, and exported when the `bench` feature is enabled, so that
+benchmarks, which are compiled as a separate crate, can use it.
+*/
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::util::{IMDB_AKAS, IMDB_BASICS, IMDB_EPISODE, IMDB_RATINGS};
+
+/// Syllables from which the words of a corpus are built.
+const SYLLABLES: &[&str] = &[
+    "ba", "ko", "ri", "ste", "mun", "la", "dor", "fi", "zan", "qu", "pel",
+    "tra", "ni", "vo", "gar", "she", "lu", "mi", "tho", "rek",
+];
+
+/// A specification of a synthetic corpus of titles.
+///
+/// Titles are built from a fixed vocabulary of made up words, where the
+/// frequency of each word follows a Zipf distribution. That is, the word with
+/// rank `k` occurs with probability proportional to `1 / k^s`. This gives the
+/// name index a realistic mix of very common ngrams and very rare ones.
+///
+/// Generating a corpus is deterministic for a given specification.
+#[derive(Clone, Debug)]
+pub struct Corpus {
+    titles: usize,
+    vocabulary: usize,
+    exponent: f64,
+    seed: u64,
+}
+
+impl Corpus {
+    /// Create a specification for a corpus with the given number of titles,
+    /// a vocabulary of 10,000 words and a Zipf exponent of `1.0`.
+    pub fn new(titles: usize) -> Corpus {
+        Corpus { titles, vocabulary: 10_000, exponent: 1.0, seed: 1 }
+    }
+
+    /// Set the number of distinct words in the corpus.
+    ///
+    /// This panics if the given size is `0`.
+    pub fn vocabulary(&mut self, size: usize) -> &mut Corpus {
+        assert!(size > 0, "vocabulary must not be empty");
+        self.vocabulary = size;
+        self
+    }
+
+    /// Set the exponent of the Zipf distribution of words. Bigger exponents
+    /// make common words more common.
+    pub fn exponent(&mut self, exponent: f64) -> &mut Corpus {
+        self.exponent = exponent;
+        self
+    }
+
+    /// Set the seed used to generate titles.
+    pub fn seed(&mut self, seed: u64) -> &mut Corpus {
+        self.seed = seed;
+        self
+    }
+
+    /// Return the word with the given rank, where the word with rank `0` is
+    /// the most common. The word returned is the same for every corpus, even
+    /// if it's beyond the vocabulary of this corpus.
+    pub fn word(&self, rank: usize) -> String {
+        // Writing the rank in base len(SYLLABLES) gives every rank a distinct
+        // word, and leading with a fixed syllable keeps short words from
+        // being substrings of longer ones too often.
+        let mut word = String::from("e");
+        let mut n = rank;
+        loop {
+            word.push_str(SYLLABLES[n % SYLLABLES.len()]);
+            n /= SYLLABLES.len();
+            if n == 0 {
+                break;
+            }
+        }
+        word
+    }
+
+    /// Generate the titles in this corpus, where each title consists of
+    /// between one and five words.
+    pub fn titles(&self) -> Vec<String> {
+        let cdf = self.cdf();
+        let mut rng = XorShift::new(self.seed);
+        (0..self.titles)
+            .map(|_| {
+                let len = 1 + (rng.next() % 5) as usize;
+                let words: Vec<String> = (0..len)
+                    .map(|_| {
+                        let p = rng.next_f64();
+                        let rank = cdf.partition_point(|&c| c < p);
+                        self.word(rank.min(self.vocabulary - 1))
+                    })
+                    .collect();
+                words.join(" ")
+            })
+            .collect()
+    }
+
+    /// Write this corpus to the given directory as IMDb data files, suitable
+    /// for building an index with `IndexBuilder::create`.
+    ///
+    /// Every title is a movie. No AKA, episode or rating records are written.
+    pub fn write<P: AsRef<Path>>(&self, data_dir: P) -> Result<()> {
+        let data_dir = data_dir.as_ref();
+        let create = |name: &str| -> Result<BufWriter<File>> {
+            let path = data_dir.join(name);
+            let file =
+                File::create(&path).map_err(|e| Error::io_path(e, &path))?;
+            Ok(BufWriter::new(file))
+        };
+        let headers = [
+            (
+                IMDB_BASICS,
+                "tconst\ttitleType\tprimaryTitle\toriginalTitle\tisAdult\t\
+                 startYear\tendYear\truntimeMinutes\tgenres",
+            ),
+            (
+                IMDB_AKAS,
+                "titleId\tordering\ttitle\tregion\tlanguage\ttypes\t\
+                 attributes\tisOriginalTitle",
+            ),
+            (
+                IMDB_EPISODE,
+                "tconst\tparentTconst\tseasonNumber\tepisodeNumber",
+            ),
+            (IMDB_RATINGS, "tconst\taverageRating\tnumVotes"),
+        ];
+        for &(name, header) in &headers[1..] {
+            let mut wtr = create(name)?;
+            writeln!(wtr, "{}", header).map_err(Error::io)?;
+            wtr.flush().map_err(Error::io)?;
+        }
+
+        let mut wtr = create(headers[0].0)?;
+        writeln!(wtr, "{}", headers[0].1).map_err(Error::io)?;
+        for (i, title) in self.titles().iter().enumerate() {
+            writeln!(
+                wtr,
+                "tt{:07}\tmovie\t{}\t{}\t0\t{}\t\\N\t90\tDrama",
+                i + 1,
+                title,
+                title,
+                1900 + i % 120,
+            )
+            .map_err(Error::io)?;
+        }
+        wtr.flush().map_err(Error::io)?;
+        Ok(())
+    }
+
+    /// Returns the cumulative distribution of word ranks.
+    fn cdf(&self) -> Vec<f64> {
+        let weights: Vec<f64> = (1..=self.vocabulary)
+            .map(|k| 1.0 / (k as f64).powf(self.exponent))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        let mut sum = 0.0;
+        weights
+            .iter()
+            .map(|w| {
+                sum += w / total;
+                sum
+            })
+            .collect()
+    }
+}
+
+/// A small and fast pseudo-random number generator, which is plenty for
+/// generating test data and saves a dependency.
+#[derive(Debug)]
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // The state must never be zero.
+        XorShift(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Corpus;
+    use crate::index::tests::TempDir;
+    use crate::index::{IndexBuilder, NameQuery};
+
+    #[test]
+    fn zipf() {
+        let corpus = Corpus::new(5_000).vocabulary(100).clone();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for title in corpus.titles() {
+            for word in title.split(' ') {
+                *counts.entry(word.to_string()).or_insert(0) += 1;
+            }
+        }
+        let count = |rank| counts.get(&corpus.word(rank)).copied();
+        // With an exponent of 1, the most common word is about twice as
+        // common as the second most common word, and ten times as common as
+        // the tenth.
+        let (first, second) = (count(0).unwrap(), count(1).unwrap());
+        assert!(first > second * 3 / 2, "{} vs {}", first, second);
+        assert!(first > count(9).unwrap() * 5);
+        assert_eq!(count(100), None);
+
+        assert_eq!(corpus.titles(), corpus.titles());
+        assert_ne!(corpus.word(1), corpus.word(21));
+    }
+
+    #[test]
+    fn index() {
+        let tmp = TempDir::new("imdb-index-test-corpus").unwrap();
+        let corpus = Corpus::new(1_000);
+        corpus.write(tmp.path()).unwrap();
+        let mut idx = IndexBuilder::new()
+            .create(tmp.path(), tmp.path().join("index"))
+            .unwrap();
+
+        let rare = corpus.word(500);
+        let results =
+            idx.search(&NameQuery::new(&rare).with_size(1000)).unwrap();
+        assert!(!results.is_empty());
+        assert!(results.as_slice()[0].value().title.contains(&rare));
+        // Every ngram of the most common word is a stop word, so stop words
+        // must be disabled to find anything.
+        let common = NameQuery::new(&corpus.word(0))
+            .with_size(1000)
+            .with_stop_word_ratio(0.0);
+        let common = idx.search(&common).unwrap();
+        assert!(common.as_slice()[0].value().title.contains(&corpus.word(0)));
+    }
+}
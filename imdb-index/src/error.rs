@@ -41,6 +41,10 @@ impl Error {
         Error { kind: ErrorKind::UnknownSimilarity(unk.as_ref().to_string()) }
     }
 
+    pub(crate) fn unknown_combine<T: AsRef<str>>(unk: T) -> Error {
+        Error { kind: ErrorKind::UnknownCombine(unk.as_ref().to_string()) }
+    }
+
     pub(crate) fn unknown_directive<T: AsRef<str>>(unk: T) -> Error {
         Error { kind: ErrorKind::UnknownDirective(unk.as_ref().to_string()) }
     }
@@ -57,6 +61,24 @@ impl Error {
         Error { kind: ErrorKind::VersionMismatch { expected, got } }
     }
 
+    pub(crate) fn read_only<P: AsRef<Path>>(index_dir: P) -> Error {
+        Error {
+            kind: ErrorKind::ReadOnlyIndex(index_dir.as_ref().to_path_buf()),
+        }
+    }
+
+    pub(crate) fn corrupt<P: AsRef<Path>, T: AsRef<str>>(
+        path: P,
+        msg: T,
+    ) -> Error {
+        Error {
+            kind: ErrorKind::Corrupt {
+                path: path.as_ref().to_path_buf(),
+                msg: msg.as_ref().to_string(),
+            },
+        }
+    }
+
     pub(crate) fn csv(err: csv::Error) -> Error {
         Error { kind: ErrorKind::Csv(err.to_string()) }
     }
@@ -105,7 +127,12 @@ impl fmt::Display for Error {
 }
 
 /// The specific kind of error that can occur.
+///
+/// This enum may grow additional variants, so clients can't count on
+/// exhaustive matching. (Otherwise, adding a new variant could break
+/// existing code.)
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ErrorKind {
     /// An index version mismatch. This error occurs when the version of the
     /// index is different from the version supported by this version of
@@ -119,6 +146,11 @@ pub enum ErrorKind {
         /// The actual version of the index on disk.
         got: u64,
     },
+    /// An attempt was made to create an index in a directory that cannot be
+    /// written to, e.g., because it is on a read-only file system.
+    ///
+    /// The data provided is the index directory.
+    ReadOnlyIndex(PathBuf),
     /// An error parsing the type of a title.
     ///
     /// The data provided is the unrecognized title type.
@@ -135,6 +167,11 @@ pub enum ErrorKind {
     ///
     /// The data provided is the unrecognized name.
     UnknownSimilarity(String),
+    /// An error parsing a way of combining scores, e.g., an unrecognized
+    /// name or a weight outside of `[0, 1]`.
+    ///
+    /// The data provided is the unrecognized combination.
+    UnknownCombine(String),
     /// An error parsing the name of a directive from a free-form query.
     ///
     /// The data provided is the unrecognized name.
@@ -143,6 +180,14 @@ pub enum ErrorKind {
     /// have occurred. Generally, these errors correspond to bugs in this
     /// library.
     Bug(String),
+    /// An index file is corrupt or truncated, e.g., because the disk filled
+    /// up while the index was being created.
+    Corrupt {
+        /// The path of the corrupt file.
+        path: PathBuf,
+        /// A description of what is wrong with the file.
+        msg: String,
+    },
     /// An error occurred while reading/writing the index config.
     Config(String),
     /// An error that occured while writing or reading CSV data.
@@ -159,13 +204,35 @@ pub enum ErrorKind {
     },
     /// An error occurred while parsing a number in a free-form query.
     Number(Box<dyn std::error::Error + Send + Sync>),
-    /// Hints that destructuring should not be exhaustive.
+}
+
+impl ErrorKind {
+    /// Return a short identifier for this kind of error.
     ///
-    /// This enum may grow additional variants, so this makes sure clients
-    /// don't count on exhaustive matching. (Otherwise, adding a new variant
-    /// could break existing code.)
-    #[doc(hidden)]
-    __Nonexhaustive,
+    /// Codes are lowercase `snake_case` strings, e.g., `version_mismatch`,
+    /// `unknown_scorer` or `io`. Unlike the error message, the code for a
+    /// particular kind of error is part of this crate's public API and will
+    /// not change, which makes it suitable for reporting errors to programs,
+    /// e.g., in JSON output or across an FFI boundary.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ErrorKind::VersionMismatch { .. } => "version_mismatch",
+            ErrorKind::ReadOnlyIndex(_) => "read_only_index",
+            ErrorKind::UnknownTitle(_) => "unknown_title",
+            ErrorKind::UnknownScorer(_) => "unknown_scorer",
+            ErrorKind::UnknownNgramType(_) => "unknown_ngram_type",
+            ErrorKind::UnknownSimilarity(_) => "unknown_similarity",
+            ErrorKind::UnknownCombine(_) => "unknown_combine",
+            ErrorKind::UnknownDirective(_) => "unknown_directive",
+            ErrorKind::Bug(_) => "bug",
+            ErrorKind::Corrupt { .. } => "corrupt",
+            ErrorKind::Config(_) => "config",
+            ErrorKind::Csv(_) => "csv",
+            ErrorKind::Fst(_) => "fst",
+            ErrorKind::Io { .. } => "io",
+            ErrorKind::Number(_) => "number",
+        }
+    }
 }
 
 impl fmt::Display for ErrorKind {
@@ -177,6 +244,11 @@ impl fmt::Display for ErrorKind {
                            but got version {}. Please rebuild the index.",
                 expected, got
             ),
+            ErrorKind::ReadOnlyIndex(ref dir) => write!(
+                f,
+                "cannot create an index in {} because it is read-only",
+                dir.display()
+            ),
             ErrorKind::UnknownTitle(ref unk) => {
                 write!(f, "unrecognized title type: '{}'", unk)
             }
@@ -189,6 +261,9 @@ impl fmt::Display for ErrorKind {
             ErrorKind::UnknownSimilarity(ref unk) => {
                 write!(f, "unrecognized similarity function: '{}'", unk)
             }
+            ErrorKind::UnknownCombine(ref unk) => {
+                write!(f, "unrecognized score combination: '{}'", unk)
+            }
             ErrorKind::UnknownDirective(ref unk) => {
                 write!(f, "unrecognized search directive: '{}'", unk)
             }
@@ -197,6 +272,13 @@ impl fmt::Display for ErrorKind {
                               https://github.com/BurntSushi/imdb-rename";
                 write!(f, "BUG: {}\n{}", msg, report)
             }
+            ErrorKind::Corrupt { ref path, ref msg } => write!(
+                f,
+                "index file {} is corrupt or truncated ({}). \
+                 Please rebuild the index.",
+                path.display(),
+                msg
+            ),
             ErrorKind::Config(ref msg) => write!(f, "config error: {}", msg),
             ErrorKind::Csv(ref msg) => write!(f, "{}", msg),
             ErrorKind::Fst(ref msg) => write!(f, "fst error: {}", msg),
@@ -205,7 +287,91 @@ impl fmt::Display for ErrorKind {
                 write!(f, "{}", p.display())
             }
             ErrorKind::Number(_) => write!(f, "error parsing number"),
-            ErrorKind::__Nonexhaustive => panic!("invalid error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+
+    use super::ErrorKind;
+
+    /// Return one value of every error kind along with its expected code.
+    ///
+    /// The match below has no wildcard arm, so adding a variant to
+    /// `ErrorKind` without listing it here fails to compile.
+    fn all_kinds() -> Vec<(ErrorKind, &'static str)> {
+        let kinds = vec![
+            (
+                ErrorKind::VersionMismatch { expected: 1, got: 2 },
+                "version_mismatch",
+            ),
+            (ErrorKind::ReadOnlyIndex(PathBuf::from("x")), "read_only_index"),
+            (ErrorKind::UnknownTitle("x".into()), "unknown_title"),
+            (ErrorKind::UnknownScorer("x".into()), "unknown_scorer"),
+            (ErrorKind::UnknownNgramType("x".into()), "unknown_ngram_type"),
+            (ErrorKind::UnknownSimilarity("x".into()), "unknown_similarity"),
+            (ErrorKind::UnknownCombine("x".into()), "unknown_combine"),
+            (ErrorKind::UnknownDirective("x".into()), "unknown_directive"),
+            (ErrorKind::Bug("x".into()), "bug"),
+            (
+                ErrorKind::Corrupt {
+                    path: PathBuf::from("x"),
+                    msg: "x".into(),
+                },
+                "corrupt",
+            ),
+            (ErrorKind::Config("x".into()), "config"),
+            (ErrorKind::Csv("x".into()), "csv"),
+            (ErrorKind::Fst("x".into()), "fst"),
+            (
+                ErrorKind::Io {
+                    err: std::io::Error::from(std::io::ErrorKind::Other),
+                    path: None,
+                },
+                "io",
+            ),
+            (
+                ErrorKind::Number(Box::new("x".parse::<u32>().unwrap_err())),
+                "number",
+            ),
+        ];
+        for (kind, _) in &kinds {
+            match *kind {
+                ErrorKind::VersionMismatch { .. }
+                | ErrorKind::ReadOnlyIndex(_)
+                | ErrorKind::UnknownTitle(_)
+                | ErrorKind::UnknownScorer(_)
+                | ErrorKind::UnknownNgramType(_)
+                | ErrorKind::UnknownSimilarity(_)
+                | ErrorKind::UnknownCombine(_)
+                | ErrorKind::UnknownDirective(_)
+                | ErrorKind::Bug(_)
+                | ErrorKind::Corrupt { .. }
+                | ErrorKind::Config(_)
+                | ErrorKind::Csv(_)
+                | ErrorKind::Fst(_)
+                | ErrorKind::Io { .. }
+                | ErrorKind::Number(_) => {}
+            }
+        }
+        kinds
+    }
+
+    #[test]
+    fn codes() {
+        let mut seen = HashSet::new();
+        for (kind, expected) in all_kinds() {
+            let code = kind.code();
+            assert_eq!(code, expected, "code changed for {:?}", kind);
+            assert!(
+                code.chars().all(|c| c.is_ascii_lowercase() || c == '_'),
+                "code is not snake_case: {}",
+                code
+            );
+            assert!(seen.insert(code), "duplicate code: {}", code);
         }
     }
 }
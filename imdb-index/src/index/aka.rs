@@ -1,22 +1,33 @@
-use std::io;
+use std::io::{self, Write};
 use std::iter;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use memmap::Mmap;
 
 use crate::error::{Error, Result};
-use crate::index::{csv_file, csv_mmap, id};
+use crate::index::writer::CursorWriter;
+use crate::index::{csv_file, footer, id, open_file};
 use crate::record::AKA;
-use crate::util::IMDB_AKAS;
+use crate::util::{csv_reader_builder, mmap_file, IMDB_AKAS};
 
 /// A name of the AKA record index file.
 ///
-/// This index represents a map from IMDb title id to a 64-bit integer. The
-/// 64-bit integer encodes two pieces of information: the number of alternate
-/// names for the title (high 16 bits) and the file offset at which the records
-/// appear in title.akas.tsv (low 48 bits).
+/// This index represents a map from IMDb title id to the position of that
+/// title's entry in `AKA_ENTRIES`.
 const AKAS: &str = "akas.fst";
 
+/// A name of the file containing an entry for every title with at least one
+/// alternate name, in the same order as the ids in `AKAS`.
+///
+/// Each entry is a pair of 64-bit little-endian integers: the file offset at
+/// which the title's records appear in title.akas.tsv, followed by the number
+/// of those records. The entries are followed by a footer.
+const AKA_ENTRIES: &str = "akas.entries.idx";
+
+/// The size, in bytes, of a single entry in `AKA_ENTRIES`.
+const ENTRY_SIZE: usize = 16;
+
 /// A handle to the AKA name index.
 ///
 /// The AKA index maps IMDb identifiers to a list of AKA records.
@@ -24,8 +35,13 @@ const AKAS: &str = "akas.fst";
 /// This index assumes that the underlying AKA CSV file is sorted by IMDb ID.
 #[derive(Debug)]
 pub struct Index {
-    akas: csv::Reader<io::Cursor<Mmap>>,
+    akas: SharedMmap,
+    /// The header row of the AKA data, which is read once when the index is
+    /// opened instead of every time records are looked up.
+    headers: csv::StringRecord,
     idx: id::IndexReader,
+    entries: Mmap,
+    entries_path: PathBuf,
 }
 
 impl Index {
@@ -36,14 +52,51 @@ impl Index {
         data_dir: P1,
         index_dir: P2,
     ) -> Result<Index> {
+        let entries_path = index_dir.as_ref().join(AKA_ENTRIES);
+        // We claim it is safe to open the following memory maps because we
+        // don't mutate them and no other process (should) either.
+        let entries = unsafe { mmap_file(&entries_path)? };
+        let payload = footer::check(&entries_path, &entries)?;
+        if payload.len() % ENTRY_SIZE != 0 {
+            return Err(Error::corrupt(
+                &entries_path,
+                format!(
+                    "{} bytes of entries is not a multiple of {}",
+                    payload.len(),
+                    ENTRY_SIZE
+                ),
+            ));
+        }
+        let akas = SharedMmap(Arc::new(unsafe {
+            mmap_file(data_dir.as_ref().join(IMDB_AKAS))?
+        }));
+        let headers = csv_reader_builder()
+            .from_reader(io::Cursor::new(akas.clone()))
+            .headers()
+            .map_err(Error::csv)?
+            .clone();
         Ok(Index {
-            // We claim it is safe to open the following memory map because we
-            // don't mutate them and no other process (should) either.
-            akas: unsafe { csv_mmap(data_dir.as_ref().join(IMDB_AKAS))? },
+            akas,
+            headers,
             idx: id::IndexReader::from_path(index_dir.as_ref().join(AKAS))?,
+            entries,
+            entries_path,
         })
     }
 
+    /// Check that the files of an AKA index exist and can be opened, without
+    /// reading any of them. This returns the same error that `open` would
+    /// for a missing file.
+    pub fn check<P1: AsRef<Path>, P2: AsRef<Path>>(
+        data_dir: P1,
+        index_dir: P2,
+    ) -> Result<()> {
+        open_file(data_dir.as_ref().join(IMDB_AKAS))?;
+        open_file(index_dir.as_ref().join(AKAS))?;
+        open_file(index_dir.as_ref().join(AKA_ENTRIES))?;
+        Ok(())
+    }
+
     /// Create an AKA index by reading the AKA data from the given data
     /// directory and writing the index to the corresponding index directory.
     pub fn create<P1: AsRef<Path>, P2: AsRef<Path>>(
@@ -55,57 +108,140 @@ impl Index {
 
         let rdr = csv_file(data_dir.join(IMDB_AKAS))?;
         let mut wtr = id::IndexSortedWriter::from_path(index_dir.join(AKAS))?;
-        let mut count = 0u64;
+        let mut entries =
+            CursorWriter::from_path(index_dir.join(AKA_ENTRIES))?;
+        let (mut titles, mut count) = (0u64, 0u64);
         for result in AKAIndexRecords::new(rdr) {
             let record = result?;
-            wtr.insert(&record.id, (record.count << 48) | record.offset)?;
+            wtr.insert(&record.id, titles)?;
+            write_entry(&mut entries, record.offset, record.count)?;
+            titles += 1;
             count += record.count;
         }
         wtr.finish()?;
+        entries.write_footer().map_err(Error::io)?;
+        entries.flush().map_err(Error::io)?;
 
         log::info!("{} alternate names indexed", count);
         Index::open(data_dir, index_dir)
     }
 
+    /// Return the number of AKA records for the given IMDb ID.
+    ///
+    /// This only consults the index, so no records are read.
+    pub fn count(&self, id: &[u8]) -> Result<u64> {
+        Ok(self.entry(id)?.map_or(0, |(_, count)| count))
+    }
+
     /// Return a (possibly empty) iterator over all AKA records for the given
     /// IMDb ID.
-    pub fn find(&mut self, id: &[u8]) -> Result<AKARecordIter> {
-        match self.idx.get(id) {
+    ///
+    /// The iterator has its own cursor into the AKA data, so it doesn't
+    /// borrow this index.
+    pub fn find(&self, id: &[u8]) -> Result<AKARecordIter> {
+        match self.entry(id)? {
             None => Ok(AKARecordIter(None)),
-            Some(v) => {
-                let count = (v >> 48) as usize;
-                let offset = v & ((1 << 48) - 1);
-
+            Some((offset, count)) => {
+                let count = match usize::try_from(count) {
+                    Ok(count) => count,
+                    Err(_) => bug!("too many alternate names ({})", count),
+                };
+
+                // The header row is needed to deserialize records. Setting
+                // it keeps the new reader from parsing it again when seeking.
+                let mut rdr = csv_reader_builder()
+                    .from_reader(io::Cursor::new(self.akas.clone()));
+                rdr.set_headers(self.headers.clone());
                 let mut pos = csv::Position::new();
                 pos.set_byte(offset);
-                self.akas.seek(pos).map_err(Error::csv)?;
-
-                Ok(AKARecordIter(Some(self.akas.deserialize().take(count))))
+                rdr.seek(pos).map_err(Error::csv)?;
+                Ok(AKARecordIter(Some(rdr.into_deserialize().take(count))))
             }
         }
     }
 }
 
+impl Index {
+    /// Return the file offset and number of AKA records for the given IMDb
+    /// ID, or `None` if it has no AKA records.
+    ///
+    /// If the ID index points past the end of the entries, then the index is
+    /// corrupt and an error is returned.
+    fn entry(&self, id: &[u8]) -> Result<Option<(u64, u64)>> {
+        let i = match self.idx.get(id) {
+            None => return Ok(None),
+            Some(i) => i,
+        };
+        let payload = &self.entries[..self.entries.len() - footer::SIZE];
+        match read_entry(payload, i) {
+            Some(entry) => Ok(Some(entry)),
+            None => Err(Error::corrupt(
+                &self.entries_path,
+                format!(
+                    "missing entry {} for {:?}",
+                    i,
+                    String::from_utf8_lossy(id)
+                ),
+            )),
+        }
+    }
+}
+
+/// Write a single entry of `AKA_ENTRIES`.
+fn write_entry<W: io::Write>(
+    wtr: &mut CursorWriter<W>,
+    offset: u64,
+    count: u64,
+) -> Result<()> {
+    wtr.write_u64(offset).map_err(Error::io)?;
+    wtr.write_u64(count).map_err(Error::io)?;
+    Ok(())
+}
+
+/// Read the `i`th entry from the given entries (without their footer) as a
+/// pair of file offset and record count. This is the inverse of
+/// `write_entry`.
+///
+/// If no such entry exists, then `None` is returned.
+fn read_entry(entries: &[u8], i: u64) -> Option<(u64, u64)> {
+    let start = usize::try_from(i).ok()?.checked_mul(ENTRY_SIZE)?;
+    let entry = entries.get(start..start.checked_add(ENTRY_SIZE)?)?;
+    let offset = u64::from_le_bytes(entry[..8].try_into().unwrap());
+    let count = u64::from_le_bytes(entry[8..].try_into().unwrap());
+    Some((offset, count))
+}
+
+/// A memory map of the AKA data that can be cheaply shared by many readers.
+#[derive(Clone, Debug)]
+struct SharedMmap(Arc<Mmap>);
+
+impl AsRef<[u8]> for SharedMmap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 /// An iterator over AKA records for a single IMDb title.
 ///
 /// This iterator is constructed via the `aka::Index::find` method.
 ///
 /// This iterator may yield no titles.
 ///
-/// The lifetime `'r` refers to the lifetime of the underlying AKA index
-/// reader.
-pub struct AKARecordIter<'r>(
-    Option<iter::Take<csv::DeserializeRecordsIter<'r, io::Cursor<Mmap>, AKA>>>,
+/// The iterator owns its position in the underlying AKA data, so other
+/// lookups may be made on the index while it's in use.
+pub struct AKARecordIter(
+    Option<
+        iter::Take<
+            csv::DeserializeRecordsIntoIter<io::Cursor<SharedMmap>, AKA>,
+        >,
+    >,
 );
 
-impl<'r> Iterator for AKARecordIter<'r> {
+impl Iterator for AKARecordIter {
     type Item = Result<AKA>;
 
     fn next(&mut self) -> Option<Result<AKA>> {
-        let next = match self.0.as_mut().and_then(|it| it.next()) {
-            None => return None,
-            Some(next) => next,
-        };
+        let next = self.0.as_mut().and_then(|it| it.next())?;
         match next {
             Ok(next) => Some(Ok(next)),
             Err(err) => Some(Err(Error::csv(err))),
@@ -172,10 +308,10 @@ impl<R: io::Read> Iterator for AKAIndexRecords<R> {
         }
         // Only initialize the record if this is our first go at it.
         // Otherwise, previous call leaves next record in `AKAIndexRecord`.
-        if self.record.is_empty() {
-            if !itry!(self.rdr.read_byte_record(&mut self.record)) {
-                return None;
-            }
+        if self.record.is_empty()
+            && !itry!(self.rdr.read_byte_record(&mut self.record))
+        {
+            return None;
         }
         let mut irecord = AKAIndexRecord {
             id: self.record[0].to_vec(),
@@ -183,7 +319,7 @@ impl<R: io::Read> Iterator for AKAIndexRecords<R> {
             count: 1,
         };
         while itry!(self.rdr.read_byte_record(&mut self.record)) {
-            if irecord.id != &self.record[0] {
+            if irecord.id != self.record[0] {
                 break;
             }
             irecord.count += 1;
@@ -199,6 +335,7 @@ impl<R: io::Read> Iterator for AKAIndexRecords<R> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::index::tests::TestContext;
     use crate::util::csv_reader_builder;
 
     #[test]
@@ -305,4 +442,87 @@ tt0117021	1	Menno's Mind	US	\N	\N	\N	0";
         assert_eq!(records[0].id, b"tt0117021");
         assert_eq!(records[0].count, 1);
     }
+
+    /// Write the given (offset, count) pairs as entries and return the
+    /// entries without their footer.
+    fn entries(pairs: &[(u64, u64)]) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut wtr = CursorWriter::new(&mut buf);
+        for &(offset, count) in pairs {
+            write_entry(&mut wtr, offset, count).unwrap();
+        }
+        wtr.write_footer().unwrap();
+        let payload = footer::check(Path::new("test"), &buf).unwrap();
+        payload.to_vec()
+    }
+
+    quickcheck::quickcheck! {
+        fn entry_roundtrip(pairs: Vec<(u64, u64)>) -> bool {
+            let buf = entries(&pairs);
+            pairs.iter().enumerate().all(|(i, &pair)| {
+                read_entry(&buf, i as u64) == Some(pair)
+            })
+        }
+    }
+
+    #[test]
+    fn entry_boundaries() {
+        // These used to be packed into a single 64-bit integer, with 16 bits
+        // for the count and 48 bits for the offset, so anything beyond those
+        // limits would silently corrupt both.
+        let pairs = [
+            (0, 0),
+            ((1 << 48) - 1, (1 << 16) - 1),
+            (1 << 48, 1 << 16),
+            (u64::MAX, u64::MAX),
+        ];
+        let buf = entries(&pairs);
+        assert_eq!(buf.len(), pairs.len() * ENTRY_SIZE);
+        for (i, &pair) in pairs.iter().enumerate() {
+            assert_eq!(read_entry(&buf, i as u64), Some(pair));
+        }
+        assert_eq!(read_entry(&buf, pairs.len() as u64), None);
+        assert_eq!(read_entry(&buf, u64::MAX), None);
+    }
+
+    #[test]
+    fn truncated_entries() {
+        let ctx = TestContext::new("small");
+        ctx.create_index();
+        let path = ctx.index_dir().join(AKA_ENTRIES);
+        let mut buf = std::fs::read(&path).unwrap();
+        buf.truncate(buf.len() - 1);
+        std::fs::write(&path, &buf).unwrap();
+
+        let err = Index::open(ctx.data_dir(), ctx.index_dir()).unwrap_err();
+        assert!(err.to_string().contains(AKA_ENTRIES), "{}", err);
+    }
+
+    #[test]
+    fn find_matches_scan() {
+        let ctx = TestContext::new("small");
+        ctx.create_index();
+        let idx = Index::open(ctx.data_dir(), ctx.index_dir()).unwrap();
+
+        // Every lookup, which reuses the header row read when the index was
+        // opened, must find the same records as reading the data in order.
+        let mut rdr = csv_file(ctx.data_dir().join(IMDB_AKAS)).unwrap();
+        let mut expected: Vec<(String, Vec<String>)> = vec![];
+        for result in rdr.deserialize() {
+            let aka: AKA = result.unwrap();
+            match expected.last_mut() {
+                Some((id, titles)) if *id == aka.id => titles.push(aka.title),
+                _ => expected.push((aka.id, vec![aka.title])),
+            }
+        }
+        assert!(!expected.is_empty());
+        for (id, titles) in expected {
+            let got: Vec<String> = idx
+                .find(id.as_bytes())
+                .unwrap()
+                .map(|r| r.unwrap().title)
+                .collect();
+            assert_eq!(got, titles, "{}", id);
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use std::cmp;
 use std::path::Path;
-use std::u32;
+use std::sync::Arc;
 
 use fst::{IntoStreamer, Streamer};
 use memmap::Mmap;
@@ -35,10 +35,12 @@ const TVSHOWS: &str = "episode.tvshows.fst";
 
 /// An episode index that supports retrieving season and episode information
 /// quickly.
-#[derive(Debug)]
+///
+/// Cloning an episode index is cheap, since its memory maps are shared.
+#[derive(Clone, Debug)]
 pub struct Index {
-    seasons: fst::Set<Mmap>,
-    tvshows: fst::Set<Mmap>,
+    seasons: Arc<fst::Set<Mmap>>,
+    tvshows: Arc<fst::Set<Mmap>>,
 }
 
 impl Index {
@@ -49,7 +51,7 @@ impl Index {
         // don't mutate them and no other process (should) either.
         let seasons = unsafe { fst_set_file(index_dir.join(SEASONS))? };
         let tvshows = unsafe { fst_set_file(index_dir.join(TVSHOWS))? };
-        Ok(Index { seasons, tvshows })
+        Ok(Index { seasons: Arc::new(seasons), tvshows: Arc::new(tvshows) })
     }
 
     /// Create an episode index from the given IMDb data directory and write
@@ -78,7 +80,7 @@ impl Index {
         });
         for episode in &episodes {
             buf.clear();
-            write_tvshow(&episode, &mut buf)?;
+            write_tvshow(episode, &mut buf)?;
             tvshows.insert(&buf).map_err(Error::fst)?;
         }
 
@@ -95,16 +97,24 @@ impl Index {
     /// Episodes without season/episode numbers are sorted after episodes with
     /// numbers.
     pub fn seasons(&self, tvshow_id: &[u8]) -> Result<Vec<Episode>> {
-        let mut upper = tvshow_id.to_vec();
-        upper.push(0xFF);
-
-        let mut episodes = vec![];
-        let mut stream =
-            self.seasons.range().ge(tvshow_id).le(upper).into_stream();
-        while let Some(episode_bytes) = stream.next() {
-            episodes.push(read_episode(episode_bytes)?);
-        }
-        Ok(episodes)
+        self.seasons_iter(tvshow_id).collect()
+    }
+
+    /// Return an iterator over every episode in this index, sorted by TV
+    /// show IMDb identifier and then in the same order as `seasons`.
+    pub fn all(&self) -> EpisodeIter<'_> {
+        EpisodeIter::new(self.seasons.stream())
+    }
+
+    /// Return an iterator over the episodes for the given TV show IMDb
+    /// identifier, in the same order as `seasons`.
+    ///
+    /// Episodes are decoded as the iterator is advanced, so stopping early
+    /// avoids the cost of decoding the rest.
+    pub fn seasons_iter(&self, tvshow_id: &[u8]) -> EpisodeIter<'_> {
+        let mut prefix = tvshow_id.to_vec();
+        prefix.push(0x00);
+        EpisodeIter::new(prefix_stream(&self.seasons, &prefix))
     }
 
     /// Return a sequence of episodes for the given TV show IMDb identifier and
@@ -117,23 +127,23 @@ impl Index {
         tvshow_id: &[u8],
         season: u32,
     ) -> Result<Vec<Episode>> {
-        let mut lower = tvshow_id.to_vec();
-        lower.push(0x00);
-        lower.extend_from_slice(&season.to_be_bytes());
-        lower.extend_from_slice(&0u32.to_be_bytes());
-
-        let mut upper = tvshow_id.to_vec();
-        upper.push(0x00);
-        upper.extend_from_slice(&season.to_be_bytes());
-        upper.extend_from_slice(&u32::MAX.to_be_bytes());
-
-        let mut episodes = vec![];
-        let mut stream =
-            self.seasons.range().ge(lower).le(upper).into_stream();
-        while let Some(episode_bytes) = stream.next() {
-            episodes.push(read_episode(episode_bytes)?);
-        }
-        Ok(episodes)
+        self.episodes_iter(tvshow_id, season).collect()
+    }
+
+    /// Return an iterator over the episodes for the given TV show IMDb
+    /// identifier and season number, in the same order as `episodes`.
+    ///
+    /// Episodes are decoded as the iterator is advanced, so stopping early
+    /// avoids the cost of decoding the rest.
+    pub fn episodes_iter(
+        &self,
+        tvshow_id: &[u8],
+        season: u32,
+    ) -> EpisodeIter<'_> {
+        let mut prefix = tvshow_id.to_vec();
+        prefix.push(0x00);
+        prefix.extend_from_slice(&season.to_be_bytes());
+        EpisodeIter::new(prefix_stream(&self.seasons, &prefix))
     }
 
     /// Return the episode information for the given episode IMDb identifier.
@@ -141,16 +151,78 @@ impl Index {
     /// If no episode information for the given ID exists, then `None` is
     /// returned.
     pub fn episode(&self, episode_id: &[u8]) -> Result<Option<Episode>> {
-        let mut upper = episode_id.to_vec();
-        upper.push(0xFF);
+        let mut prefix = episode_id.to_vec();
+        prefix.push(0x00);
+
+        let mut stream = prefix_stream(&self.tvshows, &prefix);
+        match stream.next() {
+            Some(tvshow_bytes) => Ok(Some(read_tvshow(tvshow_bytes)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A streaming iterator over the episodes of a TV show.
+///
+/// This iterator is constructed via the `episode::Index::seasons_iter` and
+/// `episode::Index::episodes_iter` methods.
+///
+/// The lifetime `'i` refers to the lifetime of the underlying episode index.
+pub struct EpisodeIter<'i> {
+    stream: fst::set::Stream<'i>,
+    /// The number of episodes decoded so far.
+    #[cfg(test)]
+    decoded: usize,
+}
+
+impl<'i> EpisodeIter<'i> {
+    fn new(stream: fst::set::Stream<'i>) -> EpisodeIter<'i> {
+        EpisodeIter {
+            stream,
+            #[cfg(test)]
+            decoded: 0,
+        }
+    }
+}
+
+impl<'i> Iterator for EpisodeIter<'i> {
+    type Item = Result<Episode>;
+
+    fn next(&mut self) -> Option<Result<Episode>> {
+        let episode_bytes = self.stream.next()?;
+        #[cfg(test)]
+        {
+            self.decoded += 1;
+        }
+        Some(read_episode(episode_bytes))
+    }
+}
+
+/// Return a stream over all keys in the given set that start with the given
+/// prefix.
+fn prefix_stream<'s>(
+    set: &'s fst::Set<Mmap>,
+    prefix: &[u8],
+) -> fst::set::Stream<'s> {
+    let range = set.range().ge(prefix);
+    match prefix_upper_bound(prefix) {
+        None => range.into_stream(),
+        Some(upper) => range.lt(upper).into_stream(),
+    }
+}
 
-        let mut stream =
-            self.tvshows.range().ge(episode_id).le(upper).into_stream();
-        while let Some(tvshow_bytes) = stream.next() {
-            return Ok(Some(read_tvshow(tvshow_bytes)?));
+/// Return the smallest key that is greater than every key starting with the
+/// given prefix. If no such key exists, i.e., when the prefix consists
+/// entirely of `0xFF` bytes, then this returns `None`.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(byte) = upper.pop() {
+        if byte < 0xFF {
+            upper.push(byte + 1);
+            return Some(upper);
         }
-        Ok(None)
     }
+    None
 }
 
 fn read_sorted_episodes(data_dir: &Path) -> Result<Vec<Episode>> {
@@ -207,7 +279,7 @@ fn read_episode(bytes: &[u8]) -> Result<Episode> {
 }
 
 fn write_episode(ep: &Episode, buf: &mut Vec<u8>) -> Result<()> {
-    if ep.tvshow_id.as_bytes().iter().any(|&b| b == 0) {
+    if ep.tvshow_id.as_bytes().contains(&0) {
         bug!("unsupported tvshow id (with NUL byte) for {:?}", ep);
     }
     buf.extend_from_slice(ep.tvshow_id.as_bytes());
@@ -243,7 +315,7 @@ fn read_tvshow(bytes: &[u8]) -> Result<Episode> {
 }
 
 fn write_tvshow(ep: &Episode, buf: &mut Vec<u8>) -> Result<()> {
-    if ep.id.as_bytes().iter().any(|&b| b == 0) {
+    if ep.id.as_bytes().contains(&0) {
         bug!("unsupported episode id (with NUL byte) for {:?}", ep);
     }
 
@@ -294,9 +366,178 @@ fn to_optional_epnum(ep: &Episode) -> Result<u32> {
 
 #[cfg(test)]
 mod tests {
-    use super::Index;
-    use crate::index::tests::TestContext;
     use std::collections::HashMap;
+    use std::fs;
+
+    use super::*;
+    use crate::index::tests::{TempDir, TestContext};
+
+    fn episode(
+        id: &str,
+        tvshow_id: &str,
+        season: Option<u32>,
+        episode: Option<u32>,
+    ) -> Episode {
+        Episode {
+            id: id.to_string(),
+            tvshow_id: tvshow_id.to_string(),
+            season,
+            episode,
+        }
+    }
+
+    fn same(ep1: &Episode, ep2: &Episode) -> bool {
+        (&ep1.id, &ep1.tvshow_id, ep1.season, ep1.episode)
+            == (&ep2.id, &ep2.tvshow_id, ep2.season, ep2.episode)
+    }
+
+    /// Build an episode from arbitrary values, removing NUL bytes from ids
+    /// since they are rejected by the index.
+    fn arbitrary_episode(
+        id: String,
+        tvshow_id: String,
+        season: Option<u32>,
+        epnum: Option<u32>,
+    ) -> Episode {
+        episode(
+            &id.replace('\0', ""),
+            &tvshow_id.replace('\0', ""),
+            season,
+            epnum,
+        )
+    }
+
+    quickcheck::quickcheck! {
+        fn episode_roundtrip(
+            id: String,
+            tvshow_id: String,
+            season: Option<u32>,
+            epnum: Option<u32>
+        ) -> bool {
+            let ep = arbitrary_episode(id, tvshow_id, season, epnum);
+            let unsupported =
+                season == Some(u32::MAX) || epnum == Some(u32::MAX);
+
+            let mut buf = vec![];
+            if write_episode(&ep, &mut buf).is_err() {
+                return unsupported;
+            }
+            let mut buf2 = vec![];
+            write_tvshow(&ep, &mut buf2).unwrap();
+            !unsupported
+                && same(&ep, &read_episode(&buf).unwrap())
+                && same(&ep, &read_tvshow(&buf2).unwrap())
+        }
+
+        fn episode_key_order(
+            tvshow1: String,
+            season1: Option<u32>,
+            tvshow2: String,
+            season2: Option<u32>
+        ) -> bool {
+            let season1 = season1.filter(|&s| s != u32::MAX);
+            let season2 = season2.filter(|&s| s != u32::MAX);
+            let ep1 = arbitrary_episode("a".into(), tvshow1, season1, None);
+            let ep2 = arbitrary_episode("a".into(), tvshow2, season2, None);
+            let (mut buf1, mut buf2) = (vec![], vec![]);
+            write_episode(&ep1, &mut buf1).unwrap();
+            write_episode(&ep2, &mut buf2).unwrap();
+            buf1.cmp(&buf2) == cmp_episode(&ep1, &ep2)
+        }
+    }
+
+    #[test]
+    fn episode_boundaries() {
+        let cases = [
+            episode("tt1", "tt2", Some(0), Some(0)),
+            episode("tt1", "tt2", Some(u32::MAX - 1), Some(u32::MAX - 1)),
+            episode("tt1", "tt2", None, None),
+            episode("", "", None, Some(0)),
+            episode("tt\u{FF}", "tt\u{10FFFF}", Some(1), None),
+        ];
+        for ep in &cases {
+            let mut buf = vec![];
+            write_episode(ep, &mut buf).unwrap();
+            assert!(same(ep, &read_episode(&buf).unwrap()), "{:?}", ep);
+            buf.clear();
+            write_tvshow(ep, &mut buf).unwrap();
+            assert!(same(ep, &read_tvshow(&buf).unwrap()), "{:?}", ep);
+        }
+
+        let mut buf = vec![];
+        let max = episode("tt1", "tt2", Some(u32::MAX), None);
+        assert!(write_episode(&max, &mut buf).is_err());
+        let max = episode("tt1", "tt2", None, Some(u32::MAX));
+        assert!(write_episode(&max, &mut buf).is_err());
+        let nul = episode("tt1", "tt\x002", None, None);
+        assert!(write_episode(&nul, &mut buf).is_err());
+        assert!(read_episode(b"tt1").is_err());
+        assert!(read_episode(b"tt1\x00\x00\x00").is_err());
+    }
+
+    #[test]
+    fn upper_bounds() {
+        assert_eq!(prefix_upper_bound(b"tt1\x00"), Some(b"tt1\x01".to_vec()));
+        assert_eq!(prefix_upper_bound(b"a\xFF\xFF"), Some(b"b".to_vec()));
+        assert_eq!(prefix_upper_bound(b"\xFF\xFF"), None);
+        assert_eq!(prefix_upper_bound(b""), None);
+    }
+
+    /// Tests that looking up IDs that are prefixes of other IDs, and seasons
+    /// with episodes lacking episode numbers, works.
+    #[test]
+    fn prefix_ids() {
+        let tmp = TempDir::new("imdb-index-test-episode-prefix").unwrap();
+        fs::write(
+            tmp.path().join(IMDB_EPISODE),
+            "tconst\tparentTconst\tseasonNumber\tepisodeNumber\n\
+             tt10\ttt1\t1\t1\n\
+             tt11\ttt1\t1\t\\N\n\
+             tt120\ttt12\t1\t1\n\
+             tt121\ttt12\t2\t1\n",
+        )
+        .unwrap();
+        let idx = Index::create(tmp.path(), tmp.path()).unwrap();
+
+        let ids = |eps: Vec<Episode>| -> Vec<String> {
+            eps.into_iter().map(|ep| ep.id).collect()
+        };
+        assert_eq!(ids(idx.seasons(b"tt1").unwrap()), vec!["tt10", "tt11"]);
+        assert_eq!(
+            ids(idx.episodes(b"tt1", 1).unwrap()),
+            vec!["tt10", "tt11"]
+        );
+        assert_eq!(ids(idx.episodes(b"tt12", 1).unwrap()), vec!["tt120"]);
+        assert!(idx.episode(b"tt12").unwrap().is_none());
+        assert_eq!(idx.episode(b"tt11").unwrap().unwrap().tvshow_id, "tt1");
+    }
+
+    #[test]
+    fn iter_stops_early() {
+        let ctx = TestContext::new("small");
+        let idx = Index::create(ctx.data_dir(), ctx.index_dir()).unwrap();
+
+        // Season 0 has a single special, so S01E03 is the fourth episode.
+        let mut it = idx.seasons_iter(b"tt0096697");
+        let ep = it
+            .by_ref()
+            .map(|r| r.unwrap())
+            .find(|ep| (ep.season, ep.episode) == (Some(1), Some(3)))
+            .unwrap();
+        assert_eq!(ep.tvshow_id, "tt0096697");
+        assert_eq!(it.decoded, 4);
+
+        let mut it = idx.episodes_iter(b"tt0096697", 2);
+        let ep = it.next().unwrap().unwrap();
+        assert_eq!((ep.season, ep.episode), (Some(2), Some(1)));
+        assert_eq!(it.decoded, 1);
+
+        let all = idx.seasons(b"tt0096697").unwrap();
+        let streamed: Vec<Episode> =
+            idx.seasons_iter(b"tt0096697").collect::<Result<_>>().unwrap();
+        assert_eq!(all.len(), streamed.len());
+        assert!(all.iter().zip(&streamed).all(|(e1, e2)| same(e1, e2)));
+    }
 
     #[test]
     fn basics() {
@@ -308,7 +549,8 @@ mod tests {
         for ep in eps {
             *counts.entry(ep.season.unwrap()).or_insert(0) += 1;
         }
-        assert_eq!(counts.len(), 3);
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts[&0], 1);
         assert_eq!(counts[&1], 13);
         assert_eq!(counts[&2], 22);
         assert_eq!(counts[&3], 24);
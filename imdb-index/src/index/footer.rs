@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use xxhash_rust::xxh64::xxh64;
+
+use crate::error::{Error, Result};
+
+/// The magic bytes that begin every footer.
+pub const MAGIC: &[u8; 8] = b"imdbfoot";
+
+/// The total size of a footer, in bytes.
+///
+/// A footer consists of the magic bytes, followed by the length of the data
+/// preceding the footer and then the xxh64 hash (with a seed of `0`) of that
+/// same data. Both the length and the hash are encoded as 64-bit
+/// little-endian integers.
+///
+/// Footers are appended to the binary files of the name index so that a file
+/// that was only partially written (for example, because the disk filled up)
+/// is reported as such, instead of producing garbage results or panics at
+/// search time.
+pub const SIZE: usize = 24;
+
+/// Check the footer at the end of the given file contents and return the
+/// data that precedes it.
+///
+/// This only checks the magic bytes and the recorded length, which is cheap
+/// and catches truncated files. Use `verify` to check the hash too.
+///
+/// The path given is only used for error messages.
+pub fn check<'a>(path: &Path, data: &'a [u8]) -> Result<&'a [u8]> {
+    if data.len() < SIZE {
+        return Err(Error::corrupt(
+            path,
+            "file is too short to have a footer",
+        ));
+    }
+    let (payload, footer) = data.split_at(data.len() - SIZE);
+    if &footer[..8] != MAGIC {
+        return Err(Error::corrupt(path, "missing footer"));
+    }
+    let len = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+    if len != payload.len() as u64 {
+        return Err(Error::corrupt(
+            path,
+            format!(
+                "footer records {} bytes of data, but found {}",
+                len,
+                payload.len()
+            ),
+        ));
+    }
+    Ok(payload)
+}
+
+/// Check the footer at the end of the given file contents, including its
+/// hash.
+///
+/// This reads every byte of the given data, so it is much slower than
+/// `check`.
+///
+/// The path given is only used for error messages.
+pub fn verify(path: &Path, data: &[u8]) -> Result<()> {
+    let payload = check(path, data)?;
+    let footer = &data[data.len() - SIZE..];
+    let hash = u64::from_le_bytes(footer[16..24].try_into().unwrap());
+    if hash != xxh64(payload, 0) {
+        return Err(Error::corrupt(path, "checksum mismatch"));
+    }
+    Ok(())
+}
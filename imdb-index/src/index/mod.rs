@@ -1,8 +1,10 @@
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
 use memmap::Mmap;
 use serde::{Deserialize, Serialize};
@@ -11,19 +13,26 @@ use crate::error::{Error, Result};
 use crate::record::{Episode, Rating, Title, TitleKind};
 use crate::scored::SearchResults;
 use crate::util::{
-    create_file, csv_file, csv_mmap, open_file, NiceDuration, IMDB_BASICS,
+    civil_from_unix, create_file, csv_file, csv_mmap, open_file, NiceDuration,
+    IMDB_AKAS, IMDB_BASICS, IMDB_EPISODE, IMDB_RATINGS,
 };
 
 pub use self::aka::AKARecordIter;
-pub use self::names::{NameQuery, NameScorer, NgramType};
+pub use self::episode::EpisodeIter;
+pub(crate) use self::names::normalize_for_comparison;
+pub use self::names::{
+    Analyzer, CorpusStats, DocStats, NameIndexStats, NameQuery, NameScorer,
+    NgramType, Scorer, TermStats,
+};
 
 mod aka;
 mod episode;
+mod footer;
 mod id;
 mod names;
 mod rating;
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
 mod writer;
 
 /// The version of the index format on disk.
@@ -33,7 +42,7 @@ mod writer;
 /// The caller must then re-generate the index.
 ///
 /// This version represents all indexing structures on disk in this module.
-const VERSION: u64 = 1;
+const VERSION: u64 = 6;
 
 /// The name of the title file index.
 ///
@@ -45,8 +54,19 @@ const TITLE: &str = "title.fst";
 ///
 /// The index configuration is a JSON file with some meta data about this
 /// index, such as its version.
+///
+/// This file is always written last when creating an index, so its existence
+/// indicates that every other file in the index is complete.
 const CONFIG: &str = "config.json";
 
+/// The name of the directory, inside the index directory, where an index is
+/// written while it's being created.
+///
+/// Once every file has been written, they are moved into the index directory
+/// one at a time. Since a rename within the same directory tree is atomic,
+/// each file in the index directory is either absent, or complete.
+const STAGING: &str = ".creating";
+
 /// A media entity is a title with optional episode and rating records.
 ///
 /// A media entity makes it convenient to deal with the complete information
@@ -54,7 +74,10 @@ const CONFIG: &str = "config.json";
 /// routines such as what the [`Searcher`](struct.Searcher.html) provides, and
 /// can also be cheaply constructed by an [`Index`](struct.Index.html) given a
 /// [`Title`](struct.Title.html) or an IMDb ID.
-#[derive(Clone, Debug)]
+///
+/// A media entity can be serialized, e.g., to save it alongside something
+/// that refers to it, and deserialized without an index.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MediaEntity {
     title: Title,
     episode: Option<Episode>,
@@ -110,21 +133,120 @@ pub struct Index {
     /// typically return offsets that can be used to seek this reader to the
     /// beginning of any `Title` record.
     csv_basic: csv::Reader<io::Cursor<Mmap>>,
+    /// The header row of `title.basics.tsv`, which is needed to deserialize
+    /// every `Title` record read from `csv_basic`.
+    basic_headers: csv::StringRecord,
+    /// The number of times `csv_basic` was sought backwards, i.e., to an
+    /// offset before the record it last read.
+    #[cfg(test)]
+    backward_seeks: usize,
     /// The name index. This is what provides fuzzy queries.
-    idx_names: names::IndexReader,
+    ///
+    /// This and the AKA index are only opened on first use, since callers
+    /// that only look up records by ID don't need them. See `names` and
+    /// `akas`.
+    idx_names: OnceLock<names::IndexReader>,
     /// The AKA index.
-    idx_aka: aka::Index,
+    idx_aka: OnceLock<aka::Index>,
     /// The episode index.
     idx_episode: episode::Index,
     /// The rating index.
     idx_rating: rating::Index,
     /// The title index.
     idx_title: id::IndexReader,
+    /// When the IMDb data was published, if known.
+    data_snapshot: Option<DataSnapshot>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Config {
     version: u64,
+    /// The snapshot date of the IMDb data, in seconds since the Unix epoch.
+    /// Indexes created before this was recorded don't have it.
+    #[serde(default)]
+    data_snapshot: Option<u64>,
+}
+
+/// The date of the IMDb data that an index was created from.
+///
+/// This is derived from the modification times of the IMDb data files when
+/// the index is created. (imdb-rename sets these to the time at which IMDb
+/// last modified each file.) Its `Display` impl prints the date in
+/// `YYYY-MM-DD` format, in UTC.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DataSnapshot {
+    secs: u64,
+}
+
+impl DataSnapshot {
+    /// Determine the snapshot date of the IMDb data in the given directory.
+    ///
+    /// This is the oldest modification time of the data files, since the
+    /// data is at least as old as that. If no modification times could be
+    /// read, then this returns `None`.
+    fn from_data_dir(data_dir: &Path) -> Option<DataSnapshot> {
+        [IMDB_BASICS, IMDB_AKAS, IMDB_EPISODE, IMDB_RATINGS]
+            .iter()
+            .filter_map(|name| fs::metadata(data_dir.join(name)).ok())
+            .filter_map(|md| md.modified().ok())
+            .filter_map(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| DataSnapshot { secs: d.as_secs() })
+            .min()
+    }
+
+    /// Return this snapshot as a system time.
+    pub fn time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.secs)
+    }
+}
+
+impl fmt::Display for DataSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = civil_from_unix(self.secs);
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// An episode along with its rating and title, if they exist, as returned by
+/// `Index::season_overview`.
+pub type EpisodeOverview = (Episode, Option<Rating>, Option<Title>);
+
+/// A report of problems with an index's data that aren't corruption, as
+/// returned by `Index::verify`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VerifyReport {
+    /// The number of episodes whose TV show has no record in
+    /// `title.basics.tsv`. IMDb's data occasionally contains these. Such
+    /// episodes can still be looked up, but not by their TV show's name.
+    pub orphaned_episodes: u64,
+}
+
+/// Statistics describing an index, as returned by `Index::stats`.
+#[derive(Clone, Debug)]
+pub struct IndexStats {
+    /// The version of the index format.
+    pub version: u64,
+    /// The date of the IMDb data the index was created from, if known.
+    pub data_snapshot: Option<DataSnapshot>,
+    /// The number of names (titles and AKAs) in the name index.
+    pub names: u64,
+    /// The type of ngram used by the name index.
+    pub ngram_type: NgramType,
+    /// The size of ngrams used by the name index.
+    pub ngram_size: usize,
+}
+
+impl fmt::Display for IndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        match self.data_snapshot {
+            None => writeln!(f, "data snapshot: unknown")?,
+            Some(snapshot) => writeln!(f, "data snapshot: {}", snapshot)?,
+        }
+        writeln!(f, "names: {}", self.names)?;
+        writeln!(f, "ngram type: {}", self.ngram_type)?;
+        write!(f, "ngram size: {}", self.ngram_size)
+    }
 }
 
 impl Index {
@@ -133,13 +255,16 @@ impl Index {
     /// error.
     ///
     /// Generally, this method is cheap to call. It opens some file
-    /// descriptors, but otherwise does no work.
+    /// descriptors, but otherwise does no work. The name and AKA indexes
+    /// aren't even opened until they're first used, although an error is
+    /// still returned right away if any of their files are missing.
     ///
     /// `data_dir` should be the directory containing decompressed IMDb
     /// `tsv` files. See: https://www.imdb.com/interfaces/
     ///
     /// `index_dir` should be the directory containing a previously created
-    /// index using `Index::create`.
+    /// index using `Index::create`. Opening an index never writes to
+    /// `index_dir`, so it may be read-only.
     pub fn open<P1: AsRef<Path>, P2: AsRef<Path>>(
         data_dir: P1,
         index_dir: P2,
@@ -167,6 +292,16 @@ impl Index {
         IndexBuilder::new().create(data_dir, index_dir)
     }
 
+    /// Returns true if the creation of an index in `index_dir` finished.
+    ///
+    /// This is false when no index was ever created in `index_dir`, and when
+    /// creating one was interrupted. In either case, the index should be
+    /// created (again) before it's opened. Whether the index was created by
+    /// a compatible version isn't checked, see `Index::open`.
+    pub fn exists<P: AsRef<Path>>(index_dir: P) -> bool {
+        index_dir.as_ref().join(CONFIG).exists()
+    }
+
     /// Attempt to clone this index, returning a distinct `Index`.
     ///
     /// This is as cheap to call as `Index::open` and returns an error if there
@@ -178,6 +313,89 @@ impl Index {
         Index::open(&self.data_dir, &self.index_dir)
     }
 
+    /// Return the date of the IMDb data this index was created from.
+    ///
+    /// This returns `None` if the index was created before snapshot dates
+    /// were recorded, or if the date couldn't be determined.
+    pub fn data_snapshot(&self) -> Option<DataSnapshot> {
+        self.data_snapshot
+    }
+
+    /// Verify the checksums of the name index files, and report problems
+    /// with the data that was indexed.
+    ///
+    /// Opening an index only checks that these files weren't truncated,
+    /// which is cheap. This reads every byte of them instead, which is
+    /// slower but also detects other kinds of corruption.
+    ///
+    /// This returns an error describing the first corrupt file found.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        self.names()?.verify()?;
+        let mut report = VerifyReport::default();
+        // Episodes are sorted by TV show, so each show is only looked up
+        // once.
+        let mut last: Option<(String, bool)> = None;
+        for result in self.idx_episode.all() {
+            let ep = result?;
+            let exists = match last {
+                Some((ref id, exists)) if *id == ep.tvshow_id => exists,
+                _ => self.idx_title.get(ep.tvshow_id.as_bytes()).is_some(),
+            };
+            if !exists {
+                report.orphaned_episodes += 1;
+            }
+            last = Some((ep.tvshow_id, exists));
+        }
+        if report.orphaned_episodes > 0 {
+            log::warn!(
+                "{} episodes belong to TV shows missing from {}",
+                report.orphaned_episodes,
+                IMDB_BASICS
+            );
+        }
+        Ok(report)
+    }
+
+    /// Return statistics describing this index.
+    ///
+    /// This returns an error if there was a problem reading the name index.
+    pub fn stats(&self) -> Result<IndexStats> {
+        let names = self.names()?;
+        Ok(IndexStats {
+            version: VERSION,
+            data_snapshot: self.data_snapshot,
+            names: names.num_documents(),
+            ngram_type: names.ngram_type(),
+            ngram_size: names.ngram_size(),
+        })
+    }
+
+    /// Return the analyzer that the name index uses to break names and
+    /// queries into ngrams.
+    ///
+    /// This returns an error if there was a problem reading the name index.
+    pub fn analyzer(&self) -> Result<Analyzer> {
+        Ok(self.names()?.analyzer())
+    }
+
+    /// Return statistics about the names in the name index, including its
+    /// `top` most common ngrams.
+    ///
+    /// Unlike `stats`, this reads through the entire term index of the name
+    /// index, although that is still fairly quick.
+    ///
+    /// This returns an error if there was a problem reading the name index.
+    pub fn name_index_stats(&self, top: usize) -> Result<NameIndexStats> {
+        self.names()?.stats(top)
+    }
+
+    /// Returns true if and only if at least one ngram of the given name is in
+    /// the name index. When this is false, searching for the name never
+    /// matches anything.
+    pub(crate) fn name_has_terms(&self, name: &str) -> Result<bool> {
+        Ok(self.names()?.contains_any_term(name))
+    }
+
     /// Search this index for `Title` records whose name matches the given
     /// query.
     ///
@@ -201,12 +419,14 @@ impl Index {
         // The name index gives us back scores with offsets. The offset can be
         // used to seek our `Title` CSV reader to the corresponding record and
         // read it in constant time.
-        for result in self.idx_names.search(query) {
-            let title = match self.read_record(*result.value())? {
-                None => continue,
-                Some(title) => title,
-            };
-            results.push(result.map(|_| title));
+        let found = self.names()?.search(query);
+        let offsets: Vec<u64> =
+            found.as_slice().iter().map(|r| *r.value()).collect();
+        let titles = self.titles_at_offsets(&offsets)?;
+        for (result, title) in found.into_iter().zip(titles) {
+            if let Some(title) = title {
+                results.push(result.map(|_| title));
+            }
         }
         Ok(results)
     }
@@ -247,18 +467,99 @@ impl Index {
     pub fn title(&mut self, id: &str) -> Result<Option<Title>> {
         match self.idx_title.get(id.as_bytes()) {
             None => Ok(None),
-            Some(offset) => self.read_record(offset),
+            Some(offset) => self.title_at_offset(offset),
+        }
+    }
+
+    /// Returns the `Title` record beginning at the given byte offset in
+    /// `title.basics.tsv`.
+    ///
+    /// Offsets are what the name index associates with each title, so this
+    /// is useful for callers that want to collect name index results
+    /// themselves. Most callers should use `search` or `title` instead.
+    ///
+    /// If no such record exists, e.g., because the offset is at the end of
+    /// the data, then this returns `None`.
+    ///
+    /// If there was a problem reading the underlying CSV data, then an error
+    /// is returned.
+    ///
+    /// If the given offset does not point to the start of a record in the CSV
+    /// data, then the behavior of this method is unspecified. It may return
+    /// an error, a garbled `Title` or `None`.
+    pub fn title_at_offset(&mut self, offset: u64) -> Result<Option<Title>> {
+        #[cfg(test)]
+        if offset < self.csv_basic.position().byte() {
+            self.backward_seeks += 1;
+        }
+        let mut pos = csv::Position::new();
+        pos.set_byte(offset);
+        self.csv_basic.seek(pos).map_err(Error::csv)?;
+
+        let mut record = csv::StringRecord::new();
+        if !self.csv_basic.read_record(&mut record).map_err(Error::csv)? {
+            Ok(None)
+        } else {
+            let headers = &self.basic_headers;
+            Ok(record.deserialize(Some(headers)).map_err(Error::csv)?)
         }
     }
 
+    /// Returns the `Title` records at each of the given file offsets, in the
+    /// same order as the offsets given.
+    ///
+    /// This is like calling `title_at_offset` for each offset, except that
+    /// records are read in the order in which they appear in the data, which
+    /// avoids jumping back and forth through it when there are many offsets,
+    /// e.g., the candidates of a search by name.
+    pub fn titles_at_offsets(
+        &mut self,
+        offsets: &[u64],
+    ) -> Result<Vec<Option<Title>>> {
+        let mut order: Vec<usize> = (0..offsets.len()).collect();
+        order.sort_by_key(|&i| offsets[i]);
+        let mut titles = vec![None; offsets.len()];
+        for i in order {
+            titles[i] = self.title_at_offset(offsets[i])?;
+        }
+        Ok(titles)
+    }
+
+    /// Return the number of times a title was read from an offset before the
+    /// previous one read.
+    #[cfg(test)]
+    pub(crate) fn backward_seeks(&self) -> usize {
+        self.backward_seeks
+    }
+
     /// Returns an iterator over all `AKA` records for the given IMDb ID.
     ///
     /// If no AKA records exist for the given ID, then an empty iterator is
-    /// returned.
+    /// returned. The iterator doesn't borrow this index, so other lookups
+    /// may be made while it's in use.
     ///
     /// If there was a problem reading the index, then an error is returned.
     pub fn aka_records(&mut self, id: &str) -> Result<AKARecordIter> {
-        self.idx_aka.find(id.as_bytes())
+        self.akas()?.find(id.as_bytes())
+    }
+
+    /// Returns the number of `AKA` records for the given IMDb ID.
+    ///
+    /// Unlike counting the records returned by `aka_records`, this doesn't
+    /// read any records. If no AKA records exist for the given ID, then this
+    /// returns `0`.
+    ///
+    /// If there was a problem reading the index, then an error is returned.
+    pub fn aka_count(&mut self, id: &str) -> Result<u64> {
+        self.akas()?.count(id.as_bytes())
+    }
+
+    /// Returns true if and only if at least one `AKA` record exists for the
+    /// given IMDb ID. See `aka_count`.
+    ///
+    /// If there was a problem reading the index, then an error is returned.
+    pub fn has_akas(&mut self, id: &str) -> Result<bool> {
+        Ok(self.aka_count(id)? > 0)
     }
 
     /// Returns the `Rating` associated with the given IMDb ID.
@@ -285,6 +586,61 @@ impl Index {
         self.idx_episode.seasons(tvshow_id.as_bytes())
     }
 
+    /// Returns an iterator over the episodes for the given TV show, in the
+    /// same order as `seasons`. The TV show should be identified by its IMDb
+    /// ID.
+    ///
+    /// Unlike `seasons`, episodes are read as the iterator is advanced, so
+    /// looking for a particular episode can stop as soon as it's found.
+    pub fn seasons_iter(&self, tvshow_id: &str) -> EpisodeIter<'_> {
+        self.idx_episode.seasons_iter(tvshow_id.as_bytes())
+    }
+
+    /// Returns a handle to the episode index, which can be iterated over
+    /// (e.g., with `episode::Index::seasons_iter`) while this index is used
+    /// to look up each episode. Cloning it is cheap.
+    pub(crate) fn episode_index(&self) -> episode::Index {
+        self.idx_episode.clone()
+    }
+
+    /// Returns all of the episodes for the given TV show along with their
+    /// ratings and titles. The TV show should be identified by its IMDb ID.
+    ///
+    /// The episodes are returned in the same order as `seasons`. This is
+    /// equivalent to calling `rating` and `title` for each episode returned
+    /// by `seasons`, but is faster since the lookups are batched and done in
+    /// sorted order.
+    ///
+    /// If there was a problem reading the index, then an error is returned.
+    pub fn season_overview(
+        &mut self,
+        tvshow_id: &str,
+    ) -> Result<Vec<EpisodeOverview>> {
+        let episodes = self.seasons(tvshow_id)?;
+        let ids: Vec<&[u8]> =
+            episodes.iter().map(|ep| ep.id.as_bytes()).collect();
+        let ratings = self.idx_rating.ratings(&ids)?;
+
+        // Reading titles in order of their offsets means the CSV reader only
+        // ever seeks forward.
+        let mut offsets: Vec<(u64, usize)> = ids
+            .iter()
+            .enumerate()
+            .filter_map(|(i, id)| self.idx_title.get(id).map(|off| (off, i)))
+            .collect();
+        offsets.sort();
+        let mut titles = vec![None; episodes.len()];
+        for (offset, i) in offsets {
+            titles[i] = self.title_at_offset(offset)?;
+        }
+        Ok(episodes
+            .into_iter()
+            .zip(ratings)
+            .zip(titles)
+            .map(|((ep, rating), title)| (ep, rating, title))
+            .collect())
+    }
+
     /// Returns all of the episodes for the given TV show and season number.
     /// The TV show should be identified by its IMDb ID, and the season should
     /// be identified by its number. (Season numbers generally start at `1`.)
@@ -305,6 +661,16 @@ impl Index {
         self.idx_episode.episodes(tvshow_id.as_bytes(), season)
     }
 
+    /// Returns an iterator over the episodes for the given TV show and
+    /// season, in the same order as `episodes`. See `seasons_iter`.
+    pub fn episodes_iter(
+        &self,
+        tvshow_id: &str,
+        season: u32,
+    ) -> EpisodeIter<'_> {
+        self.idx_episode.episodes_iter(tvshow_id.as_bytes(), season)
+    }
+
     /// Return the episode corresponding to the given IMDb ID.
     ///
     /// If the ID doesn't correspond to an episode, then `None` is returned.
@@ -324,27 +690,29 @@ impl Index {
         &self.index_dir
     }
 
-    /// Read the CSV `Title` record beginning at the given file offset.
-    ///
-    /// If no such record exists, then this returns `None`.
-    ///
-    /// If there was a problem reading the underlying CSV data, then an error
-    /// is returned.
-    ///
-    /// If the given offset does not point to the start of a record in the CSV
-    /// data, then the behavior of this method is unspecified.
-    fn read_record(&mut self, offset: u64) -> Result<Option<Title>> {
-        let mut pos = csv::Position::new();
-        pos.set_byte(offset);
-        self.csv_basic.seek(pos).map_err(Error::csv)?;
+    /// Return the name index, opening it if this is its first use.
+    fn names(&self) -> Result<&names::IndexReader> {
+        if let Some(idx) = self.idx_names.get() {
+            return Ok(idx);
+        }
+        let start = Instant::now();
+        let idx = names::IndexReader::open(&self.index_dir)?;
+        log::debug!("opened name index (took {})", NiceDuration::since(start));
+        Ok(self.idx_names.get_or_init(|| idx))
+    }
 
-        let mut record = csv::StringRecord::new();
-        if !self.csv_basic.read_record(&mut record).map_err(Error::csv)? {
-            Ok(None)
-        } else {
-            let headers = self.csv_basic.headers().map_err(Error::csv)?;
-            Ok(record.deserialize(Some(headers)).map_err(Error::csv)?)
+    /// Return the AKA index, opening it if this is its first use.
+    fn akas(&mut self) -> Result<&mut aka::Index> {
+        if self.idx_aka.get().is_none() {
+            let start = Instant::now();
+            let idx = aka::Index::open(&self.data_dir, &self.index_dir)?;
+            log::debug!(
+                "opened AKA index (took {})",
+                NiceDuration::since(start)
+            );
+            let _ = self.idx_aka.set(idx);
         }
+        Ok(self.idx_aka.get_mut().unwrap())
     }
 }
 
@@ -383,6 +751,7 @@ impl IndexBuilder {
         let data_dir = data_dir.as_ref();
         let index_dir = index_dir.as_ref();
         log::debug!("opening index {}", index_dir.display());
+        let start = Instant::now();
 
         let config_file = open_file(index_dir.join(CONFIG))?;
         let config: Config = serde_json::from_reader(config_file)
@@ -391,18 +760,32 @@ impl IndexBuilder {
             return Err(Error::version(VERSION, config.version));
         }
 
-        Ok(Index {
+        // The name and AKA indexes are opened lazily, but missing files
+        // should still be reported right away.
+        names::IndexReader::check(index_dir)?;
+        aka::Index::check(data_dir, index_dir)?;
+        // We claim it is safe to open the following memory map because we
+        // don't mutate it and no other process (should) either.
+        let mut csv_basic = unsafe { csv_mmap(data_dir.join(IMDB_BASICS))? };
+        let basic_headers = csv_basic.headers().map_err(Error::csv)?.clone();
+        let idx = Index {
             data_dir: data_dir.to_path_buf(),
             index_dir: index_dir.to_path_buf(),
-            // We claim it is safe to open the following memory map because we
-            // don't mutate them and no other process (should) either.
-            csv_basic: unsafe { csv_mmap(data_dir.join(IMDB_BASICS))? },
-            idx_names: names::IndexReader::open(index_dir)?,
-            idx_aka: aka::Index::open(data_dir, index_dir)?,
+            csv_basic,
+            basic_headers,
+            #[cfg(test)]
+            backward_seeks: 0,
+            idx_names: OnceLock::new(),
+            idx_aka: OnceLock::new(),
             idx_episode: episode::Index::open(index_dir)?,
             idx_rating: rating::Index::open(index_dir)?,
             idx_title: id::IndexReader::from_path(index_dir.join(TITLE))?,
-        })
+            data_snapshot: config
+                .data_snapshot
+                .map(|secs| DataSnapshot { secs }),
+        };
+        log::debug!("opened index (took {})", NiceDuration::since(start));
+        Ok(idx)
     }
 
     /// Use the current configuration to create a new index.
@@ -417,17 +800,33 @@ impl IndexBuilder {
     /// index using `Index::create`.
     ///
     /// This will overwrite any previous index that may have existed in
-    /// `index_dir`.
+    /// `index_dir`. If `index_dir` cannot be written to, then this returns an
+    /// error with kind `ErrorKind::ReadOnlyIndex` before doing any work.
+    ///
+    /// If index creation is interrupted, then `index_dir` is left without a
+    /// config file, and opening it returns an error until the index is
+    /// created again.
     pub fn create<P1: AsRef<Path>, P2: AsRef<Path>>(
         &self,
         data_dir: P1,
         index_dir: P2,
     ) -> Result<Index> {
         let data_dir = data_dir.as_ref();
-        let index_dir = index_dir.as_ref();
-        fs::create_dir_all(index_dir)
-            .map_err(|e| Error::io_path(e, index_dir))?;
-        log::info!("creating index at {}", index_dir.display());
+        let final_dir = index_dir.as_ref();
+        create_index_dir(final_dir)?;
+        log::info!("creating index at {}", final_dir.display());
+
+        // Remove the config of any previous index before touching anything
+        // else, so that an interrupted build never leaves behind an index
+        // that looks complete.
+        let config_path = final_dir.join(CONFIG);
+        if let Err(err) = fs::remove_file(&config_path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(Error::io_path(err, config_path));
+            }
+        }
+        let staging_dir = create_staging_dir(final_dir)?;
+        let index_dir = staging_dir.as_path();
 
         // Creating the rating and episode indices are completely independent
         // from the name/AKA indexes, so do them in a background thread. The
@@ -473,16 +872,34 @@ impl IndexBuilder {
         );
 
         job.join().unwrap()?;
+        // The AKA index has files in the staging directory memory mapped,
+        // which would prevent moving them on some platforms.
+        drop(aka_index);
 
-        // Write out our config.
+        // Move every file into place, and then write out our config last.
+        for result in fs::read_dir(index_dir)
+            .map_err(|e| Error::io_path(e, index_dir))?
+        {
+            let entry = result.map_err(|e| Error::io_path(e, index_dir))?;
+            let dst = final_dir.join(entry.file_name());
+            fs::rename(entry.path(), &dst)
+                .map_err(|e| Error::io_path(e, &dst))?;
+        }
         let config_file = create_file(index_dir.join(CONFIG))?;
         serde_json::to_writer_pretty(
             config_file,
-            &Config { version: VERSION },
+            &Config {
+                version: VERSION,
+                data_snapshot: DataSnapshot::from_data_dir(data_dir)
+                    .map(|snapshot| snapshot.secs),
+            },
         )
         .map_err(|e| Error::config(e.to_string()))?;
+        fs::rename(index_dir.join(CONFIG), &config_path)
+            .map_err(|e| Error::io_path(e, &config_path))?;
+        fs::remove_dir(index_dir).map_err(|e| Error::io_path(e, index_dir))?;
 
-        self.open(data_dir, index_dir)
+        self.open(data_dir, final_dir)
     }
 
     /// Set the type of ngram generation to use.
@@ -508,6 +925,69 @@ impl Default for IndexBuilder {
     }
 }
 
+/// Create the given index directory if it doesn't exist and check that files
+/// can be written to it.
+///
+/// Checking up front means a read-only index directory is reported as such,
+/// instead of as a permission error part way through creating the index.
+fn create_index_dir(index_dir: &Path) -> Result<()> {
+    fn is_read_only(err: &io::Error) -> bool {
+        matches!(
+            err.kind(),
+            io::ErrorKind::PermissionDenied
+                | io::ErrorKind::ReadOnlyFilesystem
+        )
+    }
+
+    if let Err(err) = fs::create_dir_all(index_dir) {
+        if is_read_only(&err) {
+            return Err(Error::read_only(index_dir));
+        }
+        return Err(Error::io_path(err, index_dir));
+    }
+    let probe = index_dir.join(".write-test");
+    match fs::File::create(&probe) {
+        Ok(_) => fs::remove_file(&probe).map_err(|e| Error::io_path(e, probe)),
+        Err(err) if is_read_only(&err) => Err(Error::read_only(index_dir)),
+        Err(err) => Err(Error::io_path(err, probe)),
+    }
+}
+
+/// Create an empty staging directory inside the given index directory and
+/// return its path. Anything left behind by a previous, interrupted attempt
+/// at creating an index is removed.
+fn create_staging_dir(index_dir: &Path) -> Result<PathBuf> {
+    let dir = index_dir.join(STAGING);
+    if let Err(err) = fs::remove_dir_all(&dir) {
+        if err.kind() != io::ErrorKind::NotFound {
+            return Err(Error::io_path(err, dir));
+        }
+    }
+    fs::create_dir(&dir).map_err(|e| Error::io_path(e, &dir))?;
+    Ok(dir)
+}
+
+/// Return the code used to record the given kind of title in the name index.
+///
+/// These codes are written to disk, so changing them requires bumping the
+/// index version.
+pub(crate) fn kind_code(kind: TitleKind) -> u8 {
+    use crate::record::TitleKind::*;
+
+    match kind {
+        Movie => 0,
+        Short => 1,
+        TVEpisode => 2,
+        TVMiniSeries => 3,
+        TVMovie => 4,
+        TVSeries => 5,
+        TVShort => 6,
+        TVSpecial => 7,
+        Video => 8,
+        VideoGame => 9,
+    }
+}
+
 /// Creates the name index from the title tsv data and an AKA index. The AKA
 /// index is used to index additional names for each title record to improve
 /// recall during search.
@@ -536,6 +1016,10 @@ fn create_name_index(
         let title = &record[2];
         let original_title = &record[3];
         let is_adult = &record[4] == "1";
+        // Unknown years are written as `\N`, which fails to parse.
+        let start_year = record[5].parse::<u32>().ok();
+        let end_year = record[6].parse::<u32>().ok();
+        let kind = record[1].parse::<TitleKind>().ok().map(kind_code);
         if is_adult {
             // TODO: Expose an option to permit this.
             continue;
@@ -545,17 +1029,29 @@ fn create_name_index(
 
         twtr.insert(id.as_bytes(), pos.byte())?;
         // Index the primary name.
-        wtr.insert(pos.byte(), title)?;
+        wtr.insert(pos.byte(), title, start_year, end_year, kind)?;
         if title != original_title {
             // Index the "original" name.
-            wtr.insert(pos.byte(), original_title)?;
+            wtr.insert(
+                pos.byte(),
+                original_title,
+                start_year,
+                end_year,
+                kind,
+            )?;
             count += 1;
         }
         // Now index all of the alternate names, if they exist.
         for result in aka_index.find(id.as_bytes())? {
             let akarecord = result?;
             if title != akarecord.title {
-                wtr.insert(pos.byte(), &akarecord.title)?;
+                wtr.insert(
+                    pos.byte(),
+                    &akarecord.title,
+                    start_year,
+                    end_year,
+                    kind,
+                )?;
                 count += 1;
             }
         }
@@ -1,18 +1,25 @@
 use std::cmp;
-use std::collections::{binary_heap, BinaryHeap};
+use std::collections::{binary_heap, BinaryHeap, HashMap};
 use std::fmt;
 use std::fs::File;
 use std::io::{self, Write};
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::str::{self, FromStr};
+use std::sync::Arc;
 use std::time::Instant;
 
 use fnv::FnvHashMap;
+use fst::Streamer;
 use memmap::Mmap;
 use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::error::{Error, Result};
 use crate::index::writer::CursorWriter;
+use crate::index::{footer, kind_code};
+use crate::record::TitleKind;
 use crate::scored::{Scored, SearchResults};
 use crate::util::{
     fst_map_builder_file, fst_map_file, mmap_file, open_file, NiceDuration,
@@ -44,10 +51,9 @@ const NGRAM: &str = "names.ngram.fst";
 /// `MAX_DOC_ID` constant below ensures we make sure to never use a doc id
 /// that won't fit this encoding scheme.
 ///
-/// The last eight bytes in the postings index contains a 64-bit little-endian
-/// encoded integer indicating the average length of all documents represented
-/// by the ngram index. The length is recorded in units of terms, which
-/// generally correspond to the total number of ngrams in a name.
+/// Like the other binary files of the name index, the postings end with a
+/// footer that is used to detect truncated or corrupt files. See the `footer`
+/// module for details.
 const POSTINGS: &str = "names.postings.idx";
 
 /// The name of the identifier map index.
@@ -56,7 +62,8 @@ const POSTINGS: &str = "names.postings.idx";
 /// 64-bit little-endian encoded integers, where the length of the sequence
 /// corresponds to the total number of names in the index. Each entry in the
 /// sequence encodes a `NameID`. In other words, the index to this sequence is
-/// a `DocID` and the value at that index is a `NameID`.
+/// a `DocID` and the value at that index is a `NameID`. The sequence is
+/// followed by a footer.
 ///
 /// The id map is used to map doc ids returned by the postings to name ids
 /// which were provided by the caller. This also permits search to deduplicate
@@ -70,12 +77,60 @@ const IDMAP: &str = "names.idmap.idx";
 /// This file consists of a sequence of 16-bit little-endian encoded
 /// integers, where the length of the sequence corresponds to the total number
 /// of names in the index. Each entry represents the length, in terms, of each
-/// name.
+/// name. The sequence is followed by a footer.
 ///
 /// The lengths are used during scoring to compute a normalization term. This
 /// allows the scoring mechanism to take document length into account.
 const NORMS: &str = "names.norms.idx";
 
+/// The name of the document years index.
+///
+/// This file consists of a sequence of 16-bit little-endian encoded
+/// integers, where the length of the sequence corresponds to the total number
+/// of names in the index. Each entry packs the start year of the title that
+/// the name belongs to, along with whether that title has an end year. The
+/// sequence is followed by a footer.
+///
+/// The low 10 bits store the start year, offset by `YEAR_OFFSET`. A value
+/// of `0` means the start year is unknown (or doesn't fit). Bit 10 is set
+/// when the title has an end year, which is the case for TV series that have
+/// stopped airing. The remaining bits are unused.
+///
+/// The years are used during collection to drop names that can't match a
+/// query's year range before they take up room in the result set.
+const YEARS: &str = "names.years.idx";
+
+/// The name of the document kinds index.
+///
+/// This file consists of a sequence of 8-bit integers, where the length of
+/// the sequence corresponds to the total number of names in the index. Each
+/// entry is the kind code of the title that the name belongs to, plus one.
+/// A value of `0` means the kind is unknown. The sequence is followed by a
+/// footer.
+///
+/// Kind codes are computed by `kind_code`. They are provided by the caller
+/// when indexing and are used during collection to drop names whose kind
+/// isn't in a query's set of kinds. Only the low 4 bits are used.
+const KINDS: &str = "names.kinds.idx";
+
+/// The maximum kind code allowed.
+///
+/// Kind codes are stored in 4 bits, and `0` is reserved for names whose kind
+/// is unknown.
+const MAX_KIND_CODE: u8 = 14;
+
+/// The offset added to the start year stored in the years index. Start
+/// years from `YEAR_OFFSET + 1` to `YEAR_OFFSET + 1023`, inclusive, can be
+/// stored.
+const YEAR_OFFSET: u32 = 1870;
+
+/// The mask for the start year in an entry of the years index.
+const YEAR_MASK: u16 = (1 << 10) - 1;
+
+/// The bit set in an entry of the years index when the title has an end
+/// year.
+const YEAR_HAS_END: u16 = 1 << 10;
+
 /// The external identifier for every distinct record represented by this name
 /// index. There are no restrictions on name ids, and multiple names may be
 /// indexed that correspond to the same name id.
@@ -125,7 +180,11 @@ pub struct NameQuery {
     name: String,
     size: usize,
     scorer: NameScorer,
+    custom_scorer: Option<Arc<dyn Scorer>>,
     stop_word_ratio: f64,
+    years: YearRange,
+    kinds: Option<u16>,
+    raw_scores: bool,
 }
 
 impl NameQuery {
@@ -135,7 +194,11 @@ impl NameQuery {
             name: name.to_string(),
             size: 30,
             scorer: NameScorer::default(),
+            custom_scorer: None,
             stop_word_ratio: 0.01,
+            years: YearRange::default(),
+            kinds: None,
+            raw_scores: false,
         }
     }
 
@@ -146,8 +209,29 @@ impl NameQuery {
     }
 
     /// Set this query's scorer. By default, Okapi BM25 is used.
+    ///
+    /// This replaces any custom scorer set on this query.
     pub fn with_scorer(self, scorer: NameScorer) -> NameQuery {
-        NameQuery { scorer, ..self }
+        NameQuery { scorer, custom_scorer: None, ..self }
+    }
+
+    /// Set a custom scorer for this query, which is used instead of the
+    /// query's `NameScorer`.
+    ///
+    /// This is useful for experimenting with ranking functions that aren't
+    /// provided by this crate.
+    pub fn with_custom_scorer(self, scorer: Arc<dyn Scorer>) -> NameQuery {
+        NameQuery { custom_scorer: Some(scorer), ..self }
+    }
+
+    /// Return the scores computed by the scorer as they are, instead of
+    /// normalizing them such that the best result has a score of `1.0`.
+    ///
+    /// This is useful for merging the results of several queries, where
+    /// normalizing each query's results would hide that one query matched
+    /// much better than another.
+    pub(crate) fn with_raw_scores(self) -> NameQuery {
+        NameQuery { raw_scores: true, ..self }
     }
 
     /// Set the ratio (in the range `0.0` to `1.0`, inclusive) at which a term
@@ -169,6 +253,163 @@ impl NameQuery {
     pub fn with_stop_word_ratio(self, ratio: f64) -> NameQuery {
         NameQuery { stop_word_ratio: ratio, ..self }
     }
+
+    /// Only return names of titles whose years might fall within the given
+    /// inclusive range. Either end of the range may be unbounded. By default,
+    /// names are returned regardless of year.
+    ///
+    /// A title is dropped when its start year is after the range, or when it
+    /// has no end year and its start year is before the range. Titles whose
+    /// start year isn't known are never dropped. This mirrors the year filter
+    /// of a `Query`, but is applied before names are deduplicated and counted
+    /// against this query's size, so that titles from the wrong years don't
+    /// crowd out the ones that match.
+    pub fn with_years(
+        self,
+        start: Option<u32>,
+        end: Option<u32>,
+    ) -> NameQuery {
+        NameQuery { years: YearRange { start, end }, ..self }
+    }
+
+    /// Only return names of titles whose kind is one of the given kinds. By
+    /// default, or when no kinds are given, names are returned regardless of
+    /// kind.
+    ///
+    /// Names whose kind isn't known are never dropped. Like the year range,
+    /// this is applied before names are deduplicated and counted against this
+    /// query's size.
+    pub fn with_kinds(self, kinds: &[TitleKind]) -> NameQuery {
+        let kinds = if kinds.is_empty() {
+            None
+        } else {
+            Some(
+                kinds
+                    .iter()
+                    .fold(0u16, |mask, &kind| mask | (1 << kind_code(kind))),
+            )
+        };
+        NameQuery { kinds, ..self }
+    }
+
+    /// Returns true if this query's scores aren't normalized. See
+    /// `with_raw_scores`.
+    pub(crate) fn has_raw_scores(&self) -> bool {
+        self.raw_scores
+    }
+
+    /// Return the scorer that this query uses.
+    fn scorer(&self) -> QueryScorer<'_> {
+        match self.custom_scorer {
+            None => QueryScorer::Builtin(self.scorer),
+            Some(ref scorer) => QueryScorer::Custom(&**scorer),
+        }
+    }
+}
+
+/// The scorer used by a single search.
+///
+/// Scores are computed for every ngram of every matching name, so the
+/// built-in scorers are dispatched statically. Only custom scorers go
+/// through a trait object.
+#[derive(Clone, Copy, Debug)]
+enum QueryScorer<'q> {
+    Builtin(NameScorer),
+    Custom(&'q dyn Scorer),
+}
+
+impl QueryScorer<'_> {
+    /// See `Scorer::term_weight`.
+    fn term_weight(
+        &self,
+        document_frequency: u64,
+        corpus: &CorpusStats,
+    ) -> f64 {
+        match *self {
+            QueryScorer::Builtin(s) => {
+                s.term_weight(document_frequency, corpus)
+            }
+            QueryScorer::Custom(s) => {
+                s.term_weight(document_frequency, corpus)
+            }
+        }
+    }
+
+    /// See `Scorer::score`.
+    fn score(&self, term: &TermStats, doc: &DocStats) -> f64 {
+        match *self {
+            QueryScorer::Builtin(s) => s.score(term, doc),
+            QueryScorer::Custom(s) => s.score(term, doc),
+        }
+    }
+
+    /// See `Scorer::finalize`.
+    fn finalize(&self, sum: f64, doc: &DocStats) -> f64 {
+        match *self {
+            QueryScorer::Builtin(s) => s.finalize(sum, doc),
+            QueryScorer::Custom(s) => s.finalize(sum, doc),
+        }
+    }
+}
+
+/// An inclusive range of years used to filter names by the years of their
+/// titles. Either end may be unbounded.
+#[derive(Clone, Copy, Debug, Default)]
+struct YearRange {
+    start: Option<u32>,
+    end: Option<u32>,
+}
+
+impl YearRange {
+    /// Returns true if and only if this range has no bounds, and thus never
+    /// rejects anything.
+    fn is_none(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+
+    /// Returns true if a title with the given entry in the years index
+    /// certainly falls outside this range.
+    fn rejects(&self, years: u16) -> bool {
+        let start = match decode_start_year(years) {
+            None => return false,
+            Some(start) => start,
+        };
+        if self.end.is_some_and(|end| start > end) {
+            return true;
+        }
+        // A title with an end year may have run into this range, even if
+        // it started before it.
+        years & YEAR_HAS_END == 0
+            && self.start.is_some_and(|range_start| start < range_start)
+    }
+}
+
+/// Pack the years of a title into an entry of the years index.
+fn encode_years(start: Option<u32>, end: Option<u32>) -> u16 {
+    let mut years = match start {
+        Some(year) if year > YEAR_OFFSET => {
+            let offset = year - YEAR_OFFSET;
+            if offset <= YEAR_MASK as u32 {
+                offset as u16
+            } else {
+                0
+            }
+        }
+        _ => 0,
+    };
+    if end.is_some() {
+        years |= YEAR_HAS_END;
+    }
+    years
+}
+
+/// Return the start year stored in the given entry of the years index, if
+/// one is known.
+fn decode_start_year(years: u16) -> Option<u32> {
+    match years & YEAR_MASK {
+        0 => None,
+        offset => Some(offset as u32 + YEAR_OFFSET),
+    }
 }
 
 /// A reader for the name index.
@@ -206,6 +447,56 @@ pub struct IndexReader {
     /// The number of entries in this map is equivalent to the total number of
     /// names indexed.
     norms: Mmap,
+    /// A sequence of 16-bit little-endian encoded integers with the packed
+    /// start year of the title of the corresponding document ID. See `YEARS`
+    /// for the encoding.
+    ///
+    /// The number of entries in this map is equivalent to the total number of
+    /// names indexed.
+    years: Mmap,
+    /// A sequence of 8-bit integers with the kind code (plus one) of the
+    /// title of the corresponding document ID. See `KINDS` for details.
+    ///
+    /// The number of entries in this map is equivalent to the total number of
+    /// names indexed.
+    kinds: Mmap,
+    /// The directory containing this index. This is used to report which
+    /// file is corrupt when verifying the index.
+    dir: PathBuf,
+}
+
+/// Statistics about the names in a name index, as returned by
+/// `Index::name_index_stats`.
+///
+/// These are mostly useful for tuning search. For example, comparing the
+/// ratios of the most common ngrams with a query's stop word ratio shows
+/// which ngrams that query treats as stop words.
+#[derive(Clone, Debug)]
+pub struct NameIndexStats {
+    /// The number of names (titles and AKAs) in the index.
+    pub documents: u64,
+    /// The average length of a name, in ngrams.
+    pub avg_document_len: f64,
+    /// The number of distinct ngrams in the index.
+    pub ngrams: u64,
+    /// The most common ngrams, each paired with the number of names that
+    /// contain it. These are sorted in descending order of that number.
+    pub top_ngrams: Vec<(String, u64)>,
+}
+
+impl fmt::Display for NameIndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "average name length: {:.2}", self.avg_document_len)?;
+        write!(f, "distinct ngrams: {}", self.ngrams)?;
+        if !self.top_ngrams.is_empty() {
+            write!(f, "\nmost common ngrams (names, ratio):")?;
+        }
+        for (term, df) in &self.top_ngrams {
+            let ratio = *df as f64 / self.documents as f64;
+            write!(f, "\n    {:?}\t{}\t{:.6}", term, df, ratio)?;
+        }
+        Ok(())
+    }
 }
 
 /// The configuration for this name index. It is JSON encoded to disk.
@@ -231,18 +522,97 @@ impl IndexReader {
         let postings = unsafe { mmap_file(dir.join(POSTINGS))? };
         let idmap = unsafe { mmap_file(dir.join(IDMAP))? };
         let norms = unsafe { mmap_file(dir.join(NORMS))? };
+        let years = unsafe { mmap_file(dir.join(YEARS))? };
+        let kinds = unsafe { mmap_file(dir.join(KINDS))? };
+        // Checking the footers is cheap, since it only looks at the end of
+        // each file. Hashing the contents is left to `verify`.
+        footer::check(&dir.join(POSTINGS), &postings)?;
+        footer::check(&dir.join(IDMAP), &idmap)?;
+        footer::check(&dir.join(NORMS), &norms)?;
+        footer::check(&dir.join(YEARS), &years)?;
+        footer::check(&dir.join(KINDS), &kinds)?;
 
         let config_file = open_file(dir.join(CONFIG))?;
         let config: Config = serde_json::from_reader(config_file)
             .map_err(|e| Error::config(e.to_string()))?;
-        Ok(IndexReader { config, ngram, postings, idmap, norms })
+        let dir = dir.to_path_buf();
+        Ok(IndexReader {
+            config,
+            ngram,
+            postings,
+            idmap,
+            norms,
+            years,
+            kinds,
+            dir,
+        })
+    }
+
+    /// Verify the checksum of every binary file in this index, and return an
+    /// error for the first one that doesn't match.
+    ///
+    /// Unlike the checks done by `open`, this reads every file in full.
+    pub fn verify(&self) -> Result<()> {
+        footer::verify(&self.dir.join(POSTINGS), &self.postings)?;
+        footer::verify(&self.dir.join(IDMAP), &self.idmap)?;
+        footer::verify(&self.dir.join(NORMS), &self.norms)?;
+        footer::verify(&self.dir.join(YEARS), &self.years)?;
+        footer::verify(&self.dir.join(KINDS), &self.kinds)?;
+        Ok(())
+    }
+
+    /// Check that every file of a name index exists in the given directory
+    /// and can be opened, without reading any of them. This returns the same
+    /// error that `open` would for a missing file.
+    pub fn check<P: AsRef<Path>>(dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        for name in [NGRAM, POSTINGS, IDMAP, NORMS, YEARS, KINDS, CONFIG] {
+            open_file(dir.join(name))?;
+        }
+        Ok(())
+    }
+
+    /// Return the number of names in this index.
+    pub fn num_documents(&self) -> u64 {
+        self.config.num_documents
+    }
+
+    /// Return the type of ngram used by this index.
+    pub fn ngram_type(&self) -> NgramType {
+        self.config.ngram_type
+    }
+
+    /// Return the size of ngrams used by this index.
+    pub fn ngram_size(&self) -> usize {
+        self.config.ngram_size
+    }
+
+    /// Return the analyzer that this index uses to break names into ngrams.
+    pub fn analyzer(&self) -> Analyzer {
+        Analyzer::new(self.config.ngram_type, self.config.ngram_size)
+    }
+
+    /// Returns true if and only if at least one ngram of the given name is in
+    /// this index. When this is false, searching for the name never matches
+    /// anything.
+    pub fn contains_any_term(&self, name: &str) -> bool {
+        let mut found = false;
+        self.analyzer().each(name, |term| {
+            found = found || self.ngram.contains_key(term.as_bytes());
+        });
+        found
     }
 
     /// Execute a search.
     pub fn search(&self, query: &NameQuery) -> SearchResults<NameID> {
         let start = Instant::now();
         let mut searcher = Searcher::new(self, query);
-        let results = CollectTopK::new(query.size).collect(&mut searcher);
+        let mut results =
+            CollectTopK::new(query.size, query.years, query.kinds)
+                .collect(&mut searcher);
+        if !query.raw_scores {
+            results.normalize();
+        }
         log::debug!(
             "search for {:?} took {}",
             query,
@@ -251,6 +621,68 @@ impl IndexReader {
         results
     }
 
+    /// Compute statistics about the names in this index, including the `top`
+    /// most common ngrams.
+    ///
+    /// This streams through every ngram in the index, so it takes time
+    /// proportional to the number of distinct ngrams.
+    pub fn stats(&self, top: usize) -> Result<NameIndexStats> {
+        let start = Instant::now();
+        let mut ngrams = 0u64;
+        let mut heap = BinaryHeap::new();
+        let mut stream = self.ngram.stream();
+        while let Some((term, offset)) = stream.next() {
+            let offset = offset as usize;
+            if offset + 4 > self.postings.len() {
+                bug!("postings offset {} is out of bounds", offset);
+            }
+            let df = read_le_u32(&self.postings[offset..]) as u64;
+            let term = String::from_utf8_lossy(term).into_owned();
+            ngrams += 1;
+            // Keep the `top` most frequent ngrams, breaking ties by
+            // preferring ngrams that sort first.
+            heap.push((cmp::Reverse(df), term));
+            if heap.len() > top {
+                heap.pop();
+            }
+        }
+        let top_ngrams = heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(cmp::Reverse(df), term)| (term, df))
+            .collect();
+        log::debug!(
+            "computed name stats (took {})",
+            NiceDuration::since(start)
+        );
+        Ok(NameIndexStats {
+            documents: self.config.num_documents,
+            avg_document_len: self.config.avg_document_len,
+            ngrams,
+            top_ngrams,
+        })
+    }
+
+    /// Return statistics about this index as a whole, for use in scoring.
+    fn corpus_stats(&self) -> CorpusStats {
+        CorpusStats {
+            documents: self.config.num_documents,
+            avg_document_len: self.config.avg_document_len,
+        }
+    }
+
+    /// Return statistics about the given document, for use in scoring.
+    ///
+    /// This panics if the given document id does not correspond to an indexed
+    /// document.
+    fn doc_stats(&self, docid: DocID, query_len: u64) -> DocStats {
+        DocStats {
+            len: self.document_length(docid),
+            query_len,
+            corpus: self.corpus_stats(),
+        }
+    }
+
     /// Return the name ID used to the index the given document id.
     ///
     /// This panics if the given document id does not correspond to an indexed
@@ -270,6 +702,26 @@ impl IndexReader {
         let buf = self.norms[start..start + 2].try_into().unwrap();
         u16::from_le_bytes(buf) as u64
     }
+
+    /// Return the packed years of the title of the given document. See
+    /// `YEARS` for the encoding.
+    ///
+    /// This panics if the given document id does not correspond to an indexed
+    /// document.
+    fn document_years(&self, docid: DocID) -> u16 {
+        let start = 2 * (docid as usize);
+        let buf = self.years[start..start + 2].try_into().unwrap();
+        u16::from_le_bytes(buf)
+    }
+
+    /// Return the kind code of the title of the given document, or `None`
+    /// if it isn't known.
+    ///
+    /// This panics if the given document id does not correspond to an indexed
+    /// document.
+    fn document_kind(&self, docid: DocID) -> Option<u8> {
+        self.kinds[docid as usize].checked_sub(1)
+    }
 }
 
 /// A collector for gathering the top K results from a search.
@@ -290,30 +742,57 @@ struct CollectTopK {
     /// We map name IDs to scores. In this way, we always report the best
     /// scoring match.
     byid: FnvHashMap<NameID, f64>,
+    /// Names of titles outside of this range are dropped before they are
+    /// deduplicated or considered for the heap.
+    years: YearRange,
+    /// When present, names whose kind code isn't in this bitmask are dropped
+    /// in the same way as names outside of `years`.
+    kinds: Option<u16>,
 }
 
 impl CollectTopK {
-    /// Build a new collector that collects at most `k` results.
-    fn new(k: usize) -> CollectTopK {
+    /// Build a new collector that collects at most `k` results, whose titles
+    /// might fall within the given range of years and have one of the given
+    /// kinds.
+    fn new(k: usize, years: YearRange, kinds: Option<u16>) -> CollectTopK {
         CollectTopK {
             k,
             queue: BinaryHeap::with_capacity(k),
             byid: FnvHashMap::default(),
+            years,
+            kinds,
         }
     }
 
     /// Collect the top K results from the given searcher using the given
-    /// index reader. Return the results with normalized scores sorted in
-    /// order of best-to-worst.
+    /// index reader. Return the results sorted in order of best-to-worst.
     fn collect(mut self, searcher: &mut Searcher) -> SearchResults<NameID> {
         if self.k == 0 {
             return SearchResults::new();
         }
         let index = searcher.index();
+        let query_terms = searcher.query_terms();
         let (mut count, mut push_count) = (0, 0);
-        for scored_with_docid in searcher {
+        let (mut year_count, mut kind_count) = (0, 0);
+        for scored_hit in searcher {
             count += 1;
-            let scored = scored_with_docid.map(|v| index.docid_to_nameid(v));
+            let Hit { docid, matched } = *scored_hit.value();
+            if !self.years.is_none()
+                && self.years.rejects(index.document_years(docid))
+            {
+                year_count += 1;
+                continue;
+            }
+            if let Some(mask) = self.kinds {
+                let kind = index.document_kind(docid);
+                if kind.is_some_and(|code| mask & (1 << code) == 0) {
+                    kind_count += 1;
+                    continue;
+                }
+            }
+            let scored = scored_hit
+                .map(|hit| index.docid_to_nameid(hit.docid))
+                .with_terms(matched, query_terms);
             // Since multiple names can correspond to a single IMDb title,
             // we dedup our results here. That is, if our result set
             // already contains this result, then update the score if need
@@ -344,15 +823,15 @@ impl CollectTopK {
             }
         }
         log::debug!(
-            "collect count: {:?}, collect push count: {:?}",
+            "collect count: {:?}, collect push count: {:?}, \
+             dropped by year: {:?}, dropped by kind: {:?}",
             count,
-            push_count
+            push_count,
+            year_count,
+            kind_count
         );
 
-        // Pull out the results from our heap and normalize the scores.
-        let mut results = SearchResults::from_min_heap(&mut self.queue);
-        results.normalize();
-        results
+        SearchResults::from_min_heap(&mut self.queue)
     }
 }
 
@@ -398,24 +877,45 @@ struct Searcher<'i> {
     /// of exclusively high frequency terms, then this is empty (which matches
     /// nothing) and `primary` is set to the disjunction of terms.
     high: Disjunction<'i>,
+    /// The number of distinct terms in the query, including terms that
+    /// aren't in the index.
+    query_terms: usize,
+}
+
+/// A document matched by a search, along with the number of distinct query
+/// terms it contains.
+#[derive(Clone, Copy, Debug)]
+struct Hit {
+    docid: DocID,
+    matched: usize,
 }
 
 impl<'i> Searcher<'i> {
     /// Create a new searcher.
-    fn new(idx: &'i IndexReader, query: &NameQuery) -> Searcher<'i> {
+    fn new(idx: &'i IndexReader, query: &'i NameQuery) -> Searcher<'i> {
         let num_docs = idx.config.num_documents as f64;
+        let scorer = query.scorer();
         let (mut low, mut high) = (vec![], vec![]);
         let (mut low_terms, mut high_terms) = (vec![], vec![]);
 
-        let name = normalize_query(&query.name);
         let mut query_len = 0;
         let mut multiset = FnvHashMap::default();
-        idx.config.ngram_type.iter(idx.config.ngram_size, &name, |term| {
-            *multiset.entry(term).or_insert(0) += 1;
+        idx.analyzer().each(&query.name, |term| {
+            *multiset.entry(term.to_string()).or_insert(0) += 1;
             query_len += 1;
         });
         for (term, &count) in multiset.iter() {
-            let postings = PostingIter::new(idx, query.scorer, count, term);
+            let postings = PostingIter::new(idx, scorer, count, term);
+            // A term that isn't in the index matches nothing. If we let it
+            // through, then it would always be treated as an infrequent term,
+            // and a query whose only infrequent terms are missing from the
+            // index would match nothing at all.
+            //
+            // Skipping it doesn't change scores, since scorers use the length
+            // of the query computed above.
+            if postings.len() == 0 {
+                continue;
+            }
             let ratio = (postings.len() as f64) / num_docs;
             if ratio < query.stop_word_ratio {
                 low.push(postings);
@@ -425,21 +925,24 @@ impl<'i> Searcher<'i> {
                 high_terms.push(format!("{}:{}:{:0.6}", term, count, ratio));
             }
         }
-        log::debug!("starting search for: {:?}", name);
+        log::debug!("starting search for: {:?}", query.name);
         log::debug!("{:?} low frequency terms: {:?}", low.len(), low_terms);
         log::debug!("{:?} high frequency terms: {:?}", high.len(), high_terms);
 
+        let query_terms = multiset.len();
         if low.is_empty() {
             Searcher {
                 index: idx,
-                primary: Disjunction::new(idx, query_len, query.scorer, high),
-                high: Disjunction::empty(idx, query.scorer),
+                primary: Disjunction::new(idx, query_len, scorer, high),
+                high: Disjunction::empty(idx, scorer),
+                query_terms,
             }
         } else {
             Searcher {
                 index: idx,
-                primary: Disjunction::new(idx, query_len, query.scorer, low),
-                high: Disjunction::new(idx, query_len, query.scorer, high),
+                primary: Disjunction::new(idx, query_len, scorer, low),
+                high: Disjunction::new(idx, query_len, scorer, high),
+                query_terms,
             }
         }
     }
@@ -448,25 +951,30 @@ impl<'i> Searcher<'i> {
     fn index(&self) -> &'i IndexReader {
         self.index
     }
+
+    /// Return the number of distinct terms in the query.
+    fn query_terms(&self) -> usize {
+        self.query_terms
+    }
 }
 
 impl<'i> Iterator for Searcher<'i> {
-    type Item = Scored<DocID>;
+    type Item = Scored<Hit>;
 
-    fn next(&mut self) -> Option<Scored<DocID>> {
+    fn next(&mut self) -> Option<Scored<Hit>> {
         // This is pretty simple. We drive the iterator via the primary
         // disjunction, which is usually a disjunction of infrequently
         // occurring ngrams.
-        let mut scored = match self.primary.next() {
-            None => return None,
-            Some(scored) => scored,
-        };
+        let mut scored = self.primary.next()?;
         // We then skip our frequently occurring disjunction to the doc ID
         // yielded above. Any frequently occurring ngrams found then improve
         // this score. This makes queries like 'the matrix' match 'The Matrix'
         // better than 'Matrix'.
-        if let Some(other_scored) = self.high.skip_to(*scored.value()) {
-            scored = scored.map_score(|s| s + other_scored.score());
+        if let Some(other_scored) = self.high.skip_to(scored.value().docid) {
+            let matched = other_scored.value().matched;
+            scored = scored
+                .map(|hit| Hit { matched: hit.matched + matched, ..hit })
+                .map_score(|s| s + other_scored.score());
         }
         Some(scored)
     }
@@ -488,9 +996,9 @@ struct Disjunction<'i> {
     /// if a query consisted of 8 infrequent ngrams and 1 frequent ngram, then
     /// the disjunction containing the single frequent ngram would contribute a
     /// disproportionately high score.
-    query_len: f64,
+    query_len: u64,
     /// The scoring function to use.
-    scorer: NameScorer,
+    scorer: QueryScorer<'i>,
     /// A min-heap of posting iterators. Each posting iterator corresponds to
     /// an iterator over (doc ID, frequency) pairs for a single ngram, sorted
     /// by doc ID in ascending order.
@@ -507,7 +1015,7 @@ impl<'i> Disjunction<'i> {
     fn new(
         index: &'i IndexReader,
         query_len: usize,
-        scorer: NameScorer,
+        scorer: QueryScorer<'i>,
         posting_iters: Vec<PostingIter<'i>>,
     ) -> Disjunction<'i> {
         let mut queue = BinaryHeap::new();
@@ -515,15 +1023,18 @@ impl<'i> Disjunction<'i> {
             queue.push(postings);
         }
         let is_done = queue.is_empty();
-        let query_len = query_len as f64;
+        let query_len = query_len as u64;
         Disjunction { index, query_len, scorer, queue, is_done }
     }
 
     /// Create an empty disjunction that never matches anything.
-    fn empty(index: &'i IndexReader, scorer: NameScorer) -> Disjunction<'i> {
+    fn empty(
+        index: &'i IndexReader,
+        scorer: QueryScorer<'i>,
+    ) -> Disjunction<'i> {
         Disjunction {
             index,
-            query_len: 0.0,
+            query_len: 0,
             scorer,
             queue: BinaryHeap::new(),
             is_done: true,
@@ -536,7 +1047,7 @@ impl<'i> Disjunction<'i> {
     /// If any posting iterator contains the given doc ID, then it is scored
     /// and returned. The score incorporates all posting iterators that contain
     /// the given doc ID.
-    fn skip_to(&mut self, target_docid: DocID) -> Option<Scored<DocID>> {
+    fn skip_to(&mut self, target_docid: DocID) -> Option<Scored<Hit>> {
         if self.is_done {
             return None;
         }
@@ -549,7 +1060,7 @@ impl<'i> Disjunction<'i> {
             // This unwrap is OK because we're only here if we have a
             // non-empty queue.
             let mut postings = self.queue.peek_mut().unwrap();
-            if postings.docid().map_or(true, |x| x >= target_docid) {
+            if postings.docid().is_none_or(|x| x >= target_docid) {
                 found = found || Some(target_docid) == postings.docid();
                 // This is the smallest posting iterator, which means all
                 // iterators are now either at or beyond target_docid.
@@ -557,7 +1068,7 @@ impl<'i> Disjunction<'i> {
             }
             // Skip through this iterator until we're at or beyond the target
             // doc ID.
-            while postings.docid().map_or(false, |x| x < target_docid) {
+            while postings.docid().is_some_and(|x| x < target_docid) {
                 postings.next();
             }
             found = found || Some(target_docid) == postings.docid();
@@ -578,9 +1089,9 @@ impl<'i> Disjunction<'i> {
 }
 
 impl<'i> Iterator for Disjunction<'i> {
-    type Item = Scored<DocID>;
+    type Item = Scored<Hit>;
 
-    fn next(&mut self) -> Option<Scored<DocID>> {
+    fn next(&mut self) -> Option<Scored<Hit>> {
         if self.is_done {
             return None;
         }
@@ -589,7 +1100,7 @@ impl<'i> Iterator for Disjunction<'i> {
             // This unwrap is OK because we're only here if we have a
             // non-empty queue.
             let mut postings = self.queue.peek_mut().unwrap();
-            match postings.score() {
+            match postings.score(self.query_len) {
                 None => {
                     self.is_done = true;
                     return None;
@@ -601,12 +1112,13 @@ impl<'i> Iterator for Disjunction<'i> {
             }
         };
         // Discover if any of the other posting iterators also match this
-        // ngram.
+        // ngram. Each posting iterator corresponds to a distinct term.
+        let mut matched = 1;
         loop {
             // This unwrap is OK because we're only here if we have a
             // non-empty queue.
             let mut postings = self.queue.peek_mut().unwrap();
-            match postings.score() {
+            match postings.score(self.query_len) {
                 None => break,
                 Some(scored2) => {
                     // If the smallest posting iterator isn't equivalent to
@@ -616,34 +1128,19 @@ impl<'i> Iterator for Disjunction<'i> {
                         break;
                     }
                     scored1 = scored1.map_score(|s| s + scored2.score());
+                    matched += 1;
                     postings.next();
                 }
             }
         }
         // Some of our scorers are more convenient to compute at the
         // disjunction level rather than at the term level.
-        if let NameScorer::Jaccard = self.scorer {
-            // When using Jaccard, the score returned by the posting
-            // iterator is always 1. Thus, `scored.score` represents the
-            // total number of terms that matched this document. In other
-            // words, it is the cardinality of the intersection of terms
-            // between the query and our candidate, `|A ∩ B|`.
-            //
-            // `query_len` represents the total number of terms in our query
-            // (not just the number of terms in this disjunction!), and
-            // `doc_len` represents the total number of terms in our candidate.
-            // Thus, since `|A u B| = |A| + |B| - |A ∩ B|`, we have that
-            // `|A u B| = query_len + doc_len - scored.score`. And finally, the
-            // Jaccard index is `|A ∩ B| / |A u B|`.
-            let doc_len = self.index.document_length(*scored1.value()) as f64;
-            let union = self.query_len + doc_len - scored1.score();
-            scored1 = scored1.map_score(|s| s / union);
-        } else if let NameScorer::QueryRatio = self.scorer {
-            // This is like Jaccard, but our score is computely purely as the
-            // ratio of query terms that matched this document.
-            scored1 = scored1.map_score(|s| s / self.query_len)
-        }
-        Some(scored1)
+        let doc = self.index.doc_stats(*scored1.value(), self.query_len);
+        Some(
+            scored1
+                .map(|docid| Hit { docid, matched })
+                .map_score(|s| self.scorer.finalize(s, &doc)),
+        )
     }
 }
 
@@ -671,7 +1168,7 @@ struct PostingIter<'i> {
     /// A handle to the underlying index.
     index: &'i IndexReader,
     /// The scoring function to use.
-    scorer: NameScorer,
+    scorer: QueryScorer<'i>,
     /// The number of times the term for these postings appeared in the
     /// original query. This increases the score proportionally.
     count: f64,
@@ -693,10 +1190,11 @@ struct PostingIter<'i> {
     /// We do this for efficiency by avoiding going through the optional
     /// Posting.
     docid: DocID,
-    /// The OkapiBM25 IDF score. This is invariant across all items in a
-    /// posting list, so we compute it once at construction. This saves a
-    /// call to `log` for every doc ID visited.
-    okapi_idf: f64,
+    /// The weight of this term, as computed by `Scorer::term_weight`. This
+    /// is invariant across all items in a posting list, so we compute it
+    /// once at construction. For example, this saves a call to `log` for
+    /// every doc ID visited when using Okapi BM25.
+    weight: f64,
 }
 
 /// A single entry in a posting list.
@@ -716,10 +1214,24 @@ impl Posting {
         if slice.is_empty() {
             None
         } else {
-            let v = read_le_u32(slice);
-            Some(Posting { docid: v & MAX_DOC_ID, frequency: v >> 28 })
+            Some(Posting::decode(read_le_u32(slice)))
         }
     }
+
+    /// Decode a posting from its on-disk representation, where the high 4
+    /// bits are the frequency and the low 28 bits are the doc ID.
+    fn decode(v: u32) -> Posting {
+        Posting { docid: v & MAX_DOC_ID, frequency: v >> 28 }
+    }
+
+    /// Encode this posting into its on-disk representation. Frequencies
+    /// greater than 15 are capped at 15.
+    ///
+    /// The doc ID must not exceed `MAX_DOC_ID`.
+    fn encode(&self) -> u32 {
+        debug_assert!(self.docid <= MAX_DOC_ID);
+        (cmp::min(15, self.frequency) << 28) | self.docid
+    }
 }
 
 impl<'i> PostingIter<'i> {
@@ -730,7 +1242,7 @@ impl<'i> PostingIter<'i> {
     /// original query string.
     fn new(
         index: &'i IndexReader,
-        scorer: NameScorer,
+        scorer: QueryScorer<'i>,
         count: usize,
         term: &str,
     ) -> PostingIter<'i> {
@@ -748,7 +1260,7 @@ impl<'i> PostingIter<'i> {
                     len: 0,
                     posting: None,
                     docid: MAX_DOC_ID + 1,
-                    okapi_idf: 0.0,
+                    weight: 0.0,
                 };
             }
         };
@@ -756,9 +1268,7 @@ impl<'i> PostingIter<'i> {
         let len = read_le_u32(postings) as usize;
         postings = &postings[4..];
 
-        let corpus_count = index.config.num_documents as f64;
-        let df = len as f64;
-        let okapi_idf = (1.0 + (corpus_count - df + 0.5) / (df + 0.5)).log2();
+        let weight = scorer.term_weight(len as u64, &index.corpus_stats());
         let mut it = PostingIter {
             index,
             scorer,
@@ -767,7 +1277,7 @@ impl<'i> PostingIter<'i> {
             len,
             posting: None,
             docid: 0,
-            okapi_idf,
+            weight,
         };
         // Advance to the first posting.
         it.next();
@@ -794,69 +1304,19 @@ impl<'i> PostingIter<'i> {
 
     /// Return the score with the current document ID. If this iterator has
     /// been exhausted, then this returns `None`.
-    fn score(&self) -> Option<Scored<DocID>> {
-        match self.scorer {
-            NameScorer::OkapiBM25 => self.score_okapibm25(),
-            NameScorer::TFIDF => self.score_tfidf(),
-            NameScorer::Jaccard => self.score_jaccard(),
-            NameScorer::QueryRatio => self.score_query_ratio(),
-        }
-        .map(|scored| scored.map_score(|s| s * self.count))
-    }
-
-    /// Score the current doc ID using Okapi BM25. It's similarish to TF-IDF,
-    /// but uses a document length normalization term.
-    fn score_okapibm25(&self) -> Option<Scored<DocID>> {
-        let post = match self.posting() {
-            None => return None,
-            Some(post) => post,
-        };
-
-        let k1 = 1.2;
-        let b = 0.75;
-        let doc_len = self.index.document_length(post.docid);
-        let norm = (doc_len as f64) / self.index.config.avg_document_len;
-        let tf = post.frequency as f64;
-
-        let num = tf * (k1 + 1.0);
-        let den = tf + k1 * (1.0 - b + b * norm);
-        let score = (num / den) * self.okapi_idf;
-        let capped = if score < 0.0 { 0.0 } else { score };
-        Some(Scored::new(post.docid).with_score(capped))
-    }
-
-    /// Score the current doc ID using the traditional TF-IDF ranking function.
-    fn score_tfidf(&self) -> Option<Scored<DocID>> {
-        let post = match self.posting() {
-            None => return None,
-            Some(post) => post,
+    ///
+    /// `query_len` is the total number of ngrams in the query.
+    fn score(&self, query_len: u64) -> Option<Scored<DocID>> {
+        let post = self.posting()?;
+        let term = TermStats {
+            frequency: post.frequency,
+            document_frequency: self.len as u64,
+            weight: self.weight,
         };
-
-        let corpus_docs = self.index.config.num_documents as f64;
-        let term_docs = self.len as f64;
-        let tf = post.frequency as f64;
-        let idf = (corpus_docs / (1.0 + term_docs)).log2();
-        let score = tf * idf;
+        let doc = self.index.doc_stats(post.docid, query_len);
+        let score = self.scorer.score(&term, &doc) * self.count;
         Some(Scored::new(post.docid).with_score(score))
     }
-
-    /// Score the current doc ID using the Jaccard index, which measures the
-    /// overlap between two sets.
-    ///
-    /// Note that this always returns `1.0`. The Jaccard index itself must be
-    /// computed by the disjunction scorer.
-    fn score_jaccard(&self) -> Option<Scored<DocID>> {
-        self.posting().map(|p| Scored::new(p.docid).with_score(1.0))
-    }
-
-    /// Score the current doc ID using the ratio of terms in the query that
-    /// matched the terms in this doc ID.
-    ///
-    /// Note that this always returns `1.0`. The query ratio itself must be
-    /// computed by the disjunction scorer.
-    fn score_query_ratio(&self) -> Option<Scored<DocID>> {
-        self.posting().map(|p| Scored::new(p.docid).with_score(1.0))
-    }
 }
 
 impl<'i> Iterator for PostingIter<'i> {
@@ -919,10 +1379,8 @@ pub struct IndexWriter {
     /// order. Each ngram is mapped to the beginning of its correspond
     /// postings list.
     ngram: fst::MapBuilder<io::BufWriter<File>>,
-    /// The type of ngram extraction to use.
-    ngram_type: NgramType,
-    /// The size of ngrams to generate.
-    ngram_size: usize,
+    /// The analyzer used to break names into ngrams.
+    analyzer: Analyzer,
     /// A writer for postings lists.
     ///
     /// This isn't written to until the caller indicates that it is done
@@ -947,6 +1405,22 @@ pub struct IndexWriter {
     /// parameters. They are written in a streaming fashion during the indexing
     /// process.
     norms: CursorWriter<io::BufWriter<File>>,
+    /// A map from document ID to the packed years of the title that the
+    /// document belongs to. The map consists of N 16-bit little endian
+    /// integers, where N is the total number of names indexed. See `YEARS`
+    /// for the encoding.
+    ///
+    /// The years are used at query time to drop names that can't match a
+    /// query's year range. They are written in a streaming fashion during
+    /// the indexing process.
+    years: CursorWriter<io::BufWriter<File>>,
+    /// A map from document ID to the kind code of the title that the
+    /// document belongs to, plus one. The map consists of N 8-bit integers,
+    /// where N is the total number of names indexed.
+    ///
+    /// Like the years, these are used at query time to drop names early and
+    /// are written in a streaming fashion during the indexing process.
+    kinds: CursorWriter<io::BufWriter<File>>,
     /// A JSON formatted configuration file that includes some aggregate
     /// statistics (such as the average document length, in ngrams) and the
     /// ngram configuration. The ngram configuration in particular is used at
@@ -994,14 +1468,17 @@ impl IndexWriter {
         let postings = CursorWriter::from_path(dir.join(POSTINGS))?;
         let idmap = CursorWriter::from_path(dir.join(IDMAP))?;
         let norms = CursorWriter::from_path(dir.join(NORMS))?;
+        let years = CursorWriter::from_path(dir.join(YEARS))?;
+        let kinds = CursorWriter::from_path(dir.join(KINDS))?;
         let config = CursorWriter::from_path(dir.join(CONFIG))?;
         Ok(IndexWriter {
             ngram,
-            ngram_type,
-            ngram_size,
+            analyzer: Analyzer::new(ngram_type, ngram_size),
             postings,
             idmap,
             norms,
+            years,
+            kinds,
             config,
             terms: FnvHashMap::default(),
             next_docid: 0,
@@ -1017,7 +1494,7 @@ impl IndexWriter {
         // We could use a BTreeMap and get out our keys in sorted order, but
         // the overhead of inserting into the BTreeMap dwarfs the savings we
         // get from pre-sorted keys.
-        ngram_to_postings.sort_by(|&(ref t1, _), &(ref t2, _)| t1.cmp(t2));
+        ngram_to_postings.sort_by(|(t1, _), (t2, _)| t1.cmp(t2));
 
         for (term, postings) in ngram_to_postings {
             let pos = self.postings.position() as u64;
@@ -1026,26 +1503,36 @@ impl IndexWriter {
                 .write_u32(postings.list.len() as u32)
                 .map_err(Error::io)?;
             for posting in postings.list {
-                let freq = cmp::min(15, posting.frequency);
-                let v = (freq << 28) | posting.docid;
-                self.postings.write_u32(v).map_err(Error::io)?;
+                self.postings
+                    .write_u32(posting.encode())
+                    .map_err(Error::io)?;
             }
         }
 
+        self.ngram.finish().map_err(Error::fst)?;
+        self.idmap.write_footer().map_err(Error::io)?;
+        self.postings.write_footer().map_err(Error::io)?;
+        self.norms.write_footer().map_err(Error::io)?;
+        self.years.write_footer().map_err(Error::io)?;
+        self.kinds.write_footer().map_err(Error::io)?;
+        self.idmap.flush().map_err(Error::io)?;
+        self.postings.flush().map_err(Error::io)?;
+        self.norms.flush().map_err(Error::io)?;
+        self.years.flush().map_err(Error::io)?;
+        self.kinds.flush().map_err(Error::io)?;
+
+        // The config is written only once everything else has been flushed,
+        // so that a config is never written for incomplete data files.
         serde_json::to_writer_pretty(
             &mut self.config,
             &Config {
-                ngram_type: self.ngram_type,
-                ngram_size: self.ngram_size,
+                ngram_type: self.analyzer.ngram_type,
+                ngram_size: self.analyzer.ngram_size,
                 avg_document_len: self.avg_document_len,
                 num_documents: num_docs as u64,
             },
         )
         .map_err(|e| Error::config(e.to_string()))?;
-        self.ngram.finish().map_err(Error::fst)?;
-        self.idmap.flush().map_err(Error::io)?;
-        self.postings.flush().map_err(Error::io)?;
-        self.norms.flush().map_err(Error::io)?;
         self.config.flush().map_err(Error::io)?;
         Ok(())
     }
@@ -1053,11 +1540,28 @@ impl IndexWriter {
     /// Inserts the given name to this index, and associates it with the
     /// provided `NameID`. Multiple names may be associated with the same
     /// `NameID`.
-    pub fn insert(&mut self, name_id: NameID, name: &str) -> Result<()> {
+    ///
+    /// The start and end years of the name's title are recorded for use with
+    /// `NameQuery::with_years`, and its kind code for use with
+    /// `NameQuery::with_kinds`. A name whose start year or kind is unknown is
+    /// never dropped by those filters.
+    ///
+    /// Kind codes must not exceed `MAX_KIND_CODE`.
+    pub fn insert(
+        &mut self,
+        name_id: NameID,
+        name: &str,
+        start_year: Option<u32>,
+        end_year: Option<u32>,
+        kind: Option<u8>,
+    ) -> Result<()> {
+        if kind.is_some_and(|code| code > MAX_KIND_CODE) {
+            bug!("kind code {:?} exceeds {}", kind, MAX_KIND_CODE);
+        }
         let docid = self.next_docid(name_id)?;
-        let name = normalize_query(name);
         let mut count = 0u16; // document length in number of ngrams
-        self.ngram_type.clone().iter(self.ngram_size, &name, |ngram| {
+        let analyzer = self.analyzer;
+        analyzer.each(name, |ngram| {
             self.insert_term(docid, ngram);
             // If a document length exceeds 2^16, then it is far too long for
             // a name anyway, so we cap it at 2^16.
@@ -1069,6 +1573,12 @@ impl IndexWriter {
         // Write the document length to disk, which is used as a normalization
         // term for some scorers (like Okapi-BM25).
         self.norms.write_u16(count).map_err(Error::io)?;
+        self.years
+            .write_u16(encode_years(start_year, end_year))
+            .map_err(Error::io)?;
+        self.kinds
+            .write_u8(kind.map_or(0, |code| code + 1))
+            .map_err(Error::io)?;
         Ok(())
     }
 
@@ -1088,15 +1598,15 @@ impl IndexWriter {
     /// Retrieve a fresh doc id, and associate it with the given name id.
     fn next_docid(&mut self, name_id: NameID) -> Result<DocID> {
         let docid = self.next_docid;
-        self.idmap.write_u64(name_id).map_err(Error::io)?;
-        self.next_docid = match self.next_docid.checked_add(1) {
-            None => bug!("exhausted doc ids"),
-            Some(next_docid) => next_docid,
-        };
-        if self.next_docid > MAX_DOC_ID {
+        // MAX_DOC_ID itself is a valid doc id. Only the one after it is used
+        // as a sentinel for exhausted posting lists.
+        if docid > MAX_DOC_ID {
             let max = MAX_DOC_ID + 1; // docids are 0-indexed
             bug!("exceeded maximum number of names ({})", max);
         }
+        self.idmap.write_u64(name_id).map_err(Error::io)?;
+        // This can't overflow since docid <= MAX_DOC_ID < u32::MAX.
+        self.next_docid = docid + 1;
         Ok(docid)
     }
 
@@ -1110,7 +1620,7 @@ impl Postings {
     /// Return a mutable reference to the posting for the given docid. If one
     /// doesn't exist, then create one (with a zero frequency) and return it.
     fn posting(&mut self, docid: DocID) -> &mut Posting {
-        if self.list.last().map_or(true, |x| x.docid != docid) {
+        if self.list.last().is_none_or(|x| x.docid != docid) {
             self.list.push(Posting { docid, frequency: 0 });
         }
         // This unwrap is OK because if the list was empty when this method was
@@ -1119,14 +1629,95 @@ impl Postings {
     }
 }
 
+/// A ranking function for name searches.
+///
+/// Every ngram in a query is scored against every name containing it with
+/// `score`. The scores of all such ngrams in a name are then summed and passed
+/// to `finalize`, which computes the score of the name.
+///
+/// More precisely, the ngrams of a query are split into frequent and
+/// infrequent ngrams (see `NameQuery::with_stop_word_ratio`), and `finalize`
+/// is called once for each group that matches a name. The name's score is
+/// the sum of the two.
+///
+/// `NameScorer` implements this trait for the scorers built into this crate.
+/// Others can be used with `NameQuery::with_custom_scorer`.
+pub trait Scorer: fmt::Debug + Send + Sync {
+    /// Compute a weight for an ngram, given the number of names containing
+    /// it. The weight is passed back to `score` as `TermStats::weight`.
+    ///
+    /// This is computed once per ngram for each search, so it's the place to
+    /// do work that doesn't depend on the name being scored, such as
+    /// computing an inverse document frequency. By default, this returns
+    /// `1.0`.
+    fn term_weight(
+        &self,
+        document_frequency: u64,
+        corpus: &CorpusStats,
+    ) -> f64 {
+        let _ = (document_frequency, corpus);
+        1.0
+    }
+
+    /// Score a single ngram of the query in a single name.
+    ///
+    /// If an ngram occurs more than once in the query, then its score is
+    /// multiplied by the number of times it occurs.
+    fn score(&self, term: &TermStats, doc: &DocStats) -> f64;
+
+    /// Compute the score of a name from the sum of the scores of the ngrams
+    /// it matched. By default, this returns the sum unchanged.
+    ///
+    /// This is useful for scorers, like Jaccard, that are defined in terms of
+    /// the number of ngrams matched.
+    fn finalize(&self, sum: f64, doc: &DocStats) -> f64 {
+        let _ = doc;
+        sum
+    }
+}
+
+/// Statistics about a name index as a whole, for use by a `Scorer`.
+#[derive(Clone, Copy, Debug)]
+pub struct CorpusStats {
+    /// The number of names in the index.
+    pub documents: u64,
+    /// The average length of a name, in ngrams.
+    pub avg_document_len: f64,
+}
+
+/// Statistics about a single ngram of a query in a single name, for use by a
+/// `Scorer`.
+#[derive(Clone, Copy, Debug)]
+pub struct TermStats {
+    /// The number of times the ngram occurs in the name. This is capped at
+    /// `15`.
+    pub frequency: u32,
+    /// The number of names containing the ngram.
+    pub document_frequency: u64,
+    /// The weight of the ngram, as computed by `Scorer::term_weight`.
+    pub weight: f64,
+}
+
+/// Statistics about a single name being scored, for use by a `Scorer`.
+#[derive(Clone, Copy, Debug)]
+pub struct DocStats {
+    /// The length of the name, in ngrams.
+    pub len: u64,
+    /// The number of ngrams in the query, including duplicates.
+    pub query_len: u64,
+    /// Statistics about the index the name is in.
+    pub corpus: CorpusStats,
+}
+
 /// The type of scorer that the name index should use.
 ///
 /// The default is OkapiBM25. If you aren't sure which scorer to use, then
 /// stick with the default.
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Default)]
 pub enum NameScorer {
     /// OkapiBM25 is a TF-IDF-like ranking function, which takes name length
     /// into account.
+    #[default]
     OkapiBM25,
     /// TFIDF is the traditional TF-IDF ranking function, which does not
     /// incorporate document length.
@@ -1161,9 +1752,72 @@ impl NameScorer {
     }
 }
 
-impl Default for NameScorer {
-    fn default() -> NameScorer {
-        NameScorer::OkapiBM25
+impl Scorer for NameScorer {
+    fn term_weight(
+        &self,
+        document_frequency: u64,
+        corpus: &CorpusStats,
+    ) -> f64 {
+        let corpus_docs = corpus.documents as f64;
+        let term_docs = document_frequency as f64;
+        match *self {
+            NameScorer::OkapiBM25 => {
+                let n = corpus_docs - term_docs + 0.5;
+                (1.0 + n / (term_docs + 0.5)).log2()
+            }
+            NameScorer::TFIDF => (corpus_docs / (1.0 + term_docs)).log2(),
+            NameScorer::Jaccard | NameScorer::QueryRatio => 1.0,
+        }
+    }
+
+    fn score(&self, term: &TermStats, doc: &DocStats) -> f64 {
+        let tf = term.frequency as f64;
+        match *self {
+            // Okapi BM25 is similarish to TF-IDF, but uses a document length
+            // normalization term.
+            NameScorer::OkapiBM25 => {
+                let k1 = 1.2;
+                let b = 0.75;
+                let norm = (doc.len as f64) / doc.corpus.avg_document_len;
+                let num = tf * (k1 + 1.0);
+                let den = tf + k1 * (1.0 - b + b * norm);
+                let score = (num / den) * term.weight;
+                if score < 0.0 {
+                    0.0
+                } else {
+                    score
+                }
+            }
+            NameScorer::TFIDF => tf * term.weight,
+            // The Jaccard index and query ratio are computed by `finalize`.
+            // Here, we just count the number of matching terms.
+            NameScorer::Jaccard | NameScorer::QueryRatio => 1.0,
+        }
+    }
+
+    fn finalize(&self, sum: f64, doc: &DocStats) -> f64 {
+        match *self {
+            NameScorer::OkapiBM25 | NameScorer::TFIDF => sum,
+            NameScorer::Jaccard => {
+                // When using Jaccard, the score of each term is always 1.
+                // Thus, `sum` represents the total number of terms that
+                // matched this document. In other words, it is the
+                // cardinality of the intersection of terms between the query
+                // and our candidate, `|A ∩ B|`.
+                //
+                // `query_len` represents the total number of terms in our
+                // query (not just the number of terms in this disjunction!),
+                // and `doc.len` represents the total number of terms in our
+                // candidate. Thus, since `|A u B| = |A| + |B| - |A ∩ B|`, we
+                // have that `|A u B| = query_len + doc.len - sum`. And
+                // finally, the Jaccard index is `|A ∩ B| / |A u B|`.
+                let union = (doc.query_len + doc.len) as f64 - sum;
+                sum / union
+            }
+            // This is like Jaccard, but our score is computely purely as the
+            // ratio of query terms that matched this document.
+            NameScorer::QueryRatio => sum / doc.query_len as f64,
+        }
     }
 }
 
@@ -1197,7 +1851,9 @@ impl FromStr for NameScorer {
 /// All ngram styles used Unicode codepoints as the definition of a character.
 /// For example, a 3-gram might contain up to 4 bytes, if it contains 3 Unicode
 /// codepoints that each require 4 UTF-8 code units.
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize, Default,
+)]
 pub enum NgramType {
     /// A windowing ngram.
     ///
@@ -1205,6 +1861,7 @@ pub enum NgramType {
     /// `N` is moved across the entire content to be index. For example, the
     /// 3-grams for the string `homer` are hom, ome and mer.
     #[serde(rename = "window")]
+    #[default]
     Window,
     /// An edge ngram.
     ///
@@ -1294,12 +1951,6 @@ impl NgramType {
     }
 }
 
-impl Default for NgramType {
-    fn default() -> NgramType {
-        NgramType::Window
-    }
-}
-
 impl fmt::Display for NgramType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.as_str())
@@ -1318,10 +1969,185 @@ impl FromStr for NgramType {
     }
 }
 
-fn normalize_query(s: &str) -> String {
+/// An analyzer breaks names into the ngrams that a name index uses as terms.
+///
+/// The same analysis is used for names when they are indexed and for queries
+/// when they are searched. An analyzer for an existing index can be obtained
+/// with `Index::analyzer`, which is useful for things like highlighting the
+/// parts of a name that matched a query.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Analyzer {
+    ngram_type: NgramType,
+    ngram_size: usize,
+}
+
+impl Analyzer {
+    /// Create an analyzer that generates ngrams of the given type and size.
+    pub fn new(ngram_type: NgramType, ngram_size: usize) -> Analyzer {
+        Analyzer { ngram_type, ngram_size }
+    }
+
+    /// Return the type of ngram generated by this analyzer.
+    pub fn ngram_type(&self) -> NgramType {
+        self.ngram_type
+    }
+
+    /// Return the size of ngrams generated by this analyzer.
+    pub fn ngram_size(&self) -> usize {
+        self.ngram_size
+    }
+
+    /// Normalize the given name and return its ngrams, in the order in which
+    /// they occur. Ngrams that occur more than once are repeated.
+    pub fn analyze(&self, name: &str) -> Vec<String> {
+        let mut ngrams = vec![];
+        self.each(name, |ngram| ngrams.push(ngram.to_string()));
+        ngrams
+    }
+
+    /// Normalize the given name and return each of its distinct ngrams, along
+    /// with the number of times it occurs.
+    pub fn analyze_counts(&self, name: &str) -> HashMap<String, u32> {
+        let mut counts = HashMap::new();
+        self.each(name, |ngram| {
+            *counts.entry(ngram.to_string()).or_insert(0) += 1;
+        });
+        counts
+    }
+
+    /// Like `analyze`, but also returns the byte range of the given name that
+    /// each ngram came from.
+    ///
+    /// Since ngrams are generated from the normalized name, an ngram may not
+    /// be equal to the text in its range, e.g., because of case differences.
+    pub fn analyze_spans(&self, name: &str) -> Vec<(String, Range<usize>)> {
+        // Map each byte offset of a character in the normalized name to the
+        // range of the character in the original name it came from. Case
+        // folding one character can produce several, so this can't be done
+        // by position alone.
+        let mut normalized = String::with_capacity(name.len());
+        let mut origins = FnvHashMap::default();
+        for (start, c) in name.char_indices() {
+            let range = start..start + c.len_utf8();
+            fold_char(c, |folded| {
+                origins.insert(normalized.len(), range.clone());
+                normalized.push(folded);
+            });
+        }
+
+        let base = normalized.as_ptr() as usize;
+        let mut spans = vec![];
+        self.ngram_type.iter(self.ngram_size, &normalized, |ngram| {
+            // Ngrams are always sub-slices of the text they're generated
+            // from, so their offset can be recovered from their address.
+            let start = ngram.as_ptr() as usize - base;
+            let last = ngram.char_indices().last().map_or(0, |(i, _)| i);
+            let range =
+                match (origins.get(&start), origins.get(&(start + last))) {
+                    (Some(first), Some(last)) => first.start..last.end,
+                    _ => return,
+                };
+            spans.push((ngram.to_string(), range));
+        });
+        spans
+    }
+
+    /// Normalize the given name and call `f` with each of its ngrams, in the
+    /// order in which they occur.
+    fn each<F: FnMut(&str)>(&self, name: &str, f: F) {
+        let name = normalize(name);
+        self.ngram_type.iter(self.ngram_size, &name, f);
+    }
+}
+
+/// Normalize a name for indexing or searching.
+///
+/// This applies Unicode case folding, so that names that differ only in case
+/// always produce the same ngrams. Unlike `str::to_lowercase`, folding
+/// doesn't depend on context (a final capital sigma lowercases to `ς` but
+/// a medial one to `σ`) and maps characters like `ß` to the same thing as
+/// their uppercase forms.
+pub(crate) fn normalize(s: &str) -> String {
     // We might consider doing Unicode normalization here, but it probably
     // doesn't matter too much on a predominantly ASCII data set.
-    s.to_lowercase()
+    let mut folded = String::with_capacity(s.len());
+    for c in s.chars() {
+        fold_char(c, |c| folded.push(c));
+    }
+    folded
+}
+
+/// Normalize a name for comparing it with other names, rather than for
+/// indexing it.
+///
+/// This is `normalize`, followed by removing diacritics. Alternate names are
+/// often transliterations that drop them (Amélie is also known as Amelie),
+/// so without this, a query that drops them too would look more similar to
+/// the alternate name than to the title's own name.
+pub(crate) fn normalize_for_comparison(s: &str) -> String {
+    normalize(s).nfd().filter(|&c| !is_combining_mark(c)).collect()
+}
+
+/// Call `f` with each character of the full Unicode case folding of `c`.
+///
+/// Case folding agrees with lowercasing for the vast majority of characters,
+/// so only the exceptions are listed here. The table covers every full case
+/// folding in Unicode's `CaseFolding.txt` that differs from
+/// `char::to_lowercase`, except for three groups that are left to
+/// lowercasing:
+///
+/// * Cherokee, whose lowercase letters fold to uppercase.
+/// * Greek letters with an iota subscript, like `ᾳ`, which fold to a base
+///   letter followed by `ι`.
+/// * Precomposed letters whose folding is only their canonical decomposition,
+///   like `ǰ` or `ΐ`. Lowercasing keeps them composed, and
+///   `normalize_for_comparison` decomposes them anyway.
+///
+/// This is not locale specific. In particular, the Turkish dotless `ı` is
+/// distinct from `i`, and `İ` folds to `i` followed by a combining dot.
+fn fold_char<F: FnMut(char)>(c: char, f: F) {
+    let folded: &str = match c {
+        'ß' | 'ẞ' => "ss",
+        'ς' => "σ",
+        'µ' => "μ",
+        'ſ' => "s",
+        'ϐ' => "β",
+        'ϑ' => "θ",
+        'ϕ' => "φ",
+        'ϖ' => "π",
+        'ϰ' => "κ",
+        'ϱ' => "ρ",
+        'ϵ' => "ε",
+        '\u{345}' | '\u{1fbe}' => "ι",
+        'ẛ' => "ṡ",
+        'ẚ' => "aʾ",
+        'ŉ' => "ʼn",
+        '\u{1c80}' => "в",
+        '\u{1c81}' => "д",
+        '\u{1c82}' => "о",
+        '\u{1c83}' => "с",
+        '\u{1c84}' | '\u{1c85}' => "т",
+        '\u{1c86}' => "ъ",
+        '\u{1c87}' => "ѣ",
+        '\u{1c88}' => "ꙋ",
+        'և' => "եւ",
+        'ﬀ' => "ff",
+        'ﬁ' => "fi",
+        'ﬂ' => "fl",
+        'ﬃ' => "ffi",
+        'ﬄ' => "ffl",
+        'ﬅ' | 'ﬆ' => "st",
+        'ﬓ' => "մն",
+        'ﬔ' => "մե",
+        'ﬕ' => "մի",
+        'ﬖ' => "վն",
+        'ﬗ' => "մխ",
+        _ => {
+            c.to_lowercase().for_each(f);
+            return;
+        }
+    };
+    folded.chars().for_each(f);
 }
 
 fn read_le_u32(slice: &[u8]) -> u32 {
@@ -1341,7 +2167,7 @@ mod tests {
         let mut wtr =
             IndexWriter::open(index_dir, NgramType::Window, 3).unwrap();
         for (i, name) in names.iter().enumerate() {
-            wtr.insert(i as u64, name).unwrap();
+            wtr.insert(i as u64, name, None, None, None).unwrap();
         }
         wtr.finish().unwrap();
 
@@ -1363,7 +2189,7 @@ mod tests {
     }
 
     /// Some names involving bruce.
-    const BRUCES: &'static [&'static str] = &[
+    const BRUCES: &[&str] = &[
         "Bruce Springsteen", // 0
         "Bruce Kulick",      // 1
         "Bruce Arians",      // 2
@@ -1373,6 +2199,112 @@ mod tests {
         "Bruce Banner",      // 6
     ];
 
+    #[test]
+    fn names_matched_terms() {
+        let ctx = TestContext::new("small");
+        let idx = create_index(ctx.index_dir(), BRUCES);
+        let query = name_query("bruce willis");
+        let results = idx.search(&query).into_vec();
+
+        let query_terms = idx.analyzer().analyze_counts("bruce willis").len();
+        for r in &results {
+            assert_eq!(r.query_terms(), Some(query_terms));
+        }
+        // Every distinct ngram of the query is in "Bruce Willis," while
+        // the other names only share the "bruce" ngrams.
+        assert_eq!(*results[0].value(), 4);
+        assert_eq!(results[0].matched_terms(), Some(query_terms));
+        assert_eq!(results[0].match_ratio(), Some(1.0));
+        for r in &results[1..] {
+            assert!(r.matched_terms().unwrap() < query_terms);
+        }
+    }
+
+    #[test]
+    fn names_years() {
+        let ctx = TestContext::new("small");
+        let mut wtr =
+            IndexWriter::open(ctx.index_dir(), NgramType::Window, 3).unwrap();
+        wtr.insert(0, "Dune", Some(1984), None, None).unwrap();
+        wtr.insert(1, "Dune", Some(2021), None, None).unwrap();
+        // A series that ran into the 2000s, even though it started earlier.
+        wtr.insert(2, "Dune", Some(1995), Some(2005), None).unwrap();
+        wtr.insert(3, "Dune", None, None, None).unwrap();
+        // Alternate names of the same title share its years.
+        wtr.insert(4, "Dune", Some(1984), None, None).unwrap();
+        wtr.insert(4, "Dune Two", Some(1984), None, None).unwrap();
+        wtr.finish().unwrap();
+        let idx = IndexReader::open(ctx.index_dir()).unwrap();
+
+        let search = |start, end| {
+            let query = name_query("dune").with_years(start, end);
+            ids(&idx.search(&query).into_vec())
+        };
+        assert_eq!(search(None, None), vec![0, 1, 2, 3, 4]);
+        assert_eq!(search(Some(1984), Some(1984)), vec![0, 3, 4]);
+        assert_eq!(search(Some(2000), None), vec![1, 2, 3]);
+        assert_eq!(search(None, Some(1990)), vec![0, 3, 4]);
+        // Only start years are recorded, so a title with an end year is kept
+        // for any range that ends after it started.
+        assert_eq!(search(Some(2030), None), vec![2, 3]);
+
+        // Filtered names don't count against the size of the query.
+        let query =
+            name_query("dune").with_size(1).with_years(Some(2021), Some(2021));
+        assert_eq!(ids(&idx.search(&query).into_vec()), vec![1]);
+    }
+
+    #[test]
+    fn names_kinds() {
+        let ctx = TestContext::new("small");
+        let mut wtr =
+            IndexWriter::open(ctx.index_dir(), NgramType::Window, 3).unwrap();
+        wtr.insert(0, "Dune", None, None, Some(0)).unwrap();
+        wtr.insert(1, "Dune", None, None, Some(2)).unwrap();
+        wtr.insert(2, "Dune", None, None, Some(MAX_KIND_CODE)).unwrap();
+        wtr.insert(3, "Dune", None, None, None).unwrap();
+        wtr.finish().unwrap();
+        let idx = IndexReader::open(ctx.index_dir()).unwrap();
+
+        let search = |kinds: &[TitleKind]| {
+            let query = name_query("dune").with_kinds(kinds);
+            ids(&idx.search(&query).into_vec())
+        };
+        assert_eq!(search(&[TitleKind::Movie]), vec![0, 3]);
+        assert_eq!(
+            search(&[TitleKind::Movie, TitleKind::TVEpisode]),
+            vec![0, 1, 3]
+        );
+        assert_eq!(search(&[TitleKind::VideoGame]), vec![3]);
+        assert_eq!(search(&[]), vec![0, 1, 2, 3]);
+
+        // Filtered names don't count against the size of the query.
+        let query = name_query("dune")
+            .with_size(1)
+            .with_kinds(&[TitleKind::TVEpisode]);
+        assert_eq!(ids(&idx.search(&query).into_vec()), vec![1]);
+    }
+
+    #[test]
+    fn years_encoding() {
+        assert_eq!(decode_start_year(encode_years(None, None)), None);
+        assert_eq!(
+            decode_start_year(encode_years(Some(1874), None)),
+            Some(1874)
+        );
+        assert_eq!(
+            decode_start_year(encode_years(Some(2893), None)),
+            Some(2893)
+        );
+        // Years that don't fit are recorded as unknown.
+        assert_eq!(decode_start_year(encode_years(Some(1870), None)), None);
+        assert_eq!(decode_start_year(encode_years(Some(2894), None)), None);
+
+        let years = encode_years(Some(1989), Some(2000));
+        assert_eq!(decode_start_year(years), Some(1989));
+        assert_ne!(years & YEAR_HAS_END, 0);
+    }
+
     #[test]
     fn names_bruces_1() {
         let ctx = TestContext::new("small");
@@ -1509,4 +2441,314 @@ mod tests {
             vec!["δεα", "δεαβ", "δεαβγ", "δε",]
         );
     }
+
+    #[test]
+    fn names_missing_term() {
+        let ctx = TestContext::new("small");
+        let idx = create_index(ctx.index_dir(), BRUCES);
+        // Every term in 'bruce' is frequent, and 'e x' isn't in the index at
+        // all. This used to find nothing, since the missing term was treated
+        // as the only infrequent term.
+        let query = NameQuery::new("bruce x").with_stop_word_ratio(0.5);
+        let results = idx.search(&query).into_vec();
+        assert_eq!(results.len(), 7);
+    }
+
+    // Test the postings encoding.
+
+    quickcheck::quickcheck! {
+        fn posting_roundtrip(docid: u32, frequency: u32) -> bool {
+            let docid = docid & MAX_DOC_ID;
+            let got = Posting::decode(Posting { docid, frequency }.encode());
+            got.docid == docid && got.frequency == cmp::min(15, frequency)
+        }
+    }
+
+    #[test]
+    fn posting_boundaries() {
+        let cases = [
+            (0, 0, 0, 0),
+            (MAX_DOC_ID, 0, MAX_DOC_ID, 0),
+            (0, 15, 0, 15),
+            (MAX_DOC_ID, 15, MAX_DOC_ID, 15),
+            (MAX_DOC_ID, 16, MAX_DOC_ID, 15),
+            (1, u32::MAX, 1, 15),
+        ];
+        for &(docid, frequency, want_docid, want_freq) in &cases {
+            let v = Posting { docid, frequency }.encode();
+            let got = Posting::read(&v.to_le_bytes()).unwrap();
+            assert_eq!((got.docid, got.frequency), (want_docid, want_freq));
+        }
+        assert!(Posting::read(&[]).is_none());
+    }
+
+    #[test]
+    fn max_doc_id() {
+        let ctx = TestContext::new("small");
+        let mut wtr =
+            IndexWriter::open(ctx.index_dir(), NgramType::Window, 3).unwrap();
+        wtr.next_docid = MAX_DOC_ID;
+        assert_eq!(wtr.next_docid(0).unwrap(), MAX_DOC_ID);
+        assert!(wtr.next_docid(1).is_err());
+    }
+
+    #[test]
+    fn title_at_offset() {
+        use crate::index::Index;
+
+        let ctx = TestContext::new("small");
+        ctx.create_index();
+        let rdr = IndexReader::open(ctx.index_dir()).unwrap();
+        let results = rdr.search(&name_query("dune")).into_vec();
+        assert!(!results.is_empty());
+
+        let mut idx = Index::open(ctx.data_dir(), ctx.index_dir()).unwrap();
+        let mut ids = vec![];
+        for result in &results {
+            let title = idx.title_at_offset(*result.value()).unwrap().unwrap();
+            ids.push(title.id);
+        }
+        ids.sort();
+        assert_eq!(ids, vec!["tt0087182", "tt1160419"]);
+
+        let end = std::fs::metadata(ctx.data_dir().join("title.basics.tsv"))
+            .unwrap()
+            .len();
+        assert!(idx.title_at_offset(end).unwrap().is_none());
+    }
+
+    #[test]
+    fn footer_truncated() {
+        use crate::error::ErrorKind;
+        use std::fs;
+
+        for name in [POSTINGS, IDMAP, NORMS, YEARS, KINDS] {
+            let ctx = TestContext::new("small");
+            create_index(ctx.index_dir(), BRUCES);
+            let path = ctx.index_dir().join(name);
+            let data = fs::read(&path).unwrap();
+            for len in [data.len() - 1, data.len() / 2, 0] {
+                fs::write(&path, &data[..len]).unwrap();
+                let err = IndexReader::open(ctx.index_dir()).err().unwrap();
+                match *err.kind() {
+                    ErrorKind::Corrupt { path: ref p, .. } => {
+                        assert_eq!(p, &path)
+                    }
+                    ref kind => panic!("unexpected error: {:?}", kind),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn footer_verify() {
+        use crate::error::ErrorKind;
+        use std::fs;
+
+        let ctx = TestContext::new("small");
+        create_index(ctx.index_dir(), BRUCES).verify().unwrap();
+
+        // Flipping a bit isn't caught when opening the index, but it is
+        // caught when verifying it.
+        let path = ctx.index_dir().join(IDMAP);
+        let mut data = fs::read(&path).unwrap();
+        data[0] ^= 1;
+        fs::write(&path, &data).unwrap();
+        let idx = IndexReader::open(ctx.index_dir()).unwrap();
+        let err = idx.verify().unwrap_err();
+        match *err.kind() {
+            ErrorKind::Corrupt { path: ref p, .. } => assert_eq!(p, &path),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn stats() {
+        let ctx = TestContext::new("small");
+        let idx = create_index(ctx.index_dir(), BRUCES);
+        let stats = idx.stats(3).unwrap();
+        assert_eq!(stats.documents, 7);
+        assert!(stats.avg_document_len > 0.0);
+        assert!(stats.ngrams > 3);
+
+        // Every name starts with "bruce ", so its ngrams are the most common.
+        // Ties are broken by the ngrams themselves.
+        let top: Vec<_> =
+            stats.top_ngrams.iter().map(|(t, df)| (&**t, *df)).collect();
+        assert_eq!(top, vec![("bru", 7), ("ce ", 7), ("ruc", 7)]);
+        assert!(idx.stats(0).unwrap().top_ngrams.is_empty());
+    }
+
+    #[test]
+    fn custom_scorer() {
+        /// Scores names by how many ngrams they have, so that longer names
+        /// are ranked first.
+        #[derive(Debug)]
+        struct Longest;
+
+        impl Scorer for Longest {
+            fn score(&self, _: &TermStats, _: &DocStats) -> f64 {
+                0.0
+            }
+
+            fn finalize(&self, _: f64, doc: &DocStats) -> f64 {
+                doc.len as f64
+            }
+        }
+
+        let ctx = TestContext::new("small");
+        let idx = create_index(ctx.index_dir(), BRUCES);
+
+        // The built-in scorers behave the same when used as custom scorers.
+        for &scorer in &[NameScorer::OkapiBM25, NameScorer::Jaccard] {
+            let query = name_query("bruce springsteen").with_scorer(scorer);
+            let custom = query.clone().with_custom_scorer(Arc::new(scorer));
+            let (got, want) = (idx.search(&custom), idx.search(&query));
+            assert_eq!(got.into_vec(), want.into_vec());
+        }
+
+        let query = name_query("bruce")
+            .with_custom_scorer(Arc::new(Longest))
+            .with_size(1);
+        assert_eq!(ids(&idx.search(&query).into_vec()), vec![0]);
+        // Setting a built-in scorer replaces the custom one.
+        let query = query.with_scorer(NameScorer::OkapiBM25);
+        assert_eq!(ids(&idx.search(&query).into_vec()), vec![3]);
+    }
+
+    #[test]
+    fn analyzer() {
+        let analyzer = Analyzer::new(NgramType::Window, 3);
+        let ngrams = analyzer.analyze("Foo Foo");
+        assert_eq!(ngrams, vec!["foo", "oo ", "o f", " fo", "foo"]);
+        let counts = analyzer.analyze_counts("Foo Foo");
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts["foo"], 2);
+        assert_eq!(counts["o f"], 1);
+    }
+
+    #[test]
+    fn analyzer_index_and_query_agree() {
+        // Case folding can change the number of codepoints (İ, ẞ), so these
+        // make sure that names are analyzed the same way at index time and at
+        // query time.
+        let names = &[
+            "Amélie",
+            "ΟΔΥΣΣΕΥΣ",
+            "İstanbul",
+            "STRAẞE",
+            "東京物語",
+            "🎬 Film Noir",
+        ];
+        for &ngram_type in &[NgramType::Window, NgramType::Edge] {
+            let ctx = TestContext::new("small");
+            let mut wtr =
+                IndexWriter::open(ctx.index_dir(), ngram_type, 3).unwrap();
+            for (i, name) in names.iter().enumerate() {
+                wtr.insert(i as u64, name, None, None, None).unwrap();
+            }
+            wtr.finish().unwrap();
+            let idx = IndexReader::open(ctx.index_dir()).unwrap();
+            let analyzer = idx.analyzer();
+            assert_eq!(analyzer, Analyzer::new(ngram_type, 3));
+
+            for (i, name) in names.iter().enumerate() {
+                let ngrams = analyzer.analyze(name);
+                assert!(!ngrams.is_empty(), "{}", name);
+                assert_eq!(
+                    idx.document_length(i as DocID),
+                    ngrams.len() as u64,
+                    "{}",
+                    name
+                );
+                for ngram in &ngrams {
+                    assert!(idx.ngram.contains_key(ngram), "{}", ngram);
+                }
+
+                let query = NameQuery::new(name)
+                    .with_scorer(NameScorer::QueryRatio)
+                    .with_stop_word_ratio(0.0);
+                let results = idx.search(&query).into_vec();
+                assert_eq!(*results[0].value(), i as u64, "{}", name);
+                assert_eq!(results[0].score(), 1.0, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn analyzer_spans() {
+        let analyzer = Analyzer::new(NgramType::Window, 3);
+        let spans = analyzer.analyze_spans("Dune");
+        assert_eq!(
+            spans,
+            vec![("dun".to_string(), 0..3), ("une".to_string(), 1..4)]
+        );
+
+        // 'İ' is two bytes, but folds to two characters totalling three
+        // bytes, so the spans of later ngrams must still line up with the
+        // original name.
+        let name = "İstanbul";
+        let spans = analyzer.analyze_spans(name);
+        assert_eq!(spans.len(), analyzer.analyze(name).len());
+        let last = spans.last().unwrap();
+        assert_eq!(last.0, "bul");
+        assert_eq!(&name[last.1.clone()], "bul");
+        assert_eq!(spans[0].1, 0..3);
+
+        let analyzer = Analyzer::new(NgramType::Edge, 4);
+        let spans: Vec<_> = analyzer
+            .analyze_spans("Bart Gets")
+            .into_iter()
+            .map(|(_, range)| range)
+            .collect();
+        assert_eq!(spans, vec![0..3, 0..4, 5..8, 5..9]);
+    }
+
+    #[test]
+    fn case_folding() {
+        let analyzer = Analyzer::new(NgramType::Window, 3);
+        let same = |a: &str, b: &str| {
+            assert_eq!(analyzer.analyze(a), analyzer.analyze(b), "{}", a);
+        };
+        same("DIE STRASSE", "Die Straße");
+        same("DIE STRAẞE", "die straße");
+        // Lowercasing turns a capital sigma into a final sigma only at the
+        // end of a word, so a name typed with a plain sigma didn't match.
+        same("Ο ΘΙΑΣΟΣ", "ο θιασοσ");
+        same("Ο Θιασος", "ο θιασοσ");
+        same("Ο Θιασος.", "Ο ΘΙΑΣΟΣ.");
+        same("İSTANBUL", "İstanbul");
+        same("ﬁlm", "FILM");
+        same("ﬓ", "մն");
+        same("\u{1c81}ом", "дом");
+    }
+
+    #[test]
+    fn case_folding_search() {
+        let names = &[
+            "Die Straße",        // 0
+            "Die Strasse Nach",  // 1
+            "Ο Θίασος",          // 2
+            "Ο Θιασος",          // 3
+            "İstanbul Hatırası", // 4
+            "Kış Uykusu",        // 5
+            "Die Strafe",        // 6
+        ];
+        let ctx = TestContext::new("small");
+        let idx = create_index(ctx.index_dir(), names);
+        let top = |query: &str| {
+            let results = idx.search(&name_query(query)).into_vec();
+            *results[0].value()
+        };
+        assert_eq!(top("DIE STRASSE"), 0);
+        assert_eq!(top("die strasse"), 0);
+        assert_eq!(top("die straße"), 0);
+        assert_eq!(top("ο θιασοσ"), 3);
+        assert_eq!(top("Ο ΘΙΑΣΟΣ"), 3);
+        assert_eq!(top("İSTANBUL HATIRASI"), 4);
+        assert_eq!(top("istanbul hatırası"), 4);
+        assert_eq!(top("KIŞ UYKUSU"), 5);
+        assert_eq!(top("kış uykusu"), 5);
+    }
 }
@@ -72,10 +72,27 @@ impl Index {
         upper.push(0xFF);
 
         let mut stream = self.idx.range().ge(id).le(upper).into_stream();
-        while let Some(rating_bytes) = stream.next() {
-            return Ok(Some(read_rating(rating_bytes)?));
+        match stream.next() {
+            Some(rating_bytes) => Ok(Some(read_rating(rating_bytes)?)),
+            None => Ok(None),
         }
-        Ok(None)
+    }
+
+    /// Return the rating information for each of the given IMDb identifiers,
+    /// in the same order as the identifiers given.
+    ///
+    /// This is like calling `rating` for each identifier, except the lookups
+    /// are done in sorted order, which keeps reads of the underlying FST
+    /// mostly moving forward.
+    pub fn ratings(&self, ids: &[&[u8]]) -> Result<Vec<Option<Rating>>> {
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.sort_by_key(|&i| ids[i]);
+
+        let mut ratings = vec![None; ids.len()];
+        for i in order {
+            ratings[i] = self.rating(ids[i])?;
+        }
+        Ok(ratings)
     }
 }
 
@@ -98,7 +115,7 @@ fn read_rating(bytes: &[u8]) -> Result<Rating> {
 }
 
 fn write_rating(rat: &Rating, buf: &mut Vec<u8>) -> Result<()> {
-    if rat.id.as_bytes().iter().any(|&b| b == 0) {
+    if rat.id.as_bytes().contains(&0) {
         bug!("unsupported rating id (with NUL byte) for {:?}", rat);
     }
 
@@ -1,5 +1,7 @@
 use std::path::{Path, PathBuf};
 
+use crate::index::{Index, IndexBuilder};
+
 /// Create an error from a format!-like syntax.
 #[macro_export]
 macro_rules! err {
@@ -45,6 +47,12 @@ impl TestContext {
     pub fn index_dir(&self) -> &Path {
         &self.index_dir
     }
+
+    /// Create an index with default settings from this context's data
+    /// directory, in its index directory.
+    pub fn create_index(&self) -> Index {
+        IndexBuilder::new().create(&self.data_dir, &self.index_dir).unwrap()
+    }
 }
 
 /// A simple wrapper for creating a temporary directory that is automatically
@@ -2,17 +2,22 @@ use std::fs::File;
 use std::io::{self, Write};
 use std::path::Path;
 
+use xxhash_rust::xxh64::Xxh64;
+
 use crate::error::Result;
+use crate::index::footer;
 use crate::util::create_file;
 
 /// Wraps any writer and records the current position in the writer.
 ///
 /// The position recorded always corresponds to the position that the next
-/// byte would be written to.
-#[derive(Clone, Debug)]
+/// byte would be written to. A running hash of everything written is kept
+/// too, so that a footer can be appended with `write_footer`.
+#[derive(Clone)]
 pub struct CursorWriter<W> {
     wtr: W,
     pos: usize,
+    hasher: Xxh64,
 }
 
 impl CursorWriter<io::BufWriter<File>> {
@@ -27,7 +32,7 @@ impl CursorWriter<io::BufWriter<File>> {
 impl<W: io::Write> CursorWriter<W> {
     /// Wrap the given writer with a counter.
     pub fn new(wtr: W) -> CursorWriter<W> {
-        CursorWriter { wtr, pos: 0 }
+        CursorWriter { wtr, pos: 0, hasher: Xxh64::new(0) }
     }
 
     /// Return the current position of this writer.
@@ -35,6 +40,11 @@ impl<W: io::Write> CursorWriter<W> {
         self.pos
     }
 
+    /// Write a u8.
+    pub fn write_u8(&mut self, n: u8) -> io::Result<()> {
+        self.write_all(&[n])
+    }
+
     /// Write a u16LE.
     pub fn write_u16(&mut self, n: u16) -> io::Result<()> {
         self.write_all(&n.to_le_bytes())
@@ -49,11 +59,23 @@ impl<W: io::Write> CursorWriter<W> {
     pub fn write_u64(&mut self, n: u64) -> io::Result<()> {
         self.write_all(&n.to_le_bytes())
     }
+
+    /// Write a footer covering everything written so far. See the `footer`
+    /// module for its format.
+    ///
+    /// Nothing else should be written after the footer.
+    pub fn write_footer(&mut self) -> io::Result<()> {
+        let (len, hash) = (self.pos as u64, self.hasher.digest());
+        self.write_all(footer::MAGIC)?;
+        self.write_u64(len)?;
+        self.write_u64(hash)
+    }
 }
 
 impl<W: io::Write> io::Write for CursorWriter<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let n = self.wtr.write(buf)?;
+        self.hasher.update(&buf[..n]);
         self.pos += n;
         Ok(n)
     }
@@ -8,12 +8,19 @@ support fuzzy name queries and using TF-IDF-like ranking functions.
 
 pub use crate::error::{Error, ErrorKind, Result};
 pub use crate::index::{
-    AKARecordIter, Index, IndexBuilder, MediaEntity, NameQuery, NameScorer,
-    NgramType,
+    AKARecordIter, Analyzer, CorpusStats, DataSnapshot, DocStats, EpisodeIter,
+    EpisodeOverview, Index, IndexBuilder, IndexStats, MediaEntity,
+    NameIndexStats, NameQuery, NameScorer, NgramType, Scorer, TermStats,
+    VerifyReport,
 };
+pub use crate::progress::{Progress, ProgressFn};
 pub use crate::record::{Episode, Rating, Title, TitleKind, AKA};
 pub use crate::scored::{Scored, SearchResults};
-pub use crate::search::{Query, Searcher, Similarity};
+pub use crate::search::{
+    best_match, best_match_with, search_once, search_once_with, Combine,
+    EmptyReason, Query, SearchDiagnostics, SearchOutcome, Searcher,
+    Similarity, Weight, DEFAULT_RESCORE_WINDOW,
+};
 
 // A macro that creates an error that represents a bug.
 //
@@ -26,8 +33,13 @@ macro_rules! bug {
     }}
 }
 
+#[cfg(any(test, feature = "bench"))]
+#[doc(hidden)]
+pub mod corpus;
 mod error;
 mod index;
+pub mod prelude;
+mod progress;
 mod record;
 mod scored;
 mod search;
@@ -0,0 +1,18 @@
+/*!
+A prelude that re-exports the types used by most programs that build or
+search an IMDb index, e.g.:
+
+```
+use imdb_index::prelude::*;
+```
+
+Only a few of this crate's types are re-exported here, and more may be added
+over time. Items that are likely to collide with names in other crates, like
+`Error`, `ErrorKind` and `Result`, are left out.
+*/
+
+pub use crate::{
+    best_match, best_match_with, search_once, search_once_with, Episode,
+    Index, IndexBuilder, MediaEntity, NameQuery, NameScorer, NgramType, Query,
+    Rating, Scored, SearchResults, Searcher, Similarity, Title, TitleKind,
+};
@@ -0,0 +1,20 @@
+use std::time::Duration;
+
+/// A snapshot of the progress of a long running operation, such as an
+/// exhaustive search.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// The number of records processed so far.
+    pub rows: usize,
+    /// The number of records processed so far that were kept, e.g., because
+    /// they matched a query's filters.
+    pub matches: usize,
+    /// The time elapsed since the operation started.
+    pub elapsed: Duration,
+    /// Whether the operation has finished. The last report for an operation
+    /// always sets this, but only if a report was made before it.
+    pub done: bool,
+}
+
+/// A callback that receives progress reports.
+pub type ProgressFn = Box<dyn FnMut(&Progress) + Send>;
@@ -2,7 +2,7 @@ use std::cmp;
 use std::fmt;
 use std::str::FromStr;
 
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::error::Error;
 
@@ -11,7 +11,7 @@ use crate::error::Error;
 /// This is the primary type of an IMDb media entry. This record defines the
 /// identifier of an IMDb title, which serves as a foreign key in other data
 /// files (such as alternate names, episodes and ratings).
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Title {
     /// An IMDb identifier.
     ///
@@ -29,7 +29,11 @@ pub struct Title {
     #[serde(rename = "originalTitle")]
     pub original_title: String,
     /// Whether this title is classified as "adult" material or not.
-    #[serde(rename = "isAdult", deserialize_with = "number_as_bool")]
+    #[serde(
+        rename = "isAdult",
+        deserialize_with = "number_as_bool",
+        serialize_with = "bool_as_number"
+    )]
     pub is_adult: bool,
     /// The start year of this title.
     ///
@@ -59,6 +63,23 @@ pub struct Title {
     pub genres: String,
 }
 
+impl Title {
+    /// Return the years this title ran, e.g., `2005–2013` for a TV show that
+    /// has ended or `2005–` for one that hasn't. Titles that aren't TV
+    /// series, along with TV series that started and ended in the same
+    /// year, get a single year.
+    ///
+    /// If this title has no start year, then this returns `None`.
+    pub fn year_range(&self) -> Option<String> {
+        let start = self.start_year?;
+        Some(match self.end_year {
+            Some(end) if end != start => format!("{}–{}", start, end),
+            None if self.kind.is_tv_series() => format!("{}–", start),
+            _ => start.to_string(),
+        })
+    }
+}
+
 /// The kind of a title. These form a partioning of all titles, where every
 /// title has exactly one kind.
 ///
@@ -117,10 +138,7 @@ impl TitleKind {
     pub fn is_tv_series(&self) -> bool {
         use self::TitleKind::*;
 
-        match *self {
-            TVMiniSeries | TVSeries => true,
-            _ => false,
-        }
+        matches!(*self, TVMiniSeries | TVSeries)
     }
 }
 
@@ -207,7 +225,7 @@ pub struct AKA {
 /// provides episode specific information, such as the season and episode
 /// number. The two title records joined correspond to the title record for the
 /// TV show and the title record for the episode.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Episode {
     /// The IMDb title identifier for this episode.
     #[serde(rename = "tconst")]
@@ -231,7 +249,7 @@ pub struct Episode {
 }
 
 /// A rating associated with a single title record.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Rating {
     /// The IMDb title identifier for this rating.
     #[serde(rename = "tconst")]
@@ -251,6 +269,13 @@ where
     i32::deserialize(de).map(|n| n != 0)
 }
 
+fn bool_as_number<S>(b: &bool, ser: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    ser.serialize_i32(*b as i32)
+}
+
 fn optional_number_as_bool<'de, D>(de: D) -> Result<Option<bool>, D::Error>
 where
     D: Deserializer<'de>,
@@ -1,5 +1,6 @@
 use std::cmp;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
 use std::num::FpCategory;
 use std::vec;
 
@@ -30,7 +31,7 @@ impl<T> SearchResults<T> {
     /// The score provided must be less than or equal to every other score in
     /// this collection, otherwise this method will panic.
     pub fn push(&mut self, scored: Scored<T>) {
-        assert!(self.0.last().map_or(true, |smallest| &scored <= smallest));
+        assert!(self.0.last().is_none_or(|smallest| &scored <= smallest));
         self.0.push(scored);
     }
 
@@ -40,7 +41,7 @@ impl<T> SearchResults<T> {
     /// This operation is idempotent and does not change the ordering of
     /// results.
     pub fn normalize(&mut self) {
-        if let Some(top_score) = self.0.get(0).map(|s| s.score()) {
+        if let Some(top_score) = self.0.first().map(|s| s.score()) {
             // The minimal score is 0, so if the top score is 0, then all
             // scores must be 0. No normalization needed. (And we avoid a
             // divide-by-zero below.)
@@ -62,7 +63,59 @@ impl<T> SearchResults<T> {
             let score = rescore(result.value());
             result.set_score(score);
         }
-        self.0.sort_by(|s1, s2| s1.cmp(&s2).reverse());
+        self.0.sort_by(|s1, s2| s1.cmp(s2).reverse());
+    }
+
+    /// Recomputes the scores in this collection using the given function,
+    /// which is given each value along with its current score.
+    ///
+    /// The results are then re-sorted according to the new scores.
+    pub fn rescore_scored<F: FnMut(&Scored<T>) -> f64>(
+        &mut self,
+        mut rescore: F,
+    ) {
+        for result in &mut self.0 {
+            let score = rescore(result);
+            result.set_score(score);
+        }
+        self.0.sort_by(|s1, s2| s1.cmp(s2).reverse());
+    }
+
+    /// Merge the given results into this collection.
+    ///
+    /// Values with the same key, as computed by the given function, are
+    /// considered the same, and only the one with the highest score is kept.
+    /// (When scores are equal, the value already in this collection wins.)
+    /// The merged results are then re-sorted according to their scores.
+    ///
+    /// Scores are compared as they are, so they should come from searches
+    /// that score in the same way, e.g., searches of the same index with the
+    /// same scorer whose scores weren't normalized.
+    pub fn merge<K, F>(&mut self, other: SearchResults<T>, mut key: F)
+    where
+        K: Eq + Hash,
+        F: FnMut(&T) -> K,
+    {
+        let mut positions: HashMap<K, usize> = self
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, result)| (key(result.value()), i))
+            .collect();
+        for result in other.0 {
+            match positions.get(&key(result.value())) {
+                Some(&i) => {
+                    if result.score() > self.0[i].score() {
+                        self.0[i] = result;
+                    }
+                }
+                None => {
+                    positions.insert(key(result.value()), self.0.len());
+                    self.0.push(result);
+                }
+            }
+        }
+        self.0.sort_by(|s1, s2| s1.cmp(s2).reverse());
     }
 
     /// Trim this collection so that it contains at most the first `size`
@@ -113,12 +166,13 @@ impl<T> IntoIterator for SearchResults<T> {
 pub struct Scored<T> {
     score: f64,
     value: T,
+    terms: Option<(usize, usize)>,
 }
 
 impl<T> Scored<T> {
     /// Create a new value `T` with a score of `1.0`.
     pub fn new(value: T) -> Scored<T> {
-        Scored { score: 1.0, value }
+        Scored { score: 1.0, value, terms: None }
     }
 
     /// Return the score for this item.
@@ -148,11 +202,48 @@ impl<T> Scored<T> {
         self
     }
 
+    /// Return the number of distinct terms of the query that matched this
+    /// value.
+    ///
+    /// This is only known for results of a search by name, where the terms
+    /// are the ngrams of the name searched for. Otherwise, this returns
+    /// `None`.
+    pub fn matched_terms(&self) -> Option<usize> {
+        self.terms.map(|(matched, _)| matched)
+    }
+
+    /// Return the number of distinct terms in the query that produced this
+    /// value. Like `matched_terms`, this is only known for results of a
+    /// search by name.
+    pub fn query_terms(&self) -> Option<usize> {
+        self.terms.map(|(_, query)| query)
+    }
+
+    /// Return the ratio of `matched_terms` to `query_terms`, if they are
+    /// known. A query without any terms has a ratio of `0`.
+    pub fn match_ratio(&self) -> Option<f64> {
+        self.terms.map(|(matched, query)| {
+            if query == 0 {
+                0.0
+            } else {
+                matched as f64 / query as f64
+            }
+        })
+    }
+
+    /// Consume this scored value and return a new scored value that records
+    /// the given number of matched terms out of the given number of terms in
+    /// the query. See `matched_terms` and `query_terms`.
+    pub fn with_terms(mut self, matched: usize, query: usize) -> Scored<T> {
+        self.terms = Some((matched, query));
+        self
+    }
+
     /// Consume this scored value and map its value using the function given,
     /// returning a new scored value with the result of the map and an
-    /// unchanged score.
+    /// unchanged score. Matched terms, if known, are also unchanged.
     pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Scored<U> {
-        Scored { score: self.score, value: f(self.value) }
+        Scored { score: self.score, value: f(self.value), terms: self.terms }
     }
 
     /// Consume this scored value and map its score using the function given,
@@ -176,7 +267,7 @@ impl<T> Scored<T> {
     }
 
     /// Consume this scored value and return the underlying pair of score and
-    /// `T`.
+    /// `T`. Matched terms are dropped.
     pub fn into_pair(self) -> (f64, T) {
         (self.score, self.value)
     }
@@ -211,24 +302,50 @@ impl<T> PartialOrd for Scored<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::Scored;
-    use std::f64::NAN;
+    use super::{Scored, SearchResults};
+
+    fn results(values: &[(&'static str, f64)]) -> SearchResults<&'static str> {
+        let mut results = SearchResults::new();
+        for &(value, score) in values {
+            results.push(Scored::new(value).with_score(score));
+        }
+        results
+    }
+
+    #[test]
+    fn merge() {
+        let mut merged = results(&[("a", 1.0), ("b", 0.5), ("c", 0.2)]);
+        merged.merge(results(&[("c", 0.9), ("d", 0.6), ("a", 0.1)]), |v| *v);
+        let got: Vec<(&str, f64)> = merged
+            .into_iter()
+            .map(|r| {
+                let (score, value) = r.into_pair();
+                (value, score)
+            })
+            .collect();
+        assert_eq!(got, vec![("a", 1.0), ("c", 0.9), ("d", 0.6), ("b", 0.5)]);
+
+        let mut merged = results(&[]);
+        merged.merge(results(&[("a", 1.0), ("a", 0.5)]), |v| *v);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged.as_slice()[0].score(), 1.0);
+    }
 
     #[test]
     #[should_panic]
     fn never_nan_1() {
-        Scored::new(()).set_score(NAN);
+        Scored::new(()).set_score(f64::NAN);
     }
 
     #[test]
     #[should_panic]
     fn never_nan_2() {
-        Scored::new(()).with_score(NAN);
+        Scored::new(()).with_score(f64::NAN);
     }
 
     #[test]
     #[should_panic]
     fn never_nan_3() {
-        Scored::new(()).map_score(|_| NAN);
+        Scored::new(()).map_score(|_| f64::NAN);
     }
 }
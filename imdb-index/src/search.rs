@@ -1,15 +1,27 @@
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::f64;
 use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
 use std::result;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::error::{Error, Result};
-use crate::index::{Index, MediaEntity, NameQuery, NameScorer};
+use crate::index::{
+    normalize_for_comparison, Index, MediaEntity, NameQuery, NameScorer,
+    Scorer,
+};
+use crate::progress::{Progress, ProgressFn};
 use crate::record::{Episode, Rating, Title, TitleKind};
 use crate::scored::{Scored, SearchResults};
 use crate::util::{csv_file, IMDB_BASICS};
@@ -24,9 +36,26 @@ use crate::util::{csv_file, IMDB_BASICS};
 /// The primary interface to a `Searcher` is its `search` method, which takes
 /// as input a [`Query`](struct.Query.html) and returns a ranked list of
 /// [`MediaEntity`](struct.MediaEntity.html) as output.
-#[derive(Debug)]
 pub struct Searcher {
     idx: Index,
+    custom_scorer: Option<Arc<dyn Scorer>>,
+    max_scan_rows: Option<usize>,
+    max_scan_time: Option<Duration>,
+    /// The progress callback, which is shared with the clones of this
+    /// searcher.
+    progress: Option<Arc<Mutex<ProgressFn>>>,
+}
+
+impl fmt::Debug for Searcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Searcher")
+            .field("idx", &self.idx)
+            .field("custom_scorer", &self.custom_scorer)
+            .field("max_scan_rows", &self.max_scan_rows)
+            .field("max_scan_time", &self.max_scan_time)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
 }
 
 impl Searcher {
@@ -37,7 +66,62 @@ impl Searcher {
     /// An existing `Index` can be opened with `Index::open`, and a new `Index`
     /// can be created with `Index::create`.
     pub fn new(idx: Index) -> Searcher {
-        Searcher { idx }
+        Searcher {
+            idx,
+            custom_scorer: None,
+            max_scan_rows: None,
+            max_scan_time: None,
+            progress: None,
+        }
+    }
+
+    /// Set a callback that periodically receives the progress of exhaustive
+    /// searches.
+    ///
+    /// Reports are made every few thousand records scanned. If any report
+    /// was made during a search, then a final report with `done` set is
+    /// made when the scan finishes.
+    pub fn progress(&mut self, progress: Option<ProgressFn>) -> &mut Searcher {
+        self.progress = progress.map(|p| Arc::new(Mutex::new(p)));
+        self
+    }
+
+    /// Set a custom scorer to use for name searches.
+    ///
+    /// When set, this is used instead of the name scorer of every query that
+    /// uses the name index. Queries without a name scorer still don't use the
+    /// name index at all.
+    ///
+    /// By default, no custom scorer is set.
+    pub fn custom_scorer(
+        &mut self,
+        scorer: Option<Arc<dyn Scorer>>,
+    ) -> &mut Searcher {
+        self.custom_scorer = scorer;
+        self
+    }
+
+    /// Limit the number of records that an exhaustive search may scan.
+    ///
+    /// When the limit is reached, the search stops and returns the results
+    /// found so far, and the diagnostics returned by `search_diagnostics`
+    /// report the search as truncated. Queries with the `exhaustive` flag
+    /// set ignore this limit.
+    ///
+    /// By default, there is no limit.
+    pub fn max_scan_rows(&mut self, rows: Option<usize>) -> &mut Searcher {
+        self.max_scan_rows = rows;
+        self
+    }
+
+    /// Limit the time that an exhaustive search may spend scanning records.
+    ///
+    /// This behaves like `max_scan_rows`, except the limit is on wall time.
+    ///
+    /// By default, there is no limit.
+    pub fn max_scan_time(&mut self, time: Option<Duration>) -> &mut Searcher {
+        self.max_scan_time = time;
+        self
     }
 
     /// Execute a search with the given `Query`.
@@ -67,42 +151,421 @@ impl Searcher {
     pub fn search(
         &mut self,
         query: &Query,
+    ) -> Result<SearchResults<MediaEntity>> {
+        self.search_imp(query, &mut SearchDiagnostics::default())
+    }
+
+    /// Execute a search with the given `Query`, and return its results along
+    /// with diagnostics and, when there are no results, the reason why.
+    ///
+    /// The results are exactly the same as the ones returned by `search`.
+    /// Determining the reason may require an additional search by name when
+    /// the query filters by year or kind, since those filters are applied by
+    /// the name index and thus aren't counted in the diagnostics.
+    pub fn search_with_diagnostics(
+        &mut self,
+        query: &Query,
+    ) -> Result<SearchOutcome> {
+        let (results, diagnostics) = self.search_diagnostics(query)?;
+        let empty_reason = if results.is_empty() {
+            Some(self.empty_reason(query, &diagnostics)?)
+        } else {
+            None
+        };
+        Ok(SearchOutcome { results, diagnostics, empty_reason })
+    }
+
+    /// Execute a search with the given `Query`, and return diagnostics about
+    /// the candidates that were dropped along with the results.
+    ///
+    /// The results are exactly the same as the ones returned by `search`.
+    /// The diagnostics are useful for determining why an expected result is
+    /// missing. e.g., whether it was never returned by the name index, was
+    /// removed by a filter or didn't make the cut for the query's size.
+    pub fn search_diagnostics(
+        &mut self,
+        query: &Query,
+    ) -> Result<(SearchResults<MediaEntity>, SearchDiagnostics)> {
+        let mut diag = SearchDiagnostics::default();
+        let results = self.search_imp(query, &mut diag)?;
+        Ok((results, diag))
+    }
+
+    fn search_imp(
+        &mut self,
+        query: &Query,
+        diag: &mut SearchDiagnostics,
     ) -> Result<SearchResults<MediaEntity>> {
         if query.is_empty() {
             return Ok(SearchResults::new());
         }
-        let mut results = match query.name_query() {
-            None => self.search_exhaustive(query)?,
-            Some(nameq) => self.search_with_name(query, &nameq)?,
+        let nameq = query.name_query().map(|nameq| match self.custom_scorer {
+            None => nameq,
+            Some(ref scorer) => nameq.with_custom_scorer(scorer.clone()),
+        });
+        let mut results = match nameq {
+            None => self.search_exhaustive(query, diag)?,
+            Some(_) if !query.other_names.is_empty() => {
+                self.search_with_names(query, diag)?
+            }
+            Some(nameq) => self.search_with_name(query, &nameq, diag)?,
         };
+        diag.dropped_size += results.len().saturating_sub(query.size);
         results.trim(query.size);
         results.normalize();
+        diag.lowest_score = results.as_slice().last().map(|r| r.score());
+        Ok(results)
+    }
+
+    /// Determine why the given query, whose search produced the given
+    /// diagnostics, returned no results.
+    fn empty_reason(
+        &mut self,
+        query: &Query,
+        diag: &SearchDiagnostics,
+    ) -> Result<EmptyReason> {
+        if query.is_empty() {
+            return Ok(EmptyReason::EmptyQuery);
+        }
+        if diag.truncated {
+            return Ok(EmptyReason::Truncated { scanned: diag.scanned });
+        }
+        let by_name = query.name_query().is_some();
+        if diag.candidates == 0 {
+            if !by_name {
+                return Ok(EmptyReason::NoCandidates);
+            }
+            let mut found = false;
+            for name in query.names() {
+                if self.idx.name_has_terms(name)? {
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return Ok(EmptyReason::NameNotFound);
+            }
+            return self.empty_reason_by_name(query);
+        }
+        if diag.dropped_size > 0 {
+            return Ok(EmptyReason::Trimmed { candidates: diag.candidates });
+        }
+        let filters = [
+            (Filter::Kind, diag.dropped_kind),
+            (Filter::Year, diag.dropped_year),
+            (Filter::Ended, diag.dropped_ended),
+            (Filter::Votes, diag.dropped_votes),
+            (Filter::Episode, diag.dropped_episode),
+            (Filter::Language, diag.dropped_language),
+            (Filter::Original, diag.dropped_original),
+        ];
+        Ok(EmptyReason::Filtered {
+            by_name,
+            candidates: diag.candidates,
+            filters: filters
+                .iter()
+                .filter(|&&(_, count)| count > 0)
+                .map(|&(filter, count)| (query.filter_syntax(filter), count))
+                .collect(),
+        })
+    }
+
+    /// Determine why a search by name found no candidates, even though the
+    /// name is in the name index. This happens when the name index drops
+    /// every match because of the query's year or kind filters, so the name
+    /// is searched again without them to count how many each one dropped.
+    fn empty_reason_by_name(&mut self, query: &Query) -> Result<EmptyReason> {
+        let (mut candidates, mut kind, mut year, mut ended) = (0, 0, 0, 0);
+        for subq in query.split_names() {
+            let nameq = match subq.name_query_unfiltered() {
+                None => return Ok(EmptyReason::NoCandidates),
+                Some(nameq) => match self.custom_scorer {
+                    None => nameq,
+                    Some(ref scorer) => {
+                        nameq.with_custom_scorer(scorer.clone())
+                    }
+                },
+            };
+            for r in self.idx.search(&nameq)? {
+                candidates += 1;
+                match query.reject_title(r.value()) {
+                    Some(Filter::Kind) => kind += 1,
+                    Some(Filter::Year) => year += 1,
+                    Some(Filter::Ended) => ended += 1,
+                    _ => {}
+                }
+            }
+        }
+        if candidates == 0 || kind + year + ended < candidates {
+            return Ok(EmptyReason::NoCandidates);
+        }
+        let mut filters = vec![];
+        if kind > 0 {
+            filters.push((query.filter_syntax(Filter::Kind), kind));
+        }
+        if year > 0 {
+            filters.push((query.filter_syntax(Filter::Year), year));
+        }
+        if ended > 0 {
+            filters.push((query.filter_syntax(Filter::Ended), ended));
+        }
+        Ok(EmptyReason::Filtered { by_name: true, candidates, filters })
+    }
+
+    /// Return titles with names similar to the title with the given IMDb
+    /// identifier, such as remakes, sequels or other entries in the same
+    /// franchise.
+    ///
+    /// Both the primary and original names of the title are searched, and
+    /// the title itself is never returned. Results with the same kind as the
+    /// title, or that started within a few years of it, are boosted. Scores
+    /// are normalized such that the top result has a score of `1.0`.
+    ///
+    /// If no title with the given identifier exists, then no results are
+    /// returned.
+    pub fn similar(
+        &mut self,
+        id: &str,
+        size: usize,
+    ) -> Result<SearchResults<MediaEntity>> {
+        let title = match self.idx.title(id)? {
+            None => return Ok(SearchResults::new()),
+            Some(title) => title,
+        };
+        let mut names = vec![&title.title];
+        if title.original_title != title.title {
+            names.push(&title.original_title);
+        }
+        // Each name's scores are normalized so that they're comparable, and
+        // a title found by both names keeps its best score.
+        let window = cmp::max(DEFAULT_RESCORE_WINDOW, size + 1);
+        let mut best: HashMap<String, Scored<Title>> = HashMap::new();
+        for name in names {
+            let mut candidates =
+                self.idx.search(&NameQuery::new(name).with_size(window))?;
+            candidates.normalize();
+            for candidate in candidates.into_vec() {
+                if candidate.value().id == title.id {
+                    continue;
+                }
+                let id = candidate.value().id.clone();
+                match best.get(&id) {
+                    Some(prev) if prev.score() >= candidate.score() => {}
+                    _ => {
+                        best.insert(id, candidate);
+                    }
+                }
+            }
+        }
+
+        let mut sorted: Vec<Scored<Title>> = best
+            .into_values()
+            .map(|r| {
+                let mut boost = 1.0;
+                if r.value().kind == title.kind {
+                    boost *= SIMILAR_BOOST;
+                }
+                let years = title.start_year.zip(r.value().start_year);
+                if years
+                    .is_some_and(|(y1, y2)| y1.abs_diff(y2) <= SIMILAR_YEARS)
+                {
+                    boost *= SIMILAR_BOOST;
+                }
+                r.map_score(|score| score * boost)
+            })
+            .collect();
+        sorted.sort_by(|s1, s2| s1.cmp(s2).reverse());
+        let mut tresults = SearchResults::new();
+        for candidate in sorted {
+            tresults.push(candidate);
+        }
+        tresults.trim(size);
+
+        let mut results = SearchResults::new();
+        for tresult in tresults.into_vec() {
+            let (score, other) = tresult.into_pair();
+            let entity = self.idx.entity_from_title(other)?;
+            results.push(Scored::new(entity).with_score(score));
+        }
+        results.normalize();
         Ok(results)
     }
 
+    /// Boost the results of a search for a numbered sequel, like
+    /// `John Wick 3`, that contain its number.
+    ///
+    /// When the given name ends with a number, and several results share the
+    /// rest of the name, the results containing that number in their title
+    /// or one of their alternate names are boosted. The number may be
+    /// written in arabic or roman numerals. Otherwise, the first entry of a
+    /// franchise tends to win, since the rest of the name dominates its
+    /// score and the number contributes little.
+    ///
+    /// Boosted results are moved ahead of every other result, but keep their
+    /// order relative to one another. Scores are normalized afterwards.
+    pub fn boost_sequel(
+        &mut self,
+        name: &str,
+        results: &mut SearchResults<MediaEntity>,
+    ) -> Result<()> {
+        let sequel = match Sequel::parse(name) {
+            None => return Ok(()),
+            Some(sequel) => sequel,
+        };
+        let related = results
+            .as_slice()
+            .iter()
+            .filter(|r| sequel.is_related(&r.value().title().title))
+            .count();
+        if related < 2 {
+            return Ok(());
+        }
+        let mut numbered = HashSet::new();
+        for r in results.as_slice() {
+            let title = r.value().title();
+            if sequel.is_numbered(&title.title) {
+                numbered.insert(title.id.clone());
+                continue;
+            }
+            for aka in self.idx.aka_records(&title.id)? {
+                if sequel.is_numbered(&aka?.title) {
+                    numbered.insert(title.id.clone());
+                    break;
+                }
+            }
+        }
+        if numbered.is_empty() {
+            return Ok(());
+        }
+        log::debug!("boosting {} results numbered {}", numbered.len(), name);
+        let top = results.as_slice()[0].score();
+        results.rescore_scored(|r| {
+            if numbered.contains(&r.value().title().id) {
+                top * SEQUEL_BOOST + r.score()
+            } else {
+                r.score()
+            }
+        });
+        results.normalize();
+        Ok(())
+    }
+
+    /// Attempt to clone this searcher, returning a distinct `Searcher` with
+    /// its own clone of the index and the same settings, e.g., the same scan
+    /// budget. A progress callback is shared with the clone.
+    ///
+    /// This returns an error if there was a problem reading the underlying
+    /// index.
+    ///
+    /// This is useful when one wants to search the same `Index` from multiple
+    /// threads.
+    pub fn try_clone(&self) -> Result<Searcher> {
+        Ok(Searcher {
+            idx: self.idx.try_clone()?,
+            custom_scorer: self.custom_scorer.clone(),
+            max_scan_rows: self.max_scan_rows,
+            max_scan_time: self.max_scan_time,
+            progress: self.progress.clone(),
+        })
+    }
+
     /// Return a mutable reference to the underlying index for this searcher.
     pub fn index(&mut self) -> &mut Index {
         &mut self.idx
     }
 
+    /// Search the name index once for each of the query's names, and merge
+    /// the results. A title found by more than one name keeps its best score.
+    ///
+    /// The raw scores are merged, and only normalized once all names were
+    /// searched. Normalizing each name's results would give the best match
+    /// of every name a score of `1.0`, even when that name barely matched
+    /// anything.
+    fn search_with_names(
+        &mut self,
+        query: &Query,
+        diag: &mut SearchDiagnostics,
+    ) -> Result<SearchResults<MediaEntity>> {
+        let mut merged = SearchResults::new();
+        for subq in query.split_names() {
+            let nameq = match subq.name_query() {
+                None => continue,
+                Some(nameq) => match self.custom_scorer {
+                    None => nameq.with_raw_scores(),
+                    Some(ref scorer) => nameq
+                        .with_raw_scores()
+                        .with_custom_scorer(scorer.clone()),
+                },
+            };
+            let results = self.search_with_name(&subq, &nameq, diag)?;
+            merged.merge(results, |ent| ent.title().id.clone());
+        }
+        Ok(merged)
+    }
+
+    /// Search the name index for the given name query, and then apply the
+    /// rest of the query to its candidates.
+    ///
+    /// The name index returns at most the query's rescore window of
+    /// candidates. Those are filtered and, if the query has a similarity
+    /// function, rescored. Only then are they trimmed to the query's size,
+    /// so that the similarity function decides which results are kept, and
+    /// normalized, so that the best result has a score of `1.0` whether or
+    /// not they were rescored. Without a similarity function, the name
+    /// index's ranking is final, so filtering stops once the query's size
+    /// is reached.
+    ///
+    /// When the name query asks for raw scores, they are left for the
+    /// caller to normalize. See `search_with_names`.
     fn search_with_name(
         &mut self,
         query: &Query,
         name_query: &NameQuery,
+        diag: &mut SearchDiagnostics,
     ) -> Result<SearchResults<MediaEntity>> {
         let mut results = SearchResults::new();
-        for r in self.idx.search(name_query)? {
+        let candidates = self.idx.search(name_query)?;
+        diag.candidates += candidates.len();
+        let mut it = candidates.into_iter();
+        for r in &mut it {
             if query.similarity.is_none() && results.len() >= query.size {
+                diag.dropped_size += 1;
                 break;
             }
+            let terms = r.matched_terms().zip(r.query_terms());
             let (score, title) = r.into_pair();
+            if query.original_only && !self.matches_original(query, &title)? {
+                diag.dropped(Filter::Original);
+                continue;
+            }
             let entity = self.idx.entity_from_title(title)?;
-            if query.matches(&entity) {
-                results.push(Scored::new(entity).with_score(score));
+            if filter(&mut self.idx, query, &entity, diag)? {
+                let mut result = Scored::new(entity).with_score(score);
+                if let Some((matched, query_terms)) = terms {
+                    result = result.with_terms(matched, query_terms);
+                }
+                results.push(result);
             }
         }
+        diag.dropped_size += it.len();
         if !query.similarity.is_none() {
-            results.rescore(|e| self.similarity(query, &e.title().title));
+            diag.rescored += results.len();
+            if query.combine == Combine::Replace {
+                results.rescore(|e| self.title_similarity(query, e.title()));
+            } else {
+                // Name scores are unbounded, so they are normalized to make
+                // them comparable with similarities.
+                results.normalize();
+                results.rescore_scored(|r| {
+                    let sim = self.title_similarity(query, r.value().title());
+                    query.combine.combine(r.score(), sim)
+                });
+            }
+        }
+        diag.dropped_size += results.len().saturating_sub(query.size);
+        results.trim(query.size);
+        if !name_query.has_raw_scores() {
+            results.normalize();
         }
         Ok(results)
     }
@@ -110,24 +573,50 @@ impl Searcher {
     fn search_exhaustive(
         &mut self,
         query: &Query,
+        diag: &mut SearchDiagnostics,
     ) -> Result<SearchResults<MediaEntity>> {
         if let Some(ref tvshow_id) = query.tvshow_id {
-            return self.search_with_tvshow(query, tvshow_id);
+            return self.search_with_tvshow(query, tvshow_id, diag);
         }
 
-        let mut rdr = csv_file(self.idx.data_dir().join(IMDB_BASICS))?;
+        let path = self.idx.data_dir().join(IMDB_BASICS);
+        let mut scan = Scan::new(
+            query,
+            self.max_scan_rows,
+            self.max_scan_time,
+            self.progress.as_deref(),
+            &path,
+        )?;
+        let mut rdr = csv_file(&path)?;
         if !query.has_filters() {
             let mut nresults = SearchResults::new();
             let mut record = csv::StringRecord::new();
-            while rdr.read_record(&mut record).map_err(Error::csv)? {
-                let id_title = (record[0].to_string(), record[2].to_string());
-                nresults.push(Scored::new(id_title));
+            while !scan.is_over(&rdr, diag)
+                && rdr.read_record(&mut record).map_err(Error::csv)?
+            {
+                // Adult titles aren't indexed, so they are never returned
+                // by searches by name. Skip them here too, for consistency.
+                if &record[4] == "1" {
+                    continue;
+                }
+                nresults.push(Scored::new((
+                    record[0].to_string(),
+                    record[2].to_string(),
+                    record[3].to_string(),
+                )));
             }
-            nresults.rescore(|t| self.similarity(query, &t.1));
+            scan.finish(diag);
+            diag.candidates += nresults.len();
+            diag.dropped_size += nresults.len().saturating_sub(query.size);
+            nresults.rescore(|t| {
+                let (_, ref title, ref original_title) = *t;
+                self.similarity(query, title)
+                    .max(self.similarity(query, original_title))
+            });
 
             let mut results = SearchResults::new();
             for nresult in nresults.into_vec().into_iter().take(query.size) {
-                let (score, (id, _)) = nresult.into_pair();
+                let (score, (id, _, _)) = nresult.into_pair();
                 let entity = match self.idx.entity(&id)? {
                     None => continue,
                     Some(entity) => entity,
@@ -137,13 +626,21 @@ impl Searcher {
             Ok(results)
         } else if query.needs_only_title() {
             let mut tresults = SearchResults::new();
-            for result in rdr.deserialize() {
+            let mut it = rdr.deserialize();
+            while let Some(result) = scan.next(&mut it, diag) {
                 let title: Title = result.map_err(Error::csv)?;
-                if query.matches_title(&title) {
-                    tresults.push(Scored::new(title));
+                if title.is_adult {
+                    continue;
+                }
+                diag.candidates += 1;
+                match query.reject_title(&title) {
+                    None => tresults.push(Scored::new(title)),
+                    Some(filter) => diag.dropped(filter),
                 }
             }
-            tresults.rescore(|t| self.similarity(query, &t.title));
+            scan.finish(diag);
+            diag.dropped_size += tresults.len().saturating_sub(query.size);
+            tresults.rescore(|t| self.title_similarity(query, t));
 
             let mut results = SearchResults::new();
             for tresult in tresults.into_vec().into_iter().take(query.size) {
@@ -154,14 +651,20 @@ impl Searcher {
             Ok(results)
         } else {
             let mut results = SearchResults::new();
-            for result in rdr.deserialize() {
-                let title = result.map_err(Error::csv)?;
+            let mut it = rdr.deserialize();
+            while let Some(result) = scan.next(&mut it, diag) {
+                let title: Title = result.map_err(Error::csv)?;
+                if title.is_adult {
+                    continue;
+                }
                 let entity = self.idx.entity_from_title(title)?;
-                if query.matches(&entity) {
+                diag.candidates += 1;
+                if filter(&mut self.idx, query, &entity, diag)? {
                     results.push(Scored::new(entity));
                 }
             }
-            results.rescore(|e| self.similarity(query, &e.title().title));
+            scan.finish(diag);
+            results.rescore(|e| self.title_similarity(query, e.title()));
             Ok(results)
         }
     }
@@ -170,31 +673,550 @@ impl Searcher {
         &mut self,
         query: &Query,
         tvshow_id: &str,
+        diag: &mut SearchDiagnostics,
     ) -> Result<SearchResults<MediaEntity>> {
+        // The episodes are read from a handle of their own, so that each one
+        // can be looked up in the index as they're streamed.
+        let episodes = self.idx.episode_index();
+        let mut it = episodes.seasons_iter(tvshow_id.as_bytes());
         let mut results = SearchResults::new();
-        for ep in self.idx.seasons(tvshow_id)? {
+        while let Some(result) = it.next() {
+            let ep = result?;
+            // Episodes are sorted by season, with unnumbered seasons last, so
+            // nothing after the query's last season can match. Those are
+            // still candidates, but they're dropped without being looked up.
+            if let Some(end) = query.season.end {
+                if ep.season.is_none_or(|season| season > end) {
+                    let rest = 1 + it.count();
+                    diag.candidates += rest;
+                    diag.dropped_episode += rest;
+                    break;
+                }
+            }
             let entity = match self.idx.entity(&ep.id)? {
                 None => continue,
                 Some(entity) => entity,
             };
-            if query.matches(&entity) {
+            diag.candidates += 1;
+            if filter(&mut self.idx, query, &entity, diag)? {
                 results.push(Scored::new(entity));
             }
         }
         if !query.similarity.is_none() {
-            results.rescore(|e| self.similarity(query, &e.title().title));
+            results.rescore(|e| self.title_similarity(query, e.title()));
         }
         Ok(results)
     }
 
+    /// Returns true if the query's name is at least as similar to the given
+    /// title's primary or original name as it is to any of the title's
+    /// alternate names. Returns false when the title was only found because
+    /// of an alternate name.
+    ///
+    /// Names are compared using the query's similarity function, or
+    /// Jaro-Winkler if it has none, without case or diacritics.
+    fn matches_original(
+        &mut self,
+        query: &Query,
+        title: &Title,
+    ) -> Result<bool> {
+        let name = match query.name {
+            None => return Ok(true),
+            Some(ref name) => normalize_for_comparison(name),
+        };
+        let sim = if query.similarity.is_none() {
+            Similarity::JaroWinkler
+        } else {
+            query.similarity
+        };
+        // Without any alternate names, the title can only have matched by
+        // its own names.
+        if !self.idx.has_akas(&title.id)? {
+            return Ok(true);
+        }
+        let score = |other: &str| {
+            sim.similarity(&name, &normalize_for_comparison(other))
+        };
+        let best = score(&title.title).max(score(&title.original_title));
+        for result in self.idx.aka_records(&title.id)? {
+            if score(&result?.title) > best {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the similarity between the query and the given title. This
+    /// is the maximum of the similarities with the title's primary name and
+    /// its original name, which helps queries written in a title's original
+    /// language.
+    fn title_similarity(&self, query: &Query, title: &Title) -> f64 {
+        let sim = self.similarity(query, &title.title);
+        if title.original_title == title.title {
+            return sim;
+        }
+        sim.max(self.similarity(query, &title.original_title))
+    }
+
+    /// Returns the similarity between the given name and the closest of the
+    /// query's names.
     fn similarity(&self, query: &Query, name: &str) -> f64 {
-        match query.name {
-            None => 0.0,
-            Some(ref qname) => query.similarity.similarity(qname, name),
+        query
+            .names()
+            .map(|qname| query.similarity.similarity(qname, name))
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Open the index in `index_dir`, built from the IMDb data in `data_dir`,
+/// and execute a single search with the given free-form query, returning at
+/// most `size` results.
+///
+/// The query uses the same syntax as `Query`'s `FromStr` implementation,
+/// e.g., `the matrix {movie} {year:1999}`. The size given replaces any size
+/// set in the query itself.
+///
+/// This is a convenience for programs that only need to run one search.
+/// Running many searches this way is wasteful, since every search opens the
+/// index anew. To reuse an index that is already open, use
+/// `search_once_with` instead.
+///
+/// # Example
+///
+/// ```
+/// # fn example() -> imdb_index::Result<()> {
+/// # let data_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../data/test/small");
+/// # let tmp = std::env::temp_dir()
+/// #     .join(format!("imdb-index-doctest-search-{}", std::process::id()));
+/// # let index_dir = tmp.as_path();
+/// # imdb_index::Index::create(data_dir, index_dir)?;
+/// let results =
+///     imdb_index::search_once(data_dir, index_dir, "the matrix {movie}", 5)?;
+/// assert_eq!(results.as_slice()[0].value().title().id, "tt0133093");
+/// # std::fs::remove_dir_all(index_dir).unwrap();
+/// # Ok(())
+/// # }
+/// # example().unwrap();
+/// ```
+pub fn search_once<P1: AsRef<Path>, P2: AsRef<Path>>(
+    data_dir: P1,
+    index_dir: P2,
+    query: &str,
+    size: usize,
+) -> Result<SearchResults<MediaEntity>> {
+    let mut searcher = Searcher::new(Index::open(data_dir, index_dir)?);
+    search_once_with(&mut searcher, query, size)
+}
+
+/// Like `search_once`, but searches with the given searcher instead of
+/// opening an index.
+///
+/// Since a searcher keeps its index open, along with any parts of the index
+/// that were loaded by previous searches, this is much cheaper than
+/// `search_once` when searching more than once.
+pub fn search_once_with(
+    searcher: &mut Searcher,
+    query: &str,
+    size: usize,
+) -> Result<SearchResults<MediaEntity>> {
+    let query: Query = query.parse()?;
+    searcher.search(&query.size(size))
+}
+
+/// Open the index in `index_dir`, built from the IMDb data in `data_dir`,
+/// and return the best match for the given free-form query, if there is
+/// any.
+///
+/// This is like `search_once`, except that only the top ranked result is
+/// returned. No attempt is made to decide whether the top result stands out
+/// from the rest, so it's only as good as the query.
+///
+/// # Example
+///
+/// ```
+/// # fn example() -> imdb_index::Result<()> {
+/// # let data_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../data/test/small");
+/// # let tmp = std::env::temp_dir()
+/// #     .join(format!("imdb-index-doctest-best-{}", std::process::id()));
+/// # let index_dir = tmp.as_path();
+/// # imdb_index::Index::create(data_dir, index_dir)?;
+/// let ent = imdb_index::best_match(data_dir, index_dir, "the simpsons {show}")?;
+/// assert_eq!(ent.unwrap().title().id, "tt0096697");
+///
+/// let ent = imdb_index::best_match(data_dir, index_dir, "zzzzzz")?;
+/// assert!(ent.is_none());
+/// # std::fs::remove_dir_all(index_dir).unwrap();
+/// # Ok(())
+/// # }
+/// # example().unwrap();
+/// ```
+pub fn best_match<P1: AsRef<Path>, P2: AsRef<Path>>(
+    data_dir: P1,
+    index_dir: P2,
+    query: &str,
+) -> Result<Option<MediaEntity>> {
+    let mut searcher = Searcher::new(Index::open(data_dir, index_dir)?);
+    best_match_with(&mut searcher, query)
+}
+
+/// Like `best_match`, but searches with the given searcher instead of
+/// opening an index. See `search_once_with`.
+pub fn best_match_with(
+    searcher: &mut Searcher,
+    query: &str,
+) -> Result<Option<MediaEntity>> {
+    let results = search_once_with(searcher, query, 1)?;
+    Ok(results.into_iter().next().map(|r| r.into_value()))
+}
+
+/// Returns true if the given entity matches the query's filters, and records
+/// the filter that rejected it in the diagnostics otherwise.
+///
+/// Unlike the other filters, the language filter requires reading the
+/// entity's AKA records, so it's applied last.
+fn filter(
+    idx: &mut Index,
+    query: &Query,
+    ent: &MediaEntity,
+    diag: &mut SearchDiagnostics,
+) -> Result<bool> {
+    if !diag.filter(query, ent) {
+        return Ok(false);
+    }
+    let language = match query.language {
+        None => return Ok(true),
+        Some(ref language) => language,
+    };
+    for result in idx.aka_records(&ent.title().id)? {
+        if result?.language.eq_ignore_ascii_case(language) {
+            return Ok(true);
+        }
+    }
+    diag.dropped(Filter::Language);
+    Ok(false)
+}
+
+/// Counters describing how a search arrived at its results.
+///
+/// These are returned by `Searcher::search_diagnostics`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SearchDiagnostics {
+    /// The number of candidates considered. For a search by name, these are
+    /// the results of the name index. Otherwise, these are every record
+    /// scanned.
+    pub candidates: usize,
+    /// The number of candidates rejected by the query's kind filter.
+    pub dropped_kind: usize,
+    /// The number of candidates rejected by the query's year filter.
+    pub dropped_year: usize,
+    /// The number of candidates rejected by the query's ended or airing
+    /// filter.
+    pub dropped_ended: usize,
+    /// The number of candidates rejected by the query's votes filter.
+    pub dropped_votes: usize,
+    /// The number of candidates rejected by the query's season, episode or
+    /// TV show filters.
+    pub dropped_episode: usize,
+    /// The number of candidates rejected by the query's language filter.
+    pub dropped_language: usize,
+    /// The number of candidates rejected because they only matched the
+    /// query by an alternate name. See `Query::original_only`.
+    pub dropped_original: usize,
+    /// The number of candidates that matched the query's filters (or that
+    /// weren't checked), but were cut because of the query's size.
+    pub dropped_size: usize,
+    /// The number of candidates rescored by the query's similarity function
+    /// in a search by name. This is bounded by the query's rescore window.
+    /// See `Query::rescore_window`.
+    #[serde(default)]
+    pub rescored: usize,
+    /// The (normalized) score of the lowest ranked result. This is `None`
+    /// when there are no results.
+    pub lowest_score: Option<f64>,
+    /// Whether an exhaustive search stopped early because it ran out of its
+    /// budget. See `Searcher::max_scan_rows` and `Searcher::max_scan_time`.
+    pub truncated: bool,
+    /// The number of records read by an exhaustive search.
+    pub scanned: usize,
+    /// When an exhaustive search was truncated, this is an estimate of the
+    /// total number of records it would have read.
+    pub estimated_total: Option<usize>,
+}
+
+impl SearchDiagnostics {
+    /// Return the number of candidates rejected by any of the query's
+    /// filters, i.e., every dropped candidate except those cut because of
+    /// the query's size.
+    pub fn dropped_by_filters(&self) -> usize {
+        self.dropped_kind
+            + self.dropped_year
+            + self.dropped_ended
+            + self.dropped_votes
+            + self.dropped_episode
+            + self.dropped_language
+            + self.dropped_original
+    }
+
+    /// Returns true if the given entity matches the query's filters, and
+    /// records the filter that rejected it otherwise.
+    fn filter(&mut self, query: &Query, ent: &MediaEntity) -> bool {
+        match query.reject(ent) {
+            None => true,
+            Some(filter) => {
+                self.dropped(filter);
+                false
+            }
+        }
+    }
+
+    fn dropped(&mut self, filter: Filter) {
+        match filter {
+            Filter::Kind => self.dropped_kind += 1,
+            Filter::Year => self.dropped_year += 1,
+            Filter::Ended => self.dropped_ended += 1,
+            Filter::Votes => self.dropped_votes += 1,
+            Filter::Episode => self.dropped_episode += 1,
+            Filter::Language => self.dropped_language += 1,
+            Filter::Original => self.dropped_original += 1,
+        }
+    }
+}
+
+impl fmt::Display for SearchDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} candidates, dropped by kind: {}, year: {}, ended: {}, \
+             votes: {}, episode: {}, language: {}, original: {}, size: {}",
+            self.candidates,
+            self.dropped_kind,
+            self.dropped_year,
+            self.dropped_ended,
+            self.dropped_votes,
+            self.dropped_episode,
+            self.dropped_language,
+            self.dropped_original,
+            self.dropped_size,
+        )?;
+        if self.rescored > 0 {
+            write!(f, ", rescored: {}", self.rescored)?;
         }
+        if let Some(score) = self.lowest_score {
+            write!(f, ", lowest score: {:.3}", score)?;
+        }
+        if self.truncated {
+            write!(f, ", truncated after scanning {} titles", self.scanned)?;
+        }
+        Ok(())
+    }
+}
+
+/// The results of a search, along with diagnostics about how they were found.
+///
+/// This is returned by `Searcher::search_with_diagnostics`.
+#[derive(Clone, Debug)]
+pub struct SearchOutcome {
+    /// The results of the search.
+    pub results: SearchResults<MediaEntity>,
+    /// How the search arrived at its results.
+    pub diagnostics: SearchDiagnostics,
+    /// Why the search returned no results. This is `None` when there are
+    /// results.
+    pub empty_reason: Option<EmptyReason>,
+}
+
+/// The reason a search returned no results.
+///
+/// The `Display` implementation describes the reason in a way that is
+/// suitable for showing to an end user.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmptyReason {
+    /// The query has no name and no filters.
+    EmptyQuery,
+    /// None of the ngrams of the name searched for are in the name index.
+    NameNotFound,
+    /// Every candidate was removed by the query's filters.
+    Filtered {
+        /// Whether the candidates were found by searching by name, as
+        /// opposed to scanning every title.
+        by_name: bool,
+        /// The number of candidates.
+        candidates: usize,
+        /// Each filter that removed candidates, written in the free-form
+        /// query syntax, along with the number of candidates it removed.
+        filters: Vec<(String, usize)>,
+    },
+    /// Every candidate was cut because of the query's size.
+    Trimmed {
+        /// The number of candidates.
+        candidates: usize,
+    },
+    /// An exhaustive search ran out of its budget before finding anything.
+    Truncated {
+        /// The number of records read before the search was stopped.
+        scanned: usize,
+    },
+    /// Nothing was found to consider, e.g., a TV show without episodes.
+    NoCandidates,
+}
+
+impl fmt::Display for EmptyReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmptyReason::EmptyQuery => write!(f, "the query is empty"),
+            EmptyReason::NameNotFound => {
+                write!(f, "no part of the name is in the index")
+            }
+            EmptyReason::Filtered { by_name, candidates, ref filters } => {
+                let what = if by_name { "name matches" } else { "titles" };
+                write!(f, "all {} {} were excluded by ", candidates, what)?;
+                if let [(ref filter, _)] = filters[..] {
+                    return write!(f, "{}", filter);
+                }
+                for (i, (filter, count)) in filters.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} ({})", filter, count)?;
+                }
+                Ok(())
+            }
+            EmptyReason::Trimmed { candidates } => write!(
+                f,
+                "all {} matches were cut by the query's {{size}}",
+                candidates
+            ),
+            EmptyReason::Truncated { scanned } => write!(
+                f,
+                "the search stopped after scanning {} titles; refine the \
+                 query or pass {{exhaustive}}",
+                scanned
+            ),
+            EmptyReason::NoCandidates => write!(f, "nothing matched"),
+        }
+    }
+}
+
+/// The budget of an exhaustive scan over title records.
+struct Scan<'a> {
+    start: Instant,
+    rows: usize,
+    max_rows: Option<usize>,
+    max_time: Option<Duration>,
+    /// The size of the file being scanned, for estimating its row count.
+    file_len: u64,
+    progress: Option<&'a Mutex<ProgressFn>>,
+    reported: bool,
+}
+
+impl<'a> Scan<'a> {
+    /// The number of rows between progress reports. This is smaller in tests
+    /// so that reports are made for small data sets.
+    const REPORT_EVERY: usize = if cfg!(test) { 16 } else { 1 << 14 };
+
+    /// Start a scan of the file at the given path with the given budget,
+    /// unless the query lifts it.
+    fn new(
+        query: &Query,
+        max_rows: Option<usize>,
+        max_time: Option<Duration>,
+        progress: Option<&'a Mutex<ProgressFn>>,
+        path: &Path,
+    ) -> Result<Scan<'a>> {
+        let (max_rows, max_time) =
+            if query.exhaustive { (None, None) } else { (max_rows, max_time) };
+        let file_len =
+            fs::metadata(path).map_err(|e| Error::io_path(e, path))?.len();
+        Ok(Scan {
+            start: Instant::now(),
+            rows: 0,
+            max_rows,
+            max_time,
+            file_len,
+            progress,
+            reported: false,
+        })
+    }
+
+    /// Report the end of this scan, if any progress was reported before.
+    fn finish(&mut self, diag: &SearchDiagnostics) {
+        if self.reported {
+            self.report(diag, true);
+        }
+    }
+
+    /// Report the progress of this scan so far.
+    fn report(&mut self, diag: &SearchDiagnostics, done: bool) {
+        let mut progress = match self.progress {
+            None => return,
+            Some(progress) => progress.lock().unwrap(),
+        };
+        (*progress)(&Progress {
+            rows: diag.scanned,
+            matches: diag.scanned.saturating_sub(diag.dropped_by_filters()),
+            elapsed: self.start.elapsed(),
+            done,
+        });
+        self.reported = true;
+    }
+
+    /// Returns true if the budget of this scan has been spent, in which case
+    /// the scan is recorded as truncated. Otherwise, a row is counted
+    /// against the budget.
+    fn is_over<R: io::Read>(
+        &mut self,
+        rdr: &csv::Reader<R>,
+        diag: &mut SearchDiagnostics,
+    ) -> bool {
+        diag.scanned = self.rows;
+        // Checking the time is comparatively expensive, so it's done only
+        // periodically.
+        let over = self.max_rows.is_some_and(|max| self.rows >= max)
+            || (self.rows.is_multiple_of(1024)
+                && self
+                    .max_time
+                    .is_some_and(|max| self.start.elapsed() >= max));
+        if !over {
+            if self.rows > 0 && self.rows.is_multiple_of(Self::REPORT_EVERY) {
+                self.report(diag, false);
+            }
+            self.rows += 1;
+            return false;
+        }
+        diag.truncated = true;
+        let read = rdr.position().byte();
+        let scanned = (self.rows as u64).saturating_mul(self.file_len);
+        diag.estimated_total =
+            scanned.checked_div(read).map(|total| total as usize);
+        true
+    }
+
+    /// Return the next record from the given iterator, unless the budget of
+    /// this scan has been spent.
+    fn next<R: io::Read, D: DeserializeOwned>(
+        &mut self,
+        it: &mut csv::DeserializeRecordsIter<R, D>,
+        diag: &mut SearchDiagnostics,
+    ) -> Option<csv::Result<D>> {
+        if self.is_over(it.reader(), diag) {
+            return None;
+        }
+        it.next()
     }
 }
 
+/// A type of filter on a query, used to attribute dropped candidates.
+#[derive(Clone, Copy, Debug)]
+enum Filter {
+    Kind,
+    Year,
+    Ended,
+    Votes,
+    Episode,
+    Language,
+    Original,
+}
+
 /// A query that can be used to search IMDb media records.
 ///
 /// A query typically consists of a fuzzy name query along with zero or more
@@ -212,17 +1234,43 @@ impl Searcher {
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Query {
     name: Option<String>,
+    /// Alternate names, any of which a result may match instead of `name`.
+    other_names: Vec<String>,
     name_scorer: Option<NameScorer>,
     similarity: Similarity,
     size: usize,
+    /// Kept sorted, so that queries with the same kinds added in a different
+    /// order are equal.
     kinds: Vec<TitleKind>,
     year: Range<u32>,
+    ended: Option<bool>,
     votes: Range<u32>,
     season: Range<u32>,
     episode: Range<u32>,
     tvshow_id: Option<String>,
+    /// Always lowercase.
+    language: Option<String>,
+    original_only: bool,
+    exhaustive: bool,
+    rescore_window: usize,
+    combine: Combine,
 }
 
+/// The default number of results requested from the name index, i.e., the
+/// default of `Query::rescore_window`.
+pub const DEFAULT_RESCORE_WINDOW: usize = 1000;
+
+/// The factor by which `Searcher::similar` boosts results that have the same
+/// kind as the title, and again for results that started within
+/// `SIMILAR_YEARS` of it.
+const SIMILAR_BOOST: f64 = 1.25;
+
+/// See `SIMILAR_BOOST`.
+const SIMILAR_YEARS: u32 = 5;
+
+/// The factor applied to the top score by `Searcher::boost_sequel`.
+const SEQUEL_BOOST: f64 = 1.5;
+
 impl Default for Query {
     fn default() -> Query {
         Query::new()
@@ -234,15 +1282,22 @@ impl Query {
     pub fn new() -> Query {
         Query {
             name: None,
+            other_names: vec![],
             name_scorer: Some(NameScorer::default()),
             similarity: Similarity::default(),
             size: 30,
             kinds: vec![],
             year: Range::none(),
+            ended: None,
             votes: Range::none(),
             season: Range::none(),
             episode: Range::none(),
             tvshow_id: None,
+            language: None,
+            original_only: false,
+            exhaustive: false,
+            rescore_window: DEFAULT_RESCORE_WINDOW,
+            combine: Combine::Replace,
         }
     }
 
@@ -250,13 +1305,15 @@ impl Query {
     ///
     /// Searching with an empty query always yields no results.
     pub fn is_empty(&self) -> bool {
-        self.name.as_ref().map_or(true, |n| n.is_empty())
+        self.names().all(|n| n.is_empty())
             && self.kinds.is_empty()
             && self.year.is_none()
+            && self.ended.is_none()
             && self.votes.is_none()
             && self.season.is_none()
             && self.episode.is_none()
             && self.tvshow_id.is_none()
+            && self.language.is_none()
     }
 
     /// Set the name to query by.
@@ -272,21 +1329,66 @@ impl Query {
         self
     }
 
-    /// Set the scorer to use for name searches.
+    /// Add an alternate name to query by.
     ///
-    /// The name scorer is used to rank results from searching the IMDb name
-    /// index. If no name query is given, then this scorer is not used.
+    /// A result only needs to match one of a query's names. This is useful
+    /// when a title is known by more than one name, e.g., its English and
+    /// original names, since searching for both names at once would match
+    /// neither of them well. The name index is searched once for each name,
+    /// and a title found by more than one name keeps its best score.
     ///
-    /// If `None` is provided here, then the name index will not be used. This
-    /// will likely cause an exhaustive search of all IMDb records, which can
-    /// be slow. The use case for providing a name query without a name scorer
-    /// is if you, for example, wanted to rank all of the records in IMDb
-    /// by the Levenshtein distance between your query and every other record
-    /// in IMDb. Normally, when the name index is used, only the (small number)
-    /// of results returned by searching the name are ranked. Typically, these
-    /// sorts of queries are useful for evaluation purposes, but not much else.
-    pub fn name_scorer(mut self, scorer: Option<NameScorer>) -> Query {
-        self.name_scorer = scorer;
+    /// If no name has been set yet, then this sets the name, like `name`.
+    /// In the free-form query syntax, names are separated by `|`, e.g.,
+    /// `oldboy | oldeuboi`.
+    pub fn name_or(mut self, name: &str) -> Query {
+        if self.name.is_none() {
+            self.name = Some(name.to_string());
+        } else {
+            self.other_names.push(name.to_string());
+        }
+        self
+    }
+
+    /// Return the name to query by, if one was set.
+    ///
+    /// This doesn't include any alternate names added by `name_or`.
+    pub fn name_str(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Return every name to query by, starting with the name set by `name`.
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.name.iter().chain(&self.other_names).map(|n| &**n)
+    }
+
+    /// Return a copy of this query for each of its names, where each copy
+    /// has only that one name.
+    fn split_names(&self) -> Vec<Query> {
+        self.names()
+            .map(|name| {
+                let mut q = self.clone();
+                q.name = Some(name.to_string());
+                q.other_names.clear();
+                q
+            })
+            .collect()
+    }
+
+    /// Set the scorer to use for name searches.
+    ///
+    /// The name scorer is used to rank results from searching the IMDb name
+    /// index. If no name query is given, then this scorer is not used.
+    ///
+    /// If `None` is provided here, then the name index will not be used. This
+    /// will likely cause an exhaustive search of all IMDb records, which can
+    /// be slow. The use case for providing a name query without a name scorer
+    /// is if you, for example, wanted to rank all of the records in IMDb
+    /// by the Levenshtein distance between your query and every other record
+    /// in IMDb. Normally, when the name index is used, only the (small number)
+    /// of results returned by searching the name are ranked. Typically, these
+    /// sorts of queries are useful for evaluation purposes, but not much else.
+    pub fn name_scorer(mut self, scorer: Option<NameScorer>) -> Query {
+        self.name_scorer = scorer;
         self
     }
 
@@ -317,6 +1419,35 @@ impl Query {
         self
     }
 
+    /// Set how the score from the name index is combined with the score from
+    /// the similarity function, when one is set.
+    ///
+    /// By default, the similarity replaces the name score. In the free-form
+    /// query syntax, this is written as, e.g., `{combine:multiply}` or
+    /// `{combine:weighted:0.7}`.
+    pub fn combine(mut self, combine: Combine) -> Query {
+        self.combine = combine;
+        self
+    }
+
+    /// Set the number of results to request from the name index, which are
+    /// then filtered and, if a similarity function is set, rescored before
+    /// being trimmed to the size of this query and normalized.
+    ///
+    /// A name search doesn't incorporate filters itself, so this is
+    /// typically much bigger than the size of the query, in case the filters
+    /// are aggressive. A smaller window makes searches with a similarity
+    /// function faster, since fewer results are rescored, at the risk of
+    /// missing results that the similarity function would rank highly. This
+    /// is never smaller than the size of the query.
+    ///
+    /// By default, this is `DEFAULT_RESCORE_WINDOW`, i.e., `1000`. In the
+    /// free-form query syntax, this is written as `{window:N}`.
+    pub fn rescore_window(mut self, window: usize) -> Query {
+        self.rescore_window = window;
+        self
+    }
+
     /// Add a title kind to filter by.
     ///
     /// Multiple title kinds can be added to query, and search results must
@@ -325,8 +1456,8 @@ impl Query {
     /// Note that it is not possible to remove title kinds from an existing
     /// query. Instead, build a new query from scratch.
     pub fn kind(mut self, kind: TitleKind) -> Query {
-        if !self.kinds.contains(&kind) {
-            self.kinds.push(kind);
+        if let Err(i) = self.kinds.binary_search(&kind) {
+            self.kinds.insert(i, kind);
         }
         self
     }
@@ -400,63 +1531,167 @@ impl Query {
         self
     }
 
-    /// Returns true if and only if the given entity matches this query.
+    /// Restrict results to TV series (including miniseries) that have ended,
+    /// or, when `false` is given, that are still airing.
+    ///
+    /// Whether a TV series has ended is determined by whether it has an end
+    /// year. Titles that aren't TV series never match this filter.
+    ///
+    /// In the free-form query syntax, this is written as `{ended}` or
+    /// `{airing}`.
+    pub fn ended(mut self, yes: bool) -> Query {
+        self.ended = Some(yes);
+        self
+    }
+
+    /// Restrict results to titles with at least one alternate name in the
+    /// given language, e.g., `ja` for Japanese. Languages are compared
+    /// case insensitively.
+    ///
+    /// Languages are given by the `language` column of IMDb's AKA data, which
+    /// are usually ISO 639-1 codes. Checking this filter requires reading
+    /// each candidate's AKA records, so it's checked after every other
+    /// filter.
+    ///
+    /// In the free-form query syntax, this is written as `{lang:ja}`.
+    pub fn language(mut self, language: &str) -> Query {
+        self.language = Some(language.to_lowercase());
+        self
+    }
+
+    /// Drop results that were only found because one of their alternate
+    /// names matched the query's name.
+    ///
+    /// The name index includes alternate names, some of which are marketing
+    /// retitles that reuse famous names. For example, a Russian video release
+    /// of Menno's Mind was called "The Matrix 2". When this is enabled, each
+    /// result of a search by name is compared with the query's name again,
+    /// and dropped if any of its alternate names is more similar to the query
+    /// than its primary and original names are. The comparison uses the
+    /// query's similarity function, or Jaro-Winkler if it has none.
+    ///
+    /// This has no effect on searches without a name, since those never
+    /// consider alternate names.
+    ///
+    /// In the free-form query syntax, this is written as `{original-only}`.
+    pub fn original_only(mut self, yes: bool) -> Query {
+        self.original_only = yes;
+        self
+    }
+
+    /// Lift any budget on exhaustive searches set on the searcher.
+    ///
+    /// When a query has no name to search by, every record in IMDb may need
+    /// to be scanned, which can take minutes. Searchers may limit such scans
+    /// (see `Searcher::max_scan_rows`), in which case this flag permits the
+    /// search to scan everything anyway.
+    ///
+    /// In the free-form query syntax, this is written as `{exhaustive}`.
+    pub fn exhaustive(mut self, yes: bool) -> Query {
+        self.exhaustive = yes;
+        self
+    }
+
+    /// Returns the first filter in this query that rejects the given entity,
+    /// or `None` if the entity matches this query.
     ///
     /// Note that this only applies filters in this query. e.g., The name
     /// aspect of the query, if one exists, is ignored.
-    fn matches(&self, ent: &MediaEntity) -> bool {
-        self.matches_title(&ent.title())
-            && self.matches_rating(ent.rating())
-            && self.matches_episode(ent.episode())
+    fn reject(&self, ent: &MediaEntity) -> Option<Filter> {
+        self.reject_title(ent.title())
+            .or_else(|| self.reject_rating(ent.rating()))
+            .or_else(|| self.reject_episode(ent.episode()))
     }
 
-    /// Returns true if and only if the given title matches this query.
+    /// Returns the first filter in this query that rejects the given title.
     ///
     /// This ignores non-title filters.
-    fn matches_title(&self, title: &Title) -> bool {
+    fn reject_title(&self, title: &Title) -> Option<Filter> {
         if !self.kinds.is_empty() && !self.kinds.contains(&title.kind) {
-            return false;
+            return Some(Filter::Kind);
         }
         if !self.year.contains(title.start_year.as_ref())
             && !self.year.contains(title.end_year.as_ref())
         {
-            return false;
+            return Some(Filter::Year);
         }
-        true
+        if let Some(ended) = self.ended {
+            if !title.kind.is_tv_series() || title.end_year.is_some() != ended
+            {
+                return Some(Filter::Ended);
+            }
+        }
+        None
     }
 
-    /// Returns true if and only if the given rating matches this query.
+    /// Returns the first filter in this query that rejects the given rating.
     ///
     /// This ignores non-rating filters.
     ///
     /// If a rating filter is present and `None` is given, then this always
-    /// returns `false`.
-    fn matches_rating(&self, rating: Option<&Rating>) -> bool {
+    /// rejects it.
+    fn reject_rating(&self, rating: Option<&Rating>) -> Option<Filter> {
         if !self.votes.contains(rating.map(|r| &r.votes)) {
-            return false;
+            return Some(Filter::Votes);
         }
-        true
+        None
     }
 
-    /// Returns true if and only if the given episode matches this query.
+    /// Returns the first filter in this query that rejects the given episode.
     ///
     /// This ignores non-episode filters.
     ///
     /// If an episode filter is present and `None` is given, then this always
-    /// returns `false`.
-    fn matches_episode(&self, ep: Option<&Episode>) -> bool {
-        if !self.season.contains(ep.and_then(|e| e.season.as_ref())) {
-            return false;
-        }
-        if !self.episode.contains(ep.and_then(|e| e.episode.as_ref())) {
-            return false;
+    /// rejects it.
+    fn reject_episode(&self, ep: Option<&Episode>) -> Option<Filter> {
+        if !self.season.contains(ep.and_then(|e| e.season.as_ref()))
+            || !self.episode.contains(ep.and_then(|e| e.episode.as_ref()))
+        {
+            return Some(Filter::Episode);
         }
         if let Some(ref tvshow_id) = self.tvshow_id {
-            if ep.map_or(true, |e| tvshow_id != &e.tvshow_id) {
-                return false;
+            if ep.is_none_or(|e| tvshow_id != &e.tvshow_id) {
+                return Some(Filter::Episode);
             }
         }
-        true
+        None
+    }
+
+    /// Return the filter given, as it appears in the free-form query syntax.
+    fn filter_syntax(&self, filter: Filter) -> String {
+        match filter {
+            Filter::Kind => self
+                .kinds
+                .iter()
+                .map(|kind| format!("{{{}}}", kind))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Filter::Year => format!("{{year:{}}}", self.year),
+            Filter::Ended => match self.ended {
+                None => String::new(),
+                Some(true) => "{ended}".to_string(),
+                Some(false) => "{airing}".to_string(),
+            },
+            Filter::Votes => format!("{{votes:{}}}", self.votes),
+            Filter::Episode => {
+                let mut parts = vec![];
+                if !self.season.is_none() {
+                    parts.push(format!("{{season:{}}}", self.season));
+                }
+                if !self.episode.is_none() {
+                    parts.push(format!("{{episode:{}}}", self.episode));
+                }
+                if let Some(ref tvshow_id) = self.tvshow_id {
+                    parts.push(format!("{{show:{}}}", tvshow_id));
+                }
+                parts.join(" ")
+            }
+            Filter::Language => match self.language {
+                None => String::new(),
+                Some(ref language) => format!("{{lang:{}}}", language),
+            },
+            Filter::Original => "{original-only}".to_string(),
+        }
     }
 
     /// Build a name query suitable for this query.
@@ -464,20 +1699,41 @@ impl Query {
     /// The name query returned may request many more results than the result
     /// size maximum on this query.
     fn name_query(&self) -> Option<NameQuery> {
+        // The year and kind filters are applied by the name index, so that
+        // titles from the wrong years or of the wrong kinds (usually
+        // episodes, which make up most of IMDb) don't take up room in the
+        // window of candidates. It only drops titles that our own filters
+        // would reject too.
+        let query = self
+            .name_query_unfiltered()?
+            .with_years(self.year.start, self.year.end);
+        // Only TV series can have ended (or still be airing), so that
+        // filter implies a kind filter when there isn't one.
+        let kinds: &[TitleKind] =
+            if self.kinds.is_empty() && self.ended.is_some() {
+                &[TitleKind::TVMiniSeries, TitleKind::TVSeries]
+            } else {
+                &self.kinds
+            };
+        Some(query.with_kinds(kinds))
+    }
+
+    /// Build a name query like `name_query`, except that the name index
+    /// doesn't apply any of this query's filters.
+    fn name_query_unfiltered(&self) -> Option<NameQuery> {
         let name = match self.name.as_ref() {
             None => return None,
             Some(name) => &**name,
         };
-        let scorer = match self.name_scorer {
-            None => return None,
-            Some(scorer) => scorer,
-        };
+        let scorer = self.name_scorer?;
         // We want our name query to return a healthy set of results, even if
         // it's well beyond the result set size requested by the user. This is
-        // primarily because a name search doesn't incorporate filters itself,
-        // which simplifies the implementation. Therefore, we need to request
-        // more results than what we need in case our filter is aggressive.
-        let size = cmp::max(1000, self.size);
+        // primarily because a name search only incorporates the year and kind
+        // filters, which simplifies the implementation. Therefore, we need to
+        // request more results than what we need in case our filter is
+        // aggressive. This is also the window of results that get rescored
+        // by a similarity function.
+        let size = cmp::max(self.rescore_window, self.size);
         Some(NameQuery::new(name).with_size(size).with_scorer(scorer))
     }
 
@@ -492,6 +1748,8 @@ impl Query {
             || self.needs_episode()
             || !self.kinds.is_empty()
             || !self.year.is_none()
+            || self.ended.is_some()
+            || self.language.is_some()
     }
 
     /// Returns true if and only this query has only title filters.
@@ -499,7 +1757,9 @@ impl Query {
     /// When true, this can make exhaustive searches faster by avoiding the
     /// need to fetch the rating and/or episode for every title in IMDb.
     fn needs_only_title(&self) -> bool {
-        !self.needs_rating() && !self.needs_episode()
+        !self.needs_rating()
+            && !self.needs_episode()
+            && self.language.is_none()
     }
 
     /// Returns true if and only if this query has a rating filter.
@@ -511,7 +1771,7 @@ impl Query {
     fn needs_episode(&self) -> bool {
         !self.season.is_none()
             || !self.episode.is_none()
-            || !self.tvshow_id.is_none()
+            || self.tvshow_id.is_some()
     }
 }
 
@@ -551,9 +1811,14 @@ impl FromStr for Query {
                 r"\{(?P<directive>[^}]+)\}|(?P<terms>[^{}\s]+)|(?P<space>\s+)"
             ).unwrap();
 
-            // Parse a directive of the form '{name:val}' or '{kind}'.
+            // Parse a directive of the form '{name:val}' or '{kind}'. The
+            // directive is trimmed before matching. A directive with an
+            // empty value is treated as a kind, which is then rejected.
+            //
+            // Directives may contain any character other than '}', including
+            // new lines, so '.' must match everything.
             static ref DIRECTIVE: Regex = Regex::new(
-                r"^(?:(?P<name>[^:]+):(?P<val>.+)|(?P<kind>.+))$"
+                r"(?s)^(?:(?P<name>[^:]+):\s*(?P<val>\S.*)|(?P<kind>.+))$"
             ).unwrap();
         }
         let mut terms = vec![];
@@ -566,9 +1831,25 @@ impl FromStr for Query {
                 continue;
             }
 
-            let dcaps = DIRECTIVE.captures(&caps["directive"]).unwrap();
+            let directive = caps["directive"].trim();
+            let dcaps = match DIRECTIVE.captures(directive) {
+                Some(dcaps) => dcaps,
+                // Only a directive consisting entirely of whitespace fails
+                // to match.
+                None => return Err(Error::unknown_directive(directive)),
+            };
             if let Some(m) = dcaps.name("kind") {
-                q = q.kind(m.as_str().parse()?);
+                if m.as_str().trim() == "exhaustive" {
+                    q.exhaustive = true;
+                } else if m.as_str().trim() == "original-only" {
+                    q.original_only = true;
+                } else if m.as_str().trim() == "ended" {
+                    q.ended = Some(true);
+                } else if m.as_str().trim() == "airing" {
+                    q.ended = Some(false);
+                } else {
+                    q = q.kind(m.as_str().parse()?);
+                }
                 continue;
             }
 
@@ -577,6 +1858,12 @@ impl FromStr for Query {
                 "size" => {
                     q.size = val.parse().map_err(Error::number)?;
                 }
+                "combine" => {
+                    q.combine = val.parse()?;
+                }
+                "window" => {
+                    q.rescore_window = val.parse().map_err(Error::number)?;
+                }
                 "year" => {
                     q.year = val.parse()?;
                 }
@@ -592,6 +1879,9 @@ impl FromStr for Query {
                 "tvseries" | "tvshow" | "show" => {
                     q.tvshow_id = Some(val.to_string());
                 }
+                "lang" | "language" => {
+                    q = q.language(val);
+                }
                 "sim" | "similarity" => {
                     q.similarity = val.parse()?;
                 }
@@ -605,8 +1895,11 @@ impl FromStr for Query {
                 unk => return Err(Error::unknown_directive(unk)),
             }
         }
-        if !terms.is_empty() {
-            q = q.name(&terms.join(" "));
+        for name in terms.join(" ").split('|') {
+            let name = name.trim();
+            if !name.is_empty() {
+                q = q.name_or(name);
+            }
         }
         Ok(q)
     }
@@ -620,15 +1913,24 @@ impl fmt::Display for Query {
         }
         write!(f, " {{sim:{}}}", self.similarity)?;
         write!(f, " {{size:{}}}", self.size)?;
+        if self.combine != Combine::Replace {
+            write!(f, " {{combine:{}}}", self.combine)?;
+        }
+        if self.rescore_window != DEFAULT_RESCORE_WINDOW {
+            write!(f, " {{window:{}}}", self.rescore_window)?;
+        }
 
-        let mut kinds: Vec<&TitleKind> = self.kinds.iter().collect();
-        kinds.sort();
-        for kind in kinds {
+        for kind in &self.kinds {
             write!(f, " {{{}}}", kind)?;
         }
         if !self.year.is_none() {
             write!(f, " {{year:{}}}", self.year)?;
         }
+        match self.ended {
+            None => {}
+            Some(true) => f.write_str(" {ended}")?,
+            Some(false) => f.write_str(" {airing}")?,
+        }
         if !self.votes.is_none() {
             write!(f, " {{votes:{}}}", self.votes)?;
         }
@@ -641,7 +1943,19 @@ impl fmt::Display for Query {
         if let Some(ref tvshow_id) = self.tvshow_id {
             write!(f, " {{show:{}}}", tvshow_id)?;
         }
-        if let Some(ref name) = self.name {
+        if let Some(ref language) = self.language {
+            write!(f, " {{lang:{}}}", language)?;
+        }
+        if self.original_only {
+            f.write_str(" {original-only}")?;
+        }
+        if self.exhaustive {
+            f.write_str(" {exhaustive}")?;
+        }
+        for (i, name) in self.names().enumerate() {
+            if i > 0 {
+                f.write_str(" |")?;
+            }
             write!(f, " {}", name)?;
         }
         Ok(())
@@ -661,9 +1975,10 @@ impl fmt::Display for Query {
 /// the IMDb name index will be rescored according to this function. If no
 /// similarity function is provided, then the results will be ranked according
 /// to scores produced by the name index.
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Default)]
 pub enum Similarity {
     /// Do not use a similarity function.
+    #[default]
     None,
     /// Computes the Levenshtein edit distance between two names and converts
     /// it to a similarity.
@@ -720,12 +2035,6 @@ impl Similarity {
     }
 }
 
-impl Default for Similarity {
-    fn default() -> Similarity {
-        Similarity::None
-    }
-}
-
 impl fmt::Display for Similarity {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -751,6 +2060,129 @@ impl FromStr for Similarity {
     }
 }
 
+/// How the score from the name index is combined with the score from a
+/// similarity function.
+///
+/// Scores from the name index are normalized to `[0, 1]` before being
+/// combined, such that the top result from the name index has a score of
+/// `1.0`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Combine {
+    /// The similarity replaces the name score. This is the default.
+    #[default]
+    Replace,
+    /// The product of the name score and the similarity.
+    Multiply,
+    /// A weighted sum of the similarity and the name score, where the given
+    /// weight applies to the similarity and the rest applies to the name
+    /// score. A weight of `1.0` is equivalent to `Replace`.
+    Weighted(Weight),
+}
+
+impl Combine {
+    /// Returns a list of strings representing the possible names of ways to
+    /// combine scores. `weighted` requires a weight, e.g., `weighted:0.7`.
+    pub fn possible_names() -> &'static [&'static str] {
+        &["replace", "multiply", "weighted"]
+    }
+
+    /// A weighted sum with the given weight. See `Weight::new`.
+    pub fn weighted(weight: f64) -> Result<Combine> {
+        Weight::new(weight).map(Combine::Weighted)
+    }
+
+    /// Combine the given (normalized) name score with the given similarity.
+    pub fn combine(&self, name_score: f64, sim: f64) -> f64 {
+        match *self {
+            Combine::Replace => sim,
+            Combine::Multiply => name_score * sim,
+            Combine::Weighted(w) => {
+                let w = w.get();
+                w * sim + (1.0 - w) * name_score
+            }
+        }
+    }
+}
+
+/// The weight of a similarity in a weighted sum with a name score. See
+/// `Combine::Weighted`.
+///
+/// A weight is always in `[0, 1]`, which in particular means that it's never
+/// NaN.
+#[derive(Clone, Copy, Debug)]
+pub struct Weight(f64);
+
+impl Weight {
+    /// Create a weight from the given value, which must be in `[0, 1]`.
+    ///
+    /// Any other value, including infinities and NaN, returns an error.
+    pub fn new(weight: f64) -> Result<Weight> {
+        if !(0.0..=1.0).contains(&weight) {
+            return Err(Error::unknown_combine(format!(
+                "weighted:{}",
+                weight
+            )));
+        }
+        // Normalize -0.0, so that equal weights have equal bits.
+        Ok(Weight(weight + 0.0))
+    }
+
+    /// Return this weight as a float.
+    pub fn get(&self) -> f64 {
+        self.0
+    }
+}
+
+// Weights are never NaN (see `Weight::new`), so comparing their bits is a
+// fine notion of equality, which lets queries containing them be hashed.
+impl PartialEq for Weight {
+    fn eq(&self, other: &Weight) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Weight {}
+
+impl Hash for Weight {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl fmt::Display for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for Combine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Combine::Replace => write!(f, "replace"),
+            Combine::Multiply => write!(f, "multiply"),
+            Combine::Weighted(w) => write!(f, "weighted:{}", w),
+        }
+    }
+}
+
+impl FromStr for Combine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Combine> {
+        match s {
+            "replace" => return Ok(Combine::Replace),
+            "multiply" => return Ok(Combine::Multiply),
+            _ => {}
+        }
+        let weight = match s.strip_prefix("weighted:") {
+            None => return Err(Error::unknown_combine(s)),
+            Some(weight) => weight,
+        };
+        let weight: f64 = weight.parse().map_err(Error::number)?;
+        Combine::weighted(weight).map_err(|_| Error::unknown_combine(s))
+    }
+}
+
 /// A range filter over any partially ordered type `T`.
 ///
 /// This type permits either end of the range to be unbounded.
@@ -778,9 +2210,9 @@ impl<T: PartialOrd> Range<T> {
         };
         match (&self.start, &self.end) {
             (&None, &None) => true,
-            (&Some(ref s), &None) => s <= t,
-            (&None, &Some(ref e)) => t <= e,
-            (&Some(ref s), &Some(ref e)) => s <= t && t <= e,
+            (Some(s), &None) => s <= t,
+            (&None, Some(e)) => t <= e,
+            (Some(s), Some(e)) => s <= t && t <= e,
         }
     }
 }
@@ -789,10 +2221,10 @@ impl<T: fmt::Display + PartialEq> fmt::Display for Range<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match (&self.start, &self.end) {
             (&None, &None) => write!(f, "-"),
-            (&Some(ref s), &None) => write!(f, "{}-", s),
-            (&None, &Some(ref e)) => write!(f, "-{}", e),
-            (&Some(ref s), &Some(ref e)) if s == e => write!(f, "{}", s),
-            (&Some(ref s), &Some(ref e)) => write!(f, "{}-{}", s, e),
+            (Some(s), &None) => write!(f, "{}-", s),
+            (&None, Some(e)) => write!(f, "-{}", e),
+            (Some(s), Some(e)) if s == e => write!(f, "{}", s),
+            (Some(s), Some(e)) => write!(f, "{}-{}", s, e),
         }
     }
 }
@@ -837,9 +2269,1197 @@ impl<E: std::error::Error + Send + Sync + 'static, T: FromStr<Err = E>> FromStr
     }
 }
 
+/// A name ending with a number, e.g., `John Wick 3` or `Rocky IV`.
+#[derive(Debug, PartialEq)]
+struct Sequel {
+    /// The lowercase words preceding the number.
+    prefix: Vec<String>,
+    /// The number at the end of the name.
+    number: u32,
+}
+
+impl Sequel {
+    /// Roman numerals recognized at the end of a name, starting with `2`.
+    /// `I` is left out, since it's far more likely to be a word.
+    const ROMAN: &'static [&'static str] = &[
+        "ii", "iii", "iv", "v", "vi", "vii", "viii", "ix", "x", "xi", "xii",
+        "xiii", "xiv", "xv", "xvi", "xvii", "xviii", "xix", "xx",
+    ];
+
+    /// Parse a name ending with a number. If the name doesn't end with a
+    /// number or consists of only a number, then `None` is returned.
+    fn parse(name: &str) -> Option<Sequel> {
+        let mut words = Sequel::words(name);
+        let last = words.pop()?;
+        if words.is_empty() {
+            return None;
+        }
+        let number = match last.parse::<u32>() {
+            Ok(number) => number,
+            Err(_) => {
+                let i = Sequel::ROMAN.iter().position(|&r| r == last)?;
+                i as u32 + 2
+            }
+        };
+        Some(Sequel { prefix: words, number })
+    }
+
+    /// Returns true if the given title starts with this sequel's prefix.
+    fn is_related(&self, title: &str) -> bool {
+        Sequel::words(title).starts_with(&self.prefix)
+    }
+
+    /// Returns true if the given title starts with this sequel's prefix and
+    /// contains its number.
+    fn is_numbered(&self, title: &str) -> bool {
+        let words = Sequel::words(title);
+        if !words.starts_with(&self.prefix) {
+            return false;
+        }
+        let arabic = self.number.to_string();
+        let roman = self
+            .number
+            .checked_sub(2)
+            .and_then(|i| Sequel::ROMAN.get(i as usize))
+            .copied();
+        words[self.prefix.len()..]
+            .iter()
+            .any(|w| *w == arabic || Some(w.as_str()) == roman)
+    }
+
+    /// Split a name into lowercase words, ignoring punctuation.
+    fn words(name: &str) -> Vec<String> {
+        name.split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_lowercase())
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
     use super::*;
+    use crate::index::tests::TestContext;
+    use crate::index::IndexBuilder;
+
+    #[test]
+    fn original_title_similarity() {
+        let ctx = TestContext::new("small");
+        let searcher = Searcher::new(ctx.create_index());
+        let title = Title {
+            id: "tt0211915".to_string(),
+            kind: TitleKind::Movie,
+            title: "Amélie".to_string(),
+            original_title: "Le fabuleux destin d'Amélie Poulain".to_string(),
+            is_adult: false,
+            start_year: Some(2001),
+            end_year: None,
+            runtime_minutes: Some(122),
+            genres: "Comedy,Romance".to_string(),
+        };
+        let query = Query::new()
+            .name("Le fabuleux destin d'Amélie Poulain")
+            .similarity(Similarity::Levenshtein);
+        assert_eq!(searcher.title_similarity(&query, &title), 1.0);
+        let query = query.name("Amélie");
+        assert_eq!(searcher.title_similarity(&query, &title), 1.0);
+        let query = query.name("Amelie");
+        assert_eq!(searcher.title_similarity(&query, &title), 0.5);
+    }
+
+    /// The snapshot of search results on the small data set, relative to the
+    /// root of this crate.
+    const SNAPSHOT_SMALL: &str = "../data/snapshots/search-small.txt";
+
+    /// The queries whose results on the small data set are recorded in
+    /// `SNAPSHOT_SMALL`. Adding a query here requires updating the snapshot.
+    const SNAPSHOT_QUERIES: &[&str] = &[
+        "homer",
+        "{scorer:tfidf} homer",
+        "{scorer:jaccard} homer",
+        "{scorer:queryratio} homer",
+        "{sim:levenshtein} bart the",
+        "{sim:jaro} bart the",
+        "{sim:jarowinkler} bart the",
+        "{sim:levenshtein} {combine:weighted:0.5} homers odyssey",
+        "{sim:jaro} {combine:multiply} lisa",
+        "{tvEpisode} {season:2} homer",
+        "{year:1990} bart",
+        "{size:3} treehouse of horror",
+        "{scorer:none} {show:tt0096697} {season:1} {episode:1-3}",
+        "krusty busted",
+        "simpsons",
+        "{movie} dune",
+        "{year:2021} dune",
+        "face off",
+        "amélie",
+        "amelie",
+        "homer alone",
+        "{sim:jaro} night of the day of the dawn",
+        "{year:1960-1999} {movie}",
+        "{tvEpisode} {season:0}",
+    ];
+
+    /// Render the results of every query in `SNAPSHOT_QUERIES` as text.
+    ///
+    /// Scores are rounded to three decimal places, and results with equal
+    /// rounded scores are sorted by IMDb ID, so that the snapshot doesn't
+    /// depend on floating point noise.
+    fn render_snapshot(searcher: &mut Searcher) -> String {
+        let mut out = String::new();
+        for qstr in SNAPSHOT_QUERIES {
+            let query: Query = qstr.parse().unwrap();
+            let results = searcher.search(&query).unwrap();
+            let mut rows: Vec<(String, &str, &str)> = results
+                .as_slice()
+                .iter()
+                .map(|r| {
+                    let t = r.value().title();
+                    (format!("{:.3}", r.score()), &*t.id, &*t.title)
+                })
+                .collect();
+            rows.sort_by(|r1, r2| r2.0.cmp(&r1.0).then(r1.1.cmp(r2.1)));
+
+            out.push_str(&format!("query: {}\n", qstr));
+            for (score, id, title) in rows {
+                out.push_str(&format!("  {} {} {}\n", score, id, title));
+            }
+        }
+        out
+    }
+
+    /// Checks that search results on the small data set match the snapshot.
+    ///
+    /// When a change to ranking is deliberate, regenerate the snapshot with
+    /// `IMDB_UPDATE_SNAPSHOTS=1 cargo test -p imdb-index snapshot` and review
+    /// the diff of the snapshot file before committing it.
+    #[test]
+    fn snapshot_small() {
+        let ctx = TestContext::new("small");
+        let idx = ctx.create_index();
+        let got = render_snapshot(&mut Searcher::new(idx));
+        if std::env::var_os("IMDB_UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(SNAPSHOT_SMALL, &got).unwrap();
+            return;
+        }
+
+        let want = std::fs::read_to_string(SNAPSHOT_SMALL).unwrap();
+        let mismatch = got.lines().zip(want.lines()).position(|(g, w)| g != w);
+        if let Some(i) = mismatch {
+            panic!(
+                "search results differ from {} at line {}:\n  \
+                 got:  {}\n  want: {}\n\
+                 If this change is deliberate, rerun with \
+                 IMDB_UPDATE_SNAPSHOTS=1 to update the snapshot.",
+                SNAPSHOT_SMALL,
+                i + 1,
+                got.lines().nth(i).unwrap(),
+                want.lines().nth(i).unwrap(),
+            );
+        }
+        assert_eq!(
+            got.lines().count(),
+            want.lines().count(),
+            "search results differ in length from {}; if this change is \
+             deliberate, rerun with IMDB_UPDATE_SNAPSHOTS=1",
+            SNAPSHOT_SMALL,
+        );
+    }
+
+    /// Tests searches for titles in the small data set that are known to
+    /// cause trouble.
+    #[test]
+    fn fixture_hazards() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let mut ids = |qstr: &str| -> Vec<String> {
+            let query: Query = qstr.parse().unwrap();
+            let results = searcher.search(&query).unwrap();
+            results.into_iter().map(|r| r.value().title().id.clone()).collect()
+        };
+
+        // Titles that differ only by year.
+        let dunes = ids("{movie} dune");
+        assert!(dunes.contains(&"tt0087182".to_string()));
+        assert!(dunes.contains(&"tt1160419".to_string()));
+        assert_eq!(ids("{movie} {year:1984} dune"), vec!["tt0087182"]);
+        assert_eq!(ids("{movie} {year:2021} dune"), vec!["tt1160419"]);
+
+        // Titles containing a path separator.
+        assert_eq!(ids("{movie} face off")[0], "tt0119094");
+        assert_eq!(ids("{movie} fahrenheit 9/11")[0], "tt0361596");
+
+        // Diacritics. Accents aren't folded, so a query without them relies
+        // on an alternate name without them.
+        assert_eq!(ids("amélie")[0], "tt0211915");
+        assert_eq!(ids("amelie")[0], "tt0211915");
+        assert_eq!(ids("le fabuleux destin")[0], "tt0211915");
+
+        // Adult titles are excluded from searches by name and exhaustive
+        // searches alike.
+        let adult = "tt9900002".to_string();
+        assert!(!ids("homer alone").contains(&adult));
+        assert!(!ids("{year:1992}").contains(&adult));
+        assert!(!ids("{year:1992} {movie}").contains(&adult));
+        assert!(!ids("{year:1992} {votes:1-}").contains(&adult));
+    }
+
+    /// Search with diagnostics, checking that every candidate is either a
+    /// result or dropped for a reason.
+    fn search_checked(
+        searcher: &mut Searcher,
+        query: &Query,
+    ) -> (SearchResults<MediaEntity>, SearchDiagnostics) {
+        let (results, diag) = searcher.search_diagnostics(query).unwrap();
+        assert_eq!(
+            diag.candidates,
+            results.len() + diag.dropped_by_filters() + diag.dropped_size,
+            "{}: {}",
+            query,
+            diag
+        );
+        (results, diag)
+    }
+
+    #[test]
+    fn diagnostics() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let mut check = |query: Query| {
+            let (results, diag) = search_checked(&mut searcher, &query);
+            assert_eq!(
+                searcher.search(&query).unwrap().len(),
+                results.len(),
+                "{}",
+                query
+            );
+            assert_eq!(
+                diag.lowest_score,
+                results.as_slice().last().map(|r| r.score())
+            );
+            diag
+        };
+
+        let all = check(Query::new().name("bart"));
+        assert!(all.candidates > 1);
+        assert_eq!(all.dropped_size, 0);
+
+        let diag = check(Query::new().name("bart").size(1));
+        assert_eq!(diag.dropped_size, diag.candidates - 1);
+
+        let diag = check(Query::new().name("bart").kind(TitleKind::VideoGame));
+        assert_eq!(diag.dropped_kind, diag.candidates);
+        assert_eq!(diag.lowest_score, None);
+
+        let diag = check(Query::new().name("bart").votes_ge(1));
+        assert!(diag.dropped_votes > 0);
+
+        // Titles from the wrong years are dropped by the name index, so they
+        // never become candidates.
+        let diag = check(Query::new().name("bart").year_ge(1991));
+        assert!(diag.candidates < all.candidates);
+        assert_eq!(diag.dropped_year, 0);
+
+        let diag = check(Query::new().year_ge(1991));
+        assert!(diag.dropped_year > 0);
+
+        let diag = check(
+            Query::new()
+                .name("bart")
+                .similarity(Similarity::Levenshtein)
+                .season_ge(2),
+        );
+        assert!(diag.dropped_episode > 0);
+
+        assert_eq!(
+            check(Query::new()),
+            SearchDiagnostics::default(),
+            "empty queries consider nothing"
+        );
+    }
+
+    #[test]
+    fn aka_counts() {
+        let ctx = TestContext::new("small");
+        let mut idx = ctx.create_index();
+        for id in ["tt0096697", "tt0117021", "tt0211915", "tt0087182"] {
+            let count = idx.aka_records(id).unwrap().count() as u64;
+            assert_eq!(idx.aka_count(id).unwrap(), count, "{}", id);
+            assert_eq!(idx.has_akas(id).unwrap(), count > 0, "{}", id);
+        }
+        assert_eq!(idx.aka_count("tt0117021").unwrap(), 4);
+        assert!(!idx.has_akas("tt0087182").unwrap());
+        assert_eq!(idx.aka_count("tt9999999").unwrap(), 0);
+    }
+
+    #[test]
+    fn one_shot() {
+        let ctx = TestContext::new("small");
+        let idx = ctx.create_index();
+        let results =
+            search_once(ctx.data_dir(), ctx.index_dir(), "the simpsons", 3)
+                .unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results.as_slice()[0].value().title().id, "tt0096697");
+
+        // The size given replaces the size in the query.
+        let mut searcher = Searcher::new(idx);
+        let results =
+            search_once_with(&mut searcher, "{size:1} the simpsons", 2)
+                .unwrap();
+        assert_eq!(results.len(), 2);
+        let ent = best_match_with(&mut searcher, "the matrix {movie}")
+            .unwrap()
+            .unwrap();
+        assert_eq!(ent.title().id, "tt0133093");
+        assert!(best_match_with(&mut searcher, "{year:nope}").is_err());
+    }
+
+    #[test]
+    fn orphaned_episodes() {
+        let ctx = TestContext::new("small");
+        let mut idx = ctx.create_index();
+        // tt9900004 is an episode of tt9900005, which has no title record.
+        let orphan = idx.entity("tt9900004").unwrap().unwrap();
+        assert_eq!(orphan.episode().unwrap().tvshow_id, "tt9900005");
+        assert!(idx.title("tt9900005").unwrap().is_none());
+        assert_eq!(idx.verify().unwrap().orphaned_episodes, 1);
+
+        let mut searcher = Searcher::new(idx);
+        let results =
+            searcher.search(&Query::new().tvshow_id("tt9900005")).unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn tvshow_seasons() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let all = Query::new().tvshow_id("tt0096697");
+        let (_, diag) = search_checked(&mut searcher, &all);
+        assert_eq!(diag.candidates, 60);
+
+        // Episodes after the last season aren't looked up, but they're still
+        // counted as candidates dropped by the season filter.
+        let first = all.clone().season_ge(1).season_le(1);
+        let (results, diag) = search_checked(&mut searcher, &first);
+        assert!(!results.is_empty());
+        assert!(results.as_slice().iter().all(|r| r
+            .value()
+            .episode()
+            .unwrap()
+            .season
+            == Some(1)));
+        assert_eq!(diag.candidates, 60);
+        assert_eq!(diag.dropped_episode, 60 - results.len());
+    }
+
+    #[test]
+    fn aka_records_interleaved() {
+        let ctx = TestContext::new("small");
+        let mut idx = ctx.create_index();
+        let expected: Vec<String> = idx
+            .aka_records("tt0096697")
+            .unwrap()
+            .map(|r| r.unwrap().title)
+            .collect();
+        let votes = idx.rating("tt0348034").unwrap().unwrap().votes;
+
+        let mut got = vec![];
+        for result in idx.aka_records("tt0096697").unwrap() {
+            got.push(result.unwrap().title);
+            let rating = idx.rating("tt0348034").unwrap().unwrap();
+            assert_eq!(rating.votes, votes);
+            assert_eq!(idx.aka_records("tt0117021").unwrap().count(), 4);
+        }
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn lazy_open() {
+        use std::fs;
+
+        let ctx = TestContext::new("small");
+        ctx.create_index();
+        let postings = ctx.index_dir().join("names.postings.idx");
+        let saved = fs::read(&postings).unwrap();
+
+        // Missing files are still reported when the index is opened.
+        fs::remove_file(&postings).unwrap();
+        let err = Index::open(ctx.data_dir(), ctx.index_dir()).unwrap_err();
+        assert!(err.to_string().contains("names.postings.idx"), "{}", err);
+
+        // But the name index isn't read until it's needed, so lookups by ID
+        // work without it.
+        fs::write(&postings, &saved).unwrap();
+        let mut idx = Index::open(ctx.data_dir(), ctx.index_dir()).unwrap();
+        fs::remove_file(&postings).unwrap();
+        let dune = idx.entity("tt0087182").unwrap().unwrap();
+        assert_eq!(dune.title().title, "Dune");
+        assert!(idx.aka_records("tt0096697").unwrap().count() > 0);
+        assert!(idx.has_akas("tt0096697").unwrap());
+        let mut searcher = Searcher::new(idx);
+        assert!(searcher.search(&Query::new().name("dune")).is_err());
+    }
+
+    #[test]
+    fn custom_scorer() {
+        let ids = |searcher: &mut Searcher, query: &Query| -> Vec<String> {
+            let results = searcher.search(query).unwrap().into_vec();
+            results.into_iter().map(|r| r.value().title().id.clone()).collect()
+        };
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+
+        let query = Query::new().name("homer");
+        let ratio = query.clone().name_scorer(Some(NameScorer::QueryRatio));
+        let want = ids(&mut searcher, &ratio);
+        assert_ne!(want, ids(&mut searcher, &query));
+
+        searcher.custom_scorer(Some(Arc::new(NameScorer::QueryRatio)));
+        assert_eq!(want, ids(&mut searcher, &query));
+        // Queries without a name scorer still don't use the name index.
+        let none = query.clone().name_scorer(None);
+        searcher.custom_scorer(None);
+        let want = ids(&mut searcher, &none);
+        searcher.custom_scorer(Some(Arc::new(NameScorer::QueryRatio)));
+        assert_eq!(want, ids(&mut searcher, &none));
+    }
+
+    #[test]
+    fn interrupted_create() {
+        use std::fs;
+
+        let ctx = TestContext::new("small");
+        ctx.create_index();
+        let index_dir = ctx.index_dir();
+        assert!(!index_dir.join(".creating").exists());
+
+        // Simulate a rebuild that was killed after the name index was
+        // written: the old config is gone and the new files are still in the
+        // staging directory.
+        fs::remove_file(index_dir.join("config.json")).unwrap();
+        let staging = index_dir.join(".creating");
+        fs::create_dir(&staging).unwrap();
+        for entry in fs::read_dir(index_dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            if name.starts_with("names.") {
+                fs::copy(&path, staging.join(name)).unwrap();
+            }
+        }
+        let err = Index::open(ctx.data_dir(), index_dir).unwrap_err();
+        assert!(err.to_string().contains("config.json"), "{}", err);
+        assert!(!Index::exists(index_dir));
+
+        // Creating the index again cleans up after the interrupted attempt.
+        ctx.create_index();
+        assert!(Index::exists(index_dir));
+        assert!(!staging.exists());
+        Index::open(ctx.data_dir(), index_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_index() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::error::ErrorKind;
+
+        let ctx = TestContext::new("small");
+        ctx.create_index();
+        let set_mode = |mode: u32| {
+            let perms = fs::Permissions::from_mode(mode);
+            fs::set_permissions(ctx.index_dir(), perms).unwrap();
+        };
+
+        set_mode(0o555);
+        let opened = Index::open(ctx.data_dir(), ctx.index_dir());
+        let results = opened.map(|idx| {
+            let mut searcher = Searcher::new(idx);
+            searcher.search(&Query::new().name("dune")).map(|r| r.len())
+        });
+        let created = Index::create(ctx.data_dir(), ctx.index_dir());
+        // Permissions don't stop root (or anyone else with CAP_DAC_OVERRIDE)
+        // from writing, so only expect an error if writing actually fails.
+        let writable = fs::write(ctx.index_dir().join("probe"), "").is_ok();
+        set_mode(0o755);
+
+        assert_eq!(results.unwrap().unwrap(), 2);
+        if !writable {
+            match created.map(|_| ()).unwrap_err().into_kind() {
+                ErrorKind::ReadOnlyIndex(dir) => {
+                    assert_eq!(dir, ctx.index_dir())
+                }
+                kind => panic!("unexpected error: {:?}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn stats() {
+        use std::fs;
+        use std::time::Duration;
+
+        let ctx = TestContext::new("small");
+        let idx = IndexBuilder::new()
+            .ngram_size(4)
+            .create(ctx.data_dir(), ctx.index_dir())
+            .unwrap();
+        let stats = idx.stats().unwrap();
+        assert_eq!(stats.ngram_size, 4);
+        assert!(stats.names > 0);
+
+        // The snapshot is the oldest modification time of the data files,
+        // truncated to seconds.
+        let oldest = fs::read_dir(ctx.data_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap())
+            .map(|md| md.modified().unwrap())
+            .min()
+            .unwrap();
+        let snapshot = idx.data_snapshot().unwrap();
+        assert!(snapshot.time() <= oldest);
+        assert!(oldest - Duration::from_secs(1) < snapshot.time());
+        assert_eq!(stats.data_snapshot, Some(snapshot));
+        assert!(Regex::new(r"^[0-9]{4}-[0-9]{2}-[0-9]{2}$")
+            .unwrap()
+            .is_match(&snapshot.to_string()));
+
+        // Indexes created before snapshots were recorded have none.
+        let config = ctx.index_dir().join("config.json");
+        fs::write(&config, r#"{"version":6}"#).unwrap();
+        let idx = Index::open(ctx.data_dir(), ctx.index_dir()).unwrap();
+        assert_eq!(idx.data_snapshot(), None);
+    }
+
+    #[test]
+    fn similar() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let mut ids = |id: &str, size: usize| -> Vec<String> {
+            let results = searcher.similar(id, size).unwrap();
+            if let Some(top) = results.as_slice().first() {
+                assert_eq!(top.score(), 1.0);
+            }
+            results
+                .into_vec()
+                .into_iter()
+                .map(|r| r.into_value().title().id.clone())
+                .collect()
+        };
+
+        // The other Dune, and never the title itself.
+        assert_eq!(ids("tt0087182", 1), vec!["tt1160419"]);
+        assert_eq!(ids("tt1160419", 1), vec!["tt0087182"]);
+        assert!(!ids("tt0087182", 100).contains(&"tt0087182".to_string()));
+        // The Simpsons finds episodes with "Simpsons" in their names.
+        assert_eq!(
+            ids("tt0096697", 4),
+            vec!["tt0766140", "tt0701228", "tt9900001", "tt0348034"]
+        );
+        assert_eq!(ids("tt0000000", 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn language_filter() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let mut ids = |query: &str| -> Vec<String> {
+            let (results, _) =
+                search_checked(&mut searcher, &query.parse().unwrap());
+            results
+                .into_vec()
+                .into_iter()
+                .map(|r| r.into_value().title().id.clone())
+                .collect()
+        };
+
+        // Many titles match "simpsons", but only the show itself has AKAs
+        // with languages.
+        assert!(ids("simpsons").len() > 1);
+        assert_eq!(ids("simpsons {lang:fr}"), vec!["tt0096697"]);
+        assert_eq!(ids("simpsons {lang:BG}"), vec!["tt0096697"]);
+        assert_eq!(ids("simpsons {lang:ja}"), Vec::<String>::new());
+        // AKAs without a language never match.
+        assert_eq!(ids("amelie {lang:fr}"), Vec::<String>::new());
+        // Without a name, every title is scanned.
+        assert_eq!(ids("{tvSeries} {lang:tr}"), vec!["tt0096697"]);
+        assert_eq!(ids("{lang:tr} {exhaustive}"), vec!["tt0096697"]);
+        assert_eq!(ids("{show:tt0096697} {lang:tr}"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn original_only() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let mut ids = |query: Query| -> (Vec<String>, SearchDiagnostics) {
+            let (results, diag) = search_checked(&mut searcher, &query);
+            let ids = results
+                .into_vec()
+                .into_iter()
+                .map(|r| r.into_value().title().id.clone())
+                .collect();
+            (ids, diag)
+        };
+
+        // Menno's Mind is only found by its alternate name.
+        let query = Query::new().name("matrix");
+        let (all, diag) = ids(query.clone());
+        assert_eq!(all, vec!["tt0133093", "tt0117021"]);
+        assert_eq!(diag.dropped_original, 0);
+        let (original, diag) = ids(query.clone().original_only(true));
+        assert_eq!(original, vec!["tt0133093"]);
+        assert_eq!(diag.dropped_original, 1);
+        let query = query.similarity(Similarity::Levenshtein);
+        let (original, _) = ids(query.original_only(true));
+        assert_eq!(original, vec!["tt0133093"]);
+        let query = Query::new().name("the matrix 2");
+        assert_eq!(ids(query.clone()).0, vec!["tt0117021"]);
+        assert_eq!(ids(query.original_only(true)).0, Vec::<String>::new());
+
+        // Titles that also have alternate names are kept when the query
+        // matches their primary name.
+        let (original, _) = ids(Query::new().name("face off").size(1));
+        assert_eq!(original, vec!["tt0119094"]);
+        let query = Query::new().name("face off").size(1).original_only(true);
+        assert_eq!(ids(query).0, vec!["tt0119094"]);
+
+        // Names are compared without their diacritics, so a query written
+        // without them still matches the primary name, even though an
+        // alternate name spells it the same way as the query.
+        let query = Query::new().name("amelie").size(1).original_only(true);
+        assert_eq!(ids(query).0, vec!["tt0211915"]);
+    }
+
+    #[test]
+    fn titles_read_in_order() {
+        let ctx = TestContext::new("small");
+        let mut idx = ctx.create_index();
+        let results = idx
+            .search(&NameQuery::new("the simpsons").with_size(50))
+            .unwrap()
+            .into_vec();
+        assert!(results.windows(2).all(|w| w[0].score() >= w[1].score()));
+        // The data is sorted by ID, so the ranking isn't in file order...
+        let ids: Vec<String> =
+            results.iter().map(|r| r.value().id.clone()).collect();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_ne!(ids, sorted);
+        // ... but the records were still read in file order.
+        assert_eq!(idx.backward_seeks(), 0);
+
+        // Each result is the same as the title read on its own, which jumps
+        // back and forth through the data.
+        for result in &results {
+            let title = idx.title(&result.value().id).unwrap().unwrap();
+            assert_eq!(title.title, result.value().title);
+            assert_eq!(title.start_year, result.value().start_year);
+        }
+        assert!(idx.backward_seeks() > 0);
+    }
+
+    #[test]
+    fn name_or() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+
+        // A name that barely matches anything doesn't promote its best match
+        // to the top. Here, "xxbombxx" only shares a few ngrams with Dr.
+        // Strangelove, which scored as well as The Matrix when each name's
+        // results were normalized on their own.
+        let query = Query::new()
+            .name("the matrix")
+            .name_or("xxbombxx")
+            .kind(TitleKind::Movie);
+        let results = searcher.search(&query).unwrap().into_vec();
+        assert_eq!(results[0].value().title().id, "tt0133093");
+        let strangelove = results
+            .iter()
+            .find(|r| r.value().title().id == "tt0057012")
+            .unwrap();
+        assert!(strangelove.score() < 0.5, "{}", strangelove.score());
+
+        let mut ids = |query: &Query| -> Vec<String> {
+            let results = searcher.search(query).unwrap().into_vec();
+            results.into_iter().map(|r| r.value().title().id.clone()).collect()
+        };
+        assert_eq!(
+            ids(&"{movie} the matrix | xxbombxx".parse().unwrap()),
+            ids(&query)
+        );
+
+        // A title found by both names is only returned once.
+        let query = Query::new().name("amélie").name_or("amelie poulain");
+        let got = ids(&query);
+        let amelie = got.iter().filter(|id| *id == "tt0211915").count();
+        assert_eq!(amelie, 1, "{:?}", got);
+
+        // Exhaustive searches compare titles with the closest name.
+        let query = Query::new()
+            .name("The Matrix")
+            .name_or("Amélie")
+            .name_scorer(None)
+            .similarity(Similarity::Levenshtein)
+            .kind(TitleKind::Movie)
+            .size(2);
+        let mut got = ids(&query);
+        got.sort();
+        assert_eq!(got, vec!["tt0133093", "tt0211915"]);
+    }
+
+    #[test]
+    fn ended_filter() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let mut ids = |query: &str| -> Vec<String> {
+            let query: Query = query.parse().unwrap();
+            let results = searcher.search(&query).unwrap().into_vec();
+            results.into_iter().map(|r| r.value().title().id.clone()).collect()
+        };
+
+        // The Simpsons has no end year, so it's still airing. The episode
+        // named "The Simpsons Christmas Special" is never a TV series.
+        assert_eq!(ids("{airing} the simpsons"), vec!["tt0096697"]);
+        assert!(ids("{ended} the simpsons").is_empty());
+        assert_eq!(
+            ids("{airing} {year:1989} {exhaustive}"),
+            vec!["tt0096697"]
+        );
+
+        let title = |kind, end_year| Title {
+            id: "tt1".into(),
+            kind,
+            title: "Show".into(),
+            original_title: "Show".into(),
+            is_adult: false,
+            start_year: Some(2019),
+            end_year,
+            runtime_minutes: None,
+            genres: String::new(),
+        };
+        let ended = Query::new().ended(true);
+        let airing = Query::new().ended(false);
+        let mini = title(TitleKind::TVMiniSeries, Some(2019));
+        assert!(ended.reject_title(&mini).is_none());
+        assert!(matches!(airing.reject_title(&mini), Some(Filter::Ended)));
+        let movie = title(TitleKind::Movie, None);
+        assert!(matches!(ended.reject_title(&movie), Some(Filter::Ended)));
+        assert!(matches!(airing.reject_title(&movie), Some(Filter::Ended)));
+    }
+
+    #[test]
+    fn season_overview() {
+        let ctx = TestContext::new("small");
+        let mut idx = ctx.create_index();
+        let overview = idx.season_overview("tt0096697").unwrap();
+        let seasons = idx.seasons("tt0096697").unwrap();
+        assert_eq!(overview.len(), 60);
+        assert_eq!(overview.len(), seasons.len());
+
+        for ((ep, rating, title), expected) in overview.iter().zip(&seasons) {
+            assert_eq!(ep.id, expected.id);
+            assert_eq!(title.as_ref().unwrap().id, ep.id);
+            assert_eq!(
+                rating.as_ref().map(|r| &r.id),
+                idx.rating(&ep.id).unwrap().as_ref().map(|r| &r.id),
+            );
+        }
+        let rated: Vec<(&str, u32, u32, f32)> = overview
+            .iter()
+            .filter_map(|(ep, rating, _)| {
+                let rating = rating.as_ref()?;
+                assert_eq!(rating.id, ep.id);
+                Some((
+                    &*ep.id,
+                    ep.season.unwrap(),
+                    ep.episode.unwrap(),
+                    rating.rating,
+                ))
+            })
+            .collect();
+        assert_eq!(
+            rated,
+            vec![
+                ("tt0348034", 1, 1, 8.1),
+                ("tt0756593", 1, 2, 7.6),
+                ("tt0763024", 2, 1, 7.9),
+            ]
+        );
+
+        assert!(idx.season_overview("tt0087182").unwrap().is_empty());
+    }
+
+    #[test]
+    fn combine_parse() {
+        assert_eq!("replace".parse::<Combine>().unwrap(), Combine::Replace);
+        assert_eq!("multiply".parse::<Combine>().unwrap(), Combine::Multiply);
+        assert_eq!(
+            "weighted:0.7".parse::<Combine>().unwrap(),
+            Combine::weighted(0.7).unwrap()
+        );
+        assert!("weighted".parse::<Combine>().is_err());
+        assert!("weighted:1.5".parse::<Combine>().is_err());
+        assert!("weighted:NaN".parse::<Combine>().is_err());
+        assert!("add".parse::<Combine>().is_err());
+        assert!(Combine::weighted(f64::NAN).is_err());
+        assert!(Combine::weighted(f64::INFINITY).is_err());
+        assert_eq!(
+            Combine::weighted(-0.0).unwrap(),
+            Combine::weighted(0.0).unwrap()
+        );
+
+        let q: Query = "{combine:weighted:0.7} foo".parse().unwrap();
+        assert_eq!(
+            q,
+            Query::new().name("foo").combine(Combine::weighted(0.7).unwrap())
+        );
+        assert_eq!(q.to_string().parse::<Query>().unwrap(), q);
+    }
+
+    #[test]
+    fn combine_scores() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let ids = |results: &SearchResults<MediaEntity>| -> Vec<String> {
+            results
+                .as_slice()
+                .iter()
+                .map(|r| r.value().title().id.clone())
+                .collect()
+        };
+        let query = Query::new().name("bart the").size(5);
+        let by_name = searcher.search(&query).unwrap();
+        let sim = query.clone().similarity(Similarity::JaroWinkler);
+        let by_sim = searcher.search(&sim).unwrap();
+
+        // A weight of 0 ignores the similarity entirely, and a weight of 1 is
+        // the same as replacing the name score.
+        let only_name = sim.clone().combine(Combine::weighted(0.0).unwrap());
+        assert_eq!(ids(&searcher.search(&only_name).unwrap()), ids(&by_name));
+        let only_sim = sim.clone().combine(Combine::weighted(1.0).unwrap());
+        assert_eq!(ids(&searcher.search(&only_sim).unwrap()), ids(&by_sim));
+
+        let multiply = sim.clone().combine(Combine::Multiply);
+        let results = searcher.search(&multiply).unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(results.as_slice()[0].score(), 1.0);
+    }
+
+    #[test]
+    fn rescore_window() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let ids = |results: &SearchResults<MediaEntity>| -> Vec<String> {
+            results
+                .as_slice()
+                .iter()
+                .map(|r| r.value().title().id.clone())
+                .collect()
+        };
+
+        let query = Query::new()
+            .name("bart the")
+            .similarity(Similarity::Levenshtein)
+            .size(3);
+        let (results, diag) = search_checked(&mut searcher, &query);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results.as_slice()[0].score(), 1.0);
+        // Every candidate is rescored before the results are trimmed.
+        assert_eq!(diag.rescored, diag.candidates);
+        assert_eq!(diag.dropped_size, diag.candidates - 3);
+        // The default window covers every result of the name index here, so
+        // widening it changes nothing.
+        let wide = query.clone().rescore_window(100_000);
+        let (wide_results, wide_diag) = search_checked(&mut searcher, &wide);
+        assert_eq!(ids(&results), ids(&wide_results));
+        assert_eq!(diag, wide_diag);
+
+        // A small window means fewer candidates are fetched and rescored,
+        // but is never smaller than the size of the query.
+        let narrow = query.clone().rescore_window(1);
+        let (narrow_results, narrow_diag) =
+            search_checked(&mut searcher, &narrow);
+        assert_eq!(narrow_diag.candidates, 3);
+        assert_eq!(narrow_diag.rescored, 3);
+        assert!(narrow_diag.rescored < diag.rescored);
+        assert_eq!(narrow_results.len(), 3);
+        assert_eq!(narrow_results.as_slice()[0].score(), 1.0);
+
+        // Without a similarity function, nothing is rescored.
+        let (_, diag) = searcher
+            .search_diagnostics(&Query::new().name("bart the"))
+            .unwrap();
+        assert_eq!(diag.rescored, 0);
+    }
+
+    #[test]
+    fn year_filter_window() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+
+        // Even when the window only has room for one candidate, the year
+        // filter is applied by the name index, so the title from the wrong
+        // year can't take the only spot.
+        for (year, id) in [(1984, "tt0087182"), (2021, "tt1160419")] {
+            let query = Query::new()
+                .name("dune")
+                .year_ge(year)
+                .year_le(year)
+                .size(1)
+                .rescore_window(1);
+            let (results, diag) = search_checked(&mut searcher, &query);
+            assert_eq!(diag.candidates, 1, "{}", query);
+            assert_eq!(results.len(), 1, "{}", query);
+            assert_eq!(results.as_slice()[0].value().title().id, id);
+        }
+    }
+
+    #[test]
+    fn empty_reasons() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        fn reason(
+            searcher: &mut Searcher,
+            query: Query,
+        ) -> Option<EmptyReason> {
+            let outcome = searcher.search_with_diagnostics(&query).unwrap();
+            assert_eq!(
+                outcome.results.len(),
+                searcher.search(&query).unwrap().len()
+            );
+            outcome.empty_reason
+        }
+
+        assert_eq!(reason(&mut searcher, Query::new().name("bart")), None);
+        assert_eq!(
+            reason(&mut searcher, Query::new()),
+            Some(EmptyReason::EmptyQuery)
+        );
+        assert_eq!(
+            reason(&mut searcher, Query::new().name("xqzjw")),
+            Some(EmptyReason::NameNotFound)
+        );
+        assert!(matches!(
+            reason(&mut searcher, Query::new().name("bart").size(0)),
+            Some(EmptyReason::Trimmed { .. })
+        ));
+
+        // Filters applied after searching the name index.
+        let r = reason(
+            &mut searcher,
+            Query::new().name("bart").votes_ge(10_000_000),
+        );
+        match r {
+            Some(EmptyReason::Filtered {
+                by_name: true, candidates, ..
+            }) => {
+                assert_eq!(
+                    r.unwrap().to_string(),
+                    format!(
+                        "all {} name matches were excluded by \
+                         {{votes:10000000-}}",
+                        candidates
+                    )
+                );
+            }
+            r => panic!("unexpected reason: {:?}", r),
+        }
+
+        // Filters applied by the name index itself.
+        let r = reason(
+            &mut searcher,
+            Query::new()
+                .name("bart")
+                .kind(TitleKind::VideoGame)
+                .year_ge(1900)
+                .year_le(1900),
+        );
+        match r {
+            Some(EmptyReason::Filtered {
+                by_name: true, ref filters, ..
+            }) => {
+                assert_eq!(filters.len(), 1);
+                assert_eq!(filters[0].0, "{videoGame}");
+            }
+            r => panic!("unexpected reason: {:?}", r),
+        }
+        let r = reason(
+            &mut searcher,
+            Query::new().name("dune").year_ge(1900).year_le(1900),
+        );
+        assert_eq!(
+            r.unwrap().to_string(),
+            "all 2 name matches were excluded by {year:1900}"
+        );
+
+        // Exhaustive searches.
+        let r = reason(&mut searcher, Query::new().year_ge(3000));
+        assert!(matches!(
+            r,
+            Some(EmptyReason::Filtered { by_name: false, .. })
+        ));
+        searcher.max_scan_rows(Some(1));
+        let r = reason(&mut searcher, Query::new().year_ge(3000));
+        assert_eq!(r, Some(EmptyReason::Truncated { scanned: 1 }));
+    }
+
+    #[test]
+    fn matched_terms() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+
+        let results =
+            searcher.search(&Query::new().name("the matrix")).unwrap();
+        let top = &results.as_slice()[0];
+        assert_eq!(top.value().title().id, "tt0133093");
+        assert!(top.query_terms().unwrap() > 0);
+        assert_eq!(top.matched_terms(), top.query_terms());
+
+        // Similarity rescoring keeps the matched terms.
+        let query = Query::new()
+            .name("the matrix")
+            .similarity(Similarity::Levenshtein);
+        let results = searcher.search(&query).unwrap();
+        assert!(results
+            .as_slice()
+            .iter()
+            .all(|r| r.matched_terms().is_some()));
+
+        // Results not found via the name index don't have any.
+        let results = searcher.search(&Query::new().year_ge(1999)).unwrap();
+        assert!(!results.is_empty());
+        assert!(results
+            .as_slice()
+            .iter()
+            .all(|r| r.matched_terms().is_none()));
+    }
+
+    #[test]
+    fn kind_filter_window() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        let query = Query::new().name("simpsons").size(1).rescore_window(1);
+
+        // The series has the best match, so it takes the only spot in the
+        // window...
+        let results = searcher.search(&query).unwrap();
+        assert_eq!(results.as_slice()[0].value().title().id, "tt0096697");
+
+        // ... unless it has the wrong kind, in which case the name index
+        // drops it before it can fill up the window.
+        let query = query.kind(TitleKind::TVEpisode);
+        let (results, diag) = search_checked(&mut searcher, &query);
+        assert_eq!(diag.candidates, 1);
+        assert_eq!(diag.dropped_kind, 0);
+        assert_eq!(results.len(), 1);
+        let title = results.as_slice()[0].value().title();
+        assert_eq!(title.kind, TitleKind::TVEpisode);
+    }
+
+    #[test]
+    fn scan_budget() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        searcher.max_scan_rows(Some(10));
+
+        let query: Query = "{year:1900-}".parse().unwrap();
+        let (results, diag) = search_checked(&mut searcher, &query);
+        assert!(diag.truncated);
+        assert_eq!(diag.scanned, 10);
+        assert_eq!(diag.candidates, 10);
+        assert!(results.len() <= 10);
+        let total = diag.estimated_total.unwrap();
+        assert!(total > 10, "estimated total of {}", total);
+
+        // No progress is reported when the budget is spent before the first
+        // report is due.
+        let reports = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&reports);
+        searcher.progress(Some(Box::new(move |p: &Progress| {
+            sink.lock().unwrap().push((p.rows, p.matches, p.done));
+        })));
+        search_checked(&mut searcher, &query);
+        assert!(reports.lock().unwrap().is_empty());
+
+        let query: Query = "{year:1900-} {exhaustive}".parse().unwrap();
+        let (results, diag) = search_checked(&mut searcher, &query);
+        assert!(!diag.truncated);
+        assert_eq!(diag.scanned, 72);
+        assert_eq!(diag.estimated_total, None);
+        assert!(results.len() > 10);
+
+        // 72 rows gives 4 reports, plus a final one. Every title in the small
+        // data set is from 1900 or later, so every row matches.
+        assert_eq!(
+            *reports.lock().unwrap(),
+            vec![
+                (16, 16, false),
+                (32, 32, false),
+                (48, 48, false),
+                (64, 64, false),
+                (72, 72, true)
+            ]
+        );
+
+        // Rows dropped by any filter don't count as matches. No title in the
+        // small data set is a TV series that has ended. (The last report
+        // includes the one adult title, which is skipped before filtering.)
+        reports.lock().unwrap().clear();
+        let query: Query = "{ended} {exhaustive}".parse().unwrap();
+        let (results, diag) = search_checked(&mut searcher, &query);
+        assert!(results.is_empty());
+        assert_eq!(diag.dropped_ended, diag.candidates);
+        assert_eq!(
+            reports.lock().unwrap()[..4],
+            [(16, 0, false), (32, 0, false), (48, 0, false), (64, 0, false)]
+        );
+    }
+
+    #[test]
+    fn try_clone() {
+        let ctx = TestContext::new("small");
+        let mut searcher = Searcher::new(ctx.create_index());
+        searcher.max_scan_rows(Some(10));
+        let query: Query = "{year:1900-}".parse().unwrap();
+        let ids = |results: &SearchResults<MediaEntity>| -> Vec<String> {
+            results
+                .as_slice()
+                .iter()
+                .map(|r| r.value().title().id.clone())
+                .collect()
+        };
+        let (results, diag) = search_checked(&mut searcher, &query);
+        assert!(diag.truncated);
+        let serial = (ids(&results), diag.truncated, diag.scanned);
+
+        // Clones searching in parallel keep the budget of the original, so
+        // they cut their scans short in the same place.
+        let mut clones = vec![];
+        for _ in 0..4 {
+            clones.push(searcher.try_clone().unwrap());
+        }
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = clones
+                .into_iter()
+                .map(|mut searcher| {
+                    let query = &query;
+                    scope.spawn(move || {
+                        let (results, diag) =
+                            search_checked(&mut searcher, query);
+                        (ids(&results), diag.truncated, diag.scanned)
+                    })
+                })
+                .collect();
+            for worker in workers {
+                assert_eq!(worker.join().unwrap(), serial);
+            }
+        });
+    }
 
     #[test]
     fn ranges() {
@@ -873,6 +3493,12 @@ mod tests {
             Query::new().kind(TitleKind::Movie).kind(TitleKind::TVSeries)
         );
 
+        let q: Query = "{tvshow}{movie}".parse().unwrap();
+        assert_eq!(
+            q,
+            Query::new().kind(TitleKind::Movie).kind(TitleKind::TVSeries)
+        );
+
         let q: Query = "{movie}{tvshow}".parse().unwrap();
         assert_eq!(
             q,
@@ -905,6 +3531,38 @@ mod tests {
 
         let q: Query = "{year:-}".parse().unwrap();
         assert_eq!(q, Query::new());
+
+        let q: Query = "{window:50} foo".parse().unwrap();
+        assert_eq!(q, Query::new().name("foo").rescore_window(50));
+        assert_eq!(q.to_string().parse::<Query>().unwrap(), q);
+
+        let q: Query = "{year:1990} { exhaustive }".parse().unwrap();
+        assert_eq!(
+            q,
+            Query::new().year_ge(1990).year_le(1990).exhaustive(true)
+        );
+        assert_eq!(q.to_string().parse::<Query>().unwrap(), q);
+
+        let q: Query = "the matrix {original-only}".parse().unwrap();
+        assert_eq!(q, Query::new().name("the matrix").original_only(true));
+        assert_eq!(q.to_string().parse::<Query>().unwrap(), q);
+
+        let q: Query = "oldboy | oldeuboi {year:2003}|".parse().unwrap();
+        assert_eq!(
+            q,
+            Query::new()
+                .name("oldboy")
+                .name_or("oldeuboi")
+                .year_ge(2003)
+                .year_le(2003)
+        );
+        assert_eq!(q.to_string().parse::<Query>().unwrap(), q);
+        assert!(q.to_string().ends_with("} oldboy | oldeuboi"));
+
+        let q: Query = "{lang:JA} foo".parse().unwrap();
+        assert_eq!(q, Query::new().name("foo").language("ja"));
+        assert_eq!(q, "{language:ja} foo".parse().unwrap());
+        assert_eq!(q.to_string().parse::<Query>().unwrap(), q);
     }
 
     #[test]
@@ -912,6 +3570,32 @@ mod tests {
         assert!("{blah}".parse::<Query>().is_err());
         assert!("{size:a}".parse::<Query>().is_err());
         assert!("{year:}".parse::<Query>().is_err());
+        // These used to panic, since '.' didn't match a new line.
+        assert!("{\n}".parse::<Query>().is_err());
+        assert!("{size:\n}".parse::<Query>().is_err());
+        // This used to parse into an empty TV show ID, which couldn't be
+        // parsed again once printed.
+        assert!("{show:\t}".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn query_parser_roundtrip() {
+        let queries = [
+            "{movie\n} foo",
+            "{size:\n5}",
+            "{show: tt0096697\r\n}",
+            "{ combine : weighted:0.25 }",
+            "{scorer:none}{sim:levenshtein} a}b{c",
+            "\u{85}foo\u{a0}bar\u{2028}",
+            "{episode} {movie}",
+            "{ended} foo",
+            "{ airing }",
+        ];
+        for qstr in &queries {
+            let q: Query = qstr.parse().unwrap();
+            let printed = q.to_string();
+            assert_eq!(printed.parse::<Query>().unwrap(), q, "{:?}", qstr);
+        }
     }
 
     #[test]
@@ -969,4 +3653,41 @@ mod tests {
         let got: Test = serde_json::from_str(json).unwrap();
         assert_eq!(got.query, expected);
     }
+
+    #[test]
+    fn sequels() {
+        let sequel = |prefix: &[&str], number: u32| Sequel {
+            prefix: prefix.iter().map(|w| w.to_string()).collect(),
+            number,
+        };
+        assert_eq!(
+            Sequel::parse("John Wick 3"),
+            Some(sequel(&["john", "wick"], 3))
+        );
+        assert_eq!(Sequel::parse("Rocky.IV"), Some(sequel(&["rocky"], 4)));
+        assert_eq!(
+            Sequel::parse("the godfather part ii"),
+            Some(sequel(&["the", "godfather", "part"], 2))
+        );
+        assert_eq!(Sequel::parse("Apollo 13"), Some(sequel(&["apollo"], 13)));
+        assert_eq!(Sequel::parse("John Wick"), None);
+        assert_eq!(Sequel::parse("Henry VIII of England"), None);
+        assert_eq!(Sequel::parse("Me and I"), None);
+        assert_eq!(Sequel::parse("300"), None);
+
+        let wick = Sequel::parse("john wick 3").unwrap();
+        assert!(wick.is_related("John Wick"));
+        assert!(wick.is_related("John Wick: Chapter 2"));
+        assert!(!wick.is_related("Wick"));
+        assert!(wick.is_numbered("John Wick: Chapter 3 - Parabellum"));
+        assert!(wick.is_numbered("John Wick III"));
+        assert!(!wick.is_numbered("John Wick: Chapter 2"));
+        assert!(!wick.is_numbered("John Wick"));
+        assert!(!wick.is_numbered("The 3 John Wicks"));
+
+        let rocky = Sequel::parse("rocky iv").unwrap();
+        assert!(rocky.is_numbered("Rocky IV"));
+        assert!(rocky.is_numbered("Rocky 4"));
+        assert!(!rocky.is_numbered("Rocky V"));
+    }
 }
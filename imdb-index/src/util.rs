@@ -34,6 +34,23 @@ pub const IMDB_EPISODE: &str = "title.episode.tsv";
 /// in creating that rating (from the IMDb web site, presumably).
 pub const IMDB_RATINGS: &str = "title.ratings.tsv";
 
+/// Convert seconds since the Unix epoch to a `(year, month, day)` date in UTC.
+///
+/// This uses Howard Hinnant's `civil_from_days` algorithm. See:
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn civil_from_unix(secs: u64) -> (u64, u64, u64) {
+    let z = secs / 86_400 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
 /// A type that provides a Display impl for std::time::Duration.
 #[derive(Debug)]
 pub struct NiceDuration(pub time::Duration);
@@ -160,3 +177,19 @@ pub unsafe fn fst_map_file<P: AsRef<Path>>(path: P) -> Result<fst::Map<Mmap>> {
     })?;
     Ok(map)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::civil_from_unix;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_unix(0), (1970, 1, 1));
+        assert_eq!(civil_from_unix(86_399), (1970, 1, 1));
+        assert_eq!(civil_from_unix(951_782_400), (2000, 2, 29));
+        assert_eq!(civil_from_unix(951_868_800), (2000, 3, 1));
+        assert_eq!(civil_from_unix(1_709_164_800), (2024, 2, 29));
+        assert_eq!(civil_from_unix(1_735_689_599), (2024, 12, 31));
+        assert_eq!(civil_from_unix(4_107_542_400), (2100, 3, 1));
+    }
+}
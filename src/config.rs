@@ -0,0 +1,601 @@
+// A config file sets defaults for imdb-rename's flags, such that flags that
+// are passed on every run don't need to be repeated. Its keys are the long
+// names of flags, without the leading `--`, e.g.:
+//
+//     dest-dir = "/media/videos"
+//     votes = 500
+//     follow = true
+//     ext-alias = ["mkv:mp4", "m4v:mp4"]
+//
+// Every flag is listed in `FLAGS`, which `app()` declares its flags from, so
+// the config file accepts exactly the flags that the command line does. Each
+// value is checked by parsing it with `app()`, and a key that isn't a flag is
+// reported by name.
+//
+// A flag on the command line or in the environment wins over the config file,
+// and so does any flag that conflicts with it, e.g., `--copy` wins over
+// `symlink = true`. Every switch has a hidden negation for turning it off
+// again, e.g., `--no-follow` wins over `follow = true`.
+
+use std::borrow::Cow;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgMatches};
+
+use crate::app;
+
+use self::Kind::{Switch, Value};
+
+/// Every flag of `app()`, i.e., every flag that a config file may set.
+///
+/// This declares what the config file needs to know about a flag. The rest of
+/// its declaration, e.g., its help, is in `app()`.
+#[rustfmt::skip]
+const FLAGS: &[Flag] = &[
+    // The flags shared by every subcommand that reads IMDb.
+    flag("config", Value).env("IMDB_RENAME_CONFIG"),
+    flag("data-dir", Value).env("IMDB_RENAME_DATA_DIR"),
+    flag("json", Switch { negation: "no-json" }),
+    flag("events", Value),
+    flag("lang", Value),
+    flag("events-file", Value).requires(&["events"]),
+    flag("debug", Switch { negation: "no-debug" }),
+    flag("color", Value),
+    flag("index-dir", Value).env("IMDB_RENAME_INDEX_DIR"),
+    flag("ngram-size", Value),
+    flag("ngram-type", Value),
+    flag("max-scan-time", Value),
+    flag("max-scan-rows", Value),
+    flag("no-auto-rebuild", Switch { negation: "auto-rebuild" }),
+    flag("read-only-index", Switch { negation: "no-read-only-index" }),
+    flag("yes", Switch { negation: "no-yes" }),
+    // The flags for renaming files.
+    flag("dest-dir", Value).env("IMDB_RENAME_DEST_DIR"),
+    flag("movies-dir", Value),
+    flag("tv-dir", Value),
+    flag("show-years", Switch { negation: "no-show-years" }),
+    flag("all-files", Switch { negation: "no-all-files" }),
+    flag("explain-rename", Switch { negation: "no-explain-rename" }),
+    flag("dry-run", Switch { negation: "no-dry-run" }),
+    flag("verbose-proposals", Switch { negation: "no-verbose-proposals" }),
+    flag("stats", Switch { negation: "no-stats" }),
+    flag("export-plan", Value).conflicts_with(&["dry-run"]),
+    flag("apply-plan", Value)
+        .conflicts_with(&["file", "query", "dry-run", "export-plan"]),
+    flag("undo", Value)
+        .conflicts_with(&["query", "dry-run", "export-plan", "apply-plan"]),
+    flag("threads", Value),
+    flag("sort-titles", Switch { negation: "no-sort-titles" }),
+    flag("keep-tags", Switch { negation: "no-keep-tags" }),
+    flag("no-query-fixups", Switch { negation: "query-fixups" }),
+    flag("ext-lower", Switch { negation: "no-ext-lower" }),
+    flag("ext-alias", Value),
+    flag("mkdir", Switch { negation: "no-mkdir" }),
+    flag("dir-mode", Value).requires(&["mkdir"]),
+    flag("chown", Value).requires(&["mkdir"]),
+    flag("write-origin", Value),
+    flag("write-nfo", Switch { negation: "no-write-nfo" }),
+    flag("force-nfo", Switch { negation: "no-force-nfo" })
+        .requires(&["write-nfo"]),
+    flag("auto-accept-margin", Value),
+    flag("min-match-ratio", Value),
+    flag("follow", Switch { negation: "no-follow" }),
+    flag("recursive", Switch { negation: "no-recursive" }),
+    flag("only", Value),
+    flag("include-dir", Value),
+    flag("exclude-dir", Value),
+    flag("exclude", Value),
+    flag("case-sensitive-globs",
+         Switch { negation: "no-case-sensitive-globs" }),
+    flag("ext", Value),
+    flag("query", Value),
+    flag("season", Value),
+    flag("episode", Value),
+    flag("re-episode", Value),
+    flag("episode-gap", Value),
+    flag("re-season", Value),
+    flag("re-season-episode", Value),
+    flag("re-year", Value),
+    flag("votes", Value),
+    flag("votes-movie", Value),
+    flag("votes-tv", Value),
+    flag("symlink", Switch { negation: "no-symlink" })
+        .conflicts_with(&["hardlink"]),
+    flag("symlink-relative", Switch { negation: "no-symlink-relative" })
+        .conflicts_with(&["hardlink"]),
+    flag("hardlink", Switch { negation: "no-hardlink" })
+        .conflicts_with(&["symlink"]),
+    flag("copy", Switch { negation: "no-copy" })
+        .conflicts_with(&["symlink", "symlink-relative", "hardlink"]),
+    flag("reflink", Value)
+        .conflicts_with(&["symlink", "symlink-relative", "hardlink", "copy"]),
+    flag("skip-in-use", Switch { negation: "no-skip-in-use" }),
+    flag("allow-cross-device", Switch { negation: "no-allow-cross-device" }),
+    flag("transactional", Switch { negation: "no-transactional" }),
+    flag("preserve-times", Switch { negation: "no-preserve-times" }),
+    // The flags for listing similar titles or the episodes of a TV show.
+    flag("similar", Value).conflicts_with(&["query"]),
+    flag("episodes", Value).conflicts_with(&["query", "similar"]),
+    // The flags that select the other subcommands.
+    flag("index-stats", Switch { negation: "no-index-stats" })
+        .conflicts_with(&["apply-plan", "undo"]),
+    flag("update-data", Switch { negation: "no-update-data" })
+        .conflicts_with(&["read-only-index", "apply-plan", "undo"]),
+    flag("update-index", Switch { negation: "no-update-index" })
+        .conflicts_with(&["read-only-index", "apply-plan", "undo"]),
+];
+
+/// A flag of `app()`.
+#[derive(Debug)]
+struct Flag {
+    /// The long name of the flag, which is also its config key.
+    name: &'static str,
+    kind: Kind,
+    env: Option<&'static str>,
+    /// The flags that this flag conflicts with.
+    conflicts: &'static [&'static str],
+    /// The flags that must be set along with this flag.
+    requires: &'static [&'static str],
+}
+
+/// Whether a flag is a switch or takes a value.
+#[derive(Debug)]
+enum Kind {
+    /// A switch, along with the name of its hidden negation. The negation of
+    /// a switch that starts with `no-` drops that prefix instead, e.g.,
+    /// `--auto-rebuild` for `--no-auto-rebuild`.
+    Switch {
+        negation: &'static str,
+    },
+    Value,
+}
+
+/// Return a flag with the given name and kind, and nothing else.
+const fn flag(name: &'static str, kind: Kind) -> Flag {
+    Flag { name, kind, env: None, conflicts: &[], requires: &[] }
+}
+
+impl Flag {
+    const fn env(mut self, var: &'static str) -> Flag {
+        self.env = Some(var);
+        self
+    }
+
+    const fn conflicts_with(mut self, names: &'static [&'static str]) -> Flag {
+        self.conflicts = names;
+        self
+    }
+
+    const fn requires(mut self, names: &'static [&'static str]) -> Flag {
+        self.requires = names;
+        self
+    }
+
+    /// Return the flag with the given name, if it exists.
+    fn get(name: &str) -> Option<&'static Flag> {
+        FLAGS.iter().find(|f| f.name == name)
+    }
+
+    /// Whether this flag and the given flag can't be set together. Only one
+    /// of them needs to declare the conflict.
+    fn excludes(&self, other: &Flag) -> bool {
+        self.conflicts.contains(&other.name)
+            || other.conflicts.contains(&self.name)
+    }
+
+    fn takes_value(&self) -> bool {
+        matches!(self.kind, Value)
+    }
+}
+
+/// Return the clap argument for the flag with the given name, as declared in
+/// `FLAGS`.
+///
+/// The flags that it requires aren't declared to clap, since one of them may
+/// come from the config file. `Matches::check_requires` checks them instead.
+///
+/// This panics if the flag isn't in `FLAGS`.
+pub fn arg(name: &'static str) -> Arg<'static, 'static> {
+    let flag = Flag::get(name)
+        .unwrap_or_else(|| panic!("--{} is missing from FLAGS", name));
+    let mut arg = Arg::with_name(flag.name)
+        .long(flag.name)
+        .takes_value(flag.takes_value())
+        .conflicts_with_all(flag.conflicts);
+    if let Some(var) = flag.env {
+        arg = arg.env(var);
+    }
+    arg
+}
+
+/// Return the hidden negation of the switch with the given name, such that
+/// a switch turned on by the config file can be turned off on the command
+/// line, e.g., `--no-follow` for `--follow`.
+///
+/// This panics if the switch isn't in `FLAGS`.
+pub fn negation(name: &'static str) -> Arg<'static, 'static> {
+    let negation = match Flag::get(name) {
+        Some(&Flag { kind: Switch { negation }, .. }) => negation,
+        _ => panic!("--{} is missing from FLAGS as a switch", name),
+    };
+    Arg::with_name(negation).long(negation).hidden(true).overrides_with(name)
+}
+
+/// The flags parsed from the command line, merged with those in a config
+/// file.
+///
+/// Each lookup takes the value of a flag from the first of these that sets
+/// it: the command line, the flag's environment variable, the config file
+/// and finally the flag's built-in default. The config file is skipped for
+/// a flag when the command line or the environment sets a flag that
+/// conflicts with it, such that, e.g., `--copy` wins over `symlink = true`.
+///
+/// When a subcommand is given, only its flags are looked up.
+#[derive(Debug)]
+pub struct Matches {
+    command: Option<String>,
+    cli: ArgMatches<'static>,
+    config: Option<ArgMatches<'static>>,
+}
+
+impl Matches {
+    /// Merge the given command line flags with the flags in the given config
+    /// file, if any.
+    pub fn new(
+        cli: ArgMatches<'static>,
+        config: Option<ArgMatches<'static>>,
+    ) -> Matches {
+        let sub = cli.subcommand_name().map(|name| {
+            (name.to_string(), cli.subcommand_matches(name).unwrap().clone())
+        });
+        match sub {
+            None => Matches { command: None, cli, config },
+            Some((name, sub)) => {
+                Matches { command: Some(name), cli: sub, config }
+            }
+        }
+    }
+
+    /// Merge these flags with the flags in the config file given by
+    /// --config, or in the default config file if it exists.
+    pub fn load(self) -> anyhow::Result<Matches> {
+        let config = match self.cli.value_of_os("config") {
+            Some(path) => Some(read(Path::new(path))?),
+            None => match default_path() {
+                Some(path) if path.exists() => Some(read(&path)?),
+                _ => None,
+            },
+        };
+        Ok(Matches { config, ..self })
+    }
+
+    /// The name of the subcommand given, if any.
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    // The lookups below mirror those of `ArgMatches`.
+
+    pub fn is_present(&self, name: &str) -> bool {
+        self.source(name).is_present(name)
+    }
+
+    pub fn value_of(&self, name: &str) -> Option<&str> {
+        self.source(name).value_of(name)
+    }
+
+    pub fn value_of_lossy(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.source(name).value_of_lossy(name)
+    }
+
+    pub fn value_of_os(&self, name: &str) -> Option<&OsStr> {
+        self.source(name).value_of_os(name)
+    }
+
+    pub fn values_of_lossy(&self, name: &str) -> Option<Vec<String>> {
+        self.source(name).values_of_lossy(name)
+    }
+
+    pub fn values_of_os(&self, name: &str) -> Option<clap::OsValues<'_>> {
+        self.source(name).values_of_os(name)
+    }
+
+    /// Return the matches that the given flag should be read from.
+    ///
+    /// Environment variables are applied by clap when parsing the command
+    /// line, so a flag set by its variable is read from the command line
+    /// too.
+    fn source(&self, name: &str) -> &ArgMatches<'static> {
+        match self.config {
+            Some(ref config) if self.is_from_config(name) => config,
+            _ => &self.cli,
+        }
+    }
+
+    /// Whether the given flag is taken from the config file, i.e., the
+    /// config file sets it and neither the command line nor the environment
+    /// sets it, its negation or a flag that conflicts with it.
+    fn is_from_config(&self, name: &str) -> bool {
+        let set = self
+            .config
+            .as_ref()
+            .is_some_and(|config| config.occurrences_of(name) > 0);
+        let flag = match Flag::get(name) {
+            Some(flag) if set => flag,
+            _ => return false,
+        };
+        if let Switch { negation } = flag.kind {
+            if self.cli.occurrences_of(negation) > 0 {
+                return false;
+            }
+        }
+        !FLAGS.iter().any(|other| {
+            (other.name == name || other.excludes(flag)) && self.is_set(other)
+        })
+    }
+
+    /// Check that every flag that is set has the flags it requires, which
+    /// may be set on the command line or in the config file.
+    pub fn check_requires(&self) -> anyhow::Result<()> {
+        for flag in FLAGS.iter().filter(|f| self.is_present(f.name)) {
+            for &required in flag.requires {
+                if self.is_present(required) {
+                    continue;
+                }
+                if self.is_from_config(flag.name) {
+                    anyhow::bail!(
+                        "'{}' in the config file requires --{}",
+                        flag.name,
+                        required
+                    );
+                }
+                anyhow::bail!("--{} requires --{}", flag.name, required);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the given flag is set on the command line or by its
+    /// environment variable.
+    fn is_set(&self, flag: &Flag) -> bool {
+        self.cli.occurrences_of(flag.name) > 0
+            || flag.env.is_some_and(|var| env::var_os(var).is_some())
+    }
+}
+
+/// The path of the default config file, if a home directory is known.
+///
+/// This is `$XDG_CONFIG_HOME/imdb-rename/config.toml`, where
+/// `$XDG_CONFIG_HOME` defaults to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("imdb-rename").join("config.toml"))
+}
+
+/// Read the config file at the given path and parse its keys as flags.
+pub fn read(path: &Path) -> anyhow::Result<ArgMatches<'static>> {
+    let data = fs::read_to_string(path).map_err(|e| {
+        anyhow::anyhow!(
+            "error reading config file '{}': {}",
+            path.display(),
+            e
+        )
+    })?;
+    parse(&data).map_err(|e| {
+        anyhow::anyhow!("invalid config file '{}': {}", path.display(), e)
+    })
+}
+
+/// Parse the given TOML as flags.
+fn parse(data: &str) -> anyhow::Result<ArgMatches<'static>> {
+    let table: toml::Table = data.parse()?;
+    let mut argv = vec!["imdb-rename".to_string()];
+    for (key, value) in &table {
+        // A flag that would point to yet another config file makes no sense
+        // as a default.
+        let flag = match Flag::get(key) {
+            Some(flag) if flag.name != "config" => flag,
+            _ => anyhow::bail!("unknown key '{}'", key),
+        };
+        let name = format!("--{}", key);
+        let args = flag_args(&name, value, flag.takes_value())
+            .ok_or_else(|| anyhow::anyhow!("invalid value for '{}'", key))?;
+        let mut one = vec!["imdb-rename".to_string()];
+        one.extend(args.iter().cloned());
+        if let Err(err) = app().get_matches_from_safe(one) {
+            let msg = err.message.lines().next().unwrap_or("").to_string();
+            anyhow::bail!(
+                "invalid value for '{}': {}",
+                key,
+                msg.trim_start_matches("error: ")
+            );
+        }
+        argv.extend(args);
+    }
+    Ok(app().get_matches_from_safe(argv)?)
+}
+
+/// Convert a config value for the given flag to command line arguments.
+///
+/// Booleans turn switches on or off. For flags that take a value, arrays
+/// repeat the flag for each of their values, and any other value is passed
+/// as the flag's value. This returns `None` for values that don't fit the
+/// flag.
+fn flag_args(
+    flag: &str,
+    value: &toml::Value,
+    takes_value: bool,
+) -> Option<Vec<String>> {
+    use toml::Value;
+
+    Some(match *value {
+        Value::Boolean(true) if !takes_value => vec![flag.to_string()],
+        Value::Boolean(false) if !takes_value => vec![],
+        Value::String(ref s) if takes_value => vec![format!("{}={}", flag, s)],
+        Value::Integer(n) if takes_value => vec![format!("{}={}", flag, n)],
+        Value::Float(n) if takes_value => vec![format!("{}={}", flag, n)],
+        Value::Array(ref values) if takes_value => {
+            let mut args = vec![];
+            for value in values {
+                if let Value::Array(_) = *value {
+                    return None;
+                }
+                args.extend(flag_args(flag, value, takes_value)?);
+            }
+            args
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ErrorKind;
+
+    use super::{parse, Kind, Matches, FLAGS};
+    use crate::app;
+
+    fn merged(config: &str, cli: &[&str]) -> anyhow::Result<Matches> {
+        let config = parse(config)?;
+        let mut argv = vec!["imdb-rename"];
+        argv.extend(cli);
+        let cli = app().get_matches_from_safe(argv)?;
+        Ok(Matches::new(cli, Some(config)))
+    }
+
+    #[test]
+    fn precedence() {
+        let config = r#"
+            dest-dir = "/config"
+            votes = 500
+            follow = true
+            ext-alias = ["mkv:mp4", "m4v:mp4"]
+        "#;
+        let m = merged(config, &[]).unwrap();
+        assert_eq!(m.value_of("votes"), Some("500"));
+        assert!(m.is_present("follow"));
+        assert!(!m.is_present("recursive"));
+        assert_eq!(
+            m.values_of_lossy("ext-alias").unwrap(),
+            vec!["mkv:mp4", "m4v:mp4"]
+        );
+        // Flags missing from the config file keep their built-in defaults.
+        assert_eq!(m.value_of("threads"), Some("1"));
+
+        let m =
+            merged(config, &["--votes", "10", "--ext-alias", "a:b"]).unwrap();
+        assert_eq!(m.value_of("votes"), Some("10"));
+        assert_eq!(m.values_of_lossy("ext-alias").unwrap(), vec!["a:b"]);
+    }
+
+    #[test]
+    fn conflicts() {
+        // A flag on the command line wins over the flags it conflicts with
+        // in the config file, in either direction of the declaration.
+        let m = merged("symlink = true", &["--copy"]).unwrap();
+        assert!(m.is_present("copy"));
+        assert!(!m.is_present("symlink"));
+        let m = merged("copy = true", &["--symlink"]).unwrap();
+        assert!(m.is_present("symlink"));
+        assert!(!m.is_present("copy"));
+        let m = merged("symlink = true", &["--reflink"]).unwrap();
+        assert!(!m.is_present("symlink"));
+
+        // Flags that don't conflict are still taken from the config file.
+        let m = merged("symlink = true\nfollow = true", &["--copy"]).unwrap();
+        assert!(m.is_present("follow"));
+    }
+
+    #[test]
+    fn negations() {
+        let m = merged("follow = true", &["--no-follow"]).unwrap();
+        assert!(!m.is_present("follow"));
+        let m = merged("follow = true", &["--no-follow", "--follow"]).unwrap();
+        assert!(m.is_present("follow"));
+        let m = merged("no-auto-rebuild = true", &["--auto-rebuild"]).unwrap();
+        assert!(!m.is_present("no-auto-rebuild"));
+        let m = merged("symlink = true", &["--no-symlink"]).unwrap();
+        assert!(!m.is_present("symlink"));
+    }
+
+    #[test]
+    fn requires() {
+        // A flag with a value is a flag with a value, even if it requires
+        // another flag.
+        let config = "events = 'jsonl'\nevents-file = '/x'";
+        let m = merged(config, &[]).unwrap();
+        assert_eq!(m.value_of("events-file"), Some("/x"));
+
+        // The required flag may come from the command line.
+        let m = Matches {
+            config: Some(parse("events-file = '/x'").unwrap()),
+            ..merged("", &["--events", "jsonl"]).unwrap()
+        };
+        m.check_requires().unwrap();
+        assert_eq!(m.value_of("events-file"), Some("/x"));
+
+        let m = Matches {
+            config: Some(parse("events-file = '/x'").unwrap()),
+            ..merged("", &[]).unwrap()
+        };
+        let err = m.check_requires().unwrap_err().to_string();
+        assert_eq!(err, "'events-file' in the config file requires --events");
+
+        let m = merged("", &["--events-file", "/x"]).unwrap();
+        let err = m.check_requires().unwrap_err().to_string();
+        assert_eq!(err, "--events-file requires --events");
+        let m = merged("mkdir = true", &["--dir-mode", "755"]).unwrap();
+        m.check_requires().unwrap();
+    }
+
+    #[test]
+    fn flags() {
+        // Every flag in the table is declared by the app, along with the
+        // negation of every switch.
+        for flag in FLAGS {
+            let mut names = vec![flag.name];
+            if let Kind::Switch { negation } = flag.kind {
+                names.push(negation);
+            }
+            for name in names {
+                let arg = format!("--{}", name);
+                if let Err(err) =
+                    app().get_matches_from_safe(vec!["imdb-rename", &arg])
+                {
+                    assert_ne!(
+                        err.kind,
+                        ErrorKind::UnknownArgument,
+                        "{}",
+                        arg
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let err = merged("nope = 1", &[]).unwrap_err().to_string();
+        assert_eq!(err, "unknown key 'nope'");
+        let err = merged("file = 'x.mkv'", &[]).unwrap_err().to_string();
+        assert_eq!(err, "unknown key 'file'");
+        let err = merged("help = true", &[]).unwrap_err().to_string();
+        assert_eq!(err, "unknown key 'help'");
+
+        let err = merged("only = 'nope'", &[]).unwrap_err().to_string();
+        assert!(err.starts_with("invalid value for 'only'"), "{}", err);
+        let err = merged("follow = 'yes'", &[]).unwrap_err().to_string();
+        assert_eq!(err, "invalid value for 'follow'");
+        let err = merged("votes = true", &[]).unwrap_err().to_string();
+        assert_eq!(err, "invalid value for 'votes'");
+        let err = merged("votes = {}", &[]).unwrap_err().to_string();
+        assert_eq!(err, "invalid value for 'votes'");
+        assert!(merged("votes = ", &[]).is_err());
+    }
+}
@@ -1,25 +1,74 @@
 use std::fs::{self, File};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 use {anyhow::Context, flate2::read::GzDecoder};
 
+use imdb_rename::events::{self, Event, Status};
+use imdb_rename::util::human_bytes;
+
 /// The base URL to the IMDb data set.
 ///
 /// It's not clear if this URL will remain free and open forever, although it
 /// is provided by IMDb proper. If this goes away, we'll need to switch to s3.
-const IMDB_BASE_URL: &'static str = "https://datasets.imdbws.com";
+const IMDB_BASE_URL: &str = "https://datasets.imdbws.com";
 
 /// All of the data sets we care about.
 ///
 /// We leave out cast/crew because we don't need them for renaming files.
-const DATA_SETS: &'static [&'static str] = &[
+const DATA_SETS: &[&str] = &[
     "title.akas.tsv.gz",
     "title.basics.tsv.gz",
     "title.episode.tsv.gz",
     "title.ratings.tsv.gz",
 ];
 
+/// The approximate compressed size, in megabytes, of each data set.
+///
+/// These only serve to tell users what they're in for on the first run, so
+/// they need not be exact. They were last checked in 2024.
+fn approx_size_mb(dataset: &str) -> u64 {
+    match dataset {
+        "title.akas.tsv.gz" => 430,
+        "title.basics.tsv.gz" => 190,
+        "title.episode.tsv.gz" => 45,
+        "title.ratings.tsv.gz" => 7,
+        _ => 0,
+    }
+}
+
+/// Returns a message explaining what happens when imdb-rename is run for the
+/// first time, i.e., when `data_dir` doesn't exist yet.
+pub fn first_run_message(data_dir: &Path, index_dir: &Path) -> String {
+    let mut msg = String::new();
+    msg.push_str(
+        "imdb-rename needs a local copy of the IMDb data set, which it \
+         hasn't downloaded yet.\n\nThe following files will be downloaded \
+         from ",
+    );
+    msg.push_str(IMDB_BASE_URL);
+    msg.push_str(":\n\n");
+    let mut total = 0;
+    for &dataset in DATA_SETS {
+        let size = approx_size_mb(dataset);
+        total += size;
+        msg.push_str(&format!("    {:<24}~{} MB\n", dataset, size));
+    }
+    msg.push_str(&format!(
+        "\nThat's about {} MB in total. The files are decompressed into\n\n",
+        total
+    ));
+    msg.push_str(&format!("    {}\n\n", data_dir.display()));
+    msg.push_str("and then indexed into\n\n");
+    msg.push_str(&format!("    {}\n\n", index_dir.display()));
+    msg.push_str(
+        "which takes several gigabytes of disk space and a few minutes. \
+         This only happens once.\n",
+    );
+    msg
+}
+
 /// Download ensures that all of the IMDb data files exist and have non-zero
 /// size in the given directory. Any path that does not meet these criteria
 /// is fetched from IMDb. Other paths are left untouched.
@@ -33,7 +82,7 @@ pub fn download_all<P: AsRef<Path>>(dir: P) -> anyhow::Result<bool> {
     for dataset in &nonexistent {
         download_one(dir, dataset)?;
     }
-    Ok(nonexistent.len() > 0)
+    Ok(!nonexistent.is_empty())
 }
 
 /// Update will update all data set files, regardless of whether they already
@@ -56,15 +105,169 @@ fn download_one(outdir: &Path, dataset: &'static str) -> anyhow::Result<()> {
 
     let url = format!("{}/{}", IMDB_BASE_URL, dataset);
     log::info!("downloading {} to {}", url, outpath.display());
+    events::emit(&Event::Download { dataset, status: Status::Started });
     let resp = ureq::get(&url).call().context("HTTP error")?;
+    let last_modified = resp.header("Last-Modified").and_then(parse_http_date);
+    let total = resp.header("Content-Length").and_then(|n| n.parse().ok());
+    let mut rdr = GzDecoder::new(ProgressReader::new(
+        dataset,
+        total,
+        resp.into_reader(),
+    ));
     log::info!("sorting CSV records");
-    write_sorted_csv_records(
-        GzDecoder::new(resp.into_reader()),
-        &mut outfile,
-    )?;
+    write_sorted_csv_records(&mut rdr, &mut outfile)?;
+    rdr.into_inner().finish();
+    // The index records the modification time of the data as its snapshot
+    // date, so make it reflect when IMDb published the data rather than
+    // when we happened to download it.
+    if let Some(time) = last_modified {
+        outfile.set_modified(time)?;
+    }
+    events::emit(&Event::Download { dataset, status: Status::Finished });
     Ok(())
 }
 
+/// A reader that shows how much of a data set has been downloaded on stderr.
+struct ProgressReader<R> {
+    rdr: R,
+    dataset: &'static str,
+    total: Option<u64>,
+    read: u64,
+    start: Instant,
+    /// When bytes were last read. The final progress is shown after the
+    /// data set was sorted, which isn't part of the download.
+    last: Instant,
+    shown: Instant,
+}
+
+impl<R: io::Read> ProgressReader<R> {
+    /// How often the progress is shown.
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    fn new(
+        dataset: &'static str,
+        total: Option<u64>,
+        rdr: R,
+    ) -> ProgressReader<R> {
+        let start = Instant::now();
+        ProgressReader {
+            rdr,
+            dataset,
+            total,
+            read: 0,
+            start,
+            last: start,
+            shown: start,
+        }
+    }
+
+    /// Show the final progress, once the download is complete.
+    fn finish(&self) {
+        eprintln!("\r{}", self.line());
+    }
+
+    fn line(&self) -> String {
+        download_progress(
+            self.dataset,
+            self.read,
+            self.total,
+            self.last.duration_since(self.start),
+        )
+    }
+}
+
+impl<R: io::Read> io::Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.rdr.read(buf)?;
+        self.read += n as u64;
+        self.last = Instant::now();
+        if self.last.duration_since(self.shown) >= Self::INTERVAL {
+            self.shown = self.last;
+            eprint!("\r{}", self.line());
+        }
+        Ok(n)
+    }
+}
+
+/// Returns a line describing the progress of downloading the given data set,
+/// given the number of bytes downloaded so far and, if known, the size of
+/// the download.
+fn download_progress(
+    dataset: &str,
+    read: u64,
+    total: Option<u64>,
+    elapsed: Duration,
+) -> String {
+    let secs = elapsed.as_secs_f64();
+    match total {
+        Some(total) if total > 0 => format!(
+            "downloading {}: {} of {} ({}%, {:.1}s)",
+            dataset,
+            human_bytes(read),
+            human_bytes(total),
+            read.min(total) * 100 / total,
+            secs,
+        ),
+        _ => format!(
+            "downloading {}: {} ({:.1}s)",
+            dataset,
+            human_bytes(read),
+            secs
+        ),
+    }
+}
+
+/// Parse an HTTP date, e.g., `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// Only the preferred format from RFC 7231 is supported, since that's the
+/// only one servers may generate. `None` is returned for anything else.
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+        "Nov", "Dec",
+    ];
+
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time) = match fields[..] {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        _ => return None,
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let hms = time
+        .split(':')
+        .map(|n| n.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let (hour, min, sec) = match hms[..] {
+        [hour, min, sec] if hour < 24 && min < 60 && sec < 61 => {
+            (hour, min, sec)
+        }
+        _ => return None,
+    };
+    if year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    let secs = days * 86_400 + hour * 3600 + min * 60 + sec;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Returns the number of days since the Unix epoch for the given date, which
+/// must not precede the epoch.
+///
+/// This uses Howard Hinnant's `days_from_civil` algorithm. See:
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
 /// Gets a list of data sets that either don't exist in the current directory
 /// or have zero size.
 fn non_existent_data_sets(dir: &Path) -> anyhow::Result<Vec<&'static str>> {
@@ -128,9 +331,79 @@ fn write_sorted_csv_records<R: io::Read, W: io::Write>(
             continue;
         }
         prev = Some(first);
-        wtr.write_all(&line)?;
+        wtr.write_all(line)?;
         wtr.write_all(b"\n")?;
     }
     wtr.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use std::time::{Duration, SystemTime};
+
+    use super::{
+        download_progress, first_run_message, parse_http_date, DATA_SETS,
+    };
+
+    #[test]
+    fn first_run() {
+        let msg = first_run_message(
+            Path::new("/home/user/imdb"),
+            Path::new("/home/user/imdb/index"),
+        );
+        for dataset in DATA_SETS {
+            assert!(msg.contains(dataset), "missing {}", dataset);
+        }
+        assert!(msg.contains("https://datasets.imdbws.com"));
+        assert!(msg.contains("about 672 MB in total"));
+        assert!(msg.contains("\n    /home/user/imdb\n"));
+        assert!(msg.contains("\n    /home/user/imdb/index\n"));
+    }
+
+    #[test]
+    fn progress() {
+        let secs = Duration::from_millis(2500);
+        assert_eq!(
+            download_progress(
+                "title.basics.tsv.gz",
+                3 << 20,
+                Some(12 << 20),
+                secs
+            ),
+            "downloading title.basics.tsv.gz: 3.0 MiB of 12.0 MiB (25%, 2.5s)"
+        );
+        assert_eq!(
+            download_progress("title.basics.tsv.gz", 3 << 20, None, secs),
+            "downloading title.basics.tsv.gz: 3.0 MiB (2.5s)"
+        );
+        assert_eq!(
+            download_progress("title.basics.tsv.gz", 0, Some(0), secs),
+            "downloading title.basics.tsv.gz: 0 B (2.5s)"
+        );
+    }
+
+    #[test]
+    fn http_date() {
+        let at = |secs: u64| {
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        };
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), at(0));
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            at(784_111_777)
+        );
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 23:59:59 GMT"),
+            at(1_709_251_199)
+        );
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(parse_http_date(""), None);
+    }
+}
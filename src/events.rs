@@ -0,0 +1,274 @@
+// Events are a machine-readable account of what imdb-rename is doing, for
+// programs that wrap it, like GUIs. When enabled via --events, one JSON
+// object is written per line for each event, and every object has an
+// "event" key naming its kind:
+//
+//   download  {"dataset", "status": "started" | "finished"}
+//   index     {"dir", "status": "started" | "finished"}
+//   progress  {"what", "rows", "matches", "elapsed", "done"}
+//   prompt    {"batch", "results": [{"id", "kind", "title", "year", ...}]}
+//   confirm   {"question"}
+//   proposal  {"src", "dst", "action", "matched"}
+//   skipped   {"reason"}
+//   renamed   {"src", "dst", "action"}
+//   rollback  {"src", "dst", "action"}
+//   error     {"error", "code"}
+//
+// The "prompt" and "confirm" events are questions, and imdb-rename waits
+// for one line of JSON on stdin in reply to each of them. A prompt is
+// answered with {"answer": "choose", "choice": N}, where N is the 1-based
+// number of a result, or, when "batch" is true, {"answer": "skip"} to skip
+// the file or {"answer": "more"} to show more results. A confirmation is
+// answered with {"answer": "yes"} or {"answer": "no"}.
+//
+// Events are written to stdout unless a file is given, in which case the
+// human readable output is written to stdout as usual. When events are
+// written to stdout, human readable output moves to stderr.
+//
+// The library never writes events anywhere on its own. The events of
+// proposing and executing renames are sent to an `EventSink` given to it,
+// which the binary points at the process wide sink set up by `init`.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::explain::ResultTrace;
+use crate::rename::{MatchSummary, RenameAction};
+
+#[cfg(feature = "cli")]
+pub use self::global::{emit, enabled, human, init, read_reply, GlobalSink};
+#[cfg(not(feature = "cli"))]
+pub(crate) use self::global::{emit, enabled, human, read_reply};
+
+/// Something that events are sent to, e.g., by a `Renamer` or by
+/// `journal::execute_transaction`.
+pub trait EventSink: fmt::Debug + Send + Sync {
+    /// Handle the given event.
+    fn emit(&self, event: &Event);
+}
+
+/// An event, which is serialized as a single JSON object.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event<'a> {
+    /// A data set is being downloaded from IMDb.
+    Download { dataset: &'a str, status: Status },
+    /// The IMDb data is being indexed into the given directory.
+    Index { dir: &'a Path, status: Status },
+    /// A scan of every title is in progress. See `imdb_index::Progress`.
+    /// The time elapsed is in seconds.
+    Progress {
+        what: &'a str,
+        rows: usize,
+        matches: usize,
+        elapsed: f64,
+        done: bool,
+    },
+    /// The end user must choose among the given search results. When
+    /// `batch` is true, they may also skip the file or ask for more results.
+    Prompt { batch: bool, results: Vec<ResultTrace> },
+    /// The end user must answer the given yes/no question.
+    Confirm { question: &'a str },
+    /// A rename was proposed.
+    Proposal {
+        src: &'a Path,
+        dst: &'a Path,
+        action: RenameAction,
+        matched: &'a MatchSummary,
+    },
+    /// A file was skipped for the given reason.
+    Skipped { reason: &'a str },
+    /// A proposal was executed, along with everything it writes besides its
+    /// destination, like an NFO file.
+    Renamed { src: &'a Path, dst: &'a Path, action: RenameAction },
+    /// A proposal that was executed was reversed, because a later proposal
+    /// in the same transaction failed.
+    Rollback { src: &'a Path, dst: &'a Path, action: RenameAction },
+    /// imdb-rename failed. The code is the stable identifier of the
+    /// underlying index error, if the error came from the index.
+    Error { error: String, code: Option<&'a str> },
+}
+
+/// Whether a step has started or finished.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Started,
+    Finished,
+}
+
+/// A reply to a "prompt" or "confirm" event, read from stdin.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "answer", rename_all = "lowercase")]
+pub enum Reply {
+    /// Choose the result with the given 1-based number.
+    Choose { choice: usize },
+    /// Skip the file being renamed.
+    Skip,
+    /// Show more results for the same search.
+    More,
+    /// Answer yes to a confirmation.
+    Yes,
+    /// Answer no to a confirmation.
+    No,
+}
+
+/// The process wide sink that the imdb-rename binary writes events to, and
+/// reads the replies to its questions from.
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod global {
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use lazy_static::lazy_static;
+
+    use super::{Event, EventSink, Reply};
+
+    lazy_static! {
+        static ref SINK: Mutex<Option<Sink>> = Mutex::new(None);
+    }
+
+    /// Where events are written.
+    struct Sink {
+        wtr: Box<dyn Write + Send>,
+        /// Whether events are written to stdout, which moves human readable
+        /// output to stderr.
+        stdout: bool,
+    }
+
+    /// An event sink that writes to the process wide sink. See `init`.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct GlobalSink;
+
+    impl EventSink for GlobalSink {
+        fn emit(&self, event: &Event) {
+            emit(event)
+        }
+    }
+
+    /// Start writing events to the given file, or to stdout if no file is
+    /// given.
+    pub fn init(path: Option<&Path>) -> anyhow::Result<()> {
+        let sink = match path {
+            None => Sink { wtr: Box::new(io::stdout()), stdout: true },
+            Some(path) => {
+                let file = File::create(path).map_err(|e| {
+                    anyhow::anyhow!(
+                        "error creating events file '{}': {}",
+                        path.display(),
+                        e,
+                    )
+                })?;
+                Sink { wtr: Box::new(file), stdout: false }
+            }
+        };
+        *SINK.lock().unwrap() = Some(sink);
+        Ok(())
+    }
+
+    /// Returns true if and only if events are being written.
+    pub fn enabled() -> bool {
+        SINK.lock().unwrap().is_some()
+    }
+
+    /// Write the given event, if events are being written.
+    ///
+    /// Failing to write an event isn't an error. A consumer that went away
+    /// won't answer any more prompts either, which is reported when reading
+    /// its reply.
+    pub fn emit(event: &Event) {
+        let mut sink = SINK.lock().unwrap();
+        let Some(ref mut sink) = *sink else { return };
+        let line = match serde_json::to_string(event) {
+            Ok(line) => line,
+            Err(err) => {
+                log::debug!("could not serialize event {:?}: {}", event, err);
+                return;
+            }
+        };
+        let _ = writeln!(sink.wtr, "{}", line).and_then(|()| sink.wtr.flush());
+    }
+
+    /// Return a writer for human readable output. This is stdout, unless events
+    /// are being written to stdout, in which case it's stderr.
+    pub fn human() -> Box<dyn Write> {
+        match *SINK.lock().unwrap() {
+            Some(Sink { stdout: true, .. }) => Box::new(io::stderr()),
+            _ => Box::new(io::stdout()),
+        }
+    }
+
+    /// Read a reply to a question from stdin.
+    pub fn read_reply() -> anyhow::Result<Reply> {
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            anyhow::bail!(
+                "expected a reply on stdin, but reached end of input"
+            );
+        }
+        parse_reply(&line)
+    }
+
+    /// Parse a single reply.
+    pub(super) fn parse_reply(line: &str) -> anyhow::Result<Reply> {
+        serde_json::from_str(line.trim()).map_err(|e| {
+            anyhow::anyhow!("invalid reply {:?}: {}", line.trim(), e)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::global::parse_reply;
+    use super::{Event, Reply, Status};
+    use crate::rename::RenameAction;
+
+    #[test]
+    fn events() {
+        let json = |event: &Event| serde_json::to_string(event).unwrap();
+        assert_eq!(
+            json(&Event::Download {
+                dataset: "title.basics.tsv.gz",
+                status: Status::Started,
+            }),
+            r#"{"event":"download","dataset":"title.basics.tsv.gz","status":"started"}"#,
+        );
+        assert_eq!(
+            json(&Event::Renamed {
+                src: Path::new("dune.mkv"),
+                dst: Path::new("Dune (1984).mkv"),
+                action: RenameAction::Rename,
+            }),
+            r#"{"event":"renamed","src":"dune.mkv","dst":"Dune (1984).mkv","action":"rename"}"#,
+        );
+        assert_eq!(
+            json(&Event::Progress {
+                what: "titles",
+                rows: 10,
+                matches: 2,
+                elapsed: 1.5,
+                done: false,
+            }),
+            r#"{"event":"progress","what":"titles","rows":10,"matches":2,"elapsed":1.5,"done":false}"#,
+        );
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(
+            parse_reply("{\"answer\": \"choose\", \"choice\": 2}\n").unwrap(),
+            Reply::Choose { choice: 2 }
+        );
+        assert_eq!(parse_reply(r#"{"answer":"skip"}"#).unwrap(), Reply::Skip);
+        assert_eq!(parse_reply(r#"{"answer":"yes"}"#).unwrap(), Reply::Yes);
+        assert!(parse_reply(r#"{"answer":"maybe"}"#).is_err());
+        assert!(parse_reply(r#"{"answer":"choose"}"#).is_err());
+        assert!(parse_reply("2").is_err());
+    }
+}
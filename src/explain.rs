@@ -0,0 +1,255 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use imdb_index::{MediaEntity, Query, Scored, SearchDiagnostics};
+use serde::Serialize;
+
+/// The maximum number of search results shown per search in a trace.
+const MAX_RESULTS: usize = 5;
+
+/// A trace of every decision made while proposing a rename for one path.
+///
+/// Traces are only collected when explicitly enabled on the renamer, and are
+/// intended to answer the question "why was this file renamed to that?"
+#[derive(Clone, Debug, Serialize)]
+pub struct Explanation {
+    /// The path given on the command line.
+    pub path: PathBuf,
+    /// How the path was interpreted, if it could be parsed at all.
+    pub parse: Option<Parse>,
+    /// Every search executed on behalf of this path, in order.
+    pub searches: Vec<SearchTrace>,
+    /// What ultimately happened to this path.
+    pub outcome: Outcome,
+}
+
+/// The structured interpretation of a path.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Parse {
+    /// A movie (or anything else that isn't an episode).
+    Any { title: String, year: u32 },
+    /// A TV episode.
+    Episode {
+        tvshow_title: String,
+        season: u32,
+        episode: u32,
+        episode_title: String,
+    },
+    /// Nothing useful could be extracted from the path.
+    Unknown,
+}
+
+/// A single search and the decision made from its results.
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchTrace {
+    /// What the search was looking for, e.g., "tvshow for episode."
+    pub purpose: String,
+    /// The query in its textual syntax, if a query was executed.
+    pub query: Option<String>,
+    /// The top results of the search.
+    pub results: Vec<ResultTrace>,
+    /// The total number of results, which may exceed those shown.
+    pub total: usize,
+    /// The IMDb identifier of the chosen entity, if one was chosen.
+    pub chosen: Option<String>,
+    /// Why the chosen entity was chosen.
+    pub reason: String,
+    /// How the search arrived at its results, if a query was executed.
+    pub diagnostics: Option<SearchDiagnostics>,
+}
+
+/// A single scored search result.
+#[derive(Clone, Debug, Serialize)]
+pub struct ResultTrace {
+    pub id: String,
+    pub kind: String,
+    pub title: String,
+    pub year: Option<u32>,
+    pub score: f64,
+    /// The number of distinct query terms this result matched, for searches
+    /// by name.
+    pub matched_terms: Option<usize>,
+    /// The number of distinct terms in the query, for searches by name.
+    pub query_terms: Option<usize>,
+}
+
+/// The final result of proposing a rename for a path.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Outcome {
+    /// A rename was proposed to the given destination.
+    Proposed { dst: PathBuf },
+    /// The path was skipped for the given reason.
+    Skipped { reason: String },
+}
+
+impl ResultTrace {
+    /// Describe the given search result.
+    pub fn new(result: &Scored<MediaEntity>) -> ResultTrace {
+        let t = result.value().title();
+        ResultTrace {
+            id: t.id.clone(),
+            kind: t.kind.to_string(),
+            title: t.title.clone(),
+            year: t.start_year,
+            score: result.score(),
+            matched_terms: result.matched_terms(),
+            query_terms: result.query_terms(),
+        }
+    }
+}
+
+impl Explanation {
+    /// Create a new explanation for the given path. Its outcome is initially
+    /// a skip, since every path that isn't explicitly proposed is skipped.
+    pub fn new(path: PathBuf) -> Explanation {
+        Explanation {
+            path,
+            parse: None,
+            searches: vec![],
+            outcome: Outcome::Skipped { reason: "no proposal".to_string() },
+        }
+    }
+
+    /// Write this explanation as an indented human readable block.
+    pub fn write_text<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        writeln!(wtr, "{}", self.path.display())?;
+        match self.parse {
+            None => writeln!(wtr, "  parsed: (failed)")?,
+            Some(Parse::Any { ref title, year }) => {
+                writeln!(wtr, "  parsed: title {:?}, year {}", title, year)?;
+            }
+            Some(Parse::Episode {
+                ref tvshow_title,
+                season,
+                episode,
+                ref episode_title,
+            }) => {
+                write!(
+                    wtr,
+                    "  parsed: episode S{:02}E{:02} of {:?}",
+                    season, episode, tvshow_title
+                )?;
+                if !episode_title.is_empty() {
+                    write!(wtr, ", episode title {:?}", episode_title)?;
+                }
+                writeln!(wtr)?;
+            }
+            Some(Parse::Unknown) => writeln!(wtr, "  parsed: (unknown)")?,
+        }
+        for search in &self.searches {
+            writeln!(wtr, "  search ({}):", search.purpose)?;
+            if let Some(ref query) = search.query {
+                writeln!(wtr, "    query: {}", query)?;
+            }
+            if let Some(ref diag) = search.diagnostics {
+                writeln!(wtr, "    diagnostics: {}", diag)?;
+            }
+            for (i, r) in search.results.iter().enumerate() {
+                write!(
+                    wtr,
+                    "    {}. {:.3} {} {} {}",
+                    i + 1,
+                    r.score,
+                    r.id,
+                    r.kind,
+                    r.title
+                )?;
+                if let Some(year) = r.year {
+                    write!(wtr, " ({})", year)?;
+                }
+                if let (Some(matched), Some(query)) =
+                    (r.matched_terms, r.query_terms)
+                {
+                    write!(wtr, " [{} of {} terms]", matched, query)?;
+                }
+                writeln!(wtr)?;
+            }
+            if search.total > search.results.len() {
+                writeln!(
+                    wtr,
+                    "    ... and {} more",
+                    search.total - search.results.len()
+                )?;
+            }
+            match search.chosen {
+                None => writeln!(wtr, "    decision: {}", search.reason)?,
+                Some(ref id) => {
+                    writeln!(wtr, "    decision: {} ({})", id, search.reason)?
+                }
+            }
+        }
+        match self.outcome {
+            Outcome::Proposed { ref dst } => {
+                writeln!(wtr, "  outcome: rename to {}", dst.display())?
+            }
+            Outcome::Skipped { ref reason } => {
+                writeln!(wtr, "  outcome: skipped: {}", reason)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SearchTrace {
+    /// Create a trace of a search that was resolved without consulting the
+    /// index, e.g., because of an override.
+    pub fn resolved(purpose: &str, chosen: &str, reason: &str) -> SearchTrace {
+        SearchTrace {
+            purpose: purpose.to_string(),
+            query: None,
+            results: vec![],
+            total: 0,
+            chosen: Some(chosen.to_string()),
+            reason: reason.to_string(),
+            diagnostics: None,
+        }
+    }
+
+    /// Create a trace of the given query and its results. The chosen entity
+    /// and the reason for choosing it are initially absent.
+    pub fn new(
+        purpose: &str,
+        query: Option<&Query>,
+        results: &[Scored<MediaEntity>],
+    ) -> SearchTrace {
+        SearchTrace {
+            purpose: purpose.to_string(),
+            query: query.map(|q| q.to_string()),
+            results: results
+                .iter()
+                .take(MAX_RESULTS)
+                .map(ResultTrace::new)
+                .collect(),
+            total: results.len(),
+            chosen: None,
+            reason: String::new(),
+            diagnostics: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let mut exp = Explanation::new(PathBuf::from("foo.s01e02.mkv"));
+        exp.parse = Some(Parse::Episode {
+            tvshow_title: "foo".to_string(),
+            season: 1,
+            episode: 2,
+            episode_title: String::new(),
+        });
+        exp.outcome = Outcome::Skipped { reason: "no results".to_string() };
+        let mut buf = vec![];
+        exp.write_text(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "foo.s01e02.mkv\n  parsed: episode S01E02 of \"foo\"\n  \
+             outcome: skipped: no results\n"
+        );
+    }
+}
@@ -0,0 +1,556 @@
+// This module validates rename proposals against the capabilities of the file
+// system that they will be written to. For example, FAT and exFAT file systems
+// reject names containing characters like `:` or `?`, which are perfectly
+// legal in IMDb titles. If we don't check for this up front, then a batch of
+// renames can fail half way through, which leaves the user's files in a
+// confusing state.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::rename::RenameProposal;
+
+/// The characters that are rejected by Windows file systems (and FAT/exFAT
+/// everywhere), in addition to ASCII control characters.
+const WINDOWS_FORBIDDEN: &[char] =
+    &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Base names that are reserved by Windows file systems, regardless of
+/// extension.
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5",
+    "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5",
+    "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The constraints that a file system places on the names of its entries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FsConstraints {
+    /// A human readable name of the file system, used in messages.
+    pub name: String,
+    /// The set of characters permitted in a single path component.
+    pub charset: Charset,
+    /// The maximum length of a single path component, in units of
+    /// `len_unit`.
+    pub max_name_len: usize,
+    /// The unit that `max_name_len` is expressed in.
+    pub len_unit: LenUnit,
+    /// Whether names that differ only in case refer to distinct entries.
+    pub case_sensitive: bool,
+}
+
+/// The set of characters that a file system permits in names.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Charset {
+    /// Anything except for `/` and `NUL`.
+    Posix,
+    /// The rules used by Windows file systems (and FAT/exFAT on any
+    /// platform): no control characters, none of `<>:"/\|?*`, no trailing
+    /// dots or spaces and no reserved device names like `CON`.
+    Windows,
+}
+
+/// The unit used to measure the length of a path component.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LenUnit {
+    /// UTF-8 code units.
+    Bytes,
+    /// UTF-16 code units.
+    Utf16,
+}
+
+impl FsConstraints {
+    /// Constraints of a typical Unix file system, such as ext4.
+    pub fn posix(name: &str, max_name_len: usize) -> FsConstraints {
+        FsConstraints {
+            name: name.to_string(),
+            charset: Charset::Posix,
+            max_name_len,
+            len_unit: LenUnit::Bytes,
+            case_sensitive: true,
+        }
+    }
+
+    /// Constraints of a Windows-like file system, such as FAT, exFAT or
+    /// NTFS.
+    pub fn windows(name: &str) -> FsConstraints {
+        FsConstraints {
+            name: name.to_string(),
+            charset: Charset::Windows,
+            max_name_len: 255,
+            len_unit: LenUnit::Utf16,
+            case_sensitive: false,
+        }
+    }
+
+    /// Returns the length of the given name in the units used by this file
+    /// system.
+    fn name_len(&self, name: &str) -> usize {
+        match self.len_unit {
+            LenUnit::Bytes => name.len(),
+            LenUnit::Utf16 => name.encode_utf16().count(),
+        }
+    }
+
+    /// Returns true if and only if the given character is not permitted in
+    /// a name on this file system.
+    fn is_forbidden(&self, c: char) -> bool {
+        match self.charset {
+            Charset::Posix => c == '/' || c == '\x00',
+            Charset::Windows => c < '\x20' || WINDOWS_FORBIDDEN.contains(&c),
+        }
+    }
+
+    /// Returns true if and only if the given name is reserved on this file
+    /// system.
+    fn is_reserved(&self, name: &str) -> bool {
+        if self.charset != Charset::Windows {
+            return false;
+        }
+        let stem = name.split('.').next().unwrap_or(name);
+        WINDOWS_RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem))
+    }
+
+    /// Returns every problem with the given name on this file system. Case
+    /// collisions are not detected here, since they depend on other names.
+    fn problems(&self, name: &str) -> Vec<Problem> {
+        let mut problems = vec![];
+        let mut forbidden: Vec<char> = vec![];
+        for c in name.chars().filter(|&c| self.is_forbidden(c)) {
+            if !forbidden.contains(&c) {
+                forbidden.push(c);
+            }
+        }
+        if !forbidden.is_empty() {
+            problems.push(Problem::ForbiddenChars(forbidden));
+        }
+        if self.charset == Charset::Windows
+            && (name.ends_with('.') || name.ends_with(' '))
+        {
+            problems.push(Problem::TrailingDotOrSpace);
+        }
+        if self.is_reserved(name) {
+            problems.push(Problem::Reserved);
+        }
+        let len = self.name_len(name);
+        if len > self.max_name_len {
+            problems.push(Problem::TooLong { len, max: self.max_name_len });
+        }
+        problems
+    }
+
+    /// Rewrite the given name such that it is valid on this file system.
+    ///
+    /// Forbidden characters are replaced with `_`, trailing dots and spaces
+    /// are removed, reserved names get a `_` suffix and names that are too
+    /// long are truncated while preserving their extension.
+    pub fn sanitize(&self, name: &str) -> String {
+        let mut name: String = name
+            .chars()
+            .map(|c| if self.is_forbidden(c) { '_' } else { c })
+            .collect();
+        if self.charset == Charset::Windows {
+            let trimmed = name.trim_end_matches(['.', ' ']);
+            name = if trimmed.is_empty() {
+                "_".to_string()
+            } else {
+                trimmed.to_string()
+            };
+        }
+        if self.is_reserved(&name) {
+            name = match name.find('.') {
+                None => format!("{}_", name),
+                Some(i) => format!("{}_{}", &name[..i], &name[i..]),
+            };
+        }
+        if self.name_len(&name) > self.max_name_len {
+            let (stem, ext) = match name.rfind('.') {
+                Some(i) if i > 0 => (&name[..i], &name[i..]),
+                _ => (&*name, ""),
+            };
+            let mut truncated = String::new();
+            for c in stem.chars() {
+                let next = format!("{}{}{}", truncated, c, ext);
+                if self.name_len(&next) > self.max_name_len {
+                    break;
+                }
+                truncated.push(c);
+            }
+            name = format!("{}{}", truncated.trim_end(), ext);
+        }
+        name
+    }
+}
+
+/// A single reason why a name is not valid on its destination file system.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Problem {
+    /// The name contains characters that the file system rejects.
+    ForbiddenChars(Vec<char>),
+    /// The name ends with a dot or a space, which some file systems strip
+    /// silently or reject.
+    TrailingDotOrSpace,
+    /// The name is reserved by the file system.
+    Reserved,
+    /// The name exceeds the maximum component length.
+    TooLong {
+        /// The length of the name.
+        len: usize,
+        /// The maximum length permitted.
+        max: usize,
+    },
+    /// The name is equivalent to another destination in the same directory
+    /// on a case insensitive file system.
+    CaseCollision(PathBuf),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::ForbiddenChars(ref chars) => {
+                write!(f, "contains forbidden characters {:?}", chars)
+            }
+            Problem::TrailingDotOrSpace => {
+                write!(f, "ends with a dot or a space")
+            }
+            Problem::Reserved => write!(f, "is a reserved name"),
+            Problem::TooLong { len, max } => {
+                write!(f, "is too long ({} > {})", len, max)
+            }
+            Problem::CaseCollision(ref other) => write!(
+                f,
+                "collides with '{}' on a case insensitive file system",
+                other.display()
+            ),
+        }
+    }
+}
+
+/// A problem found with the destination of a single rename proposal.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+    /// The destination path that has the problem.
+    pub dst: PathBuf,
+    /// The name of the file system that the destination is on.
+    pub fs_name: String,
+    /// The problem itself.
+    pub problem: Problem,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' {} (destination file system: {})",
+            self.dst.display(),
+            self.problem,
+            self.fs_name
+        )
+    }
+}
+
+/// A probe determines the constraints of the file system containing a
+/// directory.
+///
+/// This is a trait so that tests can substitute a fake file system.
+pub trait Probe {
+    /// Return the constraints for the file system containing `dir`. `dir`
+    /// is guaranteed to exist.
+    fn probe(&self, dir: &Path) -> anyhow::Result<FsConstraints>;
+}
+
+/// A probe that asks the operating system about the file system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemProbe;
+
+impl Probe for SystemProbe {
+    #[cfg(target_os = "linux")]
+    fn probe(&self, dir: &Path) -> anyhow::Result<FsConstraints> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        // Magic numbers from linux/magic.h and the respective drivers.
+        const MSDOS_SUPER_MAGIC: i64 = 0x4d44;
+        const EXFAT_SUPER_MAGIC: i64 = 0x2011BAB0;
+        const NTFS_SB_MAGIC: i64 = 0x5346544e;
+        const NTFS3_SUPER_MAGIC: i64 = 0x7366746e;
+        const SMB_SUPER_MAGIC: i64 = 0x517B;
+        const CIFS_SUPER_MAGIC: i64 = 0xFF534D42;
+        const SMB2_SUPER_MAGIC: i64 = 0xFE534D42;
+
+        let cpath = CString::new(dir.as_os_str().as_bytes())?;
+        let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+        // SAFETY: cpath is a valid NUL terminated string and buf is a valid
+        // statfs struct for the kernel to write into.
+        if unsafe { libc::statfs(cpath.as_ptr(), &mut buf) } != 0 {
+            let err = std::io::Error::last_os_error();
+            anyhow::bail!("statfs on '{}' failed: {}", dir.display(), err);
+        }
+        // The width and signedness of f_type varies by architecture.
+        #[allow(clippy::unnecessary_cast)]
+        let fstype = (buf.f_type as i64) & 0xFFFF_FFFF;
+        Ok(match fstype {
+            MSDOS_SUPER_MAGIC => FsConstraints::windows("FAT"),
+            EXFAT_SUPER_MAGIC => FsConstraints::windows("exFAT"),
+            NTFS_SB_MAGIC | NTFS3_SUPER_MAGIC => {
+                FsConstraints::windows("NTFS")
+            }
+            SMB_SUPER_MAGIC | CIFS_SUPER_MAGIC | SMB2_SUPER_MAGIC => {
+                FsConstraints::windows("SMB")
+            }
+            _ => {
+                let max = if buf.f_namelen > 0 {
+                    buf.f_namelen as usize
+                } else {
+                    255
+                };
+                FsConstraints::posix(&format!("{:#x}", fstype), max)
+            }
+        })
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn probe(&self, dir: &Path) -> anyhow::Result<FsConstraints> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let cpath = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: cpath is a valid NUL terminated string.
+        let max =
+            unsafe { libc::pathconf(cpath.as_ptr(), libc::_PC_NAME_MAX) };
+        let max = if max > 0 { max as usize } else { 255 };
+        let mut constraints = FsConstraints::posix("unix", max);
+        // The default file systems on macOS (APFS and HFS+) are case
+        // insensitive unless explicitly formatted otherwise.
+        if cfg!(target_os = "macos") {
+            constraints.case_sensitive = false;
+        }
+        Ok(constraints)
+    }
+
+    #[cfg(windows)]
+    fn probe(&self, _: &Path) -> anyhow::Result<FsConstraints> {
+        // Every file system on Windows is subject to the same naming rules,
+        // so there is no need to ask which one we have.
+        Ok(FsConstraints::windows("Windows"))
+    }
+}
+
+/// Validates rename proposals against their destination file systems.
+///
+/// Probing results are cached per destination directory.
+#[derive(Debug)]
+pub struct Validator<P> {
+    probe: P,
+    cache: HashMap<PathBuf, FsConstraints>,
+}
+
+impl<P: Probe> Validator<P> {
+    /// Create a new validator that uses the given probe.
+    pub fn new(probe: P) -> Validator<P> {
+        Validator { probe, cache: HashMap::new() }
+    }
+
+    /// Check every proposal and return all violations found.
+    ///
+    /// This returns an error only if probing a file system failed.
+    pub fn check(
+        &mut self,
+        proposals: &[RenameProposal],
+    ) -> anyhow::Result<Vec<Violation>> {
+        let mut violations = vec![];
+        // Maps (parent, folded name) to the first destination seen, for
+        // detecting collisions on case insensitive file systems.
+        let mut folded: HashMap<(PathBuf, String), PathBuf> = HashMap::new();
+        for p in proposals {
+            let (parent, name) = match split_dst(p.dst()) {
+                None => continue,
+                Some(split) => split,
+            };
+            let constraints = self.constraints(&parent)?;
+            for problem in constraints.problems(&name) {
+                violations.push(Violation {
+                    dst: p.dst().to_path_buf(),
+                    fs_name: constraints.name.clone(),
+                    problem,
+                });
+            }
+            if constraints.case_sensitive {
+                continue;
+            }
+            let key = (parent, name.to_lowercase());
+            if let Some(other) = folded.get(&key) {
+                violations.push(Violation {
+                    dst: p.dst().to_path_buf(),
+                    fs_name: constraints.name.clone(),
+                    problem: Problem::CaseCollision(other.clone()),
+                });
+            } else {
+                folded.insert(key, p.dst().to_path_buf());
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Rewrite the destination name of every proposal such that it is valid
+    /// on its destination file system. Returns the number of proposals that
+    /// were changed.
+    ///
+    /// Note that this cannot fix case collisions.
+    pub fn sanitize(
+        &mut self,
+        proposals: &mut [RenameProposal],
+    ) -> anyhow::Result<usize> {
+        let mut changed = 0;
+        for p in proposals {
+            let (parent, name) = match split_dst(p.dst()) {
+                None => continue,
+                Some(split) => split,
+            };
+            let sanitized = self.constraints(&parent)?.sanitize(&name);
+            if sanitized != name {
+                p.set_dst_name(&sanitized);
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Return the constraints for the file system that `dir` will be on.
+    ///
+    /// If `dir` doesn't exist yet, then the nearest existing ancestor is
+    /// probed instead.
+    fn constraints(&mut self, dir: &Path) -> anyhow::Result<FsConstraints> {
+        if let Some(c) = self.cache.get(dir) {
+            return Ok(c.clone());
+        }
+        let mut existing = dir;
+        while !existing.as_os_str().is_empty() && !existing.is_dir() {
+            existing = match existing.parent() {
+                None => break,
+                Some(parent) => parent,
+            };
+        }
+        if existing.as_os_str().is_empty() {
+            existing = Path::new(".");
+        }
+        let c = self.probe.probe(existing)?;
+        log::debug!("file system for {}: {:?}", dir.display(), c);
+        self.cache.insert(dir.to_path_buf(), c.clone());
+        Ok(c)
+    }
+}
+
+/// Split a destination path into its parent directory and its name.
+fn split_dst(dst: &Path) -> Option<(PathBuf, String)> {
+    let name = dst.file_name()?.to_string_lossy().into_owned();
+    let parent = dst.parent().unwrap_or(Path::new("")).to_path_buf();
+    Some((parent, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rename::RenameAction;
+    use crate::util::tests::entity;
+
+    /// A probe that reports the same constraints for every directory.
+    struct FakeProbe(FsConstraints);
+
+    impl Probe for FakeProbe {
+        fn probe(&self, _: &Path) -> anyhow::Result<FsConstraints> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn proposal(dst: &str) -> RenameProposal {
+        let dst = Path::new(dst);
+        RenameProposal::new(
+            PathBuf::from("src.mkv"),
+            dst.parent().unwrap(),
+            dst.file_name().unwrap().to_str().unwrap(),
+            RenameAction::Rename,
+            entity(),
+        )
+    }
+
+    fn problems(c: FsConstraints, dsts: &[&str]) -> Vec<Problem> {
+        let proposals: Vec<_> = dsts.iter().map(|d| proposal(d)).collect();
+        let mut validator = Validator::new(FakeProbe(c));
+        let violations = validator.check(&proposals).unwrap();
+        violations.into_iter().map(|v| v.problem).collect()
+    }
+
+    #[test]
+    fn posix_accepts_titles() {
+        let c = FsConstraints::posix("ext4", 255);
+        let got = problems(c, &["/media/Thor: Ragnarok (2017).mkv"]);
+        assert_eq!(got, vec![]);
+    }
+
+    #[test]
+    fn fat_rejects_chars() {
+        let c = FsConstraints::windows("FAT");
+        let got = problems(c, &["/media/Thor: Ragnarok? (2017).mkv"]);
+        assert_eq!(got, vec![Problem::ForbiddenChars(vec![':', '?'])]);
+    }
+
+    #[test]
+    fn fat_rejects_trailing_dot_and_reserved() {
+        let c = FsConstraints::windows("FAT");
+        let got = problems(c, &["/media/S.W.A.T.", "/media/con.mkv"]);
+        assert_eq!(got, vec![Problem::TrailingDotOrSpace, Problem::Reserved]);
+    }
+
+    #[test]
+    fn too_long() {
+        let c = FsConstraints::posix("ext4", 10);
+        let got = problems(c, &["/media/abcdefghijk"]);
+        assert_eq!(got, vec![Problem::TooLong { len: 11, max: 10 }]);
+    }
+
+    #[test]
+    fn case_collision() {
+        let c = FsConstraints::windows("exFAT");
+        let got =
+            problems(c, &["/media/Up (2009).mkv", "/media/UP (2009).mkv"]);
+        assert_eq!(
+            got,
+            vec![Problem::CaseCollision(PathBuf::from(
+                "/media/Up (2009).mkv"
+            ))]
+        );
+
+        let c = FsConstraints::posix("ext4", 255);
+        let got =
+            problems(c, &["/media/Up (2009).mkv", "/media/UP (2009).mkv"]);
+        assert_eq!(got, vec![]);
+    }
+
+    #[test]
+    fn sanitize() {
+        let c = FsConstraints::windows("FAT");
+        assert_eq!(
+            c.sanitize("Thor: Ragnarok (2017).mkv"),
+            "Thor_ Ragnarok (2017).mkv"
+        );
+        assert_eq!(c.sanitize("S.W.A.T. "), "S.W.A.T");
+        assert_eq!(c.sanitize("aux.srt"), "aux_.srt");
+
+        let c = FsConstraints::posix("ext4", 10);
+        assert_eq!(c.sanitize("abcdefghij.mkv"), "abcdef.mkv");
+        assert_eq!(c.sanitize("abcdefghijk"), "abcdefghij");
+    }
+
+    #[test]
+    fn sanitize_proposals() {
+        let mut proposals =
+            vec![proposal("/media/What If...?"), proposal("/media/ok")];
+        let mut validator =
+            Validator::new(FakeProbe(FsConstraints::windows("FAT")));
+        assert_eq!(validator.sanitize(&mut proposals).unwrap(), 1);
+        assert_eq!(proposals[0].dst(), Path::new("/media/What If..._"));
+        assert!(validator.check(&proposals).unwrap().is_empty());
+    }
+}
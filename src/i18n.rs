@@ -0,0 +1,477 @@
+// A tiny message catalog for the prompts, summaries and skip reasons shown to
+// the end user. Every such message is looked up by its id with `tr` (or
+// `trf`, for messages with arguments), which makes untranslated messages
+// easy to find: they're the ones that don't go through the catalog.
+//
+// Messages may contain positional placeholders, `{0}`, `{1}` and so on,
+// which `trf` replaces with its arguments. The tags in brackets that start
+// some lines, like `[summary]`, are the same in every language, so that
+// scripts can rely on them.
+//
+// The language is chosen once at startup via --lang, or otherwise from the
+// environment (LC_ALL, LC_MESSAGES and then LANG). English is the default,
+// and also the fallback for any message missing from another language.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+/// A language that messages are available in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Lang {
+    English,
+    German,
+}
+
+/// Every language shipped, with English first.
+pub const LANGS: &[Lang] = &[Lang::English, Lang::German];
+
+const ENGLISH: &[(&str, &str)] = &[
+    ("answer.yes", "y,yes"),
+    ("prompt.download", "Download the IMDb data now? (y/n) "),
+    (
+        "prompt.confirm-rename",
+        "Are you sure you want to rename the above files? (y/n) ",
+    ),
+    (
+        "prompt.confirm-symlink",
+        "Are you sure you want to symlink the above files? (y/n) ",
+    ),
+    (
+        "prompt.confirm-hardlink",
+        "Are you sure you want to hardlink the above files? (y/n) ",
+    ),
+    (
+        "prompt.confirm-copy",
+        "Are you sure you want to copy the above files? (y/n) ",
+    ),
+    (
+        "prompt.confirm-reflink",
+        "Are you sure you want to reflink the above files? (y/n) ",
+    ),
+    (
+        "prompt.apply-plan",
+        "Are you sure you want to apply the above plan? (y/n) ",
+    ),
+    (
+        "prompt.sanitize",
+        "Some destinations are invalid. Sanitize their names? (y/n) ",
+    ),
+    (
+        "prompt.choice",
+        "Please enter your choice [{0}-{1}] (o <n> opens its IMDb page, \
+         u prints all IMDb pages): ",
+    ),
+    (
+        "prompt.choice-batch",
+        "Please enter your choice [{0}-{1}] (o <n> opens its IMDb page, \
+         u prints all IMDb pages, r shows more results, s skips this file): ",
+    ),
+    ("summary.excluded-one", "[summary] excluded {0} entry by --only {1}"),
+    ("summary.excluded-other", "[summary] excluded {0} entries by --only {1}"),
+    (
+        "summary.skipped",
+        "[summary] skipped {0} file(s), see the [skipping] lines above",
+    ),
+    (
+        "plan.written",
+        "[plan] wrote {0} proposal(s) to {1}, run with --apply-plan to \
+         execute them",
+    ),
+    ("plan.done", "[plan] every proposal in {0} is already done"),
+    (
+        "skip.not-companion",
+        "not a companion of any video file (use --all-files to rename anyway)",
+    ),
+    ("skip.not-video", "not a video file (use --all-files to rename anyway)"),
+    ("skip.in-use", "{0} appears to be in use: {1}"),
+    ("skip.unparsable", "could not parse file path: {0}"),
+    ("skip.prompted", "{0}: skipped when prompted"),
+    ("skip.search-error", "error searching for {0}: {1}"),
+    ("skip.missing-show", "{0}: parent show {1} of {2} missing from dataset"),
+    ("skip.show-error", "error finding TV show for {0}: {1}"),
+    ("skip.absolute", "error making {0} an absolute path: {1}"),
+    ("skip.cwd", "error getting current directory: {0}"),
+    (
+        "skip.undecided",
+        "no search result stands out, and prompting is disabled",
+    ),
+    (
+        "error.undecided",
+        "skipped {0} file(s) that need a choice among search results, run \
+         without --yes to choose",
+    ),
+    (
+        "error.query-undecided",
+        "no result for the query stands out, and --yes never prompts; refine \
+         the query or run without --yes",
+    ),
+    (
+        "prompt.cross-device",
+        "{0} proposal(s) will copy {1} to other file systems and then delete \
+         the originals. Continue? (y/n) ",
+    ),
+    ("note.cross-device", "will copy across file systems, {0}"),
+    (
+        "error.cross-device",
+        "{0} proposal(s) would copy {1} to other file systems, pass \
+         --allow-cross-device to allow it",
+    ),
+    ("search.no-results", "[no results] {0}"),
+    ("proposal.unchecked", "[unchecked] {0}"),
+    ("error.no-files", "no files to rename"),
+    (
+        "error.invalid-destinations",
+        "refusing to rename to invalid destinations",
+    ),
+    (
+        "warning.nfo-exists",
+        "NFO file '{0}' already exists, pass --force-nfo to replace it",
+    ),
+    ("undo.renamed", "[undo] {0} -> {1}"),
+    ("undo.removed", "[undo] removed {0} {1}"),
+    ("undo.skipped", "[skip] {0}"),
+    (
+        "error.undo-incomplete",
+        "undid {0} of {1} renames, run --undo again to retry the rest once \
+         their sources are free",
+    ),
+    (
+        "stats.matched",
+        "matched:\t{0} ({1} automatic, {2} prompted, {3} override)",
+    ),
+    ("stats.skipped", "skipped:\t{0} ({1} undecided)"),
+    ("stats.scores", "scores:"),
+    ("stats.unscored", "none"),
+    ("stats.patterns", "patterns:"),
+];
+
+const GERMAN: &[(&str, &str)] = &[
+    ("answer.yes", "j,ja,y,yes"),
+    ("prompt.download", "Die IMDb-Daten jetzt herunterladen? (j/n) "),
+    (
+        "prompt.confirm-rename",
+        "Sollen die obigen Dateien wirklich umbenannt werden? (j/n) ",
+    ),
+    (
+        "prompt.confirm-symlink",
+        "Sollen für die obigen Dateien wirklich symbolische Links angelegt \
+         werden? (j/n) ",
+    ),
+    (
+        "prompt.confirm-hardlink",
+        "Sollen für die obigen Dateien wirklich harte Links angelegt werden? \
+         (j/n) ",
+    ),
+    (
+        "prompt.confirm-copy",
+        "Sollen die obigen Dateien wirklich kopiert werden? (j/n) ",
+    ),
+    (
+        "prompt.confirm-reflink",
+        "Sollen die obigen Dateien wirklich per Reflink kopiert werden? \
+         (j/n) ",
+    ),
+    (
+        "prompt.apply-plan",
+        "Soll der obige Plan wirklich ausgeführt werden? (j/n) ",
+    ),
+    (
+        "prompt.sanitize",
+        "Einige Ziele sind ungültig. Sollen ihre Namen bereinigt werden? \
+         (j/n) ",
+    ),
+    (
+        "prompt.choice",
+        "Bitte wählen [{0}-{1}] (o <n> öffnet die IMDb-Seite, u zeigt alle \
+         IMDb-Seiten): ",
+    ),
+    (
+        "prompt.choice-batch",
+        "Bitte wählen [{0}-{1}] (o <n> öffnet die IMDb-Seite, u zeigt alle \
+         IMDb-Seiten, r zeigt weitere Ergebnisse, s überspringt diese \
+         Datei): ",
+    ),
+    (
+        "summary.excluded-one",
+        "[summary] {0} Eintrag durch --only {1} ausgeschlossen",
+    ),
+    (
+        "summary.excluded-other",
+        "[summary] {0} Einträge durch --only {1} ausgeschlossen",
+    ),
+    (
+        "summary.skipped",
+        "[summary] {0} Datei(en) übersprungen, siehe die [skipping]-Zeilen \
+         oben",
+    ),
+    (
+        "plan.written",
+        "[plan] {0} Vorschläge nach {1} geschrieben, mit --apply-plan \
+         ausführen",
+    ),
+    ("plan.done", "[plan] jeder Vorschlag in {0} ist bereits erledigt"),
+    (
+        "skip.not-companion",
+        "gehört zu keiner Videodatei (mit --all-files trotzdem umbenennen)",
+    ),
+    (
+        "skip.not-video",
+        "keine Videodatei (mit --all-files trotzdem umbenennen)",
+    ),
+    ("skip.in-use", "{0} scheint in Benutzung zu sein: {1}"),
+    ("skip.unparsable", "Dateipfad konnte nicht gelesen werden: {0}"),
+    ("skip.prompted", "{0}: bei der Auswahl übersprungen"),
+    ("skip.search-error", "Fehler bei der Suche nach {0}: {1}"),
+    ("skip.missing-show", "{0}: Serie {1} von {2} fehlt in den Daten"),
+    ("skip.show-error", "Fehler bei der Suche nach der Serie für {0}: {1}"),
+    ("skip.absolute", "Fehler beim Bilden des absoluten Pfads von {0}: {1}"),
+    ("skip.cwd", "Fehler beim Lesen des aktuellen Verzeichnisses: {0}"),
+    (
+        "skip.undecided",
+        "kein Suchergebnis sticht heraus, und Rückfragen sind abgeschaltet",
+    ),
+    (
+        "error.undecided",
+        "{0} Datei(en) übersprungen, die eine Auswahl unter den \
+         Suchergebnissen brauchen, zum Auswählen ohne --yes ausführen",
+    ),
+    (
+        "error.query-undecided",
+        "kein Ergebnis der Suche sticht heraus, und --yes fragt nie nach; \
+         die Suche verfeinern oder ohne --yes ausführen",
+    ),
+    (
+        "prompt.cross-device",
+        "{0} Vorschläge kopieren {1} auf andere Dateisysteme und löschen \
+         danach die Originale. Fortfahren? (j/n) ",
+    ),
+    ("note.cross-device", "wird auf ein anderes Dateisystem kopiert, {0}"),
+    (
+        "error.cross-device",
+        "{0} Vorschläge würden {1} auf andere Dateisysteme kopieren, mit \
+         --allow-cross-device erlauben",
+    ),
+    ("search.no-results", "[no results] {0}"),
+    ("proposal.unchecked", "[unchecked] {0}"),
+    ("error.no-files", "keine Dateien zum Umbenennen"),
+    ("error.invalid-destinations", "ungültige Ziele werden nicht umbenannt"),
+    (
+        "warning.nfo-exists",
+        "NFO-Datei '{0}' existiert bereits, mit --force-nfo ersetzen",
+    ),
+    ("undo.renamed", "[undo] {0} -> {1}"),
+    ("undo.removed", "[undo] {0} {1} entfernt"),
+    ("undo.skipped", "[skip] {0}"),
+    (
+        "error.undo-incomplete",
+        "{0} von {1} Umbenennungen rückgängig gemacht, --undo erneut \
+         ausführen, um den Rest zu wiederholen, sobald ihre Quellen frei sind",
+    ),
+    (
+        "stats.matched",
+        "zugeordnet:\t{0} ({1} automatisch, {2} ausgewählt, {3} vorgegeben)",
+    ),
+    ("stats.skipped", "übersprungen:\t{0} ({1} unentschieden)"),
+    ("stats.scores", "Bewertungen:"),
+    ("stats.unscored", "keine"),
+    ("stats.patterns", "Muster:"),
+];
+
+lazy_static! {
+    static ref CATALOGS: HashMap<Lang, HashMap<&'static str, &'static str>> =
+        LANGS
+            .iter()
+            .map(|&lang| (lang, lang.messages().iter().cloned().collect()))
+            .collect();
+    static ref CURRENT: Mutex<Lang> = Mutex::new(Lang::English);
+}
+
+impl Lang {
+    /// Find a language by the name accepted by --lang.
+    pub fn from_name(name: &str) -> anyhow::Result<Lang> {
+        match name {
+            "en" => Ok(Lang::English),
+            "de" => Ok(Lang::German),
+            unk => anyhow::bail!("unsupported language: {:?}", unk),
+        }
+    }
+
+    /// Find the language of the given locale, like `de_DE.UTF-8`. Locales
+    /// in unsupported languages, like `C`, are `None`.
+    pub fn from_locale(locale: &str) -> Option<Lang> {
+        let name = locale.split(['_', '.', '@']).next().unwrap_or("");
+        Lang::from_name(name).ok()
+    }
+
+    /// Find the language of the end user's locale, as given by the
+    /// environment. The first of LC_ALL, LC_MESSAGES and LANG that is set
+    /// decides, and English is used if its language isn't shipped.
+    pub fn from_env() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|locale| !locale.is_empty())
+            .and_then(|locale| Lang::from_locale(&locale))
+            .unwrap_or(Lang::English)
+    }
+
+    fn messages(&self) -> &'static [(&'static str, &'static str)] {
+        match *self {
+            Lang::English => ENGLISH,
+            Lang::German => GERMAN,
+        }
+    }
+}
+
+/// Set the language used by all subsequent lookups.
+pub fn init(lang: Lang) {
+    *CURRENT.lock().unwrap() = lang;
+}
+
+/// Return the message with the given id in the current language.
+///
+/// Messages missing from the current language fall back to English, and
+/// ids missing from English are returned as is.
+pub fn tr(id: &'static str) -> &'static str {
+    let lang = *CURRENT.lock().unwrap();
+    lookup(lang, id)
+}
+
+/// Return the message with the given id in the current language, with its
+/// placeholders replaced by the given arguments. See `tr`.
+///
+/// Placeholders are replaced in a single pass, so an argument that looks
+/// like a placeholder, e.g., a file named `{1}.mkv`, is kept as is.
+/// Placeholders without a corresponding argument are kept as well.
+pub fn trf(id: &'static str, args: &[&dyn fmt::Display]) -> String {
+    format_message(tr(id), args)
+}
+
+/// Like `trf`, but for a message about `n` things, which is the message with
+/// id `one` when `n` is 1 and the message with id `other` otherwise. Every
+/// language shipped has just these two plural forms.
+pub fn trn(
+    one: &'static str,
+    other: &'static str,
+    n: usize,
+    args: &[&dyn fmt::Display],
+) -> String {
+    trf(if n == 1 { one } else { other }, args)
+}
+
+/// Returns true if and only if the given answer to a yes/no question means
+/// yes in the current language. English answers are always recognized.
+pub fn is_yes(answer: &str) -> bool {
+    let answer = answer.trim().to_lowercase();
+    tr("answer.yes").split(',').any(|yes| yes == answer)
+}
+
+/// Replace the placeholders in the given message with the given arguments.
+fn format_message(msg: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut rest = msg;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let arg = rest.find('}').and_then(|end| {
+            let i: usize = rest[1..end].parse().ok()?;
+            Some((args.get(i)?, end))
+        });
+        match arg {
+            Some((arg, end)) => {
+                out.push_str(&arg.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn lookup(lang: Lang, id: &'static str) -> &'static str {
+    CATALOGS[&lang]
+        .get(id)
+        .or_else(|| CATALOGS[&Lang::English].get(id))
+        .cloned()
+        .unwrap_or(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use regex::Regex;
+
+    use super::{format_message, lookup, trf, trn, Lang, ENGLISH, LANGS};
+
+    #[test]
+    fn every_message_in_every_language() {
+        let placeholder = Regex::new(r"\{[0-9]+\}").unwrap();
+        let placeholders = |msg: &str| -> BTreeSet<String> {
+            placeholder
+                .find_iter(msg)
+                .map(|m| m.as_str().to_string())
+                .collect()
+        };
+        let english: BTreeSet<&str> =
+            ENGLISH.iter().map(|&(id, _)| id).collect();
+        assert_eq!(english.len(), ENGLISH.len(), "duplicate message ids");
+        for lang in LANGS {
+            let ids: BTreeSet<&str> =
+                lang.messages().iter().map(|&(id, _)| id).collect();
+            assert_eq!(ids, english, "messages of {:?}", lang);
+            for &(id, msg) in ENGLISH {
+                assert_eq!(
+                    placeholders(lookup(*lang, id)),
+                    placeholders(msg),
+                    "placeholders of {} in {:?}",
+                    id,
+                    lang,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lookups() {
+        assert_eq!(
+            lookup(Lang::German, "plan.done"),
+            "[plan] jeder Vorschlag in {0} ist bereits erledigt"
+        );
+        assert_eq!(lookup(Lang::German, "no.such.id"), "no.such.id");
+        assert_eq!(
+            trf("skip.in-use", &[&"a.mkv", &"open in vlc"]),
+            "a.mkv appears to be in use: open in vlc"
+        );
+        assert_eq!(
+            format_message("{0} -> {1}", &[&"{1}.mkv", &"b.mkv"]),
+            "{1}.mkv -> b.mkv"
+        );
+        assert_eq!(format_message("{0} {2} {x} {", &[&1]), "1 {2} {x} {");
+        let excluded = |n: usize| {
+            trn(
+                "summary.excluded-one",
+                "summary.excluded-other",
+                n,
+                &[&n, &"files"],
+            )
+        };
+        assert_eq!(excluded(1), "[summary] excluded 1 entry by --only files");
+        assert_eq!(
+            excluded(2),
+            "[summary] excluded 2 entries by --only files"
+        );
+        assert_eq!(Lang::from_locale("de_DE.UTF-8"), Some(Lang::German));
+        assert_eq!(Lang::from_locale("en_US"), Some(Lang::English));
+        assert_eq!(Lang::from_locale("C"), None);
+        assert_eq!(Lang::from_name("de").unwrap(), Lang::German);
+        assert!(Lang::from_name("fr").is_err());
+    }
+}
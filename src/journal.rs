@@ -0,0 +1,1226 @@
+// A journal records the proposals that have been executed, in order, such
+// that they can be reversed. This is used to roll back a batch of renames
+// when one of them fails in transactional mode.
+//
+// Executed proposals are also appended to a log in the data directory, which
+// persists across runs, so that the most recent batch can be undone later
+// with --undo. When there's nothing in the log to undo, --undo falls back to
+// the origins written by --write-origin.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::events::{Event, EventSink};
+use crate::origin::{self, Origin};
+use crate::rename::{
+    is_case_only, move_across_devices, remove_dirs, remove_tree, rename_path,
+    same_file, Executed, RenameAction, RenameProposal,
+};
+
+/// The name of the log of executed proposals in the data directory.
+pub const LOG_NAME: &str = "rename-journal.jsonl";
+
+/// A single executed proposal.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    src: PathBuf,
+    dst: PathBuf,
+    action: RenameAction,
+    /// When the proposal was executed, in seconds since the Unix epoch.
+    time: u64,
+    /// Whether the rename was across file systems, in which case it's
+    /// reversed by copying too.
+    #[serde(default)]
+    cross_device: bool,
+    /// What a symlink points to, when that isn't `src`, e.g., because the
+    /// symlink is relative.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link_target: Option<PathBuf>,
+    /// The fingerprint of a copy or reflink right after it was made, which
+    /// tells whether it was changed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    copied: Option<Fingerprint>,
+    /// The NFO file written next to `dst`, which is removed along with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nfo: Option<PathBuf>,
+    /// The file that the origin of `dst` was written to, from which it's
+    /// removed along with `dst`. See `origin::remove`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<PathBuf>,
+    /// The directories created for `dst` because of `--mkdir`, outermost
+    /// first, which are removed along with it when they're left empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dirs: Vec<PathBuf>,
+}
+
+/// The total size and the latest modification time of the files at a path,
+/// recursively if it's a directory.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Fingerprint {
+    size: u64,
+    modified: SystemTime,
+}
+
+impl Fingerprint {
+    /// Compute the fingerprint of the given path. Symlinks are not followed.
+    fn of(path: &Path) -> io::Result<Fingerprint> {
+        let mut fp = Fingerprint { size: 0, modified: UNIX_EPOCH };
+        for result in WalkDir::new(path) {
+            let md = result?.metadata()?;
+            if md.is_file() {
+                fp.size += md.len();
+            }
+            fp.modified = fp.modified.max(md.modified()?);
+        }
+        Ok(fp)
+    }
+}
+
+impl Entry {
+    /// Create an entry for the given proposal, which was just executed.
+    ///
+    /// Every path is recorded as an absolute path, so that the entry can be
+    /// undone from any working directory.
+    fn new(proposal: &RenameProposal, executed: &Executed) -> Entry {
+        let src = absolute(proposal.src());
+        // A symlink's target is recorded whenever it isn't the (absolute)
+        // source, since that's what the link is compared to when undoing.
+        let target = proposal.link_target();
+        let link_target = match proposal.action() {
+            RenameAction::Symlink if target != src => {
+                Some(target.to_path_buf())
+            }
+            _ => None,
+        };
+        let copied = match proposal.action() {
+            RenameAction::Copy | RenameAction::Reflink => {
+                Fingerprint::of(proposal.dst()).ok()
+            }
+            _ => None,
+        };
+        Entry {
+            src,
+            dst: absolute(proposal.dst()),
+            action: proposal.action(),
+            time: now().as_secs(),
+            cross_device: proposal.cross_device().is_some(),
+            link_target,
+            copied,
+            nfo: executed.nfo.as_deref().map(absolute),
+            origin: executed.origin.as_deref().map(absolute),
+            dirs: executed.dirs.iter().map(|d| absolute(d)).collect(),
+        }
+    }
+
+    /// The path that was renamed or linked to.
+    pub fn src(&self) -> &Path {
+        &self.src
+    }
+
+    /// The path that was renamed to or created.
+    pub fn dst(&self) -> &Path {
+        &self.dst
+    }
+
+    /// The action that was executed.
+    pub fn action(&self) -> RenameAction {
+        self.action
+    }
+
+    /// Reverse the effect of this entry.
+    ///
+    /// Renames are renamed back to their source, while links, copies and
+    /// reflinks are reversed by removing whatever was created. An NFO file
+    /// and an origin written along with the destination are removed in every
+    /// case, and so are the directories created for it once they're empty.
+    pub fn undo(&self) -> anyhow::Result<()> {
+        if self.action == RenameAction::Rename {
+            self.check_rename_back()?;
+        }
+        if let Some(ref nfo) = self.nfo {
+            match fs::remove_file(nfo) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => anyhow::bail!(
+                    "error removing NFO file '{}': {}",
+                    nfo.display(),
+                    e,
+                ),
+            }
+        }
+        if let Some(ref path) = self.origin {
+            origin::remove(path, &self.src, &self.dst)?;
+        }
+        match self.action {
+            RenameAction::Rename => {
+                let result = if self.cross_device {
+                    move_across_devices(&self.dst, &self.src)
+                } else {
+                    rename_path(&self.dst, &self.src)
+                };
+                result.map_err(|e| {
+                    anyhow::anyhow!(
+                        "error renaming '{}' back to '{}': {}",
+                        self.dst.display(),
+                        self.src.display(),
+                        e,
+                    )
+                })?;
+            }
+            RenameAction::Symlink | RenameAction::Hardlink => {
+                // Removing anything other than the link that was created
+                // would lose data: the link may have been replaced by
+                // another file, and a hard link whose source is gone is the
+                // last copy of its file.
+                let replaced = match self.action {
+                    RenameAction::Symlink => {
+                        let target =
+                            self.link_target.as_deref().unwrap_or(&self.src);
+                        !fs::read_link(&self.dst).is_ok_and(|t| t == target)
+                    }
+                    _ => !same_file(&self.src, &self.dst),
+                };
+                if replaced && fs::symlink_metadata(&self.dst).is_ok() {
+                    anyhow::bail!(
+                        "cannot remove {} '{}': it is no longer a link to '{}'",
+                        self.action,
+                        self.dst.display(),
+                        self.src.display(),
+                    );
+                }
+                fs::remove_file(&self.dst).map_err(|e| {
+                    anyhow::anyhow!(
+                        "error removing {} '{}': {}",
+                        self.action,
+                        self.dst.display(),
+                        e,
+                    )
+                })?;
+            }
+            RenameAction::Copy | RenameAction::Reflink => {
+                // A copy whose source is gone is the last copy of its file,
+                // and a copy that was changed since it was made has data
+                // that its source doesn't.
+                if fs::symlink_metadata(&self.src).is_err() {
+                    anyhow::bail!(
+                        "cannot remove {} '{}': its source '{}' is gone",
+                        self.action,
+                        self.dst.display(),
+                        self.src.display(),
+                    );
+                }
+                if let Some(ref copied) = self.copied {
+                    if Fingerprint::of(&self.dst).ok().as_ref() != Some(copied)
+                    {
+                        anyhow::bail!(
+                            "cannot remove {} '{}': it was changed since it \
+                             was made",
+                            self.action,
+                            self.dst.display(),
+                        );
+                    }
+                }
+                remove_tree(&self.dst).map_err(|e| {
+                    anyhow::anyhow!(
+                        "error removing {} '{}': {}",
+                        self.action,
+                        self.dst.display(),
+                        e,
+                    )
+                })?;
+            }
+        }
+        remove_dirs(&self.dirs);
+        Ok(())
+    }
+
+    /// Return an error if this entry's destination can't be renamed back to
+    /// its source, because something else took the source's place.
+    fn check_rename_back(&self) -> anyhow::Result<()> {
+        if fs::symlink_metadata(&self.src).is_ok()
+            && !is_case_only(&self.dst, &self.src)
+        {
+            anyhow::bail!(
+                "cannot rename '{}' back to '{}': destination exists",
+                self.dst.display(),
+                self.src.display(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Return the given path with its parent made absolute, relative to the
+/// current working directory. The path itself is kept as is, since it may be
+/// a symlink. If the current directory can't be read, then the path is
+/// returned unchanged.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// An ordered record of executed proposals.
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    /// Create a new empty journal.
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    /// Record the given proposal as executed, along with what executing it
+    /// wrote.
+    pub fn record(&mut self, proposal: &RenameProposal, executed: &Executed) {
+        self.entries.push(Entry::new(proposal, executed));
+    }
+
+    /// Returns the entries recorded so far, in the order they were executed.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Reverse every entry in this journal, most recent first, and remove
+    /// them from the journal.
+    ///
+    /// Entries that fail to be reversed are skipped, kept in the journal and
+    /// their errors are returned. Rolling back continues after a failure,
+    /// since giving up would leave even more of the batch in an inconsistent
+    /// state. A rollback event is sent to the given sink, if any, for each
+    /// entry that is reversed.
+    pub fn rollback(
+        &mut self,
+        events: Option<&dyn EventSink>,
+    ) -> Vec<anyhow::Error> {
+        let mut errs = vec![];
+        let mut kept = vec![];
+        while let Some(entry) = self.entries.pop() {
+            match entry.undo() {
+                Ok(()) => {
+                    if let Some(sink) = events {
+                        sink.emit(&Event::Rollback {
+                            src: &entry.src,
+                            dst: &entry.dst,
+                            action: entry.action,
+                        });
+                    }
+                }
+                Err(err) => {
+                    errs.push(err);
+                    kept.push(entry);
+                }
+            }
+        }
+        kept.reverse();
+        self.entries = kept;
+        errs
+    }
+}
+
+/// A line in the log of executed proposals.
+#[derive(Debug, Deserialize, Serialize)]
+struct Record {
+    /// The batch, i.e., the run of imdb-rename, that executed this entry.
+    batch: u64,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+/// A log of executed proposals that persists across runs, stored as one JSON
+/// object per line.
+///
+/// Every proposal executed by one run belongs to the same batch, which is
+/// what --undo reverses.
+#[derive(Debug)]
+pub struct Log {
+    path: PathBuf,
+    batch: u64,
+}
+
+impl Log {
+    /// Open the log at the given path for a new batch. Nothing is written
+    /// until a proposal is appended.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Log {
+        Log { path: path.into(), batch: now().as_millis() as u64 }
+    }
+
+    /// Append the given proposal, which was just executed, to this log,
+    /// along with what executing it wrote.
+    pub fn append(
+        &self,
+        proposal: &RenameProposal,
+        executed: &Executed,
+    ) -> anyhow::Result<()> {
+        let entry = Entry::new(proposal, executed);
+        let record = Record { batch: self.batch, entry };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| self.error("error writing", e))?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(|e| self.error("error writing", e))?;
+        Ok(())
+    }
+
+    /// Replace the entries of this log's batch with the given entries.
+    ///
+    /// This is used after rolling back a transaction, such that only the
+    /// entries that couldn't be rolled back remain to be undone.
+    pub fn replace_batch(&self, entries: &[Entry]) -> anyhow::Result<()> {
+        let mut records = read_records(&self.path)?;
+        records.retain(|r| r.batch != self.batch);
+        records.extend(
+            entries
+                .iter()
+                .map(|e| Record { batch: self.batch, entry: e.clone() }),
+        );
+        write_records(&self.path, &records)
+    }
+
+    fn error(&self, what: &str, err: impl std::fmt::Display) -> anyhow::Error {
+        anyhow::anyhow!(
+            "{} rename journal '{}': {}",
+            what,
+            self.path.display(),
+            err
+        )
+    }
+}
+
+/// The result of undoing the most recent batch in a log.
+#[derive(Debug, Default)]
+pub struct Undone {
+    /// The entries that were reversed, most recent first.
+    pub undone: Vec<Entry>,
+    /// The errors for the entries that couldn't be reversed, most recent
+    /// first. These entries stay in the log.
+    pub skipped: Vec<anyhow::Error>,
+}
+
+/// Reverse the most recent batch in the log at the given path, most recent
+/// entry first.
+///
+/// Entries that can't be reversed, for example because their source has
+/// been recreated since, are skipped and kept in the log, such that undoing
+/// again retries them. Every other entry of the batch is removed from the
+/// log.
+pub fn undo_last(path: &Path) -> anyhow::Result<Undone> {
+    let mut records = read_records(path)?;
+    let batch = match records.last() {
+        None => anyhow::bail!(
+            "nothing to undo, rename journal '{}' is empty",
+            path.display()
+        ),
+        Some(r) => r.batch,
+    };
+    let start =
+        records.iter().rposition(|r| r.batch != batch).map_or(0, |i| i + 1);
+    let last = records.split_off(start);
+    let mut undone = Undone::default();
+    let mut kept = vec![];
+    for record in last.into_iter().rev() {
+        match record.entry.undo() {
+            Ok(()) => undone.undone.push(record.entry),
+            Err(err) => {
+                undone.skipped.push(err);
+                kept.push(record);
+            }
+        }
+    }
+    records.extend(kept.into_iter().rev());
+    write_records(path, &records)?;
+    Ok(undone)
+}
+
+/// Returns true if and only if the log at the given path has nothing to
+/// undo, e.g., because it doesn't exist.
+pub fn is_empty(path: &Path) -> anyhow::Result<bool> {
+    Ok(read_records(path)?.is_empty())
+}
+
+/// Rename each of the given paths back to the original name recorded in its
+/// origin, deepest paths first, and remove the origin. See `Origin::find`.
+///
+/// This is what --undo falls back to when the log has nothing to undo.
+/// Origins only record file names, so each path is renamed within its own
+/// directory. Paths without an origin are ignored, while paths that can't
+/// be renamed back are skipped and keep their origin. If none of the paths
+/// have an origin, then an error is returned.
+pub fn undo_origins(paths: &[PathBuf]) -> anyhow::Result<Undone> {
+    let mut paths: Vec<&PathBuf> = paths.iter().collect();
+    paths.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    let mut undone = Undone::default();
+    for path in paths {
+        match undo_origin(path) {
+            Ok(None) => {}
+            Ok(Some(entry)) => undone.undone.push(entry),
+            Err(err) => undone.skipped.push(err),
+        }
+    }
+    if undone.undone.is_empty() && undone.skipped.is_empty() {
+        anyhow::bail!("nothing to undo, none of the files given have origins");
+    }
+    Ok(undone)
+}
+
+/// Rename the given path back to the original name recorded in its origin,
+/// if it has one, and return the entry that was reversed.
+fn undo_origin(dst: &Path) -> anyhow::Result<Option<Entry>> {
+    let (origin, recorded) = match Origin::find(dst)? {
+        None => return Ok(None),
+        Some(found) => found,
+    };
+    let entry = Entry {
+        src: dst.parent().unwrap_or(Path::new("")).join(&origin.original),
+        dst: dst.to_path_buf(),
+        action: RenameAction::Rename,
+        time: origin.time,
+        cross_device: false,
+        link_target: None,
+        copied: None,
+        nfo: None,
+        origin: Some(recorded),
+        dirs: vec![],
+    };
+    entry.undo()?;
+    Ok(Some(entry))
+}
+
+/// Read every record in the log at the given path. A log that doesn't exist
+/// is empty.
+fn read_records(path: &Path) -> anyhow::Result<Vec<Record>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(vec![])
+        }
+        Err(e) => anyhow::bail!(
+            "error reading rename journal '{}': {}",
+            path.display(),
+            e
+        ),
+    };
+    let mut records = vec![];
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(line).map_err(|e| {
+            anyhow::anyhow!(
+                "invalid rename journal '{}' on line {}: {}",
+                path.display(),
+                i + 1,
+                e
+            )
+        })?);
+    }
+    Ok(records)
+}
+
+/// Replace the log at the given path with the given records. The new log is
+/// written next to the old one and then renamed over it, so that a failure
+/// never leaves a truncated log behind.
+fn write_records(path: &Path, records: &[Record]) -> anyhow::Result<()> {
+    let mut data = vec![];
+    for record in records {
+        serde_json::to_writer(&mut data, record)?;
+        data.push(b'\n');
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    fs::write(&tmp, data).and_then(|()| fs::rename(&tmp, path)).map_err(
+        |e| {
+            anyhow::anyhow!(
+                "error writing rename journal '{}': {}",
+                path.display(),
+                e
+            )
+        },
+    )?;
+    Ok(())
+}
+
+/// The current time as a duration since the Unix epoch.
+fn now() -> std::time::Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Execute all of the given proposals as a single batch.
+///
+/// Each proposal is journaled as it succeeds, and appended to the given log
+/// if there is one. Upon the first failure, every proposal executed so far
+/// is rolled back in reverse order and an error summarizing the failure (and
+/// any failures to roll back) is returned. Only the proposals that couldn't
+/// be rolled back are left in the log.
+///
+/// When every proposal succeeds, the problems that didn't fail any of them
+/// are returned, e.g., failing to append to the log.
+///
+/// When a directory is renamed, the proposals after it that refer to paths
+/// inside of it are rebased onto its new location. See
+/// `RenameProposal::rebase`.
+///
+/// If a sink is given, then a renamed event is sent to it for each proposal
+/// once it fully succeeds, and a rollback event for each proposal that is
+/// rolled back.
+pub fn execute_transaction(
+    proposals: &[RenameProposal],
+    log: Option<&Log>,
+    events: Option<&dyn EventSink>,
+) -> anyhow::Result<Vec<anyhow::Error>> {
+    let mut journal = Journal::new();
+    let mut warnings = vec![];
+    let mut proposals = proposals.to_vec();
+    for i in 0..proposals.len() {
+        let (done, rest) = proposals.split_at_mut(i + 1);
+        let p = &done[i];
+        let err = match p.rename() {
+            Ok(executed) => {
+                if let Some(sink) = events {
+                    sink.emit(&Event::Renamed {
+                        src: p.src(),
+                        dst: p.dst(),
+                        action: p.action(),
+                    });
+                }
+                journal.record(p, &executed);
+                if let Some(log) = log {
+                    warnings.extend(log.append(p, &executed).err());
+                }
+                warnings.extend(executed.warnings);
+                for later in rest {
+                    later.rebase(p);
+                }
+                continue;
+            }
+            Err(err) => err,
+        };
+        let completed = journal.entries().len();
+        let rollback_errs = journal.rollback(events);
+        let log_err =
+            log.and_then(|log| log.replace_batch(journal.entries()).err());
+        let mut msg = format!(
+            "{}\nrolled back {} completed operation(s)",
+            err,
+            completed - rollback_errs.len(),
+        );
+        if !rollback_errs.is_empty() {
+            msg.push_str(&format!(
+                ", but {} could not be rolled back:",
+                rollback_errs.len()
+            ));
+            for err in &rollback_errs {
+                msg.push_str(&format!("\n  {}", err));
+            }
+        }
+        if let Some(err) = log_err {
+            msg.push_str(&format!("\n{}", err));
+        }
+        anyhow::bail!("{}", msg);
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use super::{execute_transaction, undo_last, undo_origins, Log};
+    use crate::events::{Event, EventSink};
+    use crate::origin::{Origin, OriginFormat};
+    use crate::rename::{Executed, RenameAction, RenameProposal};
+    use crate::util::tests::{entity, TempDir};
+
+    #[test]
+    fn rollback_renames() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let (a, b) = (tmp.path().join("a.mkv"), tmp.path().join("b.mkv"));
+        let c = tmp.path().join("c.mkv");
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        fs::write(&c, "c").unwrap();
+        // A regular file can't contain other files, so renaming into it
+        // always fails, even when running as root.
+        let unwritable = tmp.path().join("unwritable");
+        fs::write(&unwritable, "").unwrap();
+
+        let proposals = vec![
+            RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                RenameAction::Rename,
+                entity(),
+            ),
+            RenameProposal::new(
+                b.clone(),
+                tmp.path(),
+                "B (2001).mkv",
+                RenameAction::Rename,
+                entity(),
+            ),
+            RenameProposal::new(
+                c.clone(),
+                &unwritable,
+                "C (2002).mkv",
+                RenameAction::Hardlink,
+                entity(),
+            ),
+        ];
+        let events = Recorder::default();
+        let err =
+            execute_transaction(&proposals, None, Some(&events)).unwrap_err();
+        // The summary doesn't name the action of the proposal that failed,
+        // since the ones rolled back may have done something else.
+        assert!(
+            err.to_string().contains("rolled back 2 completed operation(s)"),
+            "{}",
+            err
+        );
+        let unwritable_dst = unwritable.join("C (2002).mkv");
+        assert!(
+            format!("{:#}", err)
+                .contains(&unwritable_dst.display().to_string()),
+            "{:#}",
+            err
+        );
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+        assert_eq!(fs::read_to_string(&c).unwrap(), "c");
+        assert!(!tmp.path().join("A (2000).mkv").exists());
+        assert!(!tmp.path().join("B (2001).mkv").exists());
+        // The proposal that failed was never reported as renamed, and the
+        // others are rolled back most recent first.
+        assert_eq!(
+            events.names(),
+            vec![
+                "renamed A (2000).mkv",
+                "renamed B (2001).mkv",
+                "rollback B (2001).mkv",
+                "rollback A (2000).mkv",
+            ]
+        );
+    }
+
+    /// An event sink that records the kind of each event and the file name
+    /// of its destination.
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Recorder {
+        fn names(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl EventSink for Recorder {
+        fn emit(&self, event: &Event) {
+            let (kind, dst) = match *event {
+                Event::Renamed { dst, .. } => ("renamed", dst),
+                Event::Rollback { dst, .. } => ("rollback", dst),
+                _ => return,
+            };
+            let name = dst.file_name().unwrap().to_string_lossy();
+            self.0.lock().unwrap().push(format!("{} {}", kind, name));
+        }
+    }
+
+    #[test]
+    fn rollback_links() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let unwritable = tmp.path().join("unwritable");
+        fs::write(&unwritable, "").unwrap();
+
+        let proposals = vec![
+            RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                RenameAction::Hardlink,
+                entity(),
+            ),
+            RenameProposal::new(
+                a.clone(),
+                &unwritable,
+                "A (2000).mkv",
+                RenameAction::Hardlink,
+                entity(),
+            ),
+        ];
+        assert!(execute_transaction(&proposals, None, None).is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert!(!tmp.path().join("A (2000).mkv").exists());
+    }
+
+    #[test]
+    fn rollback_copies() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let (a, dir) = (tmp.path().join("a.mkv"), tmp.path().join("Show"));
+        fs::write(&a, "a").unwrap();
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("ep1.mkv"), "1").unwrap();
+        let unwritable = tmp.path().join("unwritable");
+        fs::write(&unwritable, "").unwrap();
+
+        let proposals = vec![
+            RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                RenameAction::Copy,
+                entity(),
+            ),
+            RenameProposal::new(
+                dir.clone(),
+                tmp.path(),
+                "Show (1989)",
+                RenameAction::Copy,
+                entity(),
+            ),
+            RenameProposal::new(
+                a.clone(),
+                &unwritable,
+                "A (2000).mkv",
+                RenameAction::Copy,
+                entity(),
+            ),
+        ];
+        let err = execute_transaction(&proposals, None, None).unwrap_err();
+        assert!(err.to_string().contains("rolled back 2"), "{}", err);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(dir.join("ep1.mkv")).unwrap(), "1");
+        assert!(!tmp.path().join("A (2000).mkv").exists());
+        assert!(!tmp.path().join("Show (1989)").exists());
+    }
+
+    #[test]
+    fn parent_renamed_first() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let dir = tmp.path().join("Show.S01");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("ep1.mkv"), "1").unwrap();
+        fs::write(dir.join("ep2.mkv"), "2").unwrap();
+
+        let proposals = |last: Option<RenameProposal>| {
+            let mut proposals = vec![
+                RenameProposal::new(
+                    dir.clone(),
+                    tmp.path(),
+                    "Show (1989)",
+                    RenameAction::Rename,
+                    entity(),
+                ),
+                RenameProposal::new(
+                    dir.join("ep1.mkv"),
+                    &dir,
+                    "S01E01 - Pilot.mkv",
+                    RenameAction::Rename,
+                    entity(),
+                ),
+                RenameProposal::new(
+                    dir.join("ep2.mkv"),
+                    &dir,
+                    "S01E02 - Second.mkv",
+                    RenameAction::Rename,
+                    entity(),
+                ),
+            ];
+            proposals.extend(last);
+            proposals
+        };
+
+        // A failure at the end rolls back the children before the parent,
+        // from their new locations.
+        let unwritable = tmp.path().join("unwritable");
+        fs::write(&unwritable, "").unwrap();
+        let fail = RenameProposal::new(
+            unwritable.clone(),
+            &unwritable,
+            "x",
+            RenameAction::Rename,
+            entity(),
+        );
+        let err = execute_transaction(&proposals(Some(fail)), None, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("rolled back 3"), "{}", err);
+        assert_eq!(fs::read_to_string(dir.join("ep1.mkv")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dir.join("ep2.mkv")).unwrap(), "2");
+        assert!(!tmp.path().join("Show (1989)").exists());
+
+        execute_transaction(&proposals(None), None, None).unwrap();
+        let show = tmp.path().join("Show (1989)");
+        assert!(!dir.exists());
+        let read = |name| fs::read_to_string(show.join(name)).unwrap();
+        assert_eq!(read("S01E01 - Pilot.mkv"), "1");
+        assert_eq!(read("S01E02 - Second.mkv"), "2");
+    }
+
+    #[test]
+    fn success() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let proposals = vec![RenameProposal::new(
+            a.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Rename,
+            entity(),
+        )];
+        execute_transaction(&proposals, None, None).unwrap();
+        assert!(!a.exists());
+        assert!(tmp.path().join("A (2000).mkv").exists());
+    }
+
+    /// Execute the given proposals, appending them to the log at the given
+    /// path as the given batch.
+    fn execute_logged(
+        path: &std::path::Path,
+        batch: u64,
+        proposals: &[RenameProposal],
+    ) {
+        let log = Log { path: path.to_path_buf(), batch };
+        for p in proposals {
+            let executed = p.rename().unwrap();
+            log.append(p, &executed).unwrap();
+        }
+    }
+
+    #[test]
+    fn undo_last_batch() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("data").join("rename-journal.jsonl");
+        let (a, b) = (tmp.path().join("a.mkv"), tmp.path().join("b.mkv"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        let renamed = tmp.path().join("A (2000).mkv");
+        let linked = tmp.path().join("B (2001).mkv");
+
+        execute_logged(
+            &path,
+            1,
+            &[RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                RenameAction::Rename,
+                entity(),
+            )],
+        );
+        execute_logged(
+            &path,
+            2,
+            &[RenameProposal::new(
+                b.clone(),
+                tmp.path(),
+                "B (2001).mkv",
+                RenameAction::Hardlink,
+                entity(),
+            )],
+        );
+
+        // The hard link is removed, but its source is left alone.
+        let undone = undo_last(&path).unwrap();
+        assert_eq!(undone.undone.len(), 1);
+        assert!(undone.skipped.is_empty());
+        assert!(!linked.exists());
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+        assert!(renamed.exists());
+
+        let undone = undo_last(&path).unwrap();
+        assert_eq!(undone.undone.len(), 1);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert!(!renamed.exists());
+
+        assert!(undo_last(&path).is_err());
+    }
+
+    #[test]
+    fn undo_skips_collisions() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("rename-journal.jsonl");
+        let (a, b) = (tmp.path().join("a.mkv"), tmp.path().join("b.mkv"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        execute_logged(
+            &path,
+            1,
+            &[
+                RenameProposal::new(
+                    a.clone(),
+                    tmp.path(),
+                    "A (2000).mkv",
+                    RenameAction::Rename,
+                    entity(),
+                ),
+                RenameProposal::new(
+                    b.clone(),
+                    tmp.path(),
+                    "B (2001).mkv",
+                    RenameAction::Rename,
+                    entity(),
+                ),
+            ],
+        );
+        fs::write(&a, "new").unwrap();
+
+        let undone = undo_last(&path).unwrap();
+        assert_eq!(undone.undone.len(), 1);
+        assert_eq!(undone.skipped.len(), 1);
+        let err = undone.skipped[0].to_string();
+        assert!(err.contains("destination exists"), "{}", err);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+
+        // The skipped rename is retried once its source is free.
+        fs::remove_file(&a).unwrap();
+        let undone = undo_last(&path).unwrap();
+        assert_eq!(undone.undone.len(), 1);
+        assert!(undone.skipped.is_empty());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+    }
+
+    #[test]
+    fn undo_removes_nfo() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("rename-journal.jsonl");
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let proposal = RenameProposal::new(
+            a.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Rename,
+            entity(),
+        );
+        proposal.rename().unwrap();
+        let nfo = tmp.path().join("A (2000).nfo");
+        fs::write(&nfo, "<movie/>").unwrap();
+        let executed =
+            Executed { nfo: Some(nfo.clone()), ..Executed::default() };
+        let log = Log { path: path.clone(), batch: 1 };
+        log.append(&proposal, &executed).unwrap();
+
+        let undone = undo_last(&path).unwrap();
+        assert_eq!(undone.undone.len(), 1);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert!(!nfo.exists());
+    }
+
+    #[test]
+    fn undo_removes_origin() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("rename-journal.jsonl");
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let proposal = RenameProposal::new(
+            a.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Rename,
+            entity(),
+        );
+        proposal.rename().unwrap();
+        let dst = tmp.path().join("A (2000).mkv");
+        let origin = Origin::new(&a, &dst, None)
+            .write(&dst, OriginFormat::Sidecar)
+            .unwrap();
+        let executed = Executed { origin, ..Executed::default() };
+        let log = Log { path: path.clone(), batch: 1 };
+        log.append(&proposal, &executed).unwrap();
+
+        let undone = undo_last(&path).unwrap();
+        assert_eq!(undone.undone.len(), 1);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert!(!tmp.path().join("A (2000).mkv.origin.txt").exists());
+    }
+
+    #[test]
+    fn undo_origins_without_journal() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let dir = tmp.path();
+        let write = |original: &str, renamed: &str, format| {
+            let dst = dir.join(renamed);
+            fs::write(&dst, renamed).unwrap();
+            Origin::new(Path::new(original), &dst, None)
+                .write(&dst, format)
+                .unwrap();
+            dst
+        };
+        let dune =
+            write("Dune.1984.mkv", "Dune (1984).mkv", OriginFormat::Tsv);
+        let arrival = write(
+            "Arrival.2016.mkv",
+            "Arrival (2016).mkv",
+            OriginFormat::Sidecar,
+        );
+        let blocked =
+            write("Heat.1995.mkv", "Heat (1995).mkv", OriginFormat::Tsv);
+        fs::write(dir.join("Heat.1995.mkv"), "another").unwrap();
+        let other = dir.join("other.mkv");
+        fs::write(&other, "").unwrap();
+
+        let undone =
+            undo_origins(&[dune, arrival, blocked.clone(), other]).unwrap();
+        assert_eq!(undone.undone.len(), 2);
+        assert_eq!(undone.skipped.len(), 1);
+        assert_eq!(
+            fs::read_to_string(dir.join("Dune.1984.mkv")).unwrap(),
+            "Dune (1984).mkv"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("Arrival.2016.mkv")).unwrap(),
+            "Arrival (2016).mkv"
+        );
+        assert!(!dir.join("Arrival (2016).mkv.origin.txt").exists());
+        // The file that couldn't be renamed back keeps its origin, so that
+        // undoing can be retried.
+        let (origin, _) = Origin::find(&blocked).unwrap().unwrap();
+        assert_eq!(origin.original, "Heat.1995.mkv");
+        assert!(Origin::find(&dir.join("Dune.1984.mkv")).unwrap().is_none());
+
+        assert!(undo_origins(&[dir.join("other.mkv")]).is_err());
+    }
+
+    #[test]
+    fn undo_keeps_replaced_symlinks() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("rename-journal.jsonl");
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let link = tmp.path().join("A (2000).mkv");
+        let log = Log { path: path.clone(), batch: 1 };
+        log.append(
+            &RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                RenameAction::Symlink,
+                entity(),
+            ),
+            &Executed::default(),
+        )
+        .unwrap();
+        fs::write(&link, "not a link").unwrap();
+
+        let undone = undo_last(&path).unwrap();
+        assert!(undone.undone.is_empty());
+        assert_eq!(undone.skipped.len(), 1);
+        assert_eq!(fs::read_to_string(&link).unwrap(), "not a link");
+
+        // A symlink that points somewhere else isn't ours either.
+        #[cfg(unix)]
+        {
+            let b = tmp.path().join("b.mkv");
+            fs::remove_file(&link).unwrap();
+            std::os::unix::fs::symlink(&b, &link).unwrap();
+            let undone = undo_last(&path).unwrap();
+            assert_eq!(undone.skipped.len(), 1);
+            assert_eq!(fs::read_link(&link).unwrap(), b);
+
+            fs::remove_file(&link).unwrap();
+            std::os::unix::fs::symlink(&a, &link).unwrap();
+            let undone = undo_last(&path).unwrap();
+            assert_eq!(undone.undone.len(), 1);
+            assert!(fs::symlink_metadata(&link).is_err());
+        }
+    }
+
+    #[test]
+    fn undo_keeps_changed_copies() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("rename-journal.jsonl");
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let proposal = RenameProposal::new(
+            a.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Copy,
+            entity(),
+        );
+        let executed = proposal.rename().unwrap();
+        let log = Log { path: path.clone(), batch: 1 };
+        log.append(&proposal, &executed).unwrap();
+        fs::write(proposal.dst(), "edited").unwrap();
+
+        let undone = undo_last(&path).unwrap();
+        assert!(undone.undone.is_empty());
+        assert_eq!(undone.skipped.len(), 1);
+        assert_eq!(fs::read_to_string(proposal.dst()).unwrap(), "edited");
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+    }
+
+    #[test]
+    fn undo_keeps_replaced_hardlinks() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("rename-journal.jsonl");
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let proposal = RenameProposal::new(
+            a.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Hardlink,
+            entity(),
+        );
+        let executed = proposal.rename().unwrap();
+        let log = Log { path: path.clone(), batch: 1 };
+        log.append(&proposal, &executed).unwrap();
+        // The source still exists, but the link was replaced by another
+        // file, which is the only copy of its data.
+        fs::remove_file(proposal.dst()).unwrap();
+        fs::write(proposal.dst(), "not a link").unwrap();
+
+        let undone = undo_last(&path).unwrap();
+        assert!(undone.undone.is_empty());
+        assert_eq!(undone.skipped.len(), 1);
+        assert_eq!(fs::read_to_string(proposal.dst()).unwrap(), "not a link");
+    }
+
+    #[test]
+    fn rollback_forgets_logged() {
+        let tmp = TempDir::new("imdb-rename-test-journal").unwrap();
+        let path = tmp.path().join("rename-journal.jsonl");
+        let a = tmp.path().join("a.mkv");
+        fs::write(&a, "a").unwrap();
+        let unwritable = tmp.path().join("unwritable");
+        fs::write(&unwritable, "").unwrap();
+
+        let proposals = vec![
+            RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                RenameAction::Rename,
+                entity(),
+            ),
+            RenameProposal::new(
+                tmp.path().join("A (2000).mkv"),
+                &unwritable,
+                "B (2001).mkv",
+                RenameAction::Rename,
+                entity(),
+            ),
+        ];
+        let log = Log::new(&path);
+        assert!(execute_transaction(&proposals, Some(&log), None).is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    }
+}
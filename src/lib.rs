@@ -0,0 +1,79 @@
+/*!
+This crate provides the renaming logic of imdb-rename, so that other tools
+can propose and execute renames of media files using an IMDb index.
+
+A [`Renamer`] is built with a [`RenamerBuilder`], and turns the file paths
+given to it into [`RenameProposal`]s by guessing a query from each file name
+and searching an [`imdb_index::Searcher`] with it. Each proposal is executed
+according to its [`RenameAction`].
+
+# Example
+
+```
+use std::path::PathBuf;
+
+use imdb_index::{Index, Searcher};
+use imdb_rename::{RenameAction, RenamerBuilder};
+
+# fn example() -> anyhow::Result<()> {
+# let data_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/data/test/small");
+# let tmp = std::env::temp_dir()
+#     .join(format!("imdb-rename-doctest-{}", std::process::id()));
+# let index_dir = tmp.as_path();
+# Index::create(data_dir, index_dir)?;
+let mut searcher = Searcher::new(Index::open(data_dir, index_dir)?);
+let renamer = RenamerBuilder::new().non_interactive(true).build()?;
+let paths = vec![PathBuf::from("The.Matrix.1999.mkv")];
+// Unlike `propose`, `propose_pure` doesn't require the files to exist.
+let (proposals, _) =
+    renamer.propose_pure(&mut searcher, &paths, None, RenameAction::Rename)?;
+assert_eq!(proposals[0].dst(), PathBuf::from("The Matrix (1999).mkv"));
+# std::fs::remove_dir_all(index_dir)?;
+# Ok(())
+# }
+# example().unwrap();
+```
+
+# Features
+
+The `cli` feature, which is enabled by default, exposes the `i18n` and `util`
+modules that the imdb-rename binary is built on, along with the process wide
+event sink in `events`. They write to the terminal and keep process wide
+state, like the language of messages and where events go, so they aren't
+part of this crate's stable API. Programs that only use the renamer should
+disable default features, and pass an `events::EventSink` to the renamer
+and to `journal::execute_transaction` to follow along.
+*/
+
+pub use crate::rename::{
+    is_subtitle, is_subtitle_dir, Decision, DirFilter, DirPerms, Executed,
+    ExtensionCase, IgnorePatterns, MatchStat, MatchSummary, Pattern,
+    QueryFixups, RenameAction, RenameProposal, Renamer, RenamerBuilder,
+    SkippedCheck, IGNORE_FILE,
+};
+
+// These modules are shared with the imdb-rename binary, and are only public
+// when the `cli` feature is enabled. Without it, the parts that only the
+// binary uses are dead code.
+#[cfg(feature = "cli")]
+pub mod i18n;
+#[cfg(not(feature = "cli"))]
+#[allow(dead_code)]
+mod i18n;
+#[cfg(feature = "cli")]
+pub mod util;
+#[cfg(not(feature = "cli"))]
+#[allow(dead_code)]
+mod util;
+
+pub mod events;
+pub mod explain;
+pub mod fscheck;
+pub mod journal;
+mod nfo;
+pub mod origin;
+pub mod plan;
+mod preserve;
+pub mod rename;
+#[cfg(test)]
+mod tempdir;
@@ -1,150 +1,1030 @@
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::ffi::OsStr;
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use imdb_index::{Index, IndexBuilder, NgramType, Searcher};
+use imdb_index::{
+    ErrorKind, Index, IndexBuilder, MediaEntity, NgramType, Query,
+    SearchResults, Searcher,
+};
 use lazy_static::lazy_static;
+use serde::Serialize;
 use tabwriter::TabWriter;
 use walkdir::WalkDir;
 
-use crate::rename::{RenameAction, RenamerBuilder};
-use crate::util::{choose, read_yesno, write_tsv};
+use imdb_rename::events::{self, Event, EventSink, GlobalSink, Status};
+use imdb_rename::explain::Explanation;
+use imdb_rename::fscheck::{SystemProbe, Validator};
+use imdb_rename::i18n::{self, tr, trf, trn, Lang};
+use imdb_rename::journal::{self, execute_transaction, Log};
+use imdb_rename::origin::OriginFormat;
+use imdb_rename::plan;
+use imdb_rename::util::{
+    auto_choice, choose, human_bytes, human_count, progress_printer,
+    read_yesno, write_json, write_season_overview, write_tsv, Highlighter,
+};
+use imdb_rename::{
+    is_subtitle, Decision, DirFilter, DirPerms, ExtensionCase, IgnorePatterns,
+    MatchStat, MatchSummary, QueryFixups, RenameAction, RenameProposal,
+    Renamer, RenamerBuilder, IGNORE_FILE,
+};
 
+use crate::config::Matches;
+
+mod config;
 mod download;
 mod logger;
-mod rename;
-mod util;
+#[cfg(test)]
+mod tempdir;
 
 fn main() {
-    if let Err(err) = try_main() {
+    let cli = Matches::new(app().get_matches(), None);
+    let mut json = cli.is_present("json");
+    let result = cli.load().and_then(|matches| {
+        json = matches.is_present("json");
+        try_main(&matches)
+    });
+    if let Err(err) = result {
         // A pipe error occurs when the consumer of this process's output has
         // hung up. This is a normal event, and we should quit gracefully.
         if is_pipe_error(&err) {
             process::exit(0);
         }
-        eprintln!("{:?}", err);
+        let code = error_code(&err);
+        events::emit(&Event::Error { error: format!("{:#}", err), code });
+        if json {
+            eprintln!("{}", error_json(&err));
+        } else {
+            eprintln!("{:?}", err);
+        }
         process::exit(1);
     }
 }
 
-fn try_main() -> anyhow::Result<()> {
+fn try_main(matches: &Matches) -> anyhow::Result<()> {
     logger::init()?;
     log::set_max_level(log::LevelFilter::Info);
+    // Events are enabled first, so that even errors parsing the remaining
+    // arguments are reported as events.
+    if matches.is_present("events") {
+        events::init(matches.value_of_os("events-file").map(Path::new))?;
+    }
+    i18n::init(match matches.value_of("lang") {
+        None => Lang::from_env(),
+        Some(name) => Lang::from_name(name)?,
+    });
 
-    let args = Args::from_matches(&app().get_matches())?;
-    if args.debug {
+    // The debug level is set before anything else is parsed, since
+    // collecting file paths may already log.
+    if matches.is_present("debug") {
         log::set_max_level(log::LevelFilter::Debug);
     }
+    match Command::from_matches(matches)? {
+        Command::Rename(args) => rename(*args),
+        Command::Search(args) => search(&args),
+        Command::Index(args) => index(&args),
+        Command::Data(common) => {
+            if common.read_only_index {
+                anyhow::bail!(
+                    "the IMDb data can't be refreshed with \
+                     --read-only-index"
+                );
+            }
+            common.download_all_update()?;
+            common.create_index()?;
+            Ok(())
+        }
+        Command::Completions(shell) => {
+            app().gen_completions_to("imdb-rename", shell, &mut io::stdout());
+            Ok(())
+        }
+    }
+}
 
-    // Forcefully update the data and re-index if requested.
-    if args.update_data {
-        args.download_all_update()?;
-        args.create_index()?;
-        return Ok(());
+/// Search IMDb with the query given, or print the titles similar to a
+/// title or the episodes of a TV show.
+fn search(args: &SearchArgs) -> anyhow::Result<()> {
+    let common = &args.common;
+    common.prepare()?;
+    let mut searcher = common.searcher()?;
+    if let Some(ref id) = args.similar {
+        if searcher.index().title(id)?.is_none() {
+            anyhow::bail!("could not find title with IMDb identifier {}", id);
+        }
+        let results = searcher.similar(id, 30)?;
+        if common.json {
+            return write_json(
+                events::human(),
+                &mut searcher,
+                results.as_slice(),
+            );
+        }
+        return write_tsv(
+            events::human(),
+            &mut searcher,
+            results.as_slice(),
+            None,
+        );
     }
-    // Ensure that the necessary data exists.
-    if args.download_all()? || args.update_index {
-        args.create_index()?;
-        if args.update_index {
-            return Ok(());
+    if let Some(ref id) = args.episodes {
+        match searcher.index().title(id)? {
+            None => anyhow::bail!(
+                "could not find title with IMDb identifier {}",
+                id
+            ),
+            Some(title) if !title.kind.is_tv_series() => anyhow::bail!(
+                "{} ({}) is a {}, not a TV show",
+                title.title,
+                id,
+                title.kind
+            ),
+            Some(_) => {}
         }
+        return write_season_overview(events::human(), &mut searcher, id);
     }
-    // Now ensure that the index exists.
-    if !args.index_dir.exists() {
-        args.create_index()?;
+    let query: Query = match args.query {
+        None => anyhow::bail!("run with a query, --similar or --episodes"),
+        Some(ref query) => query.parse()?,
+    };
+    let results = run_query(&mut searcher, &query)?;
+    if common.json {
+        return write_json(events::human(), &mut searcher, results.as_slice());
     }
-
-    let mut searcher = args.searcher()?;
-    let results = match args.query {
-        None => None,
-        Some(ref query) => Some(searcher.search(&query.parse()?)?),
+    let highlighter = if common.color {
+        Highlighter::for_query(&mut searcher, &query)?
+    } else {
+        None
     };
+    write_tsv(
+        events::human(),
+        &mut searcher,
+        results.as_slice(),
+        highlighter.as_ref(),
+    )
+}
+
+/// Execute the given query, warning about a scan that was cut short or
+/// explaining why there are no results.
+fn run_query(
+    searcher: &mut Searcher,
+    query: &Query,
+) -> anyhow::Result<SearchResults<MediaEntity>> {
+    let outcome = searcher.search_with_diagnostics(query)?;
+    let diag = &outcome.diagnostics;
+    if diag.truncated {
+        eprintln!(
+            "[warning] scanned {} of {} titles; refine your query or pass \
+             {{exhaustive}}",
+            human_count(diag.scanned),
+            diag.estimated_total.map_or("?".to_string(), human_count),
+        );
+    } else if let Some(ref reason) = outcome.empty_reason {
+        eprintln!("{}", trf("search.no-results", &[reason]));
+    }
+    Ok(outcome.results)
+}
+
+/// Rebuild the index, or print statistics about it.
+fn index(args: &IndexArgs) -> anyhow::Result<()> {
+    let common = &args.common;
+    if args.stats {
+        common.prepare()?;
+        let idx = common.open_index()?;
+        let mut out = events::human();
+        writeln!(out, "{}", idx.stats()?)?;
+        writeln!(out, "{}", idx.name_index_stats(20)?)?;
+        return Ok(());
+    }
+    if common.read_only_index {
+        anyhow::bail!("the index can't be rebuilt with --read-only-index");
+    }
+    common.confirm_download()?;
+    common.download_all()?;
+    common.create_index()?;
+    Ok(())
+}
+
+/// Rename the files given, unless a plan is being applied or the last batch
+/// of renames is being undone.
+fn rename(args: Args) -> anyhow::Result<()> {
+    // Applying a plan only needs the files being renamed, so it happens
+    // before anything touches the IMDb data or index.
+    if let Some(ref path) = args.apply_plan {
+        return apply_plan(&args, path);
+    }
+    if let Some(ref path) = args.undo {
+        return undo(path, &args.files);
+    }
     if args.files.is_empty() {
-        let results = match results {
-            None => anyhow::bail!("run with a file to rename or --query"),
-            Some(ref results) => results,
-        };
-        return write_tsv(io::stdout(), &mut searcher, results.as_slice());
+        anyhow::bail!("run with a file to rename or --query");
     }
+    args.common.prepare()?;
+
+    let mut searcher = args.common.searcher()?;
+    let query: Option<Query> = match args.query {
+        None => None,
+        Some(ref query) => Some(query.parse()?),
+    };
+    let highlighter = match query {
+        Some(ref query) if args.common.color => {
+            Highlighter::for_query(&mut searcher, query)?
+        }
+        _ => None,
+    };
+    let results = match query {
+        None => None,
+        Some(ref query) => Some(run_query(&mut searcher, query)?),
+    };
 
     let mut builder = RenamerBuilder::new();
     builder
+        .events(Arc::new(GlobalSink))
         .min_votes(args.min_votes)
-        .good_threshold(0.25)
+        .preserve_times(args.preserve_times)
+        .symlink_relative(args.symlink_relative)
+        .reflink_fallback(args.reflink_fallback)
+        .skip_in_use(args.skip_in_use)
+        .mkdir(args.mkdir)
+        .dir_perms(args.dir_perms)
+        .write_origin(args.write_origin)
+        .write_nfo(args.write_nfo)
+        .force_nfo(args.force_nfo)
+        .all_files(args.all_files)
+        .non_interactive(args.common.yes || args.common.json)
+        .threads(args.threads)
+        .explain(args.explain)
+        .color(args.common.color)
+        .good_threshold(args.auto_accept_margin)
+        .min_match_ratio(args.min_match_ratio)
+        .episode_gap(args.episode_gap)
         .regex_episode(&args.regex_episode)
         .regex_season(&args.regex_season)
         .regex_year(&args.regex_year);
+    if args.sort_titles {
+        builder.sort_titles(true);
+    }
+    if args.keep_tags {
+        builder.keep_tags(true);
+    }
+    if !args.query_fixups {
+        builder.query_fixups(QueryFixups::none());
+    }
+    if args.ext_lower {
+        builder.extension_case(ExtensionCase::Lower);
+    }
+    for (from, to) in &args.ext_aliases {
+        builder.extension_alias(from, to);
+    }
+    if let Some(ref pattern) = args.regex_season_episode {
+        builder.regex_season_episode(pattern);
+    }
+    if let Some(votes) = args.min_votes_movie {
+        builder.min_votes_movie(votes);
+    }
+    if let Some(votes) = args.min_votes_tvshow {
+        builder.min_votes_tvshow(votes);
+    }
+    if let Some(ref dir) = args.movies_dir {
+        builder.movies_dir(dir);
+    }
+    if let Some(ref dir) = args.tv_dir {
+        builder.tv_dir(dir);
+    }
+    if args.show_years {
+        builder.show_years(true);
+    }
+    if let Some(season) = args.season {
+        builder.season_override(season);
+    }
+    if let Some(episode) = args.episode {
+        builder.episode_override(episode);
+    }
     if let Some(ref results) = results {
-        builder.force(choose(&mut searcher, results.as_slice(), 0.25)?);
+        if (args.common.yes || args.common.json)
+            && !results.is_empty()
+            && auto_choice(
+                results.as_slice(),
+                args.auto_accept_margin,
+                args.min_match_ratio,
+            )
+            .index()
+            .is_none()
+        {
+            anyhow::bail!("{}", tr("error.query-undecided"));
+        }
+        builder.force(choose(
+            &mut searcher,
+            results.as_slice(),
+            args.auto_accept_margin,
+            args.min_match_ratio,
+            highlighter.as_ref(),
+        )?);
     }
     let renamer = builder.build()?;
-    let proposals = renamer.propose(
+    if args.dry_run {
+        let (mut proposals, skipped) = renamer.propose_pure(
+            &mut searcher,
+            &args.files,
+            args.dest_dir.clone(),
+            args.rename_action,
+        )?;
+        for p in &mut proposals {
+            p.detect_cross_device();
+        }
+        if args.explain && !args.common.json {
+            write_explanations(events::human(), &renamer.explanations())?;
+        }
+        write_proposals_or_json(&args, &renamer, &proposals)?;
+        emit_proposals(&proposals);
+        for check in &skipped {
+            eprintln!("{}", trf("proposal.unchecked", &[check]));
+        }
+        write_skip_summary(&renamer, args.only, args.excluded_by_only);
+        if args.stats && !args.common.json {
+            write_stats(&renamer)?;
+        }
+        return check_undecided(&renamer);
+    }
+    let log = args.common.journal_log();
+    let mut proposals = renamer.propose(
         &mut searcher,
         &args.files,
-        args.dest_dir,
+        args.dest_dir.clone(),
         args.rename_action,
     )?;
+    if args.explain && !args.common.json {
+        write_explanations(events::human(), &renamer.explanations())?;
+    }
+    write_skip_summary(&renamer, args.only, args.excluded_by_only);
+    if args.stats && !args.common.json {
+        write_stats(&renamer)?;
+    }
+    if proposals.is_empty() {
+        check_undecided(&renamer)?;
+        anyhow::bail!("{}", tr("error.no-files"));
+    }
+    check_destinations(&mut proposals, args.common.yes || args.common.json)?;
+    for p in &mut proposals {
+        p.detect_cross_device();
+    }
+    write_proposals_or_json(&args, &renamer, &proposals)?;
+    emit_proposals(&proposals);
+
+    if let Some(ref path) = args.export_plan {
+        plan::export(path, &proposals)?;
+        eprintln!(
+            "{}",
+            trf("plan.written", &[&proposals.len(), &path.display()])
+        );
+        return check_undecided(&renamer);
+    }
+    // In JSON mode, the proposals are only printed, without prompting, so
+    // that other programs can decide what to do with them.
+    if args.common.json {
+        return Ok(());
+    }
+    if args.common.yes
+        || read_yesno(tr(match args.rename_action {
+            RenameAction::Rename => "prompt.confirm-rename",
+            RenameAction::Symlink => "prompt.confirm-symlink",
+            RenameAction::Hardlink => "prompt.confirm-hardlink",
+            RenameAction::Copy => "prompt.confirm-copy",
+            RenameAction::Reflink => "prompt.confirm-reflink",
+        }))?
+    {
+        if !confirm_cross_device(
+            &proposals,
+            args.common.yes,
+            args.allow_cross_device,
+        )? {
+            return check_undecided(&renamer);
+        }
+        execute(&mut proposals, args.transactional, &log)?;
+    }
+    check_undecided(&renamer)
+}
+
+/// Execute the plan at the given path, after validating it and asking the
+/// end user for confirmation, unless --yes was given.
+fn apply_plan(args: &Args, path: &Path) -> anyhow::Result<()> {
+    let mut proposals = plan::validate(plan::import(path)?)?;
     if proposals.is_empty() {
-        anyhow::bail!("no files to rename");
+        eprintln!("{}", trf("plan.done", &[&path.display()]));
+        return Ok(());
+    }
+    check_destinations(&mut proposals, args.common.yes)?;
+    for p in &mut proposals {
+        p.detect_cross_device();
+    }
+    write_proposals(
+        events::human(),
+        &proposals,
+        args.verbose_proposals,
+        args.common.color,
+    )?;
+    emit_proposals(&proposals);
+    if (args.common.yes || read_yesno(tr("prompt.apply-plan"))?)
+        && confirm_cross_device(
+            &proposals,
+            args.common.yes,
+            args.allow_cross_device,
+        )?
+    {
+        execute(
+            &mut proposals,
+            args.transactional,
+            &args.common.journal_log(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Ask the end user to confirm the proposals that rename across file
+/// systems, which copies their sources and then removes them. This returns
+/// true if there are none, or if they are allowed by --allow-cross-device
+/// (`allow`).
+///
+/// With --yes (`yes`), nobody can confirm, so an error is returned instead
+/// unless they are allowed.
+fn confirm_cross_device(
+    proposals: &[RenameProposal],
+    yes: bool,
+    allow: bool,
+) -> anyhow::Result<bool> {
+    let sizes: Vec<u64> =
+        proposals.iter().filter_map(|p| p.cross_device()).collect();
+    if sizes.is_empty() || allow {
+        return Ok(true);
+    }
+    let total = human_bytes(sizes.iter().sum());
+    if yes {
+        anyhow::bail!(
+            "{}",
+            trf("error.cross-device", &[&sizes.len(), &total])
+        );
+    }
+    read_yesno(&trf("prompt.cross-device", &[&sizes.len(), &total]))
+}
+
+/// Report how many files were skipped, if any, so that skips reported while
+/// proposing renames aren't lost in the output of a big batch. Entries left
+/// out by --only are counted separately, since they were never proposed.
+fn write_skip_summary(
+    renamer: &Renamer,
+    only: Option<EntryKind>,
+    excluded_by_only: usize,
+) {
+    if let Some(only) = only {
+        if excluded_by_only > 0 {
+            let msg = trn(
+                "summary.excluded-one",
+                "summary.excluded-other",
+                excluded_by_only,
+                &[&excluded_by_only, &only.name()],
+            );
+            eprintln!("{}", msg);
+        }
+    }
+    let count = renamer.skip_count();
+    if count > 0 {
+        eprintln!("{}", trf("summary.skipped", &[&count]));
+    }
+}
+
+/// The number of buckets in the score histogram of --stats. Each bucket
+/// covers a tenth of the range of scores, which is `[0, 1]`.
+const SCORE_BUCKETS: usize = 10;
+
+/// How the proposals of a run were matched to their entities, aggregated
+/// from `Renamer::match_stats` for --stats.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct MatchReport {
+    /// The number of proposals.
+    matched: usize,
+    /// The number of proposals whose search result stood out.
+    automatic: usize,
+    /// The number of proposals whose search result was picked by the end
+    /// user.
+    prompted: usize,
+    /// The number of proposals that used the entity override.
+    overridden: usize,
+    /// The number of files skipped, for any reason.
+    skipped: usize,
+    /// The number of files skipped because no search result stood out and
+    /// prompting was disabled. These are included in `skipped`.
+    undecided: usize,
+    /// The number of scores in each bucket, lowest first. See
+    /// `score_bucket`.
+    scores: [usize; SCORE_BUCKETS],
+    /// The number of proposals without a score, since no search chose their
+    /// entity.
+    unscored: usize,
+    /// The number of proposals per pattern that their source's name was
+    /// parsed with, by the pattern's name.
+    patterns: BTreeMap<&'static str, usize>,
+}
+
+impl MatchReport {
+    /// Aggregate the given statistics, along with the number of files
+    /// skipped for any reason and the number of those that were undecided.
+    fn new(
+        stats: &[MatchStat],
+        skipped: usize,
+        undecided: usize,
+    ) -> MatchReport {
+        let mut report = MatchReport {
+            matched: stats.len(),
+            skipped,
+            undecided,
+            ..MatchReport::default()
+        };
+        for stat in stats {
+            match stat.decision {
+                Decision::Automatic => report.automatic += 1,
+                Decision::Prompted => report.prompted += 1,
+                Decision::Override => report.overridden += 1,
+            }
+            match stat.score {
+                None => report.unscored += 1,
+                Some(score) => report.scores[score_bucket(score)] += 1,
+            }
+            *report.patterns.entry(stat.pattern.name()).or_insert(0) += 1;
+        }
+        report
+    }
+
+    /// Aggregate the statistics of the proposals made so far by the given
+    /// renamer.
+    fn from_renamer(renamer: &Renamer) -> MatchReport {
+        MatchReport::new(
+            &renamer.match_stats(),
+            renamer.skip_count(),
+            renamer.undecided_count(),
+        )
+    }
+
+    /// Write this report as text. Score buckets are listed from the highest
+    /// down to the lowest one that isn't empty, each with a bar scaled to
+    /// the fullest bucket.
+    fn write_text<W: Write>(&self, wtr: W) -> anyhow::Result<()> {
+        const BAR_WIDTH: usize = 30;
+
+        let mut wtr = TabWriter::new(wtr);
+        writeln!(
+            wtr,
+            "{}",
+            trf(
+                "stats.matched",
+                &[
+                    &self.matched,
+                    &self.automatic,
+                    &self.prompted,
+                    &self.overridden
+                ]
+            ),
+        )?;
+        writeln!(
+            wtr,
+            "{}",
+            trf("stats.skipped", &[&self.skipped, &self.undecided])
+        )?;
+        let lowest = self.scores.iter().position(|&n| n > 0);
+        if lowest.is_some() || self.unscored > 0 {
+            writeln!(wtr, "{}", tr("stats.scores"))?;
+        }
+        let fullest = self.scores.iter().copied().max().unwrap_or(0);
+        for i in (lowest.unwrap_or(SCORE_BUCKETS)..SCORE_BUCKETS).rev() {
+            let count = self.scores[i];
+            write!(
+                wtr,
+                "  {:.1}-{:.1}\t{}",
+                i as f64 / SCORE_BUCKETS as f64,
+                (i + 1) as f64 / SCORE_BUCKETS as f64,
+                count,
+            )?;
+            if count > 0 {
+                let bar = (count * BAR_WIDTH).div_ceil(fullest);
+                write!(wtr, "\t{}", "#".repeat(bar))?;
+            }
+            writeln!(wtr)?;
+        }
+        if self.unscored > 0 {
+            writeln!(wtr, "  {}\t{}", tr("stats.unscored"), self.unscored)?;
+        }
+        if !self.patterns.is_empty() {
+            writeln!(wtr, "{}", tr("stats.patterns"))?;
+        }
+        for (name, count) in &self.patterns {
+            writeln!(wtr, "  {}\t{}", name, count)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Return the index of the histogram bucket of --stats that the given score
+/// belongs to. Scores outside of `[0, 1]` are put in the nearest bucket,
+/// and a perfect score belongs to the highest bucket.
+fn score_bucket(score: f64) -> usize {
+    let i = (score * SCORE_BUCKETS as f64).floor();
+    if i.is_nan() || i < 0.0 {
+        0
+    } else {
+        (i as usize).min(SCORE_BUCKETS - 1)
+    }
+}
+
+/// Report how the proposals made so far by the given renamer were matched,
+/// as text to stderr, along with the other summaries.
+fn write_stats(renamer: &Renamer) -> anyhow::Result<()> {
+    MatchReport::from_renamer(renamer).write_text(io::stderr())
+}
+
+/// Return an error if any files were skipped because no search result stood
+/// out for them and --yes disabled prompting, so that scripts notice that
+/// not everything was renamed.
+fn check_undecided(renamer: &Renamer) -> anyhow::Result<()> {
+    let count = renamer.undecided_count();
+    if count > 0 {
+        anyhow::bail!("{}", trf("error.undecided", &[&count]));
+    }
+    Ok(())
+}
+
+/// Write the given proposals as a table.
+///
+/// When `verbose` is true, each proposal is followed by a line summarizing
+/// the IMDb entity it was matched to under its destination. The
+/// line is dimmed when `color` is true.
+fn write_proposals<W: Write>(
+    wtr: W,
+    proposals: &[RenameProposal],
+    verbose: bool,
+    color: bool,
+) -> anyhow::Result<()> {
+    let mut wtr = TabWriter::new(wtr);
+    for p in proposals {
+        write!(wtr, "{}\t->\t{}", p.src().display(), p.dst().display())?;
+        match p.cross_device() {
+            None => writeln!(wtr)?,
+            Some(size) => writeln!(
+                wtr,
+                "\t({})",
+                trf("note.cross-device", &[&human_bytes(size)])
+            )?,
+        }
+        if !verbose {
+            continue;
+        }
+        if color {
+            writeln!(wtr, "\t\t\x1b[2m{}\x1b[0m", p.matched())?;
+        } else {
+            writeln!(wtr, "\t\t{}", p.matched())?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Print the given proposals to stdout, either as a table or, in JSON mode,
+/// as a single JSON value along with the explanations of --explain-rename
+/// and the report of --stats, if they were asked for. See
+/// `write_proposals_json`.
+fn write_proposals_or_json(
+    args: &Args,
+    renamer: &Renamer,
+    proposals: &[RenameProposal],
+) -> anyhow::Result<()> {
+    if !args.common.json {
+        return write_proposals(
+            events::human(),
+            proposals,
+            args.verbose_proposals,
+            args.common.color,
+        );
     }
+    let explanations =
+        if args.explain { Some(renamer.explanations()) } else { None };
+    let stats = if args.stats {
+        Some(MatchReport::from_renamer(renamer))
+    } else {
+        None
+    };
+    write_proposals_json(
+        events::human(),
+        proposals,
+        explanations.as_deref(),
+        stats.as_ref(),
+    )
+}
 
-    let mut stdout = TabWriter::new(io::stdout());
-    for p in &proposals {
-        writeln!(stdout, "{}\t->\t{}", p.src().display(), p.dst().display())?;
+/// Write the given proposals as a JSON array, with one object per proposal
+/// containing its source, destination and action, along with a summary of
+/// the IMDb entity it was matched to. See `MatchSummary`.
+///
+/// If explanations or a --stats report are given, then an object is written
+/// instead, with the array under a `proposals` key and the rest under
+/// `explanations` and `stats` keys. Either way, exactly one JSON value is
+/// written, so that the output can be parsed in one go.
+fn write_proposals_json<W: Write>(
+    mut wtr: W,
+    proposals: &[RenameProposal],
+    explanations: Option<&[Explanation]>,
+    stats: Option<&MatchReport>,
+) -> anyhow::Result<()> {
+    let rows: Vec<ProposalJson> = proposals
+        .iter()
+        .map(|p| ProposalJson {
+            src: p.src(),
+            dst: p.dst(),
+            action: p.action(),
+            matched: p.matched(),
+        })
+        .collect();
+    if explanations.is_none() && stats.is_none() {
+        serde_json::to_writer(&mut wtr, &rows)?;
+    } else {
+        let report = ReportJson { proposals: rows, explanations, stats };
+        serde_json::to_writer(&mut wtr, &report)?;
     }
-    stdout.flush()?;
+    writeln!(wtr)?;
+    wtr.flush()?;
+    Ok(())
+}
 
-    if read_yesno(&format!(
-        "Are you sure you want to {action} the above files? (y/n) ",
-        action = &args.rename_action
-    ))? {
-        for p in &proposals {
-            if let Err(err) = p.rename() {
+/// The proposals written by `write_proposals_json`, along with the
+/// explanations and --stats report that were asked for.
+#[derive(Debug, Serialize)]
+struct ReportJson<'a> {
+    proposals: Vec<ProposalJson<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanations: Option<&'a [Explanation]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a MatchReport>,
+}
+
+/// A single proposal written by `write_proposals_json`.
+#[derive(Debug, Serialize)]
+struct ProposalJson<'a> {
+    src: &'a Path,
+    dst: &'a Path,
+    action: RenameAction,
+    #[serde(flatten)]
+    matched: MatchSummary,
+}
+
+/// Emit a proposal event for each of the given proposals.
+fn emit_proposals(proposals: &[RenameProposal]) {
+    for p in proposals {
+        events::emit(&Event::Proposal {
+            src: p.src(),
+            dst: p.dst(),
+            action: p.action(),
+            matched: &p.matched(),
+        });
+    }
+}
+
+/// Reverse the most recent batch of renames in the journal at the given
+/// path. Entries that can't be reversed are reported and skipped, and an
+/// error is returned after everything else has been reversed.
+///
+/// If the journal has nothing to undo, then the given files are renamed
+/// back to the original names recorded by --write-origin instead.
+fn undo(path: &Path, files: &[PathBuf]) -> anyhow::Result<()> {
+    let undone = if !files.is_empty() && journal::is_empty(path)? {
+        journal::undo_origins(files)?
+    } else {
+        journal::undo_last(path)?
+    };
+    for entry in &undone.undone {
+        match entry.action() {
+            RenameAction::Rename => eprintln!(
+                "{}",
+                trf(
+                    "undo.renamed",
+                    &[&entry.dst().display(), &entry.src().display()]
+                )
+            ),
+            action => eprintln!(
+                "{}",
+                trf("undo.removed", &[&action, &entry.dst().display()])
+            ),
+        }
+    }
+    for err in &undone.skipped {
+        eprintln!("{}", trf("undo.skipped", &[err]));
+    }
+    if !undone.skipped.is_empty() {
+        anyhow::bail!(
+            "{}",
+            trf(
+                "error.undo-incomplete",
+                &[
+                    &undone.undone.len(),
+                    &(undone.undone.len() + undone.skipped.len()),
+                ]
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Execute the given proposals in order, appending each one that succeeds
+/// to the given log.
+///
+/// When `transactional` is true, the first failure rolls back everything
+/// executed so far. Otherwise, failures are reported and the rest of the
+/// proposals are still executed.
+fn execute(
+    proposals: &mut [RenameProposal],
+    transactional: bool,
+    log: &Log,
+) -> anyhow::Result<()> {
+    if transactional {
+        let events = Some(&GlobalSink as &dyn EventSink);
+        for warning in execute_transaction(proposals, Some(log), events)? {
+            eprintln!("[warning] {}", warning);
+        }
+        return Ok(());
+    }
+    for i in 0..proposals.len() {
+        let (done, rest) = proposals.split_at_mut(i + 1);
+        match done[i].rename() {
+            Ok(executed) => {
+                events::emit(&Event::Renamed {
+                    src: done[i].src(),
+                    dst: done[i].dst(),
+                    action: done[i].action(),
+                });
+                if let Err(err) = log.append(&done[i], &executed) {
+                    eprintln!("{}", err);
+                }
+                for warning in &executed.warnings {
+                    eprintln!("[warning] {}", warning);
+                }
+                for later in rest {
+                    later.rebase(&done[i]);
+                }
+            }
+            Err(err) => {
                 eprintln!("{}", err);
+                events::emit(&Event::Error {
+                    error: format!("{:#}", err),
+                    code: error_code(&err),
+                });
             }
         }
     }
     Ok(())
 }
 
+/// Write the given explanations as indented blocks of text separated by
+/// blank lines.
+fn write_explanations<W: Write>(
+    mut wtr: W,
+    explanations: &[Explanation],
+) -> anyhow::Result<()> {
+    for (i, exp) in explanations.iter().enumerate() {
+        if i > 0 {
+            writeln!(wtr)?;
+        }
+        exp.write_text(&mut wtr)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Check that every proposed destination is valid on the file system that
+/// it will be written to. If there are problems, then they are all reported
+/// and the user is offered the option of sanitizing the names. When `yes` is
+/// true, the names are sanitized without asking.
+fn check_destinations(
+    proposals: &mut [RenameProposal],
+    yes: bool,
+) -> anyhow::Result<()> {
+    let mut validator = Validator::new(SystemProbe);
+    let violations = validator.check(proposals)?;
+    if violations.is_empty() {
+        return Ok(());
+    }
+    for v in &violations {
+        eprintln!("[invalid] {}", v);
+    }
+    if !yes && !read_yesno(tr("prompt.sanitize"))? {
+        anyhow::bail!("{}", tr("error.invalid-destinations"));
+    }
+    validator.sanitize(proposals)?;
+    let violations = validator.check(proposals)?;
+    if !violations.is_empty() {
+        for v in &violations {
+            eprintln!("[invalid] {}", v);
+        }
+        anyhow::bail!("could not sanitize all destinations");
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 struct Args {
-    data_dir: PathBuf,
+    common: CommonArgs,
     dest_dir: Option<PathBuf>,
-    debug: bool,
     files: Vec<PathBuf>,
-    index_dir: PathBuf,
-    ngram_size: usize,
-    ngram_type: NgramType,
+    only: Option<EntryKind>,
+    excluded_by_only: usize,
     query: Option<String>,
     regex_episode: String,
+    episode_gap: usize,
     regex_season: String,
+    regex_season_episode: Option<String>,
     regex_year: String,
-    update_data: bool,
-    update_index: bool,
     min_votes: u32,
+    min_votes_movie: Option<u32>,
+    min_votes_tvshow: Option<u32>,
     rename_action: RenameAction,
+    preserve_times: bool,
+    symlink_relative: bool,
+    reflink_fallback: bool,
+    transactional: bool,
+    allow_cross_device: bool,
+    skip_in_use: bool,
+    mkdir: bool,
+    dir_perms: DirPerms,
+    write_origin: Option<OriginFormat>,
+    write_nfo: bool,
+    force_nfo: bool,
+    all_files: bool,
+    explain: bool,
+    dry_run: bool,
+    verbose_proposals: bool,
+    stats: bool,
+    export_plan: Option<PathBuf>,
+    apply_plan: Option<PathBuf>,
+    undo: Option<PathBuf>,
+    threads: usize,
+    sort_titles: bool,
+    keep_tags: bool,
+    query_fixups: bool,
+    ext_lower: bool,
+    ext_aliases: Vec<(String, String)>,
+    auto_accept_margin: f64,
+    min_match_ratio: f64,
+    movies_dir: Option<PathBuf>,
+    tv_dir: Option<PathBuf>,
+    show_years: bool,
+    season: Option<u32>,
+    episode: Option<u32>,
 }
 
 impl Args {
-    fn from_matches(matches: &clap::ArgMatches) -> anyhow::Result<Args> {
+    fn from_matches(matches: &Matches) -> anyhow::Result<Args> {
+        let mut dir_filter = DirFilter::new();
+        for name in matches.values_of_lossy("include-dir").unwrap_or_default()
+        {
+            dir_filter.include(&name);
+        }
+        for name in matches.values_of_lossy("exclude-dir").unwrap_or_default()
+        {
+            dir_filter.exclude(&name);
+        }
+        dir_filter
+            .case_sensitive_globs(matches.is_present("case-sensitive-globs"));
+        for glob in matches.values_of_lossy("exclude").unwrap_or_default() {
+            dir_filter.exclude_glob(&glob)?;
+        }
+        for exts in matches.values_of_lossy("ext").unwrap_or_default() {
+            for ext in exts.split(',') {
+                dir_filter.allow_extension(ext);
+            }
+        }
         let files = collect_paths(
             matches
                 .values_of_os("file")
                 .map(|it| it.collect())
                 .unwrap_or(vec![]),
-            matches.is_present("follow"),
+            matches.is_present("follow") || matches.is_present("recursive"),
+            &dir_filter,
         );
+        let only =
+            matches.value_of("only").map(EntryKind::from_name).transpose()?;
+        let (files, excluded_by_only) = select_entries(files, only);
         let query = matches.value_of_lossy("query").map(|q| q.into_owned());
-        let data_dir =
-            matches.value_of_os("data-dir").map(PathBuf::from).unwrap();
+        let common = CommonArgs::from_matches(matches)?;
         let dest_dir = matches.value_of_os("dest-dir").map(PathBuf::from);
-        let index_dir = matches
-            .value_of_os("index-dir")
-            .map(PathBuf::from)
-            .unwrap_or(data_dir.join("index"));
+        let undo = if matches.is_present("undo") {
+            Some(
+                matches
+                    .value_of_os("undo")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| common.journal_path()),
+            )
+        } else {
+            None
+        };
         let regex_episode =
             matches.value_of_lossy("re-episode").unwrap().into_owned();
         let regex_season =
@@ -152,8 +1032,42 @@ impl Args {
         let regex_year =
             matches.value_of_lossy("re-year").unwrap().into_owned();
         let min_votes = matches.value_of_lossy("votes").unwrap().parse()?;
+        let min_votes_movie = match matches.value_of_lossy("votes-movie") {
+            None => None,
+            Some(votes) => Some(votes.parse()?),
+        };
+        let min_votes_tvshow = match matches.value_of_lossy("votes-tv") {
+            None => None,
+            Some(votes) => Some(votes.parse()?),
+        };
+        let season = match matches.value_of_lossy("season") {
+            None => None,
+            Some(season) => Some(season.parse()?),
+        };
+        let episode = match matches.value_of_lossy("episode") {
+            None => None,
+            Some(episode) => Some(episode.parse()?),
+        };
+        if (season.is_some() || episode.is_some())
+            && files.len() > 1
+            && query.is_none()
+        {
+            anyhow::bail!(
+                "--season and --episode can only be used with a single file \
+                 or with -q/--query"
+            );
+        }
+        let mut dir_perms = DirPerms::default();
+        if let Some(mode) = matches.value_of_lossy("dir-mode") {
+            dir_perms.mode = Some(DirPerms::parse_mode(&mode)?);
+        }
+        if let Some(owner) = matches.value_of_lossy("chown") {
+            (dir_perms.uid, dir_perms.gid) = DirPerms::parse_owner(&owner)?;
+        }
         let rename_action = {
-            if matches.is_present("symlink") {
+            if matches.is_present("symlink")
+                || matches.is_present("symlink-relative")
+            {
                 if !cfg!(unix) {
                     anyhow::bail!(
                         "--symlink currently supported only on Unix \
@@ -163,16 +1077,120 @@ impl Args {
                 RenameAction::Symlink
             } else if matches.is_present("hardlink") {
                 RenameAction::Hardlink
+            } else if matches.is_present("copy") {
+                RenameAction::Copy
+            } else if matches.is_present("reflink") {
+                if !cfg!(target_os = "linux") {
+                    anyhow::bail!(
+                        "--reflink currently supported only on Linux, \
+                         try copy (-c) instead"
+                    );
+                }
+                RenameAction::Reflink
             } else {
                 RenameAction::Rename
             }
         };
         Ok(Args {
-            data_dir: data_dir,
-            dest_dir: dest_dir,
-            debug: matches.is_present("debug"),
-            files: files,
-            index_dir: index_dir,
+            common,
+            dest_dir,
+            files,
+            only,
+            excluded_by_only,
+            query,
+            regex_episode,
+            episode_gap: matches
+                .value_of_lossy("episode-gap")
+                .unwrap()
+                .parse()?,
+            regex_season,
+            regex_season_episode: matches
+                .value_of_lossy("re-season-episode")
+                .map(|re| re.into_owned()),
+            regex_year,
+            min_votes,
+            min_votes_movie,
+            min_votes_tvshow,
+            rename_action,
+            preserve_times: matches.is_present("preserve-times"),
+            symlink_relative: matches.is_present("symlink-relative"),
+            reflink_fallback: matches.value_of("reflink") == Some("auto"),
+            transactional: matches.is_present("transactional"),
+            allow_cross_device: matches.is_present("allow-cross-device"),
+            skip_in_use: matches.is_present("skip-in-use"),
+            mkdir: matches.is_present("mkdir"),
+            dir_perms,
+            write_origin: matches
+                .value_of("write-origin")
+                .map(OriginFormat::from_name)
+                .transpose()?,
+            write_nfo: matches.is_present("write-nfo"),
+            force_nfo: matches.is_present("force-nfo"),
+            all_files: matches.is_present("all-files"),
+            explain: matches.is_present("explain-rename"),
+            dry_run: matches.is_present("dry-run"),
+            verbose_proposals: matches.is_present("verbose-proposals"),
+            stats: matches.is_present("stats"),
+            export_plan: matches.value_of_os("export-plan").map(PathBuf::from),
+            apply_plan: matches.value_of_os("apply-plan").map(PathBuf::from),
+            undo,
+            threads: matches.value_of_lossy("threads").unwrap().parse()?,
+            sort_titles: matches.is_present("sort-titles"),
+            keep_tags: matches.is_present("keep-tags"),
+            query_fixups: !matches.is_present("no-query-fixups"),
+            ext_lower: matches.is_present("ext-lower"),
+            ext_aliases: matches
+                .values_of_lossy("ext-alias")
+                .unwrap_or_default()
+                .iter()
+                .map(|alias| parse_ext_alias(alias))
+                .collect::<anyhow::Result<_>>()?,
+            auto_accept_margin: parse_unit(
+                "auto-accept-margin",
+                &matches.value_of_lossy("auto-accept-margin").unwrap(),
+            )?,
+            min_match_ratio: parse_unit(
+                "min-match-ratio",
+                &matches.value_of_lossy("min-match-ratio").unwrap(),
+            )?,
+            movies_dir: matches.value_of_os("movies-dir").map(PathBuf::from),
+            tv_dir: matches.value_of_os("tv-dir").map(PathBuf::from),
+            show_years: matches.is_present("show-years"),
+            season,
+            episode,
+        })
+    }
+}
+
+/// The flags shared by every subcommand, which say where the IMDb data and
+/// index are and how to search and print results.
+#[derive(Debug)]
+struct CommonArgs {
+    data_dir: PathBuf,
+    index_dir: PathBuf,
+    ngram_size: usize,
+    ngram_type: NgramType,
+    auto_rebuild: bool,
+    read_only_index: bool,
+    color: bool,
+    yes: bool,
+    json: bool,
+    max_scan_rows: Option<usize>,
+    max_scan_time: Option<Duration>,
+}
+
+impl CommonArgs {
+    fn from_matches(matches: &Matches) -> anyhow::Result<CommonArgs> {
+        matches.check_requires()?;
+        let data_dir =
+            matches.value_of_os("data-dir").map(PathBuf::from).unwrap();
+        let index_dir = matches
+            .value_of_os("index-dir")
+            .map(PathBuf::from)
+            .unwrap_or(data_dir.join("index"));
+        Ok(CommonArgs {
+            data_dir,
+            index_dir,
             ngram_size: matches
                 .value_of_lossy("ngram-size")
                 .unwrap()
@@ -181,30 +1199,131 @@ impl Args {
                 .value_of_lossy("ngram-type")
                 .unwrap()
                 .parse()?,
-            query: query,
-            regex_episode: regex_episode,
-            regex_season: regex_season,
-            regex_year: regex_year,
-            update_data: matches.is_present("update-data"),
-            update_index: matches.is_present("update-index"),
-            min_votes: min_votes,
-            rename_action: rename_action,
+            auto_rebuild: !matches.is_present("no-auto-rebuild")
+                && !matches.is_present("read-only-index"),
+            read_only_index: matches.is_present("read-only-index"),
+            color: use_color(&matches.value_of_lossy("color").unwrap()),
+            yes: matches.is_present("yes"),
+            json: matches.is_present("json"),
+            max_scan_rows: match matches.value_of_lossy("max-scan-rows") {
+                None => None,
+                Some(rows) => Some(rows.parse()?),
+            },
+            max_scan_time: {
+                let secs = matches.value_of_lossy("max-scan-time").unwrap();
+                match Duration::try_from_secs_f64(secs.parse()?) {
+                    Ok(time) if time.is_zero() => None,
+                    Ok(time) => Some(time),
+                    Err(_) => anyhow::bail!(
+                        "max scan time {} is invalid, must be 0 or greater",
+                        secs
+                    ),
+                }
+            },
         })
     }
 
+    /// Make sure that the IMDb data has been downloaded and indexed, which
+    /// happens automatically on the first run. A read-only index is used as
+    /// is, since neither it nor the data it was built from can be written.
+    fn prepare(&self) -> anyhow::Result<()> {
+        if self.read_only_index {
+            return Ok(());
+        }
+        let first_run = self.confirm_download()?;
+        if self.download_all()? {
+            self.create_index()?;
+        }
+        // Now ensure that the index exists. Its config is written last, so
+        // an index whose creation was interrupted is created again.
+        if !Index::exists(&self.index_dir) {
+            self.create_index()?;
+        }
+        if first_run {
+            eprintln!("[ready] the IMDb data has been downloaded and indexed");
+        }
+        Ok(())
+    }
+
+    /// On the first run, explain the (large) download that's about to
+    /// happen and give the user a chance to back out. This returns whether
+    /// this is the first run.
+    fn confirm_download(&self) -> anyhow::Result<bool> {
+        let first_run = !self.data_dir.exists();
+        if first_run {
+            eprint!(
+                "{}",
+                download::first_run_message(&self.data_dir, &self.index_dir)
+            );
+            if !self.yes
+                && !read_yesno(&format!("\n{}", tr("prompt.download")))?
+            {
+                anyhow::bail!(
+                    "the IMDb data is required to continue, pass --yes to \
+                     download it without asking"
+                );
+            }
+        }
+        Ok(first_run)
+    }
+
     fn create_index(&self) -> anyhow::Result<Index> {
-        Ok(IndexBuilder::new()
+        if self.read_only_index {
+            anyhow::bail!(
+                "the index at {} needs to be created, but --read-only-index \
+                 was given",
+                self.index_dir.display()
+            );
+        }
+        eprintln!(
+            "indexing IMDb data in {} (this can take a few minutes)",
+            self.index_dir.display()
+        );
+        let dir = &self.index_dir;
+        let start = Instant::now();
+        events::emit(&Event::Index { dir, status: Status::Started });
+        let idx = IndexBuilder::new()
             .ngram_size(self.ngram_size)
             .ngram_type(self.ngram_type)
-            .create(&self.data_dir, &self.index_dir)?)
+            .create(&self.data_dir, &self.index_dir)?;
+        events::emit(&Event::Index { dir, status: Status::Finished });
+        eprintln!(
+            "indexed IMDb data in {:.1}s",
+            start.elapsed().as_secs_f64()
+        );
+        Ok(idx)
     }
 
+    /// Open the index, rebuilding it first if it was created by an
+    /// incompatible version and automatic rebuilds are enabled.
     fn open_index(&self) -> anyhow::Result<Index> {
-        Ok(Index::open(&self.data_dir, &self.index_dir)?)
+        match Index::open(&self.data_dir, &self.index_dir) {
+            Ok(idx) => Ok(idx),
+            Err(err) => match *err.kind() {
+                ErrorKind::VersionMismatch { expected, got }
+                    if self.auto_rebuild =>
+                {
+                    eprintln!(
+                        "[rebuild] index at {} has version {} but version {} \
+                         is required, rebuilding it now",
+                        self.index_dir.display(),
+                        got,
+                        expected,
+                    );
+                    self.create_index()
+                }
+                _ => Err(err.into()),
+            },
+        }
     }
 
     fn searcher(&self) -> anyhow::Result<Searcher> {
-        Ok(Searcher::new(self.open_index()?))
+        let mut searcher = Searcher::new(self.open_index()?);
+        searcher
+            .max_scan_rows(self.max_scan_rows)
+            .max_scan_time(self.max_scan_time)
+            .progress(Some(progress_printer("titles")));
+        Ok(searcher)
     }
 
     fn download_all(&self) -> anyhow::Result<bool> {
@@ -214,11 +1333,201 @@ impl Args {
     fn download_all_update(&self) -> anyhow::Result<()> {
         download::update_all(&self.data_dir)
     }
+
+    /// The path of the log that executed renames are appended to.
+    fn journal_path(&self) -> PathBuf {
+        self.data_dir.join(journal::LOG_NAME)
+    }
+
+    /// The log that executed renames are appended to, for --undo.
+    fn journal_log(&self) -> Log {
+        Log::new(self.journal_path())
+    }
 }
 
+/// The flags of the search subcommand.
+#[derive(Debug)]
+struct SearchArgs {
+    common: CommonArgs,
+    query: Option<String>,
+    similar: Option<String>,
+    episodes: Option<String>,
+}
+
+impl SearchArgs {
+    fn from_matches(matches: &Matches) -> anyhow::Result<SearchArgs> {
+        Ok(SearchArgs {
+            common: CommonArgs::from_matches(matches)?,
+            // The search subcommand takes the query as any number of
+            // arguments, which are joined like the words of a phrase.
+            query: matches.values_of_lossy("query").map(|q| q.join(" ")),
+            similar: matches
+                .value_of_lossy("similar")
+                .map(|id| id.into_owned()),
+            episodes: matches
+                .value_of_lossy("episodes")
+                .map(|id| id.into_owned()),
+        })
+    }
+}
+
+/// The flags of the index subcommand.
+#[derive(Debug)]
+struct IndexArgs {
+    common: CommonArgs,
+    /// Print statistics about the index instead of rebuilding it.
+    stats: bool,
+}
+
+/// What imdb-rename was asked to do, as chosen by a subcommand.
+#[derive(Debug)]
+enum Command {
+    /// Rename files.
+    Rename(Box<Args>),
+    /// Search IMDb, or list similar titles or the episodes of a TV show.
+    Search(SearchArgs),
+    /// Rebuild the index, or print statistics about it.
+    Index(IndexArgs),
+    /// Refresh the IMDb data and rebuild the index.
+    Data(CommonArgs),
+    /// Print a completion script for the given shell.
+    Completions(clap::Shell),
+}
+
+impl Command {
+    fn from_matches(matches: &Matches) -> anyhow::Result<Command> {
+        Ok(match matches.command() {
+            None => Command::from_flags(matches)?,
+            Some("rename") => {
+                Command::Rename(Box::new(Args::from_matches(matches)?))
+            }
+            Some("search") => {
+                Command::Search(SearchArgs::from_matches(matches)?)
+            }
+            Some("index") => Command::Index(IndexArgs {
+                common: CommonArgs::from_matches(matches)?,
+                stats: matches.is_present("stats"),
+            }),
+            Some("data") => Command::Data(CommonArgs::from_matches(matches)?),
+            Some("completions") => Command::Completions(
+                matches
+                    .value_of("shell")
+                    .unwrap()
+                    .parse()
+                    .map_err(anyhow::Error::msg)?,
+            ),
+            Some(name) => anyhow::bail!("unknown subcommand '{}'", name),
+        })
+    }
+
+    /// Choose what to do without a subcommand, which renames files unless
+    /// the flags given select another subcommand. This is how imdb-rename
+    /// worked before it had subcommands.
+    fn from_flags(matches: &Matches) -> anyhow::Result<Command> {
+        if matches.is_present("update-data") {
+            return Ok(Command::Data(CommonArgs::from_matches(matches)?));
+        }
+        if matches.is_present("update-index")
+            || matches.is_present("index-stats")
+        {
+            return Ok(Command::Index(IndexArgs {
+                common: CommonArgs::from_matches(matches)?,
+                stats: !matches.is_present("update-index"),
+            }));
+        }
+        if matches.is_present("similar") || matches.is_present("episodes") {
+            // These conflicts aren't declared on the flags, since the
+            // subcommands that share them don't have both.
+            if matches.is_present("apply-plan") || matches.is_present("undo") {
+                anyhow::bail!(
+                    "--similar and --episodes can't be used with \
+                     --apply-plan or --undo"
+                );
+            }
+            return Ok(Command::Search(SearchArgs::from_matches(matches)?));
+        }
+        let args = Args::from_matches(matches)?;
+        // A query without any files to rename is a search.
+        if args.files.is_empty() && args.query.is_some() {
+            return Ok(Command::Search(SearchArgs::from_matches(matches)?));
+        }
+        Ok(Command::Rename(Box::new(args)))
+    }
+}
+
+#[rustfmt::skip]
 fn app() -> clap::App<'static, 'static> {
-    use clap::{App, AppSettings, Arg};
+    use clap::{App, AppSettings, Arg, Shell};
 
+    let app = App::new("imdb-rename")
+        .author(clap::crate_authors!())
+        .version(clap::crate_version!())
+        .max_term_width(100)
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::VersionlessSubcommands)
+        // Once a file is given, subcommand names are taken as files, so that
+        // a file named like a subcommand can still be renamed after another
+        // one (or as, e.g., ./search).
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .subcommand(rename_args(common_args(subcommand("rename")))
+            .about("Rename files to their proper titles according to IMDb. \
+                    This is what running without a subcommand does."))
+        .subcommand(search_args(common_args(subcommand("search")))
+            .about("Search IMDb with a free-form query, or print the titles \
+                    similar to a title or the episodes of a TV show.")
+            .arg(Arg::with_name("query")
+                 .multiple(true)
+                 .required_unless_one(&["similar", "episodes"])
+                 .help("The query, e.g., 'homer loves flanders' or \
+                        'the matrix {movie} {year:1999}'. Multiple \
+                        arguments are joined by spaces.")))
+        .subcommand(common_args(subcommand("index"))
+            .about("Rebuild the index from the IMDb data that was already \
+                    downloaded, or print statistics about it.")
+            .arg(config::arg("stats")
+                 .help("Print statistics about the index, such as the date \
+                        of the IMDb data it was built from and the most \
+                        common ngrams in names, instead of rebuilding it."))
+            .arg(config::negation("stats")))
+        .subcommand(common_args(subcommand("data"))
+            .about("Download the IMDb data again and rebuild the index."))
+        .subcommand(subcommand("completions")
+            .about("Print a completion script for the given shell, e.g., \
+                    'imdb-rename completions zsh > _imdb-rename'.")
+            .arg(Arg::with_name("shell")
+                 .required(true)
+                 .possible_values(&Shell::variants())
+                 .help("The shell to print a completion script for.")));
+    // Without a subcommand, every flag is accepted, as it was before
+    // imdb-rename had subcommands. The flags below select the other
+    // subcommands.
+    search_args(rename_args(common_args(app)))
+        .arg(config::arg("index-stats")
+             .help("Print statistics about the index, such as the date of \
+                    the IMDb data it was built from and the most common \
+                    ngrams in names, and then exit."))
+        .arg(config::negation("index-stats"))
+        .arg(config::arg("update-data")
+             .help("Forcefully refreshes the IMDb data and then exits."))
+        .arg(config::negation("update-data"))
+        .arg(config::arg("update-index")
+             .help("Forcefully re-indexes the IMDb data and then exits."))
+        .arg(config::negation("update-index"))
+}
+
+/// Create an app for the subcommand with the given name.
+fn subcommand(name: &'static str) -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name(name)
+        .max_term_width(100)
+        .setting(clap::AppSettings::UnifiedHelpMessage)
+}
+
+/// Add the flags shared by every subcommand that reads IMDb, which are
+/// parsed into `CommonArgs`.
+#[rustfmt::skip]
+fn common_args(
+    app: clap::App<'static, 'static>,
+) -> clap::App<'static, 'static> {
     lazy_static! {
         // clap wants all of its strings tied to a particular lifetime, but
         // we'd really like to determine some default values dynamically. Using
@@ -232,130 +1541,772 @@ fn app() -> clap::App<'static, 'static> {
         static ref DATA_DIR: PathBuf = env::temp_dir().join("imdb-rename");
     }
 
-    App::new("imdb-rename")
-        .author(clap::crate_authors!())
-        .version(clap::crate_version!())
-        .max_term_width(100)
-        .setting(AppSettings::UnifiedHelpMessage)
-        .arg(Arg::with_name("file")
-             .multiple(true)
-             .help("One or more files to rename."))
-        .arg(Arg::with_name("data-dir")
-             .long("data-dir")
-             .env("IMDB_RENAME_DATA_DIR")
-             .takes_value(true)
+    app
+        .arg(config::arg("config")
+             .value_name("FILE")
+             .help("A TOML file that sets defaults for any of these flags, \
+                    keyed by their long names, e.g., 'votes = 500' or \
+                    'follow = true'. Flags given on the command line or by \
+                    an environment variable take precedence, as do flags \
+                    that conflict with them, e.g., --copy over \
+                    'symlink = true'. A switch turned on here is turned \
+                    off by its negation, e.g., --no-follow. When absent, \
+                    ~/.config/imdb-rename/config.toml is used if it \
+                    exists."))
+        .arg(config::arg("data-dir")
              .default_value_os(DATA_DIR.as_os_str())
              .help("The location to store IMDb data files."))
-        .arg(Arg::with_name("dest-dir")
-             .long("dest-dir")
-             .short("d")
-             .env("IMDB_RENAME_DEST_DIR")
-             .takes_value(true)
-             .help("The output directory of renamed files \
-                    (or symlinks/hardlinks with the -s/-H options). \
-                    By default, files are renamed in place."))
-        .arg(Arg::with_name("debug")
-             .long("debug")
+        .arg(config::arg("json")
+             .help("Print JSON instead of tables. Search results and \
+                    proposed renames are printed as a JSON array on one \
+                    line. Proposals include the IMDb identifier, title, \
+                    kind and year of the title they were matched to, along \
+                    with the score of its search result. With \
+                    --explain-rename or --stats, a single object is printed \
+                    instead, with the proposals under a 'proposals' key \
+                    and the rest under 'explanations' and 'stats' keys. \
+                    Nothing is renamed in JSON mode and there are no \
+                    prompts: files without a clear match are skipped, as \
+                    with --yes, and the proposals are only printed, so \
+                    that other programs can act on them. Errors are also \
+                    printed as a JSON object with 'error' and 'code' \
+                    keys."))
+        .arg(config::negation("json"))
+        .arg(config::arg("events")
+             .value_name("FORMAT")
+             .possible_values(&["jsonl"])
+             .help("Write machine-readable events to stdout, one JSON object \
+                    per line, for programs wrapping imdb-rename. Events \
+                    report downloads, indexing, progress, proposals, \
+                    renames, skips and errors. Prompts and confirmations \
+                    are events too, and are answered by writing one JSON \
+                    object per line to stdin, e.g., \
+                    {\"answer\": \"choose\", \"choice\": 2} or \
+                    {\"answer\": \"yes\"}. Human readable output moves to \
+                    stderr."))
+        .arg(config::arg("lang")
+             .value_name("LANG")
+             .possible_values(&["en", "de"])
+             .help("The language of prompts, summaries and reasons for \
+                    skipping files. By default, the language is taken from \
+                    the LC_ALL, LC_MESSAGES or LANG environment variables, \
+                    and English is used if it isn't supported."))
+        .arg(config::arg("events-file")
+             .value_name("PATH")
+             .help("Write the events of --events to PATH instead of stdout, \
+                    e.g., /dev/fd/3. Human readable output stays on \
+                    stdout."))
+        .arg(config::arg("debug")
              .help("Show debug messages. Use this when filing bugs."))
-        .arg(Arg::with_name("follow")
-             .long("follow")
-             .short("f")
-             .help("Follow directories and attempt to rename all child \
-                    entries."))
-        .arg(Arg::with_name("index-dir")
-             .long("index-dir")
-             .env("IMDB_RENAME_INDEX_DIR")
-             .takes_value(true)
+        .arg(config::negation("debug"))
+        .arg(config::arg("color")
+             .value_name("WHEN")
+             .possible_values(&["never", "auto", "always"])
+             .default_value("auto")
+             .help("Whether to highlight the parts of result titles that \
+                    match the query name. When set to auto, highlighting is \
+                    used only when writing to a terminal and NO_COLOR is \
+                    not set."))
+        .arg(config::arg("index-dir")
              .help("The location to store IMDb index files. \
                     When absent, the default is {data-dir}/index."))
-        .arg(Arg::with_name("ngram-size")
-             .long("ngram-size")
+        .arg(config::arg("ngram-size")
              .default_value("3")
              .help("Choose the ngram size for indexing names. This is only \
                     used at index time and otherwise ignored."))
-        .arg(Arg::with_name("ngram-type")
-             .long("ngram-type")
+        .arg(config::arg("ngram-type")
              .default_value("window")
              .possible_values(NgramType::possible_names())
              .help("Choose the type of ngram generation. This is only used \
                     used at index time and otherwise ignored."))
-        .arg(Arg::with_name("query")
-             .long("query")
+        .arg(config::arg("max-scan-time")
+             .value_name("SECONDS")
+             .default_value("10")
+             .help("The maximum time to spend on a query without a name, \
+                    which requires scanning every title in IMDb. When the \
+                    time runs out, the titles found so far are shown. Set \
+                    to 0 for no limit, or add {exhaustive} to the query to \
+                    lift the limit for that query."))
+        .arg(config::arg("max-scan-rows")
+             .value_name("N")
+             .help("The maximum number of titles to scan for a query \
+                    without a name. Like --max-scan-time, this is lifted by \
+                    adding {exhaustive} to the query. There is no limit by \
+                    default."))
+        .arg(config::arg("no-auto-rebuild")
+             .help("Report an error instead of rebuilding the index when it \
+                    was created by an incompatible version of imdb-rename."))
+        .arg(config::negation("no-auto-rebuild"))
+        .arg(config::arg("read-only-index")
+             .help("Never create or rebuild the index, which is useful when \
+                    it is shared and mounted read-only. Implies \
+                    --no-auto-rebuild."))
+        .arg(config::negation("read-only-index"))
+        .arg(config::arg("yes")
+             .alias("non-interactive")
+             .help("Never prompt, for use in scripts. The IMDb data is \
+                    downloaded on the first run and proposals are executed \
+                    without asking for confirmation. Files for which no \
+                    search result stands out are skipped instead of \
+                    prompting for a choice, in which case imdb-rename exits \
+                    with an error after renaming the rest. This is also \
+                    available as --non-interactive."))
+        .arg(config::negation("yes"))
+}
+
+/// Add the flags for renaming files, which are parsed into `Args`.
+#[rustfmt::skip]
+fn rename_args(
+    app: clap::App<'static, 'static>,
+) -> clap::App<'static, 'static> {
+    use clap::Arg;
+
+    app
+        .arg(Arg::with_name("file")
+             .multiple(true)
+             .help("One or more files to rename."))
+        .arg(config::arg("dest-dir")
+             .short("d")
+             .help("The output directory of renamed files (or of the \
+                    links, copies or reflinks made by -s/-H/-c/--reflink). \
+                    By default, files are renamed in place."))
+        .arg(config::arg("movies-dir")
+             .help("The output directory for movies (and anything else that \
+                    isn't a TV episode). This takes priority over \
+                    -d/--dest-dir."))
+        .arg(config::arg("tv-dir")
+             .help("The root output directory for TV episodes. Each episode \
+                    is placed in a '{show}/Season {NN}' sub-directory of \
+                    this root, which usually requires --mkdir. This takes \
+                    priority over -d/--dest-dir."))
+        .arg(config::arg("show-years")
+             .help("Include the years a TV show ran in the name of its \
+                    directory under --tv-dir, e.g., 'Futurama (1999–2013)' \
+                    or 'The Simpsons (1989–)' for a show that is still \
+                    airing."))
+        .arg(config::negation("show-years"))
+        .arg(config::arg("all-files")
+             .help("Propose renames for all files given, regardless of their \
+                    extension. By default, only video files are renamed, \
+                    along with subtitle and metadata files whose names \
+                    start with the name of a video file in the same \
+                    directory (or any subtitle and metadata files when \
+                    -q/--query is given). Everything else is skipped."))
+        .arg(config::negation("all-files"))
+        .arg(config::arg("explain-rename")
+             .help("Print how each file name was parsed, the queries \
+                    executed, the top results with their scores and why a \
+                    result was chosen (or why the file was skipped)."))
+        .arg(config::negation("explain-rename"))
+        .arg(config::arg("dry-run")
+             .help("Print the proposed renames and exit without renaming \
+                    anything. The file system is not consulted beyond \
+                    reading the names of the files given, so destinations \
+                    are not checked. The checks that were skipped are \
+                    printed to stderr."))
+        .arg(config::negation("dry-run"))
+        .arg(config::arg("verbose-proposals")
+             .help("Print a line under each proposed rename with the IMDb \
+                    entity it was matched to: its identifier, kind, year, \
+                    rating and votes, and the score of the search result \
+                    that was chosen. This makes wrong matches easier to \
+                    spot."))
+        .arg(config::negation("verbose-proposals"))
+        .arg(config::arg("stats")
+             .help("Print a report of how the proposed renames were matched \
+                    to IMDb: how many were accepted automatically, picked \
+                    when prompted or given by -q/--query, a histogram of \
+                    the scores of the chosen search results and how many \
+                    file names were parsed by each pattern. With --json, \
+                    the report is printed under a 'stats' key along with \
+                    the proposals."))
+        .arg(config::negation("stats"))
+        .arg(config::arg("export-plan")
+             .value_name("FILE")
+             .help("Write the proposed renames to FILE, after checking \
+                    their destinations and making any choices, and exit \
+                    without renaming anything. The plan can be executed \
+                    later, possibly on another machine, with \
+                    --apply-plan."))
+        .arg(config::arg("apply-plan")
+             .value_name("FILE")
+             .help("Execute the renames in a plan written by --export-plan. \
+                    Every source must still exist and every destination \
+                    must be free. The IMDb data and index are not needed, \
+                    and are neither downloaded nor read."))
+        .arg(config::arg("undo")
+             .min_values(0)
+             .max_values(1)
+             .value_name("JOURNAL")
+             .help("Reverse the most recent batch of renames, as recorded \
+                    in the rename journal in the data directory (or in \
+                    JOURNAL, if given). Links, copies and reflinks are \
+                    reversed by removing them. Renames whose source \
+                    exists again are reported and skipped, and are retried \
+                    by the next --undo. If the journal is missing or empty, \
+                    then the files given (before --undo, since a path right \
+                    after it is taken as JOURNAL) are renamed back to the \
+                    original names recorded by --write-origin, within their \
+                    own directories."))
+        .arg(config::arg("threads")
+             .short("j")
+             .default_value("1")
+             .help("The number of threads to use when searching for the \
+                    titles of many files. Prompts are still shown one at a \
+                    time."))
+        .arg(config::arg("sort-titles")
+             .help("Move a leading article to the end of a title when \
+                    renaming, e.g., 'Matrix, The (1999)'. English, French, \
+                    German and Spanish articles are recognized. Episode \
+                    titles are unaffected."))
+        .arg(config::negation("sort-titles"))
+        .arg(config::arg("keep-tags")
+             .help("Append release tags found in the original file name \
+                    (resolution, source, codec and HDR format) to the new \
+                    name, e.g., 'Title (Year) [1080p WEB x264].mkv'."))
+        .arg(config::negation("keep-tags"))
+        .arg(config::arg("no-query-fixups")
+             .help("Search for names found in file names as they are, \
+                    except for dots. By default, hyphens and underscores \
+                    are replaced with spaces, apostrophes are removed and \
+                    '&' is replaced with 'and'."))
+        .arg(config::negation("no-query-fixups"))
+        .arg(config::arg("ext-lower")
+             .help("Lowercase the extensions of renamed files, e.g., \
+                    'movie.MKV' becomes 'Title (Year).mkv'."))
+        .arg(config::negation("ext-lower"))
+        .arg(config::arg("ext-alias")
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("FROM=TO")
+             .help("Replace the extension FROM with TO when renaming, e.g., \
+                    'mpeg4=mp4'. FROM is matched case insensitively. This \
+                    flag may be given multiple times."))
+        .arg(config::arg("mkdir")
+             .help("Create missing destination directories when renaming. \
+                    Without this, a destination in a directory that doesn't \
+                    exist is an error."))
+        .arg(config::negation("mkdir"))
+        .arg(config::arg("dir-mode")
+             .value_name("MODE")
+             .help("Set the mode of directories created by --mkdir to the \
+                    given octal number, e.g., '2775', regardless of the \
+                    umask. Directories that already exist are left alone. \
+                    Unix only."))
+        .arg(config::arg("chown")
+             .value_name("USER:GROUP")
+             .help("Set the owner and/or group of directories created by \
+                    --mkdir, e.g., 'media:media', 'media' or ':media'. \
+                    Names and numeric IDs are both accepted. Changing the \
+                    owner usually requires running as root. Unix only."))
+        .arg(config::arg("write-origin")
+             .value_name("FORMAT")
+             .possible_values(&["tsv", "sidecar"])
+             .help("After each file is renamed or linked, record its \
+                    original name, its new name, the IMDb ID it was matched \
+                    to and the time. With 'tsv', a row is appended to \
+                    '.imdb-rename-origins.tsv' in the destination \
+                    directory. With 'sidecar', a '<destination>.origin.txt' \
+                    file is written. Nothing is written in directories \
+                    containing a '.imdb-rename-no-origins' file. Undoing a \
+                    rename removes its origin."))
+        .arg(config::arg("write-nfo")
+             .help("After each video is renamed or linked, write an NFO file \
+                    for media centers like Kodi and Jellyfin next to it, \
+                    e.g., 'Dune (1984).nfo'. It contains the title, year, \
+                    IMDb ID, rating and genres of the IMDb title the video \
+                    was matched to. Existing NFO files are left alone, as \
+                    are NFO files that come with a release and are renamed \
+                    along with its video. Undoing a rename removes the NFO \
+                    file written for it."))
+        .arg(config::negation("write-nfo"))
+        .arg(config::arg("force-nfo")
+             .help("Replace existing NFO files when writing them with \
+                    --write-nfo."))
+        .arg(config::negation("force-nfo"))
+        .arg(config::arg("auto-accept-margin")
+             .default_value("0.25")
+             .help("When a search returns multiple results, the top result \
+                    is chosen automatically if its score exceeds the score \
+                    of the second result by at least this margin. Otherwise, \
+                    you are prompted to choose. This must be in the range \
+                    [0, 1], where 0 always takes the top result and 1 \
+                    always prompts (unless there is only one result). This \
+                    applies to results of -q/--query and of queries derived \
+                    from file names."))
+        .arg(config::arg("min-match-ratio")
+             .default_value("0")
+             .help("Only choose a result automatically if it matched at \
+                    least this ratio of the terms (ngrams) of the name \
+                    searched for. Otherwise, you are prompted to choose, \
+                    even if there is only one result. This must be in the \
+                    range [0, 1], where 0 disables this check. The ratio of \
+                    each result is shown by --explain."))
+        .arg(config::arg("follow")
+             .short("f")
+             .help("Follow directories and attempt to rename all child \
+                    entries, along with the directories given. Everything \
+                    inside a directory is renamed before the directory \
+                    itself, so the directory's new name is derived from \
+                    its original name and entries inside it keep their \
+                    place in it. Files and directories matching a \
+                    .imdbrenameignore file are skipped in the directory \
+                    containing it and below. Each line of the file is a \
+                    glob, like those given to --exclude, matched against \
+                    paths relative to that directory, with '#' comments \
+                    and '!' to re-include what an earlier line excluded."))
+        .arg(config::negation("follow"))
+        .arg(config::arg("recursive")
+             .short("r")
+             .help("An alias for -f/--follow."))
+        .arg(config::negation("recursive"))
+        .arg(config::arg("only")
+             .value_name("KIND")
+             .possible_values(&["dirs", "files"])
+             .help("Only propose renames for directories ('dirs') or for \
+                    everything else ('files'), e.g., to fix the names of \
+                    show and season directories without touching the \
+                    episodes inside them. This applies to the paths given \
+                    and to the entries found by -f/--follow. Directories \
+                    are still followed either way."))
+        .arg(config::arg("include-dir")
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("NAME")
+             .help("Descend into directories named NAME when following \
+                    directories, even though they are skipped by default. \
+                    By default, directories named Extras, Featurettes, \
+                    Sample, Samples, Subs and Subtitles are skipped \
+                    (case insensitively), except that subtitles directly \
+                    inside Subs and Subtitles are still renamed along with \
+                    the videos in the parent directory. This flag may be \
+                    given multiple times."))
+        .arg(config::arg("exclude-dir")
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("NAME")
+             .help("Don't descend into directories named NAME (case \
+                    insensitively) when following directories. Unlike \
+                    the default, excluding Subs or Subtitles this way \
+                    also skips the subtitles inside them. This flag may \
+                    be given multiple times."))
+        .arg(config::arg("exclude")
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("GLOB")
+             .help("Skip files and directories matching GLOB when following \
+                    directories, which is matched case insensitively \
+                    (unless --case-sensitive-globs is given) against paths \
+                    relative to the directory being followed. A GLOB \
+                    without a '/' matches names at any depth, e.g., \
+                    '*.nfo', '**' matches any number of directories, \
+                    '{a,b}' matches either alternative and a trailing '/' \
+                    only matches directories. Excluding a directory skips \
+                    everything in it. This flag may be given multiple \
+                    times."))
+        .arg(config::arg("case-sensitive-globs")
+             .help("Match the globs given to --exclude, and those in \
+                    .imdbrenameignore files, case sensitively."))
+        .arg(config::negation("case-sensitive-globs"))
+        .arg(config::arg("ext")
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("EXTS")
+             .help("Only rename files with one of the comma separated \
+                    extensions in EXTS (case insensitively) when following \
+                    directories, e.g., 'mkv,mp4,srt'. Files named on the \
+                    command line are always renamed. An empty entry, like \
+                    --ext '', allows files without an extension. This flag \
+                    may be given multiple times."))
+        .arg(config::arg("query")
              .short("q")
-             .takes_value(true)
              .help("Setting an override query is necessary if the file \
                     path lacks sufficient information to find a matching \
                     title. For example, if a year could not be found. It \
                     is also useful for specifying a TV show when renaming \
                     multiple episodes at once."))
-        .arg(Arg::with_name("re-episode")
-             .long("re-episode")
-             .takes_value(true)
+        .arg(config::arg("season")
+             .value_name("N")
+             .help("Use this season number instead of the one found in the \
+                    file name. Along with --episode, this renames a file \
+                    whose name has no episode information as an episode, \
+                    usually of the TV show given by -q/--query. This can \
+                    only be used with a single file or with -q/--query."))
+        .arg(config::arg("episode")
+             .value_name("N")
+             .help("Use this episode number instead of the one found in the \
+                    file name. See --season."))
+        .arg(config::arg("re-episode")
              .default_value(r"[Ee](?P<episode>[0-9]+)")
              .help("A regex for matching episode numbers. The episode number \
                     is extracted by looking for a 'episode' capture group."))
-        .arg(Arg::with_name("re-season")
-             .long("re-season")
-             .takes_value(true)
+        .arg(config::arg("episode-gap")
+             .default_value("3")
+             .help("The maximum number of characters permitted between the \
+                    end of a season match and the start of an episode match. \
+                    For example, 'S01E02' has a gap of 0 and 'S01.E02' has a \
+                    gap of 1. This prevents unrelated text in release names, \
+                    like 'DTS5.1-E1', from being parsed as an episode."))
+        .arg(config::arg("re-season")
              .default_value(r"[Ss](?P<season>[0-9]+)")
              .help("A regex for matching season numbers. The season number \
                     is extracted by looking for a 'season' capture group."))
-        .arg(Arg::with_name("re-year")
-             .long("re-year")
-             .takes_value(true)
+        .arg(config::arg("re-season-episode")
+             .help("A regex for matching both the season and episode numbers \
+                    in one go, e.g., '\\.(?P<season>[0-9]{2})\
+                    (?P<episode>[0-9]{2})\\.' for names like 'Show.0205.mkv'. \
+                    Both numbers are extracted by looking for 'season' and \
+                    'episode' capture groups. When given, this is tried \
+                    first, and --re-season and --re-episode are only used \
+                    if it doesn't match."))
+        .arg(config::arg("re-year")
              .default_value(r"\b(?P<year>[0-9]{4})\b")
              .help("A regex for matching the year. The year is extracted by \
                     looking for a 'year' capture group."))
-        .arg(Arg::with_name("update-data")
-             .long("update-data")
-             .help("Forcefully refreshes the IMDb data and then exits."))
-        .arg(Arg::with_name("votes")
-             .long("votes")
+        .arg(config::arg("votes")
              .default_value("1000")
              .help("The minimum number of votes required for results matching \
                     a query derived from existing file names. This is not \
                     applied to explicit queries via the -q/--query flag."))
-        .arg(Arg::with_name("update-index")
-             .long("update-index")
-             .help("Forcefully re-indexes the IMDb data and then exits."))
-        .arg(Arg::with_name("symlink")
-             .long("symlink")
+        .arg(config::arg("votes-movie")
+             .help("Like --votes, but only for queries derived from file \
+                    names that aren't TV episodes. Defaults to --votes."))
+        .arg(config::arg("votes-tv")
+             .help("Like --votes, but only for finding the TV show of an \
+                    episode from its file name. Defaults to --votes."))
+        .arg(config::arg("symlink")
              .short("s")
-             .conflicts_with("hardlink")
              .help("Create a symlink instead of renaming. \
                     (Unix only feature.)"))
-        .arg(Arg::with_name("hardlink")
-             .long("hardlink")
+        .arg(config::negation("symlink"))
+        .arg(config::arg("symlink-relative")
+             .help("Like --symlink, but the symlink points to its source \
+                    via a path relative to the directory containing the \
+                    link. This keeps links working when the library is \
+                    mounted at a different prefix. If the source and the \
+                    link are on different file systems, then an absolute \
+                    symlink is created instead. (Unix only feature.)"))
+        .arg(config::negation("symlink-relative"))
+        .arg(config::arg("hardlink")
              .short("H")
-             .conflicts_with("symlink")
              .help("Create a hardlink instead of renaming. \
                     This doesn't work when renaming directories."))
+        .arg(config::negation("hardlink"))
+        .arg(config::arg("copy")
+             .short("c")
+             .help("Copy files instead of renaming them, leaving the \
+                    originals in place. Directories are copied \
+                    recursively. Copied files keep the modification time \
+                    and permissions of their source, and a copy that fails \
+                    midway is removed again."))
+        .arg(config::negation("copy"))
+        .arg(config::arg("reflink")
+             .min_values(0)
+             .max_values(1)
+             .require_equals(true)
+             .possible_values(&["auto", "always"])
+             .value_name("WHEN")
+             .help("Clone files instead of renaming them, such that each \
+                    clone shares its data with its source until either is \
+                    changed. This is instant and takes no extra space, but \
+                    needs a file system that supports it, like btrfs or \
+                    XFS. With --reflink=auto, files that can't be cloned \
+                    are copied instead. With --reflink or \
+                    --reflink=always, they fail. Directories are cloned \
+                    recursively. (Linux only feature.)"))
+        .arg(config::arg("skip-in-use")
+             .help("Skip files that appear to still be written to, e.g., by \
+                    a download client. A file is considered in use if its \
+                    size changes over a short interval or, on Linux, if any \
+                    process has it open for writing."))
+        .arg(config::negation("skip-in-use"))
+        .arg(config::arg("allow-cross-device")
+             .help("Rename files to other file systems without asking for \
+                    an extra confirmation. Such renames copy the file and \
+                    then delete the original, which can take a long time \
+                    for big files. They are marked in the list of proposed \
+                    renames along with the amount of data to copy. With \
+                    --yes, they are refused unless this flag is given."))
+        .arg(config::negation("allow-cross-device"))
+        .arg(config::arg("transactional")
+             .help("Execute the proposals as a single batch. If any one of \
+                    them fails, then all of the renames and links already \
+                    made in the batch are reversed (most recent first) \
+                    before exiting with an error. By default, failures are \
+                    reported and the rest of the batch continues."))
+        .arg(config::negation("transactional"))
+        .arg(config::arg("preserve-times")
+             .help("Also carry over the access time of each file that is \
+                    copied or reflinked. Copies and reflinks always keep \
+                    the modification time and permissions of their source, \
+                    while renames and links share all of its metadata, so \
+                    this has no effect on them."))
+        .arg(config::negation("preserve-times"))
+}
+
+/// Add the flags for listing similar titles or the episodes of a TV show.
+#[rustfmt::skip]
+fn search_args(
+    app: clap::App<'static, 'static>,
+) -> clap::App<'static, 'static> {
+    app
+        .arg(config::arg("similar")
+             .value_name("ID")
+             .help("Print titles with names similar to the title with the \
+                    given IMDb identifier, e.g., tt0133093, such as its \
+                    remakes and sequels, and then exit."))
+        .arg(config::arg("episodes")
+             .value_name("ID")
+             .help("Print every episode of the TV show with the given IMDb \
+                    identifier, e.g., tt0096697, along with its rating, and \
+                    then exit."))
+}
+
+/// Decide whether to highlight output given the value of --color.
+///
+/// Following https://no-color.org, a non-empty NO_COLOR environment variable
+/// disables highlighting unless it is explicitly requested.
+fn use_color(when: &str) -> bool {
+    match when {
+        "always" => true,
+        "never" => false,
+        _ => {
+            io::stdout().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && env::var_os("TERM").is_none_or(|t| t != "dumb")
+        }
+    }
+}
+
+/// Parse the value of a flag, such as --auto-accept-margin, which must be a
+/// number in the range [0, 1]. The flag's name is only used in errors.
+fn parse_unit(flag: &str, value: &str) -> anyhow::Result<f64> {
+    let n: f64 = value.parse().map_err(|e| {
+        anyhow::anyhow!("invalid --{} '{}': {}", flag, value, e)
+    })?;
+    if !(0.0..=1.0).contains(&n) {
+        anyhow::bail!(
+            "invalid --{} '{}': must be in the range [0, 1]",
+            flag,
+            value
+        );
+    }
+    Ok(n)
+}
+
+/// Parse a value of --ext-alias, which must be of the form `FROM=TO`. A
+/// leading `.` on either extension is ignored.
+fn parse_ext_alias(value: &str) -> anyhow::Result<(String, String)> {
+    let (from, to) = match value.split_once('=') {
+        None => {
+            anyhow::bail!("invalid --ext-alias '{}': expected FROM=TO", value)
+        }
+        Some((from, to)) => {
+            (from.trim_start_matches('.'), to.trim_start_matches('.'))
+        }
+    };
+    if from.is_empty() || to.is_empty() {
+        anyhow::bail!(
+            "invalid --ext-alias '{}': extensions must not be empty",
+            value
+        );
+    }
+    Ok((from.to_string(), to.to_string()))
 }
 
 /// Collect all file paths from a sequence of OsStrings from the command line.
 /// If `follow` is true, then any paths that are directories are expanded to
 /// include all child paths, recursively.
 ///
+/// While following directories, files and directories matching the
+/// patterns of an ignore file (see `IgnorePatterns`) in any directory being
+/// followed are skipped. The nearest ignore file with a matching pattern
+/// decides, and ignore files take precedence over the directory filter.
+///
 /// If there is an error following a path, then it is logged to stderr and
 /// otherwise skipped.
-fn collect_paths(paths: Vec<&OsStr>, follow: bool) -> Vec<PathBuf> {
+fn collect_paths(
+    paths: Vec<&OsStr>,
+    follow: bool,
+    filter: &DirFilter,
+) -> Vec<PathBuf> {
     let mut results = vec![];
+    let mut subtitle_dirs = vec![];
+    let mut ignores: HashMap<PathBuf, Option<IgnorePatterns>> = HashMap::new();
+    let mut ignored = |path: &Path, depth: usize, is_dir: bool| {
+        // Only the ignore files of directories being followed apply, which
+        // are the `depth` nearest ancestors.
+        for dir in path.ancestors().skip(1).take(depth) {
+            let patterns =
+                ignores.entry(dir.to_path_buf()).or_insert_with(|| {
+                    let case_sensitive = filter.is_case_sensitive_globs();
+                    IgnorePatterns::from_dir(dir, case_sensitive)
+                        .unwrap_or_else(|err| {
+                            eprintln!("{}", err);
+                            None
+                        })
+                });
+            let rel = path.strip_prefix(dir).unwrap_or(path);
+            let decision =
+                patterns.as_ref().and_then(|p| p.is_ignored(rel, is_dir));
+            if decision.is_some() {
+                return decision;
+            }
+        }
+        None
+    };
     for path in paths {
         let path = PathBuf::from(path);
         if !follow || !path.is_dir() {
             results.push(path);
             continue;
         }
-        for result in WalkDir::new(path) {
+        let root = path.clone();
+        let walker = WalkDir::new(path).into_iter().filter_entry(|dent| {
+            if dent.depth() == 0 {
+                return true;
+            }
+            if dent.file_name() == IGNORE_FILE {
+                return false;
+            }
+            let is_dir = dent.file_type().is_dir();
+            let rel = dent.path().strip_prefix(&root).unwrap_or(dent.path());
+            if filter.is_excluded_glob(rel, is_dir) {
+                log::debug!("excluding {}", dent.path().display());
+                return false;
+            }
+            if !is_dir && !filter.is_allowed_file(dent.path()) {
+                log::debug!(
+                    "skipping {}, its extension isn't allowed by --ext",
+                    dent.path().display()
+                );
+                return false;
+            }
+            match ignored(dent.path(), dent.depth(), is_dir) {
+                Some(true) => {
+                    log::debug!("ignoring {}", dent.path().display());
+                    return false;
+                }
+                Some(false) => return true,
+                None => {}
+            }
+            let skip = is_dir && filter.is_excluded(dent.path());
+            if skip {
+                log::debug!("skipping directory {}", dent.path().display());
+                if filter.collects_subtitles(dent.path()) {
+                    subtitle_dirs.push(dent.path().to_path_buf());
+                }
+            }
+            !skip
+        });
+        for result in walker {
             match result {
                 Ok(dent) => results.push(dent.path().to_path_buf()),
                 Err(err) => eprintln!("{}", err),
             }
         }
     }
+    // Subtitle directories aren't followed, but the subtitles directly in
+    // them are still collected so that they can be renamed as companions of
+    // the videos in the parent directory.
+    for dir in subtitle_dirs {
+        for result in WalkDir::new(dir).min_depth(1).max_depth(1) {
+            match result {
+                Ok(dent)
+                    if is_subtitle(dent.path())
+                        && filter.is_allowed_file(dent.path()) =>
+                {
+                    results.push(dent.path().to_path_buf())
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+    }
     results
 }
 
+/// The kind of entries to propose renames for, as given by --only.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum EntryKind {
+    Dirs,
+    Files,
+}
+
+impl EntryKind {
+    /// Parse the value of --only.
+    fn from_name(name: &str) -> anyhow::Result<EntryKind> {
+        match name {
+            "dirs" => Ok(EntryKind::Dirs),
+            "files" => Ok(EntryKind::Files),
+            unk => anyhow::bail!("unrecognized entry kind: '{}'", unk),
+        }
+    }
+
+    /// The name of this kind, as given to --only.
+    fn name(&self) -> &'static str {
+        match *self {
+            EntryKind::Dirs => "dirs",
+            EntryKind::Files => "files",
+        }
+    }
+
+    /// Returns true if the given path is an entry of this kind. Paths that
+    /// don't exist are considered files.
+    fn matches(&self, path: &Path) -> bool {
+        match *self {
+            EntryKind::Dirs => path.is_dir(),
+            EntryKind::Files => !path.is_dir(),
+        }
+    }
+}
+
+/// Keep only the collected paths of the given kind, if any, preserving their
+/// order. The number of paths left out is returned along with the rest.
+fn select_entries(
+    paths: Vec<PathBuf>,
+    only: Option<EntryKind>,
+) -> (Vec<PathBuf>, usize) {
+    let only = match only {
+        None => return (paths, 0),
+        Some(only) => only,
+    };
+    let total = paths.len();
+    let selected: Vec<PathBuf> = paths
+        .into_iter()
+        .filter(|path| {
+            let keep = only.matches(path);
+            if !keep {
+                log::debug!("excluding {} by --only", path.display());
+            }
+            keep
+        })
+        .collect();
+    let excluded = total - selected.len();
+    (selected, excluded)
+}
+
+/// Return the given error as a single line of JSON.
+///
+/// The object has an `error` key with the full error message and a `code`
+/// key with the stable identifier of the underlying index error, or `null`
+/// if the error did not come from the index.
+fn error_json(err: &anyhow::Error) -> String {
+    serde_json::json!({
+        "error": format!("{:#}", err),
+        "code": error_code(err),
+    })
+    .to_string()
+}
+
+/// Return the stable identifier of the index error in the causal chain of
+/// the given error, if there is one.
+fn error_code(err: &anyhow::Error) -> Option<&'static str> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<imdb_index::Error>())
+        .map(|err| err.kind().code())
+}
+
 /// Return true if and only if an I/O broken pipe error exists in the causal
 /// chain of the given error.
 fn is_pipe_error(err: &anyhow::Error) -> bool {
@@ -368,3 +2319,827 @@ fn is_pipe_error(err: &anyhow::Error) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use anyhow::Context;
+    use imdb_index::{ErrorKind, NameScorer};
+
+    use super::{
+        app, collect_paths, error_json, parse_ext_alias, parse_unit,
+        score_bucket, select_entries, write_proposals, write_proposals_json,
+        Args, Command, CommonArgs, EntryKind, MatchReport,
+    };
+    use crate::config::Matches;
+    use crate::tempdir::TempDir;
+    use imdb_rename::{
+        Decision, DirFilter, DirPerms, MatchStat, Pattern, RenameAction,
+        RenameProposal,
+    };
+
+    #[test]
+    fn margin() {
+        assert_eq!(parse_unit("auto-accept-margin", "0").unwrap(), 0.0);
+        assert_eq!(parse_unit("auto-accept-margin", "0.5").unwrap(), 0.5);
+        assert_eq!(parse_unit("auto-accept-margin", "1").unwrap(), 1.0);
+        assert!(parse_unit("auto-accept-margin", "-0.1").is_err());
+        assert!(parse_unit("auto-accept-margin", "1.5").is_err());
+        assert!(parse_unit("auto-accept-margin", "NaN").is_err());
+        assert!(parse_unit("auto-accept-margin", "lots").is_err());
+    }
+
+    #[test]
+    fn error_codes() {
+        let err = "bogus"
+            .parse::<NameScorer>()
+            .context("invalid --scorer")
+            .unwrap_err();
+        let got: serde_json::Value =
+            serde_json::from_str(&error_json(&err)).unwrap();
+        assert_eq!(got["code"], "unknown_scorer");
+        assert_eq!(
+            got["error"],
+            "invalid --scorer: unrecognized scorer name: 'bogus'"
+        );
+
+        let err = anyhow::anyhow!("not an index error");
+        let got: serde_json::Value =
+            serde_json::from_str(&error_json(&err)).unwrap();
+        assert!(got["code"].is_null());
+    }
+
+    #[test]
+    fn margin_args() {
+        let parse = |argv: &[&str]| {
+            let matches = app().get_matches_from_safe(argv).unwrap();
+            Args::from_matches(&Matches::new(matches, None))
+        };
+        let args = parse(&["imdb-rename"]).unwrap();
+        assert_eq!(args.auto_accept_margin, 0.25);
+        let args =
+            parse(&["imdb-rename", "--auto-accept-margin", "1"]).unwrap();
+        assert_eq!(args.auto_accept_margin, 1.0);
+        assert!(parse(&["imdb-rename", "--auto-accept-margin", "2"]).is_err());
+
+        assert_eq!(args.min_match_ratio, 0.0);
+        let args =
+            parse(&["imdb-rename", "--min-match-ratio", "0.8"]).unwrap();
+        assert_eq!(args.min_match_ratio, 0.8);
+        assert!(parse(&["imdb-rename", "--min-match-ratio", "1.5"]).is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let parse = |argv: &[&str]| {
+            let matches = app().get_matches_from_safe(argv)?;
+            Command::from_matches(&Matches::new(matches, None))
+        };
+        let kind = |argv: &[&str]| match parse(argv).unwrap() {
+            Command::Rename(_) => "rename",
+            Command::Search(_) => "search",
+            Command::Index(args) if args.stats => "index --stats",
+            Command::Index(_) => "index",
+            Command::Data(_) => "data",
+            Command::Completions(_) => "completions",
+        };
+        // Without a subcommand, flags select the command.
+        assert_eq!(kind(&["imdb-rename"]), "rename");
+        assert_eq!(kind(&["imdb-rename", "-q", "matrix"]), "search");
+        assert_eq!(kind(&["imdb-rename", "--similar", "tt1"]), "search");
+        assert_eq!(kind(&["imdb-rename", "--index-stats"]), "index --stats");
+        assert_eq!(kind(&["imdb-rename", "--update-index"]), "index");
+        assert_eq!(kind(&["imdb-rename", "--update-data"]), "data");
+
+        assert_eq!(kind(&["imdb-rename", "rename"]), "rename");
+        assert_eq!(
+            kind(&["imdb-rename", "search", "the", "matrix"]),
+            "search"
+        );
+        assert_eq!(
+            kind(&["imdb-rename", "search", "--episodes", "tt1"]),
+            "search"
+        );
+        assert_eq!(kind(&["imdb-rename", "index"]), "index");
+        assert_eq!(
+            kind(&["imdb-rename", "index", "--stats"]),
+            "index --stats"
+        );
+        assert_eq!(kind(&["imdb-rename", "data"]), "data");
+        assert_eq!(
+            kind(&["imdb-rename", "completions", "zsh"]),
+            "completions"
+        );
+
+        match parse(&["imdb-rename", "search", "the", "matrix"]).unwrap() {
+            Command::Search(args) => {
+                assert_eq!(args.query.as_deref(), Some("the matrix"))
+            }
+            _ => unreachable!(),
+        }
+        assert!(parse(&["imdb-rename", "search"]).is_err());
+        assert!(parse(&["imdb-rename", "rename", "--similar", "tt1"]).is_err());
+        assert!(parse(&["imdb-rename", "--update-data", "--read-only-index"])
+            .is_err());
+        assert!(parse(&[
+            "imdb-rename",
+            "--apply-plan",
+            "x",
+            "--similar",
+            "tt1"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn completions() {
+        for shell in clap::Shell::variants().iter() {
+            let mut out = vec![];
+            let shell = shell.parse().unwrap();
+            app().gen_completions_to("imdb-rename", shell, &mut out);
+            let script = String::from_utf8(out).unwrap();
+            for name in &["rename", "search", "index", "data", "completions"] {
+                assert!(script.contains(name), "{}: {}", shell, name);
+            }
+            for flag in &["dest-dir", "episodes", "update-index", "stats"] {
+                assert!(script.contains(flag), "{}: {}", shell, flag);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reflink_args() {
+        let parse = |argv: &[&str]| {
+            let matches = app().get_matches_from_safe(argv).unwrap();
+            Args::from_matches(&Matches::new(matches, None)).unwrap()
+        };
+        let args = parse(&["imdb-rename", "--reflink", "-q", "dune"]);
+        assert_eq!(args.rename_action, RenameAction::Reflink);
+        assert!(!args.reflink_fallback);
+        let args = parse(&["imdb-rename", "--reflink=auto", "-q", "dune"]);
+        assert_eq!(args.rename_action, RenameAction::Reflink);
+        assert!(args.reflink_fallback);
+        assert!(app()
+            .get_matches_from_safe(["imdb-rename", "--reflink=never"])
+            .is_err());
+    }
+
+    #[test]
+    fn max_scan_time_args() {
+        let parse = |argv: &[&str]| {
+            let matches = app().get_matches_from_safe(argv).unwrap();
+            CommonArgs::from_matches(&Matches::new(matches, None))
+        };
+        let time = |secs: &str| {
+            let flag = format!("--max-scan-time={}", secs);
+            parse(&["imdb-rename", &flag]).map(|args| args.max_scan_time)
+        };
+        assert_eq!(time("1.5").unwrap(), Some(Duration::from_millis(1500)));
+        assert_eq!(time("0").unwrap(), None);
+        assert_eq!(time("0.0").unwrap(), None);
+        for secs in &["-1", "NaN", "inf"] {
+            assert!(time(secs).is_err(), "{}", secs);
+        }
+    }
+
+    #[test]
+    fn episode_override_args() {
+        let parse = |argv: &[&str]| {
+            let matches = app().get_matches_from_safe(argv).unwrap();
+            Args::from_matches(&Matches::new(matches, None))
+        };
+        let tmp = TempDir::new("imdb-rename-test-episode-args").unwrap();
+        let (a, b) = (tmp.path().join("a.mkv"), tmp.path().join("b.mkv"));
+        fs::write(&a, "").unwrap();
+        fs::write(&b, "").unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let args =
+            parse(&["imdb-rename", "--season", "3", "--episode", "7", a])
+                .unwrap();
+        assert_eq!((args.season, args.episode), (Some(3), Some(7)));
+        assert!(parse(&["imdb-rename", "--episode", "7", a, b]).is_err());
+        let args =
+            parse(&["imdb-rename", "--episode", "7", "-q", "simpsons", a, b])
+                .unwrap();
+        assert_eq!((args.season, args.episode), (None, Some(7)));
+        assert!(parse(&["imdb-rename", "--season", "three", a]).is_err());
+    }
+
+    #[test]
+    fn ext_args() {
+        let tmp = TempDir::new("imdb-rename-test-ext-args").unwrap();
+        let root = tmp.path().join("incoming");
+        fs::create_dir_all(root.join("Subs")).unwrap();
+        for file in [
+            "Movie.2017.MKV",
+            "Movie.2017.nfo",
+            "Movie.2017.txt",
+            "README",
+            "Subs/English.srt",
+            "Subs/English.sub",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let nfo = root.join("Movie.2017.nfo");
+        let files = |exts: &[&str]| {
+            let mut argv = vec!["imdb-rename", "-f"];
+            for ext in exts {
+                argv.extend(["--ext", ext]);
+            }
+            argv.extend([root.to_str().unwrap(), nfo.to_str().unwrap()]);
+            let matches = app().get_matches_from_safe(argv).unwrap();
+            let args =
+                Args::from_matches(&Matches::new(matches, None)).unwrap();
+            let mut files: Vec<String> = args
+                .files
+                .iter()
+                .map(|p| {
+                    let p = p.strip_prefix(&root).unwrap();
+                    p.to_string_lossy().replace('\\', "/")
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        // Files named on the command line are kept, whatever their
+        // extension.
+        assert_eq!(
+            files(&["mkv,SRT"]),
+            vec!["", "Movie.2017.MKV", "Movie.2017.nfo", "Subs/English.srt"]
+        );
+        assert_eq!(
+            files(&["mkv", ""]),
+            vec!["", "Movie.2017.MKV", "Movie.2017.nfo", "README"]
+        );
+    }
+
+    #[test]
+    fn dir_perms_args() {
+        let parse = |argv: &[&str]| {
+            let matches = app().get_matches_from_safe(argv)?;
+            Args::from_matches(&Matches::new(matches, None))
+        };
+        let args = parse(&["imdb-rename", "--mkdir"]).unwrap();
+        assert_eq!(args.dir_perms, DirPerms::default());
+        let args =
+            parse(&["imdb-rename", "--mkdir", "--dir-mode", "2775"]).unwrap();
+        assert_eq!(args.dir_perms.mode, Some(0o2775));
+        // Only directories created by --mkdir are changed.
+        assert!(parse(&["imdb-rename", "--dir-mode", "2775"]).is_err());
+        let args = &["imdb-rename", "--mkdir", "--dir-mode", "x"];
+        assert!(parse(args).is_err());
+    }
+
+    /// A proposal as it would be read from a plan, renaming `src` to `dst`
+    /// and matched to a movie with the given ID, title and rating, and with
+    /// the given score.
+    fn proposal(
+        src: &str,
+        dst: &str,
+        action: &str,
+        (id, title): (&str, &str),
+        rating: Option<(f32, u32)>,
+        score: Option<f64>,
+    ) -> RenameProposal {
+        let rating = rating.map(|(rating, votes)| {
+            serde_json::json!({
+                "tconst": id,
+                "averageRating": rating,
+                "numVotes": votes,
+            })
+        });
+        serde_json::from_value(serde_json::json!({
+            "src": src,
+            "dst": dst,
+            "action": action,
+            "entity": {
+                "title": {
+                    "tconst": id,
+                    "titleType": "movie",
+                    "primaryTitle": title,
+                    "originalTitle": title,
+                    "isAdult": 0,
+                    "startYear": 1999,
+                    "endYear": null,
+                    "runtimeMinutes": 136,
+                    "genres": "Action,Sci-Fi",
+                },
+                "episode": null,
+                "rating": rating,
+            },
+            "score": score,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn verbose_proposals() {
+        let proposals = vec![
+            proposal(
+                "matrix.1999.mkv",
+                "The Matrix (1999).mkv",
+                "rename",
+                ("tt0133093", "The Matrix"),
+                Some((8.7, 2100000)),
+                Some(0.9821),
+            ),
+            proposal(
+                "x.mkv",
+                "y.mkv",
+                "rename",
+                ("tt0000001", "X"),
+                None,
+                None,
+            ),
+        ];
+        let write = |verbose, color| {
+            let mut out = vec![];
+            write_proposals(&mut out, &proposals, verbose, color).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            write(false, false),
+            "matrix.1999.mkv  ->  The Matrix (1999).mkv\n\
+             x.mkv            ->  y.mkv\n"
+        );
+        assert_eq!(
+            write(true, false),
+            "matrix.1999.mkv  ->  The Matrix (1999).mkv\n\
+             \x20                    tt0133093 movie 1999, rated 8.7 \
+             (2100000 votes), score 0.982\n\
+             x.mkv            ->  y.mkv\n\
+             \x20                    tt0000001 movie 1999, unrated, \
+             not searched\n"
+        );
+        // Renames across file systems are marked with the amount of data
+        // they copy.
+        let mut copied: serde_json::Value =
+            serde_json::to_value(&proposals[1]).unwrap();
+        copied["cross_device"] = serde_json::json!(62_921_900_000u64);
+        let copied: RenameProposal = serde_json::from_value(copied).unwrap();
+        let mut out = vec![];
+        write_proposals(&mut out, &[copied], false, false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "x.mkv  ->  y.mkv  (will copy across file systems, 58.6 GiB)\n"
+        );
+        assert!(write(true, true).contains(
+            "\x1b[2mtt0133093 movie 1999, rated 8.7 (2100000 votes), \
+             score 0.982\x1b[0m\n"
+        ));
+    }
+
+    #[test]
+    fn proposals_json() {
+        let proposals = vec![
+            proposal(
+                "matrix.1999.mkv",
+                "The Matrix (1999).mkv",
+                "symlink",
+                ("tt0133093", "The Matrix"),
+                None,
+                Some(0.5),
+            ),
+            proposal(
+                "x.mkv",
+                "y.mkv",
+                "rename",
+                ("tt0000001", "X"),
+                Some((5.0, 10)),
+                None,
+            ),
+        ];
+        let mut out = vec![];
+        write_proposals_json(&mut out, &proposals, None, None).unwrap();
+        let got: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let x = serde_json::json!({
+            "src": "x.mkv",
+            "dst": "y.mkv",
+            "action": "rename",
+            "id": "tt0000001",
+            "title": "X",
+            "kind": "movie",
+            "year": 1999,
+            "rating": 5.0,
+            "votes": 10,
+            "score": null,
+        });
+        assert_eq!(
+            got,
+            serde_json::json!([
+                {
+                    "src": "matrix.1999.mkv",
+                    "dst": "The Matrix (1999).mkv",
+                    "action": "symlink",
+                    "id": "tt0133093",
+                    "title": "The Matrix",
+                    "kind": "movie",
+                    "year": 1999,
+                    "rating": null,
+                    "votes": null,
+                    "score": 0.5,
+                },
+                x,
+            ])
+        );
+        assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 1);
+
+        // With a --stats report, everything is still a single JSON value.
+        let mut out = vec![];
+        let report = MatchReport::default();
+        write_proposals_json(&mut out, &proposals[1..], None, Some(&report))
+            .unwrap();
+        let got: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(got["proposals"], serde_json::json!([x]));
+        assert_eq!(got["stats"]["matched"], 0);
+        assert!(got.get("explanations").is_none());
+        assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 1);
+    }
+
+    #[test]
+    fn ext_alias() {
+        let alias = |from: &str, to: &str| (from.to_string(), to.to_string());
+        assert_eq!(
+            parse_ext_alias("mpeg4=mp4").unwrap(),
+            alias("mpeg4", "mp4")
+        );
+        assert_eq!(
+            parse_ext_alias(".jpeg=.jpg").unwrap(),
+            alias("jpeg", "jpg")
+        );
+        assert!(parse_ext_alias("mpeg4").is_err());
+        assert!(parse_ext_alias("=mp4").is_err());
+        assert!(parse_ext_alias("mpeg4=").is_err());
+    }
+
+    #[test]
+    fn collect_follow() {
+        let tmp = TempDir::new("imdb-rename-test-collect-follow").unwrap();
+        let root = tmp.path().join("Movie (2017)");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("Movie.2017.mkv"), "").unwrap();
+        let file = tmp.path().join("Other.2018.mkv");
+        fs::write(&file, "").unwrap();
+
+        let collect = |follow: bool| {
+            let paths = vec![OsStr::new(&root), OsStr::new(&file)];
+            collect_paths(paths, follow, &DirFilter::new())
+        };
+        let (dir, child) = (root.clone(), root.join("Movie.2017.mkv"));
+        // Without --follow, the directory is renamed as a unit.
+        assert_eq!(collect(false), vec![dir.clone(), file.clone()]);
+        // With it, the entries inside it are renamed too.
+        assert_eq!(collect(true), vec![dir, child, file]);
+    }
+
+    #[test]
+    fn collect_skips_dirs() {
+        let tmp = TempDir::new("imdb-rename-test-collect").unwrap();
+        let root = tmp.path().join("Movie (2017)");
+        for dir in ["Extras", "Subs", "Sample/nested"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "Movie.2017.mkv",
+            "Extras/Interview.mkv",
+            "Sample/nested/sample.mkv",
+            "Subs/Movie.2017.en.srt",
+            "Subs/readme.txt",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let collect = |filter: &DirFilter| {
+            let mut paths: Vec<PathBuf> =
+                collect_paths(vec![OsStr::new(&root)], true, filter)
+                    .into_iter()
+                    .map(|p| p.strip_prefix(&root).unwrap().to_path_buf())
+                    .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            collect(&DirFilter::new()),
+            vec![
+                PathBuf::from(""),
+                PathBuf::from("Movie.2017.mkv"),
+                PathBuf::from("Subs/Movie.2017.en.srt"),
+            ]
+        );
+        // Names are matched case insensitively, and excluding a subtitle
+        // directory explicitly means its subtitles aren't collected either.
+        for subs in ["subs", "SUBS", "Subs"] {
+            assert_eq!(
+                collect(DirFilter::new().include("extras").exclude(subs)),
+                vec![
+                    PathBuf::from(""),
+                    PathBuf::from("Extras"),
+                    PathBuf::from("Extras/Interview.mkv"),
+                    PathBuf::from("Movie.2017.mkv"),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn collect_ignore_files() {
+        let tmp = TempDir::new("imdb-rename-test-collect-ignore").unwrap();
+        let root = tmp.path().join("Library");
+        for dir in [
+            "WIP",
+            "Extras",
+            "Show/Season 01",
+            "Show/Season 02",
+            "Show/Sample",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "Movie.2017.mkv",
+            "bonus.mkv",
+            "WIP/encode.mkv",
+            "Extras/Interview.mkv",
+            "Show/bonus.mkv",
+            "Show/wip",
+            "Show/Season 01/e1.mkv",
+            "Show/Season 02/e1.mkv",
+            "Show/Sample/sample.mkv",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(
+            root.join(".imdbrenameignore"),
+            "# work in progress\nwip/\nBONUS.mkv\n\n!extras\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Show/.imdbrenameignore"),
+            "!bonus.mkv\n/season 02\n",
+        )
+        .unwrap();
+
+        let collect = |filter: &DirFilter| {
+            let mut paths: Vec<String> =
+                collect_paths(vec![OsStr::new(&root)], true, filter)
+                    .into_iter()
+                    .map(|p| {
+                        let p = p.strip_prefix(&root).unwrap();
+                        p.to_string_lossy().replace('\\', "/")
+                    })
+                    .collect();
+            paths.sort();
+            paths
+        };
+        // The nearest ignore file decides: Show/bonus.mkv is re-included,
+        // while the root's bonus.mkv isn't. '/season 02' is anchored to
+        // Show, which contains its ignore file. 'wip/' only matches
+        // directories, and '!extras' overrides the default directory filter.
+        assert_eq!(
+            collect(&DirFilter::new()),
+            vec![
+                "",
+                "Extras",
+                "Extras/Interview.mkv",
+                "Movie.2017.mkv",
+                "Show",
+                "Show/Season 01",
+                "Show/Season 01/e1.mkv",
+                "Show/bonus.mkv",
+                "Show/wip",
+            ]
+        );
+        // Names that no ignore file mentions are still subject to
+        // --include-dir and --exclude-dir.
+        assert_eq!(
+            collect(DirFilter::new().include("sample").exclude("season 01")),
+            vec![
+                "",
+                "Extras",
+                "Extras/Interview.mkv",
+                "Movie.2017.mkv",
+                "Show",
+                "Show/Sample",
+                "Show/Sample/sample.mkv",
+                "Show/bonus.mkv",
+                "Show/wip",
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_excluded_globs() {
+        let tmp = TempDir::new("imdb-rename-test-collect-globs").unwrap();
+        let root = tmp.path().join("incoming");
+        for dir in ["Movie.2017/Screens", "Show/Season 01/Proof"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "Movie.2017/Movie.2017.mkv",
+            "Movie.2017/Movie.2017.NFO",
+            "Movie.2017/Screens/1.png",
+            "Show/Season 01/e1.mkv",
+            "Show/Season 01/e1.txt",
+            "Show/Season 01/Proof/proof.jpg",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let mut filter = DirFilter::new();
+        filter
+            .exclude_glob("*.nfo")
+            .unwrap()
+            .exclude_glob("**/*.txt")
+            .unwrap()
+            .exclude_glob("screens/")
+            .unwrap()
+            .exclude_glob("/show/*/proof")
+            .unwrap();
+        let mut paths: Vec<String> =
+            collect_paths(vec![OsStr::new(&root)], true, &filter)
+                .into_iter()
+                .map(|p| {
+                    let p = p.strip_prefix(&root).unwrap();
+                    p.to_string_lossy().replace('\\', "/")
+                })
+                .collect();
+        paths.sort();
+        // Excluded directories are pruned, along with everything in them.
+        assert_eq!(
+            paths,
+            vec![
+                "",
+                "Movie.2017",
+                "Movie.2017/Movie.2017.mkv",
+                "Show",
+                "Show/Season 01",
+                "Show/Season 01/e1.mkv",
+            ]
+        );
+    }
+
+    #[test]
+    fn select_only() {
+        let tmp = TempDir::new("imdb-rename-test-select-only").unwrap();
+        let root = tmp.path().join("Futurama");
+        fs::create_dir_all(root.join("Season 01")).unwrap();
+        fs::create_dir_all(root.join("Season 02")).unwrap();
+        for file in [
+            "futurama.nfo",
+            "Season 01/s01e01.mkv",
+            "Season 01/s01e02.mkv",
+            "Season 02/s02e01.mkv",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let select = |only: Option<EntryKind>| {
+            let paths = collect_paths(
+                vec![OsStr::new(&root)],
+                true,
+                &DirFilter::new(),
+            );
+            let (paths, excluded) = select_entries(paths, only);
+            let mut paths: Vec<String> = paths
+                .into_iter()
+                .map(|p| {
+                    let p = p.strip_prefix(tmp.path()).unwrap();
+                    p.to_string_lossy().replace('\\', "/")
+                })
+                .collect();
+            paths.sort();
+            (paths, excluded)
+        };
+        assert_eq!(select(None).0.len(), 7);
+        // The directory given is a candidate too.
+        let (dirs, excluded) = select(Some(EntryKind::Dirs));
+        assert_eq!(
+            dirs,
+            vec!["Futurama", "Futurama/Season 01", "Futurama/Season 02"]
+        );
+        assert_eq!(excluded, 4);
+        let (files, excluded) = select(Some(EntryKind::Files));
+        assert_eq!(
+            files,
+            vec![
+                "Futurama/Season 01/s01e01.mkv",
+                "Futurama/Season 01/s01e02.mkv",
+                "Futurama/Season 02/s02e01.mkv",
+                "Futurama/futurama.nfo",
+            ]
+        );
+        assert_eq!(excluded, 3);
+
+        // Paths given directly are selected too, and paths that don't
+        // exist are files.
+        let missing = root.join("missing.mkv");
+        let (paths, excluded) = select_entries(
+            vec![root.clone(), missing.clone()],
+            Some(EntryKind::Files),
+        );
+        assert_eq!((paths, excluded), (vec![missing], 1));
+        assert_eq!(EntryKind::from_name("dirs").unwrap(), EntryKind::Dirs);
+        assert!(EntryKind::from_name("links").is_err());
+    }
+
+    #[test]
+    fn version_mismatch_rebuilds() {
+        let tmp = TempDir::new("imdb-rename-test-rebuild").unwrap();
+        let index_dir = tmp.path().join("index");
+        let args = |extra: &[&str]| {
+            let mut argv = vec![
+                OsStr::new("imdb-rename"),
+                OsStr::new("--data-dir"),
+                OsStr::new("data/test/small"),
+                OsStr::new("--index-dir"),
+                index_dir.as_os_str(),
+            ];
+            argv.extend(extra.iter().map(OsStr::new));
+            let matches = app().get_matches_from_safe(argv).unwrap();
+            CommonArgs::from_matches(&Matches::new(matches, None)).unwrap()
+        };
+        // Pretend the index was built by some other version.
+        args(&[]).create_index().unwrap();
+        let config = index_dir.join("config.json");
+        fs::write(&config, r#"{"version":0}"#).unwrap();
+
+        let err = args(&["--no-auto-rebuild"]).open_index().unwrap_err();
+        let err = err.downcast::<imdb_index::Error>().unwrap();
+        assert!(matches!(
+            *err.kind(),
+            ErrorKind::VersionMismatch { got: 0, .. }
+        ));
+
+        assert!(args(&["--read-only-index"]).open_index().is_err());
+        assert!(args(&["--read-only-index"]).create_index().is_err());
+        assert_eq!(fs::read_to_string(&config).unwrap(), r#"{"version":0}"#);
+
+        args(&[]).open_index().unwrap();
+        assert_ne!(fs::read_to_string(&config).unwrap(), r#"{"version":0}"#);
+        args(&["--no-auto-rebuild"]).open_index().unwrap();
+    }
+
+    #[test]
+    fn score_buckets() {
+        assert_eq!(score_bucket(0.0), 0);
+        assert_eq!(score_bucket(0.099), 0);
+        assert_eq!(score_bucket(0.1), 1);
+        assert_eq!(score_bucket(0.5), 5);
+        assert_eq!(score_bucket(0.7), 7);
+        assert_eq!(score_bucket(0.95), 9);
+        assert_eq!(score_bucket(1.0), 9);
+        // Boosted or degenerate scores land in the nearest bucket.
+        assert_eq!(score_bucket(1.2), 9);
+        assert_eq!(score_bucket(-0.1), 0);
+        assert_eq!(score_bucket(f64::NAN), 0);
+    }
+
+    #[test]
+    fn match_report() {
+        let stat =
+            |pattern, decision, score| MatchStat { pattern, decision, score };
+        let stats = [
+            stat(Pattern::Year, Decision::Automatic, Some(1.0)),
+            stat(Pattern::Year, Decision::Automatic, Some(0.92)),
+            stat(Pattern::SeasonThenEpisode, Decision::Prompted, Some(0.64)),
+            stat(Pattern::None, Decision::Override, None),
+        ];
+        let report = MatchReport::new(&stats, 3, 1);
+        assert_eq!(report.matched, 4);
+        assert_eq!(report.automatic, 2);
+        assert_eq!(report.prompted, 1);
+        assert_eq!(report.overridden, 1);
+        assert_eq!(report.scores, [0, 0, 0, 0, 0, 0, 1, 0, 0, 2]);
+        assert_eq!(report.unscored, 1);
+        assert_eq!(report.patterns["year"], 2);
+        assert_eq!(report.patterns["season+episode"], 1);
+        assert_eq!(report.patterns["none"], 1);
+
+        let mut out = vec![];
+        report.write_text(&mut out).unwrap();
+        let got = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = got.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "matched:  4 (2 automatic, 1 prompted, 1 override)",
+                "skipped:  3 (1 undecided)",
+                "scores:",
+                "  0.9-1.0  2   ##############################",
+                "  0.8-0.9  0",
+                "  0.7-0.8  0",
+                "  0.6-0.7  1   ###############",
+                "  none     1",
+                "patterns:",
+                "  none            1",
+                "  season+episode  1",
+                "  year            2",
+            ]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+// An NFO file describes a movie or an episode for media centers like Kodi
+// and Jellyfin, which read it instead of (or before) looking the title up
+// themselves. Only the fields known from the IMDb data are written: the
+// title, year, IMDb identifier, rating and genres. NFO files are written
+// next to the destination after a proposal is executed.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use imdb_index::MediaEntity;
+use serde::{Deserialize, Serialize};
+
+/// The extension of NFO files, which replaces the extension of the
+/// destination they describe.
+const NFO_EXT: &str = "nfo";
+
+/// The contents of an NFO file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Nfo {
+    /// The IMDb identifier of the title.
+    pub id: String,
+    /// The primary name of the title.
+    pub title: String,
+    /// The year the title was released, if known.
+    pub year: Option<u32>,
+    /// The title's rating, on a scale of 0 to 10, and its number of votes.
+    pub rating: Option<(f32, u32)>,
+    /// The title's genres, in the order IMDb lists them.
+    pub genres: Vec<String>,
+    /// The season and episode numbers, if the title is a TV episode.
+    pub episode: Option<(u32, u32)>,
+}
+
+impl Nfo {
+    /// Describe the given entity.
+    pub fn new(ent: &MediaEntity) -> Nfo {
+        let title = ent.title();
+        Nfo {
+            id: title.id.clone(),
+            title: title.title.clone(),
+            year: title.start_year,
+            rating: ent.rating().map(|r| (r.rating, r.votes)),
+            genres: title
+                .genres
+                .split(',')
+                .map(|g| g.trim())
+                .filter(|g| !g.is_empty() && *g != "\\N")
+                .map(|g| g.to_string())
+                .collect(),
+            episode: ent
+                .episode()
+                .map(|ep| (ep.season.unwrap_or(0), ep.episode.unwrap_or(0))),
+        }
+    }
+
+    /// Return the path of the NFO file describing the given destination,
+    /// which is the destination with its extension replaced by `nfo`.
+    pub fn path(dst: &Path) -> PathBuf {
+        dst.with_extension(NFO_EXT)
+    }
+
+    /// Write this NFO file for the given destination.
+    ///
+    /// If an NFO file already exists for the destination, then it is left
+    /// alone and `None` is returned, unless `overwrite` is true. Otherwise,
+    /// the path written to is returned.
+    pub fn write(
+        &self,
+        dst: &Path,
+        overwrite: bool,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let path = Nfo::path(dst);
+        let mut opts = OpenOptions::new();
+        opts.write(true);
+        if overwrite {
+            opts.create(true).truncate(true);
+        } else {
+            opts.create_new(true);
+        }
+        let result = opts
+            .open(&path)
+            .and_then(|mut file| file.write_all(self.to_xml().as_bytes()));
+        match result {
+            Ok(()) => Ok(Some(path)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => anyhow::bail!(
+                "error writing NFO file '{}': {}",
+                path.display(),
+                e,
+            ),
+        }
+    }
+
+    /// Serialize this NFO file as XML. Episodes are described by an
+    /// `episodedetails` element and everything else by a `movie` element.
+    pub fn to_xml(&self) -> String {
+        let root = match self.episode {
+            None => "movie",
+            Some(_) => "episodedetails",
+        };
+        let mut xml = XmlWriter::new();
+        xml.start(root, &[]);
+        xml.element("title", &[], &self.title);
+        if let Some(year) = self.year {
+            xml.element("year", &[], &year.to_string());
+        }
+        if let Some((season, episode)) = self.episode {
+            xml.element("season", &[], &season.to_string());
+            xml.element("episode", &[], &episode.to_string());
+        }
+        xml.element(
+            "uniqueid",
+            &[("type", "imdb"), ("default", "true")],
+            &self.id,
+        );
+        if let Some((rating, votes)) = self.rating {
+            xml.start("ratings", &[]);
+            xml.start(
+                "rating",
+                &[("name", "imdb"), ("max", "10"), ("default", "true")],
+            );
+            xml.element("value", &[], &format!("{:.1}", rating));
+            xml.element("votes", &[], &votes.to_string());
+            xml.end("rating");
+            xml.end("ratings");
+        }
+        for genre in &self.genres {
+            xml.element("genre", &[], genre);
+        }
+        xml.end(root);
+        xml.finish()
+    }
+}
+
+/// A minimal writer of indented XML documents.
+///
+/// Elements either contain text or other elements, but never both, which
+/// is all NFO files need. Callers are responsible for closing every element
+/// they start, in order.
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    /// Create a writer for a new document, starting with its declaration.
+    fn new() -> XmlWriter {
+        XmlWriter {
+            out: "<?xml version=\"1.0\" encoding=\"UTF-8\" \
+                  standalone=\"yes\"?>\n"
+                .to_string(),
+            depth: 0,
+        }
+    }
+
+    /// Start an element with the given attributes, which contains other
+    /// elements.
+    fn start(&mut self, name: &str, attrs: &[(&str, &str)]) {
+        self.open_tag(name, attrs);
+        self.out.push('\n');
+        self.depth += 1;
+    }
+
+    /// End the element started most recently.
+    fn end(&mut self, name: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.out.push_str(&format!("</{}>\n", name));
+    }
+
+    /// Write an element with the given attributes containing only the given
+    /// text.
+    fn element(&mut self, name: &str, attrs: &[(&str, &str)], text: &str) {
+        self.open_tag(name, attrs);
+        self.out.push_str(&escape(text));
+        self.out.push_str(&format!("</{}>\n", name));
+    }
+
+    /// Return the document written so far.
+    fn finish(self) -> String {
+        self.out
+    }
+
+    fn open_tag(&mut self, name: &str, attrs: &[(&str, &str)]) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(name);
+        for (key, value) in attrs {
+            self.out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+        }
+        self.out.push('>');
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+    }
+}
+
+/// Escape the characters that are special in XML text and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{escape, Nfo};
+    use crate::util::tests::TempDir;
+
+    fn movie() -> Nfo {
+        Nfo {
+            id: "tt0087182".to_string(),
+            title: "Dune".to_string(),
+            year: Some(1984),
+            rating: Some((6.3, 190000)),
+            genres: vec!["Action".to_string(), "Adventure".to_string()],
+            episode: None,
+        }
+    }
+
+    #[test]
+    fn movie_xml() {
+        assert_eq!(
+            movie().to_xml(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <movie>\n\
+             \x20 <title>Dune</title>\n\
+             \x20 <year>1984</year>\n\
+             \x20 <uniqueid type=\"imdb\" default=\"true\">tt0087182\
+             </uniqueid>\n\
+             \x20 <ratings>\n\
+             \x20   <rating name=\"imdb\" max=\"10\" default=\"true\">\n\
+             \x20     <value>6.3</value>\n\
+             \x20     <votes>190000</votes>\n\
+             \x20   </rating>\n\
+             \x20 </ratings>\n\
+             \x20 <genre>Action</genre>\n\
+             \x20 <genre>Adventure</genre>\n\
+             </movie>\n"
+        );
+    }
+
+    #[test]
+    fn episode_xml() {
+        let nfo = Nfo {
+            id: "tt0701060".to_string(),
+            title: "Bart's Girlfriend".to_string(),
+            year: None,
+            rating: None,
+            genres: vec![],
+            episode: Some((6, 7)),
+        };
+        assert_eq!(
+            nfo.to_xml(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <episodedetails>\n\
+             \x20 <title>Bart&apos;s Girlfriend</title>\n\
+             \x20 <season>6</season>\n\
+             \x20 <episode>7</episode>\n\
+             \x20 <uniqueid type=\"imdb\" default=\"true\">tt0701060\
+             </uniqueid>\n\
+             </episodedetails>\n"
+        );
+    }
+
+    #[test]
+    fn write() {
+        let tmp = TempDir::new("imdb-rename-test-nfo").unwrap();
+        let dst = tmp.path().join("Dune (1984).mkv");
+        let path = tmp.path().join("Dune (1984).nfo");
+        assert_eq!(Nfo::path(&dst), path);
+
+        assert_eq!(movie().write(&dst, false).unwrap(), Some(path.clone()));
+        assert!(fs::read_to_string(&path).unwrap().contains("<movie>"));
+
+        // Existing NFO files are only replaced when asked to.
+        fs::write(&path, "mine").unwrap();
+        assert_eq!(movie().write(&dst, false).unwrap(), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "mine");
+        assert_eq!(movie().write(&dst, true).unwrap(), Some(path.clone()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), movie().to_xml());
+
+        let missing = tmp.path().join("missing").join("Dune (1984).mkv");
+        assert!(movie().write(&missing, false).is_err());
+        assert_eq!(Nfo::path(Path::new("Dune")), Path::new("Dune.nfo"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            escape("Tom & Jerry <\"Cat\"> 'Mouse'"),
+            "Tom &amp; Jerry &lt;&quot;Cat&quot;&gt; &apos;Mouse&apos;"
+        );
+    }
+}
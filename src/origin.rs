@@ -0,0 +1,411 @@
+// An origin records the name a file had before it was renamed, along with
+// the IMDb title it was matched to, so that the original release name isn't
+// lost. Origins are written next to the destination after a proposal is
+// executed, either as one sidecar file per destination or as one TSV file
+// per directory.
+//
+// Origins are also what --undo falls back to when there's no rename journal
+// to reverse: a file with an origin can be renamed back to its original name.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the file, in a destination directory, to which origins are
+/// appended when using `OriginFormat::Tsv`.
+pub const ORIGINS_TSV: &str = ".imdb-rename-origins.tsv";
+
+/// The name of a file that, when it exists in a destination directory,
+/// disables writing origins for destinations in that directory.
+pub const NO_ORIGINS: &str = ".imdb-rename-no-origins";
+
+/// The extension appended to a destination to get the path of its sidecar
+/// when using `OriginFormat::Sidecar`.
+const SIDECAR_EXT: &str = "origin.txt";
+
+/// How origins are written.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OriginFormat {
+    /// Append a row to `.imdb-rename-origins.tsv` in the destination's
+    /// directory. The file starts with a header row, and fields are escaped
+    /// such that they never contain tabs or new lines.
+    Tsv,
+    /// Write a `<destination>.origin.txt` file with one `key: value` line
+    /// per field.
+    Sidecar,
+}
+
+impl OriginFormat {
+    /// Parse the name of an origin format, as given on the command line.
+    pub fn from_name(name: &str) -> anyhow::Result<OriginFormat> {
+        match name {
+            "tsv" => Ok(OriginFormat::Tsv),
+            "sidecar" => Ok(OriginFormat::Sidecar),
+            unk => anyhow::bail!("unrecognized origin format: '{}'", unk),
+        }
+    }
+}
+
+/// Where a file came from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Origin {
+    /// The file name before renaming.
+    pub original: String,
+    /// The file name after renaming.
+    pub renamed: String,
+    /// The IMDb identifier of the title the file was matched to, if known.
+    pub id: Option<String>,
+    /// When the file was renamed, in seconds since the Unix epoch.
+    pub time: u64,
+}
+
+impl Origin {
+    /// Create an origin for renaming `src` to `dst` at the current time.
+    pub fn new(src: &Path, dst: &Path, id: Option<&str>) -> Origin {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Origin {
+            original: file_name(src),
+            renamed: file_name(dst),
+            id: id.map(|id| id.to_string()),
+            time,
+        }
+    }
+
+    /// Write this origin for the given destination in the given format.
+    ///
+    /// If the destination's directory contains a file named
+    /// `.imdb-rename-no-origins`, then nothing is written and `None` is
+    /// returned. Otherwise, the path written to is returned.
+    pub fn write(
+        &self,
+        dst: &Path,
+        format: OriginFormat,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let dir = dst.parent().unwrap_or(Path::new(""));
+        if dir.join(NO_ORIGINS).exists() {
+            return Ok(None);
+        }
+        let id = self.id.as_deref().unwrap_or("");
+        let path = match format {
+            OriginFormat::Tsv => {
+                let path = dir.join(ORIGINS_TSV);
+                let mut row = String::new();
+                if !path.exists() {
+                    row.push_str("original\trenamed\tid\ttime\n");
+                }
+                row.push_str(&format!(
+                    "{}\t{}\t{}\t{}\n",
+                    escape(&self.original),
+                    escape(&self.renamed),
+                    escape(id),
+                    self.time,
+                ));
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(row.as_bytes()))
+                    .map(|()| path)
+            }
+            OriginFormat::Sidecar => {
+                let path = sidecar_path(dst);
+                let contents = format!(
+                    "original: {}\nrenamed: {}\nid: {}\ntime: {}\n",
+                    escape(&self.original),
+                    escape(&self.renamed),
+                    escape(id),
+                    self.time,
+                );
+                fs::write(&path, contents).map(|()| path)
+            }
+        };
+        let path = path.map_err(|e| {
+            anyhow::anyhow!(
+                "error writing origin of '{}': {}",
+                dst.display(),
+                e,
+            )
+        })?;
+        Ok(Some(path))
+    }
+
+    /// Find the origin most recently written for the given destination,
+    /// along with the path of the file it was read from.
+    ///
+    /// The destination's sidecar is consulted first, and then the TSV file
+    /// in its directory. If neither records an origin for the destination,
+    /// then `None` is returned.
+    pub fn find(dst: &Path) -> anyhow::Result<Option<(Origin, PathBuf)>> {
+        let sidecar = sidecar_path(dst);
+        if let Some(data) = read_optional(&sidecar)? {
+            let origin = Origin::parse_sidecar(&data).ok_or_else(|| {
+                anyhow::anyhow!("invalid origin file '{}'", sidecar.display())
+            })?;
+            return Ok(Some((origin, sidecar)));
+        }
+        let tsv = dst.parent().unwrap_or(Path::new("")).join(ORIGINS_TSV);
+        let data = match read_optional(&tsv)? {
+            None => return Ok(None),
+            Some(data) => data,
+        };
+        let renamed = file_name(dst);
+        let origin = data
+            .lines()
+            .skip(1)
+            .filter_map(Origin::parse_tsv_row)
+            .filter(|o| o.renamed == renamed)
+            .last();
+        Ok(origin.map(|o| (o, tsv)))
+    }
+
+    /// Parse the contents of a sidecar written by `write`.
+    fn parse_sidecar(data: &str) -> Option<Origin> {
+        let mut fields = data.lines().map(|line| line.split_once(": "));
+        let mut field = |key: &str| match fields.next()? {
+            Some((k, v)) if k == key => Some(unescape(v)),
+            _ => None,
+        };
+        let original = field("original")?;
+        let renamed = field("renamed")?;
+        let id = field("id")?;
+        let time = field("time")?.parse().ok()?;
+        let id = if id.is_empty() { None } else { Some(id) };
+        Some(Origin { original, renamed, id, time })
+    }
+
+    /// Parse a row (other than the header) of a TSV file written by `write`.
+    fn parse_tsv_row(row: &str) -> Option<Origin> {
+        let fields: Vec<&str> = row.split('\t').collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        let id = unescape(fields[2]);
+        Some(Origin {
+            original: unescape(fields[0]),
+            renamed: unescape(fields[1]),
+            id: if id.is_empty() { None } else { Some(id) },
+            time: fields[3].parse().ok()?,
+        })
+    }
+}
+
+/// Remove the origin of renaming `src` to `dst` from the file at the given
+/// path, as returned by `Origin::write` or `Origin::find`.
+///
+/// A sidecar is removed entirely, while only the most recent matching row
+/// is removed from a TSV file (and the file itself, once only its header is
+/// left). Origins that are already gone are ignored, so that this can be
+/// retried.
+pub fn remove(path: &Path, src: &Path, dst: &Path) -> anyhow::Result<()> {
+    let err = |e: io::Error| {
+        anyhow::anyhow!("error removing origin '{}': {}", path.display(), e)
+    };
+    if path.file_name() != Some(ORIGINS_TSV.as_ref()) {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(err(e)),
+            _ => Ok(()),
+        };
+    }
+    let data = match read_optional(path)? {
+        None => return Ok(()),
+        Some(data) => data,
+    };
+    let (original, renamed) = (file_name(src), file_name(dst));
+    let mut rows: Vec<&str> = data.lines().collect();
+    let found = rows.iter().skip(1).rposition(|row| {
+        Origin::parse_tsv_row(row)
+            .is_some_and(|o| o.original == original && o.renamed == renamed)
+    });
+    match found {
+        None => return Ok(()),
+        // The header was skipped.
+        Some(i) => rows.remove(i + 1),
+    };
+    if rows.len() <= 1 {
+        return fs::remove_file(path).map_err(err);
+    }
+    let mut data = rows.join("\n");
+    data.push('\n');
+    fs::write(path, data).map_err(err)
+}
+
+/// Return the path of the sidecar of the given destination.
+fn sidecar_path(dst: &Path) -> PathBuf {
+    let mut name = dst.as_os_str().to_os_string();
+    name.push(".");
+    name.push(SIDECAR_EXT);
+    PathBuf::from(name)
+}
+
+/// Return the file name of the given path as it's recorded in an origin.
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+}
+
+/// Read the file at the given path, or return `None` if it doesn't exist.
+fn read_optional(path: &Path) -> anyhow::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => {
+            anyhow::bail!("error reading origin '{}': {}", path.display(), e)
+        }
+    }
+}
+
+/// Escape backslashes, tabs and new lines, so that a field can always be
+/// written on a single line of a TSV file.
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverse `escape`.
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{
+        escape, remove, unescape, Origin, OriginFormat, NO_ORIGINS,
+        ORIGINS_TSV,
+    };
+    use crate::util::tests::TempDir;
+
+    fn origin(original: &str) -> Origin {
+        Origin {
+            original: original.to_string(),
+            renamed: "Dune (1984).mkv".to_string(),
+            id: Some("tt0087182".to_string()),
+            time: 1700000000,
+        }
+    }
+
+    #[test]
+    fn tsv() {
+        let tmp = TempDir::new("imdb-rename-test-origin-tsv").unwrap();
+        let dst = tmp.path().join("Dune (1984).mkv");
+        let path = origin("Dune.1984.mkv")
+            .write(&dst, OriginFormat::Tsv)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, tmp.path().join(ORIGINS_TSV));
+        origin("dune\t1984.mkv").write(&dst, OriginFormat::Tsv).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "original\trenamed\tid\ttime\n\
+             Dune.1984.mkv\tDune (1984).mkv\ttt0087182\t1700000000\n\
+             dune\\t1984.mkv\tDune (1984).mkv\ttt0087182\t1700000000\n",
+        );
+    }
+
+    #[test]
+    fn sidecar() {
+        let tmp = TempDir::new("imdb-rename-test-origin-sidecar").unwrap();
+        let dst = tmp.path().join("Dune (1984).mkv");
+        let path = origin("Dune.1984.mkv")
+            .write(&dst, OriginFormat::Sidecar)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, tmp.path().join("Dune (1984).mkv.origin.txt"));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "original: Dune.1984.mkv\n\
+             renamed: Dune (1984).mkv\n\
+             id: tt0087182\n\
+             time: 1700000000\n",
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let tmp = TempDir::new("imdb-rename-test-origin-disabled").unwrap();
+        fs::write(tmp.path().join(NO_ORIGINS), "").unwrap();
+        let dst = tmp.path().join("Dune (1984).mkv");
+        for format in [OriginFormat::Tsv, OriginFormat::Sidecar] {
+            let written = origin("Dune.1984.mkv").write(&dst, format).unwrap();
+            assert_eq!(written, None);
+        }
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn find_and_remove() {
+        let tmp = TempDir::new("imdb-rename-test-origin-find").unwrap();
+        let dune = tmp.path().join("Dune (1984).mkv");
+        let heat = tmp.path().join("Heat (1995).mkv");
+        origin("dune\t1984.mkv").write(&dune, OriginFormat::Tsv).unwrap();
+        let mut heat_origin = origin("Heat.1995.mkv");
+        heat_origin.renamed = "Heat (1995).mkv".to_string();
+        heat_origin.id = None;
+        let tsv =
+            heat_origin.write(&heat, OriginFormat::Tsv).unwrap().unwrap();
+
+        let (found, path) = Origin::find(&dune).unwrap().unwrap();
+        assert_eq!(found, origin("dune\t1984.mkv"));
+        assert_eq!(path, tsv);
+        assert_eq!(Origin::find(&heat).unwrap().unwrap().0, heat_origin);
+
+        // Removing one row keeps the other, and removing the last row
+        // removes the file.
+        let src = tmp.path().join("dune\t1984.mkv");
+        remove(&tsv, &src, &dune).unwrap();
+        assert_eq!(Origin::find(&dune).unwrap(), None);
+        assert_eq!(Origin::find(&heat).unwrap().unwrap().0, heat_origin);
+        remove(&tsv, &src, &dune).unwrap();
+        remove(&tsv, &tmp.path().join("Heat.1995.mkv"), &heat).unwrap();
+        assert!(!tsv.exists());
+
+        // Sidecars win over TSV files.
+        let sidecar =
+            origin("Dune.mkv").write(&dune, OriginFormat::Sidecar).unwrap();
+        origin("dune.mkv").write(&dune, OriginFormat::Tsv).unwrap();
+        let (found, path) = Origin::find(&dune).unwrap().unwrap();
+        assert_eq!(found, origin("Dune.mkv"));
+        assert_eq!(Some(path.clone()), sidecar);
+        remove(&path, &src, &dune).unwrap();
+        assert!(!path.exists());
+        remove(&path, &src, &dune).unwrap();
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape("a\tb\nc\\d\re"), "a\\tb\\nc\\\\d\\re");
+        assert_eq!(unescape(&escape("a\tb\nc\\d\re\\")), "a\tb\nc\\d\re\\");
+        assert_eq!(OriginFormat::from_name("tsv").unwrap(), OriginFormat::Tsv);
+        assert!(OriginFormat::from_name("csv").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+// A plan is a batch of confirmed proposals saved to a file, such that the
+// proposals can be computed on one machine (which has the IMDb index) and
+// executed on another (which only has the files being renamed). Applying a
+// plan never needs the index.
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rename::RenameProposal;
+
+/// The version of the plan format.
+///
+/// A plan with a different version is refused, since its proposals may not
+/// mean what this version of imdb-rename thinks they mean.
+const VERSION: u64 = 2;
+
+/// The contents of a plan file.
+#[derive(Debug, Deserialize, Serialize)]
+struct Plan<P> {
+    version: u64,
+    proposals: P,
+}
+
+/// Write the given proposals to a plan file at the given path.
+pub fn export(
+    path: &Path,
+    proposals: &[RenameProposal],
+) -> anyhow::Result<()> {
+    let plan = Plan { version: VERSION, proposals };
+    let mut json = serde_json::to_vec_pretty(&plan)
+        .map_err(|e| anyhow::anyhow!("error serializing plan: {}", e))?;
+    json.push(b'\n');
+    fs::write(path, json).map_err(|e| {
+        anyhow::anyhow!("error writing plan '{}': {}", path.display(), e)
+    })?;
+    Ok(())
+}
+
+/// Read the proposals from the plan file at the given path.
+///
+/// This only checks that the plan is well formed. Use `validate` to check
+/// that its proposals can still be executed.
+pub fn import(path: &Path) -> anyhow::Result<Vec<RenameProposal>> {
+    let data = fs::read(path).map_err(|e| {
+        anyhow::anyhow!("error reading plan '{}': {}", path.display(), e)
+    })?;
+    let invalid = |e: serde_json::Error| {
+        anyhow::anyhow!("invalid plan '{}': {}", path.display(), e)
+    };
+    // The version is checked before the proposals are deserialized, so that
+    // a plan from a different version is reported as such, instead of as an
+    // obscure error about some field.
+    let plan: Plan<serde_json::Value> =
+        serde_json::from_slice(&data).map_err(invalid)?;
+    if plan.version != VERSION {
+        anyhow::bail!(
+            "plan '{}' has version {}, but only version {} is supported, \
+             please export it again",
+            path.display(),
+            plan.version,
+            VERSION,
+        );
+    }
+    serde_json::from_value(plan.proposals).map_err(invalid)
+}
+
+/// Check that every proposal in a plan can be executed: its source must
+/// exist and its destination must be free.
+///
+/// Proposals whose destination already exists and is exactly what executing
+/// them would produce (which is only possible for links) are dropped, so
+/// that applying a plan twice is harmless. If any proposal can't be
+/// executed, then an error listing all of them is returned.
+pub fn validate(
+    proposals: Vec<RenameProposal>,
+) -> anyhow::Result<Vec<RenameProposal>> {
+    let mut pending = vec![];
+    let mut problems = vec![];
+    for p in proposals {
+        if fs::symlink_metadata(p.src()).is_err() {
+            problems.push(format!(
+                "source '{}' does not exist",
+                p.src().display()
+            ));
+            continue;
+        }
+        match p.is_done() {
+            Ok(true) => {}
+            Ok(false) => pending.push(p),
+            Err(err) => problems.push(err.to_string()),
+        }
+    }
+    if !problems.is_empty() {
+        let mut msg = format!(
+            "plan cannot be applied, {} proposal(s) are invalid:",
+            problems.len()
+        );
+        for problem in &problems {
+            msg.push_str(&format!("\n  {}", problem));
+        }
+        anyhow::bail!("{}", msg);
+    }
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{export, import, validate};
+    use crate::rename::{RenameAction, RenameProposal};
+    use crate::util::tests::{entity, TempDir};
+
+    #[test]
+    fn roundtrip() {
+        let tmp = TempDir::new("imdb-rename-test-plan").unwrap();
+        let a = tmp.path().join("a.mkv");
+        let proposals = vec![
+            RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                RenameAction::Rename,
+                entity(),
+            ),
+            RenameProposal::new(
+                a.clone(),
+                tmp.path(),
+                "B (2001).mkv",
+                RenameAction::Hardlink,
+                entity(),
+            ),
+        ];
+        let path = tmp.path().join("plan.json");
+        export(&path, &proposals).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains(r#""version": 2"#), "{}", json);
+        assert!(json.contains(r#""action": "hardlink""#), "{}", json);
+
+        let got = import(&path).unwrap();
+        assert_eq!(got.len(), 2);
+        for (got, want) in got.iter().zip(&proposals) {
+            assert_eq!(got.src(), want.src());
+            assert_eq!(got.dst(), want.dst());
+            assert_eq!(got.action(), want.action());
+            assert_eq!(got.matched(), want.matched());
+        }
+    }
+
+    #[test]
+    fn other_version() {
+        let tmp = TempDir::new("imdb-rename-test-plan").unwrap();
+        let path = tmp.path().join("plan.json");
+        fs::write(&path, r#"{"version": 99, "proposals": "?"}"#).unwrap();
+        let err = import(&path).unwrap_err().to_string();
+        assert!(err.contains("has version 99"), "{}", err);
+    }
+
+    #[test]
+    fn validate_plan() {
+        let tmp = TempDir::new("imdb-rename-test-plan").unwrap();
+        let (a, b) = (tmp.path().join("a.mkv"), tmp.path().join("b.mkv"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        fs::write(tmp.path().join("B (2001).mkv"), "taken").unwrap();
+        let proposal = |src: &std::path::Path, name, action| {
+            RenameProposal::new(
+                src.to_path_buf(),
+                tmp.path(),
+                name,
+                action,
+                entity(),
+            )
+        };
+
+        let ok = proposal(&a, "A (2000).mkv", RenameAction::Rename);
+        assert_eq!(validate(vec![ok.clone()]).unwrap().len(), 1);
+
+        // A link that already exists is dropped instead of being an error.
+        let linked = proposal(&a, "A (2000).mkv", RenameAction::Hardlink);
+        linked.rename().unwrap();
+        assert!(validate(vec![linked]).unwrap().is_empty());
+
+        let taken = proposal(&b, "B (2001).mkv", RenameAction::Rename);
+        let missing = proposal(
+            &tmp.path().join("c.mkv"),
+            "C (2002).mkv",
+            RenameAction::Rename,
+        );
+        let err = validate(vec![taken, missing]).unwrap_err().to_string();
+        assert!(err.contains("2 proposal(s) are invalid"), "{}", err);
+        assert!(err.contains("already exists"), "{}", err);
+        assert!(err.contains("does not exist"), "{}", err);
+    }
+}
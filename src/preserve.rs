@@ -0,0 +1,107 @@
+// This module provides a small abstraction for carrying file metadata (access
+// and modification times, and mode bits on Unix) from one file to another.
+// Renames and links never need this since the destination *is* the source,
+// but actions that create a new file do, otherwise media library scanners
+// think every file was just added.
+
+use std::fs::{self, File, FileTimes};
+use std::io;
+use std::path::Path;
+
+/// The metadata that is carried over from one file to another.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Preserve {
+    /// The modification time, and the permissions on Unix.
+    Modified,
+    /// The access and modification times, and the permissions on Unix.
+    All,
+}
+
+/// A snapshot of the metadata of a file that can be applied to another file.
+#[derive(Clone, Debug)]
+pub struct Preserved {
+    times: FileTimes,
+    permissions: fs::Permissions,
+}
+
+impl Preserved {
+    /// Capture the given metadata of the file at the given path.
+    ///
+    /// Symlinks are followed.
+    pub fn capture(path: &Path, what: Preserve) -> io::Result<Preserved> {
+        let md = fs::metadata(path)?;
+        let mut times = FileTimes::new().set_modified(md.modified()?);
+        if what == Preserve::All {
+            times = times.set_accessed(md.accessed()?);
+        }
+        Ok(Preserved { times, permissions: md.permissions() })
+    }
+
+    /// Apply this metadata to the file at the given path.
+    ///
+    /// Permissions are applied last, since applying them first could make
+    /// the file read-only, which prevents setting its times on some
+    /// platforms.
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        File::options().write(true).open(path)?.set_times(self.times)?;
+        fs::set_permissions(path, self.permissions.clone())
+    }
+}
+
+/// Copy the given metadata of `src` to `dst`.
+pub fn preserve_metadata(
+    src: &Path,
+    dst: &Path,
+    what: Preserve,
+) -> io::Result<()> {
+    Preserved::capture(src, what)?.apply(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    use super::{preserve_metadata, Preserve};
+    use crate::util::tests::TempDir;
+
+    #[test]
+    fn times_and_permissions() {
+        let tmp = TempDir::new("imdb-rename-test-preserve").unwrap();
+        let src = tmp.path().join("src.mkv");
+        let dst = tmp.path().join("dst.mkv");
+        fs::write(&src, "src").unwrap();
+        fs::write(&dst, "dst").unwrap();
+
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let f = fs::File::options().write(true).open(&src).unwrap();
+        f.set_times(fs::FileTimes::new().set_accessed(old).set_modified(old))
+            .unwrap();
+        drop(f);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let perms = fs::Permissions::from_mode(0o640);
+            fs::set_permissions(&src, perms).unwrap();
+        }
+
+        // The access time is only carried over when asked for.
+        preserve_metadata(&src, &dst, Preserve::Modified).unwrap();
+        let dmd = fs::metadata(&dst).unwrap();
+        assert_eq!(dmd.modified().unwrap(), old);
+        assert_ne!(dmd.accessed().unwrap(), old);
+
+        preserve_metadata(&src, &dst, Preserve::All).unwrap();
+        let (smd, dmd) =
+            (fs::metadata(&src).unwrap(), fs::metadata(&dst).unwrap());
+        assert_eq!(dmd.modified().unwrap(), old);
+        assert_eq!(dmd.modified().unwrap(), smd.modified().unwrap());
+        assert_eq!(dmd.accessed().unwrap(), old);
+        assert_eq!(dmd.permissions(), smd.permissions());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(dmd.permissions().mode() & 0o777, 0o640);
+        }
+    }
+}
@@ -1,25 +1,221 @@
 use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::fmt;
 use std::fs;
-use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
-use imdb_index::{MediaEntity, Query, SearchResults, Searcher, TitleKind};
+use globset::{GlobBuilder, GlobMatcher};
+use imdb_index::{
+    MediaEntity, Query, Scored, SearchDiagnostics, SearchResults, Searcher,
+    Similarity, Title, TitleKind,
+};
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
 
-use crate::util::choose;
+use crate::events::{Event, EventSink};
+use crate::explain::{Explanation, Outcome, Parse, SearchTrace};
+use crate::i18n::{tr, trf};
+use crate::nfo::Nfo;
+use crate::origin::{Origin, OriginFormat};
+use crate::preserve::{preserve_metadata, Preserve};
+use crate::util::{
+    auto_choice, prompt_choice, same_device, Answer, Highlighter,
+};
 
 /// A proposal to rename a `src` file path to a `dst` file path.
-#[derive(Clone, Debug)]
+///
+/// Proposals can be serialized so that they can be saved in a plan and
+/// executed later. See the `plan` module.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RenameProposal {
     src: PathBuf,
     dst: PathBuf,
     action: RenameAction,
+    #[serde(default)]
+    preserve_times: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link_target: Option<PathBuf>,
+    /// Whether a reflink falls back to a regular copy when the file system
+    /// can't clone the source.
+    #[serde(default)]
+    reflink_fallback: bool,
+    #[serde(default)]
+    mkdir: bool,
+    #[serde(default, skip_serializing_if = "DirPerms::is_default")]
+    dir_perms: DirPerms,
+    /// How to record the source's name after executing this proposal, if
+    /// at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<OriginFormat>,
+    /// The entity this proposal renames to.
+    entity: MediaEntity,
+    /// The score of the search result that the entity was chosen from, if
+    /// a search chose it.
+    #[serde(default)]
+    score: Option<f64>,
+    /// How this proposal was matched to its entity. This is only set on
+    /// proposals made by a renamer, not on those read from a plan. See
+    /// `Renamer::match_stats`.
+    #[serde(skip)]
+    stat: Option<MatchStat>,
+    /// The NFO file to write next to the destination after executing this
+    /// proposal, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nfo: Option<Nfo>,
+    /// Whether to replace an existing NFO file.
+    #[serde(default)]
+    force_nfo: bool,
+    /// The number of bytes to copy when this proposal renames its source to
+    /// another file system, which is done by copying the source and then
+    /// removing it. This is `None` when the rename stays on one file system.
+    /// See `detect_cross_device`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cross_device: Option<u64>,
+}
+
+/// What executing a proposal wrote besides its destination, and the
+/// problems that didn't fail it.
+#[derive(Debug, Default)]
+pub struct Executed {
+    /// The NFO file written next to the destination, if any.
+    pub nfo: Option<PathBuf>,
+    /// The file that the origin of the destination was written to, if any.
+    /// See `Origin::write`.
+    pub origin: Option<PathBuf>,
+    /// The directories created for the destination because of `mkdir`,
+    /// outermost first.
+    pub dirs: Vec<PathBuf>,
+    /// The problems that came up after the destination was in place.
+    pub warnings: Vec<anyhow::Error>,
+}
+
+/// A summary of the IMDb entity that a proposal's destination was derived
+/// from, so that a wrong match can be spotted without reading the new name
+/// carefully.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MatchSummary {
+    /// The IMDb identifier of the entity.
+    pub id: String,
+    /// The primary name of the entity.
+    pub title: String,
+    /// The kind of the entity, e.g., `movie` or `tvEpisode`.
+    pub kind: String,
+    /// The year the entity was released, if known.
+    pub year: Option<u32>,
+    /// The entity's rating, on a scale of 0 to 10, if it has one.
+    pub rating: Option<f32>,
+    /// The number of votes for the entity's rating, if it has one.
+    pub votes: Option<u32>,
+    /// The score of the search result that was chosen, if a search chose it.
+    /// For an episode, this is the score of its TV show, since the episode
+    /// itself is looked up by number.
+    pub score: Option<f64>,
+}
+
+impl MatchSummary {
+    /// Summarize the given entity, which was chosen with the given score.
+    pub fn new(ent: &MediaEntity, score: Option<f64>) -> MatchSummary {
+        MatchSummary {
+            id: ent.title().id.clone(),
+            title: ent.title().title.clone(),
+            kind: ent.title().kind.to_string(),
+            year: ent.title().start_year,
+            rating: ent.rating().map(|r| r.rating),
+            votes: ent.rating().map(|r| r.votes),
+            score,
+        }
+    }
+}
+
+impl fmt::Display for MatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.id, self.kind)?;
+        match self.year {
+            None => write!(f, " (no year)")?,
+            Some(year) => write!(f, " {}", year)?,
+        }
+        match (self.rating, self.votes) {
+            (Some(rating), Some(votes)) => {
+                write!(f, ", rated {:.1} ({} votes)", rating, votes)?
+            }
+            _ => write!(f, ", unrated")?,
+        }
+        match self.score {
+            None => write!(f, ", not searched"),
+            Some(score) => write!(f, ", score {:.3}", score),
+        }
+    }
+}
+
+/// The pattern that a path's name was parsed with.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Pattern {
+    /// The season and episode numbers were found by the regex given via
+    /// `RenamerBuilder::regex_season_episode`.
+    SeasonEpisode,
+    /// The season number was found by the season regex, followed closely by
+    /// the episode number found by the episode regex.
+    SeasonThenEpisode,
+    /// The episode number was found in the name, with the season number in
+    /// an ancestor directory.
+    SeasonDir,
+    /// The name had no episode information, but both the season and episode
+    /// were overridden.
+    Override,
+    /// The year was found by the year regex.
+    Year,
+    /// Nothing was found, so the name could only be renamed via an entity
+    /// override.
+    None,
+}
+
+impl Pattern {
+    /// The name of this pattern, as shown in statistics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Pattern::SeasonEpisode => "season-episode",
+            Pattern::SeasonThenEpisode => "season+episode",
+            Pattern::SeasonDir => "season-dir",
+            Pattern::Override => "override",
+            Pattern::Year => "year",
+            Pattern::None => "none",
+        }
+    }
+}
+
+/// How the entity of a proposal was decided on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decision {
+    /// A single search result stood out, so it was accepted without asking.
+    Automatic,
+    /// The end user picked a result when prompted.
+    Prompted,
+    /// The entity override was used instead of searching.
+    Override,
+}
+
+/// How a single proposal was matched to its entity. These are collected by
+/// a renamer for every proposal it makes. See `Renamer::match_stats`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchStat {
+    /// The pattern that the source's name was parsed with.
+    pub pattern: Pattern,
+    /// How the entity was decided on.
+    pub decision: Decision,
+    /// The score of the chosen search result, if a search chose it. See
+    /// `MatchSummary::score`.
+    pub score: Option<f64>,
 }
 
 /// The action to take when renaming a file.
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RenameAction {
     /// This does a simple rename of the file.
     Rename,
@@ -27,6 +223,13 @@ pub enum RenameAction {
     Symlink,
     /// This creates a hardlink to the given file.
     Hardlink,
+    /// This copies the given file, recursively if it's a directory.
+    Copy,
+    /// This clones the given file, recursively if it's a directory, such
+    /// that the clone shares its data with the source until either is
+    /// changed. This requires a file system that supports it, like btrfs or
+    /// XFS, and is only supported on Linux.
+    Reflink,
 }
 
 impl fmt::Display for RenameAction {
@@ -35,6 +238,8 @@ impl fmt::Display for RenameAction {
             RenameAction::Rename => "rename",
             RenameAction::Symlink => "symlink",
             RenameAction::Hardlink => "hardlink",
+            RenameAction::Copy => "copy",
+            RenameAction::Reflink => "reflink",
         }
         .fmt(f)
     }
@@ -43,7 +248,9 @@ impl fmt::Display for RenameAction {
 impl RenameAction {
     fn is_link(&self) -> bool {
         match *self {
-            RenameAction::Rename => false,
+            RenameAction::Rename
+            | RenameAction::Copy
+            | RenameAction::Reflink => false,
             RenameAction::Symlink | RenameAction::Hardlink => true,
         }
     }
@@ -55,12 +262,14 @@ impl RenameProposal {
     /// `dst_name` is sanitized to be safe as a file name.
     ///
     /// The given action determines whether to rename the source to the
-    /// destination, create a symlink or create a hardlink.
-    fn new(
+    /// destination, create a symlink, create a hardlink, copy it or reflink
+    /// it. The given entity is what the destination was derived from.
+    pub(crate) fn new(
         src: PathBuf,
         dst_parent: &Path,
         dst_name: &str,
         action: RenameAction,
+        entity: MediaEntity,
     ) -> RenameProposal {
         lazy_static! {
             static ref RE_BAD_PATH_CHARS: Regex =
@@ -68,14 +277,173 @@ impl RenameProposal {
         }
         let name = RE_BAD_PATH_CHARS.replace_all(dst_name, "_");
 
-        RenameProposal { src, dst: dst_parent.join(&*name), action }
+        RenameProposal {
+            src,
+            dst: dst_parent.join(&*name),
+            action,
+            preserve_times: false,
+            link_target: None,
+            reflink_fallback: false,
+            mkdir: false,
+            dir_perms: DirPerms::default(),
+            origin: None,
+            entity,
+            score: None,
+            stat: None,
+            nfo: None,
+            force_nfo: false,
+            cross_device: None,
+        }
+    }
+
+    /// Check whether executing this proposal renames its source to another
+    /// file system, which can't be done by renaming. If so, then the source
+    /// will be copied to its destination and then removed, and the number
+    /// of bytes to copy is recorded. See `cross_device`.
+    ///
+    /// If it can't be determined whether the source and destination are on
+    /// the same file system, then the source is renamed as usual, which
+    /// fails without touching the source if they are not.
+    ///
+    /// Links are never copied, so this is a no-op for them.
+    pub fn detect_cross_device(&mut self) {
+        self.cross_device = None;
+        if self.action != RenameAction::Rename {
+            return;
+        }
+        let dst_parent = self.dst.parent().unwrap_or(Path::new(""));
+        if same_device(&self.src, dst_parent) == Some(false) {
+            self.cross_device = Some(disk_size(&self.src));
+        }
+    }
+
+    /// Make the symlink created by this proposal point to its source via a
+    /// path relative to the destination's parent directory, instead of via
+    /// the source path itself. This permits moving the source and
+    /// destination trees together, e.g., by mounting them at a different
+    /// prefix.
+    ///
+    /// If the source and destination are on different devices, then a
+    /// relative link would break as soon as either mount moves, so a warning
+    /// is logged and an absolute link is used instead.
+    ///
+    /// This has no effect unless this proposal's action is a symlink. An
+    /// error is returned if either path could not be made absolute.
+    pub fn make_symlink_relative(&mut self) -> anyhow::Result<()> {
+        if self.action != RenameAction::Symlink {
+            return Ok(());
+        }
+        let src = self.src.canonicalize().map_err(|e| {
+            anyhow::anyhow!(
+                "error making {} an absolute path: {}",
+                self.src.display(),
+                e,
+            )
+        })?;
+        let dst_parent = self.dst.parent().unwrap_or(Path::new(""));
+        let dst_parent = canonicalize_lenient(dst_parent).map_err(|e| {
+            anyhow::anyhow!(
+                "error making {} an absolute path: {}",
+                dst_parent.display(),
+                e,
+            )
+        })?;
+        let relative = if same_device(&src, &dst_parent) == Some(false) {
+            None
+        } else {
+            relative_path(&dst_parent, &src)
+        };
+        match relative {
+            Some(relative) => self.link_target = Some(relative),
+            None => {
+                log::warn!(
+                    "{} and {} are on different file systems, \
+                     using an absolute symlink",
+                    src.display(),
+                    dst_parent.display(),
+                );
+                self.link_target = Some(src);
+            }
+        }
+        Ok(())
+    }
+
+    /// The metadata that a copy or reflink made by this proposal carries
+    /// over from its source.
+    fn preserve(&self) -> Preserve {
+        if self.preserve_times {
+            Preserve::All
+        } else {
+            Preserve::Modified
+        }
     }
 
     /// Execute this proposal according to `RenameAction`.
-    pub fn rename(&self) -> anyhow::Result<()> {
+    ///
+    /// Once the destination is in place, nothing fails this proposal
+    /// anymore. Instead, problems with what's written next to the
+    /// destination are returned as warnings.
+    pub fn rename(&self) -> anyhow::Result<Executed> {
+        let mut dirs = vec![];
+        if self.mkdir {
+            if let Some(parent) = self.dst.parent() {
+                dirs = create_dirs(parent, &self.dir_perms).map_err(|e| {
+                    anyhow::anyhow!(
+                        "error creating directory '{}': {}",
+                        parent.display(),
+                        e,
+                    )
+                })?;
+            }
+        }
+        if let Err(err) = self.execute_action() {
+            // Don't leave behind directories for a destination that was
+            // never created.
+            remove_dirs(&dirs);
+            return Err(err);
+        }
+        // The file has already been renamed at this point, so failing to
+        // record where it came from shouldn't fail (or roll back) anything.
+        let mut executed = Executed { dirs, ..Executed::default() };
+        if let Some(format) = self.origin {
+            let id = &self.entity.title().id;
+            let origin = Origin::new(&self.src, &self.dst, Some(id));
+            match origin.write(&self.dst, format) {
+                Ok(path) => executed.origin = path,
+                Err(err) => executed.warnings.push(err),
+            }
+        }
+        if let Some(ref nfo) = self.nfo {
+            match nfo.write(&self.dst, self.force_nfo) {
+                Ok(Some(path)) => executed.nfo = Some(path),
+                Ok(None) => executed.warnings.push(anyhow::anyhow!(
+                    "{}",
+                    trf(
+                        "warning.nfo-exists",
+                        &[&Nfo::path(&self.dst).display()]
+                    ),
+                )),
+                Err(err) => executed.warnings.push(err),
+            }
+        }
+        Ok(executed)
+    }
+
+    /// Create the destination according to `RenameAction`.
+    fn execute_action(&self) -> anyhow::Result<()> {
         match self.action {
+            RenameAction::Rename if self.cross_device.is_some() => {
+                move_across_devices(&self.src, &self.dst).map_err(|e| {
+                    anyhow::anyhow!(
+                        "error moving '{}' to '{}' across file systems: {}",
+                        self.src.display(),
+                        self.dst.display(),
+                        e,
+                    )
+                })?;
+            }
             RenameAction::Rename => {
-                fs::rename(&self.src, &self.dst).map_err(|e| {
+                rename_path(&self.src, &self.dst).map_err(|e| {
                     anyhow::anyhow!(
                         "error renaming '{}' to '{}': {}",
                         self.src.display(),
@@ -92,7 +460,8 @@ impl RenameProposal {
             RenameAction::Symlink => {
                 use std::os::unix;
 
-                unix::fs::symlink(&self.src, &self.dst).map_err(|e| {
+                let target = self.link_target();
+                unix::fs::symlink(target, &self.dst).map_err(|e| {
                     anyhow::anyhow!(
                         "error symlinking '{}' to '{}': {}",
                         self.src.display(),
@@ -111,10 +480,121 @@ impl RenameProposal {
                     )
                 })?;
             }
+            RenameAction::Copy => {
+                let what = self.preserve();
+                copy_path(&self.src, &self.dst, what).map_err(|e| {
+                    anyhow::anyhow!(
+                        "error copying '{}' to '{}': {}",
+                        self.src.display(),
+                        self.dst.display(),
+                        e,
+                    )
+                })?;
+            }
+            RenameAction::Reflink => {
+                reflink_path(
+                    &self.src,
+                    &self.dst,
+                    self.reflink_fallback,
+                    self.preserve(),
+                )
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "error reflinking '{}' to '{}': {}",
+                        self.src.display(),
+                        self.dst.display(),
+                        e,
+                    )
+                })?;
+            }
         }
         Ok(())
     }
 
+    /// Returns true if and only if the destination of this proposal already
+    /// exists and is exactly what executing this proposal would produce.
+    /// This is only possible for links: an existing hardlink to the same
+    /// inode, or an existing symlink that resolves to the source.
+    ///
+    /// If the destination exists and is anything else (including a dangling
+    /// symlink), then an error is returned. If the destination doesn't exist,
+    /// then this returns false.
+    pub(crate) fn is_done(&self) -> anyhow::Result<bool> {
+        let dst_md = match fs::symlink_metadata(&self.dst) {
+            Ok(md) => md,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(false)
+            }
+            Err(e) => anyhow::bail!(
+                "error reading metadata of '{}': {}",
+                self.dst.display(),
+                e,
+            ),
+        };
+        let done = match self.action {
+            // On case insensitive file systems, the destination of a rename
+            // that only changes case "exists" because it is the source.
+            RenameAction::Rename
+                if is_case_only(&self.src, &self.dst)
+                    && same_file(&self.src, &self.dst) =>
+            {
+                return Ok(false)
+            }
+            RenameAction::Rename
+            | RenameAction::Copy
+            | RenameAction::Reflink => false,
+            RenameAction::Hardlink => {
+                !dst_md.file_type().is_symlink()
+                    && same_file(&self.src, &self.dst)
+            }
+            RenameAction::Symlink => {
+                dst_md.file_type().is_symlink()
+                    && same_file(&self.src, &self.dst)
+            }
+        };
+        if !done {
+            anyhow::bail!("file path '{}' already exists", self.dst.display());
+        }
+        Ok(true)
+    }
+
+    /// The action executed by this proposal.
+    pub fn action(&self) -> RenameAction {
+        self.action
+    }
+
+    /// A summary of the entity this proposal renames to, along with the
+    /// score it was chosen with.
+    pub fn matched(&self) -> MatchSummary {
+        MatchSummary::new(&self.entity, self.score)
+    }
+
+    /// The entity this proposal renames to, e.g., for writing metadata
+    /// alongside the renamed file.
+    pub fn entity(&self) -> &MediaEntity {
+        &self.entity
+    }
+
+    /// The score of the search result that this proposal's entity was
+    /// chosen from. This is `None` when no search chose the entity, e.g.,
+    /// when it was given via -q/--query.
+    pub fn score(&self) -> Option<f64> {
+        self.score
+    }
+
+    /// The number of bytes that executing this proposal copies to another
+    /// file system, if it renames across file systems. This is only known
+    /// after calling `detect_cross_device`.
+    pub fn cross_device(&self) -> Option<u64> {
+        self.cross_device
+    }
+
+    /// The path that a symlink created by this proposal points to. This is
+    /// `src`, unless the symlink is relative.
+    pub(crate) fn link_target(&self) -> &Path {
+        self.link_target.as_deref().unwrap_or(&self.src)
+    }
+
     /// The `src` of this proposal.
     pub fn src(&self) -> &Path {
         &self.src
@@ -128,6 +608,228 @@ impl RenameProposal {
     pub fn dst(&self) -> &Path {
         &self.dst
     }
+
+    /// Replace the file name of this proposal's destination, keeping its
+    /// parent directory.
+    pub fn set_dst_name(&mut self, dst_name: &str) {
+        self.dst.set_file_name(dst_name);
+    }
+
+    /// Rewrite the paths of this proposal to account for `done`, an already
+    /// executed proposal, having moved a directory that contains them.
+    ///
+    /// Every proposal in a batch is computed from paths as they were before
+    /// anything was renamed. So once a directory in the batch is renamed,
+    /// the remaining proposals for entries inside of it need to refer to
+    /// its new location. Links don't move anything, so only renames cause
+    /// a rebase.
+    pub fn rebase(&mut self, done: &RenameProposal) {
+        if done.action != RenameAction::Rename {
+            return;
+        }
+        if let Some(src) = rebase_path(&self.src, &done.src, &done.dst) {
+            self.src = src;
+        }
+        if let Some(dst) = rebase_path(&self.dst, &done.src, &done.dst) {
+            self.dst = dst;
+        }
+    }
+}
+
+/// The permissions to give the directories created for a proposal's
+/// destination when `mkdir` is enabled.
+///
+/// By default, directories are created with the default permissions of the
+/// process (i.e., subject to its umask) and are owned by its user. Only
+/// directories that are created are changed, never ones that already exist.
+/// Setting any of these is only supported on Unix.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+pub struct DirPerms {
+    /// The mode bits of created directories, including the setuid, setgid
+    /// and sticky bits, e.g., `0o2775`. This is applied exactly, regardless
+    /// of the umask.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// The user ID to own created directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    /// The group ID to own created directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+}
+
+impl DirPerms {
+    /// Parse a mode given in octal, e.g., `2775`.
+    pub fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+        match u32::from_str_radix(mode, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(mode),
+            _ => anyhow::bail!(
+                "invalid directory mode '{}', expected an octal number \
+                 like 2775",
+                mode
+            ),
+        }
+    }
+
+    /// Parse an owner of the form `user:group` into a user ID and a group
+    /// ID. Either side may be omitted, e.g., `user` or `:group`, and each
+    /// may be given as a name or as a number.
+    #[cfg(unix)]
+    pub fn parse_owner(
+        owner: &str,
+    ) -> anyhow::Result<(Option<u32>, Option<u32>)> {
+        let (user, group) = match owner.split_once(':') {
+            None => (owner, ""),
+            Some((user, group)) => (user, group),
+        };
+        if user.is_empty() && group.is_empty() {
+            anyhow::bail!("invalid owner '{}', expected user:group", owner);
+        }
+        let uid = if user.is_empty() { None } else { Some(lookup_uid(user)?) };
+        let gid =
+            if group.is_empty() { None } else { Some(lookup_gid(group)?) };
+        Ok((uid, gid))
+    }
+
+    /// Parse an owner of the form `user:group`. This always fails on
+    /// platforms other than Unix.
+    #[cfg(not(unix))]
+    pub fn parse_owner(
+        owner: &str,
+    ) -> anyhow::Result<(Option<u32>, Option<u32>)> {
+        anyhow::bail!("cannot set owner '{}': only supported on Unix", owner)
+    }
+
+    fn is_default(&self) -> bool {
+        *self == DirPerms::default()
+    }
+
+    /// Apply these permissions to the given directory. The owner is set
+    /// before the mode, since changing the owner may clear the setuid and
+    /// setgid bits.
+    #[cfg(unix)]
+    fn apply(&self, dir: &Path) -> io::Result<()> {
+        use std::os::unix::fs::{chown, PermissionsExt};
+
+        if self.uid.is_some() || self.gid.is_some() {
+            chown(dir, self.uid, self.gid)?;
+        }
+        if let Some(mode) = self.mode {
+            fs::set_permissions(dir, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _: &Path) -> io::Result<()> {
+        if self.is_default() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "directory permissions are only supported on Unix",
+        ))
+    }
+}
+
+/// Resolve a user name (or a numeric ID) to a user ID.
+#[cfg(unix)]
+fn lookup_uid(user: &str) -> anyhow::Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let cname = std::ffi::CString::new(user)?;
+    // SAFETY: cname is a valid NUL terminated string. getpwnam returns
+    // either null or a pointer to a static record, which is read before
+    // anything else could call getpwnam again, since this is only used
+    // while parsing arguments.
+    let pw = unsafe { libc::getpwnam(cname.as_ptr()) };
+    if pw.is_null() {
+        anyhow::bail!("unknown user '{}'", user);
+    }
+    Ok(unsafe { (*pw).pw_uid })
+}
+
+/// Resolve a group name (or a numeric ID) to a group ID.
+#[cfg(unix)]
+fn lookup_gid(group: &str) -> anyhow::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let cname = std::ffi::CString::new(group)?;
+    // SAFETY: See lookup_uid.
+    let gr = unsafe { libc::getgrnam(cname.as_ptr()) };
+    if gr.is_null() {
+        anyhow::bail!("unknown group '{}'", group);
+    }
+    Ok(unsafe { (*gr).gr_gid })
+}
+
+/// Create the given directory along with any of its missing parents, and
+/// apply the given permissions to every directory created. Directories that
+/// already exist are left untouched.
+///
+/// The directories created are returned, outermost first. If one can't be
+/// created, then the ones created before it are removed again.
+fn create_dirs(dir: &Path, perms: &DirPerms) -> io::Result<Vec<PathBuf>> {
+    let missing: Vec<&Path> = dir
+        .ancestors()
+        .take_while(|p| {
+            !p.as_os_str().is_empty() && fs::symlink_metadata(p).is_err()
+        })
+        .collect();
+    let mut created = vec![];
+    for dir in missing.into_iter().rev() {
+        let result = match fs::create_dir(dir) {
+            Ok(()) => {
+                created.push(dir.to_path_buf());
+                perms.apply(dir)
+            }
+            // Another process may have created it in the meantime.
+            Err(ref e)
+                if e.kind() == io::ErrorKind::AlreadyExists
+                    && dir.is_dir() =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(err) = result {
+            remove_dirs(&created);
+            return Err(err);
+        }
+    }
+    Ok(created)
+}
+
+/// Remove the given directories, which were created by `create_dirs`,
+/// innermost first.
+///
+/// This stops at the first directory that can't be removed, e.g., because
+/// something else was put in it since, which then keeps its parents too.
+pub(crate) fn remove_dirs(dirs: &[PathBuf]) {
+    for dir in dirs.iter().rev() {
+        match fs::remove_dir(dir) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                log::debug!("keeping directory {}: {}", dir.display(), e);
+                return;
+            }
+        }
+    }
+}
+
+/// If `path` is strictly inside of the directory `from`, then return the
+/// equivalent path inside of `to`. Otherwise return `None`.
+fn rebase_path(path: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    let rest = path.strip_prefix(from).ok()?;
+    if rest.as_os_str().is_empty() {
+        return None;
+    }
+    Some(to.join(rest))
 }
 
 /// A renamer generates file rename proposals based on IMDb.
@@ -141,11 +843,39 @@ impl RenameProposal {
 /// touches the file system.
 #[derive(Debug)]
 pub struct Renamer {
-    cache: Mutex<HashMap<Query, SearchResults<MediaEntity>>>,
-    choose_cache: Mutex<HashMap<Query, MediaEntity>>,
+    cache: Mutex<HashMap<Query, Searched>>,
+    skip_count: AtomicUsize,
+    undecided_count: AtomicUsize,
+    choose_cache: Mutex<HashMap<ChoiceKey, Choice>>,
     force: Option<MediaEntity>,
-    min_votes: u32,
+    events: Option<Arc<dyn EventSink>>,
+    min_votes_movie: u32,
+    min_votes_tvshow: u32,
     good_threshold: f64,
+    min_match_ratio: f64,
+    preserve_times: bool,
+    symlink_relative: bool,
+    reflink_fallback: bool,
+    skip_in_use: bool,
+    mkdir: bool,
+    dir_perms: DirPerms,
+    write_origin: Option<OriginFormat>,
+    write_nfo: bool,
+    force_nfo: bool,
+    all_files: bool,
+    non_interactive: bool,
+    explain: bool,
+    explanations: Mutex<Vec<Explanation>>,
+    match_stats: Mutex<Vec<MatchStat>>,
+    color: bool,
+    layout: DestLayout,
+    naming: Naming,
+    query_fixups: QueryFixups,
+    threads: usize,
+    episode_gap: usize,
+    season_override: Option<u32>,
+    episode_override: Option<u32>,
+    season_episode: Option<Regex>,
     episode: Regex,
     season: Regex,
     year: Regex,
@@ -158,10 +888,15 @@ impl Renamer {
     ///
     /// This returns an error if any two of the proposals recommend an exactly
     /// equivalent destination path. An error is also returned if a destination
-    /// path already exists. Finally, the proposals are sorted in descending
-    /// order of path length if any one of them is a directory, which should
-    /// permit changing entries in a directory and a directory itself in one
-    /// go.
+    /// path already exists. Finally, if any one of them is a directory, the
+    /// proposals are sorted in descending order of depth, i.e., the number of
+    /// components in their source paths. Every entry inside a directory has
+    /// more components than the directory, so it is renamed first, which
+    /// permits changing entries in a directory and the directory itself in
+    /// one go. Executing the proposals rebases any remaining paths inside a
+    /// renamed directory (see `RenameProposal::rebase`) either way, but this
+    /// order means that every path shown is exactly the one that gets
+    /// renamed.
     ///
     /// An optional destination can be given, which when present, is used as
     /// the directory in which renames/links are created. Similarly, the action
@@ -179,54 +914,294 @@ impl Renamer {
         dest: Option<PathBuf>,
         action: RenameAction,
     ) -> anyhow::Result<Vec<RenameProposal>> {
+        let (proposals, _) =
+            self.propose_with(searcher, paths, dest, action, false)?;
+
+        // Make sure that the destination's directory exists (unless it will
+        // be created) and that the destination doesn't already exist. This
+        // isn't atomic, but it's probably a fine approximation.
+        //
+        // As a special case, links that already exist and point to the right
+        // place are dropped, which makes re-running with --symlink or
+        // --hardlink idempotent.
+        let mut any_dir = false;
+        let mut pending = vec![];
+        for p in proposals {
+            if !p.mkdir {
+                let parent = p.dst.parent().unwrap_or(Path::new(""));
+                if !parent.as_os_str().is_empty() && !parent.is_dir() {
+                    anyhow::bail!(
+                        "directory '{}' does not exist \
+                         (use --mkdir to create it)",
+                        parent.display()
+                    );
+                }
+            }
+            if p.is_done()? {
+                continue;
+            }
+            any_dir = any_dir || p.src.is_dir();
+            pending.push(p);
+        }
+        let mut proposals = pending;
+        // Finally, sort the proposals such that the deepest sources come
+        // first. This guarantees that every entry inside a directory is
+        // renamed before the directory itself. Executing a batch rebases
+        // paths inside of renamed directories either way, but this order
+        // means every path shown to the user is exactly the one that gets
+        // renamed. The sort is stable, so proposals at the same depth keep
+        // their order.
+        if any_dir {
+            proposals.sort_by_key(|p| {
+                std::cmp::Reverse(p.src.components().count())
+            });
+        }
+        self.record_stats(&proposals);
+        Ok(proposals)
+    }
+
+    /// Like `propose`, but without checking the file system beyond what's
+    /// needed to parse each path (namely, whether it's a directory). This
+    /// makes it possible to compute proposals for synthetic paths, or for
+    /// paths on another system.
+    ///
+    /// Along with the proposals, this returns every check that was skipped.
+    /// In particular, the destinations of the proposals returned may exist,
+    /// and their parent directories may not. Proposals are always in the
+    /// same order as the paths given.
+    pub fn propose_pure(
+        &self,
+        searcher: &mut Searcher,
+        paths: &[PathBuf],
+        dest: Option<PathBuf>,
+        action: RenameAction,
+    ) -> anyhow::Result<(Vec<RenameProposal>, Vec<SkippedCheck>)> {
+        let (proposals, mut skipped) =
+            self.propose_with(searcher, paths, dest, action, true)?;
+        for p in &proposals {
+            if !p.mkdir {
+                let parent = p.dst.parent().unwrap_or(Path::new(""));
+                if !parent.as_os_str().is_empty() {
+                    skipped.push(SkippedCheck::DestinationDir(
+                        parent.to_path_buf(),
+                    ));
+                }
+            }
+            skipped.push(SkippedCheck::DestinationExists(p.dst.clone()));
+        }
+        self.record_stats(&proposals);
+        Ok((proposals, skipped))
+    }
+
+    /// Record how each of the given proposals, which are about to be
+    /// returned, was matched. See `match_stats`.
+    fn record_stats(&self, proposals: &[RenameProposal]) {
+        self.match_stats
+            .lock()
+            .unwrap()
+            .extend(proposals.iter().filter_map(|p| p.stat));
+    }
+
+    /// Produce proposals for the given paths, without duplicate
+    /// destinations.
+    ///
+    /// When `pure` is true, no checks against the file system are performed
+    /// beyond parsing each path. Instead, the checks that were skipped are
+    /// returned.
+    fn propose_with(
+        &self,
+        searcher: &mut Searcher,
+        paths: &[PathBuf],
+        dest: Option<PathBuf>,
+        action: RenameAction,
+        pure: bool,
+    ) -> anyhow::Result<(Vec<RenameProposal>, Vec<SkippedCheck>)> {
+        let mut skipped = vec![];
+        let in_use = if !self.skip_in_use {
+            HashMap::new()
+        } else if pure {
+            skipped.extend(paths.iter().cloned().map(SkippedCheck::InUse));
+            HashMap::new()
+        } else {
+            find_in_use(paths, IN_USE_INTERVAL, std::thread::sleep)
+        };
+        let companions = Companions::new(paths);
+        // Explanations are identified by their position, which accounts for
+        // the explanations of any previous calls.
+        let first_id = {
+            let mut explanations = self.explanations.lock().unwrap();
+            let first_id = explanations.len();
+            if self.explain {
+                explanations.extend(
+                    paths.iter().map(|p| Explanation::new(p.to_path_buf())),
+                );
+            }
+            first_id
+        };
+        let mut pending = vec![];
+        for (i, path) in paths.iter().enumerate() {
+            let id = first_id + i;
+            if !self.all_files {
+                if let Some(reason) = self.skip_reason(&companions, path) {
+                    self.skip(id, format!("{}: {}", path.display(), reason));
+                    continue;
+                }
+            }
+            if let Some(reason) = in_use.get(path) {
+                self.skip(id, trf("skip.in-use", &[&path.display(), reason]));
+                continue;
+            }
+            pending.push((id, path.as_path()));
+        }
+        let results = if self.threads <= 1 || pending.len() <= 1 {
+            pending
+                .iter()
+                .map(|&(id, path)| {
+                    let mut job = Job {
+                        searcher: &mut *searcher,
+                        id,
+                        prompts: None,
+                        skipped: if pure { Some(vec![]) } else { None },
+                        score: None,
+                        prompted: false,
+                    };
+                    let result = self.propose_one(
+                        &mut job,
+                        path,
+                        dest.as_deref(),
+                        action,
+                    );
+                    (result, job.skipped.unwrap_or_default())
+                })
+                .collect()
+        } else {
+            self.propose_parallel(
+                searcher,
+                &pending,
+                dest.as_deref(),
+                action,
+                pure,
+            )?
+        };
+
         let mut proposals = vec![];
-        for path in paths {
-            let result =
-                self.propose_one(searcher, path, dest.as_deref(), action);
+        for (&(id, _), (result, checks)) in pending.iter().zip(results) {
+            skipped.extend(checks);
             let proposal = match result {
                 None => continue,
                 Some(proposal) => proposal,
             };
             // If there's no change, then skip it.
             if proposal.src == proposal.dst {
+                self.trace(id, |exp| {
+                    exp.outcome = Outcome::Skipped {
+                        reason: "already has the proposed name".to_string(),
+                    };
+                });
                 continue;
             }
+            self.trace(id, |exp| {
+                exp.outcome = Outcome::Proposed { dst: proposal.dst.clone() };
+            });
             proposals.push(proposal);
         }
 
+        for grouped in self.grouped_choices() {
+            eprintln!("[prompt] {}", grouped);
+        }
+
         // Check that we have no destination duplicates. If we permit them,
         // then it would be pretty easy to clobber the user's data. That's bad.
-        //
-        // We also make sure that the destination doesn't already exist. This
-        // isn't atomic, but it's probably a fine approximation.
         let mut seen = HashSet::new();
-        let mut any_dir = false;
         for p in &proposals {
-            if seen.contains(&p.dst) {
+            if !seen.insert(p.dst.clone()) {
                 anyhow::bail!(
                     "duplicate rename proposal for '{}'",
                     p.dst.display()
                 );
             }
-            seen.insert(p.dst.clone());
-            if p.dst.exists() {
-                anyhow::bail!(
-                    "file path '{}' already exists",
-                    p.dst.display()
-                );
+        }
+        // NFO files are destinations too. A release's own NFO file, renamed
+        // along with its video, wins over the one that would be written for
+        // the video, since the release's NFO file would otherwise be
+        // clobbered by (or clobber) the one written.
+        for p in &mut proposals {
+            if p.nfo.is_some() && seen.contains(&Nfo::path(&p.dst)) {
+                p.nfo = None;
             }
-            any_dir = any_dir || p.src.is_dir();
         }
-        // Finally, sort the proposals such that the longest ones come first.
-        // This should cause child entries to get renamed before parent
-        // entries.
-        if any_dir {
-            proposals.sort_by(|p1, p2| {
-                let (p1, p2) = (p1.dst.as_os_str(), p2.dst.as_os_str());
-                p1.len().cmp(&p2.len()).reverse()
-            });
+        Ok((proposals, skipped))
+    }
+
+    /// Propose renames for the given paths using a pool of worker threads,
+    /// each with its own searcher. Each path is paired with the identifier
+    /// of its explanation, and the proposals returned are in the same order
+    /// as the paths given.
+    ///
+    /// The calling thread coordinates the workers: any prompts for the end
+    /// user are sent to it, so that they're shown one at a time.
+    fn propose_parallel(
+        &self,
+        searcher: &mut Searcher,
+        paths: &[(usize, &Path)],
+        dest: Option<&Path>,
+        action: RenameAction,
+        pure: bool,
+    ) -> anyhow::Result<Vec<(Option<RenameProposal>, Vec<SkippedCheck>)>> {
+        let mut searchers = vec![];
+        for _ in 0..self.threads.min(paths.len()) {
+            searchers.push(searcher.try_clone()?);
         }
-        Ok(proposals)
+        let next = AtomicUsize::new(0);
+        let (prompts, prompted) = mpsc::channel();
+        let mut results = vec![(None, vec![]); paths.len()];
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = searchers
+                .into_iter()
+                .map(|mut searcher| {
+                    let prompts = prompts.clone();
+                    let next = &next;
+                    scope.spawn(move || {
+                        let mut done = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::SeqCst);
+                            let Some(&(id, path)) = paths.get(i) else {
+                                break;
+                            };
+                            let mut job = Job {
+                                searcher: &mut searcher,
+                                id,
+                                prompts: Some(&prompts),
+                                skipped: if pure {
+                                    Some(vec![])
+                                } else {
+                                    None
+                                },
+                                score: None,
+                                prompted: false,
+                            };
+                            let result =
+                                self.propose_one(&mut job, path, dest, action);
+                            let checks = job.skipped.unwrap_or_default();
+                            done.push((i, (result, checks)));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            // Once every worker has finished, all senders are dropped and
+            // this loop stops.
+            drop(prompts);
+            for prompt in prompted {
+                self.answer(searcher, prompt);
+            }
+            for worker in workers {
+                for (i, result) in worker.join().unwrap() {
+                    results[i] = result;
+                }
+            }
+        });
+        Ok(results)
     }
 
     /// Propose a single rename for the given path.
@@ -236,7 +1211,7 @@ impl Renamer {
     /// stderr and `None` is returned.
     fn propose_one(
         &self,
-        searcher: &mut Searcher,
+        job: &mut Job,
         path: &Path,
         dest: Option<&Path>,
         action: RenameAction,
@@ -244,31 +1219,76 @@ impl Renamer {
         let candidate = match self.candidate(path) {
             Ok(candidate) => candidate,
             Err(err) => {
-                eprintln!("[skipping] could not parse file path: {}", err);
-                return None;
+                return self.skip(job.id, trf("skip.unparsable", &[&err]));
             }
         };
+        self.trace(job.id, |exp| exp.parse = Some(candidate.kind.to_parse()));
         let result = match candidate.kind {
-            CandidateKind::Any(ref x) => self.find_any(searcher, x),
-            CandidateKind::Episode(ref x) => self.find_episode(searcher, x),
-            CandidateKind::Unknown => self.find_unknown(),
+            CandidateKind::Any(ref x) => self.find_any(job, x),
+            CandidateKind::Episode(ref x) => self.find_episode(job, x),
+            CandidateKind::Unknown => self.find_unknown(job),
         };
         let ent = match result {
-            Ok(ent) => ent,
+            Ok(Some(ent)) => ent,
+            Ok(None) => {
+                return self
+                    .skip(job.id, trf("skip.prompted", &[&path.display()]));
+            }
+            Err(err) if err.is::<Undecided>() => {
+                self.undecided_count.fetch_add(1, Ordering::SeqCst);
+                return self
+                    .skip(job.id, format!("{}: {}", path.display(), err));
+            }
             Err(err) => {
-                eprintln!(
-                    "[skipping] error searching for {}: {}",
-                    path.display(),
-                    err,
+                return self.skip(
+                    job.id,
+                    trf("skip.search-error", &[&path.display(), &err]),
                 );
-                return None;
             }
         };
 
+        // Route the entity to its kind specific destination, if one was
+        // configured.
+        let routed = match ent.episode() {
+            Some(ep) if self.layout.tv_dir.is_some() => {
+                let show = match job.searcher.index().title(&ep.tvshow_id) {
+                    Ok(Some(show)) => show,
+                    // IMDb's data occasionally has episodes whose show
+                    // has no title record.
+                    Ok(None) => {
+                        return self.skip(
+                            job.id,
+                            trf(
+                                "skip.missing-show",
+                                &[
+                                    &path.display(),
+                                    &ep.tvshow_id,
+                                    &ent.title().id,
+                                ],
+                            ),
+                        );
+                    }
+                    Err(err) => {
+                        return self.skip(
+                            job.id,
+                            trf("skip.show-error", &[&path.display(), &err]),
+                        );
+                    }
+                };
+                self.layout.episode_dir(
+                    &self.layout.show_dir_name(&show),
+                    ep.season.unwrap_or(0),
+                )
+            }
+            Some(_) => None,
+            None => self.layout.movie_dir(),
+        };
+        let dest = routed.as_deref().or(dest);
+
         // Setup our sources and destinations. They get tweaked depending on
         // what our rename action is and whether a destination directory was
         // explicitly given.
-        let dest_name = candidate.path.imdb_name(&ent);
+        let dest_name = candidate.path.imdb_name(&ent, &self.naming);
         let mut src_path = path.to_path_buf();
         let mut dest_parent_dir =
             dest.map(|d| d.to_path_buf()).unwrap_or(candidate.path.parent);
@@ -278,17 +1298,19 @@ impl Renamer {
         // specified on the commandline will need to be an absolute path,
         // otherwise the symlink will not point to the correct place.
         if dest.is_some() && action == RenameAction::Symlink {
-            src_path = match src_path.canonicalize() {
-                Ok(src_path) => src_path,
-                Err(err) => {
-                    eprintln!(
-                        "[skipping] error making {} an absolute path: {}",
-                        src_path.display(),
-                        err,
-                    );
-                    return None;
-                }
-            };
+            if let Some(ref mut skipped) = job.skipped {
+                skipped.push(SkippedCheck::AbsoluteSource(src_path.clone()));
+            } else {
+                src_path = match src_path.canonicalize() {
+                    Ok(src_path) => src_path,
+                    Err(err) => {
+                        return self.skip(
+                            job.id,
+                            trf("skip.absolute", &[&src_path.display(), &err]),
+                        );
+                    }
+                };
+            }
         }
         // A symlink or hardlink was requested to be created without a
         // destination specified. In this case, it only makes sense to place
@@ -298,20 +1320,154 @@ impl Renamer {
             dest_parent_dir = match std::env::current_dir() {
                 Ok(cwd) => cwd,
                 Err(err) => {
-                    eprintln!(
-                        "[skipping] error getting current directory: {}",
-                        err,
-                    );
-                    return None;
+                    return self.skip(job.id, trf("skip.cwd", &[&err]));
                 }
             };
         }
-        Some(RenameProposal::new(
+        let mut proposal = RenameProposal::new(
             src_path,
             &dest_parent_dir,
             &dest_name,
             action,
-        ))
+            ent,
+        );
+        proposal.preserve_times = self.preserve_times;
+        proposal.reflink_fallback = self.reflink_fallback;
+        proposal.mkdir = self.mkdir;
+        proposal.dir_perms = self.dir_perms;
+        proposal.score = job.score;
+        // Only videos are described by NFO files. Their companions and
+        // directories are renamed along with them.
+        if self.write_nfo && FileClass::from_path(path) == FileClass::Video {
+            proposal.nfo = Some(Nfo::new(&proposal.entity));
+            proposal.force_nfo = self.force_nfo;
+        }
+        proposal.origin = self.write_origin;
+        if self.symlink_relative {
+            if let Some(ref mut skipped) = job.skipped {
+                skipped
+                    .push(SkippedCheck::RelativeSymlink(proposal.dst.clone()));
+            } else if let Err(err) = proposal.make_symlink_relative() {
+                return self.skip(job.id, err.to_string());
+            }
+        }
+        let decision = if self.force.is_some() {
+            Decision::Override
+        } else if job.prompted {
+            Decision::Prompted
+        } else {
+            Decision::Automatic
+        };
+        proposal.stat = Some(MatchStat {
+            pattern: candidate.pattern,
+            decision,
+            score: job.score,
+        });
+        Some(proposal)
+    }
+
+    /// Log the given reason for skipping a path to stderr, and record it in
+    /// the path's explanation (identified by `id`) if one is being collected.
+    ///
+    /// This always returns `None` for the convenience of callers.
+    fn skip(&self, id: usize, reason: String) -> Option<RenameProposal> {
+        eprintln!("[skipping] {}", reason);
+        if let Some(ref sink) = self.events {
+            sink.emit(&Event::Skipped { reason: &reason });
+        }
+        self.skip_count.fetch_add(1, Ordering::SeqCst);
+        self.trace(id, |exp| exp.outcome = Outcome::Skipped { reason });
+        None
+    }
+
+    /// Apply the given function to the explanation identified by `id`. If
+    /// explanations aren't being collected, then this is a no-op.
+    fn trace<F: FnOnce(&mut Explanation)>(&self, id: usize, f: F) {
+        if !self.explain {
+            return;
+        }
+        if let Some(exp) = self.explanations.lock().unwrap().get_mut(id) {
+            f(exp);
+        }
+    }
+
+    /// Record a search in the explanation identified by `id`, along with the
+    /// entity that was chosen from its results.
+    fn trace_search(
+        &self,
+        id: usize,
+        purpose: &str,
+        query: Option<&Query>,
+        results: &[Scored<MediaEntity>],
+        chosen: Option<&MediaEntity>,
+        reason: String,
+    ) {
+        self.trace(id, |exp| {
+            let mut search = SearchTrace::new(purpose, query, results);
+            search.chosen = chosen.map(|ent| ent.title().id.clone());
+            search.reason = reason;
+            search.diagnostics = query.and_then(|q| {
+                self.cache.lock().unwrap().get(q).map(|(_, d)| d.clone())
+            });
+            exp.searches.push(search);
+        });
+    }
+
+    /// Return the explanations collected so far, in the order in which paths
+    /// were proposed. This is always empty unless explanations were enabled
+    /// via `RenamerBuilder::explain`.
+    pub fn explanations(&self) -> Vec<Explanation> {
+        self.explanations.lock().unwrap().clone()
+    }
+
+    /// Return how each proposal returned so far was matched to its entity,
+    /// in no particular order.
+    ///
+    /// Proposals that were dropped before being returned, e.g., because
+    /// their source already has the proposed name, aren't included.
+    pub fn match_stats(&self) -> Vec<MatchStat> {
+        self.match_stats.lock().unwrap().clone()
+    }
+
+    /// Return the number of paths skipped so far, for any reason, including
+    /// paths that the end user chose to skip when prompted.
+    pub fn skip_count(&self) -> usize {
+        self.skip_count.load(Ordering::SeqCst)
+    }
+
+    /// Return the number of paths skipped so far because no search result
+    /// stood out and prompting the end user was disabled via
+    /// `RenamerBuilder::non_interactive`. These are included in
+    /// `skip_count`.
+    pub fn undecided_count(&self) -> usize {
+        self.undecided_count.load(Ordering::SeqCst)
+    }
+
+    /// Returns a reason for skipping the given path based on its file
+    /// extension, if it should be skipped.
+    ///
+    /// Directories and video files are never skipped. Subtitles and metadata
+    /// are only renamed as companions of a video file in the same batch, or
+    /// when an entity override is given. Everything else is skipped.
+    fn skip_reason(
+        &self,
+        companions: &Companions,
+        path: &Path,
+    ) -> Option<&'static str> {
+        if path.is_dir() {
+            return None;
+        }
+        match FileClass::from_path(path) {
+            FileClass::Video => None,
+            FileClass::Subtitle | FileClass::Metadata => {
+                if self.force.is_some() || companions.has_video(path) {
+                    None
+                } else {
+                    Some(tr("skip.not-companion"))
+                }
+            }
+            FileClass::Other => Some(tr("skip.not-video")),
+        }
     }
 
     /// Search for any entity via its name and a year. In general, this is
@@ -319,23 +1475,70 @@ impl Renamer {
     /// movies.
     ///
     /// If an entity override is provided, then that is returned instead.
+    /// If the end user skipped the file when prompted, then `None` is
+    /// returned.
     fn find_any(
         &self,
-        searcher: &mut Searcher,
+        job: &mut Job,
         candidate: &CandidateAny,
-    ) -> anyhow::Result<MediaEntity> {
+    ) -> anyhow::Result<Option<MediaEntity>> {
         // If we already have an entity override, then just use that to build
         // the proposal and skip any automatic searches.
         if let Some(ref ent) = self.force {
-            return Ok(ent.clone());
+            self.trace_forced(job.id, "title", ent);
+            return Ok(Some(ent.clone()));
         }
 
         // Otherwise, try to figure out the "right" name by constructing a
         // query from the candidate and searching IMDb.
-        let query = self
-            .name_query(&candidate.title)
-            .year_ge(candidate.year)
-            .year_le(candidate.year)
+        //
+        // If nothing turns up, then the file might genuinely be something we
+        // filter out by default (like an episode that doesn't look like
+        // one), so try again with every kind. Those results are much noisier,
+        // so the end user is always prompted to pick one.
+        let (query, relaxed) = relax_if_empty(
+            self.any_query(candidate),
+            self.any_query_relaxed(candidate),
+            |q| self.search(job.searcher, q),
+        )?;
+        if relaxed {
+            log::debug!("relaxed automatic 'any' query: {:?}", query);
+            self.choose_one(
+                job,
+                "title (all kinds)",
+                &candidate.title,
+                &query,
+                &ChoiceKey::exact(&query),
+                true,
+            )
+        } else {
+            // A file named in a title's original language only matches that
+            // title through its alternate names, which dilutes its score.
+            // Rescoring by similarity considers original titles, so do that,
+            // but only then: other queries are ranked by name score alone.
+            let name = canonical_title(&candidate.title.replace(".", " "));
+            let results = self.search(job.searcher, &query)?;
+            let query = if prefers_original_title(&name, results.as_slice()) {
+                query.similarity(Similarity::JaroWinkler)
+            } else {
+                query
+            };
+            log::debug!("automatic 'any' query: {:?}", query);
+            self.choose_one(
+                job,
+                "title",
+                &candidate.title,
+                &query,
+                &ChoiceKey::exact(&query),
+                false,
+            )
+        }
+    }
+
+    /// Build the automatic query for finding any entity matching the given
+    /// candidate.
+    fn any_query(&self, candidate: &CandidateAny) -> Query {
+        self.any_query_relaxed(candidate)
             // Basically include every kind except for episode and video games.
             // This helps filter out a lot of noise.
             .kind(TitleKind::Movie)
@@ -346,9 +1549,15 @@ impl Renamer {
             .kind(TitleKind::TVShort)
             .kind(TitleKind::TVSpecial)
             .kind(TitleKind::Video)
-            .votes_ge(self.min_votes);
-        log::debug!("automatic 'any' query: {:?}", query);
-        self.choose_one(searcher, &query)
+    }
+
+    /// Build the automatic query for finding any entity matching the given
+    /// candidate, regardless of its kind.
+    fn any_query_relaxed(&self, candidate: &CandidateAny) -> Query {
+        self.name_query(&candidate.title)
+            .year_ge(candidate.year)
+            .year_le(candidate.year)
+            .votes_ge(self.min_votes_movie)
     }
 
     /// Search for the episode entity corresponding to the episode information
@@ -358,19 +1567,37 @@ impl Renamer {
     /// This works by assuming the candidate episode's name is actually the
     /// TV show name. So we first look for the TV show entity, and then use
     /// that to find the corresponding episode.
+    ///
+    /// If the end user skipped the file when prompted, then `None` is
+    /// returned.
     fn find_episode(
         &self,
-        searcher: &mut Searcher,
+        job: &mut Job,
         candidate: &CandidateEpisode,
-    ) -> anyhow::Result<MediaEntity> {
-        let tvshow = self.find_tvshow_for_episode(searcher, candidate)?;
-        let eps =
-            searcher.index().episodes(&tvshow.title().id, candidate.season)?;
-        let ep = match eps
-            .into_iter()
-            .find(|ep| ep.episode == Some(candidate.episode))
+    ) -> anyhow::Result<Option<MediaEntity>> {
+        let Some(tvshow) = self.find_tvshow_for_episode(job, candidate)?
+        else {
+            return Ok(None);
+        };
+        let mut found = None;
+        for result in job
+            .searcher
+            .index()
+            .episodes_iter(&tvshow.title().id, candidate.season)
         {
+            let ep = result?;
+            if ep.episode == Some(candidate.episode) {
+                found = Some(ep);
+                break;
+            }
+        }
+        let ep = match found {
             Some(ep) => ep,
+            // Season 0 conventionally means "specials," but IMDb often
+            // doesn't number specials that way.
+            None if candidate.season == 0 => {
+                return self.find_special(job, &tvshow, candidate);
+            }
             None => anyhow::bail!(
                 "could not find S{:02}E{:02} for TV show {}",
                 candidate.season,
@@ -378,8 +1605,18 @@ impl Renamer {
                 tvshow.title().id,
             ),
         };
-        match searcher.index().entity(&ep.id)? {
-            Some(ent) => Ok(ent),
+        self.trace(job.id, |exp| {
+            let reason = format!(
+                "S{:02}E{:02} in the episode list of {}",
+                candidate.season,
+                candidate.episode,
+                tvshow.title().id,
+            );
+            exp.searches
+                .push(SearchTrace::resolved("episode", &ep.id, &reason));
+        });
+        match job.searcher.index().entity(&ep.id)? {
+            Some(ent) => Ok(Some(ent)),
             None => anyhow::bail!(
                 "could not find media entity for episode {}",
                 ep.id
@@ -387,17 +1624,96 @@ impl Renamer {
         }
     }
 
+    /// Search for a special of the given TV show when looking up the
+    /// candidate's episode in season 0 failed.
+    ///
+    /// This considers all episodes of the show, ranked by the similarity of
+    /// their title to whatever follows the episode number in the file name,
+    /// along with all TV specials sharing the show's name. Unless a single
+    /// result stands out, the end user is prompted to pick one.
+    fn find_special(
+        &self,
+        job: &mut Job,
+        tvshow: &MediaEntity,
+        candidate: &CandidateEpisode,
+    ) -> anyhow::Result<Option<MediaEntity>> {
+        let (episodes, specials) =
+            self.special_queries(tvshow.title(), candidate);
+        log::debug!("automatic 'special episode' query: {:?}", episodes);
+        log::debug!("automatic 'tv special' query: {:?}", specials);
+        let mut results = self.search(job.searcher, &episodes)?.into_vec();
+        results.extend(self.search(job.searcher, &specials)?.into_vec());
+        results.sort_by(|r1, r2| r2.score().total_cmp(&r1.score()));
+        if results.is_empty() {
+            anyhow::bail!(
+                "could not find S00E{:02} or any specials for TV show {}",
+                candidate.episode,
+                tvshow.title().id,
+            );
+        }
+        // Every result is already shown, so there are no more to page
+        // through.
+        let mut answer = self.choose(job, None, &results, false);
+        while let Ok((Answer::More, _)) = answer {
+            eprintln!("[no more results]");
+            answer = self.choose(job, None, &results, true);
+        }
+        let (chosen, prompted) = match answer {
+            Ok((Answer::Chosen(ent), prompted)) => (Ok(Some(*ent)), prompted),
+            Ok((Answer::Skip | Answer::More, prompted)) => {
+                (Ok(None), prompted)
+            }
+            Err(err) => (Err(err), false),
+        };
+        self.trace_search(
+            job.id,
+            "special",
+            None,
+            &results,
+            chosen.as_ref().ok().and_then(|ent| ent.as_ref()),
+            auto_choice(&results, self.good_threshold, self.min_match_ratio)
+                .to_string(),
+        );
+        if let Ok(Some(ref ent)) = chosen {
+            job.score = score_of(&results, ent);
+            job.prompted |= prompted;
+        }
+        chosen
+    }
+
+    /// Build the queries for finding a special of the given TV show. The
+    /// first searches the show's episodes, while the second searches for
+    /// TV specials sharing the show's name.
+    fn special_queries(
+        &self,
+        tvshow: &Title,
+        candidate: &CandidateEpisode,
+    ) -> (Query, Query) {
+        // Disabling the name scorer forces an exhaustive search of the
+        // show's episodes, which are then ranked only by similarity.
+        let episodes = self
+            .name_query(&candidate.episode_title)
+            .name_scorer(None)
+            .similarity(Similarity::Levenshtein)
+            .kind(TitleKind::TVEpisode)
+            .tvshow_id(&tvshow.id);
+        let specials =
+            self.name_query(&tvshow.title).kind(TitleKind::TVSpecial);
+        (episodes, specials)
+    }
+
     /// Search for the TV show entity corresponding to the episode information
     /// in the given candidate. If one couldn't be found, then an error is
     /// returned.
     ///
     /// If there is an entity override, then it is used instead. If the
-    /// override isn't a TV show, then an error is returned.
+    /// override isn't a TV show, then an error is returned. If the end user
+    /// skipped the file when prompted, then `None` is returned.
     fn find_tvshow_for_episode(
         &self,
-        searcher: &mut Searcher,
+        job: &mut Job,
         candidate: &CandidateEpisode,
-    ) -> anyhow::Result<MediaEntity> {
+    ) -> anyhow::Result<Option<MediaEntity>> {
         // If we already have an entity override, then just use that as the
         // TV show. If it isn't a TV show, then return an error.
         if let Some(ref ent) = self.force {
@@ -407,18 +1723,35 @@ impl Renamer {
                     ent.title().kind
                 );
             }
-            return Ok(ent.clone());
+            self.trace_forced(job.id, "tvshow for episode", ent);
+            return Ok(Some(ent.clone()));
         }
 
         // Otherwise, try to figure out the "right" TV show by constructing a
         // query from the candidate and searching IMDb.
-        let query = self
-            .name_query(&candidate.tvshow_title)
+        let query = self.tvshow_query(candidate);
+        log::debug!("automatic 'tvshow for episode' query: {:?}", query);
+        self.choose_one(
+            job,
+            "tvshow for episode",
+            &candidate.tvshow_title,
+            &query,
+            &show_choice_key(
+                &query,
+                &candidate.tvshow_title,
+                candidate.episode,
+            ),
+            false,
+        )
+    }
+
+    /// Build the automatic query for finding the TV show of the given
+    /// episode candidate.
+    fn tvshow_query(&self, candidate: &CandidateEpisode) -> Query {
+        self.name_query(&candidate.tvshow_title)
             .kind(TitleKind::TVMiniSeries)
             .kind(TitleKind::TVSeries)
-            .votes_ge(self.min_votes);
-        log::debug!("automatic 'tvshow for episode' query: {:?}", query);
-        self.choose_one(searcher, &query)
+            .votes_ge(self.min_votes_tvshow)
     }
 
     /// Return an entity for a completely unknown candidate.
@@ -431,9 +1764,12 @@ impl Renamer {
     /// This is useful for renaming files like 'English.srt', where the path
     /// doesn't contain any useful information and an override is necessary
     /// anyway.
-    fn find_unknown(&self) -> anyhow::Result<MediaEntity> {
+    fn find_unknown(&self, job: &Job) -> anyhow::Result<Option<MediaEntity>> {
         match self.force {
-            Some(ref ent) => Ok(ent.clone()),
+            Some(ref ent) => {
+                self.trace_forced(job.id, "title", ent);
+                Ok(Some(ent.clone()))
+            }
             None => {
                 anyhow::bail!(
                     "could not parse file path and there is no override \
@@ -454,18 +1790,49 @@ impl Renamer {
         let cpath = CandidatePath::from_path(path)?;
         let name = cpath.base_name.clone();
 
-        if let Some(cepisode) = self.episode_parts(&cpath)? {
+        if let Some((pattern, mut cepisode)) = self.episode_parts(&cpath)? {
+            if let Some(season) = self.season_override {
+                cepisode.season = season;
+            }
+            if let Some(episode) = self.episode_override {
+                cepisode.episode = episode;
+            }
             return Ok(Candidate {
                 path: cpath,
                 kind: CandidateKind::Episode(cepisode),
+                pattern,
             });
         }
+        // When the name has no episode information, overriding both the
+        // season and episode makes it an episode anyway. The whole name is
+        // then presumed to be the TV show title.
+        match (self.season_override, self.episode_override) {
+            (Some(season), Some(episode)) => {
+                return Ok(Candidate {
+                    path: cpath,
+                    kind: CandidateKind::Episode(CandidateEpisode {
+                        tvshow_title: name,
+                        season,
+                        episode,
+                        episode_title: String::new(),
+                    }),
+                    pattern: Pattern::Override,
+                });
+            }
+            (None, None) => {}
+            _ => anyhow::bail!(
+                "{}: no season and episode found in name, so both must be \
+                 overridden",
+                path.display(),
+            ),
+        }
 
         let caps_year = match self.year.captures(&name) {
             None => {
                 return Ok(Candidate {
                     path: cpath,
                     kind: CandidateKind::Unknown,
+                    pattern: Pattern::None,
                 })
             }
             Some(caps) => caps,
@@ -479,10 +1846,12 @@ impl Renamer {
         Ok(Candidate {
             path: cpath,
             kind: CandidateKind::Any(CandidateAny { title, year }),
+            pattern: Pattern::Year,
         })
     }
 
-    /// Part episode information from the given candidate, if it exists.
+    /// Part episode information from the given candidate, if it exists,
+    /// along with the pattern it was found by.
     ///
     /// If a problem occurred (like detecting a match but missing an expected
     /// capture group name), then an error is returned. If no episode info
@@ -490,21 +1859,128 @@ impl Renamer {
     fn episode_parts(
         &self,
         cpath: &CandidatePath,
+    ) -> anyhow::Result<Option<(Pattern, CandidateEpisode)>> {
+        let name = &cpath.base_name;
+        if let Some(ref re) = self.season_episode {
+            for caps in re.captures_iter(name) {
+                let (mat_season, mat_episode) =
+                    match (caps.name("season"), caps.name("episode")) {
+                        (Some(s), Some(e)) => (s, e),
+                        _ => anyhow::bail!(
+                            "missing 'season' or 'episode' group in: {}",
+                            re
+                        ),
+                    };
+                let all = caps.get(0).unwrap();
+                let season: u32 = match mat_season.as_str().parse() {
+                    Ok(season) if season <= MAX_SEASON => season,
+                    _ => continue,
+                };
+                let episode: u32 = match mat_episode.as_str().parse() {
+                    Ok(episode) if episode <= MAX_EPISODE => episode,
+                    _ => continue,
+                };
+                if is_release_token(name, mat_season.start()) {
+                    continue;
+                }
+                return Ok(Some((
+                    Pattern::SeasonEpisode,
+                    CandidateEpisode {
+                        tvshow_title: name[..all.start()].to_string(),
+                        season,
+                        episode,
+                        episode_title: name[all.end()..].to_string(),
+                    },
+                )));
+            }
+        }
+        // Look for the first season match followed closely by an episode
+        // match, where neither are part of a release token like `DTS5` or
+        // `x265` and both numbers are plausible.
+        for caps_season in self.season.captures_iter(name) {
+            let mat_season = match caps_season.name("season") {
+                None => {
+                    anyhow::bail!("missing 'season' group in: {}", self.season)
+                }
+                Some(mat) => mat,
+            };
+            let all_season = caps_season.get(0).unwrap();
+            let season: u32 = match mat_season.as_str().parse() {
+                Ok(season) if season <= MAX_SEASON => season,
+                _ => continue,
+            };
+            if is_release_token(name, all_season.start()) {
+                continue;
+            }
+            for caps_episode in self.episode.captures_iter(name) {
+                let mat_episode = match caps_episode.name("episode") {
+                    None => anyhow::bail!(
+                        "missing 'episode' group in: {}",
+                        self.episode
+                    ),
+                    Some(mat) => mat,
+                };
+                let all_episode = caps_episode.get(0).unwrap();
+                if all_episode.start() < all_season.end() {
+                    continue;
+                }
+                if all_episode.start() - all_season.end() > self.episode_gap {
+                    break;
+                }
+                let episode: u32 = match mat_episode.as_str().parse() {
+                    Ok(episode) if episode <= MAX_EPISODE => episode,
+                    _ => continue,
+                };
+                if is_release_token(name, all_episode.start()) {
+                    continue;
+                }
+                return Ok(Some((
+                    Pattern::SeasonThenEpisode,
+                    CandidateEpisode {
+                        tvshow_title: name[..all_season.start()].to_string(),
+                        season,
+                        episode,
+                        episode_title: name[all_episode.end()..].to_string(),
+                    },
+                )));
+            }
+        }
+        Ok(self
+            .episode_parts_from_dirs(cpath)?
+            .map(|cepisode| (Pattern::SeasonDir, cepisode)))
+    }
+
+    /// Parse episode information from a path whose base name contains only
+    /// an episode number, with the season number in an ancestor directory.
+    /// This corresponds to layouts like `Show Name/Season 2/07 - Title.mkv`.
+    ///
+    /// The episode number is found via the episode regex or, failing that, a
+    /// leading bare number in the base name. The TV show name is taken from
+    /// the directory containing the season directory.
+    fn episode_parts_from_dirs(
+        &self,
+        cpath: &CandidatePath,
     ) -> anyhow::Result<Option<CandidateEpisode>> {
+        lazy_static! {
+            static ref RE_SEASON_DIR: Regex = Regex::new(
+                r"(?ix)
+                ^(?:season|series|s)[\s._-]*(?P<season>[0-9]+)$
+                |
+                ^specials$
+                ",
+            )
+            .unwrap();
+            static ref RE_BARE_EPISODE: Regex =
+                Regex::new(r"^\s*(?P<episode>[0-9]{1,3})\b").unwrap();
+        }
+
         let name = &cpath.base_name;
-        let caps_season = match self.season.captures(name) {
-            None => return Ok(None),
-            Some(caps) => caps,
-        };
         let caps_episode = match self.episode.captures(name) {
-            None => return Ok(None),
             Some(caps) => caps,
-        };
-        let mat_season = match caps_season.name("season") {
-            None => {
-                anyhow::bail!("missing 'season' group in: {}", self.season)
-            }
-            Some(mat) => mat,
+            None => match RE_BARE_EPISODE.captures(name) {
+                None => return Ok(None),
+                Some(caps) => caps,
+            },
         };
         let mat_episode = match caps_episode.name("episode") {
             None => {
@@ -512,43 +1988,332 @@ impl Renamer {
             }
             Some(mat) => mat,
         };
-
-        let title_end = caps_season.get(0).unwrap().start();
+        if mat_episode.as_str().parse::<u32>()? > MAX_EPISODE {
+            return Ok(None);
+        }
+        let mut dirs = cpath.ancestors.iter();
+        let season = loop {
+            let dir = match dirs.next() {
+                None => return Ok(None),
+                Some(dir) => dir,
+            };
+            let caps = match RE_SEASON_DIR.captures(dir.trim()) {
+                None => continue,
+                Some(caps) => caps,
+            };
+            match caps.name("season") {
+                None => break 0,
+                Some(mat) => match mat.as_str().parse() {
+                    Ok(season) if season <= MAX_SEASON => break season,
+                    _ => continue,
+                },
+            }
+        };
+        let tvshow_title = match dirs.next() {
+            None => return Ok(None),
+            Some(dir) => dir.clone(),
+        };
+        let episode_title_start = caps_episode.get(0).unwrap().end();
         Ok(Some(CandidateEpisode {
-            tvshow_title: name[..title_end].to_string(),
-            season: mat_season.as_str().parse()?,
+            tvshow_title,
+            season,
             episode: mat_episode.as_str().parse()?,
+            episode_title: name[episode_title_start..].to_string(),
         }))
     }
 
     /// Build a query and seed it with the given name, after sanitizing the
     /// name.
+    ///
+    /// Release names sometimes contain more than one name for a title,
+    /// separated by "AKA", e.g., `Oldboy AKA Oldeuboi`. Each of those is
+    /// added to the query as a separate name.
     fn name_query(&self, name: &str) -> Query {
-        let name = name.replace(".", " ");
-        let name = name.trim();
-        log::debug!("automatic name query: {:?}", name);
-        Query::new().name(name)
+        lazy_static! {
+            static ref RE_AKA: Regex = Regex::new(r"(?i)\s+aka\s+").unwrap();
+        }
+        let name = self.query_fixups.apply(&name.replace(".", " "));
+        let mut query = Query::new();
+        let mut names = vec![];
+        for name in RE_AKA.split(&name) {
+            let name = canonical_title(name);
+            if !name.is_empty() {
+                query = query.name_or(&name);
+                names.push(name);
+            }
+        }
+        if names.is_empty() {
+            query = query.name("");
+        }
+        log::debug!("automatic name query: {:?}", names);
+        query
     }
 
     /// Execute a search against the given searcher with the given query and
     /// choose a single result from the search. If no obvious single result
     /// stands out, then prompt the user for an answer.
     ///
-    /// If the given query has been executed before, then returned the cached
-    /// answer.
+    /// If a query with the given key has been executed before, then return
+    /// the cached answer. Usually, the key is the query itself, but it may
+    /// replace the query's name with a normalized title so that slightly
+    /// different queries share an answer. See `show_choice_key`.
+    ///
+    /// When `always_prompt` is true, the user is prompted even if a single
+    /// result stands out.
+    ///
+    /// The name given should be the name the query searches for. If it ends
+    /// with a number, like a sequel, then results containing that number are
+    /// preferred. See `Searcher::boost_sequel`.
+    ///
+    /// The purpose given is only used to describe the search in an
+    /// explanation.
+    ///
+    /// When prompted, the end user may ask for more results, in which case
+    /// the search is repeated for more results and only the ones that
+    /// weren't shown yet are shown. If the end user skips the file instead,
+    /// then `None` is returned.
     fn choose_one(
         &self,
-        searcher: &mut Searcher,
+        job: &mut Job,
+        purpose: &str,
+        name: &str,
         query: &Query,
-    ) -> anyhow::Result<MediaEntity> {
-        let mut choose_cache = self.choose_cache.lock().unwrap();
-        if let Some(ent) = choose_cache.get(query) {
-            return Ok(ent.clone());
+        key: &ChoiceKey,
+        always_prompt: bool,
+    ) -> anyhow::Result<Option<MediaEntity>> {
+        let cached = self.choose_cache.lock().unwrap().get_mut(key).map(|c| {
+            c.files += 1;
+            (c.ent.clone(), c.score, c.prompted)
+        });
+        if let Some((ent, score, prompted)) = cached {
+            job.score = score;
+            job.prompted |= prompted;
+            self.trace(job.id, |exp| {
+                exp.searches.push(SearchTrace {
+                    chosen: Some(ent.title().id.clone()),
+                    reason: "same choice as an earlier file".to_string(),
+                    ..SearchTrace::new(purpose, Some(query), &[])
+                });
+            });
+            return Ok(Some(ent));
+        }
+        let mut paged = query.clone();
+        let mut shown = HashSet::new();
+        let mut more = false;
+        let (page, answer) = loop {
+            let mut results = self.search(job.searcher, &paged)?;
+            job.searcher.boost_sequel(name, &mut results)?;
+            let mut page: Vec<_> = results
+                .as_slice()
+                .iter()
+                .filter(|r| !shown.contains(&r.value().title().id))
+                .cloned()
+                .collect();
+            if page.is_empty() && !shown.is_empty() {
+                eprintln!("[no more results]");
+                shown.clear();
+                page = results.into_vec();
+            }
+            let answer = self.choose(
+                job,
+                Some((query, key)),
+                &page,
+                always_prompt || more,
+            );
+            if let Ok((Answer::More, _)) = answer {
+                more = true;
+                shown
+                    .extend(page.iter().map(|r| r.value().title().id.clone()));
+                paged = paged.size(shown.len() + page.len());
+                continue;
+            }
+            break (page, answer);
+        };
+        let (chosen, prompted) = match answer {
+            Ok((Answer::Chosen(ent), prompted)) => (Ok(Some(*ent)), prompted),
+            Ok((Answer::Skip | Answer::More, prompted)) => {
+                (Ok(None), prompted)
+            }
+            Err(err) => (Err(err), false),
+        };
+        let reason = if matches!(chosen, Ok(None)) {
+            "skipped when prompted".to_string()
+        } else if always_prompt {
+            "prompted: results from a relaxed query".to_string()
+        } else if more {
+            "prompted: more results were requested".to_string()
+        } else {
+            auto_choice(&page, self.good_threshold, self.min_match_ratio)
+                .to_string()
+        };
+        self.trace_search(
+            job.id,
+            purpose,
+            Some(query),
+            &page,
+            chosen.as_ref().ok().and_then(|ent| ent.as_ref()),
+            reason,
+        );
+        if let Some(ent) = chosen? {
+            job.score = score_of(&page, &ent);
+            job.prompted |= prompted;
+            self.choose_cache
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .and_modify(|c| c.files += 1)
+                .or_insert_with(|| Choice {
+                    ent: ent.clone(),
+                    score: job.score,
+                    prompted,
+                    files: 1,
+                });
+            return Ok(Some(ent));
+        }
+        Ok(None)
+    }
+
+    /// Return a description of every choice made by the end user that was
+    /// applied to more than one file.
+    fn grouped_choices(&self) -> Vec<String> {
+        let cache = self.choose_cache.lock().unwrap();
+        let mut grouped = vec![];
+        for (key, choice) in cache.iter() {
+            if choice.prompted && choice.files > 1 {
+                grouped.push(format!(
+                    "{} ({}) for '{}': applied to {} files",
+                    choice.ent.title().title,
+                    choice.ent.title().id,
+                    key.name(),
+                    choice.files,
+                ));
+            }
+        }
+        grouped.sort();
+        grouped
+    }
+
+    /// Choose a single result from the given search results, prompting the
+    /// end user if no single result stands out (or if `always_prompt` is
+    /// true). If there are no results, then an error is returned.
+    ///
+    /// If the end user would be prompted but prompting is disabled, then an
+    /// `Undecided` error is returned instead.
+    ///
+    /// When called from a worker thread, the prompt is sent to the
+    /// coordinating thread so that prompts are never interleaved on the
+    /// terminal. The query, if given, is used to highlight results, and comes
+    /// with the key of the choice being made, which lets the coordinating
+    /// thread reuse an answer to an identical prompt from another worker.
+    ///
+    /// Along with the answer, this returns whether the end user was prompted
+    /// for it.
+    fn choose(
+        &self,
+        job: &mut Job,
+        query: Option<(&Query, &ChoiceKey)>,
+        results: &[Scored<MediaEntity>],
+        always_prompt: bool,
+    ) -> anyhow::Result<(Answer, bool)> {
+        if results.is_empty() {
+            anyhow::bail!("no search results available for query");
+        }
+        if !always_prompt {
+            if let Some(i) =
+                auto_choice(results, self.good_threshold, self.min_match_ratio)
+                    .index()
+            {
+                let ent = results[i].clone().into_value();
+                return Ok((Answer::Chosen(Box::new(ent)), false));
+            }
+        }
+        if self.non_interactive {
+            return Err(Undecided.into());
+        }
+        let prompts = match job.prompts {
+            None => {
+                let query = query.map(|(query, _)| query);
+                let hl = self.highlighter(job.searcher, query)?;
+                let answer =
+                    prompt_choice(job.searcher, results, hl.as_ref(), true)?;
+                return Ok((answer, true));
+            }
+            Some(prompts) => prompts,
+        };
+        let (reply, answer) = mpsc::channel();
+        let prompt = Prompt {
+            query: query.map(|(query, key)| (query.clone(), key.clone())),
+            results: results.to_vec(),
+            reply,
+        };
+        if prompts.send(prompt).is_err() {
+            anyhow::bail!("could not prompt: coordinating thread is gone");
+        }
+        match answer.recv() {
+            Ok(answer) => Ok((answer?, true)),
+            Err(_) => anyhow::bail!("could not prompt: no answer received"),
+        }
+    }
+
+    /// Answer a prompt sent from a worker thread by prompting the end user,
+    /// unless an identical prompt has already been answered.
+    fn answer(&self, searcher: &mut Searcher, prompt: Prompt) {
+        let cached = prompt.query.as_ref().and_then(|(_, key)| {
+            let cache = self.choose_cache.lock().unwrap();
+            cache.get(key).map(|c| c.ent.clone())
+        });
+        let answer = match cached {
+            Some(ent) => Ok(Answer::Chosen(Box::new(ent))),
+            None => self
+                .highlighter(searcher, prompt.query.as_ref().map(|(q, _)| q))
+                .and_then(|hl| {
+                    prompt_choice(searcher, &prompt.results, hl.as_ref(), true)
+                }),
+        };
+        // The worker that sent the prompt counts the files the answer was
+        // applied to, once it receives the answer.
+        if let (Some((_, key)), Ok(Answer::Chosen(ent))) =
+            (prompt.query, &answer)
+        {
+            let score = score_of(&prompt.results, ent);
+            self.choose_cache.lock().unwrap().entry(key).or_insert_with(
+                || Choice {
+                    ent: (**ent).clone(),
+                    score,
+                    prompted: true,
+                    files: 0,
+                },
+            );
+        }
+        // If the worker is gone, then there's nobody left to care.
+        let _ = prompt.reply.send(answer);
+    }
+
+    /// Return a highlighter for result titles in prompts for the given
+    /// query, if color output is enabled.
+    fn highlighter(
+        &self,
+        searcher: &mut Searcher,
+        query: Option<&Query>,
+    ) -> anyhow::Result<Option<Highlighter>> {
+        match query {
+            Some(query) if self.color => {
+                Highlighter::for_query(searcher, query)
+            }
+            _ => Ok(None),
         }
-        let results = self.search(searcher, query)?;
-        let ent = choose(searcher, results.as_slice(), self.good_threshold)?;
-        choose_cache.insert(query.clone(), ent.clone());
-        Ok(ent)
+    }
+
+    /// Record the use of the entity override in place of a search in the
+    /// explanation identified by `id`.
+    fn trace_forced(&self, id: usize, purpose: &str, ent: &MediaEntity) {
+        self.trace(id, |exp| {
+            exp.searches.push(SearchTrace::resolved(
+                purpose,
+                &ent.title().id,
+                "override set via -q/--query",
+            ));
+        });
     }
 
     /// Execute a search against the given searcher with the given query.
@@ -560,16 +2325,238 @@ impl Renamer {
         searcher: &mut Searcher,
         query: &Query,
     ) -> anyhow::Result<SearchResults<MediaEntity>> {
-        let mut cache = self.cache.lock().unwrap();
-        if let Some(results) = cache.get(query) {
+        if let Some((results, _)) = self.cache.lock().unwrap().get(query) {
             return Ok(results.clone());
         }
-        let results = searcher.search(query)?;
-        cache.insert(query.clone(), results.clone());
+        // The lock isn't held while searching, so that other threads can
+        // search concurrently. At worst, a query is executed more than once.
+        let (results, diag) = searcher.search_diagnostics(query)?;
+        log::debug!("search {}: {}", query, diag);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(query.clone(), (results.clone(), diag));
         Ok(results)
     }
 }
 
+/// The error returned when choosing among search results would prompt the
+/// end user, but prompting is disabled.
+#[derive(Clone, Copy, Debug)]
+struct Undecided;
+
+impl fmt::Display for Undecided {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", tr("skip.undecided"))
+    }
+}
+
+impl std::error::Error for Undecided {}
+
+/// A choice among search results, which is reused by later searches with the
+/// same key instead of choosing again.
+#[derive(Clone, Debug)]
+struct Choice {
+    ent: MediaEntity,
+    /// The score of the chosen search result.
+    score: Option<f64>,
+    /// Whether the end user was prompted to make this choice.
+    prompted: bool,
+    /// The number of files this choice was applied to.
+    files: usize,
+}
+
+/// Return the score of the given entity among the given search results, if
+/// it is one of them.
+fn score_of(
+    results: &[Scored<MediaEntity>],
+    ent: &MediaEntity,
+) -> Option<f64> {
+    results
+        .iter()
+        .find(|r| r.value().title().id == ent.title().id)
+        .map(|r| r.score())
+}
+
+/// Canonicalize a title extracted from a file name.
+///
+/// The part of a file name before the year or episode number usually ends
+/// with whatever separated it from that number, e.g., `Show Name -` or
+/// `Movie Name (`. These are trimmed, along with leading separators, and
+/// runs of whitespace are collapsed, so that names of files for the same
+/// title build the same query. This matters because queries are the keys of
+/// the search and choice caches, so every title that ends up in a cache key,
+/// including the show titles of `show_choice_key`, goes through here.
+fn canonical_title(name: &str) -> String {
+    let is_sep = |c: char| c.is_whitespace() || "-_.,:;".contains(c);
+    let name = name
+        .trim_start_matches(is_sep)
+        .trim_end_matches(|c: char| is_sep(c) || "([{".contains(c));
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The key under which a choice among search results is cached, so that
+/// later searches with the same key reuse it instead of choosing again.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct ChoiceKey {
+    /// The query that was searched. For a key with a title, the query's name
+    /// is cleared, since the title stands in for it.
+    query: Query,
+    /// The normalized title of a TV show, if the query was built from the
+    /// file name of one of its episodes. See `show_choice_key`.
+    title: Option<String>,
+}
+
+impl ChoiceKey {
+    /// Return a key that is the given query itself.
+    fn exact(query: &Query) -> ChoiceKey {
+        ChoiceKey { query: query.clone(), title: None }
+    }
+
+    /// Return the name that this key was chosen for.
+    fn name(&self) -> &str {
+        self.title.as_deref().or(self.query.name_str()).unwrap_or("")
+    }
+}
+
+/// Return the key under which the choice among the results of the given TV
+/// show query is cached, where the query was built from the given show title
+/// taken from the file name of the given episode.
+///
+/// The names of episode files of the same show often differ in more than
+/// the episode itself, e.g., `Show - 02 - S01E02` and `Show.S01E03`, which
+/// leaks the episode number into the show's name. The key keeps the show's
+/// title, with the leaked episode number removed and case normalized, apart
+/// from the rest of the query, so that one answer applies to every episode
+/// of a show. See `show_title` for when a number is considered leaked.
+///
+/// The title is taken from the file name rather than the query, since query
+/// fixups may remove what tells a leaked number apart, like dashes.
+fn show_choice_key(query: &Query, title: &str, episode: u32) -> ChoiceKey {
+    match query.name_str() {
+        None => ChoiceKey::exact(query),
+        Some(_) => ChoiceKey {
+            query: query.clone().name(""),
+            title: Some(show_title(title, episode)),
+        },
+    }
+}
+
+/// Return the title of a TV show from a name extracted from the file name of
+/// the given episode, which may end with the episode number.
+///
+/// A trailing number is only dropped when it is the episode number and it
+/// is marked as one: either it is prefixed or preceded by a word like
+/// "episode", it is zero padded (as in `Show 02`), or it is set apart by a
+/// dash (as in `Show - 2`). Otherwise, the number is kept, since it may be
+/// part of the title, as in `Show 2` or `9-1-1`. The whole name is never
+/// dropped.
+fn show_title(name: &str, episode: u32) -> String {
+    lazy_static! {
+        static ref RE_NUMBER: Regex =
+            Regex::new(r"^(?i)(?P<prefix>e|ep|episode)?(?P<number>[0-9]+)$")
+                .unwrap();
+        static ref RE_MARKER: Regex =
+            Regex::new(r"^(?i)(?:e|ep|episode|-)$").unwrap();
+    }
+    let name = canonical_title(&name.replace(['.', '_'], " "));
+    let mut tokens: Vec<&str> = name.split_whitespace().collect();
+    if tokens.len() > 1 {
+        let last = tokens[tokens.len() - 1];
+        let leaked = RE_NUMBER.captures(last).is_some_and(|caps| {
+            let number = &caps["number"];
+            number.parse() == Ok(episode)
+                && (caps.name("prefix").is_some()
+                    || (number.len() > 1 && number.starts_with('0'))
+                    || RE_MARKER.is_match(tokens[tokens.len() - 2]))
+        });
+        if leaked {
+            tokens.pop();
+            while tokens.len() > 1
+                && RE_MARKER.is_match(tokens[tokens.len() - 1])
+            {
+                tokens.pop();
+            }
+        }
+    }
+    canonical_title(&tokens.join(" ")).to_lowercase()
+}
+
+/// The results of a search, along with diagnostics about how they were found.
+type Searched = (SearchResults<MediaEntity>, SearchDiagnostics);
+
+/// The state needed to propose a rename for a single path, which may happen
+/// on a worker thread.
+struct Job<'a> {
+    searcher: &'a mut Searcher,
+    /// Identifies the explanation of the path, if explanations are being
+    /// collected.
+    id: usize,
+    /// Where to send prompts for the end user. This is only set on worker
+    /// threads. Otherwise, the end user is prompted directly.
+    prompts: Option<&'a mpsc::Sender<Prompt>>,
+    /// When set, checks against the file system are skipped and recorded
+    /// here instead.
+    skipped: Option<Vec<SkippedCheck>>,
+    /// The score of the search result most recently chosen for the path.
+    score: Option<f64>,
+    /// Whether the end user was prompted for any of the search results
+    /// chosen for the path, e.g., for the TV show of an episode even if the
+    /// episode itself was then chosen automatically.
+    prompted: bool,
+}
+
+/// A check against the file system that was skipped by
+/// `Renamer::propose_pure`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SkippedCheck {
+    /// Whether the given source is in use. This is only checked when
+    /// skipping files in use is enabled.
+    InUse(PathBuf),
+    /// Resolving the given source to an absolute path, so that a symlink
+    /// created in another directory points to it. The proposal's symlink
+    /// target is left relative.
+    AbsoluteSource(PathBuf),
+    /// Computing a relative symlink target for the given destination. The
+    /// proposal's symlink target is left as is.
+    RelativeSymlink(PathBuf),
+    /// Whether the given parent directory of a destination exists.
+    DestinationDir(PathBuf),
+    /// Whether the given destination already exists.
+    DestinationExists(PathBuf),
+}
+
+impl fmt::Display for SkippedCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SkippedCheck::InUse(ref p) => {
+                write!(f, "whether {} is in use", p.display())
+            }
+            SkippedCheck::AbsoluteSource(ref p) => {
+                write!(f, "making {} an absolute path", p.display())
+            }
+            SkippedCheck::RelativeSymlink(ref p) => {
+                write!(f, "making the symlink {} relative", p.display())
+            }
+            SkippedCheck::DestinationDir(ref p) => {
+                write!(f, "whether directory {} exists", p.display())
+            }
+            SkippedCheck::DestinationExists(ref p) => {
+                write!(f, "whether {} already exists", p.display())
+            }
+        }
+    }
+}
+
+/// A request from a worker thread to prompt the end user to choose one of
+/// the given results.
+struct Prompt {
+    /// The query the results are from, along with the key of the choice.
+    query: Option<(Query, ChoiceKey)>,
+    results: Vec<Scored<MediaEntity>>,
+    reply: mpsc::Sender<anyhow::Result<Answer>>,
+}
+
 /// A candidate represents a source file path with additional structured
 /// information that helps us guess what its corresponding canonical IMDb
 /// entity is.
@@ -581,6 +2568,8 @@ struct Candidate {
     /// The type of candidate, with potentially additional information
     /// depending on the type.
     kind: CandidateKind,
+    /// The pattern that the candidate's type was found by.
+    pattern: Pattern,
 }
 
 /// A representation of a source path that we'd like to rename.
@@ -604,6 +2593,10 @@ struct CandidatePath {
     /// The extension of this path, if it exists, minus the leading `.`.
     /// e.g., `mkv` in `/foo/bar.mkv`.
     ext: Option<String>,
+    /// The names of the directories containing this path, nearest first.
+    /// e.g., `["Season 2", "Show"]` for `/Show/Season 2/07.mkv`. Relative
+    /// paths are resolved against the current directory first.
+    ancestors: Vec<String>,
 }
 
 /// Type of a candidate, including any additional type-specific information.
@@ -648,6 +2641,27 @@ struct CandidateEpisode {
     season: u32,
     /// The episode number.
     episode: u32,
+    /// Whatever follows the episode number, which is sometimes the episode
+    /// title. This may be empty.
+    episode_title: String,
+}
+
+impl CandidateKind {
+    /// Convert this candidate into the parse recorded in an explanation.
+    fn to_parse(&self) -> Parse {
+        match *self {
+            CandidateKind::Any(ref x) => {
+                Parse::Any { title: x.title.clone(), year: x.year }
+            }
+            CandidateKind::Episode(ref x) => Parse::Episode {
+                tvshow_title: x.tvshow_title.clone(),
+                season: x.season,
+                episode: x.episode,
+                episode_title: x.episode_title.clone(),
+            },
+            CandidateKind::Unknown => Parse::Unknown,
+        }
+    }
 }
 
 impl CandidatePath {
@@ -682,13 +2696,25 @@ impl CandidatePath {
                 }
             }
         };
-        Ok(CandidatePath { parent, base_name, ext })
+        let ancestors = std::path::absolute(&parent)
+            .unwrap_or_else(|_| parent.clone())
+            .iter()
+            .rev()
+            .filter_map(|c| c.to_str())
+            .filter(|c| *c != "/" && *c != "." && *c != "..")
+            .map(|c| c.to_string())
+            .collect();
+        Ok(CandidatePath { parent, base_name, ext, ancestors })
     }
 
     /// Convert this candidate path to the desired name based on an IMDb
     /// entity. In general, this replaces the `base_name` of this candidate
     /// with the title found in the given entity.
-    fn imdb_name(&self, ent: &MediaEntity) -> String {
+    ///
+    /// The given naming options determine how the title is formatted, and
+    /// how the extension, if any, is normalized when it's re-attached. The
+    /// extensions of dotfiles (like `.mkv`) are always kept as is.
+    fn imdb_name(&self, ent: &MediaEntity, naming: &Naming) -> String {
         let name = match ent.episode() {
             Some(ep) => format!(
                 "S{:02}E{:02} - {}",
@@ -696,24 +2722,807 @@ impl CandidatePath {
                 ep.episode.unwrap_or(0),
                 ent.title().title,
             ),
-            None => match ent.title().start_year {
-                None => ent.title().title.to_string(),
-                Some(year) => format!("{} ({})", ent.title().title, year),
-            },
-        };
-        match self.ext {
+            None => {
+                let title = if naming.sort_titles {
+                    sort_title(&ent.title().title)
+                } else {
+                    ent.title().title.to_string()
+                };
+                match ent.title().start_year {
+                    None => title,
+                    Some(year) => format!("{} ({})", title, year),
+                }
+            }
+        };
+        let name = match SourceTags::parse(&self.base_name).format() {
+            Some(tags) if naming.keep_tags => format!("{} [{}]", name, tags),
+            _ => name,
+        };
+        match self.ext {
             None => name,
-            Some(ref ext) => format!("{}.{}", name, ext),
+            Some(ref ext) if self.base_name.is_empty() => {
+                format!("{}.{}", name, ext)
+            }
+            Some(ref ext) => {
+                format!("{}.{}", name, naming.extensions.apply(ext))
+            }
+        }
+    }
+}
+
+/// Leading articles that are moved to the end of a title to form its sort
+/// title, grouped by language.
+///
+/// German "die" is deliberately absent, since in titles it's far more often
+/// the English verb (as in "Die Hard") than the German article.
+const ARTICLES: &[(&str, &[&str])] = &[
+    ("en", &["the", "a", "an"]),
+    ("fr", &["le", "la", "les", "l'", "un", "une", "des"]),
+    ("de", &["der", "das", "ein", "eine"]),
+    ("es", &["el", "la", "los", "las", "un", "una"]),
+];
+
+/// Returns the sort title of the given title, which moves a leading article
+/// to the end, e.g., `The Matrix` becomes `Matrix, The`. Titles without a
+/// leading article, or that consist of nothing but an article, are returned
+/// unchanged.
+///
+/// An article is only recognized as a whole word, so `Them` is unchanged.
+/// The French elided article is also recognized, so `L'Avenir` becomes
+/// `Avenir, L'`.
+pub(crate) fn sort_title(title: &str) -> String {
+    let is_article = |word: &str| {
+        let word = word.to_lowercase();
+        ARTICLES.iter().any(|&(_, articles)| articles.contains(&&*word))
+    };
+    if let Some((article, rest)) = title.split_once(' ') {
+        let rest = rest.trim_start();
+        if is_article(article) && !rest.is_empty() {
+            return format!("{}, {}", rest, article);
+        }
+    }
+    // Elided articles are attached to the following word.
+    if let Some(i) = title.find('\'') {
+        let (article, rest) = title.split_at(i + 1);
+        if is_article(article) && !rest.is_empty() {
+            return format!("{}, {}", rest, article);
+        }
+    }
+    title.to_string()
+}
+
+/// The case applied to the extensions of destination file names.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExtensionCase {
+    /// Keep the extension exactly as it is in the source file name.
+    #[default]
+    Keep,
+    /// Convert the extension to lowercase.
+    Lower,
+}
+
+/// Text fixups applied to names parsed from file names before they're used
+/// as queries.
+///
+/// File names tend to encode punctuation differently than IMDb does, e.g.,
+/// `Spider_Man` or `Dont` instead of `Spider-Man` or `Don't`. These fixups
+/// remove the differences that can be removed from the query side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueryFixups {
+    /// Replace hyphens with spaces, e.g., `Spider-Man` becomes `Spider Man`.
+    pub hyphens: bool,
+    /// Remove apostrophes, e.g., `Don't` becomes `Dont`.
+    pub apostrophes: bool,
+    /// Replace underscores with spaces.
+    pub underscores: bool,
+    /// Replace `&` with `and`.
+    pub ampersands: bool,
+    /// Replace runs of whitespace with a single space.
+    pub collapse_spaces: bool,
+}
+
+impl Default for QueryFixups {
+    fn default() -> QueryFixups {
+        QueryFixups {
+            hyphens: true,
+            apostrophes: true,
+            underscores: true,
+            ampersands: true,
+            collapse_spaces: true,
+        }
+    }
+}
+
+impl QueryFixups {
+    /// Fixups that leave every name as it is.
+    pub fn none() -> QueryFixups {
+        QueryFixups {
+            hyphens: false,
+            apostrophes: false,
+            underscores: false,
+            ampersands: false,
+            collapse_spaces: false,
+        }
+    }
+
+    /// Apply these fixups to the given name.
+    pub fn apply(&self, name: &str) -> String {
+        let mut name = name.to_string();
+        if self.hyphens {
+            name = name.replace('-', " ");
+        }
+        if self.apostrophes {
+            name = name.replace(['\'', '\u{2019}'], "");
+        }
+        if self.underscores {
+            name = name.replace('_', " ");
+        }
+        if self.ampersands {
+            name = name.replace('&', " and ");
+        }
+        if self.collapse_spaces {
+            name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        name
+    }
+}
+
+/// Search with the `strict` query, and if it yields no results, search with
+/// the `relaxed` query instead. This returns whichever query should be used
+/// to choose a result, and whether it is the relaxed one.
+///
+/// The search function given is typically backed by a cache, so the chosen
+/// query can be executed again cheaply.
+fn relax_if_empty<T, F>(
+    strict: Query,
+    relaxed: Query,
+    mut search: F,
+) -> anyhow::Result<(Query, bool)>
+where
+    F: FnMut(&Query) -> anyhow::Result<SearchResults<T>>,
+{
+    if !search(&strict)?.is_empty() {
+        return Ok((strict, false));
+    }
+    // Even if the relaxed query is empty too, it's no worse a query to
+    // report an error for.
+    search(&relaxed)?;
+    Ok((relaxed, true))
+}
+
+/// Returns true if the given name is more similar to the original title of
+/// one of the given results than to that result's primary title, e.g.,
+/// `le fabuleux destin d'amelie poulain` for the movie `Amélie`.
+fn prefers_original_title(
+    name: &str,
+    results: &[Scored<MediaEntity>],
+) -> bool {
+    let name = name.to_lowercase();
+    let sim = |title: &str| {
+        Similarity::JaroWinkler.similarity(&name, &title.to_lowercase())
+    };
+    results.iter().any(|r| {
+        let title = r.value().title();
+        title.original_title != title.title
+            && sim(&title.original_title) > sim(&title.title)
+    })
+}
+
+/// The largest season number considered plausible when parsing file names.
+const MAX_SEASON: u32 = 100;
+
+/// The largest episode number considered plausible when parsing file names.
+const MAX_EPISODE: u32 = 2000;
+
+/// Returns true if and only if the given offset into a file name falls
+/// inside a token that is commonly part of release names, like a resolution,
+/// codec or audio format. Tokens are delimited by dots, dashes, underscores,
+/// whitespace and brackets.
+fn is_release_token(name: &str, offset: usize) -> bool {
+    lazy_static! {
+        static ref RE_RELEASE_TOKEN: Regex = Regex::new(
+            r"(?ix)
+            ^(?:
+                [0-9]{3,4}[pi] | [0-9]k
+                | [xh]26[345] | hevc | avc | xvid | divx
+                | hdr(?:10)?(?:plus)? | dv | sdr | [0-9]{1,2}bit
+                | dts[0-9]* | ddp?[0-9]* | e?ac3 | aac[0-9]* | truehd
+                | atmos | flac[0-9]* | mp3 | opus
+                | web | webdl | webrip | bluray | bdrip | brrip | dvdrip
+                | hdtv | remux | uhd | imax
+            )$
+            ",
+        )
+        .unwrap();
+    }
+    let is_delim = |c: char| ".-_ []()".contains(c) || c.is_whitespace();
+    let start = name[..offset]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| is_delim(c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = name[offset..].find(is_delim).map_or(name.len(), |i| offset + i);
+    RE_RELEASE_TOKEN.is_match(&name[start..end])
+}
+
+/// Release tags parsed from a source file name, like its resolution and
+/// codec. Each tag is kept exactly as it's written in the file name.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct SourceTags {
+    /// e.g., `1080p` or `4K`.
+    pub(crate) resolution: Option<String>,
+    /// e.g., `WEB`, `BluRay` or `HDTV`.
+    pub(crate) source: Option<String>,
+    /// e.g., `x264` or `HEVC`.
+    pub(crate) codec: Option<String>,
+    /// e.g., `HDR10` or `DV`.
+    pub(crate) hdr: Option<String>,
+    /// The release group, which is whatever follows the last `-` in a name
+    /// that has at least one other tag, e.g., `GROUP` in
+    /// `Movie.2019.1080p.WEB.x264-GROUP`.
+    pub(crate) group: Option<String>,
+}
+
+impl SourceTags {
+    /// Parse the release tags from the given file name, minus its
+    /// extension. Only the first tag of each kind is kept.
+    pub(crate) fn parse(name: &str) -> SourceTags {
+        lazy_static! {
+            static ref RE_RESOLUTION: Regex =
+                Regex::new(r"(?i)^(?:[0-9]{3,4}[pi]|[0-9]k)$").unwrap();
+            static ref RE_SOURCE: Regex = Regex::new(
+                r"(?ix)^(?:
+                    web | webdl | webrip | bluray | bdrip | brrip | dvdrip
+                    | hdtv | remux | uhd
+                )$"
+            )
+            .unwrap();
+            static ref RE_CODEC: Regex =
+                Regex::new(r"(?i)^(?:[xh]26[345]|hevc|avc|xvid|divx)$")
+                    .unwrap();
+            static ref RE_HDR: Regex =
+                Regex::new(r"(?i)^(?:hdr(?:10)?(?:plus)?|dv)$").unwrap();
+        }
+        let is_delim = |c: char| ".-_ []()".contains(c) || c.is_whitespace();
+        let mut tags = SourceTags::default();
+        for token in name.split(is_delim) {
+            let slot = if RE_RESOLUTION.is_match(token) {
+                &mut tags.resolution
+            } else if RE_SOURCE.is_match(token) {
+                &mut tags.source
+            } else if RE_CODEC.is_match(token) {
+                &mut tags.codec
+            } else if RE_HDR.is_match(token) {
+                &mut tags.hdr
+            } else {
+                continue;
+            };
+            if slot.is_none() {
+                *slot = Some(token.to_string());
+            }
+        }
+        if tags.format().is_some() {
+            if let Some((_, group)) = name.rsplit_once('-') {
+                let is_tag = RE_RESOLUTION.is_match(group)
+                    || RE_SOURCE.is_match(group)
+                    || RE_CODEC.is_match(group)
+                    || RE_HDR.is_match(group);
+                if !group.is_empty() && !group.contains(is_delim) && !is_tag {
+                    tags.group = Some(group.to_string());
+                }
+            }
+        }
+        tags
+    }
+
+    /// Format the resolution, source, codec and HDR tags (in that order)
+    /// separated by spaces, e.g., `1080p WEB x264`. The release group is
+    /// omitted. If there are no tags, then `None` is returned.
+    pub(crate) fn format(&self) -> Option<String> {
+        let tags: Vec<&str> =
+            [&self.resolution, &self.source, &self.codec, &self.hdr]
+                .into_iter()
+                .filter_map(|tag| tag.as_deref())
+                .collect();
+        if tags.is_empty() {
+            None
+        } else {
+            Some(tags.join(" "))
+        }
+    }
+}
+
+/// Extensions of video files, in lowercase.
+const VIDEO_EXTS: &[&str] = &[
+    "3gp", "asf", "avi", "divx", "flv", "iso", "m2ts", "m4v", "mkv", "mov",
+    "mp4", "mpeg", "mpg", "mts", "ogm", "ogv", "rm", "rmvb", "ts", "vob",
+    "webm", "wmv",
+];
+
+/// Extensions of subtitle files, in lowercase.
+const SUBTITLE_EXTS: &[&str] =
+    &["ass", "idx", "smi", "srt", "ssa", "sub", "sup", "vtt"];
+
+/// Extensions of metadata files that media managers keep next to videos, in
+/// lowercase.
+const METADATA_EXTS: &[&str] = &["nfo", "xml"];
+
+/// A classification of files by their extension.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum FileClass {
+    Video,
+    Subtitle,
+    Metadata,
+    Other,
+}
+
+impl FileClass {
+    /// Classify the given path by its extension. Paths without an extension
+    /// are classified as `Other`.
+    fn from_path(path: &Path) -> FileClass {
+        let ext = match path.extension().and_then(|e| e.to_str()) {
+            None => return FileClass::Other,
+            Some(ext) => ext.to_lowercase(),
+        };
+        if VIDEO_EXTS.contains(&&*ext) {
+            FileClass::Video
+        } else if SUBTITLE_EXTS.contains(&&*ext) {
+            FileClass::Subtitle
+        } else if METADATA_EXTS.contains(&&*ext) {
+            FileClass::Metadata
+        } else {
+            FileClass::Other
+        }
+    }
+}
+
+/// Returns true if the given path looks like a subtitle file, based on its
+/// extension.
+pub fn is_subtitle(path: &Path) -> bool {
+    FileClass::from_path(path) == FileClass::Subtitle
+}
+
+/// Names of directories, in lowercase, that typically hold the subtitles of
+/// the videos in their parent directory.
+const SUBTITLE_DIRS: &[&str] = &["subs", "subtitles"];
+
+/// Names of directories, in lowercase, that aren't descended into by default
+/// when following directories.
+const SKIPPED_DIRS: &[&str] =
+    &["extras", "featurettes", "sample", "samples", "subs", "subtitles"];
+
+/// Returns true if the given directory typically holds the subtitles of the
+/// videos in its parent directory, e.g., `Movie (2017)/Subs`.
+pub fn is_subtitle_dir(dir: &Path) -> bool {
+    dir.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| SUBTITLE_DIRS.contains(&&*name.to_lowercase()))
+}
+
+/// A filter on the names of directories to descend into when following
+/// directories.
+///
+/// By default, directories that rarely contain anything worth renaming, like
+/// `Extras` and `Sample`, are excluded. Names are matched case
+/// insensitively.
+#[derive(Clone, Debug)]
+pub struct DirFilter {
+    excluded: HashSet<String>,
+    /// Excluded directories whose subtitles are still collected, which are
+    /// the subtitle directories that weren't excluded explicitly.
+    subtitle_dirs: HashSet<String>,
+    globs: Vec<Glob>,
+    case_sensitive_globs: bool,
+    /// When set, only files with one of these extensions are collected.
+    /// Always lowercase, and the empty string stands for no extension.
+    extensions: Option<HashSet<String>>,
+}
+
+impl Default for DirFilter {
+    fn default() -> DirFilter {
+        DirFilter::new()
+    }
+}
+
+impl DirFilter {
+    /// Create a filter that excludes the default set of directory names.
+    pub fn new() -> DirFilter {
+        DirFilter {
+            excluded: SKIPPED_DIRS.iter().map(|s| s.to_string()).collect(),
+            subtitle_dirs: SUBTITLE_DIRS
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            globs: vec![],
+            case_sensitive_globs: false,
+            extensions: None,
+        }
+    }
+
+    /// Descend into directories with the given name, even if they are
+    /// excluded by default.
+    pub fn include(&mut self, name: &str) -> &mut DirFilter {
+        self.excluded.remove(&name.to_lowercase());
+        self
+    }
+
+    /// Don't descend into directories with the given name.
+    ///
+    /// Unlike the subtitle directories excluded by default, the subtitles
+    /// in a directory excluded this way aren't collected either.
+    pub fn exclude(&mut self, name: &str) -> &mut DirFilter {
+        let name = name.to_lowercase();
+        self.subtitle_dirs.remove(&name);
+        self.excluded.insert(name);
+        self
+    }
+
+    /// Skip files and directories whose path, relative to the directory
+    /// being followed, matches the given glob. See `Glob` for its syntax.
+    pub fn exclude_glob(
+        &mut self,
+        glob: &str,
+    ) -> anyhow::Result<&mut DirFilter> {
+        self.globs.push(Glob::new(glob, self.case_sensitive_globs)?);
+        Ok(self)
+    }
+
+    /// Match the globs given to `exclude_glob`, and those in ignore files,
+    /// case sensitively. By default, they are matched case insensitively,
+    /// like directory names.
+    pub fn case_sensitive_globs(&mut self, yes: bool) -> &mut DirFilter {
+        self.case_sensitive_globs = yes;
+        for glob in &mut self.globs {
+            // The glob was already compiled once, and case sensitivity
+            // doesn't change whether it's valid.
+            *glob = Glob::new(&glob.glob, yes).unwrap();
+        }
+        self
+    }
+
+    /// Returns true if globs are matched case sensitively. See
+    /// `case_sensitive_globs`.
+    pub fn is_case_sensitive_globs(&self) -> bool {
+        self.case_sensitive_globs
+    }
+
+    /// Only collect files with the given extension, with or without its
+    /// leading `.`, when following directories. This may be called more than
+    /// once to allow several extensions. The empty string allows files
+    /// without an extension.
+    pub fn allow_extension(&mut self, ext: &str) -> &mut DirFilter {
+        let ext = ext.trim().trim_start_matches('.').to_lowercase();
+        self.extensions.get_or_insert_with(HashSet::new).insert(ext);
+        self
+    }
+
+    /// Returns true if the given file has an extension allowed by
+    /// `allow_extension`, or if any extension is allowed.
+    pub fn is_allowed_file(&self, path: &Path) -> bool {
+        let allowed = match self.extensions {
+            None => return true,
+            Some(ref allowed) => allowed,
+        };
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        allowed.contains(&ext)
+    }
+
+    /// Returns true if the given directory should not be descended into.
+    pub fn is_excluded(&self, dir: &Path) -> bool {
+        dir.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| self.excluded.contains(&name.to_lowercase()))
+    }
+
+    /// Returns true if the subtitles directly inside the given excluded
+    /// directory should still be collected, so that they can be renamed
+    /// along with the videos in its parent directory. This is true for the
+    /// subtitle directories excluded by default (see `is_subtitle_dir`),
+    /// unless they were given to `exclude`.
+    pub fn collects_subtitles(&self, dir: &Path) -> bool {
+        dir.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+            self.subtitle_dirs.contains(&name.to_lowercase())
+        })
+    }
+
+    /// Returns true if the given path, relative to the directory being
+    /// followed, matches a glob given to `exclude_glob`.
+    pub fn is_excluded_glob(&self, rel: &Path, is_dir: bool) -> bool {
+        self.globs.iter().any(|g| g.is_match(rel, is_dir))
+    }
+}
+
+/// A glob given to --exclude, or a line of an ignore file.
+///
+/// Globs are matched with `globset`: `*` and `?` match anything but `/`,
+/// `[abc]` and `[!abc]` match a character in (or not in) a set, `{a,b}`
+/// matches either alternative and `\` escapes the character after it. A
+/// `**` component matches any number of directories, while a trailing `/**`
+/// matches everything inside a directory.
+///
+/// Like in `.gitignore` files, a glob without a `/` matches names at any
+/// depth, a leading `/` anchors a glob to the directory being followed (or
+/// containing the ignore file), and a trailing `/` only matches directories.
+#[derive(Clone, Debug)]
+struct Glob {
+    /// The glob as it was given, so that it can be compiled again with a
+    /// different case sensitivity.
+    glob: String,
+    matcher: GlobMatcher,
+    dir_only: bool,
+}
+
+impl Glob {
+    fn new(glob: &str, case_sensitive: bool) -> anyhow::Result<Glob> {
+        let invalid = |msg: &dyn fmt::Display| {
+            anyhow::anyhow!("invalid glob '{}': {}", glob, msg)
+        };
+        let (dir_only, pat) = match glob.strip_suffix('/') {
+            None => (false, glob),
+            Some(pat) => (true, pat),
+        };
+        let (anchored, pat) = match pat.strip_prefix('/') {
+            None => (pat.contains('/'), pat),
+            Some(pat) => (true, pat),
+        };
+        if pat.is_empty() {
+            return Err(invalid(&"it is empty"));
+        }
+        let pat =
+            if anchored { pat.to_string() } else { format!("**/{}", pat) };
+        let matcher = GlobBuilder::new(&pat)
+            .literal_separator(true)
+            .backslash_escape(true)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .map_err(|e| invalid(e.kind()))?
+            .compile_matcher();
+        Ok(Glob { glob: glob.to_string(), matcher, dir_only })
+    }
+
+    /// Returns true if the given path, relative to the directory being
+    /// followed (or containing the ignore file), matches this glob.
+    fn is_match(&self, path: &Path, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.matcher.is_match(path)
+    }
+}
+
+/// The name of a file listing the names of files and directories to skip
+/// when following directories. It applies to the directory containing it
+/// and everything below it.
+pub const IGNORE_FILE: &str = ".imdbrenameignore";
+
+/// The patterns of an ignore file.
+///
+/// Each line of an ignore file is a glob, with the same syntax as the globs
+/// given to --exclude (see `Glob`), which is matched against paths relative
+/// to the directory containing the ignore file. Like `.gitignore` files,
+/// blank lines and lines starting with `#` are skipped, and a line starting
+/// with `!` re-includes anything that an earlier line (or an ignore file
+/// further up) excluded. When several lines match, the last one wins.
+#[derive(Clone, Debug, Default)]
+pub struct IgnorePatterns {
+    patterns: Vec<IgnorePattern>,
+}
+
+#[derive(Clone, Debug)]
+struct IgnorePattern {
+    glob: Glob,
+    negated: bool,
+}
+
+impl IgnorePatterns {
+    /// Parse the contents of an ignore file, whose globs are matched case
+    /// sensitively if `case_sensitive` is true.
+    ///
+    /// This returns an error if a line isn't a valid glob.
+    pub fn parse(
+        contents: &str,
+        case_sensitive: bool,
+    ) -> anyhow::Result<IgnorePatterns> {
+        let mut patterns = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                None => (false, line),
+                Some(line) => (true, line),
+            };
+            let glob = Glob::new(line, case_sensitive)
+                .map_err(|e| anyhow::anyhow!("line {}: {}", i + 1, e))?;
+            patterns.push(IgnorePattern { glob, negated });
+        }
+        Ok(IgnorePatterns { patterns })
+    }
+
+    /// Read the ignore file in the given directory, if one exists. See
+    /// `parse`.
+    pub fn from_dir(
+        dir: &Path,
+        case_sensitive: bool,
+    ) -> anyhow::Result<Option<IgnorePatterns>> {
+        let path = dir.join(IGNORE_FILE);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(e) => {
+                anyhow::bail!("error reading '{}': {}", path.display(), e)
+            }
+        };
+        IgnorePatterns::parse(&contents, case_sensitive).map(Some).map_err(
+            |e| anyhow::anyhow!("error parsing '{}': {}", path.display(), e),
+        )
+    }
+
+    /// Returns `Some(true)` if the given path, relative to the directory
+    /// containing the ignore file, should be skipped, `Some(false)` if it
+    /// was explicitly re-included and `None` if no pattern matches it.
+    pub fn is_ignored(&self, rel: &Path, is_dir: bool) -> Option<bool> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| p.glob.is_match(rel, is_dir))
+            .map(|p| !p.negated)
+    }
+}
+
+/// The video files in a batch, used to find companions of other files.
+///
+/// A file is a companion of a video if it is in the same directory and its
+/// name, minus its extension, starts with the video's name minus its
+/// extension. e.g., `Movie.2017.en.srt` is a companion of `Movie.2017.mkv`.
+/// Subtitles in a subtitle directory (see `is_subtitle_dir`) are instead
+/// compared with the videos in its parent directory.
+#[derive(Debug)]
+struct Companions {
+    videos: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Companions {
+    fn new(paths: &[PathBuf]) -> Companions {
+        let mut videos: HashMap<PathBuf, Vec<String>> = HashMap::new();
+        for path in paths {
+            if FileClass::from_path(path) != FileClass::Video {
+                continue;
+            }
+            let stem = match path.file_stem().and_then(|s| s.to_str()) {
+                None => continue,
+                Some(stem) => stem.to_lowercase(),
+            };
+            let parent = path.parent().unwrap_or(Path::new(""));
+            videos.entry(parent.to_path_buf()).or_default().push(stem);
+        }
+        Companions { videos }
+    }
+
+    /// Returns true if the given path is a companion of a video.
+    fn has_video(&self, path: &Path) -> bool {
+        let stem = match path.file_stem().and_then(|s| s.to_str()) {
+            None => return false,
+            Some(stem) => stem.to_lowercase(),
+        };
+        let mut parent = path.parent().unwrap_or(Path::new(""));
+        if is_subtitle(path) && is_subtitle_dir(parent) {
+            parent = parent.parent().unwrap_or(Path::new(""));
+        }
+        self.videos.get(parent).is_some_and(|stems| {
+            stems.iter().any(|video| stem.starts_with(video.as_str()))
+        })
+    }
+}
+
+/// Options for formatting the names of destination files.
+#[derive(Clone, Debug, Default)]
+struct Naming {
+    /// Whether titles that aren't episodes use their sort title, e.g.,
+    /// `Matrix, The (1999)`.
+    sort_titles: bool,
+    /// Whether release tags parsed from the source name are appended, e.g.,
+    /// `Movie (2019) [1080p WEB x264]`.
+    keep_tags: bool,
+    /// How extensions are normalized.
+    extensions: ExtensionPolicy,
+}
+
+/// How the extensions of destination file names are normalized.
+///
+/// Aliases are applied after the case is changed, and are matched case
+/// insensitively.
+#[derive(Clone, Debug, Default)]
+struct ExtensionPolicy {
+    case: ExtensionCase,
+    aliases: HashMap<String, String>,
+}
+
+impl ExtensionPolicy {
+    /// Apply this policy to the given extension (minus the leading `.`).
+    fn apply(&self, ext: &str) -> String {
+        let ext = match self.case {
+            ExtensionCase::Keep => ext.to_string(),
+            ExtensionCase::Lower => ext.to_lowercase(),
+        };
+        match self.aliases.get(&ext.to_lowercase()) {
+            None => ext,
+            Some(alias) => alias.clone(),
+        }
+    }
+}
+
+/// Kind specific destination directories.
+///
+/// When a directory for a kind of title is set, it takes priority over the
+/// destination directory given to `Renamer::propose`.
+#[derive(Clone, Debug, Default)]
+struct DestLayout {
+    movies_dir: Option<PathBuf>,
+    tv_dir: Option<PathBuf>,
+    /// Whether show directories include the years the show ran.
+    show_years: bool,
+}
+
+impl DestLayout {
+    /// The directory for titles that aren't TV episodes, if one is set.
+    fn movie_dir(&self) -> Option<PathBuf> {
+        self.movies_dir.clone()
+    }
+
+    /// The name of the directory for the given show, e.g., `Futurama` or,
+    /// when show years are enabled, `Futurama (1999–2013)`.
+    fn show_dir_name(&self, show: &Title) -> String {
+        match show.year_range() {
+            Some(years) if self.show_years => {
+                format!("{} ({})", show.title, years)
+            }
+            _ => show.title.clone(),
         }
     }
+
+    /// The directory for an episode of the given show and season, if a TV
+    /// root is set.
+    fn episode_dir(&self, show: &str, season: u32) -> Option<PathBuf> {
+        let show = show.replace(['/', '\x00'], "_");
+        let season = format!("Season {:02}", season);
+        self.tv_dir.as_ref().map(|root| root.join(show).join(season))
+    }
 }
 
 /// A builder for configuring a renamer.
 #[derive(Clone, Debug)]
 pub struct RenamerBuilder {
     force: Option<MediaEntity>,
-    min_votes: u32,
+    events: Option<Arc<dyn EventSink>>,
+    min_votes_movie: u32,
+    min_votes_tvshow: u32,
     good_threshold: f64,
+    min_match_ratio: f64,
+    preserve_times: bool,
+    symlink_relative: bool,
+    reflink_fallback: bool,
+    skip_in_use: bool,
+    mkdir: bool,
+    dir_perms: DirPerms,
+    write_origin: Option<OriginFormat>,
+    write_nfo: bool,
+    force_nfo: bool,
+    all_files: bool,
+    non_interactive: bool,
+    explain: bool,
+    color: bool,
+    layout: DestLayout,
+    naming: Naming,
+    query_fixups: QueryFixups,
+    threads: usize,
+    episode_gap: usize,
+    season_override: Option<u32>,
+    episode_override: Option<u32>,
+    regex_season_episode: Option<String>,
     regex_episode: String,
     regex_season: String,
     regex_year: String,
@@ -724,8 +3533,32 @@ impl RenamerBuilder {
     pub fn new() -> RenamerBuilder {
         RenamerBuilder {
             force: None,
-            min_votes: 1000,
+            events: None,
+            min_votes_movie: 1000,
+            min_votes_tvshow: 1000,
             good_threshold: 0.25,
+            min_match_ratio: 0.0,
+            preserve_times: false,
+            symlink_relative: false,
+            reflink_fallback: false,
+            skip_in_use: false,
+            mkdir: false,
+            dir_perms: DirPerms::default(),
+            write_origin: None,
+            write_nfo: false,
+            force_nfo: false,
+            all_files: false,
+            non_interactive: false,
+            explain: false,
+            color: false,
+            layout: DestLayout::default(),
+            naming: Naming::default(),
+            query_fixups: QueryFixups::default(),
+            threads: 1,
+            episode_gap: 3,
+            season_override: None,
+            episode_override: None,
+            regex_season_episode: None,
             regex_episode: r"[Ee](?P<episode>[0-9]+)".into(),
             regex_season: r"[Ss](?P<season>[0-9]+)".into(),
             regex_year: r"\b(?P<year>[0-9]{4})\b".into(),
@@ -736,16 +3569,73 @@ impl RenamerBuilder {
     pub fn build(&self) -> anyhow::Result<Renamer> {
         Ok(Renamer {
             cache: Mutex::new(HashMap::new()),
+            skip_count: AtomicUsize::new(0),
+            undecided_count: AtomicUsize::new(0),
             choose_cache: Mutex::new(HashMap::new()),
             force: self.force.clone(),
-            min_votes: self.min_votes,
+            events: self.events.clone(),
+            min_votes_movie: self.min_votes_movie,
+            min_votes_tvshow: self.min_votes_tvshow,
             good_threshold: self.good_threshold,
+            min_match_ratio: self.min_match_ratio,
+            preserve_times: self.preserve_times,
+            symlink_relative: self.symlink_relative,
+            reflink_fallback: self.reflink_fallback,
+            skip_in_use: self.skip_in_use,
+            mkdir: self.mkdir,
+            dir_perms: self.dir_perms,
+            write_origin: self.write_origin,
+            write_nfo: self.write_nfo,
+            force_nfo: self.force_nfo,
+            all_files: self.all_files,
+            non_interactive: self.non_interactive,
+            explain: self.explain,
+            explanations: Mutex::new(vec![]),
+            match_stats: Mutex::new(vec![]),
+            color: self.color,
+            layout: self.layout.clone(),
+            naming: self.naming.clone(),
+            query_fixups: self.query_fixups,
+            threads: self.threads,
+            episode_gap: self.episode_gap,
+            season_override: self.season_override,
+            episode_override: self.episode_override,
+            season_episode: match self.regex_season_episode {
+                None => None,
+                Some(ref pattern) => Some(Regex::new(pattern)?),
+            },
             episode: Regex::new(&self.regex_episode)?,
             season: Regex::new(&self.regex_season)?,
             year: Regex::new(&self.regex_year)?,
         })
     }
 
+    /// Use the given season number for every path, instead of the one found
+    /// in its name.
+    ///
+    /// When both a season and an episode override are set, paths whose names
+    /// lack episode information are treated as episodes anyway, with the
+    /// whole name presumed to be the TV show title. This is most useful
+    /// along with `force`, which sets the TV show.
+    pub fn season_override(&mut self, season: u32) -> &mut RenamerBuilder {
+        self.season_override = Some(season);
+        self
+    }
+
+    /// Use the given episode number for every path, instead of the one found
+    /// in its name. See `season_override`.
+    pub fn episode_override(&mut self, episode: u32) -> &mut RenamerBuilder {
+        self.episode_override = Some(episode);
+        self
+    }
+
+    /// Send the events of proposing renames, like skipping a file, to the
+    /// given sink. By default, no events are sent.
+    pub fn events(&mut self, sink: Arc<dyn EventSink>) -> &mut RenamerBuilder {
+        self.events = Some(sink);
+        self
+    }
+
     /// Forcefully use the given entity when producing rename proposals.
     ///
     /// When an entity is given here, the renamer will never execute automatic
@@ -761,13 +3651,35 @@ impl RenamerBuilder {
     }
 
     /// Set the minimum number of votes required for all search results from
-    /// automatic queries. This is used when formulating queries based on file
-    /// names that aren't TV episodes. The purpose of this is to heuristically
-    /// filter out noise from the IMDb data.
+    /// automatic queries. The purpose of this is to heuristically filter out
+    /// noise from the IMDb data.
+    ///
+    /// This is a convenience for setting both `min_votes_movie` and
+    /// `min_votes_tvshow` to the same value.
     ///
     /// When this isn't specified, a non-zero default is used.
     pub fn min_votes(&mut self, min_votes: u32) -> &mut RenamerBuilder {
-        self.min_votes = min_votes;
+        self.min_votes_movie = min_votes;
+        self.min_votes_tvshow = min_votes;
+        self
+    }
+
+    /// Set the minimum number of votes required for search results from
+    /// automatic queries based on file names that aren't TV episodes.
+    ///
+    /// When this isn't specified, a non-zero default is used.
+    pub fn min_votes_movie(&mut self, min_votes: u32) -> &mut RenamerBuilder {
+        self.min_votes_movie = min_votes;
+        self
+    }
+
+    /// Set the minimum number of votes required for TV shows found by
+    /// automatic queries based on the file names of episodes. Note that this
+    /// applies to the TV show and not to the episode itself.
+    ///
+    /// When this isn't specified, a non-zero default is used.
+    pub fn min_votes_tvshow(&mut self, min_votes: u32) -> &mut RenamerBuilder {
+        self.min_votes_tvshow = min_votes;
         self
     }
 
@@ -784,36 +3696,2828 @@ impl RenamerBuilder {
         self
     }
 
-    /// Set the regex for detecting the episode number from a file path.
+    /// Sets the minimum ratio of query terms that the top result of a search
+    /// by name must match for it to be chosen automatically.
     ///
-    /// Regexes are executed against the base name of a path. The episode
-    /// number is extracted via the `episode` named capture group.
-    pub fn regex_episode(&mut self, pattern: &str) -> &mut RenamerBuilder {
-        self.regex_episode = pattern.to_string();
+    /// This is checked in addition to the "good" threshold, and applies even
+    /// when there is only one result. For example, with a ratio of `0.8`, a
+    /// result that only matched 7 of the 9 ngrams of the name searched for is
+    /// never chosen without prompting. A ratio of `0` disables this check,
+    /// which is the default.
+    pub fn min_match_ratio(&mut self, ratio: f64) -> &mut RenamerBuilder {
+        self.min_match_ratio = ratio;
         self
     }
 
-    /// Set the regex for detecting the season number from a file path.
+    /// When enabled, copies and reflinks also carry over the access time of
+    /// each file they copy. They always keep the modification time and
+    /// permissions of their source.
     ///
-    /// Regexes are executed against the base name of a path. The season
-    /// number is extracted via the `season` named capture group.
-    pub fn regex_season(&mut self, pattern: &str) -> &mut RenamerBuilder {
-        self.regex_season = pattern.to_string();
+    /// Renames and links always share metadata with their source, so this
+    /// has no effect on them.
+    pub fn preserve_times(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.preserve_times = yes;
         self
     }
 
-    /// Set the regex for detecting the year from a file path.
+    /// Set the text fixups applied to names parsed from file names before
+    /// they're searched for. By default, every fixup is enabled. Use
+    /// `QueryFixups::none()` to search for names as they are (minus dots,
+    /// which are always replaced with spaces).
+    pub fn query_fixups(
+        &mut self,
+        fixups: QueryFixups,
+    ) -> &mut RenamerBuilder {
+        self.query_fixups = fixups;
+        self
+    }
+
+    /// When enabled, symlinks point to their source via a path relative to
+    /// the directory containing the link. See
+    /// `RenameProposal::make_symlink_relative` for details.
     ///
-    /// Regexes are executed against the base name of a path. The year is
-    /// extracted via the `year` named capture group.
-    pub fn regex_year(&mut self, pattern: &str) -> &mut RenamerBuilder {
-        self.regex_year = pattern.to_string();
+    /// This has no effect on actions other than symlinking.
+    pub fn symlink_relative(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.symlink_relative = yes;
         self
     }
-}
 
-impl Default for RenamerBuilder {
-    fn default() -> RenamerBuilder {
-        RenamerBuilder::new()
+    /// When enabled, reflinks fall back to a regular copy of each file that
+    /// can't be cloned, e.g., because its file system doesn't support it.
+    /// Otherwise, such reflinks fail.
+    ///
+    /// This has no effect on actions other than reflinking.
+    pub fn reflink_fallback(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.reflink_fallback = yes;
+        self
+    }
+
+    /// When enabled, files that appear to still be written to (e.g., by a
+    /// download client) are skipped. A file is considered in use if its size
+    /// changes over a short interval or, on Linux, if any process has it
+    /// open for writing.
+    ///
+    /// Enabling this makes every call to `propose` wait for the interval.
+    pub fn skip_in_use(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.skip_in_use = yes;
+        self
+    }
+
+    /// When enabled, missing parent directories of destinations are created
+    /// when a proposal is executed. Otherwise, proposing a destination in a
+    /// directory that doesn't exist is an error.
+    pub fn mkdir(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.mkdir = yes;
+        self
+    }
+
+    /// Set the permissions given to directories created because of `mkdir`.
+    /// See `DirPerms` for details.
+    pub fn dir_perms(&mut self, perms: DirPerms) -> &mut RenamerBuilder {
+        self.dir_perms = perms;
+        self
+    }
+
+    /// When set, executing a proposal records the name of its source, along
+    /// with the IMDb title it was matched to, next to its destination in the
+    /// given format. See the `origin` module for details.
+    ///
+    /// By default, origins aren't recorded for any action.
+    pub fn write_origin(
+        &mut self,
+        format: Option<OriginFormat>,
+    ) -> &mut RenamerBuilder {
+        self.write_origin = format;
+        self
+    }
+
+    /// When enabled, executing a proposal for a video writes an NFO file
+    /// describing the IMDb title it was matched to next to its destination,
+    /// for media centers like Kodi and Jellyfin. See the `nfo` module for
+    /// details.
+    ///
+    /// By default, NFO files aren't written.
+    pub fn write_nfo(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.write_nfo = yes;
+        self
+    }
+
+    /// When enabled, NFO files written because of `write_nfo` replace any
+    /// existing NFO files. Otherwise, existing NFO files are left alone.
+    pub fn force_nfo(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.force_nfo = yes;
+        self
+    }
+
+    /// When enabled, the end user is never prompted to choose among search
+    /// results. Paths for which no single result stands out are skipped
+    /// instead, and counted by `Renamer::undecided_count`.
+    ///
+    /// This is disabled by default.
+    pub fn non_interactive(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.non_interactive = yes;
+        self
+    }
+
+    /// When enabled, files are proposed for renaming regardless of their
+    /// extension. Otherwise, only video files (and their subtitle and
+    /// metadata companions) are considered.
+    pub fn all_files(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.all_files = yes;
+        self
+    }
+
+    /// Set the number of threads used to search for the titles of the paths
+    /// given to `Renamer::propose`. Each thread uses its own clone of the
+    /// index. Prompts for the end user are always shown one at a time, from
+    /// the thread calling `propose`.
+    ///
+    /// This is `1` by default, which searches for each path in turn. A value
+    /// of `0` is treated as `1`.
+    pub fn threads(&mut self, n: usize) -> &mut RenamerBuilder {
+        self.threads = n;
+        self
+    }
+
+    /// When enabled, the renamer records how each path was parsed, which
+    /// searches were executed and why each result was chosen. The traces
+    /// can be retrieved via `Renamer::explanations`.
+    ///
+    /// This is disabled by default.
+    pub fn explain(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.explain = yes;
+        self
+    }
+
+    /// When enabled, the parts of result titles that match the query are
+    /// highlighted with ANSI escape sequences when prompting the end user to
+    /// choose a result.
+    ///
+    /// This is disabled by default.
+    pub fn color(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.color = yes;
+        self
+    }
+
+    /// Set the case applied to the extensions of destination file names.
+    ///
+    /// By default, extensions are kept as is.
+    pub fn extension_case(
+        &mut self,
+        case: ExtensionCase,
+    ) -> &mut RenamerBuilder {
+        self.naming.extensions.case = case;
+        self
+    }
+
+    /// When enabled, release tags parsed from the source name (like its
+    /// resolution, source and codec) are appended to destination names in
+    /// brackets, e.g., `Movie (2019) [1080p WEB x264].mkv`.
+    ///
+    /// This is disabled by default.
+    pub fn keep_tags(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.naming.keep_tags = yes;
+        self
+    }
+
+    /// When enabled, the names of titles that aren't episodes use their sort
+    /// title, which moves a leading article to the end, e.g.,
+    /// `Matrix, The (1999)`.
+    ///
+    /// This is disabled by default.
+    pub fn sort_titles(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.naming.sort_titles = yes;
+        self
+    }
+
+    /// Replace the extension `from` with `to` in destination file names,
+    /// e.g., `mpeg4` with `mp4`. The extensions given should not include the
+    /// leading `.`, and `from` is matched case insensitively.
+    ///
+    /// The alias is applied after the extension's case is changed via
+    /// `extension_case`.
+    pub fn extension_alias(
+        &mut self,
+        from: &str,
+        to: &str,
+    ) -> &mut RenamerBuilder {
+        self.naming
+            .extensions
+            .aliases
+            .insert(from.to_lowercase(), to.to_string());
+        self
+    }
+
+    /// Set the directory in which movies (and any other title that isn't a
+    /// TV episode) are placed. This overrides the destination given to
+    /// `Renamer::propose`.
+    pub fn movies_dir<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+    ) -> &mut RenamerBuilder {
+        self.layout.movies_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the root directory in which TV episodes are placed. Each episode
+    /// is put in a `{show}/Season {NN}` sub-directory of this root. This
+    /// overrides the destination given to `Renamer::propose`.
+    pub fn tv_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut RenamerBuilder {
+        self.layout.tv_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// When enabled, the `{show}` directories created under the TV root
+    /// include the years the show ran, e.g., `Futurama (1999–2013)` or
+    /// `The Simpsons (1989–)` for a show that is still airing.
+    ///
+    /// This is disabled by default.
+    pub fn show_years(&mut self, yes: bool) -> &mut RenamerBuilder {
+        self.layout.show_years = yes;
+        self
+    }
+
+    /// Set the maximum number of bytes permitted between the end of a season
+    /// match and the start of an episode match in a file name. For example,
+    /// `S01E02` has a gap of `0` while `S01.E02` has a gap of `1`.
+    ///
+    /// This prevents unrelated text, like `DTS5.1-E1`, from being mistaken
+    /// for episode information. When this isn't specified, a small default
+    /// is used.
+    pub fn episode_gap(&mut self, gap: usize) -> &mut RenamerBuilder {
+        self.episode_gap = gap;
+        self
+    }
+
+    /// Set a regex for detecting both the season and episode numbers from a
+    /// file path with a single match, e.g., `Show.0205` for S02E05.
+    ///
+    /// This is tried before the separate season and episode regexes, which
+    /// are only used if this regex doesn't produce a plausible match. Both
+    /// numbers are extracted via the `season` and `episode` named capture
+    /// groups, and the TV show name is all text preceding the match. By
+    /// default, no such regex is used.
+    pub fn regex_season_episode(
+        &mut self,
+        pattern: &str,
+    ) -> &mut RenamerBuilder {
+        self.regex_season_episode = Some(pattern.to_string());
+        self
+    }
+
+    /// Set the regex for detecting the episode number from a file path.
+    ///
+    /// Regexes are executed against the base name of a path. The episode
+    /// number is extracted via the `episode` named capture group.
+    pub fn regex_episode(&mut self, pattern: &str) -> &mut RenamerBuilder {
+        self.regex_episode = pattern.to_string();
+        self
+    }
+
+    /// Set the regex for detecting the season number from a file path.
+    ///
+    /// Regexes are executed against the base name of a path. The season
+    /// number is extracted via the `season` named capture group.
+    pub fn regex_season(&mut self, pattern: &str) -> &mut RenamerBuilder {
+        self.regex_season = pattern.to_string();
+        self
+    }
+
+    /// Set the regex for detecting the year from a file path.
+    ///
+    /// Regexes are executed against the base name of a path. The year is
+    /// extracted via the `year` named capture group.
+    pub fn regex_year(&mut self, pattern: &str) -> &mut RenamerBuilder {
+        self.regex_year = pattern.to_string();
+        self
+    }
+}
+
+impl Default for RenamerBuilder {
+    fn default() -> RenamerBuilder {
+        RenamerBuilder::new()
+    }
+}
+
+/// The interval over which a file's size must be stable for it to be
+/// considered not in use.
+const IN_USE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Find which of the given paths appear to be in use, and return a reason
+/// for each.
+///
+/// A file is in use if its size changes across a call to `wait` with the
+/// given interval, or if any process has it open for writing (only detected
+/// on Linux). Directories and paths that can't be read are never in use.
+fn find_in_use<F: FnOnce(Duration)>(
+    paths: &[PathBuf],
+    interval: Duration,
+    wait: F,
+) -> HashMap<PathBuf, String> {
+    let size = |path: &Path| match fs::metadata(path) {
+        Ok(md) if md.is_file() => Some(md.len()),
+        _ => None,
+    };
+    let before: Vec<Option<u64>> = paths.iter().map(|p| size(p)).collect();
+    let mut in_use = HashMap::new();
+    for (path, pid) in open_for_writing(paths) {
+        in_use.insert(path, format!("open for writing by process {}", pid));
+    }
+    wait(interval);
+    for (path, before) in paths.iter().zip(before) {
+        let before = match before {
+            None => continue,
+            Some(before) => before,
+        };
+        if let Some(after) = size(path) {
+            if before != after && !in_use.contains_key(path) {
+                let reason = format!(
+                    "size changed from {} to {} bytes in {:?}",
+                    before, after, interval,
+                );
+                in_use.insert(path.clone(), reason);
+            }
+        }
+    }
+    in_use
+}
+
+/// Return the paths among those given that some process has open for
+/// writing, along with the ID of one such process.
+///
+/// This works by scanning `/proc/*/fd`, which only includes processes that
+/// we have permission to inspect.
+#[cfg(target_os = "linux")]
+fn open_for_writing(paths: &[PathBuf]) -> HashMap<PathBuf, u32> {
+    // The low two bits of the flags in fdinfo are the access mode, where
+    // 1 is O_WRONLY and 2 is O_RDWR.
+    fn is_writable(fdinfo: &Path) -> bool {
+        let contents = match fs::read_to_string(fdinfo) {
+            Ok(contents) => contents,
+            Err(_) => return false,
+        };
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix("flags:"))
+            .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+            .is_some_and(|flags| flags & 0b11 != 0)
+    }
+
+    let mut wanted = HashMap::new();
+    for path in paths {
+        if let Ok(canonical) = path.canonicalize() {
+            wanted.insert(canonical, path);
+        }
+    }
+    let mut found = HashMap::new();
+    if wanted.is_empty() {
+        return found;
+    }
+    let procs = match fs::read_dir("/proc") {
+        Ok(procs) => procs,
+        Err(_) => return found,
+    };
+    for proc in procs.filter_map(|r| r.ok()) {
+        let pid: u32 = match proc.file_name().to_str().map(|s| s.parse()) {
+            Some(Ok(pid)) => pid,
+            _ => continue,
+        };
+        let fds = match fs::read_dir(proc.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.filter_map(|r| r.ok()) {
+            let target = match fs::read_link(fd.path()) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+            let path = match wanted.get(&target) {
+                None => continue,
+                Some(path) => path,
+            };
+            let fdinfo = proc.path().join("fdinfo").join(fd.file_name());
+            if is_writable(&fdinfo) {
+                found.insert(path.to_path_buf(), pid);
+            }
+        }
+    }
+    found
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_for_writing(_: &[PathBuf]) -> HashMap<PathBuf, u32> {
+    HashMap::new()
+}
+
+/// Rename `src` to `dst`.
+///
+/// When the rename only changes the case of the file name, it is done in two
+/// steps via a temporary name. Some case insensitive file systems (or the
+/// platforms accessing them) otherwise treat it as a no-op.
+pub(crate) fn rename_path(src: &Path, dst: &Path) -> io::Result<()> {
+    if !is_case_only(src, dst) {
+        return fs::rename(src, dst);
+    }
+    let mut tmp_name = OsString::from(".imdb-rename-tmp-");
+    tmp_name.push(dst.file_name().unwrap_or_default());
+    let tmp = dst.with_file_name(tmp_name);
+    if fs::symlink_metadata(&tmp).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("temporary path '{}' already exists", tmp.display()),
+        ));
+    }
+    fs::rename(src, &tmp)?;
+    fs::rename(&tmp, dst).inspect_err(|_| {
+        // Try to put things back the way they were. If this fails too, then
+        // there's not much else we can do.
+        let _ = fs::rename(&tmp, src);
+    })
+}
+
+/// Move `src` to `dst` on another file system, where renaming isn't possible,
+/// by copying `src` (recursively, if it's a directory) and then removing it.
+///
+/// The times and permissions of copied files are preserved. If copying
+/// fails, then whatever was copied is removed again and `src` is left
+/// alone. `dst` must not exist.
+pub(crate) fn move_across_devices(src: &Path, dst: &Path) -> io::Result<()> {
+    copy_path(src, dst, Preserve::All)?;
+    remove_tree(src)
+}
+
+/// Copy `src` to `dst`, recursively if it's a directory. File contents are
+/// streamed, so big files are never read into memory.
+///
+/// The given metadata of copied files is preserved. If copying fails, then
+/// whatever was copied is removed again. `dst` must not exist.
+pub(crate) fn copy_path(
+    src: &Path,
+    dst: &Path,
+    what: Preserve,
+) -> io::Result<()> {
+    copy_path_with(src, dst, &|from, to| copy_file(from, to, what))
+}
+
+/// Like `copy_path`, but every file is cloned instead of copied, such that
+/// it shares its data with its source. If cloning a file fails because its
+/// file system can't clone it, then it's copied instead when `fallback` is
+/// true.
+pub(crate) fn reflink_path(
+    src: &Path,
+    dst: &Path,
+    fallback: bool,
+    what: Preserve,
+) -> io::Result<()> {
+    copy_path_with(src, dst, &|from, to| match reflink_file(from, to, what) {
+        Err(ref err) if fallback && is_reflink_unsupported(err) => {
+            copy_file(from, to, what)
+        }
+        result => result,
+    })
+}
+
+/// Copy `src` to `dst` with `copy_tree`, after checking that `dst` doesn't
+/// exist. If copying fails, then whatever was copied is removed again.
+fn copy_path_with(
+    src: &Path,
+    dst: &Path,
+    copy_file: &dyn Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    if fs::symlink_metadata(dst).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", dst.display()),
+        ));
+    }
+    if let Err(err) = copy_tree(src, dst, copy_file) {
+        let _ = remove_tree(dst);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Copy `src` to `dst`, recursively if `src` is a directory, using the given
+/// function to copy each file. Symlinks are copied as symlinks on Unix and
+/// followed elsewhere.
+fn copy_tree(
+    src: &Path,
+    dst: &Path,
+    copy_file: &dyn Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    for result in WalkDir::new(src) {
+        let entry = result?;
+        // Joining an empty path would add a trailing slash, which names a
+        // directory.
+        let to = match entry.path().strip_prefix(src) {
+            Ok(rest) if !rest.as_os_str().is_empty() => dst.join(rest),
+            _ => dst.to_path_buf(),
+        };
+        let ty = entry.file_type();
+        if ty.is_dir() {
+            fs::create_dir(&to)?;
+        } else if ty.is_symlink() {
+            copy_symlink(entry.path(), &to, copy_file)?;
+        } else {
+            copy_file(entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the file `src` to `dst`, along with the given metadata.
+fn copy_file(src: &Path, dst: &Path, what: Preserve) -> io::Result<()> {
+    fs::copy(src, dst)?;
+    preserve_metadata(src, dst, what)
+}
+
+/// Clone the file `src` to `dst` with the `FICLONE` ioctl, along with the
+/// given metadata. `dst` is removed again if cloning fails.
+#[cfg(target_os = "linux")]
+fn reflink_file(src: &Path, dst: &Path, what: Preserve) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let from = fs::File::open(src)?;
+    let to = fs::File::options().write(true).create_new(true).open(dst)?;
+    // SAFETY: Both file descriptors are open for as long as the call runs,
+    // and FICLONE takes the source descriptor as its argument.
+    let rc = unsafe {
+        libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd())
+    };
+    if rc == -1 {
+        let err = io::Error::last_os_error();
+        drop(to);
+        let _ = fs::remove_file(dst);
+        return Err(err);
+    }
+    drop(to);
+    preserve_metadata(src, dst, what)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_file(_: &Path, _: &Path, _: Preserve) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
+
+/// Returns true if the given error from `reflink_file` means that the file
+/// couldn't be cloned, but could still be copied. This is the case when the
+/// file system doesn't support cloning, or when the source and destination
+/// are on different file systems.
+fn is_reflink_unsupported(err: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        matches!(
+            err.raw_os_error(),
+            Some(
+                libc::EOPNOTSUPP
+                    | libc::EXDEV
+                    | libc::EINVAL
+                    | libc::ENOTTY
+                    | libc::ENOSYS
+            )
+        )
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        err.kind() == io::ErrorKind::Unsupported
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(
+    src: &Path,
+    dst: &Path,
+    _: &dyn Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dst)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(
+    src: &Path,
+    dst: &Path,
+    copy_file: &dyn Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    copy_file(src, dst)
+}
+
+/// Remove the given path, recursively if it's a directory. Symlinks are
+/// removed, never followed.
+pub(crate) fn remove_tree(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Return the total size in bytes of the files at the given path, which is
+/// recursive if the path is a directory. Entries that can't be read are
+/// ignored.
+fn disk_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|result| result.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|md| md.len())
+        .sum()
+}
+
+/// Returns true if and only if `src` and `dst` are in the same directory and
+/// their file names differ only by case.
+pub(crate) fn is_case_only(src: &Path, dst: &Path) -> bool {
+    if src.parent() != dst.parent() {
+        return false;
+    }
+    let (src, dst) = match (src.file_name(), dst.file_name()) {
+        (Some(src), Some(dst)) => {
+            (src.to_string_lossy(), dst.to_string_lossy())
+        }
+        _ => return false,
+    };
+    src != dst && src.to_lowercase() == dst.to_lowercase()
+}
+
+/// Make the given path absolute and resolve all symlinks in it, like
+/// `Path::canonicalize`, except the path doesn't need to exist. Only its
+/// nearest existing ancestor is resolved, and any remaining components are
+/// appended as is.
+fn canonicalize_lenient(path: &Path) -> io::Result<PathBuf> {
+    let mut existing = path;
+    let mut rest = vec![];
+    while !existing.as_os_str().is_empty() && !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    if existing.as_os_str().is_empty() {
+        existing = Path::new(".");
+    }
+    let mut canonical = existing.canonicalize()?;
+    for name in rest.into_iter().rev() {
+        canonical.push(name);
+    }
+    Ok(canonical)
+}
+
+/// Return a path that, when interpreted relative to the directory `from`,
+/// refers to `to`. Both paths must be absolute and free of symlinks.
+///
+/// If no such path exists, e.g., because the paths have different prefixes
+/// on Windows, then `None` is returned.
+fn relative_path(from: &Path, to: &Path) -> Option<PathBuf> {
+    let mut from_it = from.components().peekable();
+    let mut to_it = to.components().peekable();
+    match (from_it.peek(), to_it.peek()) {
+        (Some(Component::Prefix(a)), Some(Component::Prefix(b))) if a != b => {
+            return None
+        }
+        _ => {}
+    }
+    while let (Some(a), Some(b)) = (from_it.peek(), to_it.peek()) {
+        if a != b {
+            break;
+        }
+        from_it.next();
+        to_it.next();
+    }
+    let mut relative = PathBuf::new();
+    for _ in from_it {
+        relative.push("..");
+    }
+    for c in to_it {
+        relative.push(c);
+    }
+    Some(relative)
+}
+
+/// Returns true if and only if the two paths, after following symlinks,
+/// refer to the same file. If either path can't be read, then this returns
+/// false.
+#[cfg(unix)]
+pub(crate) fn same_file(path1: &Path, path2: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(path1), fs::metadata(path2)) {
+        (Ok(md1), Ok(md2)) => md1.dev() == md2.dev() && md1.ino() == md2.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn same_file(path1: &Path, path2: &Path) -> bool {
+    match (path1.canonicalize(), path2.canonicalize()) {
+        (Ok(path1), Ok(path2)) => path1 == path2,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    use imdb_index::{
+        MediaEntity, Query, Scored, SearchResults, Searcher, Title, TitleKind,
+    };
+
+    use super::{
+        canonical_title, copy_path, disk_size, find_in_use, is_case_only,
+        is_reflink_unsupported, is_release_token, is_subtitle_dir,
+        move_across_devices, prefers_original_title, reflink_path,
+        relative_path, relax_if_empty, show_choice_key, sort_title,
+        CandidateAny, CandidateEpisode, CandidateKind, CandidatePath, Choice,
+        ChoiceKey, Companions, Decision, DestLayout, DirFilter, DirPerms,
+        ExtensionCase, ExtensionPolicy, FileClass, IgnorePatterns, Naming,
+        QueryFixups, RenameAction, RenameProposal, RenamerBuilder,
+        SkippedCheck, SourceTags,
+    };
+    use crate::explain::{Outcome, Parse};
+    use crate::fscheck::FsConstraints;
+    use crate::journal::Journal;
+    use crate::origin::{OriginFormat, ORIGINS_TSV};
+    use crate::preserve::Preserve;
+    use crate::util::tests::{entity, small_index, small_searcher, TempDir};
+
+    #[test]
+    fn relative_paths() {
+        let rel =
+            |from: &str, to: &str| relative_path(from.as_ref(), to.as_ref());
+        assert_eq!(rel("/a/b", "/a/b/c.mkv"), Some(PathBuf::from("c.mkv")));
+        assert_eq!(
+            rel("/a/links", "/a/media/c.mkv"),
+            Some(PathBuf::from("../media/c.mkv"))
+        );
+        assert_eq!(
+            rel("/x/y/z", "/a/c.mkv"),
+            Some(PathBuf::from("../../../a/c.mkv"))
+        );
+        assert_eq!(rel("/", "/c.mkv"), Some(PathBuf::from("c.mkv")));
+        assert_eq!(rel("/a/b", "/a"), Some(PathBuf::from("..")));
+    }
+
+    #[test]
+    fn preserve_times_noop() {
+        let tmp = TempDir::new("imdb-rename-test-preserve-noop").unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let actions = [
+            ("rename", RenameAction::Rename),
+            ("hardlink", RenameAction::Hardlink),
+            #[cfg(unix)]
+            ("symlink", RenameAction::Symlink),
+        ];
+        for (name, action) in actions {
+            let src = tmp.path().join(format!("{}.mkv", name));
+            fs::write(&src, name).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&src)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let perms = fs::Permissions::from_mode(0o640);
+                fs::set_permissions(&src, perms).unwrap();
+            }
+
+            let dst_name = format!("{} (1999).mkv", name);
+            let mut p = RenameProposal::new(
+                src,
+                tmp.path(),
+                &dst_name,
+                action,
+                entity(),
+            );
+            p.preserve_times = true;
+            p.rename().unwrap();
+            let md = fs::metadata(tmp.path().join(&dst_name)).unwrap();
+            assert_eq!(md.modified().unwrap(), old, "{}", name);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                assert_eq!(md.permissions().mode() & 0o777, 0o640, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn rebase() {
+        let dir = RenameProposal::new(
+            PathBuf::from("tv/Show.S01"),
+            Path::new("tv"),
+            "Show (1989)",
+            RenameAction::Rename,
+            entity(),
+        );
+        let rebased = |src: &str, dst_parent: &str, action| {
+            let mut p = RenameProposal::new(
+                PathBuf::from(src),
+                Path::new(dst_parent),
+                "S01E01.mkv",
+                action,
+                entity(),
+            );
+            p.rebase(&dir);
+            (p.src().to_path_buf(), p.dst().to_path_buf())
+        };
+        assert_eq!(
+            rebased(
+                "tv/Show.S01/ep1.mkv",
+                "tv/Show.S01",
+                RenameAction::Rename
+            ),
+            (
+                PathBuf::from("tv/Show (1989)/ep1.mkv"),
+                PathBuf::from("tv/Show (1989)/S01E01.mkv"),
+            )
+        );
+        // Paths are compared by component, not by prefix.
+        assert_eq!(
+            rebased("tv/Show.S01x/ep1.mkv", "tv", RenameAction::Rename),
+            (
+                PathBuf::from("tv/Show.S01x/ep1.mkv"),
+                PathBuf::from("tv/S01E01.mkv"),
+            )
+        );
+        // Only the destination may be inside the renamed directory.
+        assert_eq!(
+            rebased("ep1.mkv", "tv/Show.S01", RenameAction::Symlink),
+            (
+                PathBuf::from("ep1.mkv"),
+                PathBuf::from("tv/Show (1989)/S01E01.mkv"),
+            )
+        );
+
+        // Links never move anything, so they never cause a rebase.
+        let mut link = dir.clone();
+        link.action = RenameAction::Symlink;
+        let mut p = RenameProposal::new(
+            PathBuf::from("tv/Show.S01/ep1.mkv"),
+            Path::new("tv/Show.S01"),
+            "S01E01.mkv",
+            RenameAction::Rename,
+            entity(),
+        );
+        p.rebase(&link);
+        assert_eq!(p.src(), Path::new("tv/Show.S01/ep1.mkv"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_relative() {
+        let tmp = TempDir::new("imdb-rename-test-symlink").unwrap();
+        let media = tmp.path().join("media").join("movies");
+        let links = tmp.path().join("links");
+        fs::create_dir_all(&media).unwrap();
+        fs::create_dir_all(&links).unwrap();
+        let src = media.join("thor.ragnarok.2017.mkv");
+        fs::write(&src, "thor").unwrap();
+
+        let mut p = RenameProposal::new(
+            src.clone(),
+            &links,
+            "Thor: Ragnarok (2017).mkv",
+            RenameAction::Symlink,
+            entity(),
+        );
+        p.make_symlink_relative().unwrap();
+        p.rename().unwrap();
+
+        let dst = links.join("Thor: Ragnarok (2017).mkv");
+        let target = fs::read_link(&dst).unwrap();
+        assert_eq!(
+            target,
+            Path::new("../media/movies/thor.ragnarok.2017.mkv")
+        );
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "thor");
+
+        // The link should keep working if the whole tree moves.
+        let moved = tmp.path().join("moved");
+        fs::create_dir(&moved).unwrap();
+        fs::rename(tmp.path().join("media"), moved.join("media")).unwrap();
+        fs::rename(&links, moved.join("links")).unwrap();
+        let dst = moved.join("links").join("Thor: Ragnarok (2017).mkv");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "thor");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_relative_missing_dest_dir() {
+        let tmp = TempDir::new("imdb-rename-test-symlink").unwrap();
+        let src = tmp.path().join("a.mkv");
+        fs::write(&src, "a").unwrap();
+        let dest = tmp.path().join("x").join("y");
+
+        let mut p = RenameProposal::new(
+            src,
+            &dest,
+            "A (2000).mkv",
+            RenameAction::Symlink,
+            entity(),
+        );
+        p.make_symlink_relative().unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        p.rename().unwrap();
+        let dst = dest.join("A (2000).mkv");
+        assert_eq!(fs::read_link(&dst).unwrap(), Path::new("../../a.mkv"));
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "a");
+    }
+
+    #[test]
+    fn existing_identical_hardlink() {
+        let tmp = TempDir::new("imdb-rename-test-existing").unwrap();
+        let src = tmp.path().join("a.mkv");
+        fs::write(&src, "a").unwrap();
+        let p = RenameProposal::new(
+            src.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Hardlink,
+            entity(),
+        );
+        assert!(!p.is_done().unwrap());
+        p.rename().unwrap();
+        assert!(p.is_done().unwrap());
+    }
+
+    #[test]
+    fn existing_different_file() {
+        let tmp = TempDir::new("imdb-rename-test-existing").unwrap();
+        let src = tmp.path().join("a.mkv");
+        fs::write(&src, "a").unwrap();
+        fs::write(tmp.path().join("A (2000).mkv"), "a").unwrap();
+        for action in [RenameAction::Hardlink, RenameAction::Rename] {
+            let p = RenameProposal::new(
+                src.clone(),
+                tmp.path(),
+                "A (2000).mkv",
+                action,
+                entity(),
+            );
+            assert!(p.is_done().is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_symlink() {
+        let tmp = TempDir::new("imdb-rename-test-existing").unwrap();
+        let src = tmp.path().join("a.mkv");
+        fs::write(&src, "a").unwrap();
+        let p = RenameProposal::new(
+            src.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Symlink,
+            entity(),
+        );
+        p.rename().unwrap();
+        assert!(p.is_done().unwrap());
+
+        // A hardlink proposal should not accept a symlink, even though it
+        // resolves to the same file.
+        let p = RenameProposal::new(
+            src.clone(),
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Hardlink,
+            entity(),
+        );
+        assert!(p.is_done().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_dangling_symlink() {
+        let tmp = TempDir::new("imdb-rename-test-existing").unwrap();
+        let src = tmp.path().join("a.mkv");
+        fs::write(&src, "a").unwrap();
+        let dst = tmp.path().join("A (2000).mkv");
+        std::os::unix::fs::symlink(tmp.path().join("gone.mkv"), &dst).unwrap();
+        let p = RenameProposal::new(
+            src,
+            tmp.path(),
+            "A (2000).mkv",
+            RenameAction::Symlink,
+            entity(),
+        );
+        assert!(p.is_done().is_err());
+    }
+
+    #[test]
+    fn in_use_size_changes() {
+        let tmp = TempDir::new("imdb-rename-test-in-use").unwrap();
+        let growing = tmp.path().join("growing.mkv");
+        let done = tmp.path().join("done.mkv");
+        fs::write(&growing, "a").unwrap();
+        fs::write(&done, "done").unwrap();
+
+        let paths = vec![growing.clone(), done.clone(), tmp.path().into()];
+        let in_use = find_in_use(&paths, Duration::from_secs(0), |_| {
+            fs::write(&growing, "ab").unwrap();
+        });
+        assert!(in_use.contains_key(&growing));
+        assert!(!in_use.contains_key(&done));
+        assert!(!in_use.contains_key(tmp.path()));
+
+        let in_use = find_in_use(&paths, Duration::from_secs(0), |_| {});
+        assert!(in_use.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn in_use_open_for_writing() {
+        let tmp = TempDir::new("imdb-rename-test-in-use").unwrap();
+        let path = tmp.path().join("a.mkv");
+        fs::write(&path, "a").unwrap();
+
+        let paths = vec![path.clone()];
+        let file = fs::File::options().append(true).open(&path).unwrap();
+        let in_use = find_in_use(&paths, Duration::from_secs(0), |_| {});
+        assert!(in_use.contains_key(&path));
+        drop(file);
+
+        let _file = fs::File::open(&path).unwrap();
+        let in_use = find_in_use(&paths, Duration::from_secs(0), |_| {});
+        assert!(in_use.is_empty());
+    }
+
+    #[test]
+    fn case_only() {
+        let is =
+            |src: &str, dst: &str| is_case_only(src.as_ref(), dst.as_ref());
+        assert!(is("/a/the matrix (1999).mkv", "/a/The Matrix (1999).mkv"));
+        assert!(is("x.MKV", "x.mkv"));
+        assert!(!is("/a/x.mkv", "/a/x.mkv"));
+        assert!(!is("/a/x.mkv", "/b/X.mkv"));
+        assert!(!is("/a/x.mkv", "/a/y.mkv"));
+    }
+
+    #[test]
+    fn case_only_rename() {
+        let tmp = TempDir::new("imdb-rename-test-case").unwrap();
+        let src = tmp.path().join("the matrix (1999).mkv");
+        fs::write(&src, "matrix").unwrap();
+        // This test is only meaningful on a case insensitive file system,
+        // which we detect by looking up the source with different case.
+        if !tmp.path().join("THE MATRIX (1999).mkv").exists() {
+            return;
+        }
+
+        let p = RenameProposal::new(
+            src,
+            tmp.path(),
+            "The Matrix (1999).mkv",
+            RenameAction::Rename,
+            entity(),
+        );
+        assert!(!p.is_done().unwrap());
+        p.rename().unwrap();
+        let names: Vec<_> = fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["The Matrix (1999).mkv"]);
+    }
+
+    #[test]
+    fn cross_device_move() {
+        let tmp = TempDir::new("imdb-rename-test-cross-device").unwrap();
+        let src = tmp.path().join("Dune.1984");
+        fs::create_dir_all(src.join("extras")).unwrap();
+        fs::write(src.join("Dune.1984.mkv"), "dune").unwrap();
+        fs::write(src.join("extras").join("trailer.mkv"), "trailer").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("Dune.1984.mkv", src.join("link.mkv"))
+            .unwrap();
+
+        // Renames within one file system never copy.
+        let mut p = RenameProposal::new(
+            src.clone(),
+            tmp.path(),
+            "Dune (1984)",
+            RenameAction::Rename,
+            entity(),
+        );
+        p.detect_cross_device();
+        assert_eq!(p.cross_device(), None);
+        assert_eq!(disk_size(&src), 11);
+
+        // Moving works on one file system too, which lets us test it.
+        let file = tmp.path().join("file.mkv");
+        fs::write(&file, "file").unwrap();
+        move_across_devices(&file, &tmp.path().join("File.mkv")).unwrap();
+        assert!(!file.exists() && tmp.path().join("File.mkv").exists());
+        let dst = tmp.path().join("Dune (1984)");
+        move_across_devices(&src, &dst).unwrap();
+        assert!(!src.exists());
+        assert_eq!(
+            fs::read_to_string(dst.join("Dune.1984.mkv")).unwrap(),
+            "dune"
+        );
+        assert_eq!(
+            fs::read_to_string(dst.join("extras").join("trailer.mkv"))
+                .unwrap(),
+            "trailer"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(dst.join("link.mkv")).unwrap(),
+            Path::new("Dune.1984.mkv")
+        );
+
+        // An existing destination is never touched.
+        let other = tmp.path().join("other.mkv");
+        fs::write(&other, "other").unwrap();
+        assert!(move_across_devices(&other, &dst).is_err());
+        assert!(other.exists() && dst.join("Dune.1984.mkv").exists());
+    }
+
+    #[test]
+    fn copy() {
+        let tmp = TempDir::new("imdb-rename-test-copy").unwrap();
+        let src = tmp.path().join("Dune.1984");
+        fs::create_dir_all(src.join("extras")).unwrap();
+        fs::write(src.join("Dune.1984.mkv"), "dune").unwrap();
+        fs::write(src.join("extras").join("trailer.mkv"), "trailer").unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(src.join("Dune.1984.mkv"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let p = RenameProposal::new(
+            src.join("Dune.1984.mkv"),
+            tmp.path(),
+            "Dune (1984).mkv",
+            RenameAction::Copy,
+            entity(),
+        );
+        p.rename().unwrap();
+        let dst = tmp.path().join("Dune (1984).mkv");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "dune");
+        assert_eq!(fs::metadata(&dst).unwrap().modified().unwrap(), old);
+        assert!(src.join("Dune.1984.mkv").exists());
+        // Copies never overwrite anything.
+        assert!(p.is_done().is_err());
+        assert!(p.rename().is_err());
+
+        let dst = tmp.path().join("Dune (1984)");
+        copy_path(&src, &dst, Preserve::Modified).unwrap();
+        assert_eq!(
+            fs::read_to_string(dst.join("extras").join("trailer.mkv"))
+                .unwrap(),
+            "trailer"
+        );
+        assert!(src.join("extras").join("trailer.mkv").exists());
+
+        // A socket can't be copied, so copying a directory with one fails
+        // midway, and the partial copy is removed.
+        #[cfg(unix)]
+        {
+            let _socket = std::os::unix::net::UnixListener::bind(
+                src.join("extras").join("socket"),
+            )
+            .unwrap();
+            let partial = tmp.path().join("Partial");
+            assert!(copy_path(&src, &partial, Preserve::Modified).is_err());
+            assert!(!partial.exists());
+        }
+    }
+
+    #[test]
+    fn reflink() {
+        let tmp = TempDir::new("imdb-rename-test-reflink").unwrap();
+        let src = tmp.path().join("Dune.1984");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("Dune.1984.mkv"), "dune").unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(src.join("Dune.1984.mkv"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        // Whether cloning works depends on the file system that runs the
+        // tests, but falling back to a copy always works.
+        let dst = tmp.path().join("Dune (1984)");
+        reflink_path(&src, &dst, true, Preserve::Modified).unwrap();
+        let file = dst.join("Dune.1984.mkv");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "dune");
+        assert_eq!(fs::metadata(&file).unwrap().modified().unwrap(), old);
+        assert!(reflink_path(&src, &dst, true, Preserve::Modified).is_err());
+
+        // Without a fallback, a failed clone leaves nothing behind.
+        let dst = tmp.path().join("Dune (1984).mkv");
+        match reflink_path(
+            &src.join("Dune.1984.mkv"),
+            &dst,
+            false,
+            Preserve::Modified,
+        ) {
+            Ok(()) => assert_eq!(fs::read_to_string(&dst).unwrap(), "dune"),
+            Err(err) => {
+                assert!(is_reflink_unsupported(&err), "{}", err);
+                assert!(!dst.exists());
+            }
+        }
+    }
+
+    #[test]
+    fn dest_layout_mixed() {
+        // Each input is a show/season for episodes, or None for movies.
+        let inputs: &[Option<(&str, u32)>] = &[
+            None,
+            Some(("The Simpsons", 1)),
+            Some(("AC/DC: Live", 12)),
+            None,
+        ];
+        let route = |layout: &DestLayout| -> Vec<Option<PathBuf>> {
+            inputs
+                .iter()
+                .map(|input| match *input {
+                    None => layout.movie_dir(),
+                    Some((show, season)) => layout.episode_dir(show, season),
+                })
+                .collect()
+        };
+
+        let layout = DestLayout {
+            movies_dir: Some(PathBuf::from("/media/movies")),
+            tv_dir: Some(PathBuf::from("/media/tv")),
+            show_years: false,
+        };
+        assert_eq!(
+            route(&layout),
+            vec![
+                Some(PathBuf::from("/media/movies")),
+                Some(PathBuf::from("/media/tv/The Simpsons/Season 01")),
+                Some(PathBuf::from("/media/tv/AC_DC: Live/Season 12")),
+                Some(PathBuf::from("/media/movies")),
+            ]
+        );
+
+        // Kinds without a directory fall back to the default destination.
+        let layout = DestLayout {
+            movies_dir: None,
+            tv_dir: Some(PathBuf::from("/media/tv")),
+            show_years: false,
+        };
+        assert_eq!(
+            route(&layout),
+            vec![
+                None,
+                Some(PathBuf::from("/media/tv/The Simpsons/Season 01")),
+                Some(PathBuf::from("/media/tv/AC_DC: Live/Season 12")),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn show_dir_names() {
+        let show = |kind, start_year, end_year| Title {
+            id: "tt1".into(),
+            kind,
+            title: "Show".into(),
+            original_title: "Show".into(),
+            is_adult: false,
+            start_year,
+            end_year,
+            runtime_minutes: None,
+            genres: String::new(),
+        };
+        let airing = show(TitleKind::TVSeries, Some(1989), None);
+        let ended = show(TitleKind::TVSeries, Some(1999), Some(2013));
+        let mini = show(TitleKind::TVMiniSeries, Some(2019), Some(2019));
+        let unknown = show(TitleKind::TVSeries, None, None);
+
+        let mut layout = DestLayout::default();
+        assert_eq!(layout.show_dir_name(&ended), "Show");
+
+        layout.show_years = true;
+        assert_eq!(layout.show_dir_name(&airing), "Show (1989–)");
+        assert_eq!(layout.show_dir_name(&ended), "Show (1999–2013)");
+        assert_eq!(layout.show_dir_name(&mini), "Show (2019)");
+        assert_eq!(layout.show_dir_name(&unknown), "Show");
+    }
+
+    #[test]
+    fn mkdir() {
+        let tmp = TempDir::new("imdb-rename-test-mkdir").unwrap();
+        let src = tmp.path().join("the.simpsons.s01e01.mkv");
+        fs::write(&src, "a").unwrap();
+        let parent =
+            tmp.path().join("tv").join("The Simpsons").join("Season 01");
+
+        let mut p = RenameProposal::new(
+            src,
+            &parent,
+            "S01E01 - Simpsons Roasting on an Open Fire.mkv",
+            RenameAction::Hardlink,
+            entity(),
+        );
+        assert!(p.rename().is_err());
+        p.mkdir = true;
+
+        // When the link can't be made, the directories created for it are
+        // removed again.
+        let mut missing = p.clone();
+        missing.src = tmp.path().join("missing.mkv");
+        assert!(missing.rename().is_err());
+        assert!(!tmp.path().join("tv").exists());
+
+        let executed = p.rename().unwrap();
+        assert!(p.is_done().unwrap());
+        assert_eq!(
+            executed.dirs,
+            vec![
+                tmp.path().join("tv"),
+                tmp.path().join("tv").join("The Simpsons"),
+                parent.clone(),
+            ]
+        );
+
+        // Rolling back removes the link and then the directories created
+        // for it, since they're left empty.
+        let mut journal = Journal::new();
+        journal.record(&p, &executed);
+        assert!(journal.rollback(None).is_empty());
+        assert!(!tmp.path().join("tv").exists());
+
+        // But a directory that gained other files since is kept, along with
+        // its parents.
+        let executed = p.rename().unwrap();
+        let other = parent.join("S01E02.mkv");
+        fs::write(&other, "b").unwrap();
+        let mut journal = Journal::new();
+        journal.record(&p, &executed);
+        assert!(journal.rollback(None).is_empty());
+        assert!(!p.dst().exists() && other.exists());
+    }
+
+    #[test]
+    fn write_origin() {
+        let tmp = TempDir::new("imdb-rename-test-write-origin").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let (src_dir, dst_dir) =
+            (tmp.path().join("src"), tmp.path().join("dst"));
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::create_dir_all(&dst_dir).unwrap();
+        let src = src_dir.join("Dune.1984.1080p.mkv");
+        let dst = dst_dir.join("Dune (1984).mkv");
+        let sidecar = dst_dir.join("Dune (1984).mkv.origin.txt");
+        let mut execute = |format, action| {
+            let renamer = RenamerBuilder::new()
+                .min_votes(0)
+                .write_origin(format)
+                .build()
+                .unwrap();
+            let (proposals, _) = renamer
+                .propose_pure(
+                    &mut searcher,
+                    std::slice::from_ref(&src),
+                    Some(dst_dir.clone()),
+                    action,
+                )
+                .unwrap();
+            assert_eq!(proposals.len(), 1);
+            let executed = proposals[0].rename().unwrap();
+            (proposals[0].clone(), executed)
+        };
+
+        // Links don't record origins unless asked to.
+        fs::write(&src, "a").unwrap();
+        execute(None, RenameAction::Hardlink);
+        assert!(dst.exists());
+        assert_eq!(fs::read_dir(&dst_dir).unwrap().count(), 1);
+        fs::remove_file(&dst).unwrap();
+
+        execute(Some(OriginFormat::Sidecar), RenameAction::Hardlink);
+        let contents = fs::read_to_string(&sidecar).unwrap();
+        assert!(contents.starts_with("original: Dune.1984.1080p.mkv\n"));
+        assert!(contents.contains("\nid: tt0087182\n"), "{}", contents);
+        fs::remove_file(&dst).unwrap();
+        fs::remove_file(&sidecar).unwrap();
+
+        let (p, executed) =
+            execute(Some(OriginFormat::Tsv), RenameAction::Rename);
+        assert!(!src.exists() && dst.exists());
+        let tsv = dst_dir.join(ORIGINS_TSV);
+        assert_eq!(executed.origin.as_deref(), Some(tsv.as_path()));
+        let rows = fs::read_to_string(&tsv).unwrap();
+        assert!(
+            rows.contains(
+                "\nDune.1984.1080p.mkv\tDune (1984).mkv\ttt0087182\t"
+            ),
+            "{}",
+            rows
+        );
+
+        // Rolling back the rename removes its origin, which leaves nothing
+        // behind in the destination directory.
+        let mut journal = Journal::new();
+        journal.record(&p, &executed);
+        assert!(journal.rollback(None).is_empty());
+        assert!(src.exists() && !dst.exists());
+        assert_eq!(fs::read_dir(&dst_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn write_nfo() {
+        let tmp = TempDir::new("imdb-rename-test-write-nfo").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let dir = tmp.path().join("media");
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("Dune.1984.1080p.mkv");
+        let sub = dir.join("Dune.1984.1080p.srt");
+        let dst = dir.join("Dune (1984).mkv");
+        let nfo = dir.join("Dune (1984).nfo");
+        let mut execute = |force| {
+            fs::write(&src, "a").unwrap();
+            fs::write(&sub, "b").unwrap();
+            let renamer = RenamerBuilder::new()
+                .min_votes(0)
+                .write_nfo(true)
+                .force_nfo(force)
+                .build()
+                .unwrap();
+            let paths = vec![src.clone(), sub.clone()];
+            let proposals = renamer
+                .propose(&mut searcher, &paths, None, RenameAction::Rename)
+                .unwrap();
+            assert_eq!(proposals.len(), 2);
+            for p in &proposals {
+                p.rename().unwrap();
+            }
+        };
+
+        // Only the video gets an NFO file, not its subtitle.
+        execute(false);
+        let xml = fs::read_to_string(&nfo).unwrap();
+        assert!(xml.contains("\n<movie>\n  <title>Dune</title>\n"), "{}", xml);
+        assert!(xml.contains(">tt0087182</uniqueid>"), "{}", xml);
+        assert!(dir.join("Dune (1984).srt").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        let reset = || {
+            fs::remove_file(&dst).unwrap();
+            fs::remove_file(dir.join("Dune (1984).srt")).unwrap();
+        };
+        reset();
+        fs::write(&nfo, "mine").unwrap();
+        execute(false);
+        assert_eq!(fs::read_to_string(&nfo).unwrap(), "mine");
+        reset();
+        execute(true);
+        assert_eq!(fs::read_to_string(&nfo).unwrap(), xml);
+    }
+
+    #[test]
+    fn write_nfo_release_nfo() {
+        let tmp = TempDir::new("imdb-rename-test-write-nfo").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let dir = tmp.path().join("media");
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("Dune.1984.1080p.mkv");
+        let release_nfo = dir.join("Dune.1984.1080p.nfo");
+        fs::write(&src, "a").unwrap();
+        fs::write(&release_nfo, "release").unwrap();
+
+        // The release's NFO file is renamed to where the video's NFO file
+        // would be written, so it takes that file's place.
+        let renamer = RenamerBuilder::new()
+            .min_votes(0)
+            .write_nfo(true)
+            .build()
+            .unwrap();
+        let paths = vec![src.clone(), release_nfo.clone()];
+        let proposals = renamer
+            .propose(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap();
+        assert_eq!(proposals.len(), 2);
+        for p in &proposals {
+            let executed = p.rename().unwrap();
+            assert_eq!(executed.nfo, None);
+            assert!(executed.warnings.is_empty(), "{:?}", executed.warnings);
+        }
+        let nfo = dir.join("Dune (1984).nfo");
+        assert_eq!(fs::read_to_string(nfo).unwrap(), "release");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn ignore_patterns() {
+        let patterns = IgnorePatterns::parse(
+            "# comment\n\
+             \n\
+             Extras/\n\
+             *.mkv\n\
+             !WIP.mkv\n\
+             /sample\n\
+             Show/**/proof\n",
+            false,
+        )
+        .unwrap();
+        let ignored =
+            |path: &str, is_dir| patterns.is_ignored(Path::new(path), is_dir);
+        assert_eq!(ignored("m/extras", true), Some(true));
+        assert_eq!(ignored("m/extras", false), None);
+        // The last matching line wins.
+        assert_eq!(ignored("m/a.mkv", false), Some(true));
+        assert_eq!(ignored("m/wip.mkv", false), Some(false));
+        // A leading '/' anchors a glob to the ignore file's directory.
+        assert_eq!(ignored("Sample", true), Some(true));
+        assert_eq!(ignored("m/Sample", true), None);
+        assert_eq!(ignored("show/Season 01/Proof", true), Some(true));
+        assert_eq!(ignored("# comment", false), None);
+        assert_eq!(ignored("m/other.srt", false), None);
+
+        let patterns = IgnorePatterns::parse("*.mkv\n", true).unwrap();
+        assert_eq!(patterns.is_ignored(Path::new("a.MKV"), false), None);
+
+        let err = IgnorePatterns::parse("ok\n[abc\n", false).unwrap_err();
+        assert!(err.to_string().starts_with("line 2: "), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignore_patterns_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let patterns = IgnorePatterns::parse("*.mkv\n", false).unwrap();
+        let path = Path::new(OsStr::from_bytes(b"caf\xe9.mkv"));
+        assert_eq!(patterns.is_ignored(path, false), Some(true));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dir_perms() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let tmp = TempDir::new("imdb-rename-test-dir-perms").unwrap();
+        let existing = tmp.path().join("tv");
+        fs::create_dir(&existing).unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let md = fs::metadata(&existing).unwrap();
+        let perms = DirPerms {
+            mode: Some(0o2750),
+            uid: Some(md.uid()),
+            gid: Some(md.gid()),
+        };
+        let parent = existing.join("The Simpsons").join("Season 01");
+        let src = tmp.path().join("the.simpsons.s01e01.mkv");
+        fs::write(&src, "a").unwrap();
+        let mut p = RenameProposal::new(
+            src,
+            &parent,
+            "S01E01 - Simpsons Roasting on an Open Fire.mkv",
+            RenameAction::Hardlink,
+            entity(),
+        );
+        p.mkdir = true;
+        p.dir_perms = perms;
+        p.rename().unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().mode() & 0o7777;
+        assert_eq!(mode(&existing), 0o755);
+        assert_eq!(mode(&existing.join("The Simpsons")), 0o2750);
+        assert_eq!(mode(&parent), 0o2750);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dir_perms_parse() {
+        assert_eq!(DirPerms::parse_mode("2775").unwrap(), 0o2775);
+        assert_eq!(DirPerms::parse_mode("750").unwrap(), 0o750);
+        assert!(DirPerms::parse_mode("789").is_err());
+        assert!(DirPerms::parse_mode("17777").is_err());
+        assert!(DirPerms::parse_mode("").is_err());
+
+        let owner = |s| DirPerms::parse_owner(s).unwrap();
+        assert_eq!(owner("0:0"), (Some(0), Some(0)));
+        assert_eq!(owner("root"), (Some(0), None));
+        assert_eq!(owner(":0"), (None, Some(0)));
+        assert_eq!(owner("1000:"), (Some(1000), None));
+        assert!(DirPerms::parse_owner(":").is_err());
+        assert!(DirPerms::parse_owner("no-such-user-imdb-rename").is_err());
+    }
+
+    #[test]
+    fn votes_per_kind() {
+        let any = CandidateAny { title: "thor.ragnarok".into(), year: 2017 };
+        let ep = CandidateEpisode {
+            tvshow_title: "the.simpsons".into(),
+            season: 1,
+            episode: 2,
+            episode_title: String::new(),
+        };
+
+        let renamer = RenamerBuilder::new()
+            .min_votes_movie(5000)
+            .min_votes_tvshow(50)
+            .build()
+            .unwrap();
+        let q = renamer.any_query(&any).to_string();
+        assert!(q.contains(" {votes:5000-} "), "{}", q);
+        assert!(q.ends_with(" thor ragnarok"), "{}", q);
+        let q = renamer.tvshow_query(&ep).to_string();
+        assert!(q.contains(" {votes:50-} "), "{}", q);
+        assert!(q.ends_with(" the simpsons"), "{}", q);
+
+        let renamer = RenamerBuilder::new().min_votes(7).build().unwrap();
+        let q = renamer.any_query(&any).to_string();
+        assert!(q.contains(" {votes:7-} "), "{}", q);
+        let q = renamer.tvshow_query(&ep).to_string();
+        assert!(q.contains(" {votes:7-} "), "{}", q);
+    }
+
+    #[test]
+    fn special_episode_title() {
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let path = Path::new("Show.S00E01.Christmas.Special.mkv");
+        let ep = match renamer.candidate(path).unwrap().kind {
+            CandidateKind::Episode(ep) => ep,
+            kind => panic!("expected episode, got {:?}", kind),
+        };
+        assert_eq!((ep.season, ep.episode), (0, 1));
+        assert_eq!(ep.episode_title, ".Christmas.Special");
+    }
+
+    #[test]
+    fn extension_policy() {
+        let mut policy = ExtensionPolicy::default();
+        assert_eq!(policy.apply("MKV"), "MKV");
+
+        policy.case = ExtensionCase::Lower;
+        assert_eq!(policy.apply("MKV"), "mkv");
+
+        policy.aliases.insert("mpeg4".to_string(), "mp4".to_string());
+        policy.aliases.insert("jpeg".to_string(), "jpg".to_string());
+        assert_eq!(policy.apply("mpeg4"), "mp4");
+        assert_eq!(policy.apply("JPEG"), "jpg");
+
+        policy.case = ExtensionCase::Keep;
+        assert_eq!(policy.apply("MPEG4"), "mp4");
+        assert_eq!(policy.apply("AVI"), "AVI");
+    }
+
+    #[test]
+    fn imdb_name_extensions() {
+        let tmp = TempDir::new("imdb-rename-test-ext").unwrap();
+        let mut idx = small_index(&tmp);
+        let ent = idx.entity("tt0701059").unwrap().unwrap();
+        let mut naming = Naming::default();
+        naming.extensions.case = ExtensionCase::Lower;
+        naming
+            .extensions
+            .aliases
+            .insert("mpeg4".to_string(), "mp4".to_string());
+
+        let name = |path: &str| {
+            CandidatePath::from_path(Path::new(path))
+                .unwrap()
+                .imdb_name(&ent, &naming)
+        };
+        assert_eq!(name("bart.MKV"), "S01E05 - Bart the General.mkv");
+        assert_eq!(name("bart.MPEG4"), "S01E05 - Bart the General.mp4");
+        // Extensionless files and dotfiles are left alone.
+        assert_eq!(name("bart"), "S01E05 - Bart the General");
+        assert_eq!(name(".MKV"), "S01E05 - Bart the General.MKV");
+    }
+
+    #[test]
+    fn source_tags() {
+        let tags = SourceTags::parse("Movie.2019.1080p.WEB.x264-GROUP");
+        assert_eq!(tags.resolution.as_deref(), Some("1080p"));
+        assert_eq!(tags.source.as_deref(), Some("WEB"));
+        assert_eq!(tags.codec.as_deref(), Some("x264"));
+        assert_eq!(tags.hdr, None);
+        assert_eq!(tags.group.as_deref(), Some("GROUP"));
+        assert_eq!(tags.format().unwrap(), "1080p WEB x264");
+
+        let tags = SourceTags::parse("Movie 2019 2160p HDR10 HEVC");
+        assert_eq!(tags.format().unwrap(), "2160p HEVC HDR10");
+        assert_eq!(tags.group, None);
+
+        // Partial tags.
+        let tags = SourceTags::parse("movie.2019.720p");
+        assert_eq!(tags.format().unwrap(), "720p");
+        // A trailing tag isn't a group.
+        let tags = SourceTags::parse("movie-2019-BluRay-x264");
+        assert_eq!(tags.format().unwrap(), "BluRay x264");
+        assert_eq!(tags.group, None);
+
+        // No tags at all, so a dash doesn't imply a group either.
+        let tags = SourceTags::parse("Spider-Man.2002");
+        assert_eq!(tags, SourceTags::default());
+        assert_eq!(tags.format(), None);
+    }
+
+    #[test]
+    fn imdb_name_tags() {
+        let tmp = TempDir::new("imdb-rename-test-tags").unwrap();
+        let mut idx = small_index(&tmp);
+        let ent = idx.entity("tt0701059").unwrap().unwrap();
+        let naming = Naming { keep_tags: true, ..Naming::default() };
+
+        let name = |path: &str| {
+            CandidatePath::from_path(Path::new(path))
+                .unwrap()
+                .imdb_name(&ent, &naming)
+        };
+        assert_eq!(
+            name("simpsons.s01e05.1080p.WEB.x264-GRP.mkv"),
+            "S01E05 - Bart the General [1080p WEB x264].mkv"
+        );
+        assert_eq!(
+            name("simpsons.s01e05.mkv"),
+            "S01E05 - Bart the General.mkv"
+        );
+    }
+
+    #[test]
+    fn sort_titles() {
+        assert_eq!(sort_title("The Matrix"), "Matrix, The");
+        assert_eq!(sort_title("A Beautiful Mind"), "Beautiful Mind, A");
+        assert_eq!(sort_title("An American Tail"), "American Tail, An");
+        assert_eq!(sort_title("Le Samouraï"), "Samouraï, Le");
+        assert_eq!(sort_title("L'Avenir"), "Avenir, L'");
+        assert_eq!(sort_title("Das Boot"), "Boot, Das");
+        assert_eq!(
+            sort_title("El Laberinto del Fauno"),
+            "Laberinto del Fauno, El"
+        );
+        assert_eq!(sort_title("Los Olvidados"), "Olvidados, Los");
+
+        // Articles must be whole words.
+        assert_eq!(sort_title("Them"), "Them");
+        assert_eq!(sort_title("Them!"), "Them!");
+        assert_eq!(sort_title("Theodore Rex"), "Theodore Rex");
+        assert_eq!(sort_title("Anastasia"), "Anastasia");
+        assert_eq!(sort_title("Don't Look Up"), "Don't Look Up");
+        // The English verb, not the German article.
+        assert_eq!(sort_title("Die Hard"), "Die Hard");
+        // Nothing but an article.
+        assert_eq!(sort_title("The"), "The");
+        assert_eq!(sort_title("L'"), "L'");
+    }
+
+    #[test]
+    fn relaxed_query() {
+        let strict = Query::new().name("foo").kind(TitleKind::Movie);
+        let relaxed = Query::new().name("foo");
+
+        let mut searched = vec![];
+        let (query, is_relaxed) =
+            relax_if_empty(strict.clone(), relaxed.clone(), |q| {
+                searched.push(q.clone());
+                let mut results = SearchResults::new();
+                if searched.len() > 1 {
+                    results.push(Scored::new(()));
+                }
+                Ok(results)
+            })
+            .unwrap();
+        assert!(is_relaxed);
+        assert_eq!(query, relaxed);
+        assert_eq!(searched, vec![strict.clone(), relaxed.clone()]);
+
+        let mut searched = vec![];
+        let (query, is_relaxed) =
+            relax_if_empty(strict.clone(), relaxed.clone(), |q| {
+                searched.push(q.clone());
+                let mut results = SearchResults::new();
+                results.push(Scored::new(()));
+                Ok(results)
+            })
+            .unwrap();
+        assert!(!is_relaxed);
+        assert_eq!(query, strict);
+        assert_eq!(searched, vec![strict]);
+    }
+
+    #[test]
+    fn original_title_preference() {
+        let movie = |title: &str, original: &str| {
+            let ent: MediaEntity = serde_json::from_value(serde_json::json!({
+                "title": {
+                    "tconst": "tt0000001",
+                    "titleType": "movie",
+                    "primaryTitle": title,
+                    "originalTitle": original,
+                    "isAdult": 0,
+                    "startYear": 2001,
+                    "endYear": null,
+                    "runtimeMinutes": null,
+                    "genres": "",
+                },
+                "episode": null,
+                "rating": null,
+            }))
+            .unwrap();
+            Scored::new(ent)
+        };
+        let amelie = movie("Amélie", "Le fabuleux destin d'Amélie Poulain");
+        let results = vec![movie("Amelia", "Amelia"), amelie];
+        assert!(prefers_original_title(
+            "le fabuleux destin d'amelie poulain",
+            &results
+        ));
+        assert!(!prefers_original_title("amelie", &results));
+        assert!(!prefers_original_title("amelia", &results[..1]));
+    }
+
+    #[test]
+    fn non_interactive() {
+        let tmp = TempDir::new("imdb-rename-test-non-interactive").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new()
+            .min_votes(0)
+            .non_interactive(true)
+            .build()
+            .unwrap();
+        // An episode named like a movie is only found by a relaxed search,
+        // which always prompts, so it's skipped instead. A movie that stands
+        // out is still chosen.
+        let paths = vec![
+            PathBuf::from("Bart.Gets.an.F.1990.mkv"),
+            PathBuf::from("Dune.1984.1080p.mkv"),
+        ];
+        let (proposals, _) = renamer
+            .propose_pure(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap();
+        assert_eq!(proposals.len(), 1);
+        assert_eq!(proposals[0].dst(), Path::new("Dune (1984).mkv"));
+        assert_eq!(renamer.undecided_count(), 1);
+        assert_eq!(renamer.skip_count(), 1);
+    }
+
+    /// Tests renames of titles in the small data set that are known to cause
+    /// trouble.
+    #[test]
+    fn fixture_hazards() {
+        let tmp = TempDir::new("imdb-rename-test-hazards").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new().min_votes(0).build().unwrap();
+        let mut propose = |path: &str| -> Option<String> {
+            let (proposals, _) = renamer
+                .propose_pure(
+                    &mut searcher,
+                    &[PathBuf::from(path)],
+                    None,
+                    RenameAction::Rename,
+                )
+                .unwrap();
+            proposals.first().map(|p| p.dst().display().to_string())
+        };
+
+        // Titles that differ only by year.
+        assert_eq!(propose("Dune.1984.1080p.mkv").unwrap(), "Dune (1984).mkv");
+        assert_eq!(propose("Dune.2021.2160p.mkv").unwrap(), "Dune (2021).mkv");
+        assert_eq!(propose("Dune.2020.mkv"), None);
+        // Titles containing a path separator.
+        assert_eq!(
+            propose("Face.Off.1997.mkv").unwrap(),
+            "Face_Off (1997).mkv"
+        );
+        assert_eq!(
+            propose("Fahrenheit.9.11.2004.mkv").unwrap(),
+            "Fahrenheit 9_11 (2004).mkv"
+        );
+        // Diacritics, found through an alternate name without them.
+        assert_eq!(propose("Amelie.2001.mkv").unwrap(), "Amélie (2001).mkv");
+        // Release names with both the English and original names.
+        assert_eq!(
+            propose("Strangelove.AKA.Le.Fabuleux.Destin.2001.mkv").unwrap(),
+            "Amélie (2001).mkv"
+        );
+        // Specials in season 0.
+        assert_eq!(
+            propose("the.simpsons.s00e01.mkv").unwrap(),
+            "S00E01 - The Simpsons Christmas Special.mkv"
+        );
+        // Titles too long to be a file name are proposed as is, and then
+        // truncated when destinations are checked.
+        let long = propose("Night.of.the.Day.of.the.Dawn.2011.mkv").unwrap();
+        assert!(long.len() > 255, "{} bytes", long.len());
+        let long = FsConstraints::posix("ext4", 255).sanitize(&long);
+        assert!(long.len() <= 255, "{} bytes", long.len());
+        assert!(long.starts_with("Night of the Day of the Dawn"), "{}", long);
+        assert!(long.ends_with(".mkv"), "{}", long);
+        // Adult titles are never indexed.
+        assert_eq!(propose("Homer.Alone.1992.mkv"), None);
+    }
+
+    #[test]
+    fn episode_overrides() {
+        let tmp = TempDir::new("imdb-rename-test-episode-overrides").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let show = searcher.index().entity("tt0096697").unwrap().unwrap();
+        let mut propose = |builder: &RenamerBuilder, path: &str| {
+            let renamer = builder.build().unwrap();
+            let (proposals, _) = renamer
+                .propose_pure(
+                    &mut searcher,
+                    &[PathBuf::from(path)],
+                    None,
+                    RenameAction::Rename,
+                )
+                .unwrap();
+            proposals.first().map(|p| p.dst().display().to_string())
+        };
+
+        // A name without episode information becomes an episode of the
+        // forced show.
+        let mut builder = RenamerBuilder::new();
+        builder.force(show).season_override(1).episode_override(3);
+        assert_eq!(
+            propose(&builder, "stray.mkv").unwrap(),
+            "S01E03 - Homer's Odyssey.mkv"
+        );
+        // Overrides take priority over what's found in the name.
+        assert_eq!(
+            propose(&builder, "the.simpsons.s02e05.mkv").unwrap(),
+            "S01E03 - Homer's Odyssey.mkv"
+        );
+
+        // Overriding one number keeps the other from the name.
+        let mut builder = RenamerBuilder::new();
+        builder.min_votes(0).episode_override(2);
+        assert_eq!(
+            propose(&builder, "the.simpsons.s01e01.mkv").unwrap(),
+            "S01E02 - Bart the Genius.mkv"
+        );
+        // ... but both are needed when the name has neither.
+        let renamer = builder.build().unwrap();
+        assert!(renamer.candidate(Path::new("stray.mkv")).is_err());
+    }
+
+    /// Tests that an episode whose TV show is missing from the data set is
+    /// skipped when it would be moved into its show's directory.
+    #[test]
+    fn orphaned_episode() {
+        let tmp = TempDir::new("imdb-rename-test-orphan").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let orphan = searcher.index().entity("tt9900004").unwrap().unwrap();
+        assert_eq!(orphan.episode().unwrap().tvshow_id, "tt9900005");
+
+        let renamer = RenamerBuilder::new()
+            .force(orphan)
+            .tv_dir("/media/tv")
+            .explain(true)
+            .build()
+            .unwrap();
+        let (proposals, _) = renamer
+            .propose_pure(
+                &mut searcher,
+                &[PathBuf::from("lost.mkv")],
+                None,
+                RenameAction::Rename,
+            )
+            .unwrap();
+        assert!(proposals.is_empty());
+        assert_eq!(renamer.skip_count(), 1);
+        match renamer.explanations()[0].outcome {
+            Outcome::Skipped { ref reason } => {
+                assert!(reason.contains("missing from dataset"), "{}", reason)
+            }
+            ref outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn parallel_matches_serial() {
+        let tmp = TempDir::new("imdb-rename-test-parallel").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let mut paths = vec![];
+        for season in 1..=2 {
+            for episode in 1..=6 {
+                paths.push(PathBuf::from(format!(
+                    "the.simpsons.s{:02}e{:02}.mkv",
+                    season, episode
+                )));
+            }
+        }
+        // This one can't be found, and should be skipped either way.
+        paths.push(PathBuf::from("the.simpsons.s01e99.mkv"));
+
+        let propose = |searcher: &mut Searcher, threads: usize| {
+            let renamer = RenamerBuilder::new()
+                .min_votes(0)
+                .threads(threads)
+                .explain(true)
+                .build()
+                .unwrap();
+            let proposals = renamer
+                .propose(searcher, &paths, None, RenameAction::Rename)
+                .unwrap();
+            let dsts: Vec<PathBuf> =
+                proposals.iter().map(|p| p.dst().to_path_buf()).collect();
+            // Which file's search ends up being reused by the others isn't
+            // deterministic with many threads, so only outcomes are compared.
+            let outcomes: Vec<String> = renamer
+                .explanations()
+                .iter()
+                .map(|e| serde_json::to_string(&e.outcome).unwrap())
+                .collect();
+            (dsts, outcomes)
+        };
+        let serial = propose(&mut searcher, 1);
+        let parallel = propose(&mut searcher, 4);
+        assert_eq!(serial.0.len(), 12);
+        assert_eq!(
+            serial.0[0],
+            PathBuf::from("S01E01 - Simpsons Roasting on an Open Fire.mkv")
+        );
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn pure_skips_destination_checks() {
+        let tmp = TempDir::new("imdb-rename-test-pure").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new().min_votes(0).build().unwrap();
+        let dest = tmp.path().join("does").join("not").join("exist");
+        let paths = vec![
+            PathBuf::from("the.simpsons.s01e01.mkv"),
+            PathBuf::from("the.simpsons.s01e02.mkv"),
+        ];
+
+        let err = renamer
+            .propose(
+                &mut searcher,
+                &paths,
+                Some(dest.clone()),
+                RenameAction::Rename,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+
+        let (proposals, skipped) = renamer
+            .propose_pure(
+                &mut searcher,
+                &paths,
+                Some(dest.clone()),
+                RenameAction::Rename,
+            )
+            .unwrap();
+        let dsts: Vec<&Path> = proposals.iter().map(|p| p.dst()).collect();
+        let dst1 = dest.join("S01E01 - Simpsons Roasting on an Open Fire.mkv");
+        let dst2 = dest.join("S01E02 - Bart the Genius.mkv");
+        assert_eq!(dsts, vec![&*dst1, &*dst2]);
+        assert_eq!(
+            skipped,
+            vec![
+                SkippedCheck::DestinationDir(dest.clone()),
+                SkippedCheck::DestinationExists(dst1.clone()),
+                SkippedCheck::DestinationDir(dest.clone()),
+                SkippedCheck::DestinationExists(dst2.clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn matched_summaries() {
+        let tmp = TempDir::new("imdb-rename-test-matched").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new().min_votes(0).build().unwrap();
+        let paths = vec![
+            PathBuf::from("the.simpsons.s01e01.mkv"),
+            PathBuf::from("the.simpsons.s01e02.mkv"),
+        ];
+        let (proposals, _) = renamer
+            .propose_pure(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap();
+        let summaries: Vec<String> =
+            proposals.iter().map(|p| p.matched().to_string()).collect();
+        assert_eq!(proposals[1].score(), Some(1.0));
+        assert_eq!(proposals[1].entity().title().id, "tt0756593");
+        // The second episode reuses the choice of TV show, and its score.
+        assert_eq!(
+            summaries,
+            vec![
+                "tt0348034 tvEpisode 1989, rated 8.1 (9800 votes), \
+                 score 1.000",
+                "tt0756593 tvEpisode 1990, rated 7.6 (6200 votes), \
+                 score 1.000",
+            ]
+        );
+
+        // An override isn't searched for, so there's no score.
+        let show = searcher.index().entity("tt0096697").unwrap().unwrap();
+        let renamer =
+            RenamerBuilder::new().min_votes(0).force(show).build().unwrap();
+        let (proposals, _) = renamer
+            .propose_pure(
+                &mut searcher,
+                &paths[..1],
+                None,
+                RenameAction::Rename,
+            )
+            .unwrap();
+        let matched = proposals[0].matched();
+        assert_eq!(matched.id, "tt0348034");
+        assert_eq!(matched.score, None);
+        assert!(matched.to_string().ends_with(", not searched"));
+        let ent = proposals[0].entity();
+        assert_eq!(ent.title().id, "tt0348034");
+        assert_eq!(ent.episode().unwrap().tvshow_id, "tt0096697");
+        assert_eq!(proposals[0].score(), None);
+    }
+
+    #[test]
+    fn pure_duplicates() {
+        let tmp = TempDir::new("imdb-rename-test-pure-dupes").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new().min_votes(0).build().unwrap();
+        let paths = vec![
+            PathBuf::from("the.simpsons.s01e01.mkv"),
+            PathBuf::from("The.Simpsons.S01E01.720p.mkv"),
+        ];
+        let err = renamer
+            .propose_pure(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap_err();
+        assert!(err.to_string().contains("duplicate"), "{}", err);
+    }
+
+    #[test]
+    fn pure_symlink() {
+        let tmp = TempDir::new("imdb-rename-test-pure-symlink").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new()
+            .min_votes(0)
+            .symlink_relative(true)
+            .skip_in_use(true)
+            .mkdir(true)
+            .build()
+            .unwrap();
+        let src = PathBuf::from("the.simpsons.s01e01.mkv");
+        let dest = PathBuf::from("/library/tv");
+        let (proposals, skipped) = renamer
+            .propose_pure(
+                &mut searcher,
+                std::slice::from_ref(&src),
+                Some(dest.clone()),
+                RenameAction::Symlink,
+            )
+            .unwrap();
+        assert_eq!(proposals.len(), 1);
+        // Without consulting the file system, the source stays relative.
+        assert_eq!(proposals[0].src(), src);
+        let dst = dest.join("S01E01 - Simpsons Roasting on an Open Fire.mkv");
+        assert_eq!(proposals[0].dst(), dst);
+        assert_eq!(
+            skipped,
+            vec![
+                SkippedCheck::InUse(src.clone()),
+                SkippedCheck::AbsoluteSource(src.clone()),
+                SkippedCheck::RelativeSymlink(dst.clone()),
+                SkippedCheck::DestinationExists(dst.clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_fixups() {
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let query = |name: &str| renamer.name_query(name).to_string();
+        let tests = [
+            ("Spider-Man", "Spider Man"),
+            ("Dont.Look.Up", "Dont Look Up"),
+            ("Ocean's.Eleven", "Oceans Eleven"),
+            ("Ocean\u{2019}s Eleven", "Oceans Eleven"),
+            ("The_Lord_of_the_Rings", "The Lord of the Rings"),
+            ("Fast.&.Furious", "Fast and Furious"),
+            ("Fast&Furious", "Fast and Furious"),
+            ("Mission - Impossible", "Mission Impossible"),
+            ("  Face__Off. ", "Face Off"),
+        ];
+        for &(name, expected) in &tests {
+            let q = query(name);
+            assert!(q.ends_with(&format!("}} {}", expected)), "{}", q);
+        }
+
+        let renamer = RenamerBuilder::new()
+            .query_fixups(QueryFixups::none())
+            .build()
+            .unwrap();
+        let q = renamer.name_query("Spider-Man's_Day  &.Night").to_string();
+        assert!(q.ends_with("} Spider-Man's_Day & Night"), "{}", q);
+
+        let renamer = RenamerBuilder::new().build().unwrap();
+        assert_eq!(
+            renamer.name_query("Oldboy.AKA.Oldeuboi."),
+            Query::new().name("Oldboy").name_or("Oldeuboi")
+        );
+        assert_eq!(
+            renamer.name_query("Oldboy aka - Oldeuboi"),
+            Query::new().name("Oldboy").name_or("Oldeuboi")
+        );
+        assert_eq!(renamer.name_query("Kaka"), Query::new().name("Kaka"));
+
+        let fixups = QueryFixups { hyphens: false, ..QueryFixups::default() };
+        assert_eq!(fixups.apply("Spider-Man's"), "Spider-Mans");
+    }
+
+    #[test]
+    fn canonical_titles() {
+        let variants = [
+            "Show Name",
+            "Show.Name.",
+            "Show Name -",
+            "Show.Name.-.",
+            "Show Name - ",
+            "  Show   Name  ",
+            "Show Name (",
+            "Show.Name.[",
+            "Show Name _",
+            "- Show Name -",
+        ];
+        for fixups in [QueryFixups::default(), QueryFixups::none()] {
+            let renamer =
+                RenamerBuilder::new().query_fixups(fixups).build().unwrap();
+            for name in &variants {
+                assert_eq!(
+                    renamer.name_query(name),
+                    Query::new().name("Show Name"),
+                    "{:?}",
+                    name
+                );
+            }
+        }
+        // Brackets are only trimmed when they open at the end, since a
+        // closed pair is usually part of the title.
+        assert_eq!(canonical_title("Show (US) -"), "Show (US)");
+        assert_eq!(canonical_title("-_-"), "");
+
+        // The messy names of episode files of one show share their queries.
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let names = [
+            "Show.Name.S01E01.mkv",
+            "Show Name - S01E02.mkv",
+            "Show_Name_S01E03.mkv",
+            "Show Name [S01E04].mkv",
+        ];
+        let queries: HashSet<Query> = names
+            .iter()
+            .map(|name| {
+                match renamer.candidate(Path::new(name)).unwrap().kind {
+                    CandidateKind::Episode(ep) => renamer.tvshow_query(&ep),
+                    kind => panic!("{} is not an episode: {:?}", name, kind),
+                }
+            })
+            .collect();
+        assert_eq!(queries.len(), 1, "{:?}", queries);
+    }
+
+    #[test]
+    fn recursive_children_first() {
+        let tmp = TempDir::new("imdb-rename-test-recursive").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new().min_votes(0).build().unwrap();
+        let root = tmp.path().join("media");
+        let dir = root.join("Dune.1984.1080p");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("dune.1984.mkv"), "").unwrap();
+
+        // Directories come before their children when walked, but children
+        // must be renamed first.
+        let paths = vec![dir.clone(), dir.join("dune.1984.mkv")];
+        let proposals = renamer
+            .propose(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap();
+        let pairs: Vec<(&Path, &Path)> =
+            proposals.iter().map(|p| (p.src(), p.dst())).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (&*dir.join("dune.1984.mkv"), &*dir.join("Dune (1984).mkv")),
+                (&*dir, &*root.join("Dune (1984)")),
+            ]
+        );
+        for p in &proposals {
+            p.rename().unwrap();
+        }
+        assert!(root.join("Dune (1984)").join("Dune (1984).mkv").is_file());
+    }
+
+    #[test]
+    fn explain() {
+        let tmp = TempDir::new("imdb-rename-test-explain").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new().explain(true).build().unwrap();
+        let paths = vec![
+            PathBuf::from("notes.txt"),
+            PathBuf::from("bart.the.genius.s01e01.mkv"),
+        ];
+        renamer
+            .propose(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap();
+
+        let exps = renamer.explanations();
+        assert_eq!(exps.len(), 2);
+        assert!(exps[0].parse.is_none());
+        assert!(matches!(exps[0].outcome, Outcome::Skipped { .. }));
+
+        match exps[1].parse {
+            Some(Parse::Episode { season: 1, episode: 1, .. }) => {}
+            ref parse => panic!("unexpected parse: {:?}", parse),
+        }
+        // No TV show is called "bart the genius," so the search comes up
+        // empty.
+        let search = &exps[1].searches[0];
+        assert_eq!(search.purpose, "tvshow for episode");
+        assert!(search.query.as_ref().unwrap().contains("{tvSeries}"));
+        assert_eq!(search.total, 0);
+        assert_eq!(search.chosen, None);
+        assert_eq!(search.reason, "no results");
+        // ... because every episode in the name index has the wrong kind,
+        // which the name index drops before they become candidates.
+        let diag = search.diagnostics.as_ref().unwrap();
+        assert_eq!(diag.candidates, 0);
+        match exps[1].outcome {
+            Outcome::Skipped { ref reason } => {
+                assert!(reason.starts_with("error searching for"))
+            }
+            ref outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn special_queries() {
+        let tmp = TempDir::new("imdb-rename-test-special").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let tvshow = Title {
+            id: "tt0096697".into(),
+            kind: TitleKind::TVSeries,
+            title: "The Simpsons".into(),
+            original_title: "The Simpsons".into(),
+            is_adult: false,
+            start_year: Some(1989),
+            end_year: None,
+            runtime_minutes: None,
+            genres: String::new(),
+        };
+        let ep = CandidateEpisode {
+            tvshow_title: "the.simpsons.".into(),
+            season: 0,
+            episode: 1,
+            episode_title: ".treehouse.of.horror".into(),
+        };
+
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let (episodes, specials) = renamer.special_queries(&tvshow, &ep);
+        let results = renamer.search(&mut searcher, &episodes).unwrap();
+        let top = &results.as_slice()[0];
+        assert_eq!(top.value().title().title, "Treehouse of Horror");
+        assert!(specials.to_string().ends_with("{tvSpecial} The Simpsons"));
+    }
+
+    #[test]
+    fn grouped_show_prompts() {
+        let tmp = TempDir::new("imdb-rename-test-grouped").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let dir = tmp.path().join("downloads");
+        fs::create_dir(&dir).unwrap();
+        let patterns = [
+            "the.simpsons.s{s}e{e}.mkv",
+            "The Simpsons - {e} - S{s}E{e}.mkv",
+            "The.Simpsons.Ep.{e}.S{s}E{e}.mkv",
+            "The Simpsons E{e} S{s}E{e}.mkv",
+            "The Simpsons Episode {e} S{s}E{e}.mkv",
+        ];
+        let episodes =
+            (1..=13).map(|e| (1, e)).chain((1..=11).map(|e| (2, e)));
+        let mut paths = vec![];
+        for (n, (season, episode)) in episodes.enumerate() {
+            let name = patterns[n % patterns.len()]
+                .replace("{s}", &format!("{:02}", season))
+                .replace("{e}", &format!("{:02}", episode));
+            fs::write(dir.join(&name), "").unwrap();
+            paths.push(dir.join(name));
+        }
+        assert_eq!(paths.len(), 24);
+
+        let renamer = RenamerBuilder::new().explain(true).build().unwrap();
+        let keys: HashSet<ChoiceKey> = paths
+            .iter()
+            .map(|path| match renamer.candidate(path).unwrap().kind {
+                CandidateKind::Episode(ep) => show_choice_key(
+                    &renamer.tvshow_query(&ep),
+                    &ep.tvshow_title,
+                    ep.episode,
+                ),
+                kind => {
+                    panic!("{} is not an episode: {:?}", path.display(), kind)
+                }
+            })
+            .collect();
+        assert_eq!(keys.len(), 1, "{:?}", keys);
+
+        // Answering the prompt for the first file answers it for all of
+        // them. Prompts can't be answered in tests, so the answer is given
+        // up front.
+        let key = keys.into_iter().next().unwrap();
+        assert_eq!(key.title.as_deref(), Some("the simpsons"));
+        let show = searcher.index().entity("tt0096697").unwrap().unwrap();
+        renamer.choose_cache.lock().unwrap().insert(
+            key,
+            Choice { ent: show, score: None, prompted: true, files: 0 },
+        );
+        let (proposals, _) = renamer
+            .propose_pure(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap();
+        assert_eq!(proposals.len(), 24);
+        assert!(proposals[0]
+            .dst()
+            .ends_with("S01E01 - Simpsons Roasting on an Open Fire.mkv"));
+        for exp in renamer.explanations() {
+            assert_eq!(
+                exp.searches[0].reason,
+                "same choice as an earlier file",
+                "{}",
+                exp.path.display()
+            );
+        }
+        assert_eq!(
+            renamer.grouped_choices(),
+            vec![
+                "The Simpsons (tt0096697) for 'the simpsons': applied to \
+                 24 files"
+            ],
+        );
+        // Choosing each episode of the show doesn't need a prompt, but the
+        // show itself did, so every proposal counts as prompted.
+        let stats = renamer.match_stats();
+        assert_eq!(stats.len(), 24);
+        assert!(stats.iter().all(|s| s.decision == Decision::Prompted));
+    }
+
+    #[test]
+    fn match_stats_of_returned_proposals() {
+        let tmp = TempDir::new("imdb-rename-test-match-stats").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let renamer = RenamerBuilder::new().min_votes(0).build().unwrap();
+        // The first file already has the proposed name, so its proposal is
+        // dropped and not counted.
+        let paths = vec![
+            PathBuf::from("Dune (1984).mkv"),
+            PathBuf::from("Dune.1984.1080p.mkv"),
+        ];
+        let (proposals, _) = renamer
+            .propose_pure(&mut searcher, &paths, None, RenameAction::Rename)
+            .unwrap();
+        assert_eq!(proposals.len(), 1);
+        let stats = renamer.match_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].decision, Decision::Automatic);
+        assert_eq!(stats[0].score, proposals[0].score());
+    }
+
+    #[test]
+    fn show_choice_keys() {
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let key = |path: &str| -> ChoiceKey {
+            match renamer.candidate(Path::new(path)).unwrap().kind {
+                CandidateKind::Episode(ep) => show_choice_key(
+                    &renamer.tvshow_query(&ep),
+                    &ep.tvshow_title,
+                    ep.episode,
+                ),
+                kind => panic!("{} is not an episode: {:?}", path, kind),
+            }
+        };
+        let title = |path: &str| key(path).title.unwrap();
+        assert_eq!(title("The Simpsons S01E01.mkv"), "the simpsons");
+        assert_eq!(title("The.Simpsons - 02 - S01E02.mkv"), "the simpsons");
+        assert_eq!(title("the simpsons E04 S01E04.mkv"), "the simpsons");
+        assert_eq!(title("The Simpsons Episode 7 S01E07.mkv"), "the simpsons");
+        assert_eq!(title("The Simpsons Ep.7 S01E07.mkv"), "the simpsons");
+        assert_eq!(title("The Simpsons - 7 - S01E07.mkv"), "the simpsons");
+        // Numbers that aren't marked as the episode number are part of the
+        // title, and the whole name is never dropped.
+        assert_eq!(title("The 4400 S01E02.mkv"), "the 4400");
+        assert_eq!(title("The 4400 02 S01E02.mkv"), "the 4400");
+        assert_eq!(title("9-1-1 S01E01.mkv"), "9-1-1");
+        assert_eq!(title("24 S01E24.mkv"), "24");
+        assert_eq!(title("Show 7 S01E07.mkv"), "show 7");
+        assert_ne!(key("Show 2 S01E02.mkv"), key("Show S01E02.mkv"));
+        assert_ne!(key("9-1-1 S01E01.mkv"), key("9-1 S01E01.mkv"));
+        // The rest of the query is kept apart from the title, so different
+        // queries with the same title never share a choice.
+        assert_eq!(key("Show S01E02.mkv"), key("Show - 03 - S01E03.mkv"));
+        for path in [
+            "Show.Name.S01E02.mkv",
+            "Show Name - S01E02.mkv",
+            "Show_Name_-_S01E02.mkv",
+            "Show  Name (S01E02).mkv",
+            "- Show Name - 02 - S01E02.mkv",
+        ] {
+            assert_eq!(title(path), "show name", "{}", path);
+        }
+        assert_ne!(
+            key("Show S01E02.mkv"),
+            ChoiceKey::exact(&Query::new().name("show"))
+        );
+    }
+
+    #[test]
+    fn season_from_dir() {
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let episode = |path: &str| -> Option<(String, u32, u32)> {
+            match renamer.candidate(Path::new(path)).unwrap().kind {
+                CandidateKind::Episode(ep) => {
+                    Some((ep.tvshow_title, ep.season, ep.episode))
+                }
+                _ => None,
+            }
+        };
+        let ep = |show: &str, season, episode| {
+            Some((show.to_string(), season, episode))
+        };
+
+        assert_eq!(
+            episode(
+                "/tv/The Simpsons/Season 2/07 - Bart vs. Thanksgiving.mkv"
+            ),
+            ep("The Simpsons", 2, 7)
+        );
+        assert_eq!(
+            episode("/tv/The Simpsons/Season 02/E07.mkv"),
+            ep("The Simpsons", 2, 7)
+        );
+        assert_eq!(
+            episode("/tv/The Simpsons/season.2/7.mkv"),
+            ep("The Simpsons", 2, 7)
+        );
+        assert_eq!(
+            episode("/tv/The Simpsons/S2/07 Bart vs. Thanksgiving.mkv"),
+            ep("The Simpsons", 2, 7)
+        );
+        assert_eq!(
+            episode("/tv/The Simpsons/Specials/01 - Christmas.mkv"),
+            ep("The Simpsons", 0, 1)
+        );
+        // The base name still takes priority when it has a season.
+        assert_eq!(
+            episode("/tv/Foo/Season 2/the.simpsons.s01e03.mkv"),
+            ep("the.simpsons.", 1, 3)
+        );
+        // Without a season directory, bare numbers aren't episodes.
+        assert_eq!(episode("/movies/12 Angry Men (1957).mkv"), None);
+        // There must be a show directory above the season directory.
+        assert_eq!(episode("/Season 2/07.mkv"), None);
+    }
+
+    #[test]
+    fn file_classes() {
+        let class = |path: &str| FileClass::from_path(Path::new(path));
+        assert_eq!(class("Thor.Ragnarok.2017.mkv"), FileClass::Video);
+        assert_eq!(class("Thor.Ragnarok.2017.MP4"), FileClass::Video);
+        assert_eq!(class("Thor.Ragnarok.2017.en.srt"), FileClass::Subtitle);
+        assert_eq!(class("Thor.Ragnarok.2017.nfo"), FileClass::Metadata);
+        assert_eq!(class("Thor.Ragnarok.2017.jpg"), FileClass::Other);
+        assert_eq!(class("setup.2017.exe"), FileClass::Other);
+        assert_eq!(class("Thor.Ragnarok.2017.txt"), FileClass::Other);
+        assert_eq!(class("Thor.Ragnarok.2017"), FileClass::Other);
+    }
+
+    #[test]
+    fn skip_by_extension() {
+        let paths: Vec<PathBuf> = vec![
+            "/m/Thor.Ragnarok.2017.mkv".into(),
+            "/m/Thor.Ragnarok.2017.en.srt".into(),
+            "/m/Thor.Ragnarok.2017.nfo".into(),
+            "/m/Thor.Ragnarok.2017.jpg".into(),
+            "/m/Other.Movie.2017.srt".into(),
+            "/x/Thor.Ragnarok.2017.srt".into(),
+        ];
+        let companions = Companions::new(&paths);
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let skipped: Vec<bool> = paths
+            .iter()
+            .map(|p| renamer.skip_reason(&companions, p).is_some())
+            .collect();
+        assert_eq!(skipped, vec![false, false, false, true, true, true]);
+    }
+
+    #[test]
+    fn subtitle_dir_companions() {
+        let paths: Vec<PathBuf> = vec![
+            "/m/Thor.Ragnarok.2017.mkv".into(),
+            "/m/Subs/Thor.Ragnarok.2017.en.srt".into(),
+            "/m/Subs/Other.Movie.2017.srt".into(),
+            "/m/Extras/Thor.Ragnarok.2017.en.srt".into(),
+            "/m/Subs/Thor.Ragnarok.2017.nfo".into(),
+        ];
+        let companions = Companions::new(&paths);
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let skipped: Vec<bool> = paths
+            .iter()
+            .map(|p| renamer.skip_reason(&companions, p).is_some())
+            .collect();
+        assert_eq!(skipped, vec![false, false, true, true, true]);
+    }
+
+    #[test]
+    fn dir_filter() {
+        let mut filter = DirFilter::new();
+        assert!(filter.is_excluded(Path::new("/m/Extras")));
+        assert!(filter.is_excluded(Path::new("/m/SAMPLE")));
+        assert!(filter.is_excluded(Path::new("Subs")));
+        assert!(!filter.is_excluded(Path::new("/m/Season 01")));
+
+        filter.include("subs").exclude("Behind The Scenes");
+        assert!(!filter.is_excluded(Path::new("/m/Subs")));
+        assert!(filter.is_excluded(Path::new("/m/behind the scenes")));
+        assert!(is_subtitle_dir(Path::new("/m/Subs")));
+        assert!(!is_subtitle_dir(Path::new("/m/Extras")));
+
+        let mut filter = DirFilter::new();
+        assert!(filter.collects_subtitles(Path::new("/m/Subs")));
+        assert!(!filter.collects_subtitles(Path::new("/m/Extras")));
+        filter.exclude("SUBS");
+        assert!(filter.is_excluded(Path::new("/m/Subs")));
+        assert!(!filter.collects_subtitles(Path::new("/m/Subs")));
+        assert!(filter.collects_subtitles(Path::new("/m/Subtitles")));
+    }
+
+    #[test]
+    fn exclude_globs() {
+        let mut filter = DirFilter::new();
+        filter
+            .exclude_glob("*.nfo")
+            .unwrap()
+            .exclude_glob("Screens/")
+            .unwrap()
+            .exclude_glob("/Show/**/sample-*.mkv")
+            .unwrap()
+            .exclude_glob("extras/**")
+            .unwrap();
+        let excluded = |path: &str, is_dir: bool| {
+            filter.is_excluded_glob(Path::new(path), is_dir)
+        };
+        // Globs without a '/' match names at any depth, case insensitively.
+        assert!(excluded("movie.nfo", false));
+        assert!(excluded("Show/Season 01/tvshow.NFO", false));
+        assert!(!excluded("movie.nfo.mkv", false));
+        // A trailing '/' only matches directories.
+        assert!(excluded("Show/screens", true));
+        assert!(!excluded("Show/screens", false));
+        // '**' matches any number of directories, including none.
+        assert!(excluded("Show/sample-1.mkv", false));
+        assert!(excluded("show/Season 01/Disc 2/SAMPLE-2.mkv", false));
+        assert!(!excluded("Other/Show/sample-1.mkv", false));
+        assert!(!excluded("Show/sample/1.mkv", false));
+        // A trailing '/**' matches what's inside a directory, but not the
+        // directory itself.
+        assert!(excluded("Extras/a/b.mkv", false));
+        assert!(!excluded("Extras", true));
+
+        let mut filter = DirFilter::new();
+        filter.exclude_glob("s[0-9][!0-9]*").unwrap();
+        assert!(filter.is_excluded_glob(Path::new("x/S1e.mkv"), false));
+        assert!(!filter.is_excluded_glob(Path::new("x/s12.mkv"), false));
+
+        let mut filter = DirFilter::new();
+        filter.exclude_glob("*.{nfo,txt}").unwrap();
+        assert!(filter.is_excluded_glob(Path::new("x/a.TXT"), false));
+        // Globs added before and after asking for case sensitivity both
+        // become case sensitive.
+        filter.case_sensitive_globs(true).exclude_glob("Proof/").unwrap();
+        assert!(!filter.is_excluded_glob(Path::new("x/a.TXT"), false));
+        assert!(filter.is_excluded_glob(Path::new("x/a.txt"), false));
+        assert!(!filter.is_excluded_glob(Path::new("x/proof"), true));
+        assert!(filter.is_excluded_glob(Path::new("x/Proof"), true));
+
+        assert!(DirFilter::new().exclude_glob("[abc").is_err());
+        assert!(DirFilter::new().exclude_glob("/").is_err());
+    }
+
+    #[test]
+    fn allowed_extensions() {
+        let mut filter = DirFilter::new();
+        assert!(filter.is_allowed_file(Path::new("notes")));
+        assert!(filter.is_allowed_file(Path::new("movie.nfo")));
+
+        filter.allow_extension("mkv").allow_extension(".SRT");
+        assert!(filter.is_allowed_file(Path::new("/m/Movie.2017.mkv")));
+        assert!(filter.is_allowed_file(Path::new("/m/Movie.2017.MKV")));
+        assert!(filter.is_allowed_file(Path::new("/m/Movie.2017.en.srt")));
+        assert!(!filter.is_allowed_file(Path::new("/m/Movie.2017.nfo")));
+        assert!(!filter.is_allowed_file(Path::new("/m/mkv")));
+        assert!(!filter.is_allowed_file(Path::new("/m/.mkv")));
+
+        filter.allow_extension("");
+        assert!(filter.is_allowed_file(Path::new("/m/Movie 2017")));
+    }
+
+    #[test]
+    fn release_tokens() {
+        let name = "Moon.2009.1080p.BluRay.DTS5.1.x264-E1";
+        assert!(is_release_token(name, name.find("DTS5").unwrap() + 3));
+        assert!(is_release_token(name, name.find("x264").unwrap()));
+        assert!(!is_release_token(name, name.find("E1").unwrap()));
+        assert!(!is_release_token("Show.S01E02", 5));
+        // Delimiters can be more than one byte long.
+        let name = "Moon\u{a0}2009\u{3000}1080p\u{3000}S01E02";
+        assert!(is_release_token(name, name.find("1080p").unwrap() + 2));
+        assert!(!is_release_token(name, name.find("S01").unwrap()));
+    }
+
+    #[test]
+    fn movies_are_not_episodes() {
+        let renamer = RenamerBuilder::new().build().unwrap();
+        let kind =
+            |path: &str| renamer.candidate(Path::new(path)).unwrap().kind;
+        let movies = [
+            "Movie.2019.2160p.x265.mkv",
+            "Moon.2009.1080p.BluRay.DTS5.1.x264-E1.mkv",
+            "The.Thing.1982.2160p.UHD.BluRay.x265.HDR.DTS5.1-E2.mkv",
+            "Sicario.2015.1080p.BluRay.x264-SPARKS.mkv",
+            "Dune.2021.2160p.WEB-DL.DDP5.1.Atmos.HDR.HEVC-E3.mkv",
+            "Heat.1995.S2019E01.mkv",
+            "Fargo.1996.S01E2500.mkv",
+        ];
+        for movie in movies {
+            match kind(movie) {
+                CandidateKind::Any(_) => {}
+                kind => panic!("{}: expected a movie, got {:?}", movie, kind),
+            }
+        }
+
+        let episodes = [
+            ("the.simpsons.s01e05.1080p.x265.mkv", 1, 5),
+            ("The Simpsons S02.E07 Bart vs. Thanksgiving.mkv", 2, 7),
+            ("The.Simpsons.S02 E07.mkv", 2, 7),
+            ("the.simpsons.DTS5.s03e04.mkv", 3, 4),
+        ];
+        for (name, season, episode) in episodes {
+            match kind(name) {
+                CandidateKind::Episode(ep) => {
+                    assert_eq!((ep.season, ep.episode), (season, episode))
+                }
+                kind => {
+                    panic!("{}: expected an episode, got {:?}", name, kind)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn combined_season_episode() {
+        let episode = |re: &str, path: &str| -> Option<(String, u32, u32)> {
+            let renamer = RenamerBuilder::new()
+                .regex_season_episode(re)
+                .build()
+                .unwrap();
+            match renamer.candidate(Path::new(path)).unwrap().kind {
+                CandidateKind::Episode(ep) => {
+                    Some((ep.tvshow_title, ep.season, ep.episode))
+                }
+                _ => None,
+            }
+        };
+
+        let ssee = r"\.(?P<season>[0-9]{2})(?P<episode>[0-9]{2})(?:\.|$)";
+        assert_eq!(
+            episode(ssee, "Show.0205.mkv"),
+            Some(("Show".to_string(), 2, 5))
+        );
+        // Separate regexes are still used when the combined one fails.
+        assert_eq!(
+            episode(ssee, "Show.S03E04.mkv"),
+            Some(("Show.".to_string(), 3, 4))
+        );
+
+        let dotted = r"[Ss](?P<season>[0-9]+)\.[Ee](?P<episode>[0-9]+)";
+        assert_eq!(
+            episode(dotted, "Show.S02.E05.mkv"),
+            Some(("Show.".to_string(), 2, 5))
+        );
+
+        let renamer = RenamerBuilder::new()
+            .regex_season_episode(r"(?P<season>[0-9]{2})")
+            .build()
+            .unwrap();
+        assert!(renamer.candidate(Path::new("Show.0205.mkv")).is_err());
     }
 }
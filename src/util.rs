@@ -1,68 +1,393 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
 use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
 
-use imdb_index::{Episode, MediaEntity, Scored, Searcher, Title};
+use anyhow::Context;
+use imdb_index::{
+    Analyzer, Episode, MediaEntity, Progress, ProgressFn, Query, Scored,
+    Searcher, Title,
+};
+use serde::Serialize;
 use tabwriter::TabWriter;
 
+use crate::events::{self, Event, Reply};
+use crate::explain::ResultTrace;
+use crate::i18n::{self, trf};
+
 /// Make a choice among the search results given.
 ///
 /// If there is no clear winner, then a prompt is shown to the end user, where
 /// they must make a selection. If a selection is absent or invalid, then an
 /// error is returned.
 ///
-/// The threshold given determines the automatic selection criteria. Namely,
-/// if the difference of scores between the first and second results is
-/// greater than or equal to the given threshold, then the first result is
-/// returned without prompted the end user.
+/// The threshold and match ratio given determine the automatic selection
+/// criteria. See `auto_choice` for details. If a highlighter is given, then
+/// it is used to highlight result titles in the prompt.
 pub fn choose(
     searcher: &mut Searcher,
     results: &[Scored<MediaEntity>],
     good_threshold: f64,
+    min_match_ratio: f64,
+    highlighter: Option<&Highlighter>,
 ) -> anyhow::Result<MediaEntity> {
     if results.is_empty() {
         anyhow::bail!("no search results available for query");
-    } else if results.len() == 1 {
-        return Ok(results[0].clone().into_value());
-    } else if (results[0].score() - results[1].score()) >= good_threshold {
-        return Ok(results[0].clone().into_value());
+    } else if let Some(i) =
+        auto_choice(results, good_threshold, min_match_ratio).index()
+    {
+        return Ok(results[i].clone().into_value());
+    }
+    match prompt_choice(searcher, results, highlighter, false)? {
+        Answer::Chosen(ent) => Ok(*ent),
+        Answer::Skip | Answer::More => {
+            unreachable!("skipping and paging are only offered in batches")
+        }
     }
+}
+
+/// The end user's answer when prompted to choose among search results.
+#[derive(Clone, Debug)]
+pub enum Answer {
+    /// The result that was chosen.
+    Chosen(Box<MediaEntity>),
+    /// Skip the file that the search was for.
+    Skip,
+    /// Show the next page of results for the same search.
+    More,
+}
 
-    write_tsv(io::stdout(), searcher, results)?;
-    let choice = read_number(1, results.len())?;
-    Ok(results[choice - 1].clone().into_value())
+/// Show the search results given to the end user and prompt them to make a
+/// selection, even if there is only one result. If a selection is absent or
+/// invalid, then an error is returned.
+///
+/// Before making a selection, the end user may also open the IMDb page of
+/// a result in their browser, or print the IMDb pages of all results.
+///
+/// When `batch` is true, the search is for one of many files being renamed,
+/// and the end user may also skip that file or ask for more results instead
+/// of making a selection.
+pub fn prompt_choice(
+    searcher: &mut Searcher,
+    results: &[Scored<MediaEntity>],
+    highlighter: Option<&Highlighter>,
+    batch: bool,
+) -> anyhow::Result<Answer> {
+    if results.is_empty() {
+        anyhow::bail!("no search results available for query");
+    }
+    write_tsv(events::human(), searcher, results, highlighter)?;
+    events::emit(&Event::Prompt {
+        batch,
+        results: results.iter().map(ResultTrace::new).collect(),
+    });
+    loop {
+        match read_prompt_command(1, results.len(), batch)? {
+            PromptCommand::Choose(choice) => {
+                let ent = results[choice - 1].clone().into_value();
+                return Ok(Answer::Chosen(Box::new(ent)));
+            }
+            PromptCommand::Skip => return Ok(Answer::Skip),
+            PromptCommand::More => return Ok(Answer::More),
+            PromptCommand::Open(choice) => {
+                let url = imdb_url(&results[choice - 1].value().title().id);
+                if let Err(err) = open_url(&url) {
+                    eprintln!("[warning] could not open {}: {:#}", url, err);
+                }
+            }
+            PromptCommand::Urls => {
+                let mut out = events::human();
+                for (i, r) in results.iter().enumerate() {
+                    let url = imdb_url(&r.value().title().id);
+                    writeln!(out, "{}\t{}", i + 1, url)?;
+                }
+            }
+        }
+    }
+}
+
+/// A command entered by the end user when prompted to choose a result.
+#[derive(Debug, PartialEq)]
+enum PromptCommand {
+    /// Choose the result with the given number.
+    Choose(usize),
+    /// Open the IMDb page of the result with the given number.
+    Open(usize),
+    /// Print the IMDb pages of every result.
+    Urls,
+    /// Skip the file being renamed. Only available in batches.
+    Skip,
+    /// Show the next page of results. Only available in batches.
+    More,
+}
+
+impl PromptCommand {
+    /// Parse a command, where result numbers must be in the given inclusive
+    /// range. The commands for skipping and paging are only recognized when
+    /// `batch` is true.
+    fn parse(
+        line: &str,
+        start: usize,
+        end: usize,
+        batch: bool,
+    ) -> anyhow::Result<PromptCommand> {
+        let line = line.trim();
+        match line {
+            "u" => return Ok(PromptCommand::Urls),
+            "s" if batch => return Ok(PromptCommand::Skip),
+            "r" if batch => return Ok(PromptCommand::More),
+            _ => {}
+        }
+        let (open, number) = match line.strip_prefix('o') {
+            None => (false, line),
+            Some(rest) => (true, rest.trim()),
+        };
+        let choice: usize = number.parse()?;
+        if choice < start || choice > end {
+            anyhow::bail!(
+                "invalid choice: {} is not in range [{}-{}]",
+                choice,
+                start,
+                end
+            );
+        }
+        Ok(if open {
+            PromptCommand::Open(choice)
+        } else {
+            PromptCommand::Choose(choice)
+        })
+    }
 }
 
-/// Reads a number from stdin in the given inclusive range.
-pub fn read_number(start: usize, end: usize) -> anyhow::Result<usize> {
+/// Reads a prompt command from stdin, where result numbers must be in the
+/// given inclusive range.
+///
+/// When events are enabled, the command is read as a JSON reply instead.
+/// See the `events` module.
+fn read_prompt_command(
+    start: usize,
+    end: usize,
+    batch: bool,
+) -> anyhow::Result<PromptCommand> {
+    if events::enabled() {
+        let line = match events::read_reply()? {
+            Reply::Choose { choice } => choice.to_string(),
+            Reply::Skip => "s".to_string(),
+            Reply::More => "r".to_string(),
+            reply => anyhow::bail!("expected a choice, but got {:?}", reply),
+        };
+        return PromptCommand::parse(&line, start, end, batch);
+    }
     let mut stdout = io::stdout();
-    write!(stdout, "Please enter your choice [{}-{}]: ", start, end)?;
+    let id = if batch { "prompt.choice-batch" } else { "prompt.choice" };
+    write!(stdout, "{}", trf(id, &[&start, &end]))?;
     stdout.flush()?;
 
     let mut response = String::new();
     io::stdin().read_line(&mut response)?;
-    let choice: usize = response.trim().parse()?;
-    if choice < start || choice > end {
-        anyhow::bail!(
-            "invalid choice: {} is not in range [{}-{}]",
-            choice,
-            start,
-            end
-        );
+    PromptCommand::parse(&response, start, end, batch)
+}
+
+/// Returns the URL of the IMDb page for the title with the given identifier.
+pub fn imdb_url(id: &str) -> String {
+    format!("https://www.imdb.com/title/{}/", id)
+}
+
+/// Open the given URL in the default browser.
+///
+/// This returns an error if no browser could be launched, e.g., on a
+/// headless machine. Callers should treat that as a warning.
+pub fn open_url(url: &str) -> anyhow::Result<()> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        // The empty argument is the window title, which `start` would
+        // otherwise take from the first quoted argument.
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else {
+        Command::new("xdg-open")
+    };
+    let status = cmd
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .context("could not launch a browser")?;
+    if !status.success() {
+        anyhow::bail!("browser launcher exited with {}", status);
     }
-    Ok(choice)
+    Ok(())
+}
+
+/// Decides whether a result should be chosen without prompting the end
+/// user, and reports why.
+///
+/// A lone result is always chosen. Otherwise, if the difference of scores
+/// between the first and second results is greater than or equal to the
+/// given threshold, then the first result is chosen. As special cases, a
+/// threshold of `0` always chooses the first result while a threshold of `1`
+/// (or more) never chooses automatically when there is more than one result.
+///
+/// Regardless of the threshold, the first result is never chosen if it
+/// matched less than `min_match_ratio` of the query's terms. See
+/// `meets_match_ratio`.
+pub fn auto_choice<T>(
+    results: &[Scored<T>],
+    good_threshold: f64,
+    min_match_ratio: f64,
+) -> AutoChoice {
+    if results.is_empty() {
+        AutoChoice::NoResults
+    } else if !meets_match_ratio(&results[0], min_match_ratio) {
+        // These unwraps are OK because a ratio is always met when the
+        // matched terms are unknown.
+        AutoChoice::MatchRatio {
+            matched: results[0].matched_terms().unwrap(),
+            total: results[0].query_terms().unwrap(),
+            ratio: results[0].match_ratio().unwrap(),
+            min: min_match_ratio,
+        }
+    } else if results.len() == 1 {
+        AutoChoice::OnlyResult
+    } else if good_threshold <= 0.0 {
+        AutoChoice::NoThreshold
+    } else if good_threshold >= 1.0 {
+        AutoChoice::MaxThreshold
+    } else {
+        AutoChoice::Margin {
+            margin: results[0].score() - results[1].score(),
+            threshold: good_threshold,
+        }
+    }
+}
+
+/// The outcome of `auto_choice`: whether the first result is chosen without
+/// prompting, and why. Its `Display` impl describes the reason.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutoChoice {
+    /// There were no results, so there's nothing to choose.
+    NoResults,
+    /// The first result matched too few of the query's terms.
+    MatchRatio {
+        /// The number of query terms that the first result matched.
+        matched: usize,
+        /// The number of terms in the query.
+        total: usize,
+        /// The ratio of the two.
+        ratio: f64,
+        /// The minimum ratio required.
+        min: f64,
+    },
+    /// There was only one result, which is chosen.
+    OnlyResult,
+    /// The threshold is `0`, so the first result is chosen.
+    NoThreshold,
+    /// The threshold is `1`, so nothing is chosen.
+    MaxThreshold,
+    /// The first result is chosen if its margin over the runner-up is at
+    /// least the threshold.
+    Margin {
+        /// The difference of scores between the first and second results.
+        margin: f64,
+        /// The threshold given to `auto_choice`.
+        threshold: f64,
+    },
+}
+
+impl AutoChoice {
+    /// Returns the index of the result that should be chosen without
+    /// prompting the end user, if any.
+    pub fn index(&self) -> Option<usize> {
+        match *self {
+            AutoChoice::OnlyResult | AutoChoice::NoThreshold => Some(0),
+            AutoChoice::Margin { margin, threshold }
+                if margin >= threshold =>
+            {
+                Some(0)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AutoChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AutoChoice::NoResults => write!(f, "no results"),
+            AutoChoice::MatchRatio { matched, total, ratio, min } => write!(
+                f,
+                "prompted: top result matched {} of {} query terms, \
+                 ratio {:.3} < {:.3}",
+                matched, total, ratio, min
+            ),
+            AutoChoice::OnlyResult => {
+                write!(f, "auto-accepted: only one result")
+            }
+            AutoChoice::NoThreshold => {
+                write!(f, "auto-accepted: margin threshold is 0")
+            }
+            AutoChoice::MaxThreshold => {
+                write!(f, "prompted: margin threshold is 1")
+            }
+            AutoChoice::Margin { margin, threshold } => {
+                if margin >= threshold {
+                    write!(
+                        f,
+                        "auto-accepted: margin {:.3} over runner-up >= {:.3}",
+                        margin, threshold
+                    )
+                } else {
+                    write!(
+                        f,
+                        "prompted: margin {:.3} over runner-up < {:.3}",
+                        margin, threshold
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if the given result matched at least `min_match_ratio` of
+/// the terms in the query that produced it.
+///
+/// A ratio of `0` (or less) is always met. Results whose matched terms
+/// aren't known, such as those of an exhaustive search, always meet it too.
+pub fn meets_match_ratio<T>(result: &Scored<T>, min_match_ratio: f64) -> bool {
+    min_match_ratio <= 0.0
+        || result.match_ratio().is_none_or(|ratio| ratio >= min_match_ratio)
 }
 
 /// Reads a yes/no answer from stdin. This is flexible and recognizes
-/// y, Y, yes, YES as 'yes' answers. Everything else is recognized as a 'no'
-/// answer.
+/// y, Y, yes, YES as 'yes' answers, along with the equivalents in the end
+/// user's language (see `i18n::is_yes`). Everything else is recognized as a
+/// 'no' answer.
+///
+/// When events are enabled, the question is emitted as an event and the
+/// answer is read as a JSON reply instead. See the `events` module.
 pub fn read_yesno(msg: &str) -> anyhow::Result<bool> {
+    if events::enabled() {
+        events::emit(&Event::Confirm { question: msg.trim() });
+        return match events::read_reply()? {
+            Reply::Yes => Ok(true),
+            Reply::No => Ok(false),
+            reply => anyhow::bail!("expected yes or no, but got {:?}", reply),
+        };
+    }
     let mut stdout = io::stdout();
     write!(stdout, "{}", msg)?;
     stdout.flush()?;
 
     let mut response = String::new();
     io::stdin().read_line(&mut response)?;
-    let answer = response.trim().to_lowercase();
-    Ok(answer == "y" || answer == "yes")
+    Ok(i18n::is_yes(&response))
 }
 
 /// Write the given result set to the given writer.
@@ -70,18 +395,32 @@ pub fn read_yesno(msg: &str) -> anyhow::Result<bool> {
 /// If a result is an episode, then the index given is used to look up relevant
 /// info about its TV show, if one could be found, and include that information
 /// in the output.
+///
+/// When the date of the IMDb data is known, it is written before the column
+/// headers, since it's the first thing to check when a title is missing.
+///
+/// If a highlighter is given, then the parts of titles that match the query
+/// are highlighted.
 pub fn write_tsv<W: io::Write>(
     wtr: W,
     searcher: &mut Searcher,
     results: &[Scored<MediaEntity>],
+    highlighter: Option<&Highlighter>,
 ) -> anyhow::Result<()> {
-    let mut wtr = TabWriter::new(wtr).minwidth(4);
+    let hl = |title: &str| match highlighter {
+        None => title.to_string(),
+        Some(h) => h.highlight(title),
+    };
+    let mut wtr = TabWriter::new(wtr).minwidth(4).ansi(true);
+    if let Some(snapshot) = searcher.index().data_snapshot() {
+        writeln!(wtr, "# IMDb data snapshot: {}", snapshot)?;
+    }
     writeln!(wtr, "#\tscore\tid\tkind\ttitle\tyear\ttv")?;
     for (i, sr) in results.iter().enumerate() {
         let (score, ent) = (sr.score(), sr.value());
         if let Some(ep) = ent.episode() {
             match searcher.index().title(&ep.tvshow_id)? {
-                None => write_tsv_title(&mut wtr, i + 1, score, ent)?,
+                None => write_tsv_title(&mut wtr, i + 1, score, ent, hl)?,
                 Some(tvshow) => {
                     write_tsv_episode(
                         &mut wtr,
@@ -90,22 +429,85 @@ pub fn write_tsv<W: io::Write>(
                         ent,
                         &tvshow,
                         ep,
+                        hl,
                     )?;
                 }
             }
         } else {
-            write_tsv_title(&mut wtr, i + 1, score, ent)?;
+            write_tsv_title(&mut wtr, i + 1, score, ent, hl)?;
         }
     }
     wtr.flush()?;
     Ok(())
 }
 
+/// Write the given result set to the given writer as a JSON array, with one
+/// object per result, in the same order as `write_tsv`.
+///
+/// Episodes include the season and episode numbers along with the identifier
+/// and title of their TV show, if it could be found.
+pub fn write_json<W: io::Write>(
+    mut wtr: W,
+    searcher: &mut Searcher,
+    results: &[Scored<MediaEntity>],
+) -> anyhow::Result<()> {
+    let mut rows = vec![];
+    for sr in results {
+        let t = sr.value().title();
+        let tv = match sr.value().episode() {
+            None => None,
+            Some(ep) => {
+                let show = searcher.index().title(&ep.tvshow_id)?;
+                Some(JsonEpisode {
+                    show_id: ep.tvshow_id.clone(),
+                    show: show.map(|show| show.title),
+                    season: ep.season,
+                    episode: ep.episode,
+                })
+            }
+        };
+        rows.push(JsonResult {
+            score: sr.score(),
+            id: &t.id,
+            kind: t.kind.to_string(),
+            title: &t.title,
+            year: t.start_year,
+            tv,
+        });
+    }
+    serde_json::to_writer(&mut wtr, &rows)?;
+    writeln!(wtr)?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A single search result written by `write_json`.
+#[derive(Debug, Serialize)]
+struct JsonResult<'a> {
+    score: f64,
+    id: &'a str,
+    kind: String,
+    title: &'a str,
+    year: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tv: Option<JsonEpisode>,
+}
+
+/// The TV show, season and episode of a search result that is an episode.
+#[derive(Debug, Serialize)]
+struct JsonEpisode {
+    show_id: String,
+    show: Option<String>,
+    season: Option<u32>,
+    episode: Option<u32>,
+}
+
 fn write_tsv_title<W: io::Write>(
     mut wtr: W,
     position: usize,
     score: f64,
     ent: &MediaEntity,
+    hl: impl Fn(&str) -> String,
 ) -> anyhow::Result<()> {
     write!(
         wtr,
@@ -114,13 +516,13 @@ fn write_tsv_title<W: io::Write>(
         score,
         ent.title().id,
         ent.title().kind,
-        ent.title().title,
+        hl(&ent.title().title),
         ent.title()
             .start_year
             .map(|y| y.to_string())
             .unwrap_or("N/A".to_string()),
     )?;
-    write!(wtr, "\n")?;
+    writeln!(wtr)?;
     Ok(())
 }
 
@@ -131,12 +533,13 @@ fn write_tsv_episode<W: io::Write>(
     ent: &MediaEntity,
     tvshow: &Title,
     ep: &Episode,
+    hl: impl Fn(&str) -> String,
 ) -> anyhow::Result<()> {
     let tvinfo = format!(
         "S{:02}E{:02} {}",
         ep.season.unwrap_or(0),
         ep.episode.unwrap_or(0),
-        tvshow.title,
+        hl(&tvshow.title),
     );
     write!(
         wtr,
@@ -145,13 +548,546 @@ fn write_tsv_episode<W: io::Write>(
         score,
         ent.title().id,
         ent.title().kind,
-        ent.title().title,
+        hl(&ent.title().title),
         ent.title()
             .start_year
             .map(|y| y.to_string())
             .unwrap_or("N/A".to_string()),
         tvinfo,
     )?;
-    write!(wtr, "\n")?;
+    writeln!(wtr)?;
     Ok(())
 }
+
+/// Highlights the parts of result titles that match the name in a query.
+///
+/// A part of a title matches if it produces an ngram that the query's name
+/// also produces, using the same analyzer as the name index. This makes it
+/// easy to see why a seemingly unrelated title was returned by a search.
+#[derive(Debug)]
+pub struct Highlighter {
+    analyzer: Analyzer,
+    ngrams: HashSet<String>,
+}
+
+impl Highlighter {
+    /// Create a highlighter for the given query name, where names are
+    /// broken into ngrams by the given analyzer.
+    pub fn new(analyzer: Analyzer, name: &str) -> Highlighter {
+        let ngrams = analyzer.analyze(name).into_iter().collect();
+        Highlighter { analyzer, ngrams }
+    }
+
+    /// Create a highlighter for the given query, if it has a name.
+    ///
+    /// The analyzer is read from the index of the given searcher, since
+    /// it's the one that determined which titles matched.
+    pub fn for_query(
+        searcher: &mut Searcher,
+        query: &Query,
+    ) -> anyhow::Result<Option<Highlighter>> {
+        let name = match query.name_str() {
+            None => return Ok(None),
+            Some(name) => name,
+        };
+        let analyzer = searcher.index().analyzer()?;
+        Ok(Some(Highlighter::new(analyzer, name)))
+    }
+
+    /// Return the byte ranges of the given title that match the query name.
+    ///
+    /// The ranges returned are sorted and never overlap or touch.
+    pub fn ranges(&self, title: &str) -> Vec<Range<usize>> {
+        let ranges = self
+            .analyzer
+            .analyze_spans(title)
+            .into_iter()
+            .filter(|(ngram, _)| self.ngrams.contains(ngram))
+            .map(|(_, range)| range)
+            .collect();
+        merge_ranges(ranges)
+    }
+
+    /// Return the given title with the parts matching the query name
+    /// wrapped in ANSI escape sequences for bold and underlined text.
+    pub fn highlight(&self, title: &str) -> String {
+        highlight(title, &self.ranges(title))
+    }
+}
+
+/// Sort the given ranges and merge the ones that overlap or touch.
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| (r.start, r.end));
+    let mut merged: Vec<Range<usize>> = vec![];
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => {
+                last.end = last.end.max(r.end);
+            }
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+/// Wrap the given ranges of text in ANSI escape sequences for bold and
+/// underlined text. The ranges must be sorted and must not overlap.
+fn highlight(text: &str, ranges: &[Range<usize>]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for r in ranges {
+        out.push_str(&text[last..r.start]);
+        out.push_str("\x1b[1;4m");
+        out.push_str(&text[r.clone()]);
+        out.push_str("\x1b[0m");
+        last = r.end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Write every episode of the given TV show, with its title and rating, to
+/// the given writer in a tabular format. Episodes are sorted by season and
+/// episode number.
+pub fn write_season_overview<W: io::Write>(
+    wtr: W,
+    searcher: &mut Searcher,
+    tvshow_id: &str,
+) -> anyhow::Result<()> {
+    let mut wtr = TabWriter::new(wtr).minwidth(4);
+    if let Some(snapshot) = searcher.index().data_snapshot() {
+        writeln!(wtr, "# IMDb data snapshot: {}", snapshot)?;
+    }
+    writeln!(wtr, "#\tepisode\tid\ttitle\tyear\trating\tvotes")?;
+    let overview = searcher.index().season_overview(tvshow_id)?;
+    for (i, (ep, rating, title)) in overview.iter().enumerate() {
+        let na = || "N/A".to_string();
+        writeln!(
+            wtr,
+            "{}\tS{:02}E{:02}\t{}\t{}\t{}\t{}\t{}",
+            i + 1,
+            ep.season.unwrap_or(0),
+            ep.episode.unwrap_or(0),
+            ep.id,
+            title.as_ref().map_or("N/A", |t| &t.title),
+            title
+                .as_ref()
+                .and_then(|t| t.start_year)
+                .map_or_else(na, |y| y.to_string()),
+            rating.as_ref().map_or_else(na, |r| format!("{:.1}", r.rating)),
+            rating.as_ref().map_or_else(na, |r| r.votes.to_string()),
+        )?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Format a count for humans, e.g., `4.1M` for `4_123_456`.
+pub fn human_count(n: usize) -> String {
+    if n >= 1_000_000 {
+        format!("{:.1}M", n as f64 / 1_000_000.0)
+    } else if n >= 1_000 {
+        format!("{:.1}K", n as f64 / 1_000.0)
+    } else {
+        n.to_string()
+    }
+}
+
+/// Format a number of bytes for humans, e.g., `58.6 GiB`.
+pub fn human_bytes(n: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut size = n as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Returns whether the two paths (or their nearest existing ancestors) are
+/// on the same device, i.e., whether a file can be renamed from one to the
+/// other without copying it. If that can't be determined, e.g., because the
+/// metadata of either path can't be read, then this returns `None`.
+///
+/// On Unix, this compares the device IDs of the paths. On Windows, this
+/// compares the drive (or UNC share) of the paths after resolving them.
+/// Elsewhere, paths are assumed to be on the same device.
+pub fn same_device(path1: &Path, path2: &Path) -> Option<bool> {
+    Some(device(path1)? == device(path2)?)
+}
+
+/// Return the nearest ancestor of the given path that exists, including the
+/// path itself, along with its metadata. An empty relative path refers to
+/// the current directory.
+fn nearest_existing(path: &Path) -> Option<(&Path, fs::Metadata)> {
+    path.ancestors().find_map(|p| {
+        let p = if p.as_os_str().is_empty() { Path::new(".") } else { p };
+        fs::metadata(p).ok().map(|md| (p, md))
+    })
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    nearest_existing(path).map(|(_, md)| md.dev())
+}
+
+#[cfg(windows)]
+fn device(path: &Path) -> Option<std::ffi::OsString> {
+    use std::path::Component;
+
+    let (path, _) = nearest_existing(path)?;
+    match path.canonicalize().ok()?.components().next()? {
+        Component::Prefix(prefix) => {
+            Some(prefix.as_os_str().to_ascii_uppercase())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn device(path: &Path) -> Option<()> {
+    nearest_existing(path).map(|_| ())
+}
+
+/// Return a progress callback that renders progress on stderr, but only once
+/// an operation has taken longer than a second. `what` names the records
+/// being processed, e.g., "titles".
+pub fn progress_printer(what: &'static str) -> ProgressFn {
+    let mut shown = false;
+    Box::new(move |p: &Progress| {
+        if !shown && p.elapsed < Duration::from_secs(1) {
+            return;
+        }
+        shown = true;
+        events::emit(&Event::Progress {
+            what,
+            rows: p.rows,
+            matches: p.matches,
+            elapsed: p.elapsed.as_secs_f64(),
+            done: p.done,
+        });
+        eprint!(
+            "\rscanned {} {}, {} matching ({:.1}s)",
+            human_count(p.rows),
+            what,
+            human_count(p.matches),
+            p.elapsed.as_secs_f64(),
+        );
+        if p.done {
+            eprintln!();
+        }
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use imdb_index::{
+        Analyzer, Index, IndexBuilder, MediaEntity, NgramType, Query, Scored,
+        Searcher,
+    };
+
+    use super::{
+        auto_choice, highlight, human_bytes, human_count, imdb_url,
+        merge_ranges, same_device, write_json, write_season_overview,
+        write_tsv, Highlighter, PromptCommand,
+    };
+
+    pub use crate::tempdir::TempDir;
+
+    /// Create an index of the small test data set in the given directory.
+    /// The data set contains The Simpsons along with its first few seasons,
+    /// and a handful of movies.
+    pub fn small_index(tmp: &TempDir) -> Index {
+        IndexBuilder::new().create("data/test/small", tmp.path()).unwrap()
+    }
+
+    /// Create a searcher over `small_index`.
+    pub fn small_searcher(tmp: &TempDir) -> Searcher {
+        Searcher::new(small_index(tmp))
+    }
+
+    /// An entity for proposals whose entity doesn't matter, e.g., because
+    /// they are only executed.
+    pub fn entity() -> MediaEntity {
+        serde_json::from_value(serde_json::json!({
+            "title": {
+                "tconst": "tt0000001",
+                "titleType": "movie",
+                "primaryTitle": "Title",
+                "originalTitle": "Title",
+                "isAdult": 0,
+                "startYear": 2000,
+                "endYear": null,
+                "runtimeMinutes": null,
+                "genres": "",
+            },
+            "episode": null,
+            "rating": null,
+        }))
+        .unwrap()
+    }
+
+    fn scored(scores: &[f64]) -> Vec<Scored<usize>> {
+        scores
+            .iter()
+            .enumerate()
+            .map(|(i, &score)| Scored::new(i).with_score(score))
+            .collect()
+    }
+
+    #[test]
+    fn auto_choice_extremes() {
+        let results = scored(&[1.0, 0.0]);
+        assert_eq!(auto_choice(&results, 0.0, 0.0).index(), Some(0));
+        assert_eq!(auto_choice(&results, 1.0, 0.0).index(), None);
+
+        let results = scored(&[0.5, 0.5]);
+        assert_eq!(auto_choice(&results, 0.0, 0.0).index(), Some(0));
+        assert_eq!(auto_choice(&results, 1.0, 0.0).index(), None);
+    }
+
+    #[test]
+    fn auto_choice_margin() {
+        let results = scored(&[0.9, 0.6]);
+        assert_eq!(auto_choice(&results, 0.25, 0.0).index(), Some(0));
+        assert_eq!(auto_choice(&results, 0.5, 0.0).index(), None);
+        assert_eq!(auto_choice(&scored(&[0.1]), 1.0, 0.0).index(), Some(0));
+        assert_eq!(auto_choice(&scored(&[]), 0.0, 0.0).index(), None);
+    }
+
+    #[test]
+    fn auto_choice_match_ratio() {
+        let mut results = scored(&[0.9, 0.1]);
+        results[0] = results[0].with_terms(7, 9);
+        assert_eq!(auto_choice(&results, 0.25, 0.0).index(), Some(0));
+        assert_eq!(auto_choice(&results, 0.25, 0.7).index(), Some(0));
+        assert_eq!(auto_choice(&results, 0.25, 0.8).index(), None);
+        assert_eq!(auto_choice(&results[..1], 0.25, 0.8).index(), None);
+
+        // Without known terms, the ratio doesn't apply.
+        let results = scored(&[0.9, 0.1]);
+        assert_eq!(auto_choice(&results, 0.25, 1.0).index(), Some(0));
+    }
+
+    #[test]
+    fn auto_choice_reasons() {
+        let reason = |scores: &[f64], threshold, min_ratio| {
+            auto_choice(&scored(scores), threshold, min_ratio).to_string()
+        };
+        assert_eq!(reason(&[], 0.25, 0.0), "no results");
+        assert_eq!(
+            reason(&[0.5], 0.25, 0.0),
+            "auto-accepted: only one result"
+        );
+        assert_eq!(
+            reason(&[0.9, 0.8], 0.25, 0.0),
+            "prompted: margin 0.100 over runner-up < 0.250"
+        );
+        assert_eq!(
+            reason(&[0.9, 0.8], 0.05, 0.0),
+            "auto-accepted: margin 0.100 over runner-up >= 0.050"
+        );
+        assert_eq!(
+            reason(&[0.9, 0.8], 0.0, 0.0),
+            "auto-accepted: margin threshold is 0"
+        );
+        assert_eq!(
+            reason(&[0.9, 0.8], 1.0, 0.0),
+            "prompted: margin threshold is 1"
+        );
+
+        let partial = vec![Scored::new(()).with_score(0.5).with_terms(7, 9)];
+        assert_eq!(
+            auto_choice(&partial, 0.25, 0.7).to_string(),
+            "auto-accepted: only one result"
+        );
+        assert_eq!(
+            auto_choice(&partial, 0.25, 0.8).to_string(),
+            "prompted: top result matched 7 of 9 query terms, \
+             ratio 0.778 < 0.800"
+        );
+    }
+
+    #[test]
+    fn merge_ranges_overlapping() {
+        assert!(merge_ranges(vec![]).is_empty());
+        assert_eq!(merge_ranges(vec![4..7, 0..3, 1..4]), vec![0..7]);
+        assert_eq!(merge_ranges(vec![5..8, 0..3, 1..2]), vec![0..3, 5..8]);
+        assert_eq!(merge_ranges(vec![0..9, 2..4]), vec![0..9]);
+    }
+
+    #[test]
+    fn highlight_ranges() {
+        assert_eq!(highlight("Get Out", &[]), "Get Out");
+        assert_eq!(
+            highlight("Get Out", &[0..1, 4..7]),
+            "\x1b[1;4mG\x1b[0met \x1b[1;4mOut\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn highlighter() {
+        let analyzer = Analyzer::new(NgramType::Window, 3);
+        let h = Highlighter::new(analyzer, "Get Out");
+        // "t o", " ou" and "out" are the only ngrams "Bang It Out" has in
+        // common with the query, and they overlap.
+        assert_eq!(h.ranges("Bang It Out"), vec![6..11]);
+        assert_eq!(h.ranges("get out"), vec![0..7]);
+        assert!(h.ranges("Dune").is_empty());
+        assert_eq!(h.highlight("Bang It Out"), "Bang I\x1b[1;4mt Out\x1b[0m");
+    }
+
+    #[test]
+    fn human_counts() {
+        assert_eq!(human_count(999), "999");
+        assert_eq!(human_count(4_123), "4.1K");
+        assert_eq!(human_count(10_600_000), "10.6M");
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_bytes(512), "512 B");
+        assert_eq!(human_bytes(2048), "2.0 KiB");
+        assert_eq!(human_bytes(62_921_900_000), "58.6 GiB");
+        assert_eq!(human_bytes(5 << 50), "5120.0 TiB");
+    }
+
+    #[test]
+    fn same_devices() {
+        let tmp = TempDir::new("imdb-rename-test-same-device").unwrap();
+        let file = tmp.path().join("a.mkv");
+        fs::write(&file, "a").unwrap();
+        assert_eq!(same_device(&file, tmp.path()), Some(true));
+        // Paths that don't exist yet are on the device of their nearest
+        // existing ancestor.
+        assert_eq!(
+            same_device(&file, &tmp.path().join("new").join("dir")),
+            Some(true)
+        );
+        assert_eq!(
+            same_device(Path::new("Cargo.toml"), Path::new("")),
+            Some(true)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn different_devices() {
+        // procfs is never on the same device as a real file system.
+        assert_eq!(
+            same_device(Path::new("/proc/self"), Path::new("src")),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn prompt_commands() {
+        let parse = |line: &str| PromptCommand::parse(line, 1, 10, false);
+        assert_eq!(parse("3\n").unwrap(), PromptCommand::Choose(3));
+        assert_eq!(parse("o 3\n").unwrap(), PromptCommand::Open(3));
+        assert_eq!(parse("o10").unwrap(), PromptCommand::Open(10));
+        assert_eq!(parse(" u \n").unwrap(), PromptCommand::Urls);
+        assert!(parse("0").is_err());
+        assert!(parse("11").is_err());
+        assert!(parse("o 11").is_err());
+        assert!(parse("o").is_err());
+        assert!(parse("u 1").is_err());
+        assert!(parse("").is_err());
+        assert!(parse("s").is_err());
+        assert!(parse("r").is_err());
+    }
+
+    #[test]
+    fn prompt_commands_batch() {
+        let parse = |line: &str| PromptCommand::parse(line, 1, 10, true);
+        assert_eq!(parse("s\n").unwrap(), PromptCommand::Skip);
+        assert_eq!(parse(" r ").unwrap(), PromptCommand::More);
+        assert_eq!(parse("3").unwrap(), PromptCommand::Choose(3));
+        assert!(parse("s 1").is_err());
+    }
+
+    #[test]
+    fn imdb_urls() {
+        assert_eq!(
+            imdb_url("tt0133093"),
+            "https://www.imdb.com/title/tt0133093/"
+        );
+    }
+
+    #[test]
+    fn tsv_snapshot_header() {
+        let tmp = TempDir::new("imdb-rename-test-tsv").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let results = searcher.search(&Query::new().name("dune")).unwrap();
+
+        let mut out = vec![];
+        write_tsv(&mut out, &mut searcher, results.as_slice(), None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        let snapshot = searcher.index().data_snapshot().unwrap();
+        assert_eq!(
+            lines.next().unwrap(),
+            format!("# IMDb data snapshot: {}", snapshot)
+        );
+        assert!(lines.next().unwrap().starts_with("#     score"));
+        assert_eq!(lines.count(), 2);
+    }
+
+    #[test]
+    fn json_results() {
+        let tmp = TempDir::new("imdb-rename-test-json").unwrap();
+        let mut searcher = small_searcher(&tmp);
+        let query = Query::new().name("bart gets an f").size(1);
+        let results = searcher.search(&query).unwrap();
+
+        let mut out = vec![];
+        write_json(&mut out, &mut searcher, results.as_slice()).unwrap();
+        let got: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            got,
+            serde_json::json!([{
+                "score": 1.0,
+                "id": "tt0763024",
+                "kind": "tvEpisode",
+                "title": "Bart Gets an F",
+                "year": 1990,
+                "tv": {
+                    "show_id": "tt0096697",
+                    "show": "The Simpsons",
+                    "season": 2,
+                    "episode": 1,
+                },
+            }])
+        );
+    }
+
+    #[test]
+    fn season_overview() {
+        let tmp = TempDir::new("imdb-rename-test-overview").unwrap();
+        let mut searcher = small_searcher(&tmp);
+
+        let mut out = vec![];
+        write_season_overview(&mut out, &mut searcher, "tt0096697").unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = out
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(rows.len(), 60);
+        // The special in season 0 comes first, and is unrated.
+        assert_eq!(rows[0][1..3], ["S00E01", "tt9900001"]);
+        assert_eq!(rows[0][rows[0].len() - 2..], ["N/A", "N/A"]);
+        assert_eq!(rows[1][1..3], ["S01E01", "tt0348034"]);
+        assert_eq!(rows[1][rows[1].len() - 3..], ["1989", "8.1", "9800"]);
+    }
+}
@@ -0,0 +1,230 @@
+// Drives imdb-rename the way a program wrapping it would: through its event
+// stream, where events are read from stdout and replies are written to stdin,
+// or by reading the proposals it prints with --json.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use serde_json::Value;
+
+/// A temporary directory, with a copy of the small IMDb data set and its
+/// index in it, that is removed when dropped.
+///
+/// The data set is copied since imdb-rename writes its rename journal to the
+/// data directory.
+struct Session {
+    dir: PathBuf,
+}
+
+impl Session {
+    fn new(name: &str) -> Session {
+        let dir = std::env::temp_dir().join(format!(
+            "imdb-rename-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(dir.join("media")).unwrap();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let small = Path::new("data").join("test").join("small");
+        for entry in fs::read_dir(&small).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join("data").join(path.file_name().unwrap()))
+                .unwrap();
+        }
+        Session { dir }
+    }
+
+    fn media(&self, name: &str) -> PathBuf {
+        self.dir.join("media").join(name)
+    }
+
+    /// Run imdb-rename with the given arguments, writing the given replies
+    /// to its stdin, and return its output along with the events it
+    /// emitted.
+    fn run(&self, args: &[&str], replies: &[&str]) -> (Output, Vec<Value>) {
+        self.run_in(Path::new("."), args, replies)
+    }
+
+    /// Like `run`, but with the given working directory.
+    fn run_in(
+        &self,
+        cwd: &Path,
+        args: &[&str],
+        replies: &[&str],
+    ) -> (Output, Vec<Value>) {
+        let mut child = self
+            .command()
+            .current_dir(cwd)
+            .arg("--events")
+            .arg("jsonl")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        {
+            let mut stdin = child.stdin.take().unwrap();
+            for reply in replies {
+                writeln!(stdin, "{}", reply).unwrap();
+            }
+        }
+        let out = child.wait_with_output().unwrap();
+        let events = String::from_utf8(out.stdout.clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (out, events)
+    }
+
+    /// Return a command for running imdb-rename on this session's data.
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_imdb-rename"));
+        cmd.arg("--data-dir")
+            .arg(self.dir.join("data"))
+            .arg("--index-dir")
+            .arg(self.dir.join("index"))
+            .args(["--votes", "0", "--color", "never"])
+            // Keep the user's config file and environment out of the way.
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env_remove("IMDB_RENAME_CONFIG")
+            .env_remove("IMDB_RENAME_DATA_DIR")
+            .env_remove("IMDB_RENAME_DEST_DIR")
+            .env_remove("IMDB_RENAME_INDEX_DIR");
+        cmd
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn kinds(events: &[Value]) -> Vec<&str> {
+    events.iter().map(|e| e["event"].as_str().unwrap()).collect()
+}
+
+#[test]
+fn scripted_session() {
+    let session = Session::new("events");
+    let src = session.media("dune.mkv");
+    fs::write(&src, "").unwrap();
+
+    let (out, events) = session.run(
+        &["-q", "dune", src.to_str().unwrap()],
+        &[r#"{"answer": "choose", "choice": 2}"#, r#"{"answer": "yes"}"#],
+    );
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(
+        kinds(&events),
+        vec!["index", "index", "prompt", "proposal", "confirm", "renamed"],
+    );
+    assert_eq!(events[0]["status"], "started");
+    assert_eq!(events[1]["status"], "finished");
+
+    // Both versions of Dune are offered, and the second one was chosen.
+    let results = events[2]["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(events[2]["batch"], false);
+    let chosen = results[1]["id"].as_str().unwrap();
+    assert_eq!(events[3]["matched"]["id"], chosen);
+    let dst = events[3]["dst"].as_str().unwrap();
+    assert_eq!(events[5]["dst"], dst);
+    assert!(!src.exists() && Path::new(dst).exists());
+
+    // Human readable output, like the proposal table, moved to stderr.
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("dune.mkv  ->  "), "{}", stderr);
+}
+
+#[test]
+fn declined_and_invalid_replies() {
+    let session = Session::new("events-declined");
+    let src = session.media("dune.mkv");
+    fs::write(&src, "").unwrap();
+    let args = &["-q", "dune", src.to_str().unwrap()];
+
+    let (out, events) = session.run(
+        args,
+        &[r#"{"answer": "choose", "choice": 1}"#, r#"{"answer": "no"}"#],
+    );
+    assert!(out.status.success());
+    assert_eq!(kinds(&events).last(), Some(&"confirm"));
+    assert!(src.exists());
+
+    // A reply that doesn't answer the question is an error, which is
+    // reported as an event too.
+    let (out, events) = session.run(args, &[r#"{"answer": "yes"}"#]);
+    assert!(!out.status.success());
+    assert_eq!(kinds(&events), vec!["prompt", "error"]);
+    let error = events[1]["error"].as_str().unwrap();
+    assert!(error.contains("expected a choice"), "{}", error);
+
+    let (out, events) = session.run(args, &[]);
+    assert!(!out.status.success());
+    let error = events[1]["error"].as_str().unwrap();
+    assert!(error.contains("end of input"), "{}", error);
+    assert!(src.exists());
+}
+
+#[test]
+fn undo_from_another_directory() {
+    let session = Session::new("events-undo");
+    let media = session.media("");
+    fs::write(session.media("dune.mkv"), "dune").unwrap();
+
+    // Rename by a relative path, from the media directory.
+    let (out, events) = session.run_in(
+        &media,
+        &["-q", "dune", "dune.mkv"],
+        &[r#"{"answer": "choose", "choice": 1}"#, r#"{"answer": "yes"}"#],
+    );
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let dst = events.last().unwrap()["dst"].as_str().unwrap().to_string();
+    let name = Path::new(&dst).file_name().unwrap();
+    assert!(media.join(name).exists());
+
+    // Undoing from elsewhere renames the file back in the media directory,
+    // and leaves alone an unrelated file at the same relative path.
+    let decoy = session.dir.join(name);
+    fs::write(&decoy, "decoy").unwrap();
+    let (out, _) = session.run_in(&session.dir, &["--undo"], &[]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(fs::read_to_string(session.media("dune.mkv")).unwrap(), "dune");
+    assert!(!media.join(name).exists());
+    assert_eq!(fs::read_to_string(&decoy).unwrap(), "decoy");
+    assert!(!session.dir.join("dune.mkv").exists());
+}
+
+#[test]
+fn json_never_prompts() {
+    let session = Session::new("json");
+    let src = session.media("The.Matrix.1999.mkv");
+    fs::write(&src, "matrix").unwrap();
+
+    // Without anything on stdin, a prompt would fail or decline. Instead,
+    // the proposals are printed and nothing is renamed.
+    let out = session
+        .command()
+        .arg("--json")
+        .arg(&src)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let proposals: Value = serde_json::from_slice(&out.stdout).unwrap();
+    let proposals = proposals.as_array().unwrap();
+    assert_eq!(proposals.len(), 1);
+    assert_eq!(proposals[0]["src"], src.to_str().unwrap());
+    assert_eq!(fs::read_to_string(&src).unwrap(), "matrix");
+    let dst = proposals[0]["dst"].as_str().unwrap();
+    assert!(!Path::new(dst).exists());
+    assert_eq!(fs::read_dir(session.media("")).unwrap().count(), 1);
+}